@@ -0,0 +1,81 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Compare serializing tracker reports into fresh buffers against a `BufferPool`,
+//! and against writing each straight into a reused frame buffer.
+//!
+//! Each iteration serializes one second's worth of reports at 1kHz,
+//! the way an endpoint's send path does.
+
+use bytes::BytesMut;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::convert::TryFrom;
+use vrpn::{
+    buffer_unbuffer::BufferPool,
+    data_types::{
+        id_types::{MessageTypeId, SenderId, Sensor, SequenceNumber},
+        GenericMessage, Quat, TypedMessage, Vec3,
+    },
+    tracker::PoseReport,
+};
+
+const REPORTS_PER_SECOND: u32 = 1000;
+
+fn report(i: u32) -> TypedMessage<PoseReport> {
+    TypedMessage::new(
+        None,
+        MessageTypeId(0),
+        SenderId(0),
+        PoseReport {
+            sensor: Sensor(0),
+            pos: Vec3::new(f64::from(i), 0.0, 0.0),
+            quat: Quat::identity(),
+        },
+    )
+}
+
+fn send_path(c: &mut Criterion) {
+    let mut group = c.benchmark_group("1kHz tracker send path");
+    group.bench_function("fresh allocations", |b| {
+        b.iter(|| {
+            for i in 0..REPORTS_PER_SECOND {
+                let msg = GenericMessage::try_from(report(i)).unwrap();
+                let buf = msg
+                    .into_sequenced_message(SequenceNumber(i))
+                    .try_into_buf()
+                    .unwrap();
+                black_box(buf);
+            }
+        })
+    });
+    group.bench_function("buffer pool", |b| {
+        let mut body_pool = BufferPool::new();
+        let mut wire_pool = BufferPool::new();
+        b.iter(|| {
+            for i in 0..REPORTS_PER_SECOND {
+                let msg = report(i).try_into_generic_in(&mut body_pool).unwrap();
+                let buf = wire_pool
+                    .buffer(&msg.into_sequenced_message(SequenceNumber(i)))
+                    .unwrap();
+                black_box(buf);
+            }
+        })
+    });
+    group.bench_function("direct to frame", |b| {
+        let mut frames = BytesMut::new();
+        b.iter(|| {
+            for i in 0..REPORTS_PER_SECOND {
+                report(i)
+                    .buffer_to_frame(SequenceNumber(i), &mut frames)
+                    .unwrap();
+            }
+            black_box(&frames);
+            frames.clear();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, send_path);
+criterion_main!(benches);
@@ -0,0 +1,113 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! A standalone server of simulated devices, for trying out clients such as
+//! the C++ `vrpn_print_devices Tracker0@localhost`.
+//!
+//! Usage: `cargo run --example vrpn_server --features vrpn-async-std -- [-f CONFIG] [PORT [RATE]]`
+//!
+//! Serves `Tracker0`, going around a circle and reporting at RATE Hz (default 60),
+//! `Button0`, with button 0 toggling every second,
+//! and `Analog0`, with two channels following a sine and cosine.
+//!
+//! With `-f`, serves the devices of a `vrpn.cfg`-style file instead:
+//! see `vrpn::server::config` for the supported device classes.
+
+extern crate vrpn;
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Instant,
+};
+use vrpn::{
+    constants::DEFAULT_PORT,
+    data_types::{StaticSenderName, Vec3},
+    driver::split,
+    server::{read_config, DeviceConfig, ServerDevices},
+    simulation::{AnalogSine, ButtonExample, SimulatedDevice, TrackerNull, Trajectory},
+    vrpn_async_std::connection_ip::ConnectionIp,
+    Result, VrpnError,
+};
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<String>, default: T) -> Result<T> {
+    match arg {
+        Some(arg) => arg
+            .parse()
+            .map_err(|_| VrpnError::OtherMessage(format!("could not parse argument: {}", arg))),
+        None => Ok(default),
+    }
+}
+
+/// Report from each device when due, until there is an error.
+async fn run(mut devices: Vec<Box<dyn SimulatedDevice>>) -> Result<()> {
+    while let Some(due) = devices.iter().filter_map(|device| device.next_due()).min() {
+        async_std::task::sleep(due.saturating_duration_since(Instant::now())).await;
+        let now = Instant::now();
+        for device in &mut devices {
+            device.report_due(now)?;
+        }
+    }
+    // Nothing reports on its own: just serve.
+    futures::future::pending().await
+}
+
+/// The default simulated devices, with the tracker reporting at the given rate.
+fn simulated(connection: Arc<ConnectionIp>, rate: f64) -> Result<Vec<Box<dyn SimulatedDevice>>> {
+    let tracker = TrackerNull::new(
+        Arc::clone(&connection),
+        StaticSenderName(b"Tracker0"),
+        1,
+        rate,
+    )?
+    .with_trajectory(Trajectory::Circle {
+        center: Vec3::new(0.0, 0.0, 1.5),
+        radius: 0.5,
+        period: 4.0,
+    });
+    let button = ButtonExample::new(
+        Arc::clone(&connection),
+        StaticSenderName(b"Button0"),
+        1,
+        1.0,
+    )?;
+    let analog = AnalogSine::new(connection, StaticSenderName(b"Analog0"), 2, rate)?;
+    Ok(vec![Box::new(tracker), Box::new(button), Box::new(analog)])
+}
+
+async fn serve(port: u16, rate: f64, configs: Option<Vec<DeviceConfig>>) -> Result<()> {
+    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    let connection = ConnectionIp::new_server(None, Some(addr))?;
+    let (_handle, driver) = split(Arc::clone(&connection));
+    match configs {
+        Some(configs) => {
+            let names: Vec<&str> = configs.iter().map(DeviceConfig::name).collect();
+            println!("Serving {} on port {}", names.join(", "), port);
+            let devices = ServerDevices::new(connection, &configs)?;
+            futures::future::try_join(run(vec![Box::new(devices)]), driver).await?;
+        }
+        None => {
+            println!("Serving Tracker0, Button0, and Analog0 on port {}", port);
+            let devices = simulated(connection, rate)?;
+            futures::future::try_join(run(devices), driver).await?;
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    let configs = if args.peek().map(String::as_str) == Some("-f") {
+        args.next();
+        let path = args
+            .next()
+            .ok_or_else(|| VrpnError::OtherMessage("-f needs a config file".to_string()))?;
+        Some(read_config(path)?)
+    } else {
+        None
+    };
+    let port = parse_arg(args.next(), DEFAULT_PORT)?;
+    let rate = parse_arg(args.next(), 60.0)?;
+    async_std::task::block_on(serve(port, rate, configs))
+}
@@ -0,0 +1,16 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! The parts of the standard prelude that come from `alloc`,
+//! for the modules that also build without `std`.
+
+#[allow(unused_imports)]
+pub(crate) use alloc::{
+    borrow::ToOwned,
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
@@ -0,0 +1,163 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Types related to the `vrpn_Analog` device class
+
+use crate::{
+    buffer_unbuffer::{
+        buffer::{check_buffer_remaining, BufferResult, BufferTo},
+        unbuffer::{check_unbuffer_remaining, UnbufferFrom, UnbufferResult},
+        BufferSize, BufferUnbufferError, ConstantBufferSize,
+    },
+    data_types::{
+        id_types::{LocalId, SenderId},
+        message::TypedMessageBody,
+        name_types::StaticMessageTypeName,
+        ClassOfService, MessageTypeIdentifier, SenderName, TimeVal,
+    },
+    handler::HandlerHandle,
+    Connection, Handler, Result, TypedHandler,
+};
+use bytes::{Buf, BufMut};
+use std::sync::Arc;
+
+/// Most channels a device may report, as `vrpn_CHANNEL_MAX`.
+pub const MAX_CHANNELS: usize = 128;
+
+/// The values of all channels of an analog device.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AnalogChannels {
+    /// At most `MAX_CHANNELS`.
+    pub channels: Vec<f64>,
+}
+
+impl TypedMessageBody for AnalogChannels {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(StaticMessageTypeName(b"vrpn_Analog Channel"));
+}
+
+impl BufferSize for AnalogChannels {
+    fn buffer_size(&self) -> usize {
+        (1 + self.channels.len()) * f64::constant_buffer_size()
+    }
+}
+
+impl BufferTo for AnalogChannels {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        if self.channels.len() > MAX_CHANNELS {
+            return Err(BufferUnbufferError::OutOfBuffer);
+        }
+        check_buffer_remaining(buf, self.buffer_size())?;
+        // The count is sent as a float64, like the values.
+        (self.channels.len() as f64).buffer_to(buf)?;
+        for channel in &self.channels {
+            channel.buffer_to(buf)?;
+        }
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for AnalogChannels {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        let count = f64::unbuffer_from(buf)?;
+        if !(0.0..=MAX_CHANNELS as f64).contains(&count) || count.fract() != 0.0 {
+            return Err(BufferUnbufferError::ParseError {
+                parsing_kind: "analog channel count".to_string(),
+                s: count.to_string(),
+            });
+        }
+        let len = count as usize;
+        check_unbuffer_remaining(buf, len * f64::constant_buffer_size())?;
+        let channels = (0..len)
+            .map(|_| f64::unbuffer_from(buf))
+            .collect::<UnbufferResult<Vec<f64>>>()?;
+        Ok(AnalogChannels { channels })
+    }
+}
+
+/// Client side of an analog device.
+#[derive(Debug)]
+pub struct AnalogRemote<C: Connection> {
+    connection: Arc<C>,
+    sender: LocalId<SenderId>,
+}
+
+impl<C: Connection> AnalogRemote<C> {
+    pub fn new(connection: Arc<C>, name: impl Into<SenderName>) -> Result<AnalogRemote<C>> {
+        let sender = connection.register_sender(name.into())?;
+        Ok(AnalogRemote { connection, sender })
+    }
+
+    /// The local sender ID of this analog device.
+    pub fn sender(&self) -> LocalId<SenderId> {
+        self.sender
+    }
+
+    /// Add a handler for the channel values reported by this device.
+    pub fn add_channels_handler<H>(&self, handler: Box<H>) -> Result<HandlerHandle>
+    where
+        H: TypedHandler<Item = AnalogChannels> + Handler + 'static,
+    {
+        self.connection
+            .add_typed_handler(handler, Some(self.sender))
+    }
+}
+
+/// Server side of an analog device.
+#[derive(Debug)]
+pub struct AnalogServer<C: Connection> {
+    connection: Arc<C>,
+    sender: LocalId<SenderId>,
+}
+
+impl<C: Connection> AnalogServer<C> {
+    pub fn new(connection: Arc<C>, name: impl Into<SenderName>) -> Result<AnalogServer<C>> {
+        let sender = connection.register_sender(name.into())?;
+        Ok(AnalogServer { connection, sender })
+    }
+
+    /// The local sender ID of this analog device.
+    pub fn sender(&self) -> LocalId<SenderId> {
+        self.sender
+    }
+
+    /// Report the values of all channels.
+    pub fn report_channels(&self, time: Option<TimeVal>, channels: AnalogChannels) -> Result<()> {
+        self.connection
+            .pack_message_body(time, self.sender, channels, ClassOfService::RELIABLE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_unbuffer::BytesMutExtras;
+    use bytes::{Bytes, BytesMut};
+
+    #[test]
+    fn channels_wire_format() {
+        let channels = AnalogChannels {
+            channels: vec![0.5, -1.0],
+        };
+        let buf = BytesMut::allocate_and_buffer(channels.clone())
+            .unwrap()
+            .freeze();
+        // Count then values, all float64, as vrpn_Analog::encode_to
+        assert_eq!(
+            &buf[..],
+            &hex!("4000000000000000 3fe0000000000000 bff0000000000000")[..]
+        );
+        assert_eq!(
+            AnalogChannels::unbuffer_from(&mut buf.clone()).unwrap(),
+            channels
+        );
+
+        // Not a whole number of channels.
+        assert!(
+            AnalogChannels::unbuffer_from(&mut Bytes::from_static(&hex!("3fe0000000000000")))
+                .is_err()
+        );
+    }
+}
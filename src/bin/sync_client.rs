@@ -51,7 +51,7 @@ fn main() -> Result<()> {
     let _ = dispatcher.add_typed_handler(Box::new(TrackerHandler {}), None)?;
 
     loop {
-        endpoint.poll_endpoint(&mut dispatcher)?;
+        endpoint.poll_endpoint(&dispatcher)?;
         // Every time we get here, tehre is no more messages buffered for us.
     }
 }
@@ -21,10 +21,8 @@ use futures::AsyncReadExt;
 use std::io::Cursor;
 use vrpn::{
     buffer_unbuffer::{BufferTo, BufferUnbufferError, UnbufferFrom},
-    data_types::{
-        cookie::check_ver_nonfile_compatible, CookieData, MessageSize, SequencedGenericMessage,
-    },
-    vrpn_async_std::cookie::read_cookie,
+    data_types::{cookie::check_ver_nonfile_compatible, CookieData, SequencedGenericMessage},
+    vrpn_async::cookie::read_cookie,
     Result,
 };
 
@@ -82,20 +80,6 @@ fn try_decode(bytes_mut: &mut BytesMut) -> Result<Option<SequencedGenericMessage
     }
 }
 
-async fn try_read_header(stream: &mut TcpStream, bytes_mut: &mut BytesMut) -> Result<MessageSize> {
-    assert!(bytes_mut.is_empty());
-    let mut header_buf = [0u8; 24];
-    AsyncReadExt::read_exact(stream, &mut header_buf).await?;
-    let size = {
-        let mut size_buf = std::io::Cursor::new(&header_buf);
-        let total_len = u32::unbuffer_from(&mut size_buf).unwrap();
-        MessageSize::try_from_length_field(total_len).unwrap()
-    };
-    bytes_mut.extend_from_slice(&header_buf[..]);
-    println!("Got header {:?}", size);
-    Ok(size)
-}
-
 async fn try_get_message(
     stream: &mut (impl AsyncReadExt + Unpin),
     bytes_mut: &mut BytesMut,
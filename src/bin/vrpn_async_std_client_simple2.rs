@@ -19,24 +19,11 @@ use async_std::{
 use futures::StreamExt;
 
 use vrpn::{
-    data_types::TypedMessage,
-    handler::{HandlerCode, TypedHandler},
-    tracker::PoseReport,
-    vrpn_async_std::cookie::{read_and_check_nonfile_cookie, send_nonfile_cookie},
-    vrpn_async_std::AsyncReadMessagesExt,
+    vrpn_async::cookie::{read_and_check_nonfile_cookie, send_nonfile_cookie},
+    vrpn_async::AsyncReadMessagesExt,
     Result,
 };
 
-#[derive(Debug)]
-struct TrackerHandler {}
-impl TypedHandler for TrackerHandler {
-    type Item = PoseReport;
-    fn handle_typed(&mut self, msg: &TypedMessage<PoseReport>) -> Result<HandlerCode> {
-        println!("{:?}\n   {:?}", msg.header, msg.body);
-        Ok(HandlerCode::ContinueProcessing)
-    }
-}
-
 async fn async_main() -> Result<()> {
     let addr: SocketAddr = "127.0.0.1:3883".parse().unwrap();
     let mut stream = TcpStream::connect(addr).await?;
@@ -0,0 +1,32 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+// Decode captured VRPN streams to human-readable text.
+//
+// Usage: vrpn-decode CAPTURE...
+//
+// Each capture is the raw bytes sent by one side of a TCP connection,
+// starting with the cookie. See tests/decode_fixtures for synthetic examples.
+
+extern crate bytes;
+extern crate vrpn;
+
+use bytes::Bytes;
+use vrpn::{capture::decode_capture, Result, VrpnError};
+
+fn main() -> Result<()> {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        return Err(VrpnError::OtherMessage(
+            "usage: vrpn-decode CAPTURE...".to_string(),
+        ));
+    }
+    for path in paths {
+        let data = std::fs::read(&path)?;
+        println!("== {}", path);
+        let capture = decode_capture(Bytes::from(data))?;
+        print!("{}", capture);
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+// Record VRPN connections to .vrpn log files, and convert logs to JSON or CSV.
+//
+// Usage:
+//   vrpn-log record SERVER LOG   (needs feature vrpn-async-std)
+//   vrpn-log json LOG...
+//   vrpn-log csv LOG...
+//
+// Recording logs every message the server sends until interrupted.
+// Captures (see tests/decode_fixtures) may be converted as well as logs.
+
+extern crate bytes;
+extern crate vrpn;
+
+use bytes::Bytes;
+use std::io::{self, Write};
+use vrpn::{capture::decode_capture, Result, VrpnError};
+
+const USAGE: &str = "usage: vrpn-log record SERVER LOG | vrpn-log (json|csv) LOG...";
+
+#[cfg(feature = "vrpn-async-std")]
+fn record(server: &str, path: &str) -> Result<()> {
+    use vrpn::{
+        data_types::LogFileNames, driver::split, vrpn_async_std::connection_ip::ConnectionIp,
+    };
+    let connection = ConnectionIp::new_client(
+        server.parse()?,
+        Some(LogFileNames::from_names(Some(path.to_string()), None)),
+        None,
+    )?;
+    let (_handle, driver) = split(connection);
+    async_std::task::block_on(driver)
+}
+
+#[cfg(not(feature = "vrpn-async-std"))]
+fn record(_server: &str, _path: &str) -> Result<()> {
+    Err(VrpnError::OtherMessage(
+        "recording needs the vrpn-async-std feature".to_string(),
+    ))
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let usage = || VrpnError::OtherMessage(USAGE.to_string());
+    let (command, rest) = args.split_first().ok_or_else(usage)?;
+    match (command.as_str(), rest) {
+        ("record", [server, path]) => record(server, path),
+        ("json", paths) | ("csv", paths) if !paths.is_empty() => {
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            for path in paths {
+                let capture = decode_capture(Bytes::from(std::fs::read(path)?))?;
+                if command == "json" {
+                    capture.write_json(&mut out)?;
+                } else {
+                    capture.write_csv(&mut out)?;
+                }
+            }
+            out.flush()?;
+            Ok(())
+        }
+        _ => Err(usage()),
+    }
+}
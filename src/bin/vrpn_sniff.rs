@@ -0,0 +1,34 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+// Print every message a VRPN server sends, for protocol debugging.
+//
+// Usage: vrpn-sniff [--raw] SERVER
+//
+// Each message is shown with its header, sender and type names, and body in hex.
+// Bodies of known message types are decoded as well, unless --raw is given.
+
+extern crate vrpn;
+
+use std::io;
+use vrpn::{
+    driver::split, sniffer::sniff, vrpn_async_std::connection_ip::ConnectionIp, Result, VrpnError,
+};
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (decode_bodies, server) = match &args[..] {
+        [server] => (true, server),
+        [raw, server] if raw == "--raw" => (false, server),
+        _ => {
+            return Err(VrpnError::OtherMessage(
+                "usage: vrpn-sniff [--raw] SERVER".to_string(),
+            ))
+        }
+    };
+    let connection = ConnectionIp::new_client(server.parse()?, None, None)?;
+    sniff(&*connection, io::stdout(), decode_bodies)?;
+    let (_handle, driver) = split(connection);
+    async_std::task::block_on(driver)
+}
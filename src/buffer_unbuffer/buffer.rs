@@ -17,11 +17,11 @@ where
     ///
     /// # Errors
     /// If buffering fails.
-    fn allocate_and_buffer<T: BufferTo>(v: T) -> std::result::Result<Self, BufferUnbufferError>;
+    fn allocate_and_buffer<T: BufferTo>(v: T) -> core::result::Result<Self, BufferUnbufferError>;
 }
 
 impl BytesMutExtras for BytesMut {
-    fn allocate_and_buffer<T: BufferTo>(v: T) -> std::result::Result<Self, BufferUnbufferError> {
+    fn allocate_and_buffer<T: BufferTo>(v: T) -> core::result::Result<Self, BufferUnbufferError> {
         let mut buf = Self::with_capacity(v.buffer_size());
         v.buffer_to(&mut buf)?;
         Ok(buf)
@@ -29,7 +29,7 @@ impl BytesMutExtras for BytesMut {
 }
 
 /// Shorthand name for what a buffering operation should return.
-pub type BufferResult = std::result::Result<(), BufferUnbufferError>;
+pub type BufferResult = core::result::Result<(), BufferUnbufferError>;
 
 /// Trait for types that can be "buffered" (serialized to a byte buffer)
 pub trait BufferTo: BufferSize {
@@ -2,9 +2,9 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
+use crate::alloc_prelude::*;
 use bytes::Bytes;
-use std::{net::AddrParseError, num::ParseIntError};
-use thiserror::Error;
+use core::{fmt, net::AddrParseError, num::ParseIntError};
 
 use super::{
     size_requirement::{ExpandSizeRequirement, MayContainSizeRequirement},
@@ -14,31 +14,83 @@ use super::{
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct MessageSizeInvalid(pub u32);
 
-impl std::fmt::Display for MessageSizeInvalid {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for MessageSizeInvalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Message size field {} is smaller than minimum", self.0)
     }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for MessageSizeInvalid {}
 
 /// Error type returned by buffering/unbuffering.
-#[derive(Error, Debug, Clone, PartialEq, Eq)]
+///
+/// `Display` is written out by hand, rather than derived with `thiserror`,
+/// so this is available without `std`.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BufferUnbufferError {
-    #[error("unbuffering ran out of buffered bytes: need {0} additional bytes")]
     NeedMoreData(SizeRequirement),
-    #[error("unexpected data: expected '{expected:?}', got '{actual:?}'")]
-    UnexpectedAsciiData { actual: Bytes, expected: Bytes },
-    #[error("buffering ran out of buffer space")]
+    UnexpectedAsciiData {
+        actual: Bytes,
+        expected: Bytes,
+    },
     OutOfBuffer,
-    #[error("according to a length field we have complete data, but we need at least {0} additional bytes")]
     HeaderSizeMismatch(String),
-    #[error("Error parsing {parsing_kind}: {s}")]
-    ParseError { parsing_kind: String, s: String },
-    #[error("{}", .0)]
+    ParseError {
+        parsing_kind: String,
+        s: String,
+    },
     MessageSizeInvalid(MessageSizeInvalid),
+    MessageTooLarge {
+        size: usize,
+        max: usize,
+    },
+    /// A message body was decoded without using all of its bytes.
+    BodyNotConsumed {
+        len: usize,
+        remaining: usize,
+    },
 }
 
+impl fmt::Display for BufferUnbufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use BufferUnbufferError::*;
+        match self {
+            NeedMoreData(required) => write!(
+                f,
+                "unbuffering ran out of buffered bytes: need {} additional bytes",
+                required
+            ),
+            UnexpectedAsciiData { actual, expected } => write!(
+                f,
+                "unexpected data: expected '{:?}', got '{:?}'",
+                expected, actual
+            ),
+            OutOfBuffer => f.write_str("buffering ran out of buffer space"),
+            HeaderSizeMismatch(required) => write!(
+                f,
+                "according to a length field we have complete data, but we need at least {} additional bytes",
+                required
+            ),
+            ParseError { parsing_kind, s } => write!(f, "Error parsing {}: {}", parsing_kind, s),
+            MessageSizeInvalid(e) => write!(f, "{}", e),
+            MessageTooLarge { size, max } => write!(
+                f,
+                "message of {} bytes is larger than the limit of {} bytes",
+                size, max
+            ),
+            BodyNotConsumed { len, remaining } => write!(
+                f,
+                "message body length was indicated as {}, but {} bytes remain unconsumed",
+                len, remaining
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BufferUnbufferError {}
+
 impl From<SizeRequirement> for BufferUnbufferError {
     fn from(val: SizeRequirement) -> Self {
         BufferUnbufferError::NeedMoreData(val)
@@ -7,6 +7,7 @@
 pub mod buffer;
 pub mod constants;
 mod error;
+pub mod pool;
 mod primitives;
 pub(crate) mod size;
 pub mod size_requirement;
@@ -15,8 +16,11 @@ pub mod unbuffer;
 #[doc(inline)]
 pub use crate::buffer_unbuffer::{
     error::{BufferUnbufferError, MessageSizeInvalid},
-    primitives::*,
-    size::{BufferSize, ConstantBufferSize, EmptyMessage, WrappedConstantSize},
+    pool::BufferPool,
+    size::{
+        padded, padded_to, padding, padding_to, BufferSize, ConstantBufferSize, EmptyMessage,
+        LengthField, MessageSize, WrappedConstantSize,
+    },
 };
 
 pub use crate::buffer_unbuffer::{
@@ -0,0 +1,100 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! A reusable arena for serializing values, to take allocations out of the send path.
+
+use bytes::{Bytes, BytesMut};
+
+use super::{BufferTo, BufferUnbufferError};
+
+/// Default number of bytes allocated at a time by a `BufferPool`.
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+/// An arena that values are serialized into, handing out a frozen `Bytes` per value.
+///
+/// Each value is split off the front of a shared allocation.
+/// Once every `Bytes` handed out from that allocation has been dropped,
+/// the pool reclaims it for later values instead of allocating again,
+/// so a steady stream of messages that are sent and then dropped does not allocate.
+#[derive(Debug)]
+pub struct BufferPool {
+    buf: BytesMut,
+}
+
+impl BufferPool {
+    /// Create a pool allocating `DEFAULT_CHUNK_SIZE` bytes at a time.
+    pub fn new() -> BufferPool {
+        BufferPool::with_chunk_size(DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Create a pool allocating (at least) `chunk_size` bytes at a time.
+    pub fn with_chunk_size(chunk_size: usize) -> BufferPool {
+        BufferPool {
+            buf: BytesMut::with_capacity(chunk_size),
+        }
+    }
+
+    /// Serialize a value into the pool, returning just the bytes of that value.
+    ///
+    /// # Errors
+    /// If buffering fails, in which case nothing is kept in the pool.
+    pub fn buffer<T: BufferTo>(&mut self, v: &T) -> Result<Bytes, BufferUnbufferError> {
+        // Reclaims the existing allocation if nothing handed out from it is still alive.
+        self.buf.reserve(v.required_buffer_size());
+        if let Err(e) = v.buffer_to(&mut self.buf) {
+            self.buf.clear();
+            return Err(e);
+        }
+        Ok(self.buf.split().freeze())
+    }
+
+    /// The number of bytes that can be buffered before the pool needs to reclaim or allocate.
+    pub fn available(&self) -> usize {
+        self.buf.capacity()
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        BufferPool::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_split_off() {
+        let mut pool = BufferPool::with_chunk_size(64);
+        let a = pool.buffer(&0x0102_0304_u32).unwrap();
+        let b = pool.buffer(&0x0506_0708_u32).unwrap();
+        assert_eq!(&a[..], &[1, 2, 3, 4]);
+        assert_eq!(&b[..], &[5, 6, 7, 8]);
+        assert_eq!(pool.available(), 56);
+    }
+
+    #[test]
+    fn allocation_is_reclaimed() {
+        let mut pool = BufferPool::with_chunk_size(8);
+        let first = pool.buffer(&1_u32).unwrap();
+        let ptr = first.as_ptr();
+        let second = pool.buffer(&2_u32).unwrap();
+        drop(first);
+        drop(second);
+        // The chunk is used up, but nothing refers to it anymore.
+        let third = pool.buffer(&3_u32).unwrap();
+        assert_eq!(third.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn live_values_are_not_overwritten() {
+        let mut pool = BufferPool::with_chunk_size(4);
+        let first = pool.buffer(&1_u32).unwrap();
+        let second = pool.buffer(&2_u32).unwrap();
+        assert_ne!(first.as_ptr(), second.as_ptr());
+        assert_eq!(&first[..], &[0, 0, 0, 1]);
+        assert_eq!(&second[..], &[0, 0, 0, 2]);
+    }
+}
@@ -8,14 +8,17 @@ use super::{
     BufferResult, BufferTo, UnbufferResult,
 };
 use bytes::{Buf, BufMut};
+use core::mem::size_of;
 
+/// Numbers are buffered big-endian ("network byte order"), as VRPN requires,
+/// whatever the byte order of the host.
 macro_rules! buffer_primitive {
-    ($t:ty, $put:ident, $get:ident) => {
+    ($t:ty) => {
         impl ConstantBufferSize for $t {}
 
         impl BufferTo for $t {
             fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
-                buf.$put(*self);
+                buf.put_slice(&self.to_be_bytes());
                 Ok(())
             }
         }
@@ -23,21 +26,24 @@ macro_rules! buffer_primitive {
         impl UnbufferFrom for $t {
             fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
                 check_unbuffer_remaining(buf, Self::constant_buffer_size())?;
-                Ok(buf.$get())
+                let mut bytes = [0u8; size_of::<$t>()];
+                buf.copy_to_slice(&mut bytes);
+                Ok(<$t>::from_be_bytes(bytes))
             }
         }
     };
 }
 
-buffer_primitive!(i8, put_i8, get_i8);
-buffer_primitive!(i16, put_i16, get_i16);
-buffer_primitive!(u16, put_u16, get_u16);
-buffer_primitive!(i32, put_i32, get_i32);
-buffer_primitive!(u32, put_u32, get_u32);
-buffer_primitive!(i64, put_i64, get_i64);
-buffer_primitive!(u64, put_u64, get_u64);
-buffer_primitive!(f32, put_f32, get_f32);
-buffer_primitive!(f64, put_f64, get_f64);
+buffer_primitive!(i8);
+buffer_primitive!(u8);
+buffer_primitive!(i16);
+buffer_primitive!(u16);
+buffer_primitive!(i32);
+buffer_primitive!(u32);
+buffer_primitive!(i64);
+buffer_primitive!(u64);
+buffer_primitive!(f32);
+buffer_primitive!(f64);
 
 impl ConstantBufferSize for () {
     fn constant_buffer_size() -> usize {
@@ -56,3 +62,28 @@ impl UnbufferFrom for () {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_unbuffer::BytesMutExtras;
+    use bytes::BytesMut;
+
+    #[test]
+    fn big_endian() {
+        let buf = BytesMut::allocate_and_buffer(0x0102_0304_i32).unwrap();
+        assert_eq!(&buf[..], &[1, 2, 3, 4]);
+        let buf = BytesMut::allocate_and_buffer(-2_i16).unwrap();
+        assert_eq!(&buf[..], &[0xff, 0xfe]);
+        let buf = BytesMut::allocate_and_buffer(1.0_f64).unwrap();
+        assert_eq!(&buf[..], &[0x3f, 0xf0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            u64::unbuffer_from(&mut &[0, 0, 0, 0, 0, 0, 1, 0][..]).unwrap(),
+            256
+        );
+        assert_eq!(
+            f32::unbuffer_from(&mut &[0x3f, 0x80, 0, 0][..]).unwrap(),
+            1.0
+        );
+    }
+}
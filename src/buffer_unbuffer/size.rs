@@ -2,9 +2,10 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
-//! Traits describing the size of things we can read from or write to a buffer.
+//! Traits describing the size of things we can read from or write to a buffer,
+//! and the padding and message size computations shared by every module.
 
-use super::{BufferTo, UnbufferFrom};
+use super::{constants::ALIGN, BufferTo, MessageSizeInvalid, UnbufferFrom};
 
 /// Optional trait for things that always take the same amount of space in a buffer.
 ///
@@ -16,7 +17,7 @@ pub trait ConstantBufferSize {
     where
         Self: Sized,
     {
-        std::mem::size_of::<Self>()
+        core::mem::size_of::<Self>()
     }
 }
 
@@ -52,7 +53,7 @@ impl<T: WrappedConstantSize> ConstantBufferSize for T {
 
 /// Trait implemented by empty messages (no body)
 /// so that they can easily get their trivial/null serialization support.
-pub trait EmptyMessage: Default + std::fmt::Debug {}
+pub trait EmptyMessage: Default + core::fmt::Debug {}
 
 /// Empty messages are effectively a wrapped constant size type.
 impl<T: EmptyMessage> WrappedConstantSize for T {
@@ -62,3 +63,285 @@ impl<T: EmptyMessage> WrappedConstantSize for T {
         Default::default()
     }
 }
+
+/// The padding needed after `len` bytes to reach a multiple of `alignment`.
+///
+/// Already-aligned lengths, including zero, need no padding.
+#[inline]
+pub const fn padding_to(len: usize, alignment: usize) -> usize {
+    let remainder = len % alignment;
+    if remainder != 0 {
+        alignment - remainder
+    } else {
+        0
+    }
+}
+
+/// `len` rounded up to a multiple of `alignment`.
+#[inline]
+pub const fn padded_to(len: usize, alignment: usize) -> usize {
+    len + padding_to(len, alignment)
+}
+
+/// The padding needed after `len` bytes to reach a multiple of `ALIGN`.
+#[inline]
+pub const fn padding(len: usize) -> usize {
+    padding_to(len, ALIGN)
+}
+
+/// `len` rounded up to a multiple of `ALIGN`.
+#[inline]
+pub const fn padded(len: usize) -> usize {
+    len + padding(len)
+}
+
+/// Simple struct for wrapping all calculations related to Message<T> size.
+///
+/// Header is 5 i32s (padded to `vrpn_ALIGN`):
+/// - padded header size + unpadded body size
+/// - time stamp
+/// - sender
+/// - type
+///
+/// The four bytes of "padding" are actually the sequence number,
+/// which are not "officially" part of the header.
+///
+/// body is padded out to `vrpn_ALIGN`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct MessageSize {
+    // The unpadded size of a message body only
+    pub unpadded_body_size: usize,
+}
+
+pub(crate) const UNPADDED_HEADER_SIZE: usize = 5 * 4;
+/// Padded size of `UNPADDED_HEADER_SIZE`
+const MINIMUM_SIZE_FIELD: u32 = 6 * 4;
+
+/// The type of the length field in the header.
+pub type LengthField = u32;
+
+impl MessageSize {
+    /// Get a MessageSize from the unpadded size of a message body only.
+    #[inline]
+    pub const fn from_unpadded_body_size(unpadded_body_size: usize) -> MessageSize {
+        MessageSize { unpadded_body_size }
+    }
+
+    /// Get a MessageSize from the unpadded size of a message body,
+    /// or `None` if the message would be too large for the header's length field.
+    #[inline]
+    pub const fn try_from_unpadded_body_size(unpadded_body_size: usize) -> Option<MessageSize> {
+        if unpadded_body_size > LengthField::MAX as usize - padded(UNPADDED_HEADER_SIZE) {
+            None
+        } else {
+            Some(MessageSize::from_unpadded_body_size(unpadded_body_size))
+        }
+    }
+
+    /// Get a MessageSize from the total unpadded size of a message (header plus body)
+    #[inline]
+    #[deprecated = "possible to fail, looks unused so would rather remove than change"]
+    pub const fn from_unpadded_message_size(unpadded_message_size: usize) -> MessageSize {
+        MessageSize::from_unpadded_body_size(unpadded_message_size - UNPADDED_HEADER_SIZE)
+    }
+    /// Get a MessageSize from the length field of a message (padded header plus unpadded body)
+    #[inline]
+    pub const fn try_from_length_field(
+        length_field: LengthField,
+    ) -> core::result::Result<MessageSize, MessageSizeInvalid> {
+        if length_field < MINIMUM_SIZE_FIELD {
+            Err(MessageSizeInvalid(length_field))
+        } else {
+            Ok(MessageSize::from_unpadded_body_size(
+                length_field as usize - padded(UNPADDED_HEADER_SIZE),
+            ))
+        }
+    }
+
+    /// The unpadded size of just the message body.
+    #[inline]
+    pub const fn unpadded_body_size(&self) -> usize {
+        self.unpadded_body_size
+    }
+
+    /// The padded header size plus the unpadded body size.
+    ///
+    /// This is the value put in the message header's length field.
+    #[inline]
+    pub const fn length_field(&self) -> LengthField {
+        (self.unpadded_body_size + padded(UNPADDED_HEADER_SIZE)) as LengthField
+    }
+
+    /// The size of the body plus padding (multiple of ALIGN)
+    #[inline]
+    pub const fn padded_body_size(&self) -> usize {
+        padded(self.unpadded_body_size)
+    }
+
+    /// The number of padding bytes required to follow the message body.
+    #[inline]
+    pub const fn body_padding(&self) -> usize {
+        padding(self.unpadded_body_size)
+    }
+
+    /// The total padded size of a message (header plus body, padding applied individually).
+    ///
+    /// This is the size of buffer actually required for this message.
+    #[inline]
+    pub const fn padded_message_size(&self) -> usize {
+        self.padded_body_size() + padded(UNPADDED_HEADER_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc_prelude::*;
+
+    #[test]
+    fn invalid_msg_size() {
+        assert!(MessageSize::try_from_length_field(20).is_err());
+        let largest = LengthField::MAX as usize - padded(UNPADDED_HEADER_SIZE);
+        assert_eq!(
+            MessageSize::try_from_unpadded_body_size(largest)
+                .unwrap()
+                .length_field(),
+            LengthField::MAX
+        );
+        assert!(MessageSize::try_from_unpadded_body_size(largest + 1).is_none());
+    }
+
+    #[test]
+    fn sizes() {
+        // Aligned lengths get no padding, not a whole extra ALIGN.
+        assert_eq!(padding(0), 0);
+        assert_eq!(padding(ALIGN), 0);
+        assert_eq!(padding(24), 0);
+        assert_eq!(padding(17), 7);
+        assert_eq!(
+            MessageSize::from_unpadded_body_size(16).padded_body_size(),
+            16
+        );
+
+        // Based on the initial "VRPN Control" sender ID message
+        assert_eq!(
+            MessageSize::from_unpadded_body_size(17).unpadded_body_size(),
+            17
+        );
+        assert_eq!(
+            MessageSize::from_unpadded_body_size(17).padded_body_size(),
+            24
+        );
+        assert_eq!(UNPADDED_HEADER_SIZE, 20);
+        assert_eq!(MINIMUM_SIZE_FIELD, 24);
+        assert_eq!(
+            MessageSize::from_unpadded_body_size(17).padded_message_size(),
+            48
+        );
+        assert_eq!(MessageSize::from_unpadded_body_size(17).length_field(), 41);
+        assert_eq!(
+            MessageSize::try_from_length_field(41)
+                .unwrap()
+                .length_field(),
+            41
+        );
+        assert_eq!(
+            MessageSize::try_from_length_field(41)
+                .unwrap()
+                .unpadded_body_size(),
+            17
+        );
+
+        // Based on the second message, which is closer to the edge and thus breaks things.
+        assert_eq!(
+            MessageSize::from_unpadded_body_size(13),
+            MessageSize::try_from_length_field(0x25).unwrap()
+        );
+        assert_eq!(
+            MessageSize::from_unpadded_body_size(13).padded_body_size(),
+            16
+        );
+        assert_eq!(
+            MessageSize::from_unpadded_body_size(13).length_field(),
+            24 + 13
+        );
+        assert_eq!(
+            MessageSize::try_from_length_field(37)
+                .unwrap()
+                .length_field(),
+            37
+        );
+
+        assert_eq!(
+            MessageSize::try_from_length_field(37)
+                .unwrap()
+                .padded_message_size(),
+            40
+        );
+    }
+    proptest! {
+        #[test]
+        fn length_field_matches(len in 0u32..10000) {
+            let len = len as usize;
+            prop_assert_eq!(
+            MessageSize::from_unpadded_body_size(len).length_field(),
+            transcribed_padding_function(len).len_field);
+        }
+
+        #[test]
+        fn total_length_matches(len in 0u32..10000) {
+            let len = len as usize;
+            prop_assert_eq!(
+            MessageSize::from_unpadded_body_size(len).padded_message_size(),
+            transcribed_padding_function(len).total_len);
+        }
+
+        #[test]
+        fn roundtrip(len in 24u32..10000)  {
+            prop_assert_eq!(MessageSize::try_from_length_field(len).unwrap().length_field(), len);
+        }
+
+        #[test]
+        fn padding_matches(len in 0usize..10000) {
+            let expected = transcribed_padding_function(len);
+            prop_assert_eq!(padded(len), expected.total_len - expected.header_len);
+            prop_assert_eq!(padding(len), padded(len) - len);
+            prop_assert_eq!(padding(padded(len)), 0);
+        }
+
+        #[test]
+        fn padding_to_alignment(len in 0usize..10000, shift in 0u32..8) {
+            let alignment = 1 << shift;
+            prop_assert!(padding_to(len, alignment) < alignment);
+            prop_assert_eq!(padded_to(len, alignment) % alignment, 0);
+            prop_assert_eq!(padding_to(len * alignment, alignment), 0);
+        }
+    }
+
+    struct Lengths {
+        header_len: usize,
+        total_len: usize,
+        len_field: u32,
+    }
+
+    /// This is a relatively literal translation of the size computations in vrpn_Endpoint::marshall_message,
+    /// used as ground truth in testing.
+    #[allow(clippy::manual_is_multiple_of)]
+    fn transcribed_padding_function(len: usize) -> Lengths {
+        let mut ceil_len = len;
+        if (len % ALIGN) != 0 {
+            ceil_len += ALIGN - len % ALIGN;
+        }
+
+        let mut header_len = 5 * core::mem::size_of::<i32>();
+        if (header_len % ALIGN) != 0 {
+            header_len += ALIGN - header_len % ALIGN;
+        }
+        let total_len = header_len + ceil_len;
+        Lengths {
+            header_len,
+            total_len,
+            len_field: (header_len + len) as u32,
+        }
+    }
+}
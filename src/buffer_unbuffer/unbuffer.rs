@@ -4,12 +4,13 @@
 
 //! Traits, etc. related to unbuffering types
 
-use std::num::ParseIntError;
+use crate::alloc_prelude::*;
+use core::num::ParseIntError;
 
 use super::{BufferUnbufferError, ConstantBufferSize, SizeRequirement, WrappedConstantSize};
 use bytes::{Buf, Bytes};
 
-pub type UnbufferResult<T> = std::result::Result<T, BufferUnbufferError>;
+pub type UnbufferResult<T> = core::result::Result<T, BufferUnbufferError>;
 
 /// Trait for types that can be "unbuffered" (parsed from a byte buffer)
 pub trait UnbufferFrom: Sized {
@@ -41,7 +42,7 @@ impl<T: WrappedConstantSize> UnbufferFrom for T {
 pub fn check_unbuffer_remaining<T: Buf>(
     buf: &T,
     required_len: usize,
-) -> std::result::Result<(), BufferUnbufferError> {
+) -> core::result::Result<(), BufferUnbufferError> {
     let bytes_len = buf.remaining();
     if bytes_len < required_len {
         Err(SizeRequirement::Exactly(required_len - bytes_len).into())
@@ -63,7 +64,7 @@ pub fn check_unbuffer_remaining<T: Buf>(
 pub fn consume_expected<T: Buf>(
     buf: &mut T,
     expected: &'static [u8],
-) -> std::result::Result<(), BufferUnbufferError> {
+) -> core::result::Result<(), BufferUnbufferError> {
     let expected_len = expected.len();
     check_unbuffer_remaining(buf, expected_len)?;
 
@@ -106,14 +107,14 @@ impl PeekU32 for Bytes {
 /// assert_eq!(buf.remaining(), 4);
 /// ```
 pub fn peek_u32<T: Buf>(buf: &T) -> Option<u32> {
-    const SIZE_LEN: usize = std::mem::size_of::<u32>();
+    const SIZE_LEN: usize = core::mem::size_of::<u32>();
     if buf.remaining() < SIZE_LEN {
-        eprintln!("Not enough remaining bytes for the size.");
+        trace!("Not enough remaining bytes for the size.");
         return None;
     }
     let mut chunk = buf.chunk();
     if chunk.len() < SIZE_LEN {
-        eprintln!("Not enough remaining bytes in the chunk for the size.");
+        trace!("Not enough remaining bytes in the chunk for the size.");
         // Some(buf.clone().get_u32())
         None
     } else {
@@ -122,7 +123,7 @@ pub fn peek_u32<T: Buf>(buf: &T) -> Option<u32> {
 }
 
 #[inline]
-fn from_dec(input: Bytes) -> std::result::Result<u8, ParseIntError> {
+fn from_dec(input: Bytes) -> core::result::Result<u8, ParseIntError> {
     str::parse::<u8>(&String::from_utf8_lossy(&input))
 }
 
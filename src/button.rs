@@ -0,0 +1,210 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Types related to the `vrpn_Button` device class
+
+use crate::{
+    buffer_unbuffer::{
+        buffer::{check_buffer_remaining, BufferResult, BufferTo},
+        unbuffer::{check_unbuffer_remaining, UnbufferFrom, UnbufferResult},
+        BufferSize, BufferUnbufferError, ConstantBufferSize,
+    },
+    data_types::{
+        id_types::{LocalId, SenderId},
+        message::TypedMessageBody,
+        name_types::StaticMessageTypeName,
+        ClassOfService, MessageTypeIdentifier, SenderName, TimeVal,
+    },
+    handler::HandlerHandle,
+    Connection, Handler, Result, TypedHandler,
+};
+use bytes::{Buf, BufMut};
+use std::{convert::TryFrom, sync::Arc};
+
+/// Most buttons a device may report, as `vrpn_BUTTON_MAX_BUTTONS`.
+pub const MAX_BUTTONS: usize = 256;
+
+/// A button was pressed or released.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ButtonChange {
+    /// Which button changed.
+    pub button: i32,
+    pub pressed: bool,
+}
+
+impl TypedMessageBody for ButtonChange {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(StaticMessageTypeName(b"vrpn_Button Change"));
+}
+
+impl ConstantBufferSize for ButtonChange {
+    fn constant_buffer_size() -> usize {
+        2 * i32::constant_buffer_size()
+    }
+}
+
+impl BufferTo for ButtonChange {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        check_buffer_remaining(buf, Self::constant_buffer_size())?;
+        self.button.buffer_to(buf)?;
+        i32::from(self.pressed).buffer_to(buf)?;
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for ButtonChange {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        check_unbuffer_remaining(buf, Self::constant_buffer_size())?;
+        let button = i32::unbuffer_from(buf)?;
+        let pressed = i32::unbuffer_from(buf)? != 0;
+        Ok(ButtonChange { button, pressed })
+    }
+}
+
+/// The state of every button of a device, e.g. sent to a client when it connects.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ButtonStates {
+    /// Whether each button is pressed, at most `MAX_BUTTONS`.
+    pub pressed: Vec<bool>,
+}
+
+impl TypedMessageBody for ButtonStates {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(StaticMessageTypeName(b"vrpn_Button States"));
+}
+
+impl BufferSize for ButtonStates {
+    fn buffer_size(&self) -> usize {
+        (1 + self.pressed.len()) * i32::constant_buffer_size()
+    }
+}
+
+impl BufferTo for ButtonStates {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        if self.pressed.len() > MAX_BUTTONS {
+            return Err(BufferUnbufferError::OutOfBuffer);
+        }
+        check_buffer_remaining(buf, self.buffer_size())?;
+        (self.pressed.len() as i32).buffer_to(buf)?;
+        for &pressed in &self.pressed {
+            i32::from(pressed).buffer_to(buf)?;
+        }
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for ButtonStates {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        let len = i32::unbuffer_from(buf)?;
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|&len| len <= MAX_BUTTONS)
+            .ok_or_else(|| BufferUnbufferError::ParseError {
+                parsing_kind: "button count".to_string(),
+                s: len.to_string(),
+            })?;
+        check_unbuffer_remaining(buf, len * i32::constant_buffer_size())?;
+        let pressed = (0..len)
+            .map(|_| i32::unbuffer_from(buf).map(|state| state != 0))
+            .collect::<UnbufferResult<Vec<bool>>>()?;
+        Ok(ButtonStates { pressed })
+    }
+}
+
+/// Client side of a button device.
+#[derive(Debug)]
+pub struct ButtonRemote<C: Connection> {
+    connection: Arc<C>,
+    sender: LocalId<SenderId>,
+}
+
+impl<C: Connection> ButtonRemote<C> {
+    pub fn new(connection: Arc<C>, name: impl Into<SenderName>) -> Result<ButtonRemote<C>> {
+        let sender = connection.register_sender(name.into())?;
+        Ok(ButtonRemote { connection, sender })
+    }
+
+    /// The local sender ID of this button device.
+    pub fn sender(&self) -> LocalId<SenderId> {
+        self.sender
+    }
+
+    /// Add a handler for one kind of report from this device: `ButtonChange` or `ButtonStates`.
+    pub fn add_handler<H>(&self, handler: Box<H>) -> Result<HandlerHandle>
+    where
+        H: TypedHandler + Handler + 'static,
+    {
+        self.connection
+            .add_typed_handler(handler, Some(self.sender))
+    }
+}
+
+/// Server side of a button device.
+#[derive(Debug)]
+pub struct ButtonServer<C: Connection> {
+    connection: Arc<C>,
+    sender: LocalId<SenderId>,
+}
+
+impl<C: Connection> ButtonServer<C> {
+    pub fn new(connection: Arc<C>, name: impl Into<SenderName>) -> Result<ButtonServer<C>> {
+        let sender = connection.register_sender(name.into())?;
+        Ok(ButtonServer { connection, sender })
+    }
+
+    /// The local sender ID of this button device.
+    pub fn sender(&self) -> LocalId<SenderId> {
+        self.sender
+    }
+
+    /// Report that a button was pressed or released.
+    pub fn report_change(&self, time: Option<TimeVal>, change: ButtonChange) -> Result<()> {
+        self.connection
+            .pack_message_body(time, self.sender, change, ClassOfService::RELIABLE)
+    }
+
+    /// Report the state of every button.
+    pub fn report_states(&self, time: Option<TimeVal>, states: ButtonStates) -> Result<()> {
+        self.connection
+            .pack_message_body(time, self.sender, states, ClassOfService::RELIABLE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_unbuffer::BytesMutExtras;
+    use bytes::{Bytes, BytesMut};
+
+    #[test]
+    fn wire_format() {
+        let change = ButtonChange {
+            button: 3,
+            pressed: true,
+        };
+        let buf = BytesMut::allocate_and_buffer(change).unwrap().freeze();
+        // int32 button then int32 state, as vrpn_Button::encode_to
+        assert_eq!(&buf[..], &hex!("00000003 00000001")[..]);
+        assert_eq!(
+            ButtonChange::unbuffer_from(&mut buf.clone()).unwrap(),
+            change
+        );
+
+        let states = ButtonStates {
+            pressed: vec![false, true],
+        };
+        let buf = BytesMut::allocate_and_buffer(states.clone())
+            .unwrap()
+            .freeze();
+        assert_eq!(&buf[..], &hex!("00000002 00000000 00000001")[..]);
+        assert_eq!(
+            ButtonStates::unbuffer_from(&mut buf.clone()).unwrap(),
+            states
+        );
+
+        assert!(ButtonStates::unbuffer_from(&mut Bytes::from_static(&hex!("00000101"))).is_err());
+    }
+}
@@ -0,0 +1,420 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Decoding of captured VRPN streams to human-readable text, JSON, or CSV.
+//!
+//! A capture is the raw bytes sent by one side of a TCP connection:
+//! the cookie, followed by messages. Names are resolved from the sender and
+//! type descriptions found earlier in the same capture.
+//! Log files (see `message_log`) are in the same format, with a file cookie.
+//!
+//! The `vrpn-decode` and `vrpn-log` tools (feature `tools`) are thin wrappers around this module.
+//! The annotated streams in `tests/decode_fixtures` were written with this crate's own encoders,
+//! so they check the decoder against this crate, not against C++ VRPN.
+
+use crate::{
+    analog::AnalogChannels,
+    buffer_unbuffer::UnbufferFrom,
+    button::{ButtonChange, ButtonStates},
+    codec::maybe_decode_one,
+    data_types::{
+        constants,
+        id_types::{LocalId, RemoteId, SenderId, SequenceNumber},
+        CookieData, GenericMessage, Message, MessageTypeId, MessageTypeIdentifier, TypedMessage,
+        TypedMessageBody,
+    },
+    dial::DialChange,
+    endpoint::{parse_system_message, ExtendedSystemCommand, SystemCommand},
+    force_device::{ForceReport, ScpReport},
+    poser::{PoseRequest, VelocityRequest},
+    text::TextMessage,
+    tracker::{PoseReport, TrackerToRoom, UnitToSensor, Workspace},
+    translation_table::TranslationTable,
+    Result, TranslationTables,
+};
+use bytes::{Buf, Bytes};
+use std::{
+    convert::TryFrom,
+    fmt,
+    io::{self, Write},
+};
+
+/// A message from a capture, with names resolved where possible.
+#[derive(Debug, Clone)]
+pub struct DecodedMessage {
+    pub message: GenericMessage,
+    pub sequence_number: SequenceNumber,
+    /// The sender name, if this is a user message from a described sender.
+    pub sender_name: Option<Bytes>,
+    /// The message type name, if this is a system message or a described type.
+    pub type_name: Option<Bytes>,
+    /// Human-readable description of the body.
+    pub body: String,
+}
+
+impl fmt::Display for DecodedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = &self.message.header;
+        write!(f, "{} #{} ", header.time, self.sequence_number.0)?;
+        match &self.sender_name {
+            Some(name) => write!(f, "[{}] ", String::from_utf8_lossy(name))?,
+            None if header.message_type.is_system_message() => {}
+            None => write!(f, "[sender {}] ", header.sender.0)?,
+        }
+        match &self.type_name {
+            Some(name) => write!(f, "{}", String::from_utf8_lossy(name))?,
+            None => write!(f, "type {}", header.message_type.0)?,
+        }
+        write!(f, ": {}", self.body)
+    }
+}
+
+/// Name of a system message type, for display.
+pub(crate) fn system_type_name(message_type: MessageTypeId) -> Option<&'static [u8]> {
+    Some(match message_type {
+        constants::SENDER_DESCRIPTION => b"SENDER_DESCRIPTION",
+        constants::TYPE_DESCRIPTION => b"TYPE_DESCRIPTION",
+        constants::UDP_DESCRIPTION => b"UDP_DESCRIPTION",
+        constants::LOG_DESCRIPTION => b"LOG_DESCRIPTION",
+        constants::DISCONNECT_MESSAGE => b"DISCONNECT_MESSAGE",
+        _ => return None,
+    })
+}
+
+/// Whether a typed message body is identified by this message type name.
+fn is_named<T: TypedMessageBody>(name: &[u8]) -> bool {
+    matches!(T::MESSAGE_IDENTIFIER, MessageTypeIdentifier::UserMessageName(n) if n.0 == name)
+}
+
+/// Describe the body of a message of a known type, if it is that type and decodes.
+fn decode_as<T: TypedMessageBody + UnbufferFrom + fmt::Debug>(
+    name: &[u8],
+    message: &GenericMessage,
+) -> Option<String> {
+    if !is_named::<T>(name) {
+        return None;
+    }
+    TypedMessage::<T>::try_from(message)
+        .ok()
+        .map(|typed| format!("{:?}", typed.body))
+}
+
+pub(crate) fn decode_known_body(name: &[u8], message: &GenericMessage) -> Option<String> {
+    decode_as::<PoseReport>(name, message)
+        .or_else(|| decode_as::<TrackerToRoom>(name, message))
+        .or_else(|| decode_as::<UnitToSensor>(name, message))
+        .or_else(|| decode_as::<Workspace>(name, message))
+        .or_else(|| decode_as::<ButtonChange>(name, message))
+        .or_else(|| decode_as::<ButtonStates>(name, message))
+        .or_else(|| decode_as::<AnalogChannels>(name, message))
+        .or_else(|| decode_as::<DialChange>(name, message))
+        .or_else(|| decode_as::<ForceReport>(name, message))
+        .or_else(|| decode_as::<ScpReport>(name, message))
+        .or_else(|| decode_as::<PoseRequest>(name, message))
+        .or_else(|| decode_as::<VelocityRequest>(name, message))
+        .or_else(|| decode_as::<TextMessage>(name, message))
+}
+
+fn hex_body(body: &Bytes) -> String {
+    if body.is_empty() {
+        return "(empty)".to_string();
+    }
+    body.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Decodes the messages of a capture, tracking the descriptions seen so far.
+#[derive(Debug, Default)]
+pub struct CaptureDecoder {
+    tables: TranslationTables,
+}
+
+impl CaptureDecoder {
+    pub fn new() -> CaptureDecoder {
+        CaptureDecoder::default()
+    }
+
+    /// The senders and types described so far.
+    ///
+    /// There is no local side, so local IDs are the same as remote IDs.
+    pub fn tables(&self) -> &TranslationTables {
+        &self.tables
+    }
+
+    /// Decode one message, recording any description it contains.
+    pub fn decode_message(
+        &mut self,
+        message: GenericMessage,
+        sequence_number: SequenceNumber,
+    ) -> Result<DecodedMessage> {
+        let header = message.header.clone();
+        if message.is_system_message() {
+            let body = match parse_system_message(message.clone())? {
+                SystemCommand::SenderDescription(desc) => {
+                    let table: &mut TranslationTable<SenderId> = self.tables.as_mut();
+                    table.add_remote_entry(
+                        desc.name.clone(),
+                        RemoteId(desc.which),
+                        LocalId(desc.which),
+                    )?;
+                    format!("sender {} = {:?}", desc.which.0, desc.name)
+                }
+                SystemCommand::TypeDescription(desc) => {
+                    let table: &mut TranslationTable<MessageTypeId> = self.tables.as_mut();
+                    table.add_remote_entry(
+                        desc.name.clone(),
+                        RemoteId(desc.which),
+                        LocalId(desc.which),
+                    )?;
+                    format!("type {} = {:?}", desc.which.0, desc.name)
+                }
+                SystemCommand::Extended(ExtendedSystemCommand::UdpDescription(desc)) => {
+                    format!("{:?}", desc)
+                }
+                SystemCommand::Extended(ExtendedSystemCommand::LogDescription(names)) => {
+                    format!("{:?}", names)
+                }
+                SystemCommand::Extended(ExtendedSystemCommand::DisconnectMessage) => {
+                    "(empty)".to_string()
+                }
+            };
+            return Ok(DecodedMessage {
+                message,
+                sequence_number,
+                sender_name: None,
+                type_name: system_type_name(header.message_type).map(Bytes::from_static),
+                body,
+            });
+        }
+        let sender_name = self
+            .tables
+            .senders()
+            .find_by_remote_id(RemoteId(header.sender))
+            .map(|mapping| mapping.name);
+        let type_name = self
+            .tables
+            .types()
+            .find_by_remote_id(RemoteId(header.message_type))
+            .map(|mapping| mapping.name);
+        let body = type_name
+            .as_ref()
+            .and_then(|name| decode_known_body(name, &message))
+            .unwrap_or_else(|| hex_body(message.body.as_bytes()));
+        Ok(DecodedMessage {
+            message,
+            sequence_number,
+            sender_name,
+            type_name,
+            body,
+        })
+    }
+}
+
+/// A fully-decoded capture.
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub cookie: CookieData,
+    pub messages: Vec<DecodedMessage>,
+    /// Bytes at the end too short to hold a complete message.
+    pub trailing_bytes: usize,
+}
+
+impl fmt::Display for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "cookie: {}", self.cookie)?;
+        for msg in &self.messages {
+            writeln!(f, "{}", msg)?;
+        }
+        if self.trailing_bytes > 0 {
+            writeln!(f, "({} trailing bytes)", self.trailing_bytes)?;
+        }
+        Ok(())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn name_text(name: &Option<Bytes>) -> Option<String> {
+    name.as_ref()
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+}
+
+impl Capture {
+    /// Write the messages as a JSON array, with one object per message.
+    ///
+    /// Unknown names are `null`.
+    pub fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "[")?;
+        for (i, msg) in self.messages.iter().enumerate() {
+            let header = &msg.message.header;
+            let name = |name: &Option<Bytes>| {
+                name_text(name)
+                    .map(|name| json_string(&name))
+                    .unwrap_or_else(|| "null".to_string())
+            };
+            write!(
+                out,
+                "  {{\"time\": {}, \"sequence\": {}, \"sender_id\": {}, \"sender\": {}, \
+                 \"type_id\": {}, \"type\": {}, \"body\": {}}}",
+                header.time,
+                msg.sequence_number.0,
+                header.sender.0,
+                name(&msg.sender_name),
+                header.message_type.0,
+                name(&msg.type_name),
+                json_string(&msg.body)
+            )?;
+            writeln!(
+                out,
+                "{}",
+                if i + 1 < self.messages.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(out, "]")
+    }
+
+    /// Write the messages as CSV, with a header row, and one row per message.
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "time,sequence,sender_id,sender,type_id,type,body")?;
+        for msg in &self.messages {
+            let header = &msg.message.header;
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{}",
+                header.time,
+                msg.sequence_number.0,
+                header.sender.0,
+                csv_field(&name_text(&msg.sender_name).unwrap_or_default()),
+                header.message_type.0,
+                csv_field(&name_text(&msg.type_name).unwrap_or_default()),
+                csv_field(&msg.body)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode a capture: a cookie, then messages.
+pub fn decode_capture(data: Bytes) -> Result<Capture> {
+    let mut buf = data;
+    let cookie = CookieData::unbuffer_from(&mut buf)?;
+    let mut decoder = CaptureDecoder::new();
+    let mut messages = Vec::new();
+    while buf.has_remaining() {
+        match maybe_decode_one(&mut buf)? {
+            Some(msg) => {
+                let sequence_number = msg.sequence_number;
+                messages.push(decoder.decode_message(msg.into_inner(), sequence_number)?);
+            }
+            None => break,
+        }
+    }
+    Ok(Capture {
+        cookie,
+        messages,
+        trailing_bytes: buf.remaining(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::id_types::Sensor;
+
+    const TRACKER_SERVER: &[u8] = include_bytes!("../tests/decode_fixtures/tracker_server.bin");
+    const TRACKER_CLIENT: &[u8] = include_bytes!("../tests/decode_fixtures/tracker_client.bin");
+
+    #[test]
+    fn tracker_server_fixture() {
+        let capture = decode_capture(Bytes::from_static(TRACKER_SERVER)).unwrap();
+        assert_eq!(capture.cookie.version, constants::MAGIC_DATA);
+        assert_eq!(capture.trailing_bytes, 0);
+        assert_eq!(capture.messages.len(), 12);
+
+        let pose = &capture.messages[10];
+        assert_eq!(pose.sender_name.as_deref(), Some(&b"Tracker0"[..]));
+        assert_eq!(
+            pose.type_name.as_deref(),
+            Some(&b"vrpn_Tracker Pos_Quat"[..])
+        );
+        let typed = TypedMessage::<PoseReport>::try_from(&pose.message).unwrap();
+        assert_eq!(typed.body.sensor, Sensor(1));
+        assert_eq!(typed.body.pos.y, 1.25);
+
+        let text = capture.to_string();
+        assert!(text.starts_with("cookie: vrpn: ver. 07.35"));
+        assert!(text.contains("TYPE_DESCRIPTION: type 4 = b\"vrpn_Tracker Pos_Quat\""));
+        assert!(text.contains("[Tracker0] vrpn_Tracker Velocity: 00 00 00 00"));
+    }
+
+    #[test]
+    fn tracker_client_fixture() {
+        let capture = decode_capture(Bytes::from_static(TRACKER_CLIENT)).unwrap();
+        assert_eq!(capture.messages.len(), 4);
+        assert_eq!(
+            capture.messages[0].body,
+            "sender 0 = b\"Tracker0\"".to_string()
+        );
+        assert_eq!(
+            capture.messages[2].body,
+            "type 1 = b\"vrpn_Tracker Velocity\"".to_string()
+        );
+    }
+
+    #[test]
+    fn json_and_csv() {
+        let capture = decode_capture(Bytes::from_static(TRACKER_SERVER)).unwrap();
+
+        let mut json = Vec::new();
+        capture.write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("[\n  {\"time\": "));
+        assert!(json.trim_end().ends_with("}\n]"));
+        assert_eq!(json.matches("\"sequence\"").count(), 12);
+        assert!(json.contains(
+            "\"sender\": \"Tracker0\", \"type_id\": 4, \"type\": \"vrpn_Tracker Pos_Quat\""
+        ));
+        assert!(json.contains("\"body\": \"sender 1 = b\\\"Tracker0\\\"\""));
+
+        let mut csv = Vec::new();
+        capture.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<_> = csv.lines().collect();
+        assert_eq!(rows.len(), 13);
+        assert_eq!(rows[0], "time,sequence,sender_id,sender,type_id,type,body");
+        assert!(rows[11].contains(",Tracker0,4,vrpn_Tracker Pos_Quat,\"PoseReport {"));
+    }
+
+    #[test]
+    fn truncated() {
+        let data = Bytes::from_static(&TRACKER_SERVER[..TRACKER_SERVER.len() - 10]);
+        let capture = decode_capture(data).unwrap();
+        assert_eq!(capture.messages.len(), 11);
+        assert!(capture.trailing_bytes > 0);
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Overriding the class of service that outgoing messages are sent with,
+//! which decides whether they go on the reliable (TCP) or low-latency (UDP) channel.
+//!
+//! For example, to send everything reliably where UDP is blocked, as over some VPNs,
+//! or to send a chatty message type over UDP although its device asks for reliable delivery.
+
+use crate::{
+    data_types::{id_types::*, ClassOfService, MessageTypeName},
+    Result, TypeDispatcher,
+};
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// Overrides of the class of service of outgoing messages, by message type name.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ClassOfServicePolicy {
+    all_reliable: bool,
+    by_type: HashMap<Bytes, ClassOfService>,
+}
+
+impl ClassOfServicePolicy {
+    /// Send everything on the reliable channel, whatever class it is sent with,
+    /// overriding the other settings.
+    pub fn all_reliable(self) -> ClassOfServicePolicy {
+        ClassOfServicePolicy {
+            all_reliable: true,
+            ..self
+        }
+    }
+
+    /// Send messages of the named type with `class`, instead of the class they are sent with.
+    pub fn with_type(
+        mut self,
+        name: impl Into<MessageTypeName>,
+        class: ClassOfService,
+    ) -> ClassOfServicePolicy {
+        self.by_type.insert(name.into().0, class);
+        self
+    }
+
+    /// Look up the IDs of the message types, registering them if needed.
+    pub fn resolve(&self, dispatcher: &mut TypeDispatcher) -> Result<ClassOfServiceOverrides> {
+        let by_type = self
+            .by_type
+            .iter()
+            .map(|(name, class)| {
+                let id = dispatcher
+                    .register_type(MessageTypeName(name.clone()))?
+                    .into_inner();
+                Ok((id, *class))
+            })
+            .collect::<Result<_>>()?;
+        Ok(ClassOfServiceOverrides {
+            all_reliable: self.all_reliable,
+            by_type,
+        })
+    }
+}
+
+/// A `ClassOfServicePolicy` with the message types looked up, as applied by endpoints.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ClassOfServiceOverrides {
+    all_reliable: bool,
+    by_type: HashMap<LocalId<MessageTypeId>, ClassOfService>,
+}
+
+impl ClassOfServiceOverrides {
+    /// Whether nothing may be sent on the low-latency channel.
+    pub fn is_all_reliable(&self) -> bool {
+        self.all_reliable
+    }
+
+    /// The class to send a message of this type with, given the class asked for.
+    pub fn class_for(
+        &self,
+        message_type: LocalId<MessageTypeId>,
+        class: ClassOfService,
+    ) -> ClassOfService {
+        if self.all_reliable {
+            return class | ClassOfService::RELIABLE;
+        }
+        self.by_type.get(&message_type).copied().unwrap_or(class)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::StaticMessageTypeName;
+
+    #[test]
+    fn overrides() {
+        let mut dispatcher = TypeDispatcher::new();
+        const POSE: StaticMessageTypeName = StaticMessageTypeName(b"vrpn_Tracker Pos_Quat");
+        let overrides = ClassOfServicePolicy::default()
+            .with_type(POSE, ClassOfService::LOW_LATENCY)
+            .resolve(&mut dispatcher)
+            .unwrap();
+        let pose = dispatcher.get_type_id(POSE).unwrap();
+        let other = LocalId(MessageTypeId(pose.0 .0 + 1));
+        assert_eq!(
+            overrides.class_for(pose, ClassOfService::RELIABLE),
+            ClassOfService::LOW_LATENCY
+        );
+        assert_eq!(
+            overrides.class_for(other, ClassOfService::RELIABLE),
+            ClassOfService::RELIABLE
+        );
+
+        let overrides = ClassOfServicePolicy::default()
+            .with_type(POSE, ClassOfService::LOW_LATENCY)
+            .all_reliable()
+            .resolve(&mut dispatcher)
+            .unwrap();
+        assert!(overrides.is_all_reliable());
+        assert!(overrides
+            .class_for(pose, ClassOfService::LOW_LATENCY)
+            .contains(ClassOfService::RELIABLE));
+    }
+}
@@ -0,0 +1,534 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Estimating the offset between the server's clock and ours, as `vrpn_Clock` does,
+//! so timestamps in messages from the server can be compared with local ones.
+//!
+//! The client sends a query stamped with its local time; the server replies with its own time
+//! and the time of the query. From the time the reply arrives, each round trip gives a sample
+//! of the offset, assuming the two directions take about as long. The estimate uses the sample
+//! with the shortest round trip in a window of recent ones, and the drift between the clocks
+//! fitted over that window.
+
+use crate::{
+    buffer_unbuffer::{
+        buffer::{check_buffer_remaining, BufferResult, BufferTo},
+        unbuffer::{check_unbuffer_remaining, UnbufferFrom, UnbufferResult},
+        BufferSize, ConstantBufferSize,
+    },
+    data_types::{
+        id_types::*, name_types::NameIntoBytes, ClassOfService, MessageTypeId,
+        MessageTypeIdentifier, SenderName, StaticMessageTypeName, TimeVal, TypedMessage,
+        TypedMessageBody,
+    },
+    handler::{HandlerCode, HandlerHandle, TypedHandler},
+    sync::Mutex,
+    Connection, VrpnError,
+};
+use bytes::{Buf, BufMut};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+const CLOCK_QUERY: StaticMessageTypeName = StaticMessageTypeName(b"vrpn_Clock query");
+const CLOCK_REPLY: StaticMessageTypeName = StaticMessageTypeName(b"vrpn_Clock reply");
+
+/// Number of recent samples the estimate is based on.
+pub const DEFAULT_WINDOW: usize = 16;
+
+/// Bound on the estimated drift, in parts per million: far beyond any real clock,
+/// but keeps a few noisy samples from producing something absurd.
+const MAX_DRIFT_PPM: f64 = 500.0;
+
+/// Shortest span of samples to estimate drift from.
+const MIN_DRIFT_SPAN_MICROS: i64 = 1_000_000;
+
+/// Query from a client for the server's time.
+///
+/// The query time is in the message header. The server echoes the body back.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ClockQuery {
+    /// Identifies the query, to match it with its reply.
+    pub probe: i32,
+}
+
+impl TypedMessageBody for ClockQuery {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(CLOCK_QUERY);
+}
+
+impl ConstantBufferSize for ClockQuery {
+    fn constant_buffer_size() -> usize {
+        i32::constant_buffer_size()
+    }
+}
+
+impl BufferTo for ClockQuery {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        self.probe.buffer_to(buf)
+    }
+}
+
+impl UnbufferFrom for ClockQuery {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        Ok(ClockQuery {
+            probe: i32::unbuffer_from(buf)?,
+        })
+    }
+}
+
+/// Reply from a server to a `ClockQuery`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ClockReply {
+    /// The server's time when it replied.
+    pub server_time: TimeVal,
+    /// The time in the header of the query.
+    pub query_time: TimeVal,
+    /// Echoed from the query.
+    pub probe: i32,
+}
+
+impl TypedMessageBody for ClockReply {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(CLOCK_REPLY);
+}
+
+impl BufferSize for ClockReply {
+    fn buffer_size(&self) -> usize {
+        TimeVal::constant_buffer_size() * 2 + i32::constant_buffer_size()
+    }
+}
+
+impl BufferTo for ClockReply {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        check_buffer_remaining(buf, self.buffer_size())?;
+        self.server_time.buffer_to(buf)?;
+        self.query_time.buffer_to(buf)?;
+        self.probe.buffer_to(buf)
+    }
+}
+
+impl UnbufferFrom for ClockReply {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        check_unbuffer_remaining(buf, TimeVal::constant_buffer_size() * 2)?;
+        let server_time = TimeVal::unbuffer_from(buf)?;
+        let query_time = TimeVal::unbuffer_from(buf)?;
+        // Other clients may have echoed something else, or nothing.
+        let probe = if buf.remaining() >= i32::constant_buffer_size() {
+            i32::unbuffer_from(buf)?
+        } else {
+            0
+        };
+        buf.advance(buf.remaining());
+        Ok(ClockReply {
+            server_time,
+            query_time,
+            probe,
+        })
+    }
+}
+
+/// One round trip of a clock query.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ClockSample {
+    /// Local time the query was sent.
+    pub sent: TimeVal,
+    /// Server time the reply was sent.
+    pub server: TimeVal,
+    /// Local time the reply was received.
+    pub received: TimeVal,
+}
+
+impl ClockSample {
+    fn round_trip_micros(&self) -> i64 {
+        self.received.as_micros() - self.sent.as_micros()
+    }
+
+    /// Local time halfway through the round trip, when the server is assumed to have replied.
+    fn midpoint_micros(&self) -> i64 {
+        (self.sent.as_micros() + self.received.as_micros()) / 2
+    }
+
+    /// Server time minus local time.
+    fn offset_micros(&self) -> i64 {
+        self.server.as_micros() - self.midpoint_micros()
+    }
+}
+
+/// The estimated relation between the server's clock and ours.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockEstimate {
+    /// Server time minus local time, at `reference`.
+    offset_micros: i64,
+    /// Local time, in microseconds, at which `offset_micros` was measured.
+    reference: i64,
+    /// How much faster the server clock runs than ours, as a fraction.
+    drift: f64,
+    /// Round trip of the sample the offset comes from: its uncertainty is half this.
+    round_trip: Duration,
+}
+
+impl ClockEstimate {
+    fn offset_at(&self, local_micros: i64) -> i64 {
+        self.offset_micros + ((local_micros - self.reference) as f64 * self.drift).round() as i64
+    }
+
+    /// Server time minus local time, at the reference point.
+    pub fn offset(&self) -> Duration {
+        Duration::from_micros(self.offset_micros.unsigned_abs())
+    }
+
+    /// Whether the server clock is behind ours.
+    pub fn server_is_behind(&self) -> bool {
+        self.offset_micros < 0
+    }
+
+    /// How much faster the server clock runs than ours, in parts per million.
+    pub fn drift_ppm(&self) -> f64 {
+        self.drift * 1e6
+    }
+
+    /// The round trip of the best sample: the offset is accurate to within half of this.
+    pub fn round_trip(&self) -> Duration {
+        self.round_trip
+    }
+
+    /// Convert a server timestamp to local time.
+    pub fn server_to_local(&self, time: TimeVal) -> TimeVal {
+        let server = time.as_micros();
+        // The drift term is tiny, so evaluating it at the server time is close enough.
+        TimeVal::from_micros(server - self.offset_at(server - self.offset_micros))
+    }
+
+    /// Convert a local timestamp to server time.
+    pub fn local_to_server(&self, time: TimeVal) -> TimeVal {
+        let local = time.as_micros();
+        TimeVal::from_micros(local + self.offset_at(local))
+    }
+}
+
+/// Accumulates clock samples into an estimate.
+#[derive(Debug, Clone)]
+pub struct ClockSync {
+    samples: VecDeque<ClockSample>,
+    window: usize,
+    estimate: Option<ClockEstimate>,
+}
+
+impl Default for ClockSync {
+    fn default() -> Self {
+        ClockSync::new(DEFAULT_WINDOW)
+    }
+}
+
+impl ClockSync {
+    /// Create, basing the estimate on the given number of recent samples.
+    pub fn new(window: usize) -> ClockSync {
+        ClockSync {
+            samples: VecDeque::with_capacity(window),
+            window: window.max(1),
+            estimate: None,
+        }
+    }
+
+    /// The current estimate, if any samples have been added.
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        self.estimate
+    }
+
+    /// Forget all samples, e.g. on reconnecting to a different server.
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.estimate = None;
+    }
+
+    /// Add the result of a round trip, updating the estimate.
+    ///
+    /// Samples with a negative round trip (the local clock stepped back) are ignored.
+    pub fn add_sample(&mut self, sample: ClockSample) {
+        if sample.round_trip_micros() < 0 {
+            warn!(
+                "Ignoring clock sample with negative round trip: {:?}",
+                sample
+            );
+            return;
+        }
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        self.estimate = self.compute_estimate();
+    }
+
+    fn compute_estimate(&self) -> Option<ClockEstimate> {
+        let best = self
+            .samples
+            .iter()
+            .min_by_key(|sample| sample.round_trip_micros())?;
+        Some(ClockEstimate {
+            offset_micros: best.offset_micros(),
+            reference: best.midpoint_micros(),
+            drift: self.fit_drift(),
+            round_trip: Duration::from_micros(best.round_trip_micros() as u64),
+        })
+    }
+
+    /// Least-squares slope of offset against local time.
+    fn fit_drift(&self) -> f64 {
+        let first = match self.samples.front() {
+            Some(sample) => sample.midpoint_micros(),
+            None => return 0.0,
+        };
+        let span = self.samples.back().unwrap().midpoint_micros() - first;
+        if span < MIN_DRIFT_SPAN_MICROS {
+            return 0.0;
+        }
+        let n = self.samples.len() as f64;
+        let points = || {
+            self.samples.iter().map(|sample| {
+                (
+                    (sample.midpoint_micros() - first) as f64,
+                    sample.offset_micros() as f64,
+                )
+            })
+        };
+        let mean_x = points().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points().map(|(_, y)| y).sum::<f64>() / n;
+        let (cov, var) = points().fold((0.0, 0.0), |(cov, var), (x, y)| {
+            (
+                cov + (x - mean_x) * (y - mean_y),
+                var + (x - mean_x) * (x - mean_x),
+            )
+        });
+        if var == 0.0 {
+            return 0.0;
+        }
+        let max = MAX_DRIFT_PPM / 1e6;
+        (cov / var).clamp(-max, max)
+    }
+}
+
+struct ReplyHandler {
+    clock: Weak<Mutex<ClockSync>>,
+}
+
+impl std::fmt::Debug for ReplyHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ReplyHandler").finish()
+    }
+}
+
+impl TypedHandler for ReplyHandler {
+    type Item = ClockReply;
+    fn handle_typed(&mut self, msg: &TypedMessage<ClockReply>) -> Result<HandlerCode, VrpnError> {
+        match self.clock.upgrade() {
+            Some(clock) => {
+                // Handlers run right after a message arrives, so this is close enough.
+                let received = TimeVal::get_time_of_day();
+                clock.lock().add_sample(ClockSample {
+                    sent: msg.body.query_time,
+                    server: msg.body.server_time,
+                    received,
+                });
+                Ok(HandlerCode::ContinueProcessing)
+            }
+            None => Ok(HandlerCode::RemoveThisHandler),
+        }
+    }
+}
+
+/// Client side of clock synchronization: sends queries,
+/// and feeds the replies into the connection's `ClockSync`.
+///
+/// Call `send_query` periodically, e.g. every second, to keep the estimate fresh.
+pub struct Client<T: Connection + 'static> {
+    connection: Arc<T>,
+    query_type: LocalId<MessageTypeId>,
+    sender: LocalId<SenderId>,
+    next_probe: i32,
+}
+
+impl<T: Connection + 'static> Client<T> {
+    pub fn new(sender: LocalId<SenderId>, connection: Arc<T>) -> Result<Client<T>, VrpnError> {
+        let query_type = connection.register_type(CLOCK_QUERY)?;
+        let _ = connection.add_typed_handler(
+            Box::new(ReplyHandler {
+                clock: Arc::downgrade(&connection.connection_core().clock_sync()),
+            }),
+            Some(sender),
+        )?;
+        Ok(Client {
+            connection,
+            query_type,
+            sender,
+            next_probe: 0,
+        })
+    }
+
+    pub fn new_from_name(
+        sender: impl Into<SenderName> + NameIntoBytes + Clone,
+        connection: Arc<T>,
+    ) -> Result<Client<T>, VrpnError> {
+        let sender_id = connection.register_sender(sender)?;
+        Self::new(sender_id, connection)
+    }
+
+    /// Send a query to the server, stamped with the current time.
+    pub fn send_query(&mut self) -> Result<(), VrpnError> {
+        let probe = self.next_probe;
+        self.next_probe = self.next_probe.wrapping_add(1);
+        let msg = TypedMessage::new(None, self.query_type, self.sender, ClockQuery { probe });
+        self.connection.pack_message(msg, ClassOfService::RELIABLE)
+    }
+}
+
+#[derive(Debug)]
+struct QueryHandler<T: Connection> {
+    connection: Weak<T>,
+    reply_type: LocalId<MessageTypeId>,
+    sender: LocalId<SenderId>,
+}
+
+impl<T: Connection + Send> TypedHandler for QueryHandler<T> {
+    type Item = ClockQuery;
+    fn handle_typed(&mut self, msg: &TypedMessage<ClockQuery>) -> Result<HandlerCode, VrpnError> {
+        match self.connection.upgrade() {
+            Some(connection) => {
+                let now = TimeVal::get_time_of_day();
+                let reply = ClockReply {
+                    server_time: now,
+                    query_time: msg.header.time,
+                    probe: msg.body.probe,
+                };
+                let msg = TypedMessage::new(Some(now), self.reply_type, self.sender, reply);
+                connection.pack_message(msg, ClassOfService::RELIABLE)?;
+                Ok(HandlerCode::ContinueProcessing)
+            }
+            None => Ok(HandlerCode::RemoveThisHandler),
+        }
+    }
+}
+
+/// Server side of clock synchronization: replies to queries with the current time.
+#[derive(Debug)]
+pub struct Server {
+    handler: HandlerHandle,
+}
+
+impl Server {
+    pub fn new<T: Connection + 'static>(
+        sender: LocalId<SenderId>,
+        connection: Arc<T>,
+    ) -> Result<Server, VrpnError> {
+        let reply_type = connection.register_type(CLOCK_REPLY)?;
+        let handler = connection.add_typed_handler(
+            Box::new(QueryHandler {
+                connection: Arc::downgrade(&connection),
+                reply_type,
+                sender,
+            }),
+            Some(sender),
+        )?;
+        Ok(Server { handler })
+    }
+
+    pub fn new_from_name<T: Connection + 'static>(
+        sender: impl Into<SenderName> + NameIntoBytes + Clone,
+        connection: Arc<T>,
+    ) -> Result<Server, VrpnError> {
+        let sender_id = connection.register_sender(sender)?;
+        Self::new(sender_id, connection)
+    }
+
+    /// The handle of the query handler, to remove it.
+    pub fn handler(&self) -> HandlerHandle {
+        self.handler
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{buffer_unbuffer::BytesMutExtras, data_types::Microseconds};
+    use bytes::BytesMut;
+
+    fn sample(sent: i64, server: i64, received: i64) -> ClockSample {
+        ClockSample {
+            sent: TimeVal::from_micros(sent),
+            server: TimeVal::from_micros(server),
+            received: TimeVal::from_micros(received),
+        }
+    }
+
+    #[test]
+    fn micros_roundtrip() {
+        for micros in &[
+            0,
+            1,
+            999_999,
+            1_000_000,
+            -1,
+            -1_000_001,
+            1_650_000_000_123_456,
+        ] {
+            assert_eq!(TimeVal::from_micros(*micros).as_micros(), *micros);
+        }
+        assert_eq!(
+            TimeVal::from_micros(-1).microseconds(),
+            Microseconds(999_999)
+        );
+    }
+
+    #[test]
+    fn best_round_trip_wins() {
+        let mut clock = ClockSync::default();
+        assert!(clock.estimate().is_none());
+        // Server is 5 seconds ahead.
+        clock.add_sample(sample(1_000_000, 6_002_000, 1_010_000));
+        clock.add_sample(sample(1_100_000, 6_101_000, 1_102_000));
+        let estimate = clock.estimate().unwrap();
+        assert_eq!(estimate.round_trip(), Duration::from_millis(2));
+        assert_eq!(estimate.offset(), Duration::from_secs(5));
+        assert!(!estimate.server_is_behind());
+        assert_eq!(estimate.drift_ppm(), 0.0);
+
+        let server = TimeVal::from_micros(7_000_000);
+        let local = estimate.server_to_local(server);
+        assert_eq!(local, TimeVal::from_micros(2_000_000));
+        assert_eq!(estimate.local_to_server(local), server);
+    }
+
+    #[test]
+    fn drift() {
+        let mut clock = ClockSync::default();
+        // Server gains 100us per second.
+        for i in 0..10 {
+            let local = i * 1_000_000;
+            clock.add_sample(sample(local, local + i * 100, local));
+        }
+        let estimate = clock.estimate().unwrap();
+        assert!((estimate.drift_ppm() - 100.0).abs() < 1e-6);
+        let local = estimate.server_to_local(TimeVal::from_micros(20_000_000 + 2_000));
+        assert!((local.as_micros() - 20_000_000).abs() <= 1);
+
+        clock.reset();
+        assert!(clock.estimate().is_none());
+        clock.add_sample(sample(10, 0, 5));
+        assert!(clock.estimate().is_none());
+    }
+
+    #[test]
+    fn reply_roundtrip() {
+        let reply = ClockReply {
+            server_time: TimeVal::from_micros(5_000_001),
+            query_time: TimeVal::from_micros(3_000_002),
+            probe: 7,
+        };
+        let mut buf = BytesMut::allocate_and_buffer(reply).unwrap().freeze();
+        assert_eq!(buf.len(), reply.buffer_size());
+        assert_eq!(ClockReply::unbuffer_from(&mut buf).unwrap(), reply);
+    }
+}
@@ -2,13 +2,31 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
-use bytes::Buf;
+//! VRPN message framing, for building transports on top of the message layer.
+//!
+//! `MessageCodec` turns a byte stream into `SequencedGenericMessage`s and back.
+//! With the `tokio-util` feature, it implements
+//! `tokio_util::codec::{Decoder, Encoder}`, so it can be used with `Framed`
+//! over any `AsyncRead + AsyncWrite`. With the `asynchronous-codec` feature,
+//! it implements that crate's `Decoder` and `Encoder`, for use with its `Framed`
+//! over `futures::io` streams. `vrpn_async::MessageStream` also decodes with this codec.
+//! Compressed frames are unpacked as they are decoded, with the `compression` feature,
+//! once compression has been agreed with the peer: see `set_compressed_frames`.
+
+use bytes::{Buf, BufMut, BytesMut};
+use std::collections::VecDeque;
 
 use crate::{
-    buffer_unbuffer::{BufferUnbufferError, UnbufferResult},
-    data_types::SequencedGenericMessage,
+    buffer_unbuffer::{
+        constants::ALIGN, padding_to, BufferSize, BufferUnbufferError, UnbufferResult,
+    },
+    compatibility::{CompatibilityProfile, PaddingPolicy, WireConfig},
+    data_types::{MessageSize, SequencedGenericMessage, DEFAULT_MAX_MESSAGE_SIZE},
+    Result, VrpnError,
 };
 
+use crate::compression::{decompress_frame, COMPRESSED_FRAME};
+
 /// Decode at most 1 message. Returns Ok(None) if we don't have enough data.
 pub(crate) fn maybe_decode_one<T: Buf + Clone>(
     buf: &mut T,
@@ -21,30 +39,410 @@ pub(crate) fn maybe_decode_one<T: Buf + Clone>(
     }
 }
 
-// pub(crate) fn decode_one_mut(buf: &mut BytesMut) -> Result<Option<SequencedGenericMessage>> {
-//     let initial_len = buf.len();
-//     if let Some(combined_size) = peek_u32_bytes_mut(buf)? {
-//         let mut inner_buf = buf.clone();
-//         let size = MessageSize::from_length_field(combined_size);
-//         if initial_len < size.padded_message_size() {
-//             return Ok(None);
-//         }
-//         let mut taken_buf = inner_buf.split_to(size.padded_message_size());
-//         let unbuffered = SequencedGenericMessage::unbuffer_ref(&mut taken_buf);
-//         match unbuffered {
-//             Ok(v) => {
-//                 Buf::advance(&mut buf, size.padded_message_size());
-//                 Ok(Some(v))
-//             }
-//             Err(BufferUnbufferError::NeedMoreData(_)) => {
-//                 unreachable!();
-//             }
-//             Err(e) => Err(e),
-//         }
-//     } else {
-//         Ok(None)
-//     }
-// }
+/// What a codec does when the stream stops making sense as messages.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum FramingRecovery {
+    /// Return an error, so the endpoint is closed.
+    #[default]
+    Close,
+    /// Skip ahead to the next plausible message header, and carry on from there.
+    ///
+    /// A message over the size limit is taken to be corruption, rather than skipped whole.
+    Resync,
+}
+
+/// Corrupt framing found in a byte stream, and how much was discarded to get past it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FramingError {
+    /// What was wrong with the data.
+    pub reason: String,
+    /// Bytes skipped to find the next plausible message (0 if the stream was abandoned).
+    pub skipped: usize,
+}
+
+/// Bytes of a header checked for plausibility: the length field and timestamp.
+const PLAUSIBLE_HEADER_LEN: usize = 12;
+
+/// Codec providing VRPN message framing.
+///
+/// All partial data stays in the caller's buffer: the state is the settings to decode with,
+/// what is left to skip of an oversize or corrupt message,
+/// the framing errors found but not yet taken,
+/// whether compressed frames are accepted,
+/// and the messages unpacked from a compressed frame but not yet returned.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MessageCodec {
+    profile: CompatibilityProfile,
+    wire: WireConfig,
+    max_message_size: usize,
+    recovery: FramingRecovery,
+    discarding: usize,
+    resync: Option<FramingError>,
+    framing_errors: Vec<FramingError>,
+    compressed_frames: bool,
+    unpacked: VecDeque<SequencedGenericMessage>,
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        MessageCodec {
+            profile: CompatibilityProfile::default(),
+            wire: WireConfig::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            recovery: FramingRecovery::default(),
+            discarding: 0,
+            resync: None,
+            framing_errors: Vec::new(),
+            compressed_frames: false,
+            unpacked: VecDeque::new(),
+        }
+    }
+}
+
+impl MessageCodec {
+    pub fn new() -> MessageCodec {
+        MessageCodec::default()
+    }
+
+    /// Create a codec that decodes according to a compatibility profile.
+    pub fn with_profile(profile: CompatibilityProfile) -> MessageCodec {
+        MessageCodec {
+            profile,
+            wire: profile.wire_config(),
+            ..MessageCodec::default()
+        }
+    }
+
+    /// Use different framing than the profile's: alignment and padding policy.
+    pub fn with_wire_config(self, wire: WireConfig) -> MessageCodec {
+        MessageCodec { wire, ..self }
+    }
+
+    /// Use a different limit on the padded size of incoming messages.
+    pub fn with_max_message_size(self, max_message_size: usize) -> MessageCodec {
+        MessageCodec {
+            max_message_size,
+            ..self
+        }
+    }
+
+    /// Use a different way of recovering from corrupt framing.
+    pub fn with_framing_recovery(self, recovery: FramingRecovery) -> MessageCodec {
+        MessageCodec { recovery, ..self }
+    }
+
+    /// Accept compressed frames, as once compression has been agreed with the peer.
+    pub fn with_compressed_frames(self, compressed_frames: bool) -> MessageCodec {
+        MessageCodec {
+            compressed_frames,
+            ..self
+        }
+    }
+
+    pub fn profile(&self) -> CompatibilityProfile {
+        self.profile
+    }
+
+    pub fn wire_config(&self) -> WireConfig {
+        self.wire
+    }
+
+    pub fn framing_recovery(&self) -> FramingRecovery {
+        self.recovery
+    }
+
+    /// Change the way of recovering from corrupt framing.
+    pub fn set_framing_recovery(&mut self, recovery: FramingRecovery) {
+        self.recovery = recovery;
+    }
+
+    /// Take the framing errors found since the last call.
+    pub fn take_framing_errors(&mut self) -> Vec<FramingError> {
+        std::mem::take(&mut self.framing_errors)
+    }
+
+    pub fn compressed_frames(&self) -> bool {
+        self.compressed_frames
+    }
+
+    /// Change whether compressed frames are accepted: a peer only sends them
+    /// once both sides have offered compression. Otherwise they are an error.
+    pub fn set_compressed_frames(&mut self, compressed_frames: bool) {
+        self.compressed_frames = compressed_frames;
+    }
+
+    /// Whether the start of `src` could be a message header: a sane length and timestamp.
+    ///
+    /// Requires at least `PLAUSIBLE_HEADER_LEN` bytes.
+    fn is_plausible_header(&self, src: &[u8]) -> bool {
+        let field = |i: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&src[i * 4..i * 4 + 4]);
+            i32::from_be_bytes(bytes)
+        };
+        let plausible_size = MessageSize::try_from_length_field(field(0) as u32)
+            .is_ok_and(|size| size.padded_message_size() <= self.max_message_size);
+        plausible_size && field(1) >= 0 && (0..1_000_000).contains(&field(2))
+    }
+
+    /// Skip bytes until the start of `src` is a plausible header.
+    ///
+    /// Messages are padded to the wire alignment, so only aligned offsets are tried.
+    /// Returns `false` if more data is needed to find one,
+    /// or to skip a whole alignment step past an implausible one.
+    fn resync(&mut self, src: &mut BytesMut) -> bool {
+        let mut error = match self.resync.take() {
+            Some(error) => error,
+            None => return true,
+        };
+        let alignment = self.wire.alignment();
+        let needed = PLAUSIBLE_HEADER_LEN.max(alignment);
+        while src.len() >= needed && !self.is_plausible_header(src) {
+            src.advance(alignment);
+            error.skipped += alignment;
+        }
+        if src.len() < needed {
+            self.resync = Some(error);
+            return false;
+        }
+        warn!(
+            "Skipped {} bytes to resynchronize after {}",
+            error.skipped, error.reason
+        );
+        self.framing_errors.push(error);
+        true
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Change the limit on the padded size of incoming messages.
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// Decode one message from the front of `src`, removing its bytes.
+    ///
+    /// Returns `Ok(None)`, leaving `src` untouched, if there is not yet a whole message.
+    /// Messages over the size limit are skipped, as their bytes arrive,
+    /// and decoding resumes with the message after them.
+    /// Corrupt framing is handled according to the `FramingRecovery`,
+    /// and recorded to be returned by `take_framing_errors`.
+    /// Compressed frames are unpacked if accepted, and are an error otherwise.
+    pub fn decode_from(&mut self, src: &mut BytesMut) -> Result<Option<SequencedGenericMessage>> {
+        loop {
+            if let Some(msg) = self.unpacked.pop_front() {
+                return Ok(Some(msg));
+            }
+            match self.decode_one(src)? {
+                Some(msg) if msg.message().header.message_type == COMPRESSED_FRAME => {
+                    if !self.compressed_frames {
+                        return Err(VrpnError::Incompatible(
+                            "compressed frame, without compression agreed".to_string(),
+                        ));
+                    }
+                    self.unpack(msg)?
+                }
+                msg => return Ok(msg),
+            }
+        }
+    }
+
+    /// Decode the messages in a compressed frame, to be returned next.
+    fn unpack(&mut self, frame: SequencedGenericMessage) -> Result<()> {
+        let data = decompress_frame(frame.message().body.as_bytes(), self.max_message_size)?;
+        self.unpacked = self.decode_frame(&data)?.into();
+        Ok(())
+    }
+
+    fn decode_one(&mut self, src: &mut BytesMut) -> Result<Option<SequencedGenericMessage>> {
+        loop {
+            if self.discarding > 0 {
+                let skip = self.discarding.min(src.len());
+                src.advance(skip);
+                self.discarding -= skip;
+                if self.discarding > 0 {
+                    return Ok(None);
+                }
+            }
+            if !self.resync(src) || src.is_empty() {
+                return Ok(None);
+            }
+            if self.recovery == FramingRecovery::Resync
+                && src.len() >= PLAUSIBLE_HEADER_LEN
+                && !self.is_plausible_header(src)
+            {
+                self.resync = Some(FramingError {
+                    reason: "an implausible message header".to_string(),
+                    skipped: 0,
+                });
+                continue;
+            }
+            let mut remaining: &[u8] = &src[..];
+            let alignment = self.wire.alignment();
+            match SequencedGenericMessage::try_read_from_buf_aligned(
+                &mut remaining,
+                self.max_message_size,
+                alignment,
+            ) {
+                Ok(msg) => return self.finish_decode(src, remaining.len(), msg),
+                Err(BufferUnbufferError::NeedMoreData(_)) => return Ok(None),
+                Err(e) if self.recovery == FramingRecovery::Resync => {
+                    if src.len() < alignment {
+                        return Ok(None);
+                    }
+                    src.advance(alignment);
+                    self.resync = Some(FramingError {
+                        reason: e.to_string(),
+                        skipped: alignment,
+                    });
+                }
+                Err(BufferUnbufferError::MessageTooLarge { size, max }) => {
+                    warn!(
+                        "Skipping a message of {} bytes, over the limit of {}",
+                        size, max
+                    );
+                    self.discarding = size;
+                }
+                Err(e) => {
+                    self.framing_errors.push(FramingError {
+                        reason: e.to_string(),
+                        skipped: 0,
+                    });
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    fn finish_decode(
+        &self,
+        src: &mut BytesMut,
+        remaining: usize,
+        msg: SequencedGenericMessage,
+    ) -> Result<Option<SequencedGenericMessage>> {
+        let consumed = src.len() - remaining;
+        if self.wire.padding() == PaddingPolicy::Strict {
+            let body_len = msg.message().body.as_bytes().len();
+            let body_padding = padding_to(body_len, self.wire.alignment());
+            let body_start = consumed - body_padding - body_len;
+            // Header padding beyond the sequence number, with an alignment over `ALIGN`.
+            let header_padding =
+                &src[MessageSize::from_unpadded_body_size(0).padded_message_size()..body_start];
+            let body_padding = &src[consumed - body_padding..consumed];
+            if header_padding.iter().chain(body_padding).any(|&b| b != 0) {
+                return Err(VrpnError::Incompatible(
+                    "non-zero padding in message".to_string(),
+                ));
+            }
+        }
+        src.advance(consumed);
+        Ok(Some(msg))
+    }
+
+    /// Decode every message in a buffer holding only whole messages, like a datagram.
+    ///
+    /// The frame is decoded on its own, with this codec's settings but none of its state.
+    /// Bytes left over after the last message are an error,
+    /// unless the padding policy is `Lenient`, which drops them as trailing garbage.
+    /// A compressed frame within is always an error: they are only sent on their own over TCP.
+    pub fn decode_frame(&self, frame: &[u8]) -> Result<Vec<SequencedGenericMessage>> {
+        let mut codec = MessageCodec {
+            profile: self.profile,
+            wire: self.wire,
+            max_message_size: self.max_message_size,
+            ..MessageCodec::default()
+        };
+        let mut buf = BytesMut::from(frame);
+        let mut messages = Vec::new();
+        // Bytes up to the end of the last message: an oversize one is skipped, not decoded.
+        let mut decoded = 0;
+        loop {
+            match codec.decode_one(&mut buf) {
+                Ok(Some(msg)) if msg.message().header.message_type == COMPRESSED_FRAME => {
+                    return Err(VrpnError::Incompatible(
+                        "compressed frame within a frame".to_string(),
+                    ));
+                }
+                Ok(Some(msg)) => {
+                    messages.push(msg);
+                    decoded = frame.len() - buf.len();
+                }
+                Ok(None) => break,
+                Err(e) if self.wire.padding() == PaddingPolicy::Lenient => {
+                    trace!("Dropping trailing garbage: {}", e);
+                    return Ok(messages);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let leftover = frame.len() - decoded;
+        if leftover == 0 {
+            Ok(messages)
+        } else if self.wire.padding() == PaddingPolicy::Lenient {
+            trace!("Dropping {} bytes of trailing garbage", leftover);
+            Ok(messages)
+        } else {
+            Err(VrpnError::Incompatible(format!(
+                "{} bytes left over after the last message",
+                leftover
+            )))
+        }
+    }
+
+    /// Append the wire form of a message to `dst`.
+    pub fn encode_into(&mut self, item: SequencedGenericMessage, dst: &mut BytesMut) -> Result<()> {
+        if self.wire.alignment() != ALIGN {
+            dst.put(item.try_into_buf_aligned(self.wire.alignment())?);
+            return Ok(());
+        }
+        dst.reserve(item.buffer_size());
+        let buf = item.try_into_buf()?;
+        dst.put(buf);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl tokio_util::codec::Decoder for MessageCodec {
+    type Item = SequencedGenericMessage;
+    type Error = VrpnError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
+        self.decode_from(src)
+    }
+}
+
+#[cfg(feature = "tokio-util")]
+impl tokio_util::codec::Encoder<SequencedGenericMessage> for MessageCodec {
+    type Error = VrpnError;
+
+    fn encode(&mut self, item: SequencedGenericMessage, dst: &mut BytesMut) -> Result<()> {
+        self.encode_into(item, dst)
+    }
+}
+
+#[cfg(feature = "asynchronous-codec")]
+impl asynchronous_codec::Decoder for MessageCodec {
+    type Item = SequencedGenericMessage;
+    type Error = VrpnError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
+        self.decode_from(src)
+    }
+}
+
+#[cfg(feature = "asynchronous-codec")]
+impl asynchronous_codec::Encoder for MessageCodec {
+    type Item = SequencedGenericMessage;
+    type Error = VrpnError;
+
+    fn encode(&mut self, item: SequencedGenericMessage, dst: &mut BytesMut) -> Result<()> {
+        self.encode_into(item, dst)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use bytes::Bytes;
@@ -110,4 +508,301 @@ mod tests {
             assert_eq!(data.len(), 0);
         }
     }
+
+    #[test]
+    fn codec_roundtrip() {
+        use crate::data_types::{
+            id_types::{MessageTypeId, SenderId, SequenceNumber},
+            GenericBody, GenericMessage, Message, MessageHeader, TimeVal,
+        };
+        let msg = GenericMessage::from_header_and_body(
+            MessageHeader::new(Some(TimeVal::default()), MessageTypeId(3), SenderId(1)),
+            GenericBody::new(Bytes::from_static(b"hello")),
+        )
+        .into_sequenced_message(SequenceNumber(7));
+
+        let mut codec = MessageCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode_into(msg.clone(), &mut buf).unwrap();
+        codec.encode_into(msg.clone(), &mut buf).unwrap();
+        let one_len = buf.len() / 2;
+        assert_eq!(one_len % 8, 0);
+
+        // A partial message is left alone.
+        let mut partial = BytesMut::from(&buf[..one_len - 1]);
+        assert!(codec.decode_from(&mut partial).unwrap().is_none());
+        assert_eq!(partial.len(), one_len - 1);
+
+        assert_eq!(codec.decode_from(&mut buf).unwrap(), Some(msg.clone()));
+        assert_eq!(codec.decode_from(&mut buf).unwrap(), Some(msg));
+        assert!(buf.is_empty());
+        assert!(codec.decode_from(&mut buf).unwrap().is_none());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn unpack_compressed() {
+        use crate::{
+            compression::compress_frame,
+            data_types::{
+                id_types::{MessageTypeId, SenderId, SequenceNumber},
+                GenericBody, GenericMessage, Message, MessageHeader, TimeVal,
+            },
+        };
+        let messages: Vec<_> = (1..=20)
+            .map(|seq| {
+                GenericMessage::from_header_and_body(
+                    MessageHeader::new(Some(TimeVal::default()), MessageTypeId(3), SenderId(1)),
+                    GenericBody::new(Bytes::from_static(b"hello")),
+                )
+                .into_sequenced_message(SequenceNumber(seq))
+            })
+            .collect();
+        let mut codec = MessageCodec::new().with_compressed_frames(true);
+        let mut plain = BytesMut::new();
+        for msg in &messages {
+            codec.encode_into(msg.clone(), &mut plain).unwrap();
+        }
+        let frame = compress_frame(&plain).unwrap();
+
+        // Not until compression has been agreed.
+        let mut buf = BytesMut::from(&frame[..]);
+        assert!(MessageCodec::new().decode_from(&mut buf).is_err());
+
+        let mut buf = BytesMut::from(&frame[..]);
+        buf.extend_from_slice(&plain[..plain.len() / 20]);
+        for msg in messages.iter().chain(&messages[..1]) {
+            assert_eq!(codec.decode_from(&mut buf).unwrap().as_ref(), Some(msg));
+        }
+        assert!(buf.is_empty());
+
+        // Unpacked sizes count against the limit.
+        let mut codec = MessageCodec::new()
+            .with_compressed_frames(true)
+            .with_max_message_size(plain.len() - 1);
+        let mut buf = BytesMut::from(&frame[..]);
+        assert!(codec.decode_from(&mut buf).is_err());
+
+        // Nor are frames unpacked from within frames, or from datagrams.
+        let mut nested = frame.to_vec();
+        nested.extend_from_slice(&plain);
+        let nested = compress_frame(&nested).unwrap();
+        let mut codec = MessageCodec::new().with_compressed_frames(true);
+        assert!(codec.decode_from(&mut BytesMut::from(&nested[..])).is_err());
+        assert!(codec.decode_frame(&frame).is_err());
+    }
+
+    #[cfg(feature = "asynchronous-codec")]
+    #[test]
+    fn asynchronous_codec_framed() {
+        use crate::data_types::{
+            id_types::{MessageTypeId, SenderId, SequenceNumber},
+            GenericBody, GenericMessage, Message, MessageHeader, TimeVal,
+        };
+        use asynchronous_codec::Framed;
+        use futures::{executor::block_on, io::Cursor, SinkExt, StreamExt};
+        let msg = GenericMessage::from_header_and_body(
+            MessageHeader::new(Some(TimeVal::default()), MessageTypeId(3), SenderId(1)),
+            GenericBody::new(Bytes::from_static(b"hello")),
+        )
+        .into_sequenced_message(SequenceNumber(7));
+
+        let mut framed = Framed::new(Cursor::new(Vec::new()), MessageCodec::new());
+        block_on(framed.send(msg.clone())).unwrap();
+        let written = framed.into_inner().into_inner();
+
+        let mut framed = Framed::new(Cursor::new(written), MessageCodec::new());
+        assert_eq!(block_on(framed.next()).unwrap().unwrap(), msg);
+        assert!(block_on(framed.next()).is_none());
+    }
+
+    #[test]
+    fn strict_padding() {
+        // From individual_decode_one: body is 13 bytes, so 3 bytes of padding.
+        let mut bytes = BytesMut::from(
+            &hex!(
+                "00 00 00 25 5b eb 33 2e 00 0c 58 b1 00 00 00 01 ff ff ff ff 00 00 00 01"
+                "00 00 00 09 54 72 61 63 6b 65 72 30 00 00 00 00"
+            )[..],
+        );
+        let last = bytes.len() - 1;
+        bytes[last] = 0xcd;
+        let mut lenient = bytes.clone();
+        assert!(MessageCodec::new()
+            .decode_from(&mut lenient)
+            .unwrap()
+            .is_some());
+        let mut strict = MessageCodec::with_profile(CompatibilityProfile::Vrpn08Strict);
+        assert!(strict.decode_from(&mut bytes).is_err());
+    }
+
+    #[test]
+    fn wide_alignment() {
+        let wire = WireConfig::default().with_alignment(16).unwrap();
+        let mut codec = MessageCodec::new().with_wire_config(wire);
+        let mut msg = empty_message().into_inner();
+        msg.body = crate::data_types::GenericBody::new(Bytes::from_static(b"hello"));
+        let msg = msg.into_sequenced_message(crate::data_types::id_types::SequenceNumber(4));
+        let mut buf = BytesMut::new();
+        codec.encode_into(msg.clone(), &mut buf).unwrap();
+        // 32 bytes of header, 16 of padded body.
+        assert_eq!(buf.len(), 48);
+        assert_eq!(&buf[..4], &[0, 0, 0, 37]);
+        let mut strict = MessageCodec::with_profile(CompatibilityProfile::Vrpn08Strict)
+            .with_wire_config(wire.with_padding(PaddingPolicy::Strict));
+        assert_eq!(strict.decode_from(&mut buf.clone()).unwrap(), Some(msg));
+        buf[28] = 1;
+        assert!(codec.decode_from(&mut buf.clone()).unwrap().is_some());
+        assert!(strict.decode_from(&mut buf).is_err());
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        let mut frame = BytesMut::new();
+        MessageCodec::new()
+            .encode_into(empty_message(), &mut frame)
+            .unwrap();
+        frame.extend_from_slice(b"junk");
+        assert!(MessageCodec::new().decode_frame(&frame).is_err());
+        let lenient = MessageCodec::with_profile(CompatibilityProfile::Lenient);
+        assert_eq!(lenient.decode_frame(&frame).unwrap(), vec![empty_message()]);
+        assert_eq!(
+            MessageCodec::new().decode_frame(&frame[..24]).unwrap(),
+            vec![empty_message()]
+        );
+    }
+
+    fn empty_message() -> SequencedGenericMessage {
+        use crate::data_types::{
+            id_types::{MessageTypeId, SenderId, SequenceNumber},
+            GenericBody, GenericMessage, Message, MessageHeader, TimeVal,
+        };
+        GenericMessage::from_header_and_body(
+            MessageHeader::new(Some(TimeVal::default()), MessageTypeId(3), SenderId(1)),
+            GenericBody::default(),
+        )
+        .into_sequenced_message(SequenceNumber(2))
+    }
+
+    #[test]
+    fn empty_body_at_end() {
+        let msg = empty_message();
+        let mut buf = BytesMut::new();
+        MessageCodec::new()
+            .encode_into(msg.clone(), &mut buf)
+            .unwrap();
+        assert_eq!(buf.len(), 24);
+        assert_eq!(
+            MessageCodec::new().decode_from(&mut buf).unwrap(),
+            Some(msg)
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn skip_oversize() {
+        use crate::data_types::id_types::SequenceNumber;
+        let mut codec = MessageCodec::new().with_max_message_size(32);
+        let mut big = BytesMut::new();
+        let mut large = empty_message().into_inner();
+        large.body = crate::data_types::GenericBody::new(Bytes::from(vec![1u8; 40]));
+        codec
+            .encode_into(large.into_sequenced_message(SequenceNumber(1)), &mut big)
+            .unwrap();
+        codec.encode_into(empty_message(), &mut big).unwrap();
+
+        // Fed a few bytes at a time, the large message is skipped and the next decoded.
+        let mut buf = BytesMut::new();
+        let mut decoded = Vec::new();
+        for chunk in big.chunks(7) {
+            buf.extend_from_slice(chunk);
+            while let Some(msg) = codec.decode_from(&mut buf).unwrap() {
+                decoded.push(msg);
+            }
+        }
+        assert_eq!(decoded, vec![empty_message()]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn malformed_length() {
+        let mut buf = BytesMut::from(&hex!("00 00 00 05 00 00 00 00")[..]);
+        assert!(MessageCodec::new().decode_from(&mut buf).is_err());
+        assert_eq!(buf.len(), 8);
+    }
+
+    #[test]
+    fn resync_after_garbage() {
+        let mut good = BytesMut::new();
+        let mut codec = MessageCodec::new();
+        codec.encode_into(empty_message(), &mut good).unwrap();
+        let mut stream = good.clone();
+        // A bad length field, then some bytes that do not look like a header.
+        stream.extend_from_slice(&hex!(
+            "00 00 00 05 ff ff ff ff 00 00 00 00 12 34 56 78 00 00 00 00 00 00 00 01"
+        ));
+        stream.extend_from_slice(&good);
+
+        let mut closing = stream.clone();
+        assert_eq!(
+            codec.decode_from(&mut closing).unwrap(),
+            Some(empty_message())
+        );
+        assert!(codec.decode_from(&mut closing).is_err());
+        assert_eq!(codec.take_framing_errors()[0].skipped, 0);
+
+        let mut codec = MessageCodec::new().with_framing_recovery(FramingRecovery::Resync);
+        let mut buf = BytesMut::new();
+        let mut decoded = Vec::new();
+        for chunk in stream.chunks(5) {
+            buf.extend_from_slice(chunk);
+            while let Some(msg) = codec.decode_from(&mut buf).unwrap() {
+                decoded.push(msg);
+            }
+        }
+        assert_eq!(decoded, vec![empty_message(), empty_message()]);
+        let errors = codec.take_framing_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].skipped, 24);
+        assert!(codec.take_framing_errors().is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_input_does_not_panic(data: Vec<u8>) {
+            let mut codec = MessageCodec::new().with_max_message_size(1024);
+            let mut buf = BytesMut::from(&data[..]);
+            while let Ok(Some(_)) = codec.decode_from(&mut buf) {}
+        }
+
+        #[test]
+        fn resync_arbitrary_input_does_not_panic(
+            data: Vec<u8>,
+            alignment in proptest::sample::select(vec![8usize, 16, 32]),
+            chunk in 1usize..64,
+        ) {
+            let wire = WireConfig::default().with_alignment(alignment).unwrap();
+            let mut codec = MessageCodec::new()
+                .with_wire_config(wire)
+                .with_max_message_size(1024)
+                .with_framing_recovery(FramingRecovery::Resync);
+            let mut buf = BytesMut::new();
+            for piece in data.chunks(chunk) {
+                buf.extend_from_slice(piece);
+                while let Ok(Some(_)) = codec.decode_from(&mut buf) {}
+            }
+        }
+    }
+
+    #[test]
+    fn resync_short_tail_with_wide_alignment() {
+        let wire = WireConfig::default().with_alignment(16).unwrap();
+        let mut codec = MessageCodec::new()
+            .with_wire_config(wire)
+            .with_framing_recovery(FramingRecovery::Resync);
+        // A bad length field, then a tail shorter than one alignment step.
+        let mut buf = BytesMut::from(&hex!("00 00 00 05 ff ff ff ff 00 00 00 00 12 34")[..]);
+        assert!(codec.decode_from(&mut buf).unwrap().is_none());
+        assert!(codec.decode_from(&mut buf).unwrap().is_none());
+    }
 }
@@ -0,0 +1,227 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Wire-compatibility policy: which peers to accept, and how strictly to read what they send.
+
+use crate::{
+    buffer_unbuffer::constants::ALIGN,
+    data_types::{
+        constants,
+        cookie::{Version, VersionMismatch},
+    },
+};
+use core::fmt;
+
+/// A wire-compatibility policy, selected per connection.
+///
+/// All decisions about which protocol variations to accept go through here,
+/// so the protocol can evolve by adding profiles rather than scattering checks.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum CompatibilityProfile {
+    /// Behave like mainline VRPN 07.xx: require major version 7, ignore padding contents
+    /// and sequence numbers. This is the default.
+    #[default]
+    Vrpn07,
+    /// Require major version 7 like `Vrpn07`, and also well-formed framing:
+    /// zero padding, and consecutive sequence numbers.
+    Vrpn08Strict,
+    /// Accept any version, for talking to old or unusual implementations.
+    Lenient,
+}
+
+impl CompatibilityProfile {
+    /// Check the version in a peer's network cookie.
+    pub fn check_cookie_version(&self, version: Version) -> Result<(), VersionMismatch> {
+        let ok = match self {
+            CompatibilityProfile::Vrpn07 | CompatibilityProfile::Vrpn08Strict => {
+                version.major == constants::MAGIC_DATA.major
+            }
+            CompatibilityProfile::Lenient => true,
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(VersionMismatch::new(version, constants::MAGIC_DATA))
+        }
+    }
+
+    /// Check the version in a log file's cookie.
+    pub fn check_file_cookie_version(&self, version: Version) -> Result<(), VersionMismatch> {
+        let ok = match self {
+            CompatibilityProfile::Lenient => true,
+            _ => version.major == constants::FILE_MAGIC_DATA.major,
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(VersionMismatch::new(version, constants::FILE_MAGIC_DATA))
+        }
+    }
+
+    /// Whether the padding after a message body must be all zero bytes.
+    ///
+    /// Mainline VRPN does not initialize its padding, so only strict profiles check it.
+    pub fn requires_zero_padding(&self) -> bool {
+        matches!(self, CompatibilityProfile::Vrpn08Strict)
+    }
+
+    /// Whether a gap in the sequence numbers of received messages is an error.
+    ///
+    /// Mainline VRPN sends sequence numbers but never reads them.
+    pub fn requires_consecutive_sequence(&self) -> bool {
+        matches!(self, CompatibilityProfile::Vrpn08Strict)
+    }
+
+    /// The framing used with peers under this profile.
+    pub fn wire_config(&self) -> WireConfig {
+        let padding = match self {
+            CompatibilityProfile::Vrpn07 => PaddingPolicy::Ignore,
+            CompatibilityProfile::Vrpn08Strict => PaddingPolicy::Strict,
+            CompatibilityProfile::Lenient => PaddingPolicy::Lenient,
+        };
+        WireConfig::default().with_padding(padding)
+    }
+}
+
+/// How to treat padding, and bytes left over after the last message of a datagram or record.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum PaddingPolicy {
+    /// Skip padding without looking at it, as mainline VRPN does.
+    /// Leftover bytes are an error. This is the default.
+    #[default]
+    Ignore,
+    /// Require all padding to be zero bytes, and reject leftover bytes.
+    Strict,
+    /// Skip padding without looking at it, and drop leftover bytes as trailing garbage.
+    Lenient,
+}
+
+/// The framing parameters of the wire protocol: what mainline VRPN hard-codes.
+///
+/// The defaults match mainline VRPN; others are for talking to older or unusual builds.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct WireConfig {
+    alignment: usize,
+    cookie_version: Version,
+    padding: PaddingPolicy,
+}
+
+/// The error from `WireConfig::with_alignment`, holding the alignment asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedAlignment(pub usize);
+
+impl fmt::Display for UnsupportedAlignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unsupported alignment {}: must be a power of two, at least {}",
+            self.0, ALIGN
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UnsupportedAlignment {}
+
+impl Default for WireConfig {
+    fn default() -> Self {
+        WireConfig {
+            alignment: ALIGN,
+            cookie_version: constants::MAGIC_DATA,
+            padding: PaddingPolicy::default(),
+        }
+    }
+}
+
+impl WireConfig {
+    /// Pad message headers and bodies to `alignment` bytes instead of `ALIGN`.
+    ///
+    /// The alignment must be a power of two, and at least `ALIGN`,
+    /// so the sequence number still fits in the padded header.
+    pub fn with_alignment(self, alignment: usize) -> Result<WireConfig, UnsupportedAlignment> {
+        if !alignment.is_power_of_two() || alignment < ALIGN {
+            return Err(UnsupportedAlignment(alignment));
+        }
+        Ok(WireConfig { alignment, ..self })
+    }
+
+    /// Send `cookie_version` in our cookie instead of `MAGIC_DATA`.
+    pub fn with_cookie_version(self, cookie_version: Version) -> WireConfig {
+        WireConfig {
+            cookie_version,
+            ..self
+        }
+    }
+
+    pub fn with_padding(self, padding: PaddingPolicy) -> WireConfig {
+        WireConfig { padding, ..self }
+    }
+
+    pub fn alignment(&self) -> usize {
+        self.alignment
+    }
+
+    pub fn cookie_version(&self) -> Version {
+        self.cookie_version
+    }
+
+    pub fn padding(&self) -> PaddingPolicy {
+        self.padding
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        let v7 = constants::MAGIC_DATA;
+        let v8 = Version { major: 8, minor: 0 };
+        let v6 = Version {
+            major: 6,
+            minor: 12,
+        };
+        let profile = CompatibilityProfile::default();
+        assert_eq!(profile, CompatibilityProfile::Vrpn07);
+        assert!(profile.check_cookie_version(v7).is_ok());
+        assert!(profile.check_cookie_version(v8).is_err());
+
+        let strict = CompatibilityProfile::Vrpn08Strict;
+        assert!(strict.check_cookie_version(v7).is_ok());
+        assert!(strict.check_cookie_version(v8).is_err());
+        assert!(strict.check_cookie_version(v6).is_err());
+
+        assert!(CompatibilityProfile::Lenient
+            .check_cookie_version(v6)
+            .is_ok());
+
+        assert!(profile
+            .check_file_cookie_version(constants::FILE_MAGIC_DATA)
+            .is_ok());
+        assert!(profile.check_file_cookie_version(v7).is_err());
+    }
+
+    #[test]
+    fn strictness() {
+        assert!(!CompatibilityProfile::Vrpn07.requires_zero_padding());
+        assert!(CompatibilityProfile::Vrpn08Strict.requires_zero_padding());
+        assert!(!CompatibilityProfile::Lenient.requires_consecutive_sequence());
+    }
+
+    #[test]
+    fn wire_config() {
+        let config = CompatibilityProfile::default().wire_config();
+        assert_eq!(config, WireConfig::default());
+        assert_eq!(config.alignment(), ALIGN);
+        assert_eq!(config.cookie_version(), constants::MAGIC_DATA);
+        assert_eq!(
+            CompatibilityProfile::Vrpn08Strict.wire_config().padding(),
+            PaddingPolicy::Strict
+        );
+        assert_eq!(config.with_alignment(16).unwrap().alignment(), 16);
+        assert!(config.with_alignment(4).is_err());
+        assert!(config.with_alignment(12).is_err());
+    }
+}
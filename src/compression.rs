@@ -0,0 +1,252 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Compressing TCP traffic between two peers that are both this crate.
+//!
+//! A peer with compression enabled sends a `CompressionOffer` after the cookie handshake,
+//! naming the algorithms it wants. Once both peers have offered LZ4, each writes its coalesced
+//! TCP traffic as `COMPRESSED_FRAME` messages, where that makes it smaller,
+//! and `MessageCodec` unpacks them as they arrive. Compressed frames before then,
+//! or inside another frame, are an error.
+//! Mainline VRPN peers ignore the offer and never send one, so they get plain VRPN.
+
+use crate::{
+    buffer_unbuffer::{
+        buffer::{BufferResult, BufferTo},
+        unbuffer::{UnbufferFrom, UnbufferResult},
+        ConstantBufferSize,
+    },
+    data_types::{
+        constants, id_types::*, GenericMessage, MessageTypeId, MessageTypeIdentifier,
+        StaticMessageTypeName, TypedMessage, TypedMessageBody,
+    },
+    endpoint::Endpoint,
+    Result, TypeDispatcher, VrpnError,
+};
+use bytes::{Buf, BufMut};
+use std::convert::TryFrom;
+
+/// The message type of `CompressionOffer`.
+///
+/// Not part of mainline VRPN.
+pub const COMPRESSION_OFFER: StaticMessageTypeName =
+    StaticMessageTypeName(b"vrpn-rs Compression Offer");
+
+/// The system message type of a compressed run of messages.
+///
+/// Its body is the LZ4 block of the messages' wire form, after its size as a little-endian u32.
+/// Outside the range mainline VRPN uses, and only sent to peers that offered LZ4.
+pub const COMPRESSED_FRAME: MessageTypeId = MessageTypeId(-64);
+
+/// Fewest bytes of messages worth compressing.
+#[cfg(feature = "compression")]
+pub(crate) const MIN_COMPRESSED_INPUT: usize = 128;
+
+/// Most bytes of messages compressed into one frame:
+/// well under the size limit a peer is likely to set for incoming messages.
+#[cfg(feature = "compression")]
+pub(crate) const MAX_COMPRESSED_INPUT: usize = 256 * 1024;
+
+/// Bit for LZ4 in `CompressionOffer::algorithms`.
+const LZ4: u32 = 1;
+
+/// Compression of an endpoint's outgoing TCP traffic, once the peer has offered the same.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Compression {
+    /// Plain VRPN, without an offer.
+    #[default]
+    Off,
+    /// LZ4 blocks.
+    #[cfg(feature = "compression")]
+    Lz4,
+}
+
+impl Compression {
+    /// The bits of `CompressionOffer::algorithms` for this setting.
+    pub fn algorithms(self) -> u32 {
+        match self {
+            Compression::Off => 0,
+            #[cfg(feature = "compression")]
+            Compression::Lz4 => LZ4,
+        }
+    }
+
+    /// Whether to compress, given the algorithms the peer offered.
+    pub fn agreed_with(self, peer_algorithms: u32) -> bool {
+        self.algorithms() & peer_algorithms & LZ4 != 0
+    }
+}
+
+/// Offers compression to the peer: a bit set for each algorithm wanted.
+///
+/// Sent from the "VRPN Control" sender. Mainline VRPN peers ignore it.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct CompressionOffer {
+    pub algorithms: u32,
+}
+
+impl TypedMessageBody for CompressionOffer {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(COMPRESSION_OFFER);
+}
+
+impl ConstantBufferSize for CompressionOffer {
+    fn constant_buffer_size() -> usize {
+        u32::constant_buffer_size()
+    }
+}
+
+impl BufferTo for CompressionOffer {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        self.algorithms.buffer_to(buf)
+    }
+}
+
+impl UnbufferFrom for CompressionOffer {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        Ok(CompressionOffer {
+            algorithms: u32::unbuffer_from(buf)?,
+        })
+    }
+}
+
+/// Pack a `CompressionOffer` for a setting, with the IDs registered in every dispatcher.
+pub(crate) fn compression_offer(
+    dispatcher: &TypeDispatcher,
+    compression: Compression,
+) -> Result<GenericMessage> {
+    let message_type = dispatcher
+        .get_type_id(COMPRESSION_OFFER)
+        .ok_or(VrpnError::InvalidId(0))?;
+    let sender = dispatcher
+        .get_sender_id(constants::CONTROL)
+        .ok_or(VrpnError::InvalidId(0))?;
+    Ok(GenericMessage::try_from(TypedMessage::new(
+        None,
+        message_type,
+        sender,
+        CompressionOffer {
+            algorithms: compression.algorithms(),
+        },
+    ))?)
+}
+
+/// If this message is a `CompressionOffer`, record what the peer offered, returning `true`.
+///
+/// Call with messages that have already been mapped to local IDs.
+pub(crate) fn update_peer_compression<T: Endpoint + ?Sized>(
+    endpoint: &mut T,
+    dispatcher: &TypeDispatcher,
+    msg: &GenericMessage,
+) -> Result<bool> {
+    if dispatcher.get_type_id(COMPRESSION_OFFER) == Some(LocalId(msg.header.message_type)) {
+        let offer = TypedMessage::<CompressionOffer>::try_from(msg)?;
+        debug!("Peer offered compression: {:#x}", offer.body.algorithms);
+        endpoint.set_peer_compression(offer.body.algorithms);
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Compress the wire form of some messages into a `COMPRESSED_FRAME` message, in wire form,
+/// if there are enough of them and it comes out smaller.
+#[cfg(feature = "compression")]
+pub(crate) fn compress_frame(data: &[u8]) -> Option<bytes::Bytes> {
+    use crate::data_types::{GenericBody, Message, MessageHeader};
+    if !(MIN_COMPRESSED_INPUT..=MAX_COMPRESSED_INPUT).contains(&data.len()) {
+        return None;
+    }
+    let body = lz4_flex::compress_prepend_size(data);
+    let frame = GenericMessage::from_header_and_body(
+        MessageHeader::new(None, COMPRESSED_FRAME, SenderId(0)),
+        GenericBody::new(body.into()),
+    )
+    .into_sequenced_message(SequenceNumber(0))
+    .try_into_buf()
+    .ok()?;
+    if frame.len() < data.len() {
+        Some(frame)
+    } else {
+        None
+    }
+}
+
+/// Without the `compression` feature, nothing is compressed.
+#[cfg(not(feature = "compression"))]
+pub(crate) fn compress_frame(_data: &[u8]) -> Option<bytes::Bytes> {
+    None
+}
+
+/// Decompress the body of a `COMPRESSED_FRAME` message, refusing more than `max_size` bytes.
+///
+/// `MessageCodec` only calls this once compression has been agreed with the peer,
+/// and rejects any compressed frame found in the result.
+#[cfg(feature = "compression")]
+pub(crate) fn decompress_frame(body: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let corrupt = |reason: String| VrpnError::Incompatible(format!("compressed frame {}", reason));
+    if body.len() < 4 {
+        return Err(corrupt("too short".to_string()));
+    }
+    let size = u32::from_le_bytes([body[0], body[1], body[2], body[3]]) as usize;
+    if size > max_size {
+        return Err(corrupt(format!(
+            "of {} bytes, over the limit of {}",
+            size, max_size
+        )));
+    }
+    lz4_flex::decompress_size_prepended(body).map_err(|e| corrupt(e.to_string()))
+}
+
+/// Without the `compression` feature, compressed frames are an error: we never offered.
+#[cfg(not(feature = "compression"))]
+pub(crate) fn decompress_frame(_body: &[u8], _max_size: usize) -> Result<Vec<u8>> {
+    Err(VrpnError::Incompatible(
+        "compressed frame, without the compression feature".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offer_roundtrip() {
+        let dispatcher = TypeDispatcher::new();
+        let msg = compression_offer(&dispatcher, Compression::default()).unwrap();
+        let offer = TypedMessage::<CompressionOffer>::try_from(&msg).unwrap();
+        assert_eq!(offer.body.algorithms, 0);
+        assert!(!Compression::Off.agreed_with(u32::MAX));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn frame_roundtrip() {
+        assert!(Compression::Lz4.agreed_with(LZ4 | 0x100));
+        assert!(!Compression::Lz4.agreed_with(0));
+        assert_eq!(compress_frame(&[7; 64]), None);
+        // Incompressible data is left alone.
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        assert_eq!(compress_frame(&noise), None);
+
+        let data = [7; 4096];
+        let mut frame = compress_frame(&data).unwrap();
+        assert!(frame.len() < 200);
+        let msg = crate::data_types::SequencedGenericMessage::try_read_from_buf(&mut frame)
+            .unwrap()
+            .into_inner();
+        assert_eq!(msg.header.message_type, COMPRESSED_FRAME);
+        let body = msg.body.as_bytes();
+        assert_eq!(decompress_frame(body, 4096).unwrap(), &data[..]);
+        assert!(decompress_frame(body, 4095).is_err());
+        assert!(decompress_frame(&body[..8], 4096).is_err());
+    }
+}
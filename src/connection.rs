@@ -2,21 +2,54 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
+use bytes::Bytes;
+use futures::task::AtomicWaker;
 use std::{
-    convert::TryFrom,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
 };
 
 use crate::{
-    buffer_unbuffer::BufferTo,
+    buffer_unbuffer::{BufferPool, BufferTo, UnbufferFrom},
+    class_policy::{ClassOfServiceOverrides, ClassOfServicePolicy},
+    clock_sync::ClockSync,
+    codec::FramingRecovery,
+    compatibility::CompatibilityProfile,
+    compression::{compression_offer, Compression},
     data_types::{
+        constants,
         id_types::*,
         name_types::{MessageTypeIdentifier, NameIntoBytes},
-        ClassOfService, GenericMessage, LogFileNames, MessageTypeId, MessageTypeName, SenderName,
-        TimeVal, TypedMessage, TypedMessageBody,
+        ClassOfService, CookieData, GenericBody, GenericMessage, LogFileNames, Message,
+        MessageHeader, MessageSize, MessageTypeId, MessageTypeName, SenderName, TimeVal,
+        TypedMessage, TypedMessageBody, DEFAULT_MAX_MESSAGE_SIZE,
     },
-    type_dispatcher::HandlerHandle,
+    handler::{AsyncHandler, HandlerErrorPolicy, HandlerErrorReport, HandlerTiming},
+    latency::LatencyStats,
+    lifecycle::LifecycleEvents,
+    message_cache::MessageStats,
+    message_history::MessageHistoryConfig,
+    message_log::RemoteLogPolicy,
+    net_util::SocketConfig,
+    poll_config::PollConfig,
+    registrations::Registrations,
+    send_queue::SendQueueLimits,
+    sequence::SequenceStats,
+    sink::MessageSink,
+    status_watch::{StatusSender, StatusWatch},
+    sync::{Mutex, RwLock},
+    system_events::{DroppedLastConnection, EventCallback, GotFirstConnection},
+    throttle::Throttle,
+    timeouts::Timeouts,
+    timestamp_policy::TimestampPolicy,
+    translation_table::TranslationTablesSnapshot,
+    type_dispatcher::{DispatcherLimits, HandlerHandle, ScopedHandler},
     Endpoint, EndpointGeneric, Handler, RegisterMapping, Result, TypeDispatcher, TypedHandler,
+    VrpnError, DEFAULT_COALESCE_THRESHOLD,
 };
 
 pub type EndpointVec<EP> = Vec<Option<EP>>;
@@ -28,10 +61,47 @@ pub enum ConnectionStatus {
     ClientConnecting,
     /// This is a client connection that is successfully connected.
     ClientConnected,
+    /// This is a client connection whose connection attempt failed:
+    /// it will not try again until asked to reconnect.
+    ClientDisconnected,
     /// This is a server connection, the number of connected endpoints is provided
     Server(usize),
 }
 
+/// The message telling a peer we are closing the connection.
+pub(crate) fn disconnect_message() -> GenericMessage {
+    GenericMessage::from_header_and_body(
+        MessageHeader::new(None, constants::DISCONNECT_MESSAGE, SenderId(0)),
+        GenericBody::default(),
+    )
+}
+
+/// A VRPN connection, with some number of endpoints.
+///
+/// # Thread safety
+///
+/// Implementations are `Send + Sync`, and all methods take `&self`,
+/// so an `Arc` of a connection may be shared between threads and tasks:
+/// registering types, senders, and handlers, and packing messages,
+/// are all safe to do concurrently with each other and with polling the connection.
+/// Only one thread or task should poll a connection at a time, however:
+/// otherwise the wake-ups for whichever one is not currently polling may be lost.
+///
+/// Internally, state is protected by mutexes, always locked in this order
+/// (skipping any not needed):
+///
+/// 1. backend-specific connection state (e.g. client connection status)
+/// 2. the type dispatcher, only locked for writing to register names and handlers
+/// 3. the endpoints
+///
+/// Handlers are called with the dispatcher locked for reading,
+/// so they must not call methods on the connection that dispatched them.
+///
+/// # Endpoint settings
+///
+/// The `set_` methods configuring endpoints, such as `set_poll_config`,
+/// apply to current endpoints as well as those connected later,
+/// unless their documentation says otherwise.
 pub trait Connection: Send + Sync {
     type SpecificEndpoint: Endpoint + EndpointGeneric;
 
@@ -50,17 +120,18 @@ pub trait Connection: Send + Sync {
     where
         T: Into<MessageTypeName> + Clone,
     {
-        let mut dispatcher = self.connection_core().type_dispatcher.lock()?;
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
         let name: MessageTypeName = name.into();
         match dispatcher.register_type(name.clone())? {
             RegisterMapping::Found(id) => Ok(id),
             RegisterMapping::NewMapping(id) => {
-                eprintln!("New mapping (coming from our side): {:?} -> {:?}", name, id);
-                let mut endpoints = self.connection_core().endpoints.lock()?;
+                debug!("New mapping (coming from our side): {:?} -> {:?}", name, id);
+                let mut endpoints = self.connection_core().endpoints.lock();
                 let name = name.into_bytes();
                 for ep in endpoints.iter_mut().flatten() {
                     ep.new_local_id(&name, id)?;
                 }
+                self.connection_core().wake_driver();
                 Ok(id)
             }
         }
@@ -73,15 +144,16 @@ pub trait Connection: Send + Sync {
     where
         T: Into<SenderName> + Clone + NameIntoBytes,
     {
-        let mut dispatcher = self.connection_core().type_dispatcher.lock()?;
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
         match dispatcher.register_sender(name.clone())? {
             RegisterMapping::Found(id) => Ok(id),
             RegisterMapping::NewMapping(id) => {
-                let mut endpoints = self.connection_core().endpoints.lock()?;
+                let mut endpoints = self.connection_core().endpoints.lock();
                 let name = name.into_bytes();
                 for ep in endpoints.iter_mut().flatten() {
                     ep.new_local_id(&name, id)?;
                 }
+                self.connection_core().wake_driver();
                 Ok(id)
             }
         }
@@ -96,22 +168,50 @@ pub trait Connection: Send + Sync {
         message_type_filter: Option<LocalId<MessageTypeId>>,
         sender_filter: Option<LocalId<SenderId>>,
     ) -> Result<HandlerHandle> {
-        let mut dispatcher = self.connection_core().type_dispatcher.lock()?;
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
         dispatcher.add_handler(handler, message_type_filter, sender_filter)
     }
 
+    /// Add one generic handler for several message types, with an optional filter on sender.
+    ///
+    /// Returns a struct usable to remove the handler from all of them at once.
+    fn add_handler_for_types(
+        &self,
+        handler: Box<dyn Handler + Send>,
+        message_types: &[LocalId<MessageTypeId>],
+        sender_filter: Option<LocalId<SenderId>>,
+    ) -> Result<HandlerHandle> {
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
+        dispatcher.add_handler_for_types(handler, message_types, sender_filter)
+    }
+
+    /// Add an async handler, with optional filters on message type and sender.
+    ///
+    /// The futures it returns are run while the connection's endpoints are polled.
+    ///
+    /// Returns a struct usable to remove the handler later.
+    fn add_async_handler(
+        &self,
+        handler: Box<dyn AsyncHandler + Send>,
+        message_type_filter: Option<LocalId<MessageTypeId>>,
+        sender_filter: Option<LocalId<SenderId>>,
+    ) -> Result<HandlerHandle> {
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
+        dispatcher.add_async_handler(handler, message_type_filter, sender_filter)
+    }
+
     /// Add a "typed" handler, with optional filters on sender.
     ///
     /// The message type filter is automatically populated based on the TypedHandler trait.
     ///
     /// Returns a struct usable to remove the handler later.
-    fn add_typed_handler<T: 'static>(
+    fn add_typed_handler<T>(
         &self,
         handler: Box<T>,
         sender_filter: Option<LocalId<SenderId>>,
     ) -> Result<HandlerHandle>
     where
-        T: TypedHandler + Handler + Sized,
+        T: TypedHandler + Handler + Sized + 'static,
     {
         let message_type_filter = match T::Item::MESSAGE_IDENTIFIER {
             MessageTypeIdentifier::UserMessageName(name) => Some(self.register_type(name)?),
@@ -120,12 +220,179 @@ pub trait Connection: Send + Sync {
         self.add_handler(handler, message_type_filter, sender_filter)
     }
 
+    /// Add a "typed" handler like `add_typed_handler`, removed when the returned guard is dropped.
+    fn add_typed_handler_scoped<T>(
+        &self,
+        handler: Box<T>,
+        sender_filter: Option<LocalId<SenderId>>,
+    ) -> Result<ScopedHandler>
+    where
+        T: TypedHandler + Handler + Sized + 'static,
+    {
+        let handle = self.add_typed_handler(handler, sender_filter)?;
+        Ok(ScopedHandler::new(
+            &self.connection_core().type_dispatcher,
+            handle,
+        ))
+    }
+
+    /// Call `callback` each time the connection drops: on a client, when the server is lost,
+    /// so remotes can mark their data stale; on a server, when the last client leaves.
+    ///
+    /// Like a handler for `DroppedLastConnection`, as C++ users register for
+    /// `vrpn_dropped_last_connection`. Removed when the returned guard is dropped.
+    fn on_disconnect<F>(&self, callback: F) -> Result<ScopedHandler>
+    where
+        F: FnMut() + Send + Sync + 'static,
+    {
+        self.add_typed_handler_scoped(
+            Box::new(EventCallback::<DroppedLastConnection, F>::new(callback)),
+            None,
+        )
+    }
+
+    /// Call `callback` each time the connection is established: on a client, when it connects
+    /// to the server, first or again after a drop; on a server, when the first client arrives.
+    ///
+    /// Like a handler for `GotFirstConnection`. Removed when the returned guard is dropped.
+    fn on_reconnect<F>(&self, callback: F) -> Result<ScopedHandler>
+    where
+        F: FnMut() + Send + Sync + 'static,
+    {
+        self.add_typed_handler_scoped(
+            Box::new(EventCallback::<GotFirstConnection, F>::new(callback)),
+            None,
+        )
+    }
+
     /// Remove a handler previously added with add_handler() or add_typed_handler()
     fn remove_handler(&self, handler_handle: HandlerHandle) -> Result<()> {
-        let mut dispatcher = self.connection_core().type_dispatcher.lock()?;
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
         dispatcher.remove_handler(handler_handle)
     }
 
+    /// Set (or with `None`, remove) the handler for a system message type
+    /// that is not handled internally, e.g. for a protocol extension.
+    ///
+    /// Without one, such messages are logged and ignored.
+    fn set_system_handler(
+        &self,
+        message_type: MessageTypeId,
+        handler: Option<Box<dyn Handler + Send>>,
+    ) -> Result<()> {
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
+        dispatcher.set_system_handler(message_type, handler)
+    }
+
+    /// Limit how often messages of a type are delivered to handlers,
+    /// or with `None`, remove the limit.
+    ///
+    /// May be changed at any time.
+    fn set_throttle(
+        &self,
+        message_type: LocalId<MessageTypeId>,
+        throttle: Option<Throttle>,
+    ) -> Result<()> {
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
+        dispatcher.set_throttle(message_type, throttle);
+        Ok(())
+    }
+
+    /// Set what to do when a handler returns an error:
+    /// by default, it is logged and recorded, and other handlers are still called.
+    fn set_handler_error_policy(&self, policy: HandlerErrorPolicy) -> Result<()> {
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
+        dispatcher.set_handler_error_policy(policy);
+        Ok(())
+    }
+
+    /// Take the handler errors recorded since last taken.
+    fn take_handler_errors(&self) -> Result<HandlerErrorReport> {
+        let dispatcher = self.connection_core().type_dispatcher.read();
+        Ok(dispatcher.take_handler_errors())
+    }
+
+    /// Warn whenever a handler takes longer than this to handle a message, or never with `None`:
+    /// a slow handler holds up receiving everything else.
+    fn set_handler_budget(&self, budget: Option<Duration>) -> Result<()> {
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
+        dispatcher.set_handler_budget(budget);
+        Ok(())
+    }
+
+    /// How long a handler has taken to handle messages so far, or `None` if it has been removed.
+    ///
+    /// Don't call this from within a handler of the same connection: that would deadlock.
+    fn handler_timing(&self, handle: HandlerHandle) -> Result<Option<HandlerTiming>> {
+        let dispatcher = self.connection_core().type_dispatcher.read();
+        Ok(dispatcher.handler_timing(handle))
+    }
+
+    /// Limit the senders, message types, and handlers registered from now on.
+    fn set_dispatcher_limits(&self, limits: DispatcherLimits) -> Result<()> {
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
+        dispatcher.set_limits(limits);
+        Ok(())
+    }
+
+    /// Enable or disable caching the latest message of each type from each sender,
+    /// for `latest` and `message_stats`. Disabled by default.
+    fn set_message_cache(&self, enabled: bool) -> Result<()> {
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
+        dispatcher.set_message_cache(enabled);
+        Ok(())
+    }
+
+    /// The latest message of type `T` from a sender.
+    ///
+    /// `None` if there has been none, or the message cache is disabled.
+    fn latest<T: TypedMessageBody + UnbufferFrom>(
+        &self,
+        sender: LocalId<SenderId>,
+    ) -> Result<Option<TypedMessage<T>>> {
+        let dispatcher = self.connection_core().type_dispatcher.read();
+        let message_type = dispatcher.get_typed_message_type_id::<T>();
+        let latest = match (message_type, dispatcher.message_cache()) {
+            (Some(message_type), Some(cache)) => cache.latest(message_type, sender),
+            _ => Ok(None),
+        };
+        latest
+    }
+
+    /// The count and rate of messages of type `T` from a sender.
+    ///
+    /// `None` if there has been none, or the message cache is disabled.
+    fn message_stats<T: TypedMessageBody>(
+        &self,
+        sender: LocalId<SenderId>,
+    ) -> Result<Option<MessageStats>> {
+        let dispatcher = self.connection_core().type_dispatcher.read();
+        let message_type = dispatcher.get_typed_message_type_id::<T>();
+        Ok(message_type
+            .zip(dispatcher.message_cache())
+            .and_then(|(message_type, cache)| cache.stats(message_type, sender)))
+    }
+
+    /// Enable or disable measuring the latency of each received message type,
+    /// from its header time to its arrival, keeping up to `window` recent samples of each.
+    /// Disabled by default.
+    fn set_latency_tracking(&self, window: Option<usize>) -> Result<()> {
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
+        dispatcher.set_latency_tracking(window);
+        Ok(())
+    }
+
+    /// The latency of recent messages of type `T`, from all senders.
+    ///
+    /// `None` if there has been none, or latency tracking is disabled.
+    fn latency_stats<T: TypedMessageBody>(&self) -> Result<Option<LatencyStats>> {
+        let dispatcher = self.connection_core().type_dispatcher.read();
+        let message_type = dispatcher.get_typed_message_type_id::<T>();
+        Ok(message_type
+            .zip(dispatcher.latency_tracker())
+            .and_then(|(message_type, latency)| latency.stats(message_type)))
+    }
+
     /// Pack a message to send to all connected endpoints.
     ///
     /// May not actually send immediately, might need to poll the connection somehow.
@@ -133,12 +400,46 @@ pub trait Connection: Send + Sync {
     where
         T: TypedMessageBody + BufferTo,
     {
-        let generic_msg = GenericMessage::try_from(msg)?;
+        let generic_msg = {
+            let mut pool = self.connection_core().buffer_pool.lock();
+            msg.try_into_generic_in(&mut pool)?
+        };
 
-        let mut endpoints = self.connection_core().endpoints.lock()?;
+        let mut endpoints = self.connection_core().endpoints.lock();
         for ep in endpoints.iter_mut().flatten() {
             ep.buffer_generic_message(generic_msg.clone(), class)?;
         }
+        self.connection_core().wake_driver();
+        Ok(())
+    }
+
+    /// Pack messages to send to all connected endpoints back to back,
+    /// in one write where the endpoint allows, like the reports of every sensor in a frame.
+    ///
+    /// Each message still gets its own sequence number, as if sent one at a time.
+    ///
+    /// May not actually send immediately, might need to poll the connection somehow.
+    fn send_batch<T, I>(&self, messages: I, class: ClassOfService) -> Result<()>
+    where
+        T: TypedMessageBody + BufferTo,
+        I: IntoIterator<Item = TypedMessage<T>>,
+    {
+        let batch = {
+            let mut pool = self.connection_core().buffer_pool.lock();
+            messages
+                .into_iter()
+                .map(|msg| Ok(msg.try_into_generic_in(&mut pool)?))
+                .collect::<Result<Vec<_>>>()?
+        };
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.buffer_generic_batch(batch.clone(), class)?;
+        }
+        self.connection_core().wake_driver();
         Ok(())
     }
 
@@ -148,7 +449,7 @@ pub trait Connection: Send + Sync {
     /// the MESSAGE_IDENTIFIER constant in the TypedMessageBody implementation.
     ///
     /// May not actually send immediately, might need to poll the connection somehow.
-    fn pack_message_body<T: TypedMessageBody>(
+    fn pack_message_body<T>(
         &self,
         timeval: Option<TimeVal>,
         sender: LocalId<SenderId>,
@@ -166,6 +467,66 @@ pub trait Connection: Send + Sync {
         self.pack_message(message, class)
     }
 
+    /// Send a message body, stamped with the current time, to all connected endpoints.
+    ///
+    /// Registers the message type named by `T::MESSAGE_IDENTIFIER` if needed:
+    /// like `connection.send::<PoseReport>(sender, body, ClassOfService::RELIABLE)?`.
+    ///
+    /// May not actually send immediately, might need to poll the connection somehow.
+    fn send<T>(&self, sender: LocalId<SenderId>, body: T, class: ClassOfService) -> Result<()>
+    where
+        T: TypedMessageBody + BufferTo,
+    {
+        self.pack_message_body(None, sender, body, class)
+    }
+
+    /// Pack an already-serialized message body to send to all connected endpoints,
+    /// for message types with no `TypedMessageBody` implementation.
+    ///
+    /// The body is unpadded: padding is added when the message is buffered.
+    /// Fails if the body is too large for a VRPN message,
+    /// or if `message_type` is a system message type.
+    ///
+    /// May not actually send immediately, might need to poll the connection somehow.
+    fn send_generic(
+        &self,
+        time: TimeVal,
+        message_type: LocalId<MessageTypeId>,
+        sender: LocalId<SenderId>,
+        body: Bytes,
+        class: ClassOfService,
+    ) -> Result<()> {
+        if message_type.0.is_system_message() {
+            return Err(VrpnError::SystemMessageType(message_type.0.get()));
+        }
+        if MessageSize::try_from_unpadded_body_size(body.len()).is_none() {
+            return Err(VrpnError::MessageTooLarge(body.len()));
+        }
+        let msg = GenericMessage::from_header_and_body(
+            MessageHeader::new(Some(time), message_type, sender),
+            GenericBody::new(body),
+        );
+
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.buffer_generic_message(msg.clone(), class)?;
+        }
+        self.connection_core().wake_driver();
+        Ok(())
+    }
+
+    /// Get a `futures::Sink` that packs each message given to it, as with `pack_message_body`,
+    /// from the given sender.
+    ///
+    /// It also accepts complete `TypedMessage<T>`, as with `pack_message`.
+    fn sink<T>(&self, sender: LocalId<SenderId>, class: ClassOfService) -> MessageSink<'_, Self, T>
+    where
+        Self: Sized,
+        T: TypedMessageBody + BufferTo,
+    {
+        MessageSink::new(self, sender, class)
+    }
+
     // /// Pack an ID description (either message type or sender) on all endpoints.
     // ///
     // /// May not actually send immediately, might need to poll the connection somehow.
@@ -185,23 +546,310 @@ pub trait Connection: Send + Sync {
 
     /// Pack all message type and sender descriptions on all endpoints.
     ///
+    /// Endpoints that track what they have already been sent only get the new ones.
+    /// (New registrations on a live connection are sent automatically.)
+    ///
     /// May not actually send immediately, might need to poll the connection somehow.
     fn send_all_descriptions(&self) -> Result<()> {
-        let mut endpoints = self.connection_core().endpoints.lock()?;
-        let dispatcher = self.connection_core().type_dispatcher.lock()?;
+        let dispatcher = self.connection_core().type_dispatcher.read();
+        let mut endpoints = self.connection_core().endpoints.lock();
         for ep in endpoints.iter_mut().flatten() {
             ep.send_all_descriptions(&dispatcher)?;
         }
+        self.connection_core().wake_driver();
         Ok(())
     }
 
+    /// Enable (or with `None`, disable) recording recent messages on each endpoint,
+    /// to be dumped on protocol error or endpoint drop.
+    fn set_message_history(&self, config: Option<MessageHistoryConfig>) -> Result<()> {
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.set_message_history(config.clone());
+        }
+        *self.connection_core().message_history.lock() = config;
+        Ok(())
+    }
+
+    /// Set the limits on how much each endpoint receives and dispatches per poll.
+    fn set_poll_config(&self, config: PollConfig) -> Result<()> {
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.set_poll_config(config);
+        }
+        *self.connection_core().poll_config.lock() = config;
+        Ok(())
+    }
+
+    /// Set the limits on connecting, the handshake, and idle endpoints.
+    ///
+    /// Connection attempts started after this use the new limits.
+    /// The read idle limit and keep-alive interval apply to current endpoints
+    /// as well as those connected later.
+    fn set_timeouts(&self, timeouts: Timeouts) -> Result<()> {
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.set_read_idle_timeout(timeouts.read_idle);
+            ep.set_keepalive(timeouts.keepalive);
+        }
+        *self.connection_core().timeouts.lock() = timeouts;
+        Ok(())
+    }
+
+    /// Set how many bytes of outgoing messages each endpoint accumulates into a single write.
+    ///
+    /// Queued messages are always written once there are no more waiting,
+    /// so this only limits how much is held back during a burst.
+    fn set_coalesce_threshold(&self, threshold: usize) -> Result<()> {
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.set_coalesce_threshold(threshold);
+        }
+        self.connection_core()
+            .coalesce_threshold
+            .store(threshold, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Set the largest padded size of incoming message to accept, by default `DEFAULT_MAX_MESSAGE_SIZE`.
+    ///
+    /// Larger messages are skipped, with a warning, rather than buffered:
+    /// a corrupt length field would otherwise stall the stream waiting for data.
+    fn set_max_message_size(&self, max_message_size: usize) -> Result<()> {
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.set_max_message_size(max_message_size);
+        }
+        self.connection_core()
+            .max_message_size
+            .store(max_message_size, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Set how corrupt framing in the bytes received from peers is handled.
+    ///
+    /// Either way, a `LifecycleEvent::FramingError` is sent for each occurrence.
+    fn set_framing_recovery(&self, recovery: FramingRecovery) -> Result<()> {
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.set_framing_recovery(recovery);
+        }
+        *self.connection_core().framing_recovery.lock() = recovery;
+        Ok(())
+    }
+
+    /// Set whether to log the connection to the files a peer names, when it asks.
+    ///
+    /// By default peers are refused, with a text message, as this writes files on this host.
+    fn set_remote_log_policy(&self, policy: RemoteLogPolicy) -> Result<()> {
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.set_remote_log_policy(policy);
+        }
+        *self.connection_core().remote_log_policy.lock() = policy;
+        Ok(())
+    }
+
+    /// Override the class of service outgoing messages are sent with, by message type,
+    /// or send everything reliably: see `ClassOfServicePolicy`.
+    ///
+    /// The message types named are registered. Replaces any previous policy.
+    fn set_class_of_service_policy(&self, policy: &ClassOfServicePolicy) -> Result<()> {
+        let overrides = {
+            let mut dispatcher = self.connection_core().type_dispatcher.write();
+            policy.resolve(&mut dispatcher)?
+        };
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.set_class_overrides(overrides.clone());
+        }
+        *self.connection_core().class_overrides.lock() = overrides;
+        Ok(())
+    }
+
+    /// Set how far behind each peer may fall in reading what we send,
+    /// so one stalled client does not degrade a server for the rest: see `SendQueueLimits`.
+    fn set_send_queue_limits(&self, limits: SendQueueLimits) -> Result<()> {
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.set_send_queue_limits(limits);
+        }
+        *self.connection_core().send_queue_limits.lock() = limits;
+        Ok(())
+    }
+
+    /// Set which clock the times of user messages reflect, sent and received:
+    /// see `TimestampPolicy`.
+    fn set_timestamp_policy(&self, policy: TimestampPolicy) -> Result<()> {
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.set_timestamp_policy(policy);
+        }
+        *self.connection_core().timestamp_policy.lock() = policy;
+        Ok(())
+    }
+
+    /// Set whether to compress TCP traffic with peers that are also this crate: see `Compression`.
+    ///
+    /// Each peer is sent an offer, and compression starts once it offers the same.
+    /// Mainline VRPN peers ignore the offer, and keep getting plain VRPN.
+    fn set_compression(&self, compression: Compression) -> Result<()> {
+        let offer = {
+            let dispatcher = self.connection_core().type_dispatcher.read();
+            compression_offer(&dispatcher, compression)?
+        };
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.set_compression(compression);
+            ep.buffer_generic_message(offer.clone(), ClassOfService::RELIABLE)?;
+        }
+        *self.connection_core().compression.lock() = compression;
+        self.connection_core().wake_driver();
+        Ok(())
+    }
+
+    /// Set options on the sockets of endpoints, such as buffer sizes and keepalive.
+    fn set_socket_config(&self, config: SocketConfig) -> Result<()> {
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            ep.set_socket_config(&config)?;
+        }
+        *self.connection_core().socket_config.lock() = config;
+        Ok(())
+    }
+
+    /// Send each peer a disconnect message, and close each endpoint once that is sent.
+    fn close_endpoints(&self) -> Result<()> {
+        let disconnect = disconnect_message();
+        let mut endpoints = self.connection_core().endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            if let Err(e) = ep.buffer_generic_message(disconnect.clone(), ClassOfService::RELIABLE)
+            {
+                warn!("Could not send disconnect message: {}", e);
+            }
+            ep.close_when_sent();
+        }
+        self.connection_core().wake_driver();
+        Ok(())
+    }
+
+    /// Ready once every endpoint has written out the messages queued so far.
+    ///
+    /// This does not poll the endpoints: see `PollEndpoints::flush` for that.
+    fn poll_flushed(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let endpoints = self.connection_core().endpoints.lock();
+        let mut flushed = true;
+        for ep in endpoints.iter().flatten() {
+            // Poll them all, to be woken by whichever is still writing.
+            flushed &= ep.poll_flushed(cx).is_ready();
+        }
+        if flushed {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Copy the translation tables of each open endpoint,
+    /// to see what senders and message types the remote sides have declared.
+    fn translation_snapshots(&self) -> Result<Vec<TranslationTablesSnapshot>> {
+        let endpoints = self.connection_core().endpoints.lock();
+        Ok(endpoints
+            .iter()
+            .flatten()
+            .map(|ep| ep.translation_snapshot())
+            .collect())
+    }
+
+    /// Convert a timestamp from the server's clock to ours,
+    /// using the estimate from `clock_sync::Client`.
+    ///
+    /// Returns `None` if there is no estimate yet.
+    fn server_time_to_local(&self, time: TimeVal) -> Result<Option<TimeVal>> {
+        let clock = self.connection_core().clock_sync.lock();
+        Ok(clock
+            .estimate()
+            .map(|estimate| estimate.server_to_local(time)))
+    }
+
+    /// Get the sequence number counters of each open endpoint that tracks them,
+    /// to quantify message loss and reordering.
+    fn sequence_stats(&self) -> Result<Vec<SequenceStats>> {
+        let endpoints = self.connection_core().endpoints.lock();
+        Ok(endpoints
+            .iter()
+            .flatten()
+            .filter_map(|ep| ep.sequence_stats())
+            .collect())
+    }
+
+    /// Get the cookie each open endpoint's peer sent in the handshake,
+    /// with its VRPN version and log mode, e.g. to work around version-specific quirks.
+    fn remote_versions(&self) -> Result<Vec<CookieData>> {
+        let endpoints = self.connection_core().endpoints.lock();
+        Ok(endpoints
+            .iter()
+            .flatten()
+            .filter_map(|ep| ep.remote_cookie())
+            .collect())
+    }
+
+    /// Call `observer` with each sender a peer describes, and the local ID it maps to,
+    /// e.g. to list what a server offers without registering names first.
+    ///
+    /// It is called while dispatching, so must not register names or handlers.
+    fn on_new_sender(
+        &self,
+        observer: impl FnMut(&SenderName, LocalId<SenderId>) + Send + 'static,
+    ) -> Result<()> {
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
+        dispatcher.add_sender_observer(Box::new(observer));
+        Ok(())
+    }
+
+    /// Call `observer` with each message type a peer describes, and the local ID it maps to.
+    ///
+    /// It is called while dispatching, so must not register names or handlers.
+    fn on_new_type(
+        &self,
+        observer: impl FnMut(&MessageTypeName, LocalId<MessageTypeId>) + Send + 'static,
+    ) -> Result<()> {
+        let mut dispatcher = self.connection_core().type_dispatcher.write();
+        dispatcher.add_type_observer(Box::new(observer));
+        Ok(())
+    }
+
+    /// Subscribe to lifecycle events: endpoints connecting and closing, descriptions received, and so on.
+    fn events(&self) -> Result<LifecycleEvents> {
+        Ok(self
+            .connection_core()
+            .type_dispatcher
+            .read()
+            .subscribe_events())
+    }
+
+    /// Look up the IDs of senders and message types by name, without locking the dispatcher.
+    fn registrations(&self) -> Registrations {
+        self.connection_core()
+            .type_dispatcher
+            .read()
+            .registrations()
+    }
+
+    /// Watch the status of this connection, to react to changes instead of polling `status`.
+    fn status_watch(&self) -> Result<StatusWatch> {
+        let core = self.connection_core();
+        core.status.send(self.status());
+        Ok(core.status.subscribe())
+    }
+
     /// Gets a reference-counted handle to the mutex-protected endpoint vector.
     fn endpoints(&self) -> SharedEndpointVec<Self::SpecificEndpoint> {
         Arc::clone(&self.connection_core().endpoints)
     }
 
-    /// Gets a reference-counted handle to the mutex-protected type dispatcher.
-    fn dispatcher(&self) -> Arc<Mutex<TypeDispatcher>> {
+    /// Gets a reference-counted handle to the lock-protected type dispatcher.
+    fn dispatcher(&self) -> Arc<RwLock<TypeDispatcher>> {
         Arc::clone(&self.connection_core().type_dispatcher)
     }
 }
@@ -212,24 +860,197 @@ where
     EP: Endpoint + EndpointGeneric,
 {
     pub(crate) endpoints: SharedEndpointVec<EP>,
-    pub(crate) type_dispatcher: Arc<Mutex<TypeDispatcher>>,
+    pub(crate) type_dispatcher: Arc<RwLock<TypeDispatcher>>,
     remote_log_names: LogFileNames,
     local_log_names: LogFileNames,
+    message_history: Mutex<Option<MessageHistoryConfig>>,
+    driver_waker: AtomicWaker,
+    compatibility: CompatibilityProfile,
+    buffer_pool: Mutex<BufferPool>,
+    coalesce_threshold: AtomicUsize,
+    max_message_size: AtomicUsize,
+    framing_recovery: Mutex<FramingRecovery>,
+    remote_log_policy: Mutex<RemoteLogPolicy>,
+    class_overrides: Mutex<ClassOfServiceOverrides>,
+    send_queue_limits: Mutex<SendQueueLimits>,
+    timestamp_policy: Mutex<TimestampPolicy>,
+    compression: Mutex<Compression>,
+    socket_config: Mutex<SocketConfig>,
+    poll_config: Mutex<PollConfig>,
+    timeouts: Mutex<Timeouts>,
+    clock_sync: Arc<Mutex<ClockSync>>,
+    status: StatusSender,
 }
 impl<EP> ConnectionCore<EP>
 where
     EP: Endpoint + EndpointGeneric,
 {
+    /// Create the core of a connection, logging to the given files.
+    ///
+    /// The names are validated and normalized as by `LogFileNames::builder()`.
     pub fn new(
         endpoints: Vec<Option<EP>>,
         local_log_names: Option<LogFileNames>,
         remote_log_names: Option<LogFileNames>,
+    ) -> Result<ConnectionCore<EP>> {
+        let validate = |names: Option<LogFileNames>| -> Result<LogFileNames> {
+            Ok(names
+                .map(LogFileNames::validated)
+                .transpose()?
+                .unwrap_or_default())
+        };
+        let remote_log_names = validate(remote_log_names)?;
+        let local_log_names = validate(local_log_names)?;
+        Ok(ConnectionCore::with_log_names(
+            endpoints,
+            local_log_names,
+            remote_log_names,
+        ))
+    }
+
+    /// Create the core of a connection that does not log.
+    pub fn without_logs(endpoints: Vec<Option<EP>>) -> ConnectionCore<EP> {
+        ConnectionCore::with_log_names(endpoints, LogFileNames::new(), LogFileNames::new())
+    }
+
+    fn with_log_names(
+        endpoints: Vec<Option<EP>>,
+        local_log_names: LogFileNames,
+        remote_log_names: LogFileNames,
     ) -> ConnectionCore<EP> {
         ConnectionCore {
             endpoints: Arc::new(Mutex::new(endpoints)),
-            type_dispatcher: Arc::new(Mutex::new(TypeDispatcher::new())),
-            remote_log_names: LogFileNames::from(remote_log_names),
-            local_log_names: LogFileNames::from(local_log_names),
+            type_dispatcher: Arc::new(RwLock::new(TypeDispatcher::new())),
+            remote_log_names,
+            local_log_names,
+            message_history: Mutex::new(None),
+            driver_waker: AtomicWaker::new(),
+            compatibility: CompatibilityProfile::default(),
+            buffer_pool: Mutex::new(BufferPool::new()),
+            coalesce_threshold: AtomicUsize::new(DEFAULT_COALESCE_THRESHOLD),
+            max_message_size: AtomicUsize::new(DEFAULT_MAX_MESSAGE_SIZE),
+            framing_recovery: Mutex::new(FramingRecovery::default()),
+            remote_log_policy: Mutex::new(RemoteLogPolicy::default()),
+            class_overrides: Mutex::new(ClassOfServiceOverrides::default()),
+            send_queue_limits: Mutex::new(SendQueueLimits::default()),
+            timestamp_policy: Mutex::new(TimestampPolicy::default()),
+            compression: Mutex::new(Compression::default()),
+            socket_config: Mutex::new(SocketConfig::default()),
+            poll_config: Mutex::new(PollConfig::default()),
+            timeouts: Mutex::new(Timeouts::default()),
+            clock_sync: Arc::new(Mutex::new(ClockSync::default())),
+            status: StatusSender::new(ConnectionStatus::ClientConnecting),
         }
     }
+
+    /// Use the given compatibility profile for the peers of this connection.
+    pub fn with_compatibility(self, compatibility: CompatibilityProfile) -> ConnectionCore<EP> {
+        ConnectionCore {
+            compatibility,
+            ..self
+        }
+    }
+
+    /// Use the given limits from the start, including for the first connection attempt.
+    pub fn with_timeouts(self, timeouts: Timeouts) -> ConnectionCore<EP> {
+        ConnectionCore {
+            timeouts: Mutex::new(timeouts),
+            ..self
+        }
+    }
+
+    /// The compatibility profile used for the peers of this connection.
+    pub fn compatibility(&self) -> CompatibilityProfile {
+        self.compatibility
+    }
+
+    /// Register the waker of the task driving this connection.
+    pub fn register_driver_waker(&self, waker: &Waker) {
+        self.driver_waker.register(waker)
+    }
+
+    /// Wake the task driving this connection, if any, e.g. because there are messages to send.
+    pub fn wake_driver(&self) {
+        self.driver_waker.wake()
+    }
+
+    /// Tell status watchers the connection's current status, if it changed.
+    pub fn publish_status(&self, status: ConnectionStatus) {
+        self.status.send(status)
+    }
+
+    /// The message history settings to apply to new endpoints.
+    pub fn message_history_config(&self) -> Result<Option<MessageHistoryConfig>> {
+        Ok(self.message_history.lock().clone())
+    }
+
+    /// The poll limits to apply to new endpoints.
+    pub fn poll_config(&self) -> Result<PollConfig> {
+        Ok(*self.poll_config.lock())
+    }
+
+    /// The limits to use for connection attempts and new endpoints.
+    pub fn timeouts(&self) -> Result<Timeouts> {
+        Ok(*self.timeouts.lock())
+    }
+
+    /// The estimate of the server's clock, shared with the handler updating it.
+    pub fn clock_sync(&self) -> Arc<Mutex<ClockSync>> {
+        Arc::clone(&self.clock_sync)
+    }
+
+    /// The write coalescing threshold to apply to new endpoints.
+    pub fn coalesce_threshold(&self) -> usize {
+        self.coalesce_threshold.load(Ordering::Relaxed)
+    }
+
+    /// The incoming message size limit to apply to new endpoints.
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size.load(Ordering::Relaxed)
+    }
+
+    /// How new endpoints handle corrupt framing.
+    pub fn framing_recovery(&self) -> Result<FramingRecovery> {
+        Ok(*self.framing_recovery.lock())
+    }
+
+    /// Whether new endpoints log when their peer asks.
+    pub fn remote_log_policy(&self) -> Result<RemoteLogPolicy> {
+        Ok(*self.remote_log_policy.lock())
+    }
+
+    /// The class of service overrides to apply to new endpoints.
+    pub fn class_overrides(&self) -> Result<ClassOfServiceOverrides> {
+        Ok(self.class_overrides.lock().clone())
+    }
+
+    /// The send queue limits to apply to new endpoints.
+    pub fn send_queue_limits(&self) -> Result<SendQueueLimits> {
+        Ok(*self.send_queue_limits.lock())
+    }
+
+    /// The timestamp policy to apply to new endpoints.
+    pub fn timestamp_policy(&self) -> Result<TimestampPolicy> {
+        Ok(*self.timestamp_policy.lock())
+    }
+
+    /// The compression to apply to new endpoints.
+    pub fn compression(&self) -> Result<Compression> {
+        Ok(*self.compression.lock())
+    }
+
+    /// The socket options to apply to new endpoints.
+    pub fn socket_config(&self) -> Result<SocketConfig> {
+        Ok(*self.socket_config.lock())
+    }
+
+    /// The names of the files this side logs to.
+    pub fn local_log_names(&self) -> &LogFileNames {
+        &self.local_log_names
+    }
+
+    /// The names of the files we ask the other side to log to.
+    pub fn remote_log_names(&self) -> &LogFileNames {
+        &self.remote_log_names
+    }
 }
@@ -0,0 +1,232 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Backend-independent state machine for the connection life cycle.
+//!
+//! The backends own the actual sockets and connection futures:
+//! they feed events into a `ConnectionFsm` and carry out the actions it returns.
+
+use crate::{connection::ConnectionStatus, ServerInfo};
+use std::{fmt, time::Duration};
+
+/// The state of a connection.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum ConnectionState {
+    /// A client that is trying to connect to the server.
+    ClientConnecting(ServerInfo),
+    /// A client that has connected to the server.
+    ClientConnected(ServerInfo),
+    /// A client whose connection attempt failed, or was lost under `ReconnectPolicy::Manual`,
+    /// waiting to be told to try again.
+    ClientDisconnected(ServerInfo),
+    /// A server, accepting any number of endpoints.
+    Server,
+}
+
+impl ConnectionState {
+    /// The server a client is for, or `None` for a server.
+    pub fn server(&self) -> Option<&ServerInfo> {
+        match self {
+            ConnectionState::ClientConnecting(server)
+            | ConnectionState::ClientConnected(server)
+            | ConnectionState::ClientDisconnected(server) => Some(server),
+            ConnectionState::Server => None,
+        }
+    }
+}
+
+/// Something that happened that may change the state of a connection.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ConnectionEvent {
+    /// A connection attempt succeeded.
+    ConnectSucceeded,
+    /// A connection attempt failed.
+    ConnectFailed,
+    /// All endpoints have closed.
+    AllEndpointsClosed,
+    /// The user asked to reconnect a disconnected client.
+    Reconnect,
+}
+
+/// What the backend should do as a result of a transition.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum ConnectionAction {
+    /// Nothing to do.
+    None,
+    /// Start a (new) attempt to connect to this server.
+    StartConnecting(ServerInfo),
+}
+
+/// An event that is not valid in the current state.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct InvalidTransition {
+    pub state: ConnectionState,
+    pub event: ConnectionEvent,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "connection event {:?} not valid in state {:?}",
+            self.event, self.state
+        )
+    }
+}
+
+impl std::error::Error for InvalidTransition {}
+
+/// When a client connects to its server again, without being asked to with `reconnect`.
+///
+/// The backends apply this on top of the state machine.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum ReconnectPolicy {
+    /// Reconnect straight away when the connection is lost,
+    /// but after a failed attempt, wait to be asked.
+    #[default]
+    OnLoss,
+    /// Only connect again when asked: a lost connection leaves the client disconnected.
+    Manual,
+    /// Reconnect when the connection is lost, and retry each failed attempt after `delay`.
+    Retry { delay: Duration },
+}
+
+/// The connection state machine.
+///
+/// Transitions:
+///
+/// | State                | Event                | New state            | Action             |
+/// |----------------------|----------------------|----------------------|--------------------|
+/// | `ClientConnecting`   | `ConnectSucceeded`   | `ClientConnected`    |                    |
+/// | `ClientConnecting`   | `ConnectFailed`      | `ClientDisconnected` |                    |
+/// | `ClientConnected`    | `AllEndpointsClosed` | `ClientConnecting`   | `StartConnecting`  |
+/// | `ClientDisconnected` | `Reconnect`          | `ClientConnecting`   | `StartConnecting`  |
+/// | `Server`             | `AllEndpointsClosed` | `Server`             |                    |
+///
+/// Any other combination is an `InvalidTransition`, which leaves the state unchanged.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ConnectionFsm {
+    state: ConnectionState,
+}
+
+impl ConnectionFsm {
+    /// Create the state machine for a client, along with the action to start connecting.
+    pub fn new_client(server: ServerInfo) -> (ConnectionFsm, ConnectionAction) {
+        (
+            ConnectionFsm {
+                state: ConnectionState::ClientConnecting(server.clone()),
+            },
+            ConnectionAction::StartConnecting(server),
+        )
+    }
+
+    /// Create the state machine for a server.
+    pub fn new_server() -> ConnectionFsm {
+        ConnectionFsm {
+            state: ConnectionState::Server,
+        }
+    }
+
+    pub fn state(&self) -> &ConnectionState {
+        &self.state
+    }
+
+    /// Apply an event, returning the action the backend should take.
+    pub fn handle(
+        &mut self,
+        event: ConnectionEvent,
+    ) -> std::result::Result<ConnectionAction, InvalidTransition> {
+        use ConnectionEvent::*;
+        use ConnectionState::*;
+        let (new_state, action) = match (&self.state, event) {
+            (ClientConnecting(server), ConnectSucceeded) => {
+                (ClientConnected(server.clone()), ConnectionAction::None)
+            }
+            (ClientConnecting(server), ConnectFailed) => {
+                (ClientDisconnected(server.clone()), ConnectionAction::None)
+            }
+            (ClientConnected(server), AllEndpointsClosed)
+            | (ClientDisconnected(server), Reconnect) => (
+                ClientConnecting(server.clone()),
+                ConnectionAction::StartConnecting(server.clone()),
+            ),
+            (Server, AllEndpointsClosed) => (Server, ConnectionAction::None),
+            (state, event) => {
+                return Err(InvalidTransition {
+                    state: state.clone(),
+                    event,
+                })
+            }
+        };
+        self.state = new_state;
+        Ok(action)
+    }
+
+    /// Get the status corresponding to the current state.
+    pub fn status(&self, num_endpoints: usize) -> ConnectionStatus {
+        match self.state {
+            ConnectionState::ClientConnecting(_) => ConnectionStatus::ClientConnecting,
+            ConnectionState::ClientConnected(_) => ConnectionStatus::ClientConnected,
+            ConnectionState::ClientDisconnected(_) => ConnectionStatus::ClientDisconnected,
+            ConnectionState::Server => ConnectionStatus::Server(num_endpoints),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> ServerInfo {
+        "tcp://127.0.0.1:3883".parse().unwrap()
+    }
+
+    #[test]
+    fn client_lifecycle() {
+        let (mut fsm, action) = ConnectionFsm::new_client(server());
+        assert_eq!(action, ConnectionAction::StartConnecting(server()));
+        assert_eq!(fsm.status(0), ConnectionStatus::ClientConnecting);
+
+        assert_eq!(
+            fsm.handle(ConnectionEvent::ConnectSucceeded),
+            Ok(ConnectionAction::None)
+        );
+        assert_eq!(fsm.status(1), ConnectionStatus::ClientConnected);
+
+        // Losing the server means trying again.
+        assert_eq!(
+            fsm.handle(ConnectionEvent::AllEndpointsClosed),
+            Ok(ConnectionAction::StartConnecting(server()))
+        );
+        assert_eq!(fsm.state(), &ConnectionState::ClientConnecting(server()));
+
+        // Failing to connect waits for a reconnect request.
+        assert_eq!(
+            fsm.handle(ConnectionEvent::ConnectFailed),
+            Ok(ConnectionAction::None)
+        );
+        assert_eq!(fsm.status(0), ConnectionStatus::ClientDisconnected);
+        assert_eq!(
+            fsm.handle(ConnectionEvent::Reconnect),
+            Ok(ConnectionAction::StartConnecting(server()))
+        );
+    }
+
+    #[test]
+    fn invalid_transitions() {
+        let (mut fsm, _) = ConnectionFsm::new_client(server());
+        let err = fsm.handle(ConnectionEvent::Reconnect).unwrap_err();
+        assert_eq!(err.event, ConnectionEvent::Reconnect);
+        // State unchanged
+        assert_eq!(fsm.state(), &ConnectionState::ClientConnecting(server()));
+
+        let mut fsm = ConnectionFsm::new_server();
+        assert!(fsm.handle(ConnectionEvent::ConnectSucceeded).is_err());
+        assert_eq!(
+            fsm.handle(ConnectionEvent::AllEndpointsClosed),
+            Ok(ConnectionAction::None)
+        );
+        assert_eq!(fsm.status(3), ConnectionStatus::Server(3));
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! The datagram a server multicasts periodically so clients can discover it.
+
+use crate::alloc_prelude::*;
+use crate::buffer_unbuffer::{
+    check_buffer_remaining, BufferResult, BufferSize, BufferTo, BufferUnbufferError, UnbufferFrom,
+    UnbufferResult,
+};
+use bytes::{Buf, BufMut};
+
+/// The first line of every announcement, identifying the format.
+const MAGIC: &str = "vrpn-announce 1";
+
+/// A server announcing its name, the port it listens on, and the devices it serves.
+///
+/// Sent as lines of text: a fixed first line, then the port, the server name,
+/// and one device name per line, with a null terminator.
+/// The address to connect to is the source address of the datagram.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Announcement {
+    pub name: String,
+    pub port: u16,
+    pub devices: Vec<String>,
+}
+
+impl Announcement {
+    pub fn new(name: impl Into<String>, port: u16) -> Announcement {
+        Announcement {
+            name: name.into(),
+            port,
+            devices: Vec::new(),
+        }
+    }
+
+    /// Add the names of devices served, like `Tracker0`.
+    pub fn with_devices<I, S>(mut self, devices: I) -> Announcement
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.devices.extend(devices.into_iter().map(Into::into));
+        self
+    }
+
+    fn text(&self) -> String {
+        let mut text = format!("{}\n{}\n{}", MAGIC, self.port, self.name);
+        for device in &self.devices {
+            text.push('\n');
+            text.push_str(device);
+        }
+        text
+    }
+}
+
+impl BufferSize for Announcement {
+    fn buffer_size(&self) -> usize {
+        self.text().len() + 1
+    }
+}
+
+impl BufferTo for Announcement {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        let text = self.text();
+        check_buffer_remaining(buf, text.len() + 1)?;
+        buf.put(text.as_bytes());
+        buf.put_u8(0);
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for Announcement {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        let mut text = Vec::new();
+        while buf.has_remaining() {
+            match buf.get_u8() {
+                0 => break,
+                c => text.push(c),
+            }
+        }
+        let parse_error = || BufferUnbufferError::ParseError {
+            parsing_kind: "announcement".to_string(),
+            s: String::from_utf8_lossy(&text).into_owned(),
+        };
+        let text_str = core::str::from_utf8(&text).map_err(|_| parse_error())?;
+        let mut lines = text_str.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err(parse_error());
+        }
+        let port: u16 = lines.next().ok_or_else(parse_error)?.trim().parse()?;
+        let name = lines.next().ok_or_else(parse_error)?;
+        Ok(Announcement::new(name, port).with_devices(lines.filter(|line| !line.is_empty())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_unbuffer::BytesMutExtras;
+    use bytes::{Bytes, BytesMut};
+
+    #[test]
+    fn roundtrip() {
+        let announcement =
+            Announcement::new("lab tracker", 3883).with_devices(["Tracker0", "Button0"]);
+        let buf = BytesMut::allocate_and_buffer(announcement.clone())
+            .unwrap()
+            .freeze();
+        assert_eq!(
+            &buf[..],
+            b"vrpn-announce 1\n3883\nlab tracker\nTracker0\nButton0\0"
+        );
+        assert_eq!(
+            Announcement::unbuffer_from(&mut buf.clone()).unwrap(),
+            announcement
+        );
+
+        let announcement = Announcement::new("empty", 4500);
+        let mut buf = BytesMut::allocate_and_buffer(announcement.clone())
+            .unwrap()
+            .freeze();
+        assert_eq!(Announcement::unbuffer_from(&mut buf).unwrap(), announcement);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(Announcement::unbuffer_from(&mut Bytes::from_static(b"127.0.0.1 4500\0")).is_err());
+        assert!(Announcement::unbuffer_from(&mut Bytes::from_static(
+            b"vrpn-announce 1\nport\nx\0"
+        ))
+        .is_err());
+        assert!(
+            Announcement::unbuffer_from(&mut Bytes::from_static(b"vrpn-announce 1\n3883\0"))
+                .is_err()
+        );
+    }
+}
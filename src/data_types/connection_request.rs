@@ -0,0 +1,102 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! The datagram a client sends to a server's UDP port, asking to be connected back to over TCP.
+
+use crate::alloc_prelude::*;
+use crate::buffer_unbuffer::{
+    check_buffer_remaining, BufferResult, BufferSize, BufferTo, BufferUnbufferError, UnbufferFrom,
+    UnbufferResult,
+};
+use bytes::{Buf, BufMut};
+use core::net::{IpAddr, SocketAddr};
+
+/// A request for the server to open a TCP connection to `socket_address`.
+///
+/// Sent as the address and port in text, separated by a space, with a null terminator.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ConnectionRequest {
+    pub socket_address: SocketAddr,
+}
+
+impl ConnectionRequest {
+    pub fn new(socket_address: SocketAddr) -> ConnectionRequest {
+        ConnectionRequest { socket_address }
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "{} {}",
+            self.socket_address.ip(),
+            self.socket_address.port()
+        )
+    }
+}
+
+impl BufferSize for ConnectionRequest {
+    fn buffer_size(&self) -> usize {
+        self.text().len() + 1
+    }
+}
+
+impl BufferTo for ConnectionRequest {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        let text = self.text();
+        check_buffer_remaining(buf, text.len() + 1)?;
+        buf.put(text.as_bytes());
+        buf.put_u8(0);
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for ConnectionRequest {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        let mut text = Vec::new();
+        while buf.has_remaining() {
+            match buf.get_u8() {
+                0 => break,
+                c => text.push(c),
+            }
+        }
+        let parse_error = || BufferUnbufferError::ParseError {
+            parsing_kind: "connection request".to_string(),
+            s: String::from_utf8_lossy(&text).into_owned(),
+        };
+        let text_str = core::str::from_utf8(&text).map_err(|_| parse_error())?;
+        let (ip, port) = text_str.trim().split_once(' ').ok_or_else(parse_error)?;
+        let ip: IpAddr = ip.parse()?;
+        let port: u16 = port.trim().parse()?;
+        Ok(ConnectionRequest::new(SocketAddr::new(ip, port)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_unbuffer::BytesMutExtras;
+    use bytes::{Bytes, BytesMut};
+
+    #[test]
+    fn roundtrip() {
+        let request = ConnectionRequest::new("127.0.0.1:4500".parse().unwrap());
+        let buf = BytesMut::allocate_and_buffer(request).unwrap().freeze();
+        assert_eq!(&buf[..], b"127.0.0.1 4500\0");
+        assert_eq!(
+            ConnectionRequest::unbuffer_from(&mut buf.clone()).unwrap(),
+            request
+        );
+
+        let request = ConnectionRequest::new("[::1]:4500".parse().unwrap());
+        let mut buf = BytesMut::allocate_and_buffer(request).unwrap().freeze();
+        assert_eq!(ConnectionRequest::unbuffer_from(&mut buf).unwrap(), request);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(ConnectionRequest::unbuffer_from(&mut Bytes::from_static(b"127.0.0.1\0")).is_err());
+        assert!(
+            ConnectionRequest::unbuffer_from(&mut Bytes::from_static(b"localhost 3883\0")).is_err()
+        );
+    }
+}
@@ -2,14 +2,16 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
+use crate::alloc_prelude::*;
 use crate::buffer_unbuffer::{
     check_buffer_remaining, check_unbuffer_remaining, consume_expected, unbuffer_decimal_digits,
     BufferResult, BufferTo, ConstantBufferSize, UnbufferFrom, UnbufferResult,
 };
 
 use super::{constants, LogMode};
+use crate::compatibility::CompatibilityProfile;
 use bytes::{Buf, BufMut};
-use std::fmt::{self, Display, Formatter};
+use core::fmt::{self, Display, Formatter};
 
 const COOKIE_PADDING: &[u8] = b"\0\0\0\0\0";
 
@@ -167,6 +169,22 @@ pub struct VersionMismatch {
     expected: Version,
 }
 
+impl VersionMismatch {
+    pub fn new(actual: Version, expected: Version) -> VersionMismatch {
+        VersionMismatch { actual, expected }
+    }
+
+    /// The version the other side sent.
+    pub fn actual(&self) -> Version {
+        self.actual
+    }
+
+    /// The version we expected something compatible with.
+    pub fn expected(&self) -> Version {
+        self.expected
+    }
+}
+
 impl Display for VersionMismatch {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(
@@ -177,28 +195,17 @@ impl Display for VersionMismatch {
     }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for VersionMismatch {}
 
+/// Check a network cookie version using the default `CompatibilityProfile`.
 pub fn check_ver_nonfile_compatible(ver: Version) -> Result<(), VersionMismatch> {
-    if ver.major == constants::MAGIC_DATA.major {
-        Ok(())
-    } else {
-        Err(VersionMismatch {
-            actual: ver,
-            expected: constants::MAGIC_DATA,
-        })
-    }
+    CompatibilityProfile::default().check_cookie_version(ver)
 }
 
+/// Check a file cookie version using the default `CompatibilityProfile`.
 pub fn check_ver_file_compatible(ver: Version) -> Result<(), VersionMismatch> {
-    if ver.major == constants::FILE_MAGIC_DATA.major {
-        Ok(())
-    } else {
-        Err(VersionMismatch {
-            actual: ver,
-            expected: constants::FILE_MAGIC_DATA,
-        })
-    }
+    CompatibilityProfile::default().check_file_cookie_version(ver)
 }
 
 #[cfg(test)]
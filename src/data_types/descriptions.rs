@@ -2,10 +2,10 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
+use crate::alloc_prelude::*;
 use bytes::{Buf, BufMut, Bytes};
 
-use std::{
-    io::BufRead,
+use core::{
     marker::PhantomData,
     net::{IpAddr, SocketAddr},
 };
@@ -55,13 +55,33 @@ impl<I: IdWithNameAndDescription> From<TypedMessage<InnerDescription<I>>> for De
 ///
 /// Converted to a Message<InnerDescription> before being sent.
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Description<T> {
     /// The ID
     pub which: T,
     /// The name associated with the ID (no null termination in this string)
+    #[cfg_attr(feature = "serde", serde(with = "name_serde"))]
     pub name: Bytes,
 }
 
+/// Names are text in practice, so are serialized as strings.
+#[cfg(feature = "serde")]
+mod name_serde {
+    use crate::alloc_prelude::*;
+    use bytes::Bytes;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(name: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from_utf8_lossy(name))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Bytes, D::Error> {
+        Ok(Bytes::from(String::deserialize(deserializer)?))
+    }
+}
+
 impl<I: IdWithNameAndDescription> Description<I> {
     pub fn from_id_and_name(id: I, name: Bytes) -> Description<I> {
         Description {
@@ -169,14 +189,10 @@ impl<I: IdWithNameAndDescription> UnbufferFrom for InnerDescription<I> {
 
 impl UnbufferFrom for UdpInnerDescription {
     fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
-        let mut ip_buf: Vec<u8> = Vec::default();
-        // ok to unwrap: a buf reader is infallible. The reader also prevents us from modifying the buffer state.
-        let length = buf.reader().read_until(0, &mut ip_buf).unwrap();
-        // let ip_buf: Vec<u8> = buf.into_iter().take_while(|b| **b != 0).cloned().collect();
-        let ip_str = String::from_utf8_lossy(&ip_buf);
-        let addr: IpAddr = ip_str.parse()?;
-        buf.advance(length);
-
+        // The address string, up to its null terminator, is the whole body.
+        let body = buf.copy_to_bytes(buf.remaining());
+        let end = body.iter().position(|&b| b == 0).unwrap_or(body.len());
+        let addr: IpAddr = String::from_utf8_lossy(&body[..end]).parse()?;
         Ok(UdpInnerDescription::new(addr))
     }
 }
@@ -196,3 +212,21 @@ impl BufferTo for UdpInnerDescription {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_unbuffer::BytesMutExtras;
+    use bytes::BytesMut;
+
+    #[test]
+    fn udp_description_roundtrip() {
+        let desc = UdpInnerDescription::new(IpAddr::from([192, 168, 1, 5]));
+        let mut buf = BytesMut::allocate_and_buffer(desc.clone())
+            .unwrap()
+            .freeze();
+        assert_eq!(&buf[..], b"192.168.1.5\0");
+        assert_eq!(UdpInnerDescription::unbuffer_from(&mut buf).unwrap(), desc);
+        assert!(buf.is_empty());
+    }
+}
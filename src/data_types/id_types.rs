@@ -4,7 +4,10 @@
 
 //! Basic ID types used across VRPN.
 
-use std::{convert::TryFrom, fmt::Debug, hash::Hash};
+use core::{
+    fmt::{self, Debug, Display},
+    hash::Hash,
+};
 
 use crate::buffer_unbuffer::WrappedConstantSize;
 
@@ -14,7 +17,7 @@ pub type IdType = i32;
 /// Unsigned version of IdType
 pub type IdTypeUnsigned = u32;
 
-pub const MAX_VEC_USIZE: usize = (IdType::max_value() - 2) as usize;
+pub const MAX_VEC_USIZE: usize = (IdType::MAX - 2) as usize;
 
 /// Trait for types that wrap an integer to treat it as an ID, namely `MessageTypeId` and `SenderId`
 ///
@@ -67,6 +70,12 @@ impl<T: UnwrappedId> IntoId for LocalId<T> {
     }
 }
 
+impl<T: UnwrappedId + Display> Display for LocalId<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "local {}", self.0)
+    }
+}
+
 /// Remote-side ID in the translation table
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct RemoteId<T: UnwrappedId>(pub T);
@@ -78,6 +87,12 @@ impl<T: UnwrappedId> IntoId for RemoteId<T> {
     }
 }
 
+impl<T: UnwrappedId + Display> Display for RemoteId<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "remote {}", self.0)
+    }
+}
+
 impl<T: UnwrappedId> Id for RemoteId<T> {
     fn get(&self) -> IdType {
         self.0.get()
@@ -89,6 +104,7 @@ impl<T: UnwrappedId> Id for RemoteId<T> {
 
 /// ID for a message type
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MessageTypeId(pub IdType);
 
 impl MessageTypeId {
@@ -111,6 +127,12 @@ impl Id for MessageTypeId {
 
 impl UnwrappedId for MessageTypeId {}
 
+impl Display for MessageTypeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MessageTypeId({})", self.0)
+    }
+}
+
 impl WrappedConstantSize for MessageTypeId {
     type WrappedType = IdType;
     fn get(&self) -> Self::WrappedType {
@@ -123,6 +145,7 @@ impl WrappedConstantSize for MessageTypeId {
 
 /// ID for a sender
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SenderId(pub IdType);
 
 impl Id for SenderId {
@@ -136,6 +159,12 @@ impl Id for SenderId {
 
 impl UnwrappedId for SenderId {}
 
+impl Display for SenderId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SenderId({})", self.0)
+    }
+}
+
 impl WrappedConstantSize for SenderId {
     type WrappedType = IdType;
     fn get(&self) -> Self::WrappedType {
@@ -169,6 +198,7 @@ where
 
 /// Sequence number - not used on receive side, only used for sniffers (?)
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SequenceNumber(pub u32);
 
 impl WrappedConstantSize for SequenceNumber {
@@ -183,6 +213,7 @@ impl WrappedConstantSize for SequenceNumber {
 
 /// Sensor ID for trackers.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sensor(pub i32);
 
 impl WrappedConstantSize for Sensor {
@@ -195,6 +226,7 @@ impl WrappedConstantSize for Sensor {
     }
 }
 
+#[cfg(feature = "std")]
 pub(crate) enum CategorizedId {
     BelowZero(IdType),
     InArray(IdTypeUnsigned),
@@ -206,7 +238,9 @@ pub(crate) enum CategorizedId {
 /// Typically, calling code will then match on the result and make one or more
 /// of the variants produce an error. However, which ones are errors vary between
 /// functions.
+#[cfg(feature = "std")]
 pub(crate) fn categorize_id<T: UnwrappedId>(id: T, len: usize) -> CategorizedId {
+    use core::convert::TryFrom;
     let id = id.get();
     match u32::try_from(id) {
         Ok(id_u32) if (id_u32 as usize) < len => CategorizedId::InArray(id_u32),
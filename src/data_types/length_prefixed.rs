@@ -2,13 +2,20 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
+//! Strings preceded by their length, as in sender and message type descriptions.
+//!
+//! Implementations differ in whether the length counts a trailing null,
+//! so unbuffering accepts either.
+
+use crate::alloc_prelude::*;
 use bytes::{Buf, BufMut, Bytes};
-use std::mem::size_of;
+use core::mem::size_of;
 
 use crate::buffer_unbuffer::{
     buffer::{self, BufferTo},
     size_requirement::*,
     unbuffer::{self, UnbufferFrom},
+    BufferUnbufferError,
 };
 
 /// Does the "length prefix" value include a trailing null character (strlen() + 1)?
@@ -43,38 +50,161 @@ pub fn buffer_string<T: BufMut>(
     termination: NullTermination,
     null_in_len: LengthBehavior,
 ) -> buffer::BufferResult {
-    let mut buf_size = buffer_size(s, termination);
+    let buf_size = buffer_size(s, termination);
 
     buffer::check_buffer_remaining(buf, buf_size)?;
+    // The transmitted length does not include the length prefix itself.
+    let mut len = buf_size - size_of::<u32>();
     if termination == NullTermination::AddTrailingNull && null_in_len == LengthBehavior::ExcludeNull
     {
         // Decrement the length that we transmit if we're adding a null terminator but not including it in the length.
-        buf_size -= 1;
+        len -= 1;
     }
-    let buf_size = buf_size as u32;
-    buf_size.buffer_to(buf)?;
+    (len as u32).buffer_to(buf)?;
 
     buf.put(s);
-    buf.put_u8(0);
+    if termination == NullTermination::AddTrailingNull {
+        buf.put_u8(0);
+    }
     Ok(())
 }
 
-/// Unbuffer a string, preceded by its length and followed by a null bytes.
+/// Unbuffer a string preceded by its length, encoded as specified.
+///
+/// The returned string never includes the null terminator.
+pub fn unbuffer_string_with<T: Buf>(
+    buf: &mut T,
+    termination: NullTermination,
+    null_in_len: LengthBehavior,
+) -> unbuffer::UnbufferResult<Bytes> {
+    let len = unbuffer_len(buf)?;
+    match (termination, null_in_len) {
+        (NullTermination::AddTrailingNull, LengthBehavior::IncludeNull) => {
+            if len == 0 {
+                return Err(BufferUnbufferError::ParseError {
+                    parsing_kind: "length-prefixed string".to_string(),
+                    s: "length of 0 has no room for the null terminator".to_string(),
+                });
+            }
+            let s = buf.copy_to_bytes(len - 1);
+            unbuffer::consume_expected(buf, b"\0")?;
+            Ok(s)
+        }
+        (NullTermination::AddTrailingNull, LengthBehavior::ExcludeNull) => {
+            let s = buf.copy_to_bytes(len);
+            unbuffer::consume_expected(buf, b"\0")?;
+            Ok(s)
+        }
+        (NullTermination::NoNull, _) => Ok(buf.copy_to_bytes(len)),
+    }
+}
+
+/// Unbuffer a string preceded by its length, detecting how it was encoded.
+///
+/// Accepts a length that includes a trailing null (as the C++ implementation sends),
+/// a length that excludes a trailing null that follows, or no null at all.
+/// The returned string never includes the null terminator.
 pub fn unbuffer_string<T: Buf>(buf: &mut T) -> unbuffer::UnbufferResult<Bytes> {
-    let buf_size =
-        u32::unbuffer_from(buf).map_err(ExpandSizeRequirement::expand_size_requirement)?;
-
-    let buf_size = buf_size as usize;
-    unbuffer::check_unbuffer_remaining(buf, buf_size)?;
-    assert_ne!(
-        buf_size, 0,
-        "length-prefixed string size is expected to be greater than 0"
-    );
-    // Subtract null-terminator from length we want.
-    let buf_size = buf_size - 1;
-
-    let s = buf.copy_to_bytes(buf_size);
-    // Grab null terminator
-    unbuffer::consume_expected(buf, b"\0")?;
+    let len = unbuffer_len(buf)?;
+    let mut s = buf.copy_to_bytes(len);
+    if s.last() == Some(&0) {
+        s.truncate(len - 1);
+    } else if buf.has_remaining() && buf.chunk()[0] == 0 {
+        // Null terminator not counted in the length.
+        buf.advance(1);
+    }
     Ok(s)
 }
+
+/// Unbuffer the length prefix, checking that the string it describes is all there.
+fn unbuffer_len<T: Buf>(buf: &mut T) -> unbuffer::UnbufferResult<usize> {
+    let len = u32::unbuffer_from(buf).map_err(ExpandSizeRequirement::expand_size_requirement)?;
+    let len = len as usize;
+    unbuffer::check_unbuffer_remaining(buf, len)?;
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+
+    #[test]
+    fn roundtrip() {
+        let mut buf = BytesMut::new();
+        buffer_string(
+            b"Tracker0",
+            &mut buf,
+            NullTermination::AddTrailingNull,
+            LengthBehavior::IncludeNull,
+        )
+        .unwrap();
+        assert_eq!(&buf[..], b"\0\0\0\x09Tracker0\0");
+        let mut bytes = buf.freeze();
+        assert_eq!(unbuffer_string(&mut bytes).unwrap(), &b"Tracker0"[..]);
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn each_encoding() {
+        let encodings = [
+            (
+                NullTermination::AddTrailingNull,
+                LengthBehavior::IncludeNull,
+                &b"\0\0\0\x09Tracker0\0"[..],
+            ),
+            (
+                NullTermination::AddTrailingNull,
+                LengthBehavior::ExcludeNull,
+                &b"\0\0\0\x08Tracker0\0"[..],
+            ),
+            (
+                NullTermination::NoNull,
+                LengthBehavior::ExcludeNull,
+                &b"\0\0\0\x08Tracker0"[..],
+            ),
+        ];
+        for (termination, null_in_len, expected) in encodings {
+            let mut buf = BytesMut::new();
+            buffer_string(b"Tracker0", &mut buf, termination, null_in_len).unwrap();
+            assert_eq!(&buf[..], expected);
+
+            let mut bytes = buf.freeze();
+            assert_eq!(
+                unbuffer_string_with(&mut bytes.clone(), termination, null_in_len).unwrap(),
+                &b"Tracker0"[..]
+            );
+            // Auto-detection gives the same name, without stray nulls, consuming it all.
+            assert_eq!(unbuffer_string(&mut bytes).unwrap(), &b"Tracker0"[..]);
+            assert!(bytes.is_empty());
+        }
+    }
+
+    #[test]
+    fn empty_and_malformed() {
+        let mut bytes = Bytes::from_static(b"\0\0\0\x01\0");
+        assert_eq!(unbuffer_string(&mut bytes).unwrap(), &b""[..]);
+        assert!(bytes.is_empty());
+        assert_eq!(
+            unbuffer_string(&mut Bytes::from_static(b"\0\0\0\0")).unwrap(),
+            &b""[..]
+        );
+
+        // A length of 0 cannot include a null.
+        assert!(unbuffer_string_with(
+            &mut Bytes::from_static(b"\0\0\0\0"),
+            NullTermination::AddTrailingNull,
+            LengthBehavior::IncludeNull
+        )
+        .is_err());
+        // Missing terminator where one is expected.
+        assert!(unbuffer_string_with(
+            &mut Bytes::from_static(b"\0\0\0\x02ab"),
+            NullTermination::AddTrailingNull,
+            LengthBehavior::IncludeNull
+        )
+        .is_err());
+        // Length longer than the data.
+        assert!(unbuffer_string(&mut Bytes::from_static(b"\0\0\0\x09Track")).is_err());
+    }
+}
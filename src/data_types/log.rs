@@ -3,11 +3,16 @@
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
 use crate::buffer_unbuffer::{
-    buffer, size_requirement::*, unbuffer, BufferSize, BufferUnbufferError, ConstantBufferSize,
+    buffer, size_requirement::*, unbuffer, BufferSize, BufferUnbufferError, BytesMutExtras,
+    ConstantBufferSize, UnbufferFrom,
 };
-use bytes::{Buf, BufMut, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use core::fmt;
 
-use super::{constants, name_types::MessageTypeIdentifier, TypedMessageBody};
+use super::{
+    constants, id_types::SenderId, name_types::MessageTypeIdentifier, TypedMessage,
+    TypedMessageBody,
+};
 
 bitflags! {
     pub struct LogMode: u8  {
@@ -18,7 +23,38 @@ bitflags! {
     }
 }
 
+/// Errors from validating log file names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogFileNameError {
+    EmptyName,
+    ContainsNull,
+    SameInAndOut(Bytes),
+}
+
+impl fmt::Display for LogFileNameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFileNameError::EmptyName => {
+                f.write_str("log file name is empty after normalization")
+            }
+            LogFileNameError::ContainsNull => {
+                f.write_str("log file name contains an embedded null byte")
+            }
+            LogFileNameError::SameInAndOut(name) => write!(
+                f,
+                "incoming and outgoing log file names are both {:?}",
+                name
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LogFileNameError {}
+
 /// Stores an optional byte string for log file name, one for in, one for out.
+///
+/// Construct with [`LogFileNames::builder()`] to get validated, normalized names.
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct LogFileNames {
     in_log_file: Option<Bytes>,
@@ -27,7 +63,7 @@ pub struct LogFileNames {
 
 fn make_log_name<T>(name: Option<T>) -> Option<Bytes>
 where
-    Bytes: std::convert::From<T>,
+    Bytes: core::convert::From<T>,
 {
     match name {
         None => None,
@@ -42,7 +78,110 @@ where
     }
 }
 
+/// Normalize a log file name: trims surrounding whitespace,
+/// collapses repeated `/` separators, and drops `.` path components.
+fn normalize_log_name(name: &[u8]) -> Result<Bytes, LogFileNameError> {
+    if name.contains(&0) {
+        return Err(LogFileNameError::ContainsNull);
+    }
+    let start = name
+        .iter()
+        .position(|c| !c.is_ascii_whitespace())
+        .unwrap_or(name.len());
+    let end = name
+        .iter()
+        .rposition(|c| !c.is_ascii_whitespace())
+        .map_or(start, |i| i + 1);
+    let name = &name[start..end];
+    if name.is_empty() {
+        return Err(LogFileNameError::EmptyName);
+    }
+    let absolute = name[0] == b'/';
+    let mut normalized = BytesMut::with_capacity(name.len());
+    if absolute {
+        normalized.put_u8(b'/');
+    }
+    let mut first = true;
+    for component in name
+        .split(|c| *c == b'/')
+        .filter(|c| !c.is_empty() && *c != b".")
+    {
+        if !first {
+            normalized.put_u8(b'/');
+        }
+        normalized.put_slice(component);
+        first = false;
+    }
+    if first {
+        // Nothing but separators and `.` components
+        if absolute {
+            return Ok(normalized.freeze());
+        }
+        return Err(LogFileNameError::EmptyName);
+    }
+    Ok(normalized.freeze())
+}
+
+/// Builder for validated `LogFileNames`.
+///
+/// Names are normalized (see [`LogFileNamesBuilder::build`]),
+/// and must be non-empty and distinct from each other.
+#[derive(Debug, Clone, Default)]
+pub struct LogFileNamesBuilder {
+    in_log_file: Option<Bytes>,
+    out_log_file: Option<Bytes>,
+}
+
+impl LogFileNamesBuilder {
+    pub fn new() -> LogFileNamesBuilder {
+        LogFileNamesBuilder::default()
+    }
+
+    /// Set the file name to log incoming messages to.
+    pub fn in_log_file(mut self, name: impl Into<Bytes>) -> LogFileNamesBuilder {
+        self.in_log_file = Some(name.into());
+        self
+    }
+
+    /// Set the file name to log outgoing messages to.
+    pub fn out_log_file(mut self, name: impl Into<Bytes>) -> LogFileNamesBuilder {
+        self.out_log_file = Some(name.into());
+        self
+    }
+
+    /// Validate and normalize the names.
+    ///
+    /// Each provided name has surrounding whitespace trimmed,
+    /// repeated separators collapsed, and `.` components removed.
+    /// Fails if a provided name is empty after that, contains a null byte,
+    /// or if the incoming and outgoing names are the same.
+    pub fn build(self) -> Result<LogFileNames, LogFileNameError> {
+        let in_log_file = self
+            .in_log_file
+            .map(|name| normalize_log_name(&name))
+            .transpose()?;
+        let out_log_file = self
+            .out_log_file
+            .map(|name| normalize_log_name(&name))
+            .transpose()?;
+        if let (Some(in_name), Some(out_name)) = (&in_log_file, &out_log_file) {
+            if in_name == out_name {
+                return Err(LogFileNameError::SameInAndOut(in_name.clone()));
+            }
+        }
+        Ok(LogFileNames {
+            in_log_file,
+            out_log_file,
+        })
+    }
+}
+
 impl LogFileNames {
+    /// Start building a validated set of log file names.
+    pub fn builder() -> LogFileNamesBuilder {
+        LogFileNamesBuilder::new()
+    }
+
     pub fn new() -> LogFileNames {
         LogFileNames {
             out_log_file: None,
@@ -51,7 +190,7 @@ impl LogFileNames {
     }
     pub fn from_names<T>(in_log_file: Option<T>, out_log_file: Option<T>) -> LogFileNames
     where
-        Bytes: std::convert::From<T>,
+        Bytes: core::convert::From<T>,
     {
         LogFileNames {
             out_log_file: make_log_name(out_log_file),
@@ -81,6 +220,33 @@ impl LogFileNames {
         in_mode | out_mode
     }
 
+    /// Re-validate these names, normalizing them as the builder does.
+    ///
+    /// Useful for names that did not come from the builder,
+    /// such as those received in a `LOG_DESCRIPTION` message.
+    pub fn validated(self) -> Result<LogFileNames, LogFileNameError> {
+        let mut builder = LogFileNames::builder();
+        if let Some(name) = self.in_log_file {
+            builder = builder.in_log_file(name);
+        }
+        if let Some(name) = self.out_log_file {
+            builder = builder.out_log_file(name);
+        }
+        builder.build()
+    }
+
+    /// Serialize to the body of a `LOG_DESCRIPTION` message.
+    pub fn to_wire(&self) -> Result<Bytes, BufferUnbufferError> {
+        Ok(BytesMut::allocate_and_buffer(self.clone())?.freeze())
+    }
+
+    /// Parse from the body of a `LOG_DESCRIPTION` message.
+    ///
+    /// Does not validate: use [`LogFileNames::validated`] on the result if desired.
+    pub fn from_wire(mut buf: Bytes) -> Result<LogFileNames, BufferUnbufferError> {
+        LogFileNames::unbuffer_from(&mut buf)
+    }
+
     pub fn filenames_iter(&'_ self) -> LogFileNameIter<'_> {
         LogFileNameIter {
             names: self,
@@ -91,10 +257,7 @@ impl LogFileNames {
 
 impl From<Option<LogFileNames>> for LogFileNames {
     fn from(v: Option<LogFileNames>) -> LogFileNames {
-        match v {
-            None => LogFileNames::new(),
-            Some(names) => names,
-        }
+        v.unwrap_or_default()
     }
 }
 
@@ -173,7 +336,21 @@ impl TypedMessageBody for LogFileNames {
         MessageTypeIdentifier::SystemMessageId(constants::LOG_DESCRIPTION);
 }
 
+impl From<LogFileNames> for TypedMessage<LogFileNames> {
+    /// Wraps in a `LOG_DESCRIPTION` message, which (like the C++ implementation)
+    /// carries the requested log mode in the sender field.
+    fn from(v: LogFileNames) -> TypedMessage<LogFileNames> {
+        TypedMessage::new(
+            None,
+            constants::LOG_DESCRIPTION,
+            SenderId(v.log_mode().bits().into()),
+            v,
+        )
+    }
+}
+
 fn unbuffer_logname<T: Buf>(len: usize, buf: &mut T) -> unbuffer::UnbufferResult<Option<Bytes>> {
+    unbuffer::check_unbuffer_remaining(buf, len)?;
     let name = if len > 0 {
         Some(buf.copy_to_bytes(len))
     } else {
@@ -184,7 +361,7 @@ fn unbuffer_logname<T: Buf>(len: usize, buf: &mut T) -> unbuffer::UnbufferResult
     Ok(name)
 }
 
-impl unbuffer::UnbufferFrom for LogFileNames {
+impl UnbufferFrom for LogFileNames {
     fn unbuffer_from<T: Buf>(buf: &mut T) -> unbuffer::UnbufferResult<Self> {
         let min_size = 2 * u32::constant_buffer_size() + 2;
         if buf.remaining() < min_size {
@@ -232,4 +409,80 @@ mod tests {
             LogMode::INCOMING_OUTGOING
         );
     }
+
+    #[test]
+    fn builder_normalizes() {
+        let names = LogFileNames::builder()
+            .in_log_file(&b"  ./logs//./in.vrpn "[..])
+            .out_log_file(&b"/tmp///out.vrpn"[..])
+            .build()
+            .unwrap();
+        assert_eq!(names.in_log(), &Some(Bytes::from_static(b"logs/in.vrpn")));
+        assert_eq!(names.out_log(), &Some(Bytes::from_static(b"/tmp/out.vrpn")));
+        assert_eq!(names.log_mode(), LogMode::INCOMING_OUTGOING);
+
+        assert_eq!(
+            LogFileNames::builder().build().unwrap().log_mode(),
+            LogMode::NONE
+        );
+    }
+
+    #[test]
+    fn builder_rejects_invalid() {
+        assert_eq!(
+            LogFileNames::builder().in_log_file(&b""[..]).build(),
+            Err(LogFileNameError::EmptyName)
+        );
+        assert_eq!(
+            LogFileNames::builder().out_log_file(&b" ./ "[..]).build(),
+            Err(LogFileNameError::EmptyName)
+        );
+        assert_eq!(
+            LogFileNames::builder().in_log_file(&b"a\0b"[..]).build(),
+            Err(LogFileNameError::ContainsNull)
+        );
+        assert_eq!(
+            LogFileNames::builder()
+                .in_log_file(&b"a.vrpn"[..])
+                .out_log_file(&b"./a.vrpn"[..])
+                .build(),
+            Err(LogFileNameError::SameInAndOut(Bytes::from_static(
+                b"a.vrpn"
+            )))
+        );
+    }
+
+    #[test]
+    fn wire_roundtrip() {
+        let names = LogFileNames::builder()
+            .out_log_file(&b"out.vrpn"[..])
+            .build()
+            .unwrap();
+        let wire = names.to_wire().unwrap();
+        assert_eq!(wire.len(), names.buffer_size());
+        assert_eq!(LogFileNames::from_wire(wire).unwrap(), names);
+
+        let empty = LogFileNames::new();
+        assert_eq!(
+            LogFileNames::from_wire(empty.to_wire().unwrap()).unwrap(),
+            empty
+        );
+    }
+
+    #[test]
+    fn wire_malformed() {
+        // Names longer than the body.
+        let mut wire = BytesMut::new();
+        wire.put_u32(1000);
+        wire.put_u32(0);
+        wire.put_slice(b"ab\0\0");
+        assert!(LogFileNames::from_wire(wire.freeze()).is_err());
+
+        // Missing null terminator.
+        let mut wire = BytesMut::new();
+        wire.put_u32(2);
+        wire.put_u32(0);
+        wire.put_slice(b"abc\0");
+        assert!(LogFileNames::from_wire(wire.freeze()).is_err());
+    }
 }
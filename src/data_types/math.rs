@@ -3,12 +3,17 @@
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
 //! Math types used across VRPN.
+//!
+//! On the wire, as in mainline VRPN, each is a run of big-endian doubles:
+//! `Vec3` as x, y, z, and `Quat` as x, y, z, w (vector part first).
+//! Custom message bodies can embed them with `BufferTo` and `UnbufferFrom`.
 
 use crate::buffer_unbuffer::{buffer, unbuffer, ConstantBufferSize};
 use bytes::{Buf, BufMut};
 
-/// A 3D vector of 64-bit floats
+/// A 3D vector of 64-bit floats, buffered as x, y, z.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vec3 {
     pub x: f64,
     pub y: f64,
@@ -32,7 +37,7 @@ impl Default for Vec3 {
 
 impl ConstantBufferSize for Vec3 {
     fn constant_buffer_size() -> usize {
-        std::mem::size_of::<f64>() * 3
+        core::mem::size_of::<f64>() * 3
     }
 }
 
@@ -56,8 +61,9 @@ impl unbuffer::UnbufferFrom for Vec3 {
     }
 }
 
-/// A (typically unit) quaternion corresponding to a rotation.
+/// A (typically unit) quaternion corresponding to a rotation, buffered as x, y, z, w.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Quat {
     pub s: f64,
     pub v: Vec3,
@@ -88,7 +94,7 @@ impl Quat {
 
 impl ConstantBufferSize for Quat {
     fn constant_buffer_size() -> usize {
-        std::mem::size_of::<f64>() * 4
+        core::mem::size_of::<f64>() * 4
     }
 }
 
@@ -109,3 +115,52 @@ impl unbuffer::UnbufferFrom for Quat {
         Ok(Quat::from_sv(w, v))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_unbuffer::{BufferTo, UnbufferFrom};
+    use bytes::{Bytes, BytesMut};
+    use hex_literal::hex;
+
+    #[test]
+    fn wire_order() {
+        let pos = Vec3::new(1.0, -2.5, 0.125);
+        let quat = Quat::new(0.5, 0.25, -0.5, 2.0);
+        let mut buf = BytesMut::new();
+        pos.buffer_to(&mut buf).unwrap();
+        quat.buffer_to(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..],
+            &hex!(
+                "3ff0000000000000 c004000000000000 3fc0000000000000"
+                "3fd0000000000000 bfe0000000000000 4000000000000000 3fe0000000000000"
+            )[..]
+        );
+
+        let mut bytes = buf.freeze();
+        assert_eq!(Vec3::unbuffer_from(&mut bytes).unwrap(), pos);
+        assert_eq!(Quat::unbuffer_from(&mut bytes).unwrap(), quat);
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn null_tracker_capture() {
+        // The position and orientation of a report from a C++ vrpn_Tracker_NULL,
+        // after its sensor number and padding.
+        let mut bytes = Bytes::from_static(&hex!(
+            "0000000000000000 0000000000000000 0000000000000000"
+            "0000000000000000 0000000000000000 0000000000000000 3ff0000000000000"
+        ));
+        assert_eq!(Vec3::unbuffer_from(&mut bytes).unwrap(), Vec3::default());
+        assert_eq!(Quat::unbuffer_from(&mut bytes).unwrap(), Quat::identity());
+    }
+
+    #[test]
+    fn short_buffers() {
+        let mut bytes = Bytes::from_static(&[0u8; 31]);
+        assert!(Quat::unbuffer_from(&mut bytes).is_err());
+        let mut buf = [0u8; 23];
+        assert!(Vec3::default().buffer_to(&mut &mut buf[..]).is_err());
+    }
+}
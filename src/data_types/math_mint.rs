@@ -0,0 +1,51 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Conversions to and from `mint` types, for interoperating with other math libraries.
+
+use super::{Quat, Vec3};
+
+impl From<mint::Vector3<f64>> for Vec3 {
+    fn from(v: mint::Vector3<f64>) -> Self {
+        Vec3::new(v.x, v.y, v.z)
+    }
+}
+
+impl From<Vec3> for mint::Vector3<f64> {
+    fn from(v: Vec3) -> Self {
+        mint::Vector3 {
+            x: v.x,
+            y: v.y,
+            z: v.z,
+        }
+    }
+}
+
+impl From<mint::Quaternion<f64>> for Quat {
+    fn from(q: mint::Quaternion<f64>) -> Self {
+        Quat::from_sv(q.s, q.v.into())
+    }
+}
+
+impl From<Quat> for mint::Quaternion<f64> {
+    fn from(q: Quat) -> Self {
+        mint::Quaternion {
+            s: q.s,
+            v: q.v.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let q = Quat::new(0.5, 0.5, -0.5, 0.5);
+        let m: mint::Quaternion<f64> = q.into();
+        assert_eq!((m.s, m.v.x, m.v.y, m.v.z), (0.5, 0.5, -0.5, 0.5));
+        assert_eq!(Quat::from(m), q);
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Conversions to and from `nalgebra` types.
+
+use super::{Quat, Vec3};
+use nalgebra::{Quaternion, UnitQuaternion, Vector3};
+
+impl From<Vector3<f64>> for Vec3 {
+    fn from(v: Vector3<f64>) -> Self {
+        Vec3::new(v.x, v.y, v.z)
+    }
+}
+
+impl From<Vec3> for Vector3<f64> {
+    fn from(v: Vec3) -> Self {
+        Vector3::new(v.x, v.y, v.z)
+    }
+}
+
+impl From<Quaternion<f64>> for Quat {
+    fn from(q: Quaternion<f64>) -> Self {
+        Quat::new(q.w, q.i, q.j, q.k)
+    }
+}
+
+impl From<Quat> for Quaternion<f64> {
+    fn from(q: Quat) -> Self {
+        Quaternion::new(q.s, q.v.x, q.v.y, q.v.z)
+    }
+}
+
+impl From<UnitQuaternion<f64>> for Quat {
+    fn from(q: UnitQuaternion<f64>) -> Self {
+        q.into_inner().into()
+    }
+}
+
+/// Normalizes, as received quaternions are only approximately unit length.
+impl From<Quat> for UnitQuaternion<f64> {
+    fn from(q: Quat) -> Self {
+        UnitQuaternion::from_quaternion(q.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let v = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!(Vec3::from(Vector3::from(v)), v);
+
+        let half_turn_y = Quat::new(0.0, 0.0, 1.0, 0.0);
+        let unit: UnitQuaternion<f64> = half_turn_y.into();
+        let rotated: Vec3 = (unit * Vector3::new(1.0, 0.0, 0.0)).into();
+        assert!((rotated.x + 1.0).abs() < 1e-12);
+        assert_eq!(Quat::from(unit), half_turn_y);
+    }
+}
@@ -5,25 +5,27 @@
 //! Message types and message size computations.
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use std::convert::TryFrom;
-
-use crate::{
-    buffer_unbuffer::{
-        buffer::{self},
-        size_requirement::*,
-        unbuffer::{self, UnbufferFrom},
-        BufferSize, BufferUnbufferError, ConstantBufferSize, MessageSizeInvalid,
-    },
-    Result, VrpnError,
+use core::convert::TryFrom;
+
+use crate::buffer_unbuffer::{
+    buffer::{self},
+    constants::ALIGN,
+    size::{padded, padded_to, padding_to, UNPADDED_HEADER_SIZE},
+    size_requirement::*,
+    unbuffer::{self, UnbufferFrom},
+    BufferPool, BufferSize, BufferUnbufferError, ConstantBufferSize, MessageSizeInvalid,
 };
+pub use crate::buffer_unbuffer::{LengthField, MessageSize};
+#[cfg(feature = "std")]
+use crate::{Result, VrpnError};
 
 use super::{
     descriptions::InnerDescription, id_types::*, name_types::MessageTypeIdentifier,
-    IdWithNameAndDescription, TimeVal,
+    time::default_time, IdWithNameAndDescription, TimeVal,
 };
 
 /// Trait for typed message bodies.
-pub trait TypedMessageBody: std::fmt::Debug {
+pub trait TypedMessageBody: core::fmt::Debug {
     /// The name string (for user messages) or type ID (for system messages) used to identify this message type.
     const MESSAGE_IDENTIFIER: MessageTypeIdentifier;
 }
@@ -36,6 +38,7 @@ impl<I: IdWithNameAndDescription> TypedMessageBody for InnerDescription<I> {
 
 /// Header information for a message.
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MessageHeader {
     pub time: TimeVal,
     pub message_type: MessageTypeId,
@@ -50,7 +53,7 @@ impl MessageHeader {
         sender: impl IntoId<BaseId = SenderId>,
     ) -> MessageHeader {
         MessageHeader {
-            time: time.unwrap_or_else(TimeVal::get_time_of_day),
+            time: time.unwrap_or_else(default_time),
             message_type: message_type.into_id(),
             sender: sender.into_id(),
         }
@@ -115,6 +118,7 @@ where
 
 /// A message with header information, almost ready to be buffered to the wire.
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TypedMessage<T: TypedMessageBody> {
     pub header: MessageHeader,
     pub body: T,
@@ -155,28 +159,28 @@ impl<T: TypedMessageBody> Message for TypedMessage<T> {
 }
 
 impl<T: TypedMessageBody + unbuffer::UnbufferFrom> TryFrom<&GenericMessage> for TypedMessage<T> {
-    type Error = VrpnError;
+    type Error = BufferUnbufferError;
 
     /// Try parsing a generic message into a typed message
     ///
     /// # Errors
     /// - If the unbuffering of the given type fails
     /// - If the generic message's body isn't fully consumed by the typed message body
-    fn try_from(msg: &GenericMessage) -> std::result::Result<Self, Self::Error> {
+    fn try_from(msg: &GenericMessage) -> core::result::Result<Self, Self::Error> {
         let mut buf = msg.body.inner.clone();
         let body = T::unbuffer_from(&mut buf)
             .map_err(BufferUnbufferError::map_bytes_required_to_size_mismatch)?;
         if !buf.is_empty() {
-            return Err(VrpnError::OtherMessage(format!(
-                "message body length was indicated as {}, but {} bytes remain unconsumed",
-                msg.body.inner.len(),
-                buf.len()
-            )));
+            return Err(BufferUnbufferError::BodyNotConsumed {
+                len: msg.body.inner.len(),
+                remaining: buf.len(),
+            });
         }
         Ok(TypedMessage::from_header_and_body(msg.header.clone(), body))
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: TypedMessageBody + unbuffer::UnbufferFrom> TypedMessage<T> {
     #[deprecated]
     pub fn try_from_generic(msg: &GenericMessage) -> Result<TypedMessage<T>> {
@@ -196,6 +200,7 @@ impl<T: TypedMessageBody + unbuffer::UnbufferFrom> TypedMessage<T> {
 
 /// A special type of message, with just an (exact-size) buffer as the body.
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GenericMessage {
     pub header: MessageHeader,
     pub body: GenericBody,
@@ -232,7 +237,7 @@ impl Message for GenericMessage {
 impl<T: TypedMessageBody + buffer::BufferTo> TryFrom<TypedMessage<T>> for GenericMessage {
     type Error = BufferUnbufferError;
 
-    fn try_from(value: TypedMessage<T>) -> std::result::Result<Self, Self::Error> {
+    fn try_from(value: TypedMessage<T>) -> core::result::Result<Self, Self::Error> {
         let old_body = value.body;
         let header = value.header;
         let mut buf = BytesMut::with_capacity(old_body.buffer_size());
@@ -244,10 +249,60 @@ impl<T: TypedMessageBody + buffer::BufferTo> TryFrom<TypedMessage<T>> for Generi
     }
 }
 
+impl<T: TypedMessageBody + buffer::BufferTo> TypedMessage<T> {
+    /// Convert to a `GenericMessage`, serializing the body into the given pool
+    /// rather than a freshly-allocated buffer.
+    pub fn try_into_generic_in(
+        self,
+        pool: &mut BufferPool,
+    ) -> core::result::Result<GenericMessage, BufferUnbufferError> {
+        let body = pool.buffer(&self.body)?;
+        Ok(GenericMessage::from_header_and_body(
+            self.header,
+            GenericBody::new(body),
+        ))
+    }
+
+    /// Append this message to `buf` as a complete frame with the given sequence number,
+    /// as `SequencedGenericMessage` would be buffered.
+    ///
+    /// The body is serialized straight into the frame, rather than into a buffer of its own
+    /// that is then copied. On error, `buf` is left as it was.
+    pub fn buffer_to_frame(
+        &self,
+        sequence_number: SequenceNumber,
+        buf: &mut BytesMut,
+    ) -> core::result::Result<(), BufferUnbufferError> {
+        use buffer::BufferTo;
+        let start = buf.len();
+        buf.reserve(padded(UNPADDED_HEADER_SIZE) + padded(self.body.buffer_size()));
+        let result = (|| {
+            // The length field is filled in once the body is written.
+            0u32.buffer_to(buf)?;
+            self.header.buffer_to(buf)?;
+            sequence_number.buffer_to(buf)?;
+            let body_start = buf.len();
+            self.body.buffer_to(buf)?;
+            let size = MessageSize::try_from_unpadded_body_size(buf.len() - body_start)
+                .ok_or(BufferUnbufferError::OutOfBuffer)?;
+            size.length_field()
+                .buffer_to(&mut &mut buf[start..start + 4])?;
+            for _ in 0..size.body_padding() {
+                buf.put_u8(0);
+            }
+            Ok(())
+        })();
+        if result.is_err() {
+            buf.truncate(start);
+        }
+        result
+    }
+}
+
 impl<T: TypedMessageBody + unbuffer::UnbufferFrom> TryFrom<GenericMessage> for TypedMessage<T> {
     type Error = BufferUnbufferError;
 
-    fn try_from(value: GenericMessage) -> std::result::Result<Self, Self::Error> {
+    fn try_from(value: GenericMessage) -> core::result::Result<Self, Self::Error> {
         let mut buf = value.body.clone().into_inner();
         let typed_body = T::unbuffer_from(&mut buf)?;
         Ok(TypedMessage {
@@ -257,6 +312,12 @@ impl<T: TypedMessageBody + unbuffer::UnbufferFrom> TryFrom<GenericMessage> for T
     }
 }
 
+/// Default limit on the padded size of a received message.
+///
+/// Far larger than any real VRPN message, but small enough that a corrupt length field
+/// does not make us wait for (and buffer) gigabytes.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
 /// A generic message with header information and sequence number, ready to be buffered to the wire.
 ///
 /// Wraps `GenericMessage`
@@ -277,60 +338,67 @@ impl SequencedGenericMessage {
     }
 
     /// Serialize to a buffer.
-    pub fn try_into_buf(self) -> std::result::Result<Bytes, BufferUnbufferError> {
+    pub fn try_into_buf(self) -> core::result::Result<Bytes, BufferUnbufferError> {
         let mut buf = BytesMut::with_capacity(self.buffer_size());
-
-        let size = generic_message_size(&self);
-        let length_field = size.length_field() as u32;
-
-        buffer::BufferTo::buffer_to(&length_field, &mut buf)?;
-        buffer::BufferTo::buffer_to(&self.message.header, &mut buf)?;
-        buffer::BufferTo::buffer_to(&self.sequence_number, &mut buf)?;
-
-        buf.put_slice(&self.message.body.inner);
-        for _ in 0..size.body_padding() {
-            buf.put_u8(0);
-        }
+        buffer::BufferTo::buffer_to(&self, &mut buf)?;
         Ok(buf.freeze())
     }
 
     /// Like `try_read_from_buf`, but starting after the length field, and allowed to modify the buffer
     /// even in case of error.
+    ///
+    /// `header_padding` is the number of bytes between the sequence number and the body.
     fn try_finish_read_from_local_buf<T: Buf + Clone>(
         local_buf: T,
         size: &MessageSize,
-        initial_remaining: usize,
+        header_padding: usize,
     ) -> unbuffer::UnbufferResult<Self> {
         let mut local_buf = local_buf;
-        let header = MessageHeader::unbuffer_from(&mut local_buf)?;
-        assert_ne!(local_buf.remaining(), 0);
-
-        let sequence_number = SequenceNumber::unbuffer_from(&mut local_buf)?;
-        assert_ne!(local_buf.remaining(), 0);
-
-        // Assert that handling the sequence number meant we're now aligned again.
-        assert_eq!(
-            (initial_remaining - local_buf.remaining()) % crate::buffer_unbuffer::constants::ALIGN,
-            0
-        );
+        let header = MessageHeader::unbuffer_from(&mut local_buf)
+            .map_err(BufferUnbufferError::map_bytes_required_to_size_mismatch)?;
+        let sequence_number = SequenceNumber::unbuffer_from(&mut local_buf)
+            .map_err(BufferUnbufferError::map_bytes_required_to_size_mismatch)?;
+        local_buf.advance(header_padding);
 
-        let body = {
-            let mut body_buf = local_buf.copy_to_bytes(size.unpadded_body_size());
-            let my_body = GenericBody::unbuffer_from(&mut body_buf)
-                .map_err(ExpandSizeRequirement::expand_size_requirement)?;
-            assert_eq!(body_buf.remaining(), 0);
-            my_body
-        };
+        // The caller checked the whole padded message is there, so this cannot run short.
+        let mut body_buf = local_buf.copy_to_bytes(size.unpadded_body_size());
+        let body = GenericBody::unbuffer_from(&mut body_buf)
+            .map_err(ExpandSizeRequirement::expand_size_requirement)?;
         Ok(SequencedGenericMessage {
             message: GenericMessage { header, body },
             sequence_number,
         })
     }
 
-    /// Deserialize from a buffer.
+    /// Deserialize from a buffer, rejecting messages larger than `DEFAULT_MAX_MESSAGE_SIZE`.
     ///
     /// In case of error, your buffer is unmodified.
     pub fn try_read_from_buf<T: Buf + Clone>(buf: &mut T) -> unbuffer::UnbufferResult<Self> {
+        Self::try_read_from_buf_with_limit(buf, DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    /// Deserialize from a buffer, rejecting messages whose padded size exceeds `max_message_size`.
+    ///
+    /// The limit is checked as soon as the length field is available,
+    /// so `MessageTooLarge` carries the size to skip to get past the message.
+    /// In case of error, your buffer is unmodified.
+    pub fn try_read_from_buf_with_limit<T: Buf + Clone>(
+        buf: &mut T,
+        max_message_size: usize,
+    ) -> unbuffer::UnbufferResult<Self> {
+        Self::try_read_from_buf_aligned(buf, max_message_size, ALIGN)
+    }
+
+    /// Like `try_read_from_buf_with_limit`, but with the header and body padded to `alignment`.
+    ///
+    /// `alignment` must be a power of two, at least `ALIGN`, so the sequence number
+    /// still fits in the padded header. Mainline VRPN always uses `ALIGN`.
+    pub fn try_read_from_buf_aligned<T: Buf + Clone>(
+        buf: &mut T,
+        max_message_size: usize,
+        alignment: usize,
+    ) -> unbuffer::UnbufferResult<Self> {
+        debug_assert!(alignment.is_power_of_two() && alignment >= ALIGN);
         let u32_size = u32::constant_buffer_size();
         let initial_remaining = buf.remaining();
         if initial_remaining < u32_size {
@@ -342,17 +410,53 @@ impl SequencedGenericMessage {
         // we have at least a length field.
         let mut local_buf = buf.clone();
         let length_field = u32::unbuffer_from(&mut local_buf)?;
-        let size = MessageSize::try_from_length_field(length_field)?;
+        let header_size = padded_to(UNPADDED_HEADER_SIZE, alignment);
+        let size = (length_field as usize)
+            .checked_sub(header_size)
+            .map(MessageSize::from_unpadded_body_size)
+            .ok_or(MessageSizeInvalid(length_field))?;
+        let padded_size = header_size + padded_to(size.unpadded_body_size(), alignment);
+        if padded_size > max_message_size {
+            return Err(BufferUnbufferError::MessageTooLarge {
+                size: padded_size,
+                max: max_message_size,
+            });
+        }
 
         // make sure our original buf has enough for an entire padded message
-        unbuffer::check_unbuffer_remaining(buf, size.padded_message_size())?;
-        let seq_generic_message =
-            Self::try_finish_read_from_local_buf(local_buf, &size, initial_remaining)?;
+        unbuffer::check_unbuffer_remaining(buf, padded_size)?;
+        let seq_generic_message = Self::try_finish_read_from_local_buf(
+            local_buf,
+            &size,
+            header_size - padded(UNPADDED_HEADER_SIZE),
+        )?;
 
         // We can advance the buf now that we know we succeed.
-        buf.advance(size.padded_message_size());
+        buf.advance(padded_size);
         Ok(seq_generic_message)
     }
+
+    /// Serialize to a buffer, with the header and body padded to `alignment`.
+    ///
+    /// See `try_read_from_buf_aligned` for the allowed alignments.
+    pub fn try_into_buf_aligned(
+        self,
+        alignment: usize,
+    ) -> core::result::Result<Bytes, BufferUnbufferError> {
+        debug_assert!(alignment.is_power_of_two() && alignment >= ALIGN);
+        let body_size = self.message.body.inner.len();
+        let header_size = padded_to(UNPADDED_HEADER_SIZE, alignment);
+        let length_field = LengthField::try_from(header_size + body_size)
+            .map_err(|_| BufferUnbufferError::OutOfBuffer)?;
+        let mut buf = BytesMut::with_capacity(header_size + padded_to(body_size, alignment));
+        buffer::BufferTo::buffer_to(&length_field, &mut buf)?;
+        buffer::BufferTo::buffer_to(&self.message.header, &mut buf)?;
+        buffer::BufferTo::buffer_to(&self.sequence_number, &mut buf)?;
+        buf.put_bytes(0, header_size - padded(UNPADDED_HEADER_SIZE));
+        buf.put_slice(&self.message.body.inner);
+        buf.put_bytes(0, padding_to(body_size, alignment));
+        Ok(buf.freeze())
+    }
 }
 
 impl BufferSize for SequencedGenericMessage {
@@ -361,8 +465,27 @@ impl BufferSize for SequencedGenericMessage {
     }
 }
 
+impl buffer::BufferTo for SequencedGenericMessage {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> buffer::BufferResult {
+        let size = generic_message_size(self);
+        buffer::check_buffer_remaining(buf, size.padded_message_size())?;
+        let length_field = size.length_field();
+
+        length_field.buffer_to(buf)?;
+        self.message.header.buffer_to(buf)?;
+        self.sequence_number.buffer_to(buf)?;
+
+        buf.put_slice(&self.message.body.inner);
+        for _ in 0..size.body_padding() {
+            buf.put_u8(0);
+        }
+        Ok(())
+    }
+}
+
 /// Generic body struct used in unbuffering process, before dispatch on type to fully decode.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GenericBody {
     inner: Bytes,
 }
@@ -377,107 +500,39 @@ impl GenericBody {
     pub fn into_inner(self) -> Bytes {
         self.inner
     }
-}
 
-#[inline]
-const fn compute_padding(len: usize) -> usize {
-    let remainder = len % crate::buffer_unbuffer::constants::ALIGN;
-    if remainder != 0 {
-        crate::buffer_unbuffer::constants::ALIGN - remainder
-    } else {
-        0
+    /// Borrow the inner Bytes
+    pub fn as_bytes(&self) -> &Bytes {
+        &self.inner
     }
 }
 
-#[inline]
-const fn padded(len: usize) -> usize {
-    len + compute_padding(len)
-}
-
-/// Simple struct for wrapping all calculations related to Message<T> size.
-///
-/// Header is 5 i32s (padded to `vrpn_ALIGN`):
-/// - padded header size + unpadded body size
-/// - time stamp
-/// - sender
-/// - type
-///
-/// The four bytes of "padding" are actually the sequence number,
-/// which are not "officially" part of the header.
-///
-/// body is padded out to `vrpn_ALIGN`
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
-pub struct MessageSize {
-    // The unpadded size of a message body only
-    pub unpadded_body_size: usize,
-}
-
-const UNPADDED_HEADER_SIZE: usize = 5 * 4;
-/// Padded size of `UNPADDED_HEADER_SIZE`
-const MINIMUM_SIZE_FIELD: u32 = 6 * 4;
-
-/// The type of the length field in the header.
-pub type LengthField = u32;
-
-impl MessageSize {
-    /// Get a MessageSize from the unpadded size of a message body only.
-    #[inline]
-    pub const fn from_unpadded_body_size(unpadded_body_size: usize) -> MessageSize {
-        MessageSize { unpadded_body_size }
-    }
-
-    /// Get a MessageSize from the total unpadded size of a message (header plus body)
-    #[inline]
-    #[deprecated = "possible to fail, looks unused so would rather remove than change"]
-    pub const fn from_unpadded_message_size(unpadded_message_size: usize) -> MessageSize {
-        MessageSize::from_unpadded_body_size(unpadded_message_size - UNPADDED_HEADER_SIZE)
-    }
-    /// Get a MessageSize from the length field of a message (padded header plus unpadded body)
-    #[inline]
-    pub const fn try_from_length_field(
-        length_field: LengthField,
-    ) -> std::result::Result<MessageSize, MessageSizeInvalid> {
-        if length_field < MINIMUM_SIZE_FIELD {
-            Err(MessageSizeInvalid(length_field as u32))
-        } else {
-            Ok(MessageSize::from_unpadded_body_size(
-                length_field as usize - padded(UNPADDED_HEADER_SIZE),
-            ))
+/// A hex and ASCII dump of the body, 16 bytes to a line.
+impl core::fmt::Display for GenericBody {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.inner.is_empty() {
+            return f.write_str("(empty)");
         }
-    }
-
-    /// The unpadded size of just the message body.
-    #[inline]
-    pub const fn unpadded_body_size(&self) -> usize {
-        self.unpadded_body_size
-    }
-
-    /// The padded header size plus the unpadded body size.
-    ///
-    /// This is the value put in the message header's length field.
-    #[inline]
-    pub const fn length_field(&self) -> LengthField {
-        (self.unpadded_body_size + padded(UNPADDED_HEADER_SIZE)) as LengthField
-    }
-
-    /// The size of the body plus padding (multiple of ALIGN)
-    #[inline]
-    pub const fn padded_body_size(&self) -> usize {
-        padded(self.unpadded_body_size)
-    }
-
-    /// The number of padding bytes required to follow the message body.
-    #[inline]
-    pub const fn body_padding(&self) -> usize {
-        compute_padding(self.unpadded_body_size)
-    }
-
-    /// The total padded size of a message (header plus body, padding applied individually).
-    ///
-    /// This is the size of buffer actually required for this message.
-    #[inline]
-    pub const fn padded_message_size(&self) -> usize {
-        self.padded_body_size() + padded(UNPADDED_HEADER_SIZE)
+        for (i, line) in self.inner.chunks(16).enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{:04x}: ", i * 16)?;
+            for byte in line {
+                write!(f, " {:02x}", byte)?;
+            }
+            write!(f, "{:width$}  |", "", width = 3 * (16 - line.len()))?;
+            for &byte in line {
+                let c = if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                };
+                write!(f, "{}", c)?;
+            }
+            f.write_str("|")?;
+        }
+        Ok(())
     }
 }
 
@@ -508,8 +563,9 @@ impl buffer::BufferTo for GenericBody {
 
 #[cfg(test)]
 mod tests {
-    use std::mem::size_of;
+    use core::mem::size_of;
 
+    use crate::alloc_prelude::*;
     use crate::buffer_unbuffer::{constants::ALIGN, ConstantBufferSize};
 
     use super::*;
@@ -535,115 +591,75 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "std")]
     #[test]
-    fn invalid_msg_size() {
-        assert!(MessageSize::try_from_length_field(20).is_err())
+    fn buffer_to_frame() {
+        use crate::{
+            data_types::{Quat, Vec3},
+            tracker::PoseReport,
+        };
+        let msg = TypedMessage::new(
+            Some(TimeVal::default()),
+            MessageTypeId(3),
+            SenderId(1),
+            PoseReport {
+                sensor: Sensor(2),
+                pos: Vec3::new(1.0, 2.0, 3.0),
+                quat: Quat::identity(),
+            },
+        );
+        let mut buf = BytesMut::from(&b"before"[..]);
+        msg.buffer_to_frame(SequenceNumber(5), &mut buf).unwrap();
+        let expected = GenericMessage::try_from(msg)
+            .unwrap()
+            .into_sequenced_message(SequenceNumber(5))
+            .try_into_buf()
+            .unwrap();
+        assert_eq!(&buf[..6], b"before");
+        assert_eq!(&buf[6..], &expected[..]);
     }
 
     #[test]
-    fn sizes() {
-        // Based on the initial "VRPN Control" sender ID message
-        assert_eq!(
-            MessageSize::from_unpadded_body_size(17).unpadded_body_size(),
-            17
-        );
+    fn body_dump() {
+        assert_eq!(GenericBody::default().to_string(), "(empty)");
+        let body = GenericBody::new(Bytes::from_static(b"Tracker0\0\x01 \xff"));
         assert_eq!(
-            MessageSize::from_unpadded_body_size(17).padded_body_size(),
-            24
+            body.to_string(),
+            "0000:  54 72 61 63 6b 65 72 30 00 01 20 ff              |Tracker0.. .|"
         );
-        assert_eq!(UNPADDED_HEADER_SIZE, 20);
-        assert_eq!(MINIMUM_SIZE_FIELD, 24);
+        let body = GenericBody::new(Bytes::from(vec![0x41; 17]));
         assert_eq!(
-            MessageSize::from_unpadded_body_size(17).padded_message_size(),
-            48
-        );
-        assert_eq!(MessageSize::from_unpadded_body_size(17).length_field(), 41);
-        assert_eq!(
-            MessageSize::try_from_length_field(41)
-                .unwrap()
-                .length_field(),
-            41
-        );
-        assert_eq!(
-            MessageSize::try_from_length_field(41)
-                .unwrap()
-                .unpadded_body_size(),
-            17
-        );
-
-        // Based on the second message, which is closer to the edge and thus breaks things.
-        assert_eq!(
-            MessageSize::from_unpadded_body_size(13),
-            MessageSize::try_from_length_field(0x25).unwrap()
-        );
-        assert_eq!(
-            MessageSize::from_unpadded_body_size(13).padded_body_size(),
-            16
-        );
-        assert_eq!(
-            MessageSize::from_unpadded_body_size(13).length_field(),
-            24 + 13
-        );
-        assert_eq!(
-            MessageSize::try_from_length_field(37)
-                .unwrap()
-                .length_field(),
-            37
+            body.to_string(),
+            "0000:  41 41 41 41 41 41 41 41 41 41 41 41 41 41 41 41  |AAAAAAAAAAAAAAAA|\n\
+             0010:  41                                               |A|"
         );
+    }
 
+    #[cfg(feature = "std")]
+    #[test]
+    fn body_not_consumed() {
+        use crate::{
+            data_types::{Quat, Vec3},
+            tracker::PoseReport,
+        };
+        let msg = GenericMessage::try_from(TypedMessage::new(
+            Some(TimeVal::default()),
+            MessageTypeId(3),
+            SenderId(1),
+            PoseReport {
+                sensor: Sensor(2),
+                pos: Vec3::new(1.0, 2.0, 3.0),
+                quat: Quat::identity(),
+            },
+        ))
+        .unwrap();
+        let mut body = BytesMut::from(&msg.body.inner[..]);
+        body.put_u32(0);
+        let len = body.len();
+        let msg = GenericMessage::from_header_and_body(msg.header, GenericBody::new(body.freeze()));
         assert_eq!(
-            MessageSize::try_from_length_field(37)
-                .unwrap()
-                .padded_message_size(),
-            40
+            TypedMessage::<PoseReport>::try_from(&msg).unwrap_err(),
+            BufferUnbufferError::BodyNotConsumed { len, remaining: 4 }
         );
     }
-    proptest! {
-        #[test]
-        fn length_field_matches(len in 0u32..10000) {
-            let len = len as usize;
-            prop_assert_eq!(
-            MessageSize::from_unpadded_body_size(len).length_field(),
-            transcribed_padding_function(len).len_field);
-        }
-
-        #[test]
-        fn total_length_matches(len in 0u32..10000) {
-            let len = len as usize;
-            prop_assert_eq!(
-            MessageSize::from_unpadded_body_size(len).padded_message_size(),
-            transcribed_padding_function(len).total_len);
-        }
-
-        #[test]
-        fn roundtrip(len in 24u32..10000)  {
-            prop_assert_eq!(MessageSize::try_from_length_field(len).unwrap().length_field(), len);
-        }
-    }
-
-    struct Lengths {
-        header_len: usize,
-        total_len: usize,
-        len_field: u32,
-    }
-
-    /// This is a relatively literal translation of the size computations in vrpn_Endpoint::marshall_message,
-    /// used as ground truth in testing.
-    fn transcribed_padding_function(len: usize) -> Lengths {
-        let mut ceil_len = len;
-        if (len % ALIGN) != 0 {
-            ceil_len += ALIGN - len % ALIGN;
-        }
-
-        let mut header_len = 5 * std::mem::size_of::<i32>();
-        if (header_len % ALIGN) != 0 {
-            header_len += ALIGN - header_len % ALIGN;
-        }
-        let total_len = header_len + ceil_len;
-        Lengths {
-            header_len,
-            total_len,
-            len_field: (header_len + len) as u32,
-        }
-    }
 }
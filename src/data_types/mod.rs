@@ -4,41 +4,51 @@
 
 //! Data types
 
+pub mod announcement;
+pub mod connection_request;
 pub mod constants;
 pub mod cookie;
 pub(crate) mod descriptions;
 pub mod id_types;
-pub(crate) mod length_prefixed;
-pub(crate) mod log;
-mod math;
+pub mod length_prefixed;
+pub mod log;
+pub mod math;
 pub(crate) mod message;
 pub mod name_types;
 mod time;
+pub mod wire;
 
-#[cfg(cgmath)]
+#[cfg(feature = "cgmath")]
 pub mod math_cgmath;
+#[cfg(feature = "mint")]
+pub mod math_mint;
+#[cfg(feature = "nalgebra")]
+pub mod math_nalgebra;
+#[cfg(feature = "chrono")]
+pub mod time_chrono;
 
 #[doc(inline)]
 pub use crate::data_types::{
+    announcement::Announcement,
+    connection_request::ConnectionRequest,
     cookie::{CookieData, Version},
     descriptions::{Description, UdpDescription},
+    log::{LogFileNameError, LogFileNames, LogFileNamesBuilder, LogMode},
     math::{Quat, Vec3},
-    time::TimeVal,
+    time::{Microseconds, NegativeTimeVal, Seconds, TimeVal},
 };
 pub use crate::data_types::{
     id_types::MessageTypeId,
     message::{
         GenericBody, GenericMessage, Message, MessageHeader, MessageSize, SequencedGenericMessage,
-        TypedMessage, TypedMessageBody,
+        TypedMessage, TypedMessageBody, DEFAULT_MAX_MESSAGE_SIZE,
     },
     name_types::{
-        IdWithNameAndDescription, MessageTypeIdentifier, MessageTypeName, SenderName,
+        IdWithNameAndDescription, MessageTypeIdentifier, MessageTypeName, QuotedName, SenderName,
         StaticMessageTypeName, StaticSenderName,
     },
 };
 
-pub(crate) use crate::data_types::log::{LogFileNames, LogMode};
-
 bitflags! {
     /// Class of service flags matching those in the original vrpn
     pub struct ClassOfService : u32 {
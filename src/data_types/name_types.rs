@@ -5,6 +5,7 @@
 //! Name types used across VRPN
 
 use bytes::Bytes;
+use core::fmt;
 
 use super::{
     constants,
@@ -37,10 +38,54 @@ pub trait NameIntoBytes {
     fn into_bytes(self) -> Bytes;
 }
 
+/// Displays a name as a quoted string, escaping any bytes that are not printable ASCII.
+#[derive(Clone, Copy)]
+pub struct QuotedName<'a>(pub &'a [u8]);
+
+impl fmt::Display for QuotedName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"")?;
+        for c in self.0.iter().flat_map(|b| core::ascii::escape_default(*b)) {
+            fmt::Write::write_char(f, c as char)?;
+        }
+        f.write_str("\"")
+    }
+}
+
+impl fmt::Debug for QuotedName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Display a name type as its quoted name, and debug-format it as the type wrapping that.
+macro_rules! impl_name_fmt {
+    ($name:ident) => {
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&QuotedName(&self.0[..]), f)
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_tuple(stringify!($name))
+                    .field(&QuotedName(&self.0[..]))
+                    .finish()
+            }
+        }
+    };
+}
+
+impl_name_fmt!(StaticSenderName);
+impl_name_fmt!(SenderName);
+impl_name_fmt!(StaticMessageTypeName);
+impl_name_fmt!(MessageTypeName);
+
 /// Wrapper for a fixed sender name, as a static byte array.
 ///
 /// Convertible to `SenderName`
-#[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+#[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Hash)]
 pub struct StaticSenderName(pub &'static [u8]);
 
 impl From<&'static [u8]> for SenderName {
@@ -55,7 +100,7 @@ impl From<&'static [u8]> for StaticSenderName {
     }
 }
 
-impl std::cmp::PartialEq<SenderName> for StaticSenderName {
+impl core::cmp::PartialEq<SenderName> for StaticSenderName {
     fn eq(&self, other: &SenderName) -> bool {
         Bytes::from_static(self.0) == other.0
     }
@@ -74,7 +119,7 @@ impl NameIntoBytes for StaticSenderName {
 }
 
 /// Wrapper for an arbitrary sender name.
-#[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+#[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Hash)]
 pub struct SenderName(pub Bytes);
 
 impl From<StaticSenderName> for SenderName {
@@ -102,7 +147,7 @@ impl IdWithNameAndDescription for SenderId {
 }
 
 /// Be able to compare `StaticSenderName` and `SenderName`
-impl std::cmp::PartialEq<StaticSenderName> for SenderName {
+impl core::cmp::PartialEq<StaticSenderName> for SenderName {
     fn eq(&self, other: &StaticSenderName) -> bool {
         self.0 == Bytes::from_static(other.0)
     }
@@ -111,7 +156,7 @@ impl std::cmp::PartialEq<StaticSenderName> for SenderName {
 /// Wrapper for a fixed type name, as a static byte array.
 ///
 /// Convertible to `TypeName`
-#[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+#[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Hash)]
 pub struct StaticMessageTypeName(pub &'static [u8]);
 
 impl From<&'static [u8]> for StaticMessageTypeName {
@@ -126,7 +171,7 @@ impl From<StaticMessageTypeName> for Bytes {
     }
 }
 
-impl std::cmp::PartialEq<MessageTypeName> for StaticMessageTypeName {
+impl core::cmp::PartialEq<MessageTypeName> for StaticMessageTypeName {
     fn eq(&self, other: &MessageTypeName) -> bool {
         Bytes::from_static(self.0) == other.0
     }
@@ -138,7 +183,7 @@ impl NameIntoBytes for StaticMessageTypeName {
     }
 }
 /// Wrapper for an arbitrary message type name.
-#[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+#[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Hash)]
 pub struct MessageTypeName(pub Bytes);
 
 impl From<&'static [u8]> for MessageTypeName {
@@ -159,7 +204,7 @@ impl From<MessageTypeName> for Bytes {
     }
 }
 
-impl std::cmp::PartialEq<StaticMessageTypeName> for MessageTypeName {
+impl core::cmp::PartialEq<StaticMessageTypeName> for MessageTypeName {
     fn eq(&self, other: &StaticMessageTypeName) -> bool {
         self.0 == Bytes::from_static(other.0)
     }
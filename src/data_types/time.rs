@@ -9,28 +9,40 @@
 use crate::buffer_unbuffer::{buffer, unbuffer, ConstantBufferSize, WrappedConstantSize};
 
 use bytes::{Buf, BufMut};
-use std::{
-    fmt::{Debug, Display},
-    time::{Duration, SystemTime},
+use core::{
+    convert::TryFrom,
+    fmt::{self, Debug, Display},
+    time::Duration,
 };
+#[cfg(feature = "std")]
+use std::time::SystemTime;
 
 /// Structure corresponding to the C struct time_val type.
 ///
-/// Conversions to and from native rust types are provided.
+/// Conversions to and from native rust types are provided:
+/// `SystemTime` (and with the `chrono` feature, `chrono::DateTime<Utc>`) for times,
+/// and `Duration` for intervals.
+/// Conversions into `TimeVal` truncate to microseconds, and saturate outside its range.
 ///
 /// ```
+/// # #[cfg(feature = "std")] {
 /// use vrpn::data_types::TimeVal;
 /// let tv = TimeVal::get_time_of_day();
 /// println!("{}s, {}us since the Unix epoch", tv.seconds(), tv.microseconds());
 /// println!("{}s since the Unix epoch", tv);
+/// # }
 /// ```
 #[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TimeVal {
     sec: Seconds,
     usec: Microseconds,
 }
 
 impl TimeVal {
+    const MIN_MICROS: i64 = i32::MIN as i64 * 1_000_000;
+    const MAX_MICROS: i64 = i32::MAX as i64 * 1_000_000 + 999_999;
+
     /// Constructor from components.
     ///
     /// TODO normalize?
@@ -48,35 +60,101 @@ impl TimeVal {
         self.usec
     }
 
+    /// The time as a single count of microseconds.
+    pub fn as_micros(&self) -> i64 {
+        i64::from(self.sec.0) * 1_000_000 + i64::from(self.usec.0)
+    }
+
+    /// Normalized time from a single count of microseconds,
+    /// saturating outside the range of `TimeVal`.
+    pub fn from_micros(micros: i64) -> TimeVal {
+        let micros = micros.clamp(TimeVal::MIN_MICROS, TimeVal::MAX_MICROS);
+        TimeVal::new(
+            Seconds(micros.div_euclid(1_000_000) as i32),
+            Microseconds(micros.rem_euclid(1_000_000) as i32),
+        )
+    }
+
     /// Get now as this type: equivalent to `vrpn_gettimeofday`
+    #[cfg(feature = "std")]
     pub fn get_time_of_day() -> TimeVal {
         TimeVal::from(SystemTime::now())
     }
 }
 
+/// The time to stamp messages with when none is given: now.
+#[cfg(feature = "std")]
+pub(crate) fn default_time() -> TimeVal {
+    TimeVal::get_time_of_day()
+}
+
+/// The time to stamp messages with when none is given.
+///
+/// Without `std` there is no clock to ask, so this is zero:
+/// give messages a time from the device's own clock instead.
+#[cfg(not(feature = "std"))]
+pub(crate) fn default_time() -> TimeVal {
+    TimeVal::default()
+}
+
 impl Default for TimeVal {
     fn default() -> Self {
         Self::new(Seconds(0), Microseconds(0))
     }
 }
 
+/// Truncated towards the Unix epoch.
+#[cfg(feature = "std")]
 impl From<SystemTime> for TimeVal {
     fn from(v: SystemTime) -> Self {
-        // In practice this should always work.
-        let since_epoch = v.duration_since(SystemTime::UNIX_EPOCH).unwrap();
-
-        TimeVal::new(
-            Seconds(since_epoch.as_secs() as i32),
-            Microseconds(since_epoch.subsec_micros() as i32),
-        )
+        let micros = match v.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(after) => i64::try_from(after.as_micros()).unwrap_or(i64::MAX),
+            Err(before) => -i64::try_from(before.duration().as_micros()).unwrap_or(i64::MAX),
+        };
+        TimeVal::from_micros(micros)
     }
 }
 
+#[cfg(feature = "std")]
 impl From<TimeVal> for SystemTime {
     fn from(v: TimeVal) -> Self {
-        SystemTime::UNIX_EPOCH
-            + Duration::from_secs(v.seconds().0 as u64)
-            + Duration::from_micros(v.microseconds().0 as u64)
+        let micros = v.as_micros();
+        let offset = Duration::from_micros(micros.unsigned_abs());
+        if micros >= 0 {
+            SystemTime::UNIX_EPOCH + offset
+        } else {
+            SystemTime::UNIX_EPOCH - offset
+        }
+    }
+}
+
+/// An interval, truncated to microseconds.
+impl From<Duration> for TimeVal {
+    fn from(v: Duration) -> Self {
+        TimeVal::from_micros(i64::try_from(v.as_micros()).unwrap_or(i64::MAX))
+    }
+}
+
+/// The error converting a negative `TimeVal` to a `Duration`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NegativeTimeVal(pub TimeVal);
+
+impl Display for NegativeTimeVal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "negative time {} is not a duration", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for NegativeTimeVal {}
+
+impl TryFrom<TimeVal> for Duration {
+    type Error = NegativeTimeVal;
+
+    fn try_from(v: TimeVal) -> Result<Self, Self::Error> {
+        u64::try_from(v.as_micros())
+            .map(Duration::from_micros)
+            .map_err(|_| NegativeTimeVal(v))
     }
 }
 
@@ -104,16 +182,54 @@ impl unbuffer::UnbufferFrom for TimeVal {
     }
 }
 
+/// Seconds since the Unix epoch, or with `{:#}`, an ISO 8601 timestamp in UTC.
 impl Display for TimeVal {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}.{}", self.sec, self.usec)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !f.alternate() {
+            return write!(f, "{}.{}", self.sec, self.usec);
+        }
+        let micros = self.as_micros();
+        let secs = micros.div_euclid(1_000_000);
+        let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+        let secs_of_day = secs.rem_euclid(86_400);
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+            year,
+            month,
+            day,
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60,
+            micros.rem_euclid(1_000_000)
+        )
     }
 }
 
+/// The Gregorian calendar date `days` after 1970-01-01, as in Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
 /// Wrapper for an integer type for seconds
 ///
 /// For use in `TimeVal`.
 #[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Seconds(pub i32);
 
 /// Buffer and unbuffer seconds just like the corresponding integer
@@ -128,8 +244,8 @@ impl WrappedConstantSize for Seconds {
 }
 
 impl Display for Seconds {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        std::fmt::Display::fmt(&self.0, f)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.0, f)
     }
 }
 
@@ -137,6 +253,7 @@ impl Display for Seconds {
 ///
 /// For use in `TimeVal`.
 #[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Microseconds(pub i32);
 
 /// Buffer and unbuffer microseconds just like the corresponding integer
@@ -151,7 +268,54 @@ impl WrappedConstantSize for Microseconds {
 }
 
 impl Display for Microseconds {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:06}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc_prelude::*;
+
+    #[test]
+    fn iso8601() {
+        let format = |micros| format!("{:#}", TimeVal::from_micros(micros));
+        assert_eq!(format(0), "1970-01-01T00:00:00.000000Z");
+        assert_eq!(format(-1), "1969-12-31T23:59:59.999999Z");
+        assert_eq!(format(1_542_140_718_809_137), "2018-11-13T20:25:18.809137Z");
+        // A leap day.
+        assert_eq!(format(951_782_400_000_000), "2000-02-29T00:00:00.000000Z");
+        assert_eq!(TimeVal::from_micros(1_500_005).to_string(), "1.500005");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn system_time_roundtrip() {
+        for micros in [0, 1_650_000_000_123_456, -1, -1_500_000] {
+            let tv = TimeVal::from_micros(micros);
+            let time = SystemTime::from(tv);
+            assert_eq!(TimeVal::from(time), tv);
+        }
+        assert_eq!(
+            SystemTime::from(TimeVal::new(Seconds(-2), Microseconds(500_000))),
+            SystemTime::UNIX_EPOCH - Duration::from_millis(1500)
+        );
+        // Sub-microsecond precision is truncated.
+        let time = SystemTime::UNIX_EPOCH + Duration::from_nanos(1_999);
+        assert_eq!(TimeVal::from(time), TimeVal::from_micros(1));
+    }
+
+    #[test]
+    fn duration_roundtrip() {
+        let duration = Duration::from_micros(3_000_042);
+        let tv = TimeVal::from(duration);
+        assert_eq!(tv, TimeVal::new(Seconds(3), Microseconds(42)));
+        assert_eq!(Duration::try_from(tv), Ok(duration));
+        assert!(Duration::try_from(TimeVal::from_micros(-1)).is_err());
+        assert_eq!(
+            TimeVal::from(Duration::from_secs(u64::MAX)),
+            TimeVal::new(Seconds(i32::MAX), Microseconds(999_999))
+        );
+    }
+}
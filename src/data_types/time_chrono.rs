@@ -0,0 +1,42 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Conversions to and from `chrono` times.
+
+use super::TimeVal;
+use chrono::{DateTime, Utc};
+use std::time::SystemTime;
+
+impl From<TimeVal> for DateTime<Utc> {
+    fn from(v: TimeVal) -> Self {
+        SystemTime::from(v).into()
+    }
+}
+
+/// Truncated towards the Unix epoch.
+impl From<DateTime<Utc>> for TimeVal {
+    fn from(v: DateTime<Utc>) -> Self {
+        SystemTime::from(v).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let tv = TimeVal::from_micros(1_650_000_000_123_456);
+        let time = DateTime::<Utc>::from(tv);
+        assert_eq!(time.timestamp(), 1_650_000_000);
+        assert_eq!(time.timestamp_subsec_micros(), 123_456);
+        assert_eq!(TimeVal::from(time), tv);
+
+        let before_epoch = TimeVal::from_micros(-1_500_000);
+        assert_eq!(
+            TimeVal::from(DateTime::<Utc>::from(before_epoch)),
+            before_epoch
+        );
+    }
+}
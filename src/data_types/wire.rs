@@ -0,0 +1,179 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! The VRPN wire format, as read from the C++ sources, and round-trip tests of it.
+//! See also `Protocol.md`.
+//!
+//! All numbers are big-endian ("network byte order"): integers as two's complement,
+//! floating point as IEEE 754.
+//!
+//! # Cookie
+//!
+//! Each side of a connection (and each log file) starts with a 24-byte cookie:
+//! `vrpn: ver. MM.mm`, two spaces, a log mode digit, then null padding.
+//!
+//! # Messages
+//!
+//! | Offset | Size | Field                                                         |
+//! |--------|------|---------------------------------------------------------------|
+//! | 0      | 4    | length: 24 plus the unpadded body length                      |
+//! | 4      | 4    | timestamp seconds                                             |
+//! | 8      | 4    | timestamp microseconds                                        |
+//! | 12     | 4    | sender ID                                                     |
+//! | 16     | 4    | message type ID: negative for system messages                 |
+//! | 20     | 4    | sequence number                                               |
+//! | 24     |      | body, zero-padded to a multiple of 8 bytes                    |
+//!
+//! # Bodies
+//!
+//! - Sender and type descriptions (types -1 and -2): the ID described is the sender
+//!   of the message. The body is the name length, including a null terminator,
+//!   then the null-terminated name.
+//! - Tracker pose (`vrpn_Tracker Pos_Quat`): the sensor as a 32-bit integer,
+//!   repeated as padding, then position x, y, z and orientation x, y, z, w as doubles.
+//! - Text messages (`vrpn_Base text_message`): the severity and level as 32-bit
+//!   unsigned integers, then the null-terminated text.
+//!
+//! The tests decode and re-encode the hand-assembled hex dumps in `tests/wire_format`.
+//! They check this crate against the format described here, not against C++ VRPN itself.
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{
+        buffer_unbuffer::{BufferTo, BytesMutExtras, UnbufferFrom},
+        codec::maybe_decode_one,
+        data_types::{
+            constants,
+            descriptions::{Description, InnerDescription},
+            id_types::{MessageTypeId, SenderId, SequenceNumber},
+            CookieData, Message, Quat, SequencedGenericMessage, TimeVal, TypedMessage, Vec3,
+        },
+        endpoint::{parse_system_message, SystemCommand},
+        text::{TextMessage, TextSeverity},
+        tracker::PoseReport,
+    };
+    use bytes::{Bytes, BytesMut};
+    use std::convert::TryFrom;
+
+    fn parse_hex(dump: &str) -> Bytes {
+        let digits: String = dump
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(|line| line.chars().filter(|c| !c.is_whitespace()))
+            .collect();
+        assert_eq!(digits.len() % 2, 0);
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+            .collect::<Vec<u8>>()
+            .into()
+    }
+
+    /// Decode one framed message, checking it encodes to the same bytes.
+    fn decode_exact(dump: &str) -> SequencedGenericMessage {
+        let bytes = parse_hex(dump);
+        let msg = maybe_decode_one(&mut bytes.clone()).unwrap().unwrap();
+        let mut encoded = BytesMut::new();
+        msg.buffer_to(&mut encoded).unwrap();
+        assert_eq!(&encoded[..], &bytes[..]);
+        msg
+    }
+
+    #[test]
+    fn cookie() {
+        let bytes = parse_hex(include_str!("../../tests/wire_format/cookie.hex"));
+        let cookie = CookieData::unbuffer_from(&mut bytes.clone()).unwrap();
+        assert_eq!(cookie.version, constants::MAGIC_DATA);
+        let encoded = BytesMut::allocate_and_buffer(CookieData::make_cookie()).unwrap();
+        assert_eq!(&encoded[..], &bytes[..]);
+    }
+
+    #[test]
+    fn sender_description() {
+        let msg = decode_exact(include_str!(
+            "../../tests/wire_format/sender_description.hex"
+        ));
+        assert_eq!(msg.sequence_number, SequenceNumber(1));
+        let msg = msg.into_inner();
+        assert_eq!(msg.header.time, TimeVal::from_micros(1_542_140_718_809_137));
+        match parse_system_message(msg).unwrap() {
+            SystemCommand::SenderDescription(desc) => {
+                assert_eq!(desc.which, SenderId(1));
+                assert_eq!(&desc.name[..], b"Tracker0");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sender_description_name_lengths() {
+        let canonical = parse_hex(include_str!(
+            "../../tests/wire_format/sender_description.hex"
+        ));
+        // The name length excluding the null that follows it.
+        let mut exclude_null = BytesMut::from(&canonical[..]);
+        exclude_null[27] = 8;
+        // No null at all: one byte shorter, with more padding.
+        let mut no_null = BytesMut::from(&canonical[..36]);
+        no_null[3] = 36;
+        no_null[27] = 8;
+        no_null.extend_from_slice(&[0; 4]);
+
+        for variant in [exclude_null, no_null] {
+            let msg = maybe_decode_one(&mut variant.freeze()).unwrap().unwrap();
+            match parse_system_message(msg.clone().into_inner()).unwrap() {
+                SystemCommand::SenderDescription(desc) => {
+                    assert_eq!(&desc.name[..], b"Tracker0");
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+
+        // What we send always counts the null, as the C++ sources do.
+        let desc: TypedMessage<InnerDescription<SenderId>> =
+            Description::from_id_and_name(SenderId(1), Bytes::from_static(b"Tracker0")).into();
+        let body = BytesMut::allocate_and_buffer(desc.body).unwrap();
+        assert_eq!(&body[..], &canonical[24..37]);
+    }
+
+    #[test]
+    fn type_description() {
+        let msg = decode_exact(include_str!("../../tests/wire_format/type_description.hex"));
+        match parse_system_message(msg.into_inner()).unwrap() {
+            SystemCommand::TypeDescription(desc) => {
+                assert_eq!(desc.which, MessageTypeId(0));
+                assert_eq!(&desc.name[..], constants::GOT_FIRST_CONNECTION.0);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tracker_pos_quat() {
+        let msg = decode_exact(include_str!("../../tests/wire_format/tracker_pos_quat.hex"));
+        let msg = msg.into_inner();
+        assert!(!msg.is_system_message());
+        let pose = TypedMessage::<PoseReport>::try_from(&msg).unwrap();
+        assert_eq!(pose.header.sender, SenderId(1));
+        assert_eq!(pose.body.pos, Vec3::new(0.5, 0.0, -1.0));
+        assert_eq!(pose.body.quat, Quat::identity());
+
+        // And the typed message encodes the same body.
+        let encoded = BytesMut::allocate_and_buffer(pose.body).unwrap();
+        assert_eq!(&encoded[..], &msg.body.as_bytes()[..]);
+    }
+
+    #[test]
+    fn text_message() {
+        let msg = decode_exact(include_str!("../../tests/wire_format/text_message.hex"));
+        let msg = msg.into_inner();
+        let text = TypedMessage::<TextMessage>::try_from(&msg).unwrap();
+        assert_eq!(
+            text.body,
+            TextMessage::new(TextSeverity::Warning, 0, &b"Tracker ready"[..])
+        );
+        let encoded = BytesMut::allocate_and_buffer(text.body).unwrap();
+        assert_eq!(&encoded[..], &msg.body.as_bytes()[..]);
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Types related to the `vrpn_Dial` device class
+
+use crate::{
+    buffer_unbuffer::{
+        buffer::{check_buffer_remaining, BufferResult, BufferTo},
+        unbuffer::{check_unbuffer_remaining, UnbufferFrom, UnbufferResult},
+        ConstantBufferSize,
+    },
+    data_types::{
+        id_types::{LocalId, SenderId},
+        message::TypedMessageBody,
+        name_types::StaticMessageTypeName,
+        ClassOfService, MessageTypeIdentifier, SenderName, TimeVal,
+    },
+    handler::HandlerHandle,
+    Connection, Handler, Result, TypedHandler,
+};
+use bytes::{Buf, BufMut};
+use std::sync::Arc;
+
+/// A dial turned: the change is in fractions of a full revolution.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DialChange {
+    /// Amount turned since the last report, in revolutions.
+    pub change: f64,
+    /// Which dial turned.
+    pub dial: i32,
+}
+
+impl TypedMessageBody for DialChange {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(StaticMessageTypeName(b"vrpn_Dial update"));
+}
+
+impl ConstantBufferSize for DialChange {
+    fn constant_buffer_size() -> usize {
+        f64::constant_buffer_size() + i32::constant_buffer_size()
+    }
+}
+
+impl BufferTo for DialChange {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        check_buffer_remaining(buf, Self::constant_buffer_size())?;
+        self.change.buffer_to(buf)?;
+        self.dial.buffer_to(buf)?;
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for DialChange {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        check_unbuffer_remaining(buf, Self::constant_buffer_size())?;
+        let change = f64::unbuffer_from(buf)?;
+        let dial = i32::unbuffer_from(buf)?;
+        Ok(DialChange { change, dial })
+    }
+}
+
+/// Client side of a dial device.
+#[derive(Debug)]
+pub struct DialRemote<C: Connection> {
+    connection: Arc<C>,
+    sender: LocalId<SenderId>,
+}
+
+impl<C: Connection> DialRemote<C> {
+    pub fn new(connection: Arc<C>, name: impl Into<SenderName>) -> Result<DialRemote<C>> {
+        let sender = connection.register_sender(name.into())?;
+        Ok(DialRemote { connection, sender })
+    }
+
+    /// The local sender ID of this dial.
+    pub fn sender(&self) -> LocalId<SenderId> {
+        self.sender
+    }
+
+    /// Add a handler for the changes reported by this dial.
+    pub fn add_change_handler<H>(&self, handler: Box<H>) -> Result<HandlerHandle>
+    where
+        H: TypedHandler<Item = DialChange> + Handler + 'static,
+    {
+        self.connection
+            .add_typed_handler(handler, Some(self.sender))
+    }
+}
+
+/// Server side of a dial device.
+#[derive(Debug)]
+pub struct DialServer<C: Connection> {
+    connection: Arc<C>,
+    sender: LocalId<SenderId>,
+}
+
+impl<C: Connection> DialServer<C> {
+    pub fn new(connection: Arc<C>, name: impl Into<SenderName>) -> Result<DialServer<C>> {
+        let sender = connection.register_sender(name.into())?;
+        Ok(DialServer { connection, sender })
+    }
+
+    /// The local sender ID of this dial.
+    pub fn sender(&self) -> LocalId<SenderId> {
+        self.sender
+    }
+
+    /// Report that a dial turned.
+    pub fn report_change(&self, time: Option<TimeVal>, change: DialChange) -> Result<()> {
+        self.connection
+            .pack_message_body(time, self.sender, change, ClassOfService::RELIABLE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_unbuffer::BytesMutExtras;
+    use bytes::BytesMut;
+
+    #[test]
+    fn change_wire_format() {
+        let change = DialChange {
+            change: 0.25,
+            dial: 2,
+        };
+        let buf = BytesMut::allocate_and_buffer(change).unwrap().freeze();
+        // Big-endian float64 then int32, as vrpn_Dial::encode_to
+        assert_eq!(&buf[..], &hex!("3fd0000000000000 00000002")[..]);
+        assert_eq!(DialChange::unbuffer_from(&mut buf.clone()).unwrap(), change);
+    }
+}
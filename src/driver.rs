@@ -0,0 +1,486 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Split a connection into a cloneable handle and a single driver future.
+//!
+//! The handle may be used from any task or thread to register types, senders,
+//! and handlers, and to send messages. The driver owns the job of polling the
+//! connection's sockets: spawn it on the runtime that those sockets belong to.
+
+use crate::{
+    connection::{Connection, ConnectionCore, ConnectionStatus},
+    Result,
+};
+use futures::{
+    future::{self, Either},
+    pin_mut,
+    task::ArcWake,
+    Future,
+};
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// Connections that can be driven by polling their endpoints.
+///
+/// Implemented by the `ConnectionIp` of each backend.
+pub trait PollEndpoints: Connection {
+    /// Poll all endpoints, dispatching received messages and sending queued ones.
+    ///
+    /// Returns `Poll::Ready(Ok(Some(())))` if there are no open endpoints to wait on,
+    /// and `Poll::Ready(Ok(None))` if the connection is done for good.
+    fn poll_endpoints(&self, cx: &mut Context<'_>) -> Poll<Result<Option<()>>>;
+
+    /// Begin shutting down: stop accepting clients and reconnecting to servers,
+    /// send each peer a disconnect message, and close endpoints once their queues are sent.
+    ///
+    /// Keep polling the endpoints until that finishes.
+    fn shutdown(&self) -> Result<()> {
+        self.close_endpoints()
+    }
+
+    /// Poll the endpoints on the current thread, dispatching to handlers synchronously,
+    /// like `vrpn_Connection::mainloop`.
+    ///
+    /// With no timeout, handles only what is ready and returns without blocking.
+    /// Otherwise, if nothing was ready, waits up to `timeout` for something to be,
+    /// and handles that before returning.
+    fn mainloop(&self, timeout: Option<Duration>) -> Result<()> {
+        let waker = Arc::new(ThreadWaker::default());
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut waited = false;
+        loop {
+            let task_waker = futures::task::waker(Arc::clone(&waker));
+            self.connection_core().register_driver_waker(&task_waker);
+            let mut cx = Context::from_waker(&task_waker);
+            match self.poll_endpoints(&mut cx) {
+                Poll::Ready(Err(e)) => return Err(e),
+                Poll::Ready(Ok(None)) => return Ok(()),
+                Poll::Ready(Ok(Some(()))) | Poll::Pending => {}
+            }
+            match deadline {
+                Some(deadline) if !waited && waker.wait_until(deadline) => waited = true,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Poll the endpoints until every message queued so far has been written out to the OS,
+    /// e.g. before shutting down.
+    ///
+    /// Like `mainloop`, this is for connections without a running `ConnectionDriver`:
+    /// with one, use `ConnectionHandle::flush`.
+    fn flush(&self) -> Flush<'_, Self>
+    where
+        Self: Sized,
+    {
+        Flush(self)
+    }
+
+    /// Poll the endpoints once, like `mainloop(None)`, and return whether every message
+    /// queued so far has been written out to the OS.
+    fn try_flush(&self) -> Result<bool> {
+        self.mainloop(None)?;
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        Ok(self.poll_flushed(&mut cx).is_ready())
+    }
+}
+
+/// Future returned by `PollEndpoints::flush`.
+#[derive(Debug)]
+#[must_use = "nothing is flushed unless the future is polled"]
+pub struct Flush<'a, C: PollEndpoints>(&'a C);
+
+impl<'a, C: PollEndpoints> Future for Flush<'a, C> {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let connection = self.0;
+        connection
+            .connection_core()
+            .register_driver_waker(cx.waker());
+        match connection.poll_endpoints(cx) {
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Ready(Ok(None)) => return Poll::Ready(Ok(())),
+            Poll::Ready(Ok(Some(()))) | Poll::Pending => {}
+        }
+        connection.poll_flushed(cx).map(Ok)
+    }
+}
+
+/// Wakes a thread blocked in `PollEndpoints::mainloop`.
+#[derive(Debug, Default)]
+struct ThreadWaker {
+    woken: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl ThreadWaker {
+    /// Wait to be woken, until the deadline: returns whether we were woken.
+    fn wait_until(&self, deadline: Instant) -> bool {
+        let mut woken = self.woken.lock().unwrap_or_else(|e| e.into_inner());
+        while !*woken {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+            woken = match self.condvar.wait_timeout(woken, remaining) {
+                Ok((guard, _)) => guard,
+                Err(e) => e.into_inner().0,
+            };
+        }
+        *woken = false;
+        true
+    }
+}
+
+impl ArcWake for ThreadWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        *arc_self.woken.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        arc_self.condvar.notify_one();
+    }
+}
+
+/// Cloneable handle to a connection, usable from any task or thread.
+///
+/// Implements `Connection`, so all the usual methods are available.
+/// When the last handle is dropped, the corresponding `ConnectionDriver` completes.
+#[derive(Debug)]
+pub struct ConnectionHandle<C: PollEndpoints> {
+    connection: Arc<C>,
+    /// Number of live handles, shared with the driver.
+    handles: Arc<AtomicUsize>,
+}
+
+impl<C: PollEndpoints> Clone for ConnectionHandle<C> {
+    fn clone(&self) -> Self {
+        self.handles.fetch_add(1, Ordering::SeqCst);
+        ConnectionHandle {
+            connection: Arc::clone(&self.connection),
+            handles: Arc::clone(&self.handles),
+        }
+    }
+}
+
+impl<C: PollEndpoints> ConnectionHandle<C> {
+    /// Access the underlying connection.
+    pub fn connection(&self) -> &Arc<C> {
+        &self.connection
+    }
+
+    /// Wait for the driver to write out to the OS every message queued so far.
+    pub async fn flush(&self) {
+        self.connection.connection_core().wake_driver();
+        future::poll_fn(|cx| self.connection.poll_flushed(cx)).await
+    }
+}
+
+impl<C: PollEndpoints> Connection for ConnectionHandle<C> {
+    type SpecificEndpoint = C::SpecificEndpoint;
+
+    fn connection_core(&self) -> &ConnectionCore<Self::SpecificEndpoint> {
+        self.connection.connection_core()
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        self.connection.status()
+    }
+}
+
+/// Future that drives a connection: spawn it on your runtime.
+///
+/// Completes with an error if the connection fails, or with `Ok(())`
+/// once the connection is done or all `ConnectionHandle`s have been dropped.
+#[derive(Debug)]
+#[must_use = "the connection does nothing unless the driver is polled"]
+pub struct ConnectionDriver<C: PollEndpoints> {
+    connection: Arc<C>,
+    handles: Arc<AtomicUsize>,
+}
+
+impl<C: PollEndpoints> ConnectionDriver<C> {
+    /// Drive the connection until `shutdown` completes, then shut it down cleanly.
+    ///
+    /// Once `shutdown` completes, this stops accepting and reconnecting, sends each peer
+    /// a disconnect message, and resolves once the endpoints have flushed and closed.
+    /// Any future works as the signal: a channel receiver, a `ctrl_c` future,
+    /// or a tokio `CancellationToken::cancelled()`.
+    pub async fn run_until<F: Future<Output = ()>>(self, shutdown: F) -> Result<()> {
+        let connection = Arc::clone(&self.connection);
+        pin_mut!(shutdown);
+        match future::select(self, shutdown).await {
+            Either::Left((result, _)) => result,
+            Either::Right(((), _)) => {
+                connection.shutdown()?;
+                future::poll_fn(|cx| match connection.poll_endpoints(cx) {
+                    Poll::Ready(result) => Poll::Ready(result.map(|_| ())),
+                    Poll::Pending => Poll::Pending,
+                })
+                .await
+            }
+        }
+    }
+
+    /// Drive the connection until `token` is cancelled, then shut it down cleanly,
+    /// as with `run_until`.
+    #[cfg(feature = "tokio-util")]
+    pub async fn run_until_cancelled(
+        self,
+        token: tokio_util::sync::CancellationToken,
+    ) -> Result<()> {
+        self.run_until(token.cancelled()).await
+    }
+}
+
+impl<C: PollEndpoints> Future for ConnectionDriver<C> {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let connection = &self.connection;
+        // Register first, so changes made while we poll are not missed.
+        connection
+            .connection_core()
+            .register_driver_waker(cx.waker());
+        if self.handles.load(Ordering::SeqCst) == 0 {
+            // All handles are gone.
+            return Poll::Ready(Ok(()));
+        }
+        match connection.poll_endpoints(cx) {
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Ready(Ok(None)) => Poll::Ready(Ok(())),
+            // Nothing to wait on right now: we'll be woken by the handles.
+            Poll::Ready(Ok(Some(()))) | Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<C: PollEndpoints> Drop for ConnectionHandle<C> {
+    fn drop(&mut self) {
+        // Count ourselves out before waking, so the driver can't miss that we were the last handle.
+        self.handles.fetch_sub(1, Ordering::SeqCst);
+        self.connection.connection_core().wake_driver();
+    }
+}
+
+/// Split a connection into a cloneable handle and the driver future.
+pub fn split<C: PollEndpoints>(connection: Arc<C>) -> (ConnectionHandle<C>, ConnectionDriver<C>) {
+    let handles = Arc::new(AtomicUsize::new(1));
+    (
+        ConnectionHandle {
+            connection: Arc::clone(&connection),
+            handles: Arc::clone(&handles),
+        },
+        ConnectionDriver {
+            connection,
+            handles,
+        },
+    )
+}
+
+#[cfg(all(test, feature = "async-std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        data_types::{id_types::Sensor, ClassOfService, Quat, StaticSenderName, Vec3},
+        tracker::PoseReport,
+        vrpn_async::{connection_ip::ConnectionIp, endpoint_ip::EndpointIp},
+        vrpn_async_std::AsyncStd,
+        CompatibilityProfile,
+    };
+    use std::{io::Read, sync::mpsc, time::Duration};
+
+    #[test]
+    fn handle_sends_while_driver_runs() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_side, _) = listener.accept().unwrap();
+        let (bytes_tx, bytes_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut client = client;
+            let mut buf = [0_u8; 4096];
+            while let Ok(n) = client.read(&mut buf) {
+                if n == 0 || bytes_tx.send(n).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let conn = ConnectionIp::new_server(None, None).unwrap();
+        conn.endpoints().lock().push(Some(EndpointIp::new(
+            Arc::new(AsyncStd),
+            server_side.into(),
+            None,
+            CompatibilityProfile::default(),
+        )));
+        let (handle, driver) = split(conn);
+        let driver = async_std::task::spawn(driver);
+
+        let sender_thread = {
+            let handle = handle.clone();
+            std::thread::spawn(move || {
+                let sender = handle
+                    .register_sender(StaticSenderName(b"Tracker0"))
+                    .unwrap();
+                handle
+                    .pack_message_body(
+                        None,
+                        sender,
+                        PoseReport {
+                            sensor: Sensor(0),
+                            pos: Vec3::new(0.0, 0.0, 0.0),
+                            quat: Quat::identity(),
+                        },
+                        ClassOfService::RELIABLE,
+                    )
+                    .unwrap();
+            })
+        };
+        sender_thread.join().unwrap();
+
+        // The driver flushes what the other thread sent.
+        let n = bytes_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(n > 0);
+
+        drop(handle);
+        async_std::task::block_on(async_std::future::timeout(Duration::from_secs(5), driver))
+            .expect("driver should finish once handles are dropped")
+            .unwrap();
+    }
+
+    #[test]
+    fn mainloop_sends_and_times_out() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_side, _) = listener.accept().unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let conn = ConnectionIp::new_server(None, None).unwrap();
+        conn.endpoints().lock().push(Some(EndpointIp::new(
+            Arc::new(AsyncStd),
+            server_side.into(),
+            None,
+            CompatibilityProfile::default(),
+        )));
+        let sender = conn.register_sender(StaticSenderName(b"Tracker0")).unwrap();
+        conn.send(
+            sender,
+            PoseReport {
+                sensor: Sensor(0),
+                pos: Vec3::new(0.0, 0.0, 0.0),
+                quat: Quat::identity(),
+            },
+            ClassOfService::RELIABLE,
+        )
+        .unwrap();
+
+        conn.mainloop(Some(Duration::from_millis(100))).unwrap();
+        let mut buf = [0_u8; 4096];
+        assert!(client.read(&mut buf).unwrap() > 0);
+
+        // Without a timeout, it does not block.
+        let start = std::time::Instant::now();
+        conn.mainloop(None).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // With nothing to wake it, it waits out the timeout.
+        let idle = ConnectionIp::new_server(None, None).unwrap();
+        let start = std::time::Instant::now();
+        idle.mainloop(Some(Duration::from_millis(100))).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn flush_writes_queued() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_side, _) = listener.accept().unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let conn = ConnectionIp::new_server(None, None).unwrap();
+        conn.endpoints().lock().push(Some(EndpointIp::new(
+            Arc::new(AsyncStd),
+            server_side.into(),
+            None,
+            CompatibilityProfile::default(),
+        )));
+        let sender = conn.register_sender(StaticSenderName(b"Tracker0")).unwrap();
+        let report = PoseReport {
+            sensor: Sensor(0),
+            pos: Vec3::new(0.0, 0.0, 0.0),
+            quat: Quat::identity(),
+        };
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        conn.send(sender, report.clone(), ClassOfService::RELIABLE)
+            .unwrap();
+        assert!(conn.poll_flushed(&mut cx).is_pending());
+
+        async_std::task::block_on(async_std::future::timeout(
+            Duration::from_secs(5),
+            conn.flush(),
+        ))
+        .unwrap()
+        .unwrap();
+        assert!(conn.poll_flushed(&mut cx).is_ready());
+        let mut buf = [0_u8; 4096];
+        assert!(client.read(&mut buf).unwrap() > 0);
+
+        conn.send(sender, report, ClassOfService::RELIABLE).unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !conn.try_flush().unwrap() {
+            assert!(std::time::Instant::now() < deadline);
+        }
+        assert!(client.read(&mut buf).unwrap() > 0);
+    }
+
+    #[test]
+    fn run_until_shuts_down() {
+        use crate::{codec::maybe_decode_one, data_types::constants};
+        use bytes::Bytes;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_side, _) = listener.accept().unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut client = client;
+            let mut received = Vec::new();
+            client.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let conn = ConnectionIp::new_server(None, Some("127.0.0.1:0".parse().unwrap())).unwrap();
+        conn.endpoints().lock().push(Some(EndpointIp::new(
+            Arc::new(AsyncStd),
+            server_side.into(),
+            None,
+            CompatibilityProfile::default(),
+        )));
+        let (handle, driver) = split(conn);
+        let (stop_tx, stop_rx) = futures::channel::oneshot::channel::<()>();
+        let driver = async_std::task::spawn(driver.run_until(async {
+            let _ = stop_rx.await;
+        }));
+        stop_tx.send(()).unwrap();
+        async_std::task::block_on(async_std::future::timeout(Duration::from_secs(5), driver))
+            .expect("driver should finish once shut down")
+            .unwrap();
+        assert!(handle.endpoints().lock().is_empty());
+
+        // The peer sees a disconnect message, then the end of the stream.
+        let mut received = Bytes::from(reader.join().unwrap());
+        let mut last = None;
+        while let Some(msg) = maybe_decode_one(&mut received).unwrap() {
+            last = Some(msg.into_inner().header.message_type);
+        }
+        assert_eq!(last, Some(constants::DISCONNECT_MESSAGE));
+    }
+}
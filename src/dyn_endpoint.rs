@@ -0,0 +1,361 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Connections whose endpoints are chosen at runtime, as boxed trait objects.
+//!
+//! Implement `DynEndpoint` for a custom transport, like a serial port or shared memory,
+//! and add it to a `DynConnection` alongside any other endpoints.
+
+use crate::{
+    class_policy::ClassOfServiceOverrides,
+    codec::FramingRecovery,
+    compression::Compression,
+    connection::{ConnectionCore, ConnectionStatus},
+    data_types::{ClassOfService, CookieData, GenericMessage},
+    endpoint::{DescriptionTracker, SystemCommand},
+    message_history::{Direction, MessageHistory, MessageHistoryConfig},
+    message_log::{FileLogWriter, RemoteLogPolicy},
+    net_util::SocketConfig,
+    poll_config::PollConfig,
+    send_queue::SendQueueLimits,
+    sequence::SequenceStats,
+    timestamp_policy::TimestampPolicy,
+    tracker::SensorFilter,
+    translation_table::TranslationTablesSnapshot,
+    Connection, Endpoint, PollEndpoints, Result, TranslationTables, TypeDispatcher,
+};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// An endpoint that can be stored as a trait object and polled by a `DynConnection`.
+pub trait DynEndpoint: Endpoint + Send + fmt::Debug {
+    /// Dispatch received messages and send queued ones.
+    ///
+    /// Ready once the endpoint has closed: with an error if it failed.
+    fn poll_endpoint(
+        &mut self,
+        dispatcher: &TypeDispatcher,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>>;
+}
+
+impl<T: Endpoint + ?Sized> Endpoint for Box<T> {
+    fn translation_tables(&self) -> &TranslationTables {
+        (**self).translation_tables()
+    }
+
+    fn translation_tables_mut(&mut self) -> &mut TranslationTables {
+        (**self).translation_tables_mut()
+    }
+
+    fn translation_snapshot(&self) -> TranslationTablesSnapshot {
+        (**self).translation_snapshot()
+    }
+
+    fn send_system_change(&self, message: SystemCommand) -> Result<()> {
+        (**self).send_system_change(message)
+    }
+
+    fn sensor_filter(&self) -> Option<&SensorFilter> {
+        (**self).sensor_filter()
+    }
+
+    fn sensor_filter_mut(&mut self) -> Option<&mut SensorFilter> {
+        (**self).sensor_filter_mut()
+    }
+
+    fn set_message_history(&mut self, config: Option<MessageHistoryConfig>) {
+        (**self).set_message_history(config)
+    }
+
+    fn message_history_mut(&mut self) -> Option<&mut MessageHistory> {
+        (**self).message_history_mut()
+    }
+
+    fn set_message_log(&mut self, direction: Direction, log: Option<FileLogWriter>) {
+        (**self).set_message_log(direction, log)
+    }
+
+    fn message_log_mut(&mut self, direction: Direction) -> Option<&mut FileLogWriter> {
+        (**self).message_log_mut(direction)
+    }
+
+    fn set_poll_config(&mut self, config: PollConfig) {
+        (**self).set_poll_config(config)
+    }
+
+    fn set_coalesce_threshold(&mut self, threshold: usize) {
+        (**self).set_coalesce_threshold(threshold)
+    }
+
+    fn set_max_message_size(&mut self, max_message_size: usize) {
+        (**self).set_max_message_size(max_message_size)
+    }
+
+    fn set_framing_recovery(&mut self, recovery: FramingRecovery) {
+        (**self).set_framing_recovery(recovery)
+    }
+
+    fn set_socket_config(&mut self, config: &SocketConfig) -> Result<()> {
+        (**self).set_socket_config(config)
+    }
+
+    fn sequence_stats(&self) -> Option<SequenceStats> {
+        (**self).sequence_stats()
+    }
+
+    fn set_read_idle_timeout(&mut self, timeout: Option<Duration>) {
+        (**self).set_read_idle_timeout(timeout)
+    }
+
+    fn set_keepalive(&mut self, interval: Option<Duration>) {
+        (**self).set_keepalive(interval)
+    }
+
+    fn set_udp_only(&mut self) {
+        (**self).set_udp_only()
+    }
+
+    fn set_compression(&mut self, compression: Compression) {
+        (**self).set_compression(compression)
+    }
+
+    fn set_peer_compression(&mut self, algorithms: u32) {
+        (**self).set_peer_compression(algorithms)
+    }
+
+    fn set_remote_log_policy(&mut self, policy: RemoteLogPolicy) {
+        (**self).set_remote_log_policy(policy)
+    }
+
+    fn set_class_overrides(&mut self, overrides: ClassOfServiceOverrides) {
+        (**self).set_class_overrides(overrides)
+    }
+
+    fn set_send_queue_limits(&mut self, limits: SendQueueLimits) {
+        (**self).set_send_queue_limits(limits)
+    }
+
+    fn set_timestamp_policy(&mut self, policy: TimestampPolicy) {
+        (**self).set_timestamp_policy(policy)
+    }
+
+    fn timestamp_policy(&self) -> TimestampPolicy {
+        (**self).timestamp_policy()
+    }
+
+    fn remote_cookie(&self) -> Option<CookieData> {
+        (**self).remote_cookie()
+    }
+
+    fn close_when_sent(&mut self) {
+        (**self).close_when_sent()
+    }
+
+    fn poll_flushed(&self, cx: &mut Context<'_>) -> Poll<()> {
+        (**self).poll_flushed(cx)
+    }
+
+    fn buffer_generic_message(&mut self, msg: GenericMessage, class: ClassOfService) -> Result<()> {
+        (**self).buffer_generic_message(msg, class)
+    }
+
+    fn buffer_generic_batch(
+        &mut self,
+        msgs: Vec<GenericMessage>,
+        class: ClassOfService,
+    ) -> Result<()> {
+        (**self).buffer_generic_batch(msgs, class)
+    }
+
+    fn description_tracker_mut(&mut self) -> Option<&mut DescriptionTracker> {
+        (**self).description_tracker_mut()
+    }
+
+    fn send_all_descriptions(&mut self, dispatcher: &TypeDispatcher) -> Result<()> {
+        (**self).send_all_descriptions(dispatcher)
+    }
+}
+
+/// A connection polling any mix of boxed endpoints, added with `add_endpoint`.
+#[derive(Debug)]
+pub struct DynConnection {
+    core: ConnectionCore<Box<dyn DynEndpoint>>,
+    is_server: bool,
+    /// Endpoints added since the last poll, not yet reported to the got-connection handlers.
+    new_endpoints: AtomicUsize,
+}
+
+impl DynConnection {
+    fn new(is_server: bool) -> Arc<DynConnection> {
+        Arc::new(DynConnection {
+            core: ConnectionCore::without_logs(Vec::new()),
+            is_server,
+            new_endpoints: AtomicUsize::new(0),
+        })
+    }
+
+    /// Create a server with no endpoints yet.
+    pub fn new_server() -> Arc<DynConnection> {
+        DynConnection::new(true)
+    }
+
+    /// Create a client with no endpoints yet: it is disconnected until one is added.
+    pub fn new_client() -> Arc<DynConnection> {
+        DynConnection::new(false)
+    }
+
+    /// Apply this connection's settings to an endpoint, describe our senders and types to it,
+    /// and start polling it.
+    pub fn add_endpoint(&self, mut endpoint: Box<dyn DynEndpoint>) -> Result<()> {
+        let dispatcher = self.dispatcher();
+        let dispatcher = dispatcher.read();
+        let endpoints = self.endpoints();
+        let mut endpoints = endpoints.lock();
+        endpoint.set_message_history(self.core.message_history_config()?);
+        endpoint.set_coalesce_threshold(self.core.coalesce_threshold());
+        endpoint.set_max_message_size(self.core.max_message_size());
+        endpoint.set_framing_recovery(self.core.framing_recovery()?);
+        if let Err(e) = endpoint.set_socket_config(&self.core.socket_config()?) {
+            warn!("Could not set socket options: {}", e);
+        }
+        endpoint.set_poll_config(self.core.poll_config()?);
+        let timeouts = self.core.timeouts()?;
+        endpoint.set_read_idle_timeout(timeouts.read_idle);
+        endpoint.set_keepalive(timeouts.keepalive);
+        endpoint.set_remote_log_policy(self.core.remote_log_policy()?);
+        endpoint.set_class_overrides(self.core.class_overrides()?);
+        endpoint.set_send_queue_limits(self.core.send_queue_limits()?);
+        endpoint.set_timestamp_policy(self.core.timestamp_policy()?);
+        endpoint.send_all_descriptions(&dispatcher)?;
+        endpoints.push(Some(endpoint));
+        self.new_endpoints.fetch_add(1, Ordering::SeqCst);
+        self.core.wake_driver();
+        Ok(())
+    }
+}
+
+impl PollEndpoints for DynConnection {
+    fn poll_endpoints(&self, cx: &mut Context<'_>) -> Poll<Result<Option<()>>> {
+        let dispatcher = self.dispatcher();
+        let dispatcher = dispatcher.read();
+        let endpoints = self.endpoints();
+        let mut endpoints = endpoints.lock();
+
+        let new_endpoints = self.new_endpoints.swap(0, Ordering::SeqCst);
+        let existing = endpoints.iter().flatten().count() - new_endpoints;
+        for i in 0..new_endpoints {
+            dispatcher.call_got_connection(existing == 0 && i == 0)?;
+        }
+
+        let mut dropped = 0;
+        for ep in endpoints.iter_mut() {
+            let closed = match ep {
+                Some(endpoint) => match endpoint.poll_endpoint(&dispatcher, cx) {
+                    Poll::Ready(Err(e)) => {
+                        warn!("Dropping endpoint after error: {}", e);
+                        true
+                    }
+                    poll => poll.is_ready(),
+                },
+                None => true,
+            };
+            if closed && ep.take().is_some() {
+                dropped += 1;
+            }
+        }
+        endpoints.retain(|ep| ep.is_some());
+        // Names first seen in one endpoint's descriptions got new local IDs to describe.
+        for endpoint in endpoints.iter_mut().flatten() {
+            endpoint.send_all_descriptions(&dispatcher)?;
+        }
+        for i in 0..dropped {
+            dispatcher.call_dropped_connection(endpoints.is_empty() && i + 1 == dropped)?;
+        }
+
+        let poll = if endpoints.is_empty() {
+            Poll::Ready(Ok(Some(())))
+        } else {
+            Poll::Pending
+        };
+        drop(endpoints);
+        drop(dispatcher);
+        self.core.publish_status(self.status());
+        poll
+    }
+}
+
+impl Connection for DynConnection {
+    type SpecificEndpoint = Box<dyn DynEndpoint>;
+
+    fn connection_core(&self) -> &ConnectionCore<Self::SpecificEndpoint> {
+        &self.core
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        let endpoints = self.endpoints();
+        let count = endpoints.lock().len();
+        match (self.is_server, count) {
+            (true, count) => ConnectionStatus::Server(count),
+            (false, 0) => ConnectionStatus::ClientDisconnected,
+            (false, _) => ConnectionStatus::ClientConnected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        button::{ButtonChange, ButtonRemote, ButtonServer},
+        data_types::StaticSenderName,
+        loopback::EndpointLoopback,
+        testing::Record,
+    };
+    use std::sync::Mutex;
+
+    #[test]
+    fn boxed_endpoints() {
+        let server = DynConnection::new_server();
+        let client = DynConnection::new_client();
+        assert_eq!(client.status(), ConnectionStatus::ClientDisconnected);
+        let (server_end, client_end) = EndpointLoopback::pair();
+        server.add_endpoint(Box::new(server_end)).unwrap();
+        client.add_endpoint(Box::new(client_end)).unwrap();
+        assert_eq!(server.status(), ConnectionStatus::Server(1));
+        assert_eq!(client.status(), ConnectionStatus::ClientConnected);
+
+        let button = ButtonServer::new(Arc::clone(&server), StaticSenderName(b"Button0")).unwrap();
+        let received = Arc::new(Mutex::new(Vec::<ButtonChange>::new()));
+        ButtonRemote::new(Arc::clone(&client), StaticSenderName(b"Button0"))
+            .unwrap()
+            .add_handler(Box::new(Record(Arc::clone(&received))))
+            .unwrap();
+        let change = ButtonChange {
+            button: 2,
+            pressed: true,
+        };
+        button.report_change(None, change).unwrap();
+        for _ in 0..4 {
+            server.mainloop(None).unwrap();
+            client.mainloop(None).unwrap();
+        }
+        assert_eq!(received.lock().unwrap()[..], [change]);
+
+        client.shutdown().unwrap();
+        for _ in 0..4 {
+            client.mainloop(None).unwrap();
+            server.mainloop(None).unwrap();
+        }
+        assert_eq!(client.status(), ConnectionStatus::ClientDisconnected);
+        assert_eq!(server.status(), ConnectionStatus::Server(0));
+    }
+}
@@ -2,22 +2,43 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
-use std::convert::{TryFrom, TryInto};
+use std::{
+    collections::HashSet,
+    convert::{TryFrom, TryInto},
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use bytes::Bytes;
 
 use crate::{
-    buffer_unbuffer::BufferTo,
+    buffer_unbuffer::{BufferTo, EmptyMessage},
+    class_policy::ClassOfServiceOverrides,
+    codec::FramingRecovery,
+    compression::Compression,
+    constants::TCP_BUFLEN,
     data_types::{
-        constants, id_types::*, message::Message, ClassOfService, Description, GenericMessage,
-        IdWithNameAndDescription, LogFileNames, MessageHeader, MessageTypeId, MessageTypeName,
-        SenderName, TypedMessage, TypedMessageBody, UdpDescription,
+        constants, id_types::*, message::Message, ClassOfService, CookieData, Description,
+        GenericMessage, IdWithNameAndDescription, LogFileNames, MessageHeader, MessageTypeId,
+        MessageTypeIdentifier, MessageTypeName, SenderName, StaticMessageTypeName, TypedMessage,
+        TypedMessageBody, UdpDescription,
     },
-    translation_table::{TranslationTable, TranslationTableExt},
+    message_history::{Direction, MessageHistory, MessageHistoryConfig},
+    message_log::{FileLogWriter, RemoteLogPolicy},
+    net_util::SocketConfig,
+    poll_config::PollConfig,
+    send_queue::SendQueueLimits,
+    sequence::SequenceStats,
+    timestamp_policy::TimestampPolicy,
+    tracker::SensorFilter,
+    translation_table::{TranslationTable, TranslationTableExt, TranslationTablesSnapshot},
     type_dispatcher::TryIntoDescriptionMessage,
     Result, TranslationTables, TypeDispatcher, VrpnError,
 };
 
+/// Default number of bytes of outgoing messages an endpoint accumulates before writing them out.
+pub const DEFAULT_COALESCE_THRESHOLD: usize = TCP_BUFLEN;
+
 /// These are all "system commands".
 /// They are converted from system messages by Endpoint::handle_message_as_system
 /// (and thus Endpoint::passthrough_nonsystem_message).
@@ -38,13 +59,29 @@ pub enum ExtendedSystemCommand {
     DisconnectMessage,
 }
 
+/// Whether this is one of the system message types handled internally.
+///
+/// Others go to the system handlers of the `TypeDispatcher`, if any.
+pub fn is_known_system_message(message_type: MessageTypeId) -> bool {
+    matches!(
+        message_type,
+        constants::TYPE_DESCRIPTION
+            | constants::SENDER_DESCRIPTION
+            | constants::UDP_DESCRIPTION
+            | constants::LOG_DESCRIPTION
+            | constants::DISCONNECT_MESSAGE
+    )
+}
+
 /// Parse a "system" message (for which message_type.is_system_message() returns true).
 ///
 /// Call from within your dispatch function once you've recognized that a message is a system message.
+/// Fails for types where `is_known_system_message` is false.
 pub fn parse_system_message(msg: GenericMessage) -> Result<SystemCommand> {
     if !msg.is_system_message() {
         return Err(VrpnError::NotSystemMessage);
     }
+    trace!("System message {:?}", msg.header.message_type);
     Ok(match msg.header.message_type {
         constants::TYPE_DESCRIPTION => {
             let msg = TypedMessage::try_from(&msg)?;
@@ -59,8 +96,8 @@ pub fn parse_system_message(msg: GenericMessage) -> Result<SystemCommand> {
             SystemCommand::Extended(ExtendedSystemCommand::UdpDescription(msg.into()))
         }
         constants::LOG_DESCRIPTION => {
-            let msg = TypedMessage::try_from(&msg)?;
-            SystemCommand::Extended(ExtendedSystemCommand::LogDescription(msg.body))
+            let names = LogFileNames::from_wire(msg.body.into_inner())?;
+            SystemCommand::Extended(ExtendedSystemCommand::LogDescription(names))
         }
         constants::DISCONNECT_MESSAGE => {
             SystemCommand::Extended(ExtendedSystemCommand::DisconnectMessage)
@@ -73,20 +110,64 @@ pub fn parse_system_message(msg: GenericMessage) -> Result<SystemCommand> {
     })
 }
 
+/// Whether a description repeats a mapping already in the tables, counting it if so.
+///
+/// Repeats are only logged as their count reaches each power of two,
+/// so a peer resending descriptions in a loop cannot flood the log.
+fn is_duplicate<I: UnwrappedId>(
+    translation_tables: &mut TranslationTables,
+    name: &[u8],
+    remote_id: RemoteId<I>,
+) -> bool
+where
+    TranslationTables: AsRef<TranslationTable<I>>,
+{
+    let table: &TranslationTable<I> = translation_tables.as_ref();
+    if !table.contains_remote_entry(name, remote_id) {
+        return false;
+    }
+    let count = translation_tables.count_duplicate_description();
+    if count.is_power_of_two() {
+        debug!(
+            "Ignored {} repeated descriptions, the latest of {:?}",
+            count,
+            String::from_utf8_lossy(name)
+        );
+    }
+    true
+}
+
 /// Apply the changes from a system command to your TypeDispatcher and TranslationTables.
 ///
+/// Repeats of descriptions already applied are ignored, and counted in the tables.
 /// Passes through any extended commands.
 pub fn handle_system_command(
-    dispatcher: &mut TypeDispatcher,
+    dispatcher: &TypeDispatcher,
     translation_tables: &mut TranslationTables,
     system_command: SystemCommand,
 ) -> Result<Option<ExtendedSystemCommand>> {
     match system_command {
+        SystemCommand::SenderDescription(desc)
+            if is_duplicate::<SenderId>(translation_tables, &desc.name, RemoteId(desc.which)) =>
+        {
+            Ok(None)
+        }
+        SystemCommand::TypeDescription(desc)
+            if is_duplicate::<MessageTypeId>(
+                translation_tables,
+                &desc.name,
+                RemoteId(desc.which),
+            ) =>
+        {
+            Ok(None)
+        }
         SystemCommand::SenderDescription(desc) => {
+            let name = SenderName(desc.name.clone());
             let local_id = dispatcher
-                .register_sender(SenderName(desc.name.clone()))?
+                .register_remote_sender(name.clone())?
                 .into_inner();
-            eprintln!(
+            dispatcher.sender_described(name, local_id);
+            debug!(
                 "Registering sender {:?}: local {:?} = remote {:?}",
                 desc.name, local_id, desc.which
             );
@@ -95,10 +176,10 @@ pub fn handle_system_command(
             Ok(None)
         }
         SystemCommand::TypeDescription(desc) => {
-            let local_id = dispatcher
-                .register_type(MessageTypeName(desc.name.clone()))?
-                .into_inner();
-            eprintln!(
+            let name = MessageTypeName(desc.name.clone());
+            let local_id = dispatcher.register_remote_type(name.clone())?.into_inner();
+            dispatcher.type_described(name, local_id);
+            debug!(
                 "Registering type {:?}: local {:?} = remote {:?}",
                 desc.name, local_id, desc.which
             );
@@ -110,6 +191,99 @@ pub fn handle_system_command(
     }
 }
 
+/// The message type of `UdpOnlyRequest`.
+///
+/// Not part of mainline VRPN.
+pub const UDP_ONLY_REQUEST: StaticMessageTypeName =
+    StaticMessageTypeName(b"vrpn-rs Connection UDP Only");
+
+/// Asks the peer to send all user messages over the low-latency channel,
+/// as a client connecting with `Scheme::UdpOnly` does.
+///
+/// Sent from the "VRPN Control" sender. Mainline VRPN peers ignore it. Has no body.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct UdpOnlyRequest;
+
+impl EmptyMessage for UdpOnlyRequest {}
+impl TypedMessageBody for UdpOnlyRequest {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(UDP_ONLY_REQUEST);
+}
+
+/// Pack a `UdpOnlyRequest`, with the IDs registered in every dispatcher.
+pub(crate) fn udp_only_request(dispatcher: &TypeDispatcher) -> Result<GenericMessage> {
+    let message_type = dispatcher
+        .get_type_id(UDP_ONLY_REQUEST)
+        .ok_or(VrpnError::InvalidId(0))?;
+    let sender = dispatcher
+        .get_sender_id(constants::CONTROL)
+        .ok_or(VrpnError::InvalidId(0))?;
+    Ok(GenericMessage::try_from(TypedMessage::new(
+        None,
+        message_type,
+        sender,
+        UdpOnlyRequest,
+    ))?)
+}
+
+/// If this message is a `UdpOnlyRequest`, switch the endpoint to sending all it can over UDP.
+///
+/// Call with messages that have already been mapped to local IDs.
+pub(crate) fn update_udp_only<T: Endpoint + ?Sized>(
+    endpoint: &mut T,
+    dispatcher: &TypeDispatcher,
+    msg: &GenericMessage,
+) {
+    if dispatcher.get_type_id(UDP_ONLY_REQUEST) == Some(LocalId(msg.header.message_type)) {
+        debug!("Peer asked for all user messages over UDP");
+        endpoint.set_udp_only();
+    }
+}
+
+/// Records which sender and type descriptions have been sent to an endpoint,
+/// so that only new ones need to be sent.
+#[derive(Debug, Clone, Default)]
+pub struct DescriptionTracker {
+    senders: HashSet<IdType>,
+    types: HashSet<IdType>,
+}
+
+impl DescriptionTracker {
+    pub fn new() -> DescriptionTracker {
+        DescriptionTracker::default()
+    }
+
+    fn set_for<I: IdWithNameAndDescription>(&mut self) -> &mut HashSet<IdType> {
+        if I::DESCRIPTION_MESSAGE_TYPE == constants::SENDER_DESCRIPTION {
+            &mut self.senders
+        } else {
+            &mut self.types
+        }
+    }
+
+    /// Record that the description of this ID has been sent.
+    ///
+    /// Returns true if it had not been sent before.
+    pub fn record<I: IdWithNameAndDescription>(&mut self, id: LocalId<I>) -> bool {
+        self.set_for::<I>().insert(id.get())
+    }
+
+    /// Has the description of this ID been sent?
+    pub fn contains<I: IdWithNameAndDescription>(&self, id: LocalId<I>) -> bool {
+        if I::DESCRIPTION_MESSAGE_TYPE == constants::SENDER_DESCRIPTION {
+            self.senders.contains(&id.get())
+        } else {
+            self.types.contains(&id.get())
+        }
+    }
+
+    /// Forget everything sent, so the next delta includes all descriptions.
+    pub fn clear(&mut self) {
+        self.senders.clear();
+        self.types.clear();
+    }
+}
+
 /// An endpoint for communication.
 ///
 /// An endpoint must own:
@@ -120,17 +294,180 @@ pub trait Endpoint /* : AsMut<TranslationTables> + AsRef<TranslationTables> */ {
     /// Access the translation tables mutably.
     fn translation_tables_mut(&mut self) -> &mut TranslationTables;
 
+    /// Copy the senders and message types the remote side has declared, with their local IDs.
+    fn translation_snapshot(&self) -> TranslationTablesSnapshot {
+        self.translation_tables().snapshot()
+    }
+
     /// Send a system change message.
     ///
     /// Implementation should use interior mutability.
     fn send_system_change(&self, message: SystemCommand) -> Result<()>;
 
+    /// Access the tracker sensor subscriptions requested by the remote side, if tracked.
+    ///
+    /// Endpoints that do not track subscriptions get every message.
+    fn sensor_filter(&self) -> Option<&SensorFilter> {
+        None
+    }
+
+    /// Mutable access to the tracker sensor subscriptions requested by the remote side, if tracked.
+    fn sensor_filter_mut(&mut self) -> Option<&mut SensorFilter> {
+        None
+    }
+
+    /// Enable (or with `None`, disable) recording recent messages on this endpoint.
+    ///
+    /// Endpoints that do not support message history ignore this.
+    fn set_message_history(&mut self, _config: Option<MessageHistoryConfig>) {}
+
+    /// Mutable access to the recent message history, if enabled.
+    fn message_history_mut(&mut self) -> Option<&mut MessageHistory> {
+        None
+    }
+
+    /// Start (or with `None`, stop) logging the messages in one direction to a file.
+    ///
+    /// Endpoints that do not support logging ignore this.
+    fn set_message_log(&mut self, _direction: Direction, _log: Option<FileLogWriter>) {}
+
+    /// Mutable access to the log of messages in one direction, if enabled.
+    fn message_log_mut(&mut self, _direction: Direction) -> Option<&mut FileLogWriter> {
+        None
+    }
+
+    /// Set the limits on how much each poll of this endpoint receives.
+    ///
+    /// Endpoints that do not support this ignore it.
+    fn set_poll_config(&mut self, _config: PollConfig) {}
+
+    /// Set how many bytes of outgoing messages to accumulate into a single write.
+    ///
+    /// Endpoints that do not coalesce writes ignore this.
+    fn set_coalesce_threshold(&mut self, _threshold: usize) {}
+
+    /// Set the largest padded size of incoming message to accept: larger ones are skipped.
+    ///
+    /// Endpoints that do not decode a byte stream ignore this.
+    fn set_max_message_size(&mut self, _max_message_size: usize) {}
+
+    /// Set how corrupt framing in incoming bytes is handled.
+    ///
+    /// Endpoints that do not decode a byte stream ignore this.
+    fn set_framing_recovery(&mut self, _recovery: FramingRecovery) {}
+
+    /// Set options on the sockets of this endpoint.
+    ///
+    /// Endpoints without sockets to configure ignore this.
+    fn set_socket_config(&mut self, _config: &SocketConfig) -> Result<()> {
+        Ok(())
+    }
+
+    /// Counters of the sequence numbers received on this endpoint's reliable channel.
+    ///
+    /// Endpoints that do not track sequence numbers return `None`.
+    fn sequence_stats(&self) -> Option<SequenceStats> {
+        None
+    }
+
+    /// Close this endpoint if nothing is received for this long, or never with `None`.
+    ///
+    /// Endpoints that cannot time out ignore this.
+    fn set_read_idle_timeout(&mut self, _timeout: Option<Duration>) {}
+
+    /// Send a keep-alive message whenever nothing has been sent for this long, or never with `None`.
+    ///
+    /// Endpoints whose peer cannot vanish unnoticed ignore this.
+    fn set_keepalive(&mut self, _interval: Option<Duration>) {}
+
+    /// Send all user messages over the low-latency channel once it is connected,
+    /// not just those without `ClassOfService::RELIABLE`. Not part of mainline VRPN.
+    ///
+    /// Endpoints without a low-latency channel ignore this.
+    fn set_udp_only(&mut self) {}
+
+    /// Set how to compress outgoing TCP traffic, once the peer offers the same.
+    ///
+    /// Endpoints that cannot compress ignore this.
+    fn set_compression(&mut self, _compression: Compression) {}
+
+    /// Record the algorithms in the peer's `CompressionOffer`.
+    fn set_peer_compression(&mut self, _algorithms: u32) {}
+
+    /// Set whether to log this endpoint when the peer asks for it.
+    ///
+    /// Does nothing by default, for endpoints that cannot log.
+    fn set_remote_log_policy(&mut self, _policy: RemoteLogPolicy) {}
+
+    /// Override the class of service of outgoing messages, and so the channel they go on.
+    ///
+    /// Does nothing by default, for endpoints with only one channel.
+    fn set_class_overrides(&mut self, _overrides: ClassOfServiceOverrides) {}
+
+    /// Set the limits on messages waiting to be written to the peer.
+    ///
+    /// Endpoints that do not queue outgoing messages ignore this.
+    fn set_send_queue_limits(&mut self, _limits: SendQueueLimits) {}
+
+    /// Set which clock the times of user messages reflect, sent and received.
+    ///
+    /// Endpoints that do not support this keep the times they are given.
+    fn set_timestamp_policy(&mut self, _policy: TimestampPolicy) {}
+
+    /// The timestamp policy of this endpoint.
+    fn timestamp_policy(&self) -> TimestampPolicy {
+        TimestampPolicy::default()
+    }
+
+    /// The cookie the peer sent in the handshake: its version and log mode, if known.
+    fn remote_cookie(&self) -> Option<CookieData> {
+        None
+    }
+
+    /// Close this endpoint once the messages already queued have been sent.
+    ///
+    /// Endpoints that cannot close on their own ignore this.
+    fn close_when_sent(&mut self) {}
+
+    /// Ready once the messages queued so far have been written out to the OS.
+    ///
+    /// Endpoints that write synchronously, or in-process, always are.
+    fn poll_flushed(&self, _cx: &mut Context<'_>) -> Poll<()> {
+        Poll::Ready(())
+    }
+
     /// Queue up a generic message for sending.
     fn buffer_generic_message(&mut self, msg: GenericMessage, class: ClassOfService) -> Result<()>;
 
-    /// Pack all descriptions from the dispatcher and send them.
+    /// Queue up generic messages to be sent together, back to back, in a single write if possible.
+    ///
+    /// Each still gets its own sequence number. Endpoints that do not batch writes
+    /// queue them one by one.
+    fn buffer_generic_batch(
+        &mut self,
+        msgs: Vec<GenericMessage>,
+        class: ClassOfService,
+    ) -> Result<()> {
+        for msg in msgs {
+            self.buffer_generic_message(msg, class)?;
+        }
+        Ok(())
+    }
+
+    /// Access the record of descriptions already sent on this endpoint, if tracked.
+    ///
+    /// Endpoints that do not track this get all descriptions every time.
+    fn description_tracker_mut(&mut self) -> Option<&mut DescriptionTracker> {
+        None
+    }
+
+    /// Pack all descriptions from the dispatcher not yet sent on this endpoint, and send them.
     fn send_all_descriptions(&mut self, dispatcher: &TypeDispatcher) -> Result<()> {
-        for msg in dispatcher.pack_all_descriptions()? {
+        let messages = match self.description_tracker_mut() {
+            Some(tracker) => dispatcher.pack_new_descriptions(tracker)?,
+            None => dispatcher.pack_all_descriptions()?.collect(),
+        };
+        for msg in messages {
             self.buffer_generic_message(msg, ClassOfService::RELIABLE)?;
         }
         Ok(())
@@ -207,6 +544,12 @@ where
         (self.translation_tables_mut().as_mut() as &mut TranslationTable<I>)
             .add_local_id(name.clone(), local_id);
 
+        if let Some(tracker) = self.description_tracker_mut() {
+            if !tracker.record(local_id) {
+                // Already described to the other side.
+                return Ok(());
+            }
+        }
         self.buffer_generic_message(
             local_id.try_into_description_message(name.clone())?,
             ClassOfService::RELIABLE,
@@ -231,13 +574,13 @@ where
             Ok(msg)
         } else {
             let remote_type = RemoteId(msg.header.message_type);
-            let LocalId(new_type) = self.map_to_local_id(remote_type).ok_or_else(|| {
-                VrpnError::OtherMessage("Could not map sender to local".to_string())
-            })?;
+            let LocalId(new_type) = self
+                .map_to_local_id(remote_type)
+                .ok_or_else(|| VrpnError::UnmappedRemoteType(remote_type.get()))?;
             let remote_sender = RemoteId(msg.header.sender);
-            let LocalId(new_sender) = self.map_to_local_id(remote_sender).ok_or_else(|| {
-                VrpnError::OtherMessage("Could not map type to local".to_string())
-            })?;
+            let LocalId(new_sender) = self
+                .map_to_local_id(remote_sender)
+                .ok_or_else(|| VrpnError::UnmappedRemoteSender(remote_sender.get()))?;
 
             // eprintln!("user message: {:?}", msg.header);
             let msg = GenericMessage::from_header_and_body(
@@ -248,3 +591,123 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::{StaticMessageTypeName, StaticSenderName};
+
+    /// Endpoint that just keeps the messages it is asked to send.
+    #[derive(Debug, Default)]
+    struct RecordingEndpoint {
+        translation: TranslationTables,
+        sent: Vec<GenericMessage>,
+        tracker: DescriptionTracker,
+    }
+
+    impl Endpoint for RecordingEndpoint {
+        fn translation_tables(&self) -> &TranslationTables {
+            &self.translation
+        }
+        fn translation_tables_mut(&mut self) -> &mut TranslationTables {
+            &mut self.translation
+        }
+        fn send_system_change(&self, _message: SystemCommand) -> Result<()> {
+            Ok(())
+        }
+        fn description_tracker_mut(&mut self) -> Option<&mut DescriptionTracker> {
+            Some(&mut self.tracker)
+        }
+        fn buffer_generic_message(
+            &mut self,
+            msg: GenericMessage,
+            _class: ClassOfService,
+        ) -> Result<()> {
+            self.sent.push(msg);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn log_description_parsed_as_sent() {
+        // Checked only when acted on, as mainline VRPN never rejects these.
+        let names = LogFileNames::from_names(Some("same.vrpn"), Some("same.vrpn"));
+        let msg = GenericMessage::try_from(TypedMessage::from(names.clone())).unwrap();
+        match parse_system_message(msg).unwrap() {
+            SystemCommand::Extended(ExtendedSystemCommand::LogDescription(parsed)) => {
+                assert_eq!(parsed, names)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn description_deltas() {
+        let mut dispatcher = TypeDispatcher::new();
+        let mut ep = RecordingEndpoint::default();
+
+        ep.send_all_descriptions(&dispatcher).unwrap();
+        let initial = ep.sent.len();
+        assert!(initial > 0);
+
+        // Nothing new: nothing sent.
+        ep.send_all_descriptions(&dispatcher).unwrap();
+        assert_eq!(ep.sent.len(), initial);
+
+        // Only the new ones get sent.
+        let sender = dispatcher
+            .register_sender(StaticSenderName(b"Tracker0"))
+            .unwrap()
+            .into_inner();
+        let message_type = dispatcher
+            .register_type(StaticMessageTypeName(b"vrpn_Tracker Pos_Quat"))
+            .unwrap()
+            .into_inner();
+        ep.send_all_descriptions(&dispatcher).unwrap();
+        assert_eq!(ep.sent.len(), initial + 2);
+        assert!(ep.tracker.contains(sender));
+        assert!(ep.tracker.contains(message_type));
+
+        // Described already, so registering on the endpoint sends nothing more.
+        ep.new_local_id(&Bytes::from_static(b"Tracker0"), sender)
+            .unwrap();
+        assert_eq!(ep.sent.len(), initial + 2);
+
+        ep.tracker.clear();
+        ep.send_all_descriptions(&dispatcher).unwrap();
+        assert_eq!(ep.sent.len(), 2 * initial + 4);
+    }
+
+    #[test]
+    fn repeated_descriptions() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+        let mut dispatcher = TypeDispatcher::new();
+        let described = Arc::new(AtomicUsize::new(0));
+        let count = Arc::clone(&described);
+        dispatcher.add_sender_observer(Box::new(move |_, _| {
+            count.fetch_add(1, Ordering::SeqCst);
+        }));
+        let mut tables = TranslationTables::new();
+        let describe = |which, name: &'static [u8]| {
+            SystemCommand::SenderDescription(Description::from_id_and_name(
+                SenderId(which),
+                Bytes::from_static(name),
+            ))
+        };
+        for _ in 0..3 {
+            handle_system_command(&dispatcher, &mut tables, describe(0, b"Tracker0")).unwrap();
+        }
+        assert_eq!(described.load(Ordering::SeqCst), 1);
+        assert_eq!(tables.duplicate_descriptions(), 2);
+
+        // A new name for the same remote ID is not a repeat.
+        handle_system_command(&dispatcher, &mut tables, describe(0, b"Tracker1")).unwrap();
+        assert_eq!(described.load(Ordering::SeqCst), 2);
+        let snapshot = tables.snapshot();
+        assert_eq!(snapshot.duplicate_descriptions, 2);
+        assert_eq!(snapshot.senders[0].name, "Tracker1");
+    }
+}
@@ -7,7 +7,8 @@ use crate::{
         ExpandSizeRequirement, MayContainSizeRequirement, SizeRequirement,
     },
     buffer_unbuffer::{BufferUnbufferError, MessageSizeInvalid},
-    data_types::id_types::IdType,
+    data_types::{id_types::IdType, name_types::QuotedName},
+    limits::LimitKind,
 };
 
 use thiserror::Error;
@@ -19,16 +20,44 @@ pub enum VrpnError {
     BufferUnbuffer(#[from] BufferUnbufferError),
     #[error("invalid id {0}")]
     InvalidId(IdType),
+    #[error("id {0} was removed, until its name is registered again")]
+    RemovedId(IdType),
     #[error("empty translation table entry")]
     EmptyEntry,
-    #[error("too many handlers")]
-    TooManyHandlers,
-    #[error("too many mappings")]
-    TooManyMappings,
+    #[error("reached the limit of {limit} {kind}, registering {}", QuotedName(.name))]
+    LimitReached {
+        kind: LimitKind,
+        limit: usize,
+        name: bytes::Bytes,
+    },
     #[error("handler not found")]
     HandlerNotFound,
     #[error("could not connect")]
     CouldNotConnect,
+    #[error("connection to {addr} refused")]
+    ConnectionRefused { addr: std::net::SocketAddr },
+    #[error("handshake failed: {0}")]
+    HandshakeFailed(#[source] Box<VrpnError>),
+    #[error("no path or name given for the {0:?} server")]
+    MissingServerPath(crate::Scheme),
+    #[error("{0:?} is not supported here")]
+    UnsupportedScheme(crate::Scheme),
+    #[error("this requires the {0} feature")]
+    FeatureDisabled(&'static str),
+    #[error("no server set for the client to connect to")]
+    NoServerSet,
+    #[error("no device name in address {0}")]
+    MissingDeviceName(String),
+    #[error("already connected to server {0:?}")]
+    AlreadyConnected(Box<crate::ServerInfo>),
+    #[error("not a server, or already shut down")]
+    NotAServer,
+    #[error("memory server {0} already exists")]
+    MemoryServerExists(String),
+    #[error("remote type id {0} has no local mapping")]
+    UnmappedRemoteType(IdType),
+    #[error("remote sender id {0} has no local mapping")]
+    UnmappedRemoteSender(IdType),
     #[error("handler returned an error")]
     GenericErrorReturn,
     #[error("a non-system message was forwarded to Endpoint::handle_message_as_system()")]
@@ -39,16 +68,59 @@ pub enum VrpnError {
     EndpointClosed,
     #[error("{0}")]
     MessageSizeInvalid(MessageSizeInvalid),
+    #[error("message body of {0} bytes is too large")]
+    MessageTooLarge(usize),
+    #[error("message type id {0} is reserved for system messages")]
+    SystemMessageType(IdType),
+    #[error("invalid log file name: {0}")]
+    InvalidLogFileName(#[from] crate::data_types::log::LogFileNameError),
+    #[error("version mismatch: expected something compatible with {ours}, got {theirs}")]
+    VersionMismatch {
+        ours: crate::data_types::cookie::Version,
+        theirs: crate::data_types::cookie::Version,
+    },
+    #[error("not allowed by the compatibility profile: {0}")]
+    Incompatible(String),
     #[error("{0}")]
-    VersionMismatch(#[from] crate::data_types::cookie::VersionMismatch),
+    InvalidTransition(#[from] crate::connection_state::InvalidTransition),
     #[error("{0}")]
     UrlParseError(#[from] url::ParseError),
     #[error("{0}")]
     IoError(#[from] std::io::Error),
+    #[cfg(feature = "websocket")]
+    #[error("websocket error: {0}")]
+    WebSocket(Box<async_tungstenite::tungstenite::Error>),
+    #[cfg(feature = "tls")]
+    #[error("TLS error: {0}")]
+    Tls(Box<futures_rustls::rustls::Error>),
+    #[error("timed out {0}")]
+    Timeout(crate::timeouts::TimeoutKind),
+    #[error("internal channel closed")]
+    ChannelClosed,
+    #[error("config line {line}: {message}")]
+    ConfigLine { line: usize, message: String },
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("lock poisoned by a panic in another thread")]
+    LockPoisoned,
     #[error("{0}")]
     OtherMessage(String),
 }
 
+#[cfg(feature = "websocket")]
+impl From<async_tungstenite::tungstenite::Error> for VrpnError {
+    fn from(e: async_tungstenite::tungstenite::Error) -> Self {
+        VrpnError::WebSocket(Box::new(e))
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<futures_rustls::rustls::Error> for VrpnError {
+    fn from(e: futures_rustls::rustls::Error) -> Self {
+        VrpnError::Tls(Box::new(e))
+    }
+}
+
 impl MayContainSizeRequirement for VrpnError {
     fn try_get_size_requirement(self) -> Option<SizeRequirement> {
         match self {
@@ -102,15 +174,36 @@ impl VrpnError {
     }
 }
 
+impl From<crate::data_types::cookie::VersionMismatch> for VrpnError {
+    fn from(v: crate::data_types::cookie::VersionMismatch) -> VrpnError {
+        VrpnError::VersionMismatch {
+            ours: v.expected(),
+            theirs: v.actual(),
+        }
+    }
+}
+
+impl From<crate::compatibility::UnsupportedAlignment> for VrpnError {
+    fn from(e: crate::compatibility::UnsupportedAlignment) -> VrpnError {
+        VrpnError::OtherMessage(e.to_string())
+    }
+}
+
 impl<T> From<std::sync::PoisonError<T>> for VrpnError {
-    fn from(v: std::sync::PoisonError<T>) -> VrpnError {
-        VrpnError::OtherMessage(v.to_string())
+    fn from(_: std::sync::PoisonError<T>) -> VrpnError {
+        VrpnError::LockPoisoned
+    }
+}
+
+impl<T> From<futures::channel::mpsc::TrySendError<T>> for VrpnError {
+    fn from(_: futures::channel::mpsc::TrySendError<T>) -> VrpnError {
+        VrpnError::ChannelClosed
     }
 }
 
 impl From<MessageSizeInvalid> for VrpnError {
-    fn from(_: MessageSizeInvalid) -> Self {
-        todo!()
+    fn from(v: MessageSizeInvalid) -> Self {
+        VrpnError::MessageSizeInvalid(v)
     }
 }
 
@@ -0,0 +1,492 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Types related to the `vrpn_ForceDevice` device class, for haptics.
+//!
+//! Covers the reports a force device sends (force, surface contact point, errors),
+//! the force field request, and the constraint requests.
+//! Unlike the rest of VRPN, requests use 32-bit floats on the wire.
+
+use crate::{
+    buffer_unbuffer::{
+        buffer::{check_buffer_remaining, BufferResult, BufferTo},
+        unbuffer::{check_unbuffer_remaining, UnbufferFrom, UnbufferResult},
+        BufferUnbufferError, ConstantBufferSize,
+    },
+    data_types::{
+        id_types::{LocalId, SenderId},
+        message::TypedMessageBody,
+        name_types::StaticMessageTypeName,
+        ClassOfService, MessageTypeIdentifier, Quat, SenderName, TimeVal, Vec3,
+    },
+    handler::HandlerHandle,
+    Connection, Handler, Result, TypedHandler,
+};
+use bytes::{Buf, BufMut};
+use std::sync::Arc;
+
+/// A 3D vector as sent in force device requests.
+pub type Vec3f = [f32; 3];
+
+fn buffer_floats<T: BufMut>(buf: &mut T, values: &[f32]) -> BufferResult {
+    check_buffer_remaining(buf, values.len() * f32::constant_buffer_size())?;
+    for v in values {
+        v.buffer_to(buf)?;
+    }
+    Ok(())
+}
+
+fn unbuffer_vec3f<T: Buf>(buf: &mut T) -> UnbufferResult<Vec3f> {
+    check_unbuffer_remaining(buf, 3 * f32::constant_buffer_size())?;
+    Ok([
+        f32::unbuffer_from(buf)?,
+        f32::unbuffer_from(buf)?,
+        f32::unbuffer_from(buf)?,
+    ])
+}
+
+/// The force currently being applied by the device.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ForceReport {
+    pub force: Vec3,
+}
+
+impl TypedMessageBody for ForceReport {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(StaticMessageTypeName(b"vrpn_ForceDevice Force"));
+}
+
+impl ConstantBufferSize for ForceReport {
+    fn constant_buffer_size() -> usize {
+        Vec3::constant_buffer_size()
+    }
+}
+
+impl BufferTo for ForceReport {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        self.force.buffer_to(buf)
+    }
+}
+
+impl UnbufferFrom for ForceReport {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        Ok(ForceReport {
+            force: Vec3::unbuffer_from(buf)?,
+        })
+    }
+}
+
+/// The surface contact point: where the probe is held on the surface being touched.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScpReport {
+    pub pos: Vec3,
+    pub quat: Quat,
+}
+
+impl TypedMessageBody for ScpReport {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(StaticMessageTypeName(b"vrpn_ForceDevice SCP"));
+}
+
+impl ConstantBufferSize for ScpReport {
+    fn constant_buffer_size() -> usize {
+        Vec3::constant_buffer_size() + Quat::constant_buffer_size()
+    }
+}
+
+impl BufferTo for ScpReport {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        check_buffer_remaining(buf, Self::constant_buffer_size())?;
+        self.pos.buffer_to(buf)?;
+        self.quat.buffer_to(buf)?;
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for ScpReport {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        check_unbuffer_remaining(buf, Self::constant_buffer_size())?;
+        let pos = Vec3::unbuffer_from(buf)?;
+        let quat = Quat::unbuffer_from(buf)?;
+        Ok(ScpReport { pos, quat })
+    }
+}
+
+/// An error reported by the device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ForceError {
+    /// One of the `ForceError` constants, or a device-specific code.
+    pub code: i32,
+}
+
+impl ForceError {
+    pub const VALUE_OUT_OF_RANGE: i32 = 0;
+    pub const DUTY_CYCLE_ERROR: i32 = 1;
+    pub const FORCE_ERROR: i32 = 2;
+    pub const MISC_ERROR: i32 = 3;
+    pub const OK: i32 = 4;
+}
+
+impl TypedMessageBody for ForceError {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier = MessageTypeIdentifier::UserMessageName(
+        StaticMessageTypeName(b"vrpn_ForceDevice Force_Error"),
+    );
+}
+
+impl ConstantBufferSize for ForceError {
+    fn constant_buffer_size() -> usize {
+        i32::constant_buffer_size()
+    }
+}
+
+impl BufferTo for ForceError {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        self.code.buffer_to(buf)
+    }
+}
+
+impl UnbufferFrom for ForceError {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        Ok(ForceError {
+            code: i32::unbuffer_from(buf)?,
+        })
+    }
+}
+
+/// Request for a force that varies linearly with position, within a radius of an origin:
+/// `force + jacobian * (position - origin)`.
+///
+/// Sending an all-zero field turns it off.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ForceField {
+    pub origin: Vec3f,
+    pub force: Vec3f,
+    /// Row-major
+    pub jacobian: [Vec3f; 3],
+    pub radius: f32,
+}
+
+impl TypedMessageBody for ForceField {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier = MessageTypeIdentifier::UserMessageName(
+        StaticMessageTypeName(b"vrpn_ForceDevice Force_Field"),
+    );
+}
+
+impl ConstantBufferSize for ForceField {
+    fn constant_buffer_size() -> usize {
+        16 * f32::constant_buffer_size()
+    }
+}
+
+impl BufferTo for ForceField {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        check_buffer_remaining(buf, Self::constant_buffer_size())?;
+        buffer_floats(buf, &self.origin)?;
+        buffer_floats(buf, &self.force)?;
+        for row in &self.jacobian {
+            buffer_floats(buf, row)?;
+        }
+        self.radius.buffer_to(buf)
+    }
+}
+
+impl UnbufferFrom for ForceField {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        check_unbuffer_remaining(buf, Self::constant_buffer_size())?;
+        let origin = unbuffer_vec3f(buf)?;
+        let force = unbuffer_vec3f(buf)?;
+        let jacobian = [
+            unbuffer_vec3f(buf)?,
+            unbuffer_vec3f(buf)?,
+            unbuffer_vec3f(buf)?,
+        ];
+        let radius = f32::unbuffer_from(buf)?;
+        Ok(ForceField {
+            origin,
+            force,
+            jacobian,
+            radius,
+        })
+    }
+}
+
+/// Request to turn the constraint on or off.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConstraintEnable(pub bool);
+
+impl TypedMessageBody for ConstraintEnable {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier = MessageTypeIdentifier::UserMessageName(
+        StaticMessageTypeName(b"vrpn_ForceDevice constraint_enable"),
+    );
+}
+
+impl ConstantBufferSize for ConstraintEnable {
+    fn constant_buffer_size() -> usize {
+        i32::constant_buffer_size()
+    }
+}
+
+impl BufferTo for ConstraintEnable {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        i32::from(self.0).buffer_to(buf)
+    }
+}
+
+impl UnbufferFrom for ConstraintEnable {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        Ok(ConstraintEnable(i32::unbuffer_from(buf)? != 0))
+    }
+}
+
+/// The shape the probe is constrained to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConstraintGeometry {
+    None = 0,
+    Point = 1,
+    Line = 2,
+    Plane = 3,
+}
+
+/// Request to change the shape the probe is constrained to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConstraintMode(pub ConstraintGeometry);
+
+impl TypedMessageBody for ConstraintMode {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier = MessageTypeIdentifier::UserMessageName(
+        StaticMessageTypeName(b"vrpn_ForceDevice constraint_mode"),
+    );
+}
+
+impl ConstantBufferSize for ConstraintMode {
+    fn constant_buffer_size() -> usize {
+        i32::constant_buffer_size()
+    }
+}
+
+impl BufferTo for ConstraintMode {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        (self.0 as i32).buffer_to(buf)
+    }
+}
+
+impl UnbufferFrom for ConstraintMode {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        let geometry = match i32::unbuffer_from(buf)? {
+            0 => ConstraintGeometry::None,
+            1 => ConstraintGeometry::Point,
+            2 => ConstraintGeometry::Line,
+            3 => ConstraintGeometry::Plane,
+            v => {
+                return Err(BufferUnbufferError::ParseError {
+                    parsing_kind: "constraint mode".to_string(),
+                    s: v.to_string(),
+                })
+            }
+        };
+        Ok(ConstraintMode(geometry))
+    }
+}
+
+/// Defines a request message type carrying one 3D vector of constraint geometry.
+macro_rules! constraint_vector {
+    ($(#[$meta:meta])* $name:ident, $message_name:expr) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, Debug, Default, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub struct $name(pub Vec3f);
+
+        impl TypedMessageBody for $name {
+            const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+                MessageTypeIdentifier::UserMessageName(StaticMessageTypeName($message_name));
+        }
+
+        impl ConstantBufferSize for $name {
+            fn constant_buffer_size() -> usize {
+                3 * f32::constant_buffer_size()
+            }
+        }
+
+        impl BufferTo for $name {
+            fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+                buffer_floats(buf, &self.0)
+            }
+        }
+
+        impl UnbufferFrom for $name {
+            fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+                Ok($name(unbuffer_vec3f(buf)?))
+            }
+        }
+    };
+}
+
+constraint_vector!(
+    /// Request to set the point of a point constraint.
+    ConstraintPoint,
+    b"vrpn_ForceDevice constraint_point"
+);
+constraint_vector!(
+    /// Request to set a point on the line of a line constraint.
+    ConstraintLinePoint,
+    b"vrpn_ForceDevice constraint_linepoint"
+);
+constraint_vector!(
+    /// Request to set the direction of the line of a line constraint.
+    ConstraintLineDirection,
+    b"vrpn_ForceDevice constraint_linedir"
+);
+constraint_vector!(
+    /// Request to set a point on the plane of a plane constraint.
+    ConstraintPlanePoint,
+    b"vrpn_ForceDevice constraint_plpoint"
+);
+constraint_vector!(
+    /// Request to set the normal of the plane of a plane constraint.
+    ConstraintPlaneNormal,
+    b"vrpn_ForceDevice constraint_plnorm"
+);
+
+/// Request to set the spring constant pulling the probe to the constraint.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConstraintKSpring(pub f32);
+
+impl TypedMessageBody for ConstraintKSpring {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier = MessageTypeIdentifier::UserMessageName(
+        StaticMessageTypeName(b"vrpn_ForceDevice constraint_KSpring"),
+    );
+}
+
+impl ConstantBufferSize for ConstraintKSpring {
+    fn constant_buffer_size() -> usize {
+        f32::constant_buffer_size()
+    }
+}
+
+impl BufferTo for ConstraintKSpring {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        self.0.buffer_to(buf)
+    }
+}
+
+impl UnbufferFrom for ConstraintKSpring {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        Ok(ConstraintKSpring(f32::unbuffer_from(buf)?))
+    }
+}
+
+/// Client side of a force device.
+#[derive(Debug)]
+pub struct ForceDeviceRemote<C: Connection> {
+    connection: Arc<C>,
+    sender: LocalId<SenderId>,
+}
+
+impl<C: Connection> ForceDeviceRemote<C> {
+    pub fn new(connection: Arc<C>, name: impl Into<SenderName>) -> Result<ForceDeviceRemote<C>> {
+        let sender = connection.register_sender(name.into())?;
+        Ok(ForceDeviceRemote { connection, sender })
+    }
+
+    /// The local sender ID of this force device.
+    pub fn sender(&self) -> LocalId<SenderId> {
+        self.sender
+    }
+
+    /// Add a handler for the force, surface contact point, or error reports of this device.
+    pub fn add_handler<H>(&self, handler: Box<H>) -> Result<HandlerHandle>
+    where
+        H: TypedHandler + Handler + 'static,
+    {
+        self.connection
+            .add_typed_handler(handler, Some(self.sender))
+    }
+
+    /// Send a request to the device.
+    pub fn send<T>(&self, time: Option<TimeVal>, request: T) -> Result<()>
+    where
+        T: TypedMessageBody + BufferTo,
+    {
+        self.connection
+            .pack_message_body(time, self.sender, request, ClassOfService::RELIABLE)
+    }
+
+    /// Apply a force field.
+    pub fn send_force_field(&self, field: ForceField) -> Result<()> {
+        self.send(None, field)
+    }
+
+    /// Turn off the force field.
+    pub fn stop_force_field(&self) -> Result<()> {
+        self.send(None, ForceField::default())
+    }
+
+    /// Turn the constraint on or off.
+    pub fn enable_constraint(&self, enable: bool) -> Result<()> {
+        self.send(None, ConstraintEnable(enable))
+    }
+
+    /// Change the shape the probe is constrained to.
+    pub fn set_constraint_mode(&self, geometry: ConstraintGeometry) -> Result<()> {
+        self.send(None, ConstraintMode(geometry))
+    }
+
+    /// Set the spring constant pulling the probe to the constraint.
+    pub fn set_constraint_kspring(&self, k: f32) -> Result<()> {
+        self.send(None, ConstraintKSpring(k))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_unbuffer::BytesMutExtras;
+    use bytes::BytesMut;
+
+    #[test]
+    fn force_field_wire_format() {
+        let field = ForceField {
+            origin: [1.0, 0.0, 0.0],
+            force: [0.0, 0.0, 0.5],
+            jacobian: [[0.0; 3], [0.0; 3], [0.0; 3]],
+            radius: 2.0,
+        };
+        let buf = BytesMut::allocate_and_buffer(field).unwrap().freeze();
+        assert_eq!(buf.len(), 64);
+        // All big-endian float32, radius last
+        assert_eq!(&buf[..4], &hex!("3f800000")[..]);
+        assert_eq!(&buf[20..24], &hex!("3f000000")[..]);
+        assert_eq!(&buf[60..], &hex!("40000000")[..]);
+        assert_eq!(ForceField::unbuffer_from(&mut buf.clone()).unwrap(), field);
+    }
+
+    #[test]
+    fn constraints() {
+        let mut buf = BytesMut::allocate_and_buffer(ConstraintMode(ConstraintGeometry::Plane))
+            .unwrap()
+            .freeze();
+        assert_eq!(&buf[..], &hex!("00000003")[..]);
+        assert_eq!(
+            ConstraintMode::unbuffer_from(&mut buf).unwrap(),
+            ConstraintMode(ConstraintGeometry::Plane)
+        );
+        let mut buf = BytesMut::allocate_and_buffer(7_i32).unwrap().freeze();
+        assert!(ConstraintMode::unbuffer_from(&mut buf).is_err());
+
+        let normal = ConstraintPlaneNormal([0.0, 1.0, 0.0]);
+        let mut buf = BytesMut::allocate_and_buffer(normal).unwrap().freeze();
+        assert_eq!(buf.len(), 12);
+        assert_eq!(
+            ConstraintPlaneNormal::unbuffer_from(&mut buf).unwrap(),
+            normal
+        );
+    }
+}
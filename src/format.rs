@@ -0,0 +1,251 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Human-readable messages, with IDs shown alongside their names, for logs and the sniffer.
+
+use crate::{
+    capture::{decode_known_body, system_type_name},
+    data_types::{
+        id_types::{LocalId, RemoteId, SenderId},
+        name_types::QuotedName,
+        GenericMessage, MessageTypeId,
+    },
+    translation_table::TranslationTables,
+    type_dispatcher::TypeDispatcher,
+};
+use bytes::Bytes;
+use std::fmt;
+
+/// Somewhere to look up the names of the IDs in message headers.
+pub trait NameLookup {
+    /// The name of a sender ID, if known.
+    fn sender_name(&self, id: SenderId) -> Option<Bytes>;
+
+    /// The name of a message type ID, if known.
+    fn type_name(&self, id: MessageTypeId) -> Option<Bytes>;
+}
+
+/// Names of local IDs, as in messages being dispatched.
+impl NameLookup for TypeDispatcher {
+    fn sender_name(&self, id: SenderId) -> Option<Bytes> {
+        TypeDispatcher::sender_name(self, LocalId(id))
+    }
+
+    fn type_name(&self, id: MessageTypeId) -> Option<Bytes> {
+        TypeDispatcher::type_name(self, LocalId(id))
+    }
+}
+
+/// Names of a peer's IDs, as in messages on the wire.
+impl NameLookup for TranslationTables {
+    fn sender_name(&self, id: SenderId) -> Option<Bytes> {
+        self.senders()
+            .find_by_remote_id(RemoteId(id))
+            .map(|mapping| mapping.name)
+    }
+
+    fn type_name(&self, id: MessageTypeId) -> Option<Bytes> {
+        self.types()
+            .find_by_remote_id(RemoteId(id))
+            .map(|mapping| mapping.name)
+    }
+}
+
+/// An ID displayed with its name, if known, like `SenderId(3) "Tracker0"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Named<I> {
+    pub id: I,
+    pub name: Option<Bytes>,
+}
+
+impl<I: fmt::Display> fmt::Display for Named<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " {}", QuotedName(name))?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats messages, looking up the names of their IDs.
+#[derive(Clone, Copy)]
+pub struct MessageFormatter<'a> {
+    names: &'a dyn NameLookup,
+    decode_bodies: bool,
+}
+
+impl fmt::Debug for MessageFormatter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageFormatter")
+            .field("decode_bodies", &self.decode_bodies)
+            .finish()
+    }
+}
+
+impl<'a> MessageFormatter<'a> {
+    /// Look names up in `names`: a `TypeDispatcher` for the local IDs of received messages,
+    /// or `TranslationTables` for the IDs a peer sends.
+    pub fn new(names: &'a dyn NameLookup) -> MessageFormatter<'a> {
+        MessageFormatter {
+            names,
+            decode_bodies: false,
+        }
+    }
+
+    /// Show the bodies of known message types decoded, as well as in hex.
+    pub fn decode_bodies(self, decode_bodies: bool) -> MessageFormatter<'a> {
+        MessageFormatter {
+            decode_bodies,
+            ..self
+        }
+    }
+
+    /// A sender ID with its name.
+    pub fn sender(&self, id: SenderId) -> Named<SenderId> {
+        Named {
+            id,
+            name: self.names.sender_name(id),
+        }
+    }
+
+    /// A message type ID with its name, including those of system message types.
+    pub fn message_type(&self, id: MessageTypeId) -> Named<MessageTypeId> {
+        Named {
+            id,
+            name: self
+                .names
+                .type_name(id)
+                .or_else(|| system_type_name(id).map(Bytes::from_static)),
+        }
+    }
+
+    /// Format a message: a line with its header and names, then its body.
+    pub fn format(&self, msg: &GenericMessage) -> String {
+        let header = &msg.header;
+        let message_type = self.message_type(header.message_type);
+        let mut out = format!(
+            "{:#} {} {}: {} bytes",
+            header.time,
+            self.sender(header.sender),
+            message_type,
+            msg.body.as_bytes().len()
+        );
+        if let Some(decoded) = message_type
+            .name
+            .filter(|_| self.decode_bodies)
+            .and_then(|name| decode_known_body(&name, msg))
+        {
+            out.push('\n');
+            out.push_str(&decoded);
+        }
+        if !msg.body.as_bytes().is_empty() {
+            out.push('\n');
+            out.push_str(&msg.body.to_string());
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        button::ButtonChange,
+        data_types::{
+            constants, GenericBody, Message, MessageHeader, SenderName, StaticMessageTypeName,
+            StaticSenderName, TimeVal, TypedMessage,
+        },
+        translation_table::TranslationTable,
+    };
+    use std::convert::TryFrom;
+
+    #[test]
+    fn local_names() {
+        let mut dispatcher = TypeDispatcher::new();
+        let sender = dispatcher
+            .register_sender(StaticSenderName(b"Button0"))
+            .unwrap()
+            .into_inner();
+        let message_type = dispatcher
+            .register_type(StaticMessageTypeName(b"vrpn_Button Change"))
+            .unwrap()
+            .into_inner();
+        let msg = GenericMessage::try_from(TypedMessage::new(
+            Some(TimeVal::from_micros(1_500_000)),
+            message_type.0,
+            sender.0,
+            ButtonChange {
+                button: 4,
+                pressed: true,
+            },
+        ))
+        .unwrap();
+        let formatter = MessageFormatter::new(&dispatcher);
+        assert_eq!(
+            formatter.sender(sender.0).to_string(),
+            format!("SenderId({}) \"Button0\"", sender.0 .0)
+        );
+        assert_eq!(formatter.sender(SenderId(100)).to_string(), "SenderId(100)");
+        let text = formatter.format(&msg);
+        assert!(
+            text.starts_with("1970-01-01T00:00:01.500000Z SenderId("),
+            "{}",
+            text
+        );
+        assert!(text.contains("\"vrpn_Button Change\": 8 bytes"), "{}", text);
+        assert!(!text.contains("button: 4"), "{}", text);
+        let text = formatter.decode_bodies(true).format(&msg);
+        assert!(text.contains("button: 4"), "{}", text);
+        assert!(text.ends_with('|'), "{}", text);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            format!("{:?}", StaticSenderName(b"Tracker0")),
+            "StaticSenderName(\"Tracker0\")"
+        );
+        assert_eq!(
+            SenderName::from(&b"a\"\n\xff"[..]).to_string(),
+            "\"a\\\"\\n\\xff\""
+        );
+        assert_eq!(LocalId(SenderId(2)).to_string(), "local SenderId(2)");
+    }
+
+    #[test]
+    fn remote_names() {
+        let mut tables = TranslationTables::new();
+        AsMut::<TranslationTable<SenderId>>::as_mut(&mut tables)
+            .add_remote_entry(
+                Bytes::from_static(b"Tracker0"),
+                RemoteId(SenderId(3)),
+                LocalId(SenderId(7)),
+            )
+            .unwrap();
+        let formatter = MessageFormatter::new(&tables);
+        assert_eq!(
+            formatter.sender(SenderId(3)).to_string(),
+            "SenderId(3) \"Tracker0\""
+        );
+        assert_eq!(
+            formatter
+                .message_type(constants::SENDER_DESCRIPTION)
+                .to_string(),
+            format!(
+                "MessageTypeId({}) \"SENDER_DESCRIPTION\"",
+                constants::SENDER_DESCRIPTION.0
+            )
+        );
+        let msg = GenericMessage::from_header_and_body(
+            MessageHeader::new(Some(TimeVal::default()), MessageTypeId(3), SenderId(3)),
+            GenericBody::new(Bytes::from_static(&[0xde, 0xad])),
+        );
+        assert_eq!(
+            formatter.format(&msg),
+            "1970-01-01T00:00:00.000000Z SenderId(3) \"Tracker0\" MessageTypeId(3): 2 bytes\n\
+             0000:  de ad                                            |..|"
+        );
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Passing messages received on one connection on to another, like `vrpn_StreamForwarder`:
+//! e.g. to bridge networks, or to record a stream (with a log on the destination)
+//! while passing it through.
+//!
+//! The source is typically a client connection, and the destination a server connection.
+//! They must be different connections.
+
+use crate::{
+    data_types::{
+        id_types::{LocalId, MessageTypeId, SenderId},
+        ClassOfService, GenericMessage, MessageTypeName, SenderName,
+    },
+    handler::{HandlerCode, HandlerHandle},
+    Connection, Handler, Result,
+};
+use std::sync::Arc;
+
+/// Re-sends one stream (sender and message type) on the destination connection.
+#[derive(Debug)]
+struct ForwardHandler<D: Connection> {
+    destination: Arc<D>,
+    message_type: LocalId<MessageTypeId>,
+    sender: LocalId<SenderId>,
+    class: ClassOfService,
+}
+
+impl<D: Connection + Send + Sync> Handler for ForwardHandler<D> {
+    fn handle(&mut self, msg: &GenericMessage) -> Result<HandlerCode> {
+        self.destination.send_generic(
+            msg.header.time,
+            self.message_type,
+            self.sender,
+            msg.body.as_bytes().clone(),
+            self.class,
+        )?;
+        Ok(HandlerCode::ContinueProcessing)
+    }
+}
+
+/// Forwards selected streams from a source connection to a destination connection,
+/// optionally under different sender names.
+///
+/// Messages keep their timestamps and bodies.
+#[derive(Debug)]
+pub struct Forwarder<S: Connection, D: Connection> {
+    source: Arc<S>,
+    destination: Arc<D>,
+    handlers: Vec<HandlerHandle>,
+}
+
+impl<S: Connection, D: Connection + Send + Sync + 'static> Forwarder<S, D> {
+    pub fn new(source: Arc<S>, destination: Arc<D>) -> Forwarder<S, D> {
+        Forwarder {
+            source,
+            destination,
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Forward messages of one type from a source sender, sending them from `destination_sender`.
+    ///
+    /// The message type keeps its name.
+    pub fn forward(
+        &mut self,
+        source_sender: impl Into<SenderName>,
+        message_type: impl Into<MessageTypeName>,
+        destination_sender: impl Into<SenderName>,
+        class: ClassOfService,
+    ) -> Result<()> {
+        let message_type = message_type.into();
+        let handler = ForwardHandler {
+            destination: Arc::clone(&self.destination),
+            message_type: self.destination.register_type(message_type.clone())?,
+            sender: self
+                .destination
+                .register_sender(destination_sender.into())?,
+            class,
+        };
+        let handle = self.source.add_handler(
+            Box::new(handler),
+            Some(self.source.register_type(message_type)?),
+            Some(self.source.register_sender(source_sender.into())?),
+        )?;
+        self.handlers.push(handle);
+        Ok(())
+    }
+
+    /// Stop forwarding all streams.
+    pub fn stop(&mut self) -> Result<()> {
+        for handle in self.handlers.drain(..) {
+            self.source.remove_handler(handle)?;
+        }
+        Ok(())
+    }
+
+    /// The connection messages are forwarded from.
+    pub fn source(&self) -> &Arc<S> {
+        &self.source
+    }
+
+    /// The connection messages are forwarded to.
+    pub fn destination(&self) -> &Arc<D> {
+        &self.destination
+    }
+}
+
+#[cfg(all(test, feature = "async-std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        codec::MessageCodec,
+        data_types::{
+            GenericBody, Message, MessageHeader, Microseconds, Seconds, StaticMessageTypeName,
+            StaticSenderName, TimeVal,
+        },
+        vrpn_async::{connection_ip::ConnectionIp, endpoint_ip::EndpointIp},
+        vrpn_async_std::AsyncStd,
+        CompatibilityProfile,
+    };
+    use bytes::{Bytes, BytesMut};
+    use std::{io::Read, task::Poll, time::Duration};
+
+    #[test]
+    fn forwards_with_new_sender_name() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server_side, _) = listener.accept().unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+
+        let source = ConnectionIp::new_server(None, None).unwrap();
+        let destination = ConnectionIp::new_server(None, None).unwrap();
+        destination.endpoints().lock().push(Some(EndpointIp::new(
+            Arc::new(AsyncStd),
+            server_side.into(),
+            None,
+            CompatibilityProfile::default(),
+        )));
+        let mut forwarder = Forwarder::new(Arc::clone(&source), Arc::clone(&destination));
+        forwarder
+            .forward(
+                StaticSenderName(b"Tracker0"),
+                StaticMessageTypeName(b"Custom"),
+                StaticSenderName(b"Relayed0"),
+                ClassOfService::RELIABLE,
+            )
+            .unwrap();
+
+        let time = TimeVal::new(Seconds(12), Microseconds(34));
+        let msg = GenericMessage::from_header_and_body(
+            MessageHeader::new(
+                Some(time),
+                source
+                    .register_type(StaticMessageTypeName(b"Custom"))
+                    .unwrap()
+                    .0,
+                source
+                    .register_sender(StaticSenderName(b"Tracker0"))
+                    .unwrap()
+                    .0,
+            ),
+            GenericBody::new(Bytes::from_static(b"hello")),
+        );
+        source.dispatcher().read().call(&msg).unwrap();
+
+        let relayed = destination
+            .register_sender(StaticSenderName(b"Relayed0"))
+            .unwrap()
+            .0;
+        let mut codec = MessageCodec::new();
+        let mut received = BytesMut::new();
+        let mut buf = [0u8; 4096];
+        for _ in 0..50 {
+            async_std::task::block_on(futures::future::poll_fn(|cx| {
+                let _ = destination.poll_endpoints(cx);
+                Poll::Ready(())
+            }));
+            if let Ok(n) = client.read(&mut buf) {
+                received.extend_from_slice(&buf[..n]);
+            }
+            while let Ok(Some(msg)) = codec.decode_from(&mut received) {
+                let msg = msg.into_inner();
+                if msg.body.as_bytes() == &b"hello"[..] {
+                    assert_eq!(msg.header.time, time);
+                    assert_eq!(msg.header.sender, relayed);
+                    forwarder.stop().unwrap();
+                    return;
+                }
+            }
+        }
+        panic!("forwarded message not received");
+    }
+}
@@ -2,13 +2,17 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
-pub use crate::type_dispatcher::HandlerHandle;
+pub use crate::type_dispatcher::{HandlerHandle, ScopedHandler};
 use crate::{
     buffer_unbuffer::{EmptyMessage, UnbufferFrom},
-    data_types::{GenericMessage, MessageHeader, TypedMessage, TypedMessageBody},
-    Result,
+    data_types::{
+        id_types::{MessageTypeId, SenderId},
+        GenericMessage, MessageHeader, TypedMessage, TypedMessageBody,
+    },
+    Result, VrpnError,
 };
-use std::{convert::TryFrom, fmt};
+use futures::future::BoxFuture;
+use std::{collections::VecDeque, convert::TryFrom, fmt, time::Duration};
 
 /// Return from a Handler (or its related traits),
 /// indicating whether the handler that just executed should be kept around for the future.
@@ -66,3 +70,106 @@ where
         self.handle_typed_bodyless(&msg.header)
     }
 }
+
+/// A trait implemented by structs that handle generic messages asynchronously,
+/// e.g. forwarding them or writing them to a database.
+///
+/// `handle` is called during dispatch, like `Handler::handle`, and the future it returns
+/// is then run while the connection is polled, alongside processing further messages.
+/// So, the future must own what it needs, such as a clone of the message.
+///
+/// Errors from the future are handled according to the `HandlerErrorPolicy`.
+pub trait AsyncHandler: Send + Sync {
+    fn handle(&mut self, msg: &GenericMessage) -> BoxFuture<'static, Result<()>>;
+}
+
+/// What the dispatcher does when a handler returns an error.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum HandlerErrorPolicy {
+    /// Log and record the error, then keep calling the other handlers.
+    #[default]
+    LogAndContinue,
+    /// Stop dispatching the message and return the error,
+    /// which usually ends polling the connection.
+    Abort,
+}
+
+/// A handler error recorded under `HandlerErrorPolicy::LogAndContinue`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerError {
+    pub message_type: MessageTypeId,
+    pub sender: SenderId,
+    pub error: String,
+}
+
+/// The handler errors recorded since last taken.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HandlerErrorReport {
+    /// All errors, including those no longer in `recent`.
+    pub count: u64,
+    /// The most recent errors, oldest first.
+    pub recent: VecDeque<HandlerError>,
+}
+
+impl HandlerErrorReport {
+    /// How many errors are kept in `recent`.
+    pub const MAX_RECENT: usize = 32;
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub(crate) fn record(&mut self, header: &MessageHeader, error: &VrpnError) {
+        if self.recent.len() == Self::MAX_RECENT {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(HandlerError {
+            message_type: header.message_type,
+            sender: header.sender,
+            error: error.to_string(),
+        });
+        self.count += 1;
+    }
+}
+
+/// How long a handler has taken to handle messages, from `Connection::handler_timing`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HandlerTiming {
+    /// Messages handled: those passing its sender filter.
+    pub calls: u64,
+    pub total: Duration,
+    pub max: Duration,
+    /// Calls that took longer than the handler budget.
+    pub over_budget: u64,
+}
+
+impl HandlerTiming {
+    /// The average time per message, if any were handled.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.calls == 0 {
+            return None;
+        }
+        Some(Duration::from_nanos(
+            (self.total.as_nanos() / u128::from(self.calls)) as u64,
+        ))
+    }
+
+    pub(crate) fn record(&mut self, elapsed: Duration, over_budget: bool) {
+        self.calls += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+        if over_budget {
+            self.over_budget += 1;
+        }
+    }
+
+    /// Combine the timings of one handler added for several message types.
+    pub(crate) fn merge(self, other: HandlerTiming) -> HandlerTiming {
+        HandlerTiming {
+            calls: self.calls + other.calls,
+            total: self.total + other.total,
+            max: self.max.max(other.max),
+            over_budget: self.over_budget + other.over_budget,
+        }
+    }
+}
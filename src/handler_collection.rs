@@ -0,0 +1,350 @@
+// Copyright 2018, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! The handlers a `TypeDispatcher` calls for one message type (or for any type),
+//! and the policy they are called under: what to do with their errors,
+//! and how long they may take before that is worth a warning.
+
+use crate::{
+    data_types::{
+        id_types::*,
+        message::{GenericMessage, MessageHeader},
+    },
+    handler::{Handler, HandlerCode, HandlerErrorPolicy, HandlerErrorReport, HandlerTiming},
+    sync::{Mutex, MutexGuard},
+    Result, VrpnError,
+};
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+pub(crate) type HandlerHandleInnerType = IdType;
+
+/// Identifies a handler within its `CallbackCollection`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct HandlerHandleInner(pub(crate) HandlerHandleInnerType);
+
+/// What the dispatcher does with handler errors, and how long handlers may take.
+#[derive(Debug, Default)]
+pub(crate) struct HandlerPolicy {
+    pub(crate) on_error: HandlerErrorPolicy,
+    /// Calls taking longer than this are warned about.
+    pub(crate) budget: Option<Duration>,
+    errors: Mutex<HandlerErrorReport>,
+}
+
+impl HandlerPolicy {
+    /// Handle an error from a handler (`what`) of a message:
+    /// logged and recorded under `HandlerErrorPolicy::LogAndContinue`, returned otherwise.
+    pub(crate) fn handler_failed(
+        &self,
+        what: &str,
+        header: &MessageHeader,
+        error: VrpnError,
+    ) -> Result<()> {
+        match self.on_error {
+            HandlerErrorPolicy::LogAndContinue => {
+                warn!(
+                    "{} for message type {} failed: {}",
+                    what,
+                    header.message_type.get(),
+                    error
+                );
+                self.errors.lock().record(header, &error);
+                Ok(())
+            }
+            HandlerErrorPolicy::Abort => Err(error),
+        }
+    }
+
+    /// The handler errors recorded since last taken.
+    pub(crate) fn errors(&self) -> MutexGuard<'_, HandlerErrorReport> {
+        self.errors.lock()
+    }
+
+    /// Take the handler errors recorded, starting a new report.
+    pub(crate) fn take_errors(&self) -> HandlerErrorReport {
+        std::mem::take(&mut *self.errors.lock())
+    }
+}
+
+/// Type storing a boxed callback function, an optional sender ID filter,
+/// and the unique-per-CallbackCollection handle that can be used to unregister a handler.
+struct MsgCallbackEntry {
+    handle: HandlerHandleInner,
+    pub handler: Box<dyn Handler + Send>,
+    pub sender_filter: Option<LocalId<SenderId>>,
+    timing: HandlerTiming,
+}
+
+impl fmt::Debug for MsgCallbackEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MsgCallbackEntry")
+            .field("handle", &self.handle)
+            .field("sender_filter", &self.sender_filter)
+            .field("timing", &self.timing)
+            .finish()
+    }
+}
+
+impl MsgCallbackEntry {
+    pub fn new(
+        handle: HandlerHandleInner,
+        handler: Box<dyn Handler + Send>,
+        sender_filter: Option<LocalId<SenderId>>,
+    ) -> MsgCallbackEntry {
+        MsgCallbackEntry {
+            handle,
+            handler,
+            sender_filter,
+            timing: HandlerTiming::default(),
+        }
+    }
+
+    /// Invokes the callback with the given msg, if the sender filter (if not None) matches,
+    /// timing it against the budget.
+    ///
+    /// Calls over budget are only logged as their count reaches each power of two,
+    /// so a handler that is always slow cannot flood the log.
+    pub fn call(&mut self, msg: &GenericMessage, budget: Option<Duration>) -> Result<HandlerCode> {
+        if !id_filter_matches(self.sender_filter, LocalId(msg.header.sender)) {
+            return Ok(HandlerCode::ContinueProcessing);
+        }
+        let start = Instant::now();
+        let result = self.handler.handle(msg);
+        let elapsed = start.elapsed();
+        let over = budget.filter(|budget| elapsed > *budget);
+        self.timing.record(elapsed, over.is_some());
+        if let Some(budget) = over {
+            if self.timing.over_budget.is_power_of_two() {
+                warn!(
+                    "Handler for message type {} took {:?}, over the budget of {:?} ({} times)",
+                    msg.header.message_type.get(),
+                    elapsed,
+                    budget,
+                    self.timing.over_budget
+                );
+            }
+        }
+        result
+    }
+}
+
+/// One handler added for several message types, `None` once it has asked to be removed.
+#[derive(Clone)]
+pub(crate) struct SharedHandlerEntry(pub(crate) Arc<Mutex<Option<Box<dyn Handler + Send>>>>);
+
+impl Handler for SharedHandlerEntry {
+    fn handle(&mut self, msg: &GenericMessage) -> Result<HandlerCode> {
+        let mut shared = self.0.lock();
+        let handler = match shared.as_mut() {
+            Some(handler) => handler,
+            None => return Ok(HandlerCode::RemoveThisHandler),
+        };
+        let code = handler.handle(msg)?;
+        if code == HandlerCode::RemoveThisHandler {
+            *shared = None;
+        }
+        Ok(code)
+    }
+}
+
+/// Stores a collection of callbacks, associated with either a message type,
+/// or as a "global" handler mapping called for all message types.
+#[derive(Debug)]
+pub(crate) struct CallbackCollection {
+    callbacks: Vec<Option<MsgCallbackEntry>>,
+    next_handle: HandlerHandleInnerType,
+}
+impl Default for CallbackCollection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CallbackCollection {
+    /// Create CallbackCollection instance
+    pub fn new() -> CallbackCollection {
+        CallbackCollection {
+            callbacks: Vec::new(),
+            next_handle: 0,
+        }
+    }
+
+    /// The number of callbacks, counting against the handler limit.
+    pub(crate) fn len(&self) -> usize {
+        self.callbacks.len()
+    }
+
+    /// Add a callback with optional sender ID filter
+    pub(crate) fn add(
+        &mut self,
+        handler: Box<dyn Handler + Send>,
+        sender: Option<LocalId<SenderId>>,
+    ) -> Result<HandlerHandleInner> {
+        let handle = HandlerHandleInner(self.next_handle);
+        self.callbacks
+            .push(Some(MsgCallbackEntry::new(handle, handler, sender)));
+        self.next_handle += 1;
+        Ok(handle)
+    }
+
+    /// Remove a callback
+    pub(crate) fn remove(&mut self, handle: HandlerHandleInner) -> Result<()> {
+        let index = self
+            .callbacks
+            .iter()
+            .position(|x| {
+                x.as_ref()
+                    .map(|handler| handler.handle == handle)
+                    .unwrap_or(false)
+            })
+            .ok_or(VrpnError::HandlerNotFound)?;
+        self.callbacks.remove(index);
+        Ok(())
+    }
+
+    /// How long a callback has taken, if it is still here.
+    pub(crate) fn timing(&self, handle: HandlerHandleInner) -> Option<HandlerTiming> {
+        self.callbacks
+            .iter()
+            .flatten()
+            .find(|entry| entry.handle == handle)
+            .map(|entry| entry.timing)
+    }
+
+    /// Call all callbacks (subject to sender filters) and remove the callbacks who ask for it.
+    ///
+    /// Handler errors are handled according to the policy.
+    /// Each call is timed, and warned about if it takes longer than the policy's budget.
+    pub(crate) fn call(&mut self, msg: &GenericMessage, policy: &HandlerPolicy) -> Result<()> {
+        for entry in &mut self.callbacks.iter_mut() {
+            if let Some(unwrapped_entry) = entry {
+                match unwrapped_entry.call(msg, policy.budget) {
+                    Ok(HandlerCode::RemoveThisHandler) => {
+                        entry.take();
+                    }
+                    Ok(HandlerCode::ContinueProcessing) => {}
+                    Err(e) => policy.handler_failed("Handler", &msg.header, e)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::{
+        message::{GenericBody, Message},
+        TimeVal,
+    };
+
+    #[derive(Debug, Clone)]
+    struct SetTo {
+        val: Arc<Mutex<i8>>,
+        to: i8,
+    }
+    impl Handler for SetTo {
+        fn handle(&mut self, _msg: &GenericMessage) -> Result<HandlerCode> {
+            *self.val.lock() = self.to;
+            Ok(HandlerCode::ContinueProcessing)
+        }
+    }
+
+    struct Fail;
+    impl Handler for Fail {
+        fn handle(&mut self, _msg: &GenericMessage) -> Result<HandlerCode> {
+            Err(VrpnError::GenericErrorReturn)
+        }
+    }
+
+    fn message() -> GenericMessage {
+        GenericMessage::from_header_and_body(
+            MessageHeader::new(
+                Some(TimeVal::get_time_of_day()),
+                MessageTypeId(0),
+                SenderId(0),
+            ),
+            GenericBody::default(),
+        )
+    }
+
+    #[test]
+    fn callback_collection() {
+        let val: Arc<Mutex<i8>> = Arc::new(Mutex::new(5));
+        let sample_callback = SetTo {
+            val: Arc::clone(&val),
+            to: 10,
+        };
+        let sample_callback2 = SetTo {
+            val: Arc::clone(&val),
+            to: 15,
+        };
+
+        let mut collection = CallbackCollection::new();
+        let policy = HandlerPolicy::default();
+        let handler = collection
+            .add(Box::new(sample_callback.clone()), None)
+            .unwrap();
+        let msg = message();
+        collection.call(&msg, &policy).unwrap();
+        assert_eq!(*val.lock(), 10);
+
+        collection
+            .remove(handler)
+            .expect("Can't remove added callback");
+        // No callbacks should fire now.
+        *val.lock() = 5;
+        collection.call(&msg, &policy).unwrap();
+        assert_eq!(*val.lock(), 5);
+
+        let _ = collection
+            .add(Box::new(sample_callback2), Some(LocalId(SenderId(0))))
+            .unwrap();
+        *val.lock() = 5;
+        collection.call(&msg, &policy).unwrap();
+        assert_eq!(*val.lock(), 15);
+
+        // Check that later-registered callbacks get run later
+        let _ = collection.add(Box::new(sample_callback), None).unwrap();
+        *val.lock() = 5;
+        collection.call(&msg, &policy).unwrap();
+        assert_eq!(*val.lock(), 10);
+
+        // This shouldn't trigger callback 2
+        let mut msg2 = msg.clone();
+        msg2.header.sender = SenderId(1);
+        *val.lock() = 5;
+        collection.call(&msg2, &policy).unwrap();
+        assert_eq!(*val.lock(), 10);
+    }
+
+    #[test]
+    fn policy() {
+        let mut collection = CallbackCollection::new();
+        collection.add(Box::new(Fail), None).unwrap();
+        let msg = message();
+
+        let policy = HandlerPolicy::default();
+        collection.call(&msg, &policy).unwrap();
+        collection.call(&msg, &policy).unwrap();
+        assert_eq!(policy.errors().count, 2);
+        assert_eq!(policy.take_errors().recent.len(), 2);
+        assert!(policy.errors().is_empty());
+
+        let policy = HandlerPolicy {
+            on_error: HandlerErrorPolicy::Abort,
+            ..HandlerPolicy::default()
+        };
+        assert!(matches!(
+            collection.call(&msg, &policy),
+            Err(VrpnError::GenericErrorReturn)
+        ));
+        assert!(policy.errors().is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Optional measurement of message latency: the time from a message's header timestamp
+//! to its arrival here, for each message type.
+//!
+//! The header time comes from the sender's clock, so the measured latency includes
+//! any clock skew, and may even be negative. Compare with a `ClockEstimate` to separate them.
+
+use crate::data_types::{id_types::*, GenericMessage, TimeVal};
+use std::collections::{HashMap, VecDeque};
+
+/// Number of recent samples kept for each message type by default.
+pub const DEFAULT_LATENCY_WINDOW: usize = 1024;
+
+/// Latency statistics for one message type, over recent messages, in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LatencyStats {
+    /// Messages measured since tracking was enabled.
+    pub count: u64,
+    /// Number of recent messages the rest of these are computed from.
+    pub window: usize,
+    pub min: i64,
+    pub max: i64,
+    pub mean: i64,
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    count: u64,
+    recent: VecDeque<i64>,
+}
+
+/// Recent latency samples for each message type, kept by the dispatcher.
+#[derive(Debug)]
+pub struct LatencyTracker {
+    window: usize,
+    samples: HashMap<MessageTypeId, Samples>,
+}
+
+impl Default for LatencyTracker {
+    fn default() -> Self {
+        LatencyTracker::new(DEFAULT_LATENCY_WINDOW)
+    }
+}
+
+impl LatencyTracker {
+    /// Create a tracker keeping up to `window` recent samples per message type.
+    pub fn new(window: usize) -> LatencyTracker {
+        LatencyTracker {
+            window: window.max(1),
+            samples: HashMap::new(),
+        }
+    }
+
+    /// Record a message received at `now`.
+    pub fn record(&mut self, msg: &GenericMessage, now: TimeVal) {
+        let latency = now.as_micros() - msg.header.time.as_micros();
+        let samples = self.samples.entry(msg.header.message_type).or_default();
+        if samples.recent.len() == self.window {
+            samples.recent.pop_front();
+        }
+        samples.recent.push_back(latency);
+        samples.count += 1;
+    }
+
+    /// The latency statistics of a message type, if any have been received.
+    pub fn stats(&self, message_type: LocalId<MessageTypeId>) -> Option<LatencyStats> {
+        let samples = self.samples.get(&message_type.into_id())?;
+        let mut sorted: Vec<i64> = samples.recent.iter().copied().collect();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_unstable();
+        let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+        Some(LatencyStats {
+            count: samples.count,
+            window: sorted.len(),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            mean: sorted.iter().sum::<i64>() / sorted.len() as i64,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::{GenericBody, Message, MessageHeader};
+    use bytes::Bytes;
+
+    fn message(message_type: i32, sent_micros: i64) -> GenericMessage {
+        GenericMessage::from_header_and_body(
+            MessageHeader::new(
+                Some(TimeVal::from_micros(sent_micros)),
+                MessageTypeId(message_type),
+                SenderId(0),
+            ),
+            GenericBody::new(Bytes::new()),
+        )
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut tracker = LatencyTracker::new(100);
+        let now = TimeVal::from_micros(1_000_000);
+        // Latencies of 200 down to 1 us: only the last 100 are kept.
+        for latency in (1..=200).rev() {
+            tracker.record(&message(3, 1_000_000 - latency), now);
+        }
+        let stats = tracker.stats(LocalId(MessageTypeId(3))).unwrap();
+        assert_eq!(stats.count, 200);
+        assert_eq!(stats.window, 100);
+        assert_eq!(stats.min, 1);
+        assert_eq!(stats.max, 100);
+        assert_eq!(stats.p50, 50);
+        assert_eq!(stats.p90, 90);
+        assert_eq!(stats.p99, 99);
+        assert_eq!(stats.mean, 50);
+
+        assert!(tracker.stats(LocalId(MessageTypeId(4))).is_none());
+    }
+
+    #[test]
+    fn clock_skew_gives_negative_latency() {
+        let mut tracker = LatencyTracker::default();
+        tracker.record(&message(0, 2_000), TimeVal::from_micros(1_500));
+        let stats = tracker.stats(LocalId(MessageTypeId(0))).unwrap();
+        assert_eq!(stats.min, -500);
+        assert_eq!(stats.p50, -500);
+    }
+}
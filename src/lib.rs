@@ -2,16 +2,22 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
+//! Without the default `std` feature, only the wire format is built, needing just `alloc`:
+//! `buffer_unbuffer`, `data_types` and `compatibility`,
+//! so devices without an OS can share the exact message structs.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 extern crate bytes;
+#[cfg(feature = "std")]
 extern crate url;
 
-// #[cfg(feature = "async-tokio")]
-// extern crate tk_listen;
-
 #[cfg(feature = "cgmath")]
 extern crate cgmath;
 
-#[cfg(test)]
+// Only the tests of the std modules use `hex!`.
+#[cfg(all(test, feature = "std"))]
 #[macro_use]
 extern crate hex_literal;
 
@@ -22,44 +28,166 @@ extern crate proptest;
 #[macro_use]
 extern crate bitflags;
 
+#[cfg(feature = "std")]
 extern crate futures;
 
-#[cfg(feature = "async-tokio")]
-extern crate tokio;
+// Must come before the modules using its macros.
+#[macro_use]
+#[cfg_attr(not(feature = "std"), allow(unused_macros))]
+mod trace;
 
-#[cfg(feature = "async-tokio")]
-pub mod vrpn_tokio;
+mod alloc_prelude;
 
 #[cfg(feature = "async-std")]
 pub mod vrpn_async_std;
 
+#[cfg(feature = "async-tokio")]
+pub mod vrpn_tokio;
+
+#[cfg(feature = "std")]
+pub mod analog;
 pub mod buffer_unbuffer;
+#[cfg(feature = "std")]
+pub mod button;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "std")]
+pub mod class_policy;
+#[cfg(feature = "std")]
+pub mod clock_sync;
 pub mod data_types;
 
-mod codec;
+#[cfg(feature = "std")]
+pub mod codec;
+pub mod compatibility;
+#[cfg(feature = "std")]
+pub mod compression;
+#[cfg(feature = "std")]
 pub mod connection;
+#[cfg(feature = "std")]
+pub mod connection_state;
+#[cfg(feature = "std")]
 pub mod constants;
+#[cfg(feature = "std")]
+pub mod dial;
+#[cfg(feature = "std")]
+pub mod driver;
+#[cfg(feature = "std")]
+pub mod dyn_endpoint;
+#[cfg(feature = "std")]
 pub mod endpoint;
+#[cfg(feature = "std")]
 pub mod error;
+#[cfg(feature = "std")]
+pub mod force_device;
+#[cfg(feature = "std")]
+pub mod format;
+#[cfg(feature = "std")]
+pub mod forwarder;
+#[cfg(feature = "std")]
 pub mod handler;
+#[cfg(feature = "std")]
+mod handler_collection;
+#[cfg(feature = "std")]
+pub mod latency;
+#[cfg(feature = "std")]
+pub mod lifecycle;
+#[cfg(feature = "std")]
+pub mod limits;
+#[cfg(feature = "std")]
+pub mod loopback;
+#[cfg(feature = "std")]
+pub mod message_cache;
+#[cfg(feature = "std")]
+pub mod message_history;
+#[cfg(feature = "std")]
+pub mod message_log;
+#[cfg(feature = "std")]
 mod name_registration;
+#[cfg(feature = "std")]
+pub mod net_util;
+#[cfg(feature = "std")]
 mod parse_name;
+#[cfg(feature = "std")]
 pub mod ping;
+#[cfg(feature = "std")]
+pub mod playback;
+#[cfg(feature = "std")]
+pub mod poll_config;
+#[cfg(feature = "std")]
+pub mod poser;
+#[cfg(feature = "std")]
 #[deprecated]
 pub mod prelude;
+#[cfg(feature = "std")]
+pub mod registrations;
+#[cfg(feature = "std")]
+pub mod send_queue;
+#[cfg(feature = "std")]
+pub mod sequence;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod simulation;
+#[cfg(feature = "std")]
+pub mod sink;
+#[cfg(feature = "tools")]
+pub mod sniffer;
+#[cfg(feature = "std")]
+pub mod status_watch;
+#[cfg(feature = "std")]
+pub mod sync;
+#[cfg(feature = "std")]
 pub mod sync_io;
+#[cfg(feature = "std")]
+pub mod system_events;
+#[cfg(all(feature = "std", any(test, feature = "testing")))]
+pub mod testing;
+#[cfg(feature = "std")]
+pub mod text;
+#[cfg(feature = "std")]
+pub mod throttle;
+#[cfg(feature = "std")]
+pub mod timeouts;
+#[cfg(feature = "std")]
+pub mod timestamp_policy;
+#[cfg(feature = "std")]
+pub mod tls;
+#[cfg(feature = "std")]
 pub mod tracker;
+#[cfg(feature = "std")]
 pub mod translation_table;
+#[cfg(feature = "std")]
 pub mod type_dispatcher;
+#[cfg(feature = "std")]
 pub mod vrpn_async;
 
+pub use crate::compatibility::{CompatibilityProfile, PaddingPolicy, WireConfig};
+
+#[cfg(feature = "std")]
 pub use crate::{
+    codec::{FramingRecovery, MessageCodec},
+    compression::Compression,
     connection::{Connection, ConnectionStatus},
+    connection_state::ReconnectPolicy,
+    driver::{ConnectionDriver, ConnectionHandle, PollEndpoints},
     endpoint::*,
     error::{Result, VrpnError},
-    handler::{Handler, TypedBodylessHandler, TypedHandler},
-    parse_name::{Scheme, ServerInfo},
-    type_dispatcher::{RegisterMapping, TypeDispatcher},
+    handler::{AsyncHandler, Handler, TypedBodylessHandler, TypedHandler},
+    limits::{DispatcherLimits, LimitKind},
+    parse_name::{DeviceInfo, Scheme, ServerInfo},
+    poll_config::{PollConfig, YieldStrategy},
+    registrations::Registrations,
+    send_queue::SendQueueLimits,
+    sequence::{SequenceGap, SequenceStats},
+    sink::MessageSink,
+    status_watch::StatusWatch,
+    throttle::{Throttle, ThrottleMode},
+    timeouts::{TimeoutKind, Timeouts},
+    timestamp_policy::{ReceiveTimestamp, SendTimestamp, TimestampPolicy},
+    tls::{TlsClientOptions, TlsServerOptions},
+    type_dispatcher::{RegisterMapping, TypeDispatcher, ANY_SENDER, ANY_TYPE},
 };
 
+#[cfg(feature = "std")]
 pub(crate) use crate::translation_table::TranslationTables;
@@ -0,0 +1,87 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Subscribing to connection lifecycle events, as a stream,
+//! instead of polling `Connection::status` or registering system event handlers.
+//!
+//! Events are queued as they happen, for each subscriber, until it reads them.
+
+use crate::{
+    codec::FramingError,
+    data_types::name_types::{MessageTypeName, SenderName},
+};
+use futures::channel::mpsc;
+use std::time::Duration;
+
+/// Something that happened to a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// The first endpoint connected: sent before its `EndpointAdded`.
+    Connected,
+    /// The last endpoint closed: sent after its `EndpointRemoved`.
+    Disconnected,
+    /// An endpoint connected.
+    EndpointAdded,
+    /// An endpoint closed.
+    EndpointRemoved,
+    /// A peer described one of its senders.
+    SenderDescribed(SenderName),
+    /// A peer described one of its message types.
+    TypeDescribed(MessageTypeName),
+    /// The peer has not answered pings (see `ping::Client`) for this long.
+    PingTimeout(Duration),
+    /// A peer's byte stream was corrupt, and was skipped over or abandoned (see `FramingRecovery`).
+    FramingError(FramingError),
+}
+
+/// A stream of events, from `Connection::events`.
+///
+/// Dropping it unsubscribes.
+pub type LifecycleEvents = mpsc::UnboundedReceiver<LifecycleEvent>;
+
+/// The subscribers to a connection's events, kept by the dispatcher.
+#[derive(Debug, Default)]
+pub struct LifecycleEventBus {
+    subscribers: Vec<mpsc::UnboundedSender<LifecycleEvent>>,
+}
+
+impl LifecycleEventBus {
+    pub fn new() -> LifecycleEventBus {
+        LifecycleEventBus::default()
+    }
+
+    /// Get a stream of all events from now on.
+    pub fn subscribe(&mut self) -> LifecycleEvents {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Send an event to every subscriber, forgetting those that have gone away.
+    pub fn emit(&mut self, event: LifecycleEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_subscribers_removed() {
+        let mut bus = LifecycleEventBus::new();
+        let mut kept = bus.subscribe();
+        let dropped = bus.subscribe();
+        drop(dropped);
+        bus.emit(LifecycleEvent::Connected);
+        assert_eq!(bus.subscriber_count(), 1);
+        assert_eq!(kept.try_next().unwrap().unwrap(), LifecycleEvent::Connected);
+        assert!(kept.try_next().is_err());
+    }
+}
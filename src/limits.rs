@@ -0,0 +1,102 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Limits on how many senders, message types, and handlers a `TypeDispatcher` registers,
+//! so that a peer describing names without end cannot exhaust memory.
+
+use crate::{data_types::id_types::MAX_VEC_USIZE, Result, VrpnError};
+use bytes::Bytes;
+use std::fmt;
+
+/// Which of the `DispatcherLimits` was reached.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum LimitKind {
+    Senders,
+    MessageTypes,
+    Handlers,
+}
+
+impl fmt::Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LimitKind::Senders => "senders",
+            LimitKind::MessageTypes => "message types",
+            LimitKind::Handlers => "handlers",
+        })
+    }
+}
+
+/// The most senders, message types, and handlers a `TypeDispatcher` will register.
+///
+/// Names described by peers count, as do those registered for system use:
+/// one sender and seven message types. Handlers are limited per message type,
+/// with those for any type limited separately. The default is as many as IDs allow.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct DispatcherLimits {
+    pub max_senders: usize,
+    pub max_message_types: usize,
+    pub max_handlers: usize,
+}
+
+impl Default for DispatcherLimits {
+    fn default() -> DispatcherLimits {
+        DispatcherLimits {
+            max_senders: MAX_VEC_USIZE,
+            max_message_types: MAX_VEC_USIZE,
+            max_handlers: MAX_VEC_USIZE,
+        }
+    }
+}
+
+/// One of the limits, for a count of things of one kind, never above what IDs allow.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub(crate) struct Limit {
+    kind: LimitKind,
+    max: usize,
+}
+
+impl Limit {
+    pub(crate) fn new(kind: LimitKind, max: usize) -> Limit {
+        Limit {
+            kind,
+            max: max.min(MAX_VEC_USIZE),
+        }
+    }
+
+    /// Change the most to hold. Those already held are kept.
+    pub(crate) fn set_max(&mut self, max: usize) {
+        self.max = max.min(MAX_VEC_USIZE);
+    }
+
+    /// Check that there is room for one more, with `count` already held,
+    /// failing with `VrpnError::LimitReached` naming what was being registered.
+    pub(crate) fn check(&self, count: usize, name: impl FnOnce() -> Bytes) -> Result<()> {
+        if count >= self.max {
+            return Err(VrpnError::LimitReached {
+                kind: self.kind,
+                limit: self.max,
+                name: name(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check() {
+        let mut limit = Limit::new(LimitKind::Handlers, 2);
+        assert!(limit.check(1, || unreachable!()).is_ok());
+        assert!(matches!(
+            limit.check(2, || Bytes::from_static(b"Report")),
+            Err(VrpnError::LimitReached { kind: LimitKind::Handlers, limit: 2, name }) if name == "Report"
+        ));
+        limit.set_max(usize::MAX);
+        assert!(limit.check(MAX_VEC_USIZE - 1, || unreachable!()).is_ok());
+        assert!(limit.check(MAX_VEC_USIZE, Bytes::new).is_err());
+    }
+}
@@ -0,0 +1,539 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Connections in the same process, passing messages through channels rather than sockets:
+//! for tests, and for joining parts of one application, like a simulation feeding a renderer.
+//!
+//! Messages are not encoded, so settings for byte streams, like coalescing and
+//! framing recovery, are ignored. Otherwise these work like any other connection:
+//! poll them with a `ConnectionDriver`, `mainloop`, or `poll_endpoints`.
+
+use crate::{
+    connection::{ConnectionCore, ConnectionStatus},
+    data_types::{ClassOfService, GenericMessage},
+    dyn_endpoint::DynEndpoint,
+    endpoint::{handle_system_command, DescriptionTracker, SystemCommand},
+    poll_config::{poll_and_dispatch, PollConfig},
+    timestamp_policy::{stamp_now, SendTimestamp, TimestampPolicy},
+    Connection, Endpoint, PollEndpoints, Result, TranslationTables, TypeDispatcher, VrpnError,
+};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    StreamExt,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+/// One end of an in-process channel pair, receiving what the other end sends.
+#[derive(Debug)]
+pub struct EndpointLoopback {
+    translation: TranslationTables,
+    descriptions_sent: DescriptionTracker,
+    tx: UnboundedSender<GenericMessage>,
+    /// Taken while polling, so the endpoint can be borrowed alongside it.
+    rx: Option<UnboundedReceiver<GenericMessage>>,
+    system_tx: UnboundedSender<SystemCommand>,
+    system_rx: UnboundedReceiver<SystemCommand>,
+    poll_config: PollConfig,
+    timestamp_policy: TimestampPolicy,
+}
+
+impl EndpointLoopback {
+    fn new(
+        tx: UnboundedSender<GenericMessage>,
+        rx: UnboundedReceiver<GenericMessage>,
+    ) -> EndpointLoopback {
+        let (system_tx, system_rx) = unbounded();
+        EndpointLoopback {
+            translation: TranslationTables::new(),
+            descriptions_sent: DescriptionTracker::new(),
+            tx,
+            rx: Some(rx),
+            system_tx,
+            system_rx,
+            poll_config: PollConfig::default(),
+            timestamp_policy: TimestampPolicy::default(),
+        }
+    }
+
+    /// Create two endpoints, each receiving what the other sends.
+    pub fn pair() -> (EndpointLoopback, EndpointLoopback) {
+        let (a_tx, a_rx) = unbounded();
+        let (b_tx, b_rx) = unbounded();
+        (
+            EndpointLoopback::new(a_tx, b_rx),
+            EndpointLoopback::new(b_tx, a_rx),
+        )
+    }
+
+    /// Dispatch received messages, ready once the other end has gone away.
+    fn poll_endpoint(
+        &mut self,
+        dispatcher: &TypeDispatcher,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        let mut rx = match self.rx.take() {
+            Some(rx) => rx,
+            None => return Poll::Ready(Ok(())),
+        };
+        let config = self.poll_config;
+        let result = loop {
+            let result = poll_and_dispatch(self, &mut rx, dispatcher, &config, cx);
+            // Dispatching stops at each description received: apply it and carry on.
+            let mut applied = false;
+            while let Poll::Ready(Some(cmd)) = self.system_rx.poll_next_unpin(cx) {
+                applied = true;
+                if let Some(cmd) = handle_system_command(dispatcher, &mut self.translation, cmd)? {
+                    debug!("Ignoring system command on loopback endpoint: {:?}", cmd);
+                }
+            }
+            if !applied || result.is_ready() {
+                break result;
+            }
+        };
+        self.rx = Some(rx);
+        if self.tx.is_closed() {
+            // We closed our end: nothing more will be sent.
+            return Poll::Ready(Ok(()));
+        }
+        result
+    }
+}
+
+impl DynEndpoint for EndpointLoopback {
+    fn poll_endpoint(
+        &mut self,
+        dispatcher: &TypeDispatcher,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        EndpointLoopback::poll_endpoint(self, dispatcher, cx)
+    }
+}
+
+impl Endpoint for EndpointLoopback {
+    fn translation_tables(&self) -> &TranslationTables {
+        &self.translation
+    }
+
+    fn translation_tables_mut(&mut self) -> &mut TranslationTables {
+        &mut self.translation
+    }
+
+    fn send_system_change(&self, message: SystemCommand) -> Result<()> {
+        Ok(self.system_tx.unbounded_send(message)?)
+    }
+
+    fn set_poll_config(&mut self, config: PollConfig) {
+        self.poll_config = config;
+    }
+
+    fn set_timestamp_policy(&mut self, policy: TimestampPolicy) {
+        self.timestamp_policy = policy;
+    }
+
+    fn timestamp_policy(&self) -> TimestampPolicy {
+        self.timestamp_policy
+    }
+
+    fn close_when_sent(&mut self) {
+        // Whatever was sent stays in the channel for the other end to receive.
+        self.tx.close_channel();
+    }
+
+    fn buffer_generic_message(
+        &mut self,
+        mut msg: GenericMessage,
+        _class: ClassOfService,
+    ) -> Result<()> {
+        // Nothing is written out: the message is as good as written once queued.
+        if self.timestamp_policy.send != SendTimestamp::Caller {
+            stamp_now(&mut msg);
+        }
+        self.tx
+            .unbounded_send(msg)
+            .map_err(|_| VrpnError::EndpointClosed)
+    }
+
+    fn description_tracker_mut(&mut self) -> Option<&mut DescriptionTracker> {
+        Some(&mut self.descriptions_sent)
+    }
+}
+
+/// A connection whose endpoints are in-process channels.
+///
+/// Create a server with `new_server`, then a client for it with `connect`,
+/// or both at once with `pair`.
+#[derive(Debug)]
+pub struct LoopbackConnection {
+    core: ConnectionCore<EndpointLoopback>,
+    is_server: bool,
+    /// Endpoints added since the last poll, not yet reported to the got-connection handlers.
+    new_endpoints: AtomicUsize,
+}
+
+impl LoopbackConnection {
+    fn new(is_server: bool) -> Arc<LoopbackConnection> {
+        Arc::new(LoopbackConnection {
+            core: ConnectionCore::without_logs(Vec::new()),
+            is_server,
+            new_endpoints: AtomicUsize::new(0),
+        })
+    }
+
+    /// Create a server with no clients yet.
+    pub fn new_server() -> Arc<LoopbackConnection> {
+        LoopbackConnection::new(true)
+    }
+
+    /// Create a server and one client connected to it, returned in that order.
+    pub fn pair() -> Result<(Arc<LoopbackConnection>, Arc<LoopbackConnection>)> {
+        let server = LoopbackConnection::new_server();
+        let client = server.connect()?;
+        Ok((server, client))
+    }
+
+    /// Create a new client connected to this server.
+    pub fn connect(&self) -> Result<Arc<LoopbackConnection>> {
+        if !self.is_server {
+            return Err(VrpnError::NotAServer);
+        }
+        let (server_end, client_end) = EndpointLoopback::pair();
+        let client = LoopbackConnection::new(false);
+        self.add_endpoint(server_end)?;
+        client.add_endpoint(client_end)?;
+        Ok(client)
+    }
+
+    /// Describe our senders and types to a new endpoint, and start polling it.
+    fn add_endpoint(&self, mut endpoint: EndpointLoopback) -> Result<()> {
+        let dispatcher = self.dispatcher();
+        let dispatcher = dispatcher.read();
+        let endpoints = self.endpoints();
+        let mut endpoints = endpoints.lock();
+        endpoint.set_poll_config(self.core.poll_config()?);
+        endpoint.set_timestamp_policy(self.core.timestamp_policy()?);
+        endpoint.send_all_descriptions(&dispatcher)?;
+        endpoints.push(Some(endpoint));
+        self.new_endpoints.fetch_add(1, Ordering::SeqCst);
+        self.core.wake_driver();
+        Ok(())
+    }
+}
+
+impl PollEndpoints for LoopbackConnection {
+    fn poll_endpoints(&self, cx: &mut Context<'_>) -> Poll<Result<Option<()>>> {
+        let dispatcher = self.dispatcher();
+        let dispatcher = dispatcher.read();
+        let endpoints = self.endpoints();
+        let mut endpoints = endpoints.lock();
+
+        let new_endpoints = self.new_endpoints.swap(0, Ordering::SeqCst);
+        let existing = endpoints.iter().flatten().count() - new_endpoints;
+        for i in 0..new_endpoints {
+            dispatcher.call_got_connection(existing == 0 && i == 0)?;
+        }
+
+        let mut dropped = 0;
+        for ep in endpoints.iter_mut() {
+            let closed = match ep {
+                Some(endpoint) => endpoint.poll_endpoint(&dispatcher, cx).is_ready(),
+                None => true,
+            };
+            if closed && ep.take().is_some() {
+                dropped += 1;
+            }
+        }
+        endpoints.retain(|ep| ep.is_some());
+        // Names first seen in one endpoint's descriptions got new local IDs to describe.
+        for endpoint in endpoints.iter_mut().flatten() {
+            endpoint.send_all_descriptions(&dispatcher)?;
+        }
+        for i in 0..dropped {
+            dispatcher.call_dropped_connection(endpoints.is_empty() && i + 1 == dropped)?;
+        }
+
+        let poll = if endpoints.is_empty() {
+            Poll::Ready(Ok(Some(())))
+        } else {
+            Poll::Pending
+        };
+        drop(endpoints);
+        drop(dispatcher);
+        self.core.publish_status(self.status());
+        poll
+    }
+}
+
+impl Connection for LoopbackConnection {
+    type SpecificEndpoint = EndpointLoopback;
+
+    fn connection_core(&self) -> &ConnectionCore<Self::SpecificEndpoint> {
+        &self.core
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        let endpoints = self.endpoints();
+        let count = endpoints.lock().len();
+        match (self.is_server, count) {
+            (true, count) => ConnectionStatus::Server(count),
+            (false, 0) => ConnectionStatus::ClientDisconnected,
+            (false, _) => ConnectionStatus::ClientConnected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        button::{ButtonChange, ButtonRemote, ButtonServer},
+        data_types::{SenderName, StaticMessageTypeName, StaticSenderName, TimeVal, TypedMessage},
+        handler::{HandlerCode, TypedHandler},
+        testing::Record,
+        timestamp_policy::ReceiveTimestamp,
+    };
+    use std::sync::Mutex;
+
+    #[derive(Debug)]
+    struct RecordTime(Arc<Mutex<Vec<TimeVal>>>);
+
+    impl TypedHandler for RecordTime {
+        type Item = ButtonChange;
+        fn handle_typed(&mut self, msg: &TypedMessage<ButtonChange>) -> Result<HandlerCode> {
+            self.0.lock()?.push(msg.header.time);
+            Ok(HandlerCode::ContinueProcessing)
+        }
+    }
+
+    fn poll(connections: &[&Arc<LoopbackConnection>]) {
+        for _ in 0..4 {
+            for connection in connections {
+                connection.mainloop(None).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn both_directions_and_close() {
+        let (server, client) = LoopbackConnection::pair().unwrap();
+        let button = ButtonServer::new(Arc::clone(&server), StaticSenderName(b"Button0")).unwrap();
+        let remote = ButtonRemote::new(Arc::clone(&client), StaticSenderName(b"Button0")).unwrap();
+        let received = Arc::new(Mutex::new(Vec::<ButtonChange>::new()));
+        remote
+            .add_handler(Box::new(Record(Arc::clone(&received))))
+            .unwrap();
+        assert_eq!(server.status(), ConnectionStatus::Server(1));
+        assert_eq!(client.status(), ConnectionStatus::ClientConnected);
+
+        let change = ButtonChange {
+            button: 1,
+            pressed: true,
+        };
+        button.report_change(None, change).unwrap();
+        poll(&[&server, &client]);
+        assert_eq!(received.lock().unwrap()[..], [change]);
+
+        // A second client gets the descriptions too.
+        let other = server.connect().unwrap();
+        let other_received = Arc::new(Mutex::new(Vec::<ButtonChange>::new()));
+        ButtonRemote::new(Arc::clone(&other), StaticSenderName(b"Button0"))
+            .unwrap()
+            .add_handler(Box::new(Record(Arc::clone(&other_received))))
+            .unwrap();
+        button.report_change(None, change).unwrap();
+        poll(&[&server, &client, &other]);
+        assert_eq!(received.lock().unwrap().len(), 2);
+        assert_eq!(other_received.lock().unwrap()[..], [change]);
+        assert!(other.connect().is_err());
+
+        client.shutdown().unwrap();
+        poll(&[&server, &client, &other]);
+        assert_eq!(client.status(), ConnectionStatus::ClientDisconnected);
+        assert_eq!(server.status(), ConnectionStatus::Server(1));
+    }
+
+    #[test]
+    fn status_watch() {
+        use futures::{executor::block_on, StreamExt};
+        let (server, client) = LoopbackConnection::pair().unwrap();
+        let mut server_watch = server.status_watch().unwrap();
+        let mut client_watch = client.status_watch().unwrap();
+        assert_eq!(
+            block_on(server_watch.next()),
+            Some(ConnectionStatus::Server(1))
+        );
+        assert_eq!(
+            block_on(client_watch.next()),
+            Some(ConnectionStatus::ClientConnected)
+        );
+
+        let other = server.connect().unwrap();
+        poll(&[&server, &client, &other]);
+        assert_eq!(
+            block_on(server_watch.next()),
+            Some(ConnectionStatus::Server(2))
+        );
+        assert!(!client_watch.has_changed());
+
+        client.shutdown().unwrap();
+        poll(&[&server, &client, &other]);
+        assert_eq!(
+            block_on(client_watch.next()),
+            Some(ConnectionStatus::ClientDisconnected)
+        );
+        assert_eq!(
+            block_on(server_watch.next()),
+            Some(ConnectionStatus::Server(1))
+        );
+    }
+
+    #[test]
+    fn disconnect_callbacks() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let (server, client) = LoopbackConnection::pair().unwrap();
+        poll(&[&server, &client]);
+        let counter = |count: &Arc<AtomicUsize>| {
+            let count = Arc::clone(count);
+            move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            }
+        };
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let connected = Arc::new(AtomicUsize::new(0));
+        let _on_disconnect = server.on_disconnect(counter(&dropped)).unwrap();
+        let _on_reconnect = server.on_reconnect(counter(&connected)).unwrap();
+        let client_dropped = Arc::new(AtomicUsize::new(0));
+        let _on_client_disconnect = client.on_disconnect(counter(&client_dropped)).unwrap();
+
+        client.shutdown().unwrap();
+        poll(&[&server, &client]);
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+        assert_eq!(client_dropped.load(Ordering::SeqCst), 1);
+        assert_eq!(connected.load(Ordering::SeqCst), 0);
+
+        let other = server.connect().unwrap();
+        poll(&[&server, &other]);
+        assert_eq!(connected.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn timestamp_policy() {
+        let (server, client) = LoopbackConnection::pair().unwrap();
+        let times = Arc::new(Mutex::new(Vec::new()));
+        client
+            .add_typed_handler(Box::new(RecordTime(Arc::clone(&times))), None)
+            .unwrap();
+        let sender = server
+            .register_sender(StaticSenderName(b"Button0"))
+            .unwrap();
+        let old = TimeVal::from_micros(1_000_000);
+        let send = || {
+            let change = ButtonChange {
+                button: 0,
+                pressed: true,
+            };
+            server
+                .pack_message_body(Some(old), sender, change, ClassOfService::RELIABLE)
+                .unwrap();
+            poll(&[&server, &client]);
+            times.lock().unwrap().pop().unwrap()
+        };
+        assert_eq!(send(), old);
+
+        server
+            .set_timestamp_policy(TimestampPolicy {
+                send: SendTimestamp::Buffered,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(send() > old);
+
+        server
+            .set_timestamp_policy(TimestampPolicy::default())
+            .unwrap();
+        assert_eq!(send(), old);
+        client
+            .set_timestamp_policy(TimestampPolicy {
+                receive: ReceiveTimestamp::Arrival,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(send() > old);
+    }
+
+    #[test]
+    fn description_observers() {
+        let (server, client) = LoopbackConnection::pair().unwrap();
+        let senders = Arc::new(Mutex::new(Vec::new()));
+        let types = Arc::new(Mutex::new(Vec::new()));
+        {
+            let senders = Arc::clone(&senders);
+            client
+                .on_new_sender(move |name, id| senders.lock().unwrap().push((name.clone(), id)))
+                .unwrap();
+            let types = Arc::clone(&types);
+            client
+                .on_new_type(move |name, _| types.lock().unwrap().push(name.clone()))
+                .unwrap();
+        }
+        let button = ButtonServer::new(Arc::clone(&server), StaticSenderName(b"Button0")).unwrap();
+        button
+            .report_change(
+                None,
+                ButtonChange {
+                    button: 0,
+                    pressed: true,
+                },
+            )
+            .unwrap();
+        poll(&[&server, &client]);
+
+        let button = SenderName::from(StaticSenderName(b"Button0"));
+        let id = client.dispatcher().read().get_sender_id(button.clone());
+        assert!(senders
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(name, local)| *name == button && Some(*local) == id));
+        assert!(types
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|name| *name == StaticMessageTypeName(b"vrpn_Button Change")));
+    }
+
+    #[test]
+    fn scoped_handler() {
+        let (server, client) = LoopbackConnection::pair().unwrap();
+        let button = ButtonServer::new(Arc::clone(&server), StaticSenderName(b"Button0")).unwrap();
+        let received = Arc::new(Mutex::new(Vec::<ButtonChange>::new()));
+        let scoped = client
+            .add_typed_handler_scoped(Box::new(Record(Arc::clone(&received))), None)
+            .unwrap();
+        let change = ButtonChange {
+            button: 0,
+            pressed: true,
+        };
+        button.report_change(None, change).unwrap();
+        poll(&[&server, &client]);
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        drop(scoped);
+        button.report_change(None, change).unwrap();
+        poll(&[&server, &client]);
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        // Outliving the connection is fine.
+        let scoped = client
+            .add_typed_handler_scoped(Box::new(Record(Arc::clone(&received))), None)
+            .unwrap();
+        drop(client);
+        drop(scoped);
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! An optional cache of the latest message of each type from each sender,
+//! with counts and rate estimates, for applications that just want the current state
+//! (e.g. the current pose) without handlers.
+//!
+//! Messages are cached as received, including those held back by a throttle.
+
+use crate::{
+    buffer_unbuffer::UnbufferFrom,
+    data_types::{id_types::*, GenericMessage, TypedMessage, TypedMessageBody},
+    Result, VrpnError,
+};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    time::{Duration, Instant},
+};
+
+/// Counters for the messages of one type from one sender.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageStats {
+    pub count: u64,
+    pub last_received: Instant,
+    /// Messages per second, smoothed over recent messages.
+    ///
+    /// `None` until two messages have been received.
+    pub rate: Option<f64>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    latest: GenericMessage,
+    count: u64,
+    last_received: Instant,
+    /// Smoothed time between messages.
+    interval: Option<Duration>,
+}
+
+/// The latest message and stats for each (type, sender), kept by the dispatcher.
+#[derive(Debug, Default)]
+pub struct MessageCache {
+    entries: HashMap<(MessageTypeId, SenderId), Entry>,
+}
+
+impl MessageCache {
+    pub fn new() -> MessageCache {
+        MessageCache::default()
+    }
+
+    /// Record a received message.
+    pub fn record(&mut self, msg: &GenericMessage, now: Instant) {
+        let key = (msg.header.message_type, msg.header.sender);
+        match self.entries.get_mut(&key) {
+            Some(entry) => {
+                let interval = now.saturating_duration_since(entry.last_received);
+                // Weight of 1/8 for the new interval, as for TCP round trip times.
+                entry.interval = Some(match entry.interval {
+                    Some(smoothed) => (smoothed * 7 + interval) / 8,
+                    None => interval,
+                });
+                entry.latest = msg.clone();
+                entry.count += 1;
+                entry.last_received = now;
+            }
+            None => {
+                self.entries.insert(
+                    key,
+                    Entry {
+                        latest: msg.clone(),
+                        count: 1,
+                        last_received: now,
+                        interval: None,
+                    },
+                );
+            }
+        }
+    }
+
+    /// The latest message of a type from a sender.
+    pub fn latest_generic(
+        &self,
+        message_type: LocalId<MessageTypeId>,
+        sender: LocalId<SenderId>,
+    ) -> Option<&GenericMessage> {
+        self.entries
+            .get(&(message_type.into_id(), sender.into_id()))
+            .map(|entry| &entry.latest)
+    }
+
+    /// The latest message of a type from a sender, parsed.
+    pub fn latest<T: TypedMessageBody + UnbufferFrom>(
+        &self,
+        message_type: LocalId<MessageTypeId>,
+        sender: LocalId<SenderId>,
+    ) -> Result<Option<TypedMessage<T>>> {
+        self.latest_generic(message_type, sender)
+            .map(TypedMessage::try_from)
+            .transpose()
+            .map_err(VrpnError::from)
+    }
+
+    /// The counters for a type from a sender.
+    pub fn stats(
+        &self,
+        message_type: LocalId<MessageTypeId>,
+        sender: LocalId<SenderId>,
+    ) -> Option<MessageStats> {
+        self.entries
+            .get(&(message_type.into_id(), sender.into_id()))
+            .map(|entry| MessageStats {
+                count: entry.count,
+                last_received: entry.last_received,
+                rate: entry
+                    .interval
+                    .filter(|interval| !interval.is_zero())
+                    .map(|interval| 1.0 / interval.as_secs_f64()),
+            })
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data_types::{Quat, Vec3},
+        tracker::PoseReport,
+    };
+
+    #[test]
+    fn latest_and_rate() {
+        let mut cache = MessageCache::new();
+        let start = Instant::now();
+        for i in 0..10 {
+            let pose = PoseReport {
+                sensor: Sensor(0),
+                pos: Vec3::new(i as f64, 0.0, 0.0),
+                quat: Quat::identity(),
+            };
+            let msg = TypedMessage::new(None, MessageTypeId(3), SenderId(1), pose);
+            cache.record(
+                &GenericMessage::try_from(msg).unwrap(),
+                start + Duration::from_millis(10 * i),
+            );
+        }
+        let latest: TypedMessage<PoseReport> = cache
+            .latest(LocalId(MessageTypeId(3)), LocalId(SenderId(1)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.body.pos, Vec3::new(9.0, 0.0, 0.0));
+
+        let stats = cache
+            .stats(LocalId(MessageTypeId(3)), LocalId(SenderId(1)))
+            .unwrap();
+        assert_eq!(stats.count, 10);
+        assert!((stats.rate.unwrap() - 100.0).abs() < 1.0);
+
+        assert!(cache
+            .stats(LocalId(MessageTypeId(3)), LocalId(SenderId(0)))
+            .is_none());
+    }
+}
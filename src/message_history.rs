@@ -0,0 +1,243 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Optional bounded record of recent messages on an endpoint,
+//! dumped when a protocol error occurs or the endpoint drops,
+//! to help debug intermittent interoperability failures.
+
+use crate::data_types::{GenericMessage, MessageHeader};
+use bytes::Bytes;
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+/// Whether a message was received or sent.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Inbound => write!(f, "in "),
+            Direction::Outbound => write!(f, "out"),
+        }
+    }
+}
+
+/// Where to write the history when it is dumped.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
+pub enum DumpTarget {
+    /// Emit a debug-level event, with the `tracing` feature: otherwise, discard it.
+    #[default]
+    Log,
+    /// Write to standard error.
+    Stderr,
+    /// Append to the named file.
+    File(PathBuf),
+}
+
+/// Settings for recording message history.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct MessageHistoryConfig {
+    /// Number of most recent messages to keep.
+    pub capacity: usize,
+    /// Number of bytes of each message body to keep.
+    pub max_body_bytes: usize,
+    /// Where to dump the history: a debug-level event unless set otherwise.
+    pub target: DumpTarget,
+}
+
+impl Default for MessageHistoryConfig {
+    fn default() -> MessageHistoryConfig {
+        MessageHistoryConfig {
+            capacity: 64,
+            max_body_bytes: 32,
+            target: DumpTarget::default(),
+        }
+    }
+}
+
+/// A recorded message: the full header, and a possibly-truncated body.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct HistoryEntry {
+    pub direction: Direction,
+    pub header: MessageHeader,
+    /// The first `max_body_bytes` of the body.
+    pub body: Bytes,
+    /// The length of the full body.
+    pub body_len: usize,
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} type {} sender {} body {} bytes:",
+            self.direction,
+            self.header.time,
+            self.header.message_type.0,
+            self.header.sender.0,
+            self.body_len
+        )?;
+        for byte in self.body.iter() {
+            write!(f, " {:02x}", byte)?;
+        }
+        if self.body.len() < self.body_len {
+            write!(f, " ...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Bounded ring buffer of recent messages.
+#[derive(Debug, Clone)]
+pub struct MessageHistory {
+    config: MessageHistoryConfig,
+    entries: VecDeque<HistoryEntry>,
+}
+
+impl MessageHistory {
+    pub fn new(config: MessageHistoryConfig) -> MessageHistory {
+        MessageHistory {
+            entries: VecDeque::with_capacity(config.capacity),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &MessageHistoryConfig {
+        &self.config
+    }
+
+    /// Record a message, evicting the oldest if full.
+    pub fn record(&mut self, direction: Direction, msg: &GenericMessage) {
+        if self.config.capacity == 0 {
+            return;
+        }
+        while self.entries.len() >= self.config.capacity {
+            self.entries.pop_front();
+        }
+        let body = msg.body.as_bytes();
+        let kept = body.len().min(self.config.max_body_bytes);
+        self.entries.push_back(HistoryEntry {
+            direction,
+            header: msg.header.clone(),
+            body: body.slice(..kept),
+            body_len: body.len(),
+        });
+    }
+
+    /// Iterate through the recorded messages, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear()
+    }
+
+    /// Write the history, with a line explaining why, to a writer.
+    pub fn write_to<W: Write>(&self, writer: &mut W, reason: &str) -> io::Result<()> {
+        writeln!(
+            writer,
+            "Last {} messages before {}:",
+            self.entries.len(),
+            reason
+        )?;
+        for entry in self.iter() {
+            writeln!(writer, "  {}", entry)?;
+        }
+        Ok(())
+    }
+
+    /// Write the history to the configured target.
+    pub fn dump(&self, reason: &str) -> io::Result<()> {
+        match &self.config.target {
+            DumpTarget::Log => {
+                let mut text = Vec::new();
+                self.write_to(&mut text, reason)?;
+                debug!("{}", String::from_utf8_lossy(&text).trim_end());
+                Ok(())
+            }
+            DumpTarget::Stderr => self.write_to(&mut io::stderr().lock(), reason),
+            DumpTarget::File(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                self.write_to(&mut file, reason)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::{
+        id_types::{MessageTypeId, SenderId},
+        GenericBody, Message, TimeVal,
+    };
+
+    fn make_message(len: usize) -> GenericMessage {
+        GenericMessage::from_header_and_body(
+            MessageHeader::new(Some(TimeVal::default()), MessageTypeId(1), SenderId(2)),
+            GenericBody::new(Bytes::from(vec![0xab_u8; len])),
+        )
+    }
+
+    #[test]
+    fn bounded_and_truncated() {
+        let mut history = MessageHistory::new(MessageHistoryConfig {
+            capacity: 2,
+            max_body_bytes: 4,
+            target: DumpTarget::Log,
+        });
+        history.record(Direction::Inbound, &make_message(1));
+        history.record(Direction::Outbound, &make_message(2));
+        history.record(Direction::Inbound, &make_message(10));
+        assert_eq!(history.len(), 2);
+
+        let entries: Vec<_> = history.iter().collect();
+        assert_eq!(entries[0].direction, Direction::Outbound);
+        assert_eq!(entries[0].body_len, 2);
+        assert_eq!(entries[1].body.len(), 4);
+        assert_eq!(entries[1].body_len, 10);
+
+        let mut out = Vec::new();
+        history.write_to(&mut out, "test").unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Last 2 messages before test:"));
+        assert!(out.contains("ab ab ab ab ..."));
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut history = MessageHistory::new(MessageHistoryConfig {
+            capacity: 0,
+            ..Default::default()
+        });
+        history.record(Direction::Inbound, &make_message(1));
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn dumped_as_event_by_default() {
+        let mut history = MessageHistory::new(MessageHistoryConfig::default());
+        assert_eq!(history.config().target, DumpTarget::Log);
+        history.record(Direction::Inbound, &make_message(1));
+        history.dump("test").unwrap();
+    }
+}
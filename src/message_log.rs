@@ -0,0 +1,148 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Writing `.vrpn` log files: a file cookie, then messages as sent over TCP.
+//!
+//! An endpoint logs the messages it receives or sends to the files named by the
+//! local `LogFileNames` of its connection. Incoming messages are logged as received,
+//! with the peer's IDs, and outgoing ones as sent, so each log includes the
+//! descriptions needed to understand it.
+//!
+//! Logs are read like captures (see `capture`), or played back with `playback`.
+//!
+//! A peer may also ask, in its cookie and a `LOG_DESCRIPTION` message, for the connection
+//! to be logged here, to files it names. Since that writes files on this host,
+//! it is only done if the `RemoteLogPolicy` allows it.
+
+use crate::{
+    buffer_unbuffer::{BufferSize, BufferTo},
+    data_types::{id_types::SequenceNumber, CookieData, GenericMessage},
+    Result,
+};
+use bytes::BytesMut;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// Whether to log a connection when the peer asks for it.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum RemoteLogPolicy {
+    /// Tell the peer, with a text message, that its request is refused. This is the default.
+    #[default]
+    Refuse,
+    /// Log to the files the peer names, as mainline VRPN does.
+    Allow,
+}
+
+/// A log being written to a file.
+pub type FileLogWriter = LogWriter<BufWriter<File>>;
+
+/// Writes messages in the `.vrpn` log format.
+#[derive(Debug)]
+pub struct LogWriter<W: Write> {
+    writer: W,
+    sequence: u32,
+    buf: BytesMut,
+}
+
+impl LogWriter<BufWriter<File>> {
+    /// Create (or truncate) a log file.
+    pub fn create(path: impl AsRef<Path>) -> Result<FileLogWriter> {
+        LogWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> LogWriter<W> {
+    /// Start a log, writing the file cookie.
+    pub fn new(writer: W) -> Result<LogWriter<W>> {
+        let mut log = LogWriter {
+            writer,
+            sequence: 0,
+            buf: BytesMut::new(),
+        };
+        let cookie = CookieData::make_file_cookie();
+        log.buf.reserve(cookie.buffer_size());
+        cookie.buffer_to(&mut log.buf)?;
+        log.write_buffered()?;
+        Ok(log)
+    }
+
+    fn write_buffered(&mut self) -> Result<()> {
+        let bytes = self.buf.split();
+        self.writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Append a message, numbering it after the previous one.
+    pub fn write_message(&mut self, msg: &GenericMessage) -> Result<()> {
+        let msg = msg
+            .clone()
+            .into_sequenced_message(SequenceNumber(self.sequence));
+        self.sequence = self.sequence.wrapping_add(1);
+        self.buf.reserve(msg.buffer_size());
+        msg.buffer_to(&mut self.buf)?;
+        self.write_buffered()
+    }
+
+    /// Write out anything buffered.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Stop logging, returning the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        capture::decode_capture,
+        data_types::{constants, id_types::*, Quat, TypedMessage, Vec3},
+        tracker::PoseReport,
+        type_dispatcher::TryIntoDescriptionMessage,
+    };
+    use bytes::Bytes;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn written_log_decodes() {
+        let mut log = LogWriter::new(Vec::new()).unwrap();
+        log.write_message(
+            &LocalId(SenderId(0))
+                .try_into_description_message("Tracker0")
+                .unwrap(),
+        )
+        .unwrap();
+        log.write_message(
+            &LocalId(MessageTypeId(0))
+                .try_into_description_message("vrpn_Tracker Pos_Quat")
+                .unwrap(),
+        )
+        .unwrap();
+        let pose = PoseReport {
+            sensor: Sensor(0),
+            pos: Vec3::new(1.0, 2.0, 3.0),
+            quat: Quat::identity(),
+        };
+        let pose = TypedMessage::new(None, MessageTypeId(0), SenderId(0), pose);
+        log.write_message(&GenericMessage::try_from(pose).unwrap())
+            .unwrap();
+
+        let capture = decode_capture(Bytes::from(log.into_inner())).unwrap();
+        assert_eq!(capture.cookie.version, constants::FILE_MAGIC_DATA);
+        assert_eq!(capture.trailing_bytes, 0);
+        assert_eq!(capture.messages.len(), 3);
+        assert_eq!(capture.messages[2].sequence_number, SequenceNumber(2));
+        assert_eq!(
+            capture.messages[2].sender_name.as_deref(),
+            Some(&b"Tracker0"[..])
+        );
+    }
+}
@@ -5,15 +5,18 @@ use crate::{
     data_types::{
         id_types::{
             categorize_id, CategorizedId, Id, IdType, IdTypeUnsigned, LocalId, UnwrappedId,
-            MAX_VEC_USIZE,
         },
         name_types::NameIntoBytes,
         IdWithNameAndDescription,
     },
+    limits::{Limit, LimitKind},
     Result, VrpnError,
 };
 use bytes::Bytes;
-use std::{collections::HashMap, convert::TryInto};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::{TryFrom, TryInto},
+};
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub(crate) struct Name(Bytes);
@@ -38,16 +41,6 @@ pub(crate) enum InsertOrGet<I> {
     New(I),
 }
 
-impl<I: Copy> InsertOrGet<I> {
-    /// Access the wrapped ID, no matter if it was new or not.
-    pub fn into_inner(&self) -> I {
-        match self {
-            InsertOrGet::Found(v) => *v,
-            InsertOrGet::New(v) => *v,
-        }
-    }
-}
-
 pub(crate) trait IntoCorrespondingName<I: IdWithNameAndDescription>: Into<I::Name> {
     fn into_corresponding_name(self) -> I::Name;
 }
@@ -78,6 +71,14 @@ pub(crate) trait LocalNameRegistration {
 
     /// Categorize a given ID based on whether or not it is valid
     fn categorize_id(&self, id: Self::IdType) -> Self::CategorizedId;
+
+    /// Remove a registration, without freeing its ID for reuse.
+    ///
+    /// Registering the name again restores the same ID.
+    fn remove(&mut self, id: LocalId<Self::IdType>) -> Result<()>;
+
+    /// Has this ID been removed (and not registered again)?
+    fn is_removed(&self, id: LocalId<Self::IdType>) -> bool;
 }
 
 pub(crate) trait IterableNameRegistration<'a>: LocalNameRegistration {
@@ -97,16 +98,27 @@ pub(crate) trait ExtraDataById: LocalNameRegistration {
 pub(crate) struct NameRegistrationContainer<I: RegisterableId> {
     /// Index is the local type ID
     names: Vec<Name>,
+    /// Includes removed names, so they get their old IDs back.
     ids_by_name: HashMap<Name, LocalId<I>>,
+    removed: HashSet<IdType>,
+    /// The most names to hold, including removed ones.
+    limit: Limit,
 }
 
-impl<I: RegisterableId> Default for NameRegistrationContainer<I> {
-    fn default() -> NameRegistrationContainer<I> {
+impl<I: RegisterableId> NameRegistrationContainer<I> {
+    pub(crate) fn new(kind: LimitKind, limit: usize) -> NameRegistrationContainer<I> {
         NameRegistrationContainer {
             names: vec![],
             ids_by_name: HashMap::default(),
+            removed: HashSet::default(),
+            limit: Limit::new(kind, limit),
         }
     }
+
+    /// Change the most names to hold. Those already registered are kept.
+    pub(crate) fn set_limit(&mut self, limit: usize) {
+        self.limit.set_max(limit);
+    }
 }
 
 impl<I: IdWithNameAndDescription> LocalNameRegistration for NameRegistrationContainer<I> {
@@ -121,6 +133,7 @@ impl<I: IdWithNameAndDescription> LocalNameRegistration for NameRegistrationCont
         let name = name.into_bytes();
         let name = Name(name);
         Ok(match self.ids_by_name.get(&name) {
+            Some(id) if self.removed.remove(&id.get()) => InsertOrGet::New(*id),
             Some(id) => InsertOrGet::Found(*id),
             None => InsertOrGet::New(self.try_insert(&name)?),
         })
@@ -129,19 +142,44 @@ impl<I: IdWithNameAndDescription> LocalNameRegistration for NameRegistrationCont
     fn try_get_id_by_name<N: IntoCorrespondingName<I>>(&self, name: N) -> Option<LocalId<I>> {
         let name: Bytes = name.into().into_bytes();
         let name = Name(name);
-        self.ids_by_name.get(&name).copied()
+        self.ids_by_name
+            .get(&name)
+            .copied()
+            .filter(|id| !self.removed.contains(&id.get()))
     }
 
     fn categorize_id(&self, id: Self::IdType) -> Self::CategorizedId {
         categorize_id(id, self.names.len())
     }
+
+    fn remove(&mut self, id: LocalId<I>) -> Result<()> {
+        match self.categorize_id(id.0) {
+            CategorizedId::InArray(_) => {
+                self.removed.insert(id.get());
+                Ok(())
+            }
+            _ => Err(VrpnError::InvalidId(id.get())),
+        }
+    }
+
+    fn is_removed(&self, id: LocalId<I>) -> bool {
+        self.removed.contains(&id.get())
+    }
 }
 
 impl<I: RegisterableId> NameRegistrationContainer<I> {
-    fn try_insert(&mut self, name: &Name) -> Result<LocalId<I>> {
-        if self.names.len() > MAX_VEC_USIZE {
-            return Err(VrpnError::TooManyMappings);
+    /// Get the name registered for an ID, if it has not been removed.
+    pub(crate) fn try_get_name(&self, id: LocalId<I>) -> Option<&Name> {
+        if self.removed.contains(&id.get()) {
+            return None;
         }
+        usize::try_from(id.get())
+            .ok()
+            .and_then(|i| self.names.get(i))
+    }
+
+    fn try_insert(&mut self, name: &Name) -> Result<LocalId<I>> {
+        self.limit.check(self.names.len(), || name.0.clone())?;
         self.names.push(name.clone());
         let id = LocalId(I::new((self.names.len() - 1) as IdType));
         self.ids_by_name.insert(name.clone(), id);
@@ -162,13 +200,14 @@ impl<'a, I: RegisterableId> Iterator for NameRegIter<'a, I> {
     type Item = (LocalId<I>, &'a Name);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let id = self.i;
-        match self.container.names.get(id as usize) {
-            Some(name) => {
-                self.i += 1;
-                Some((LocalId(I::new(id.try_into().unwrap())), name))
+        loop {
+            let id = self.i;
+            let name = self.container.names.get(id as usize)?;
+            self.i += 1;
+            let id: IdType = id.try_into().unwrap();
+            if !self.container.removed.contains(&id) {
+                return Some((LocalId(I::new(id)), name));
             }
-            None => None,
         }
     }
 }
@@ -203,9 +242,7 @@ impl<T: LocalNameRegistration, U: std::fmt::Debug> PerIdData<T, U> {
 
     fn try_get_data_mut_impl(&mut self, id: T::IdType) -> Result<&mut U> {
         let id = id.get();
-
         let index: usize = id.try_into().map_err(|_| VrpnError::InvalidId(id))?;
-        let x = &self.data[index];
         self.data.get_mut(index).ok_or(VrpnError::InvalidId(id))
     }
 }
@@ -224,9 +261,12 @@ impl<T: LocalNameRegistration<CategorizedId = CategorizedId>, U: std::fmt::Debug
         Ok(match self.inner.try_insert_or_get(name.clone())? {
             InsertOrGet::Found(id) => InsertOrGet::Found(id),
             InsertOrGet::New(id) => {
+                // A restored ID keeps its data.
                 let index: usize = id.get().try_into().unwrap();
-                assert_eq!(self.data.len(), index);
-                self.data.push(U::default());
+                assert!(index <= self.data.len());
+                if index == self.data.len() {
+                    self.data.push(U::default());
+                }
                 InsertOrGet::New(id)
             }
         })
@@ -244,6 +284,14 @@ impl<T: LocalNameRegistration<CategorizedId = CategorizedId>, U: std::fmt::Debug
     fn categorize_id(&self, id: Self::IdType) -> Self::CategorizedId {
         self.inner.categorize_id(id)
     }
+
+    fn remove(&mut self, id: LocalId<Self::IdType>) -> Result<()> {
+        self.inner.remove(id)
+    }
+
+    fn is_removed(&self, id: LocalId<Self::IdType>) -> bool {
+        self.inner.is_removed(id)
+    }
 }
 
 impl<T: LocalNameRegistration<CategorizedId = CategorizedId>, U: std::fmt::Debug + Default>
@@ -267,3 +315,11 @@ impl<T: LocalNameRegistration<CategorizedId = CategorizedId>, U: std::fmt::Debug
         &self.inner
     }
 }
+
+impl<T: LocalNameRegistration<CategorizedId = CategorizedId>, U: std::fmt::Debug + Default> AsMut<T>
+    for PerIdData<T, U>
+{
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright 2018-2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Socket construction shared by the async backends.
+//!
+//! These create plain `std`/`socket2` sockets, in non-blocking mode,
+//! for the backends to wrap in their own types.
+
+use socket2::{Domain, Protocol, SockAddr, SockRef, Socket, TcpKeepalive, Type};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+/// Maximum number of pending connections on a listening socket.
+const LISTEN_BACKLOG: i32 = 128;
+
+fn domain_for(addr: &SocketAddr) -> Domain {
+    if addr.is_ipv4() {
+        Domain::IPV4
+    } else {
+        Domain::IPV6
+    }
+}
+
+/// The wildcard address, with port 0, in the same family as `addr`.
+fn unspecified_like(addr: &SocketAddr) -> SocketAddr {
+    let ip = if addr.is_ipv4() {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    };
+    SocketAddr::new(ip, 0)
+}
+
+/// Allow quickly re-binding a recently-used address, without letting others steal it.
+///
+/// On Windows, `SO_REUSEADDR` would let another socket bind the same port even while in use,
+/// so `SO_EXCLUSIVEADDRUSE` is used there instead.
+#[cfg(not(windows))]
+fn set_reuse(sock: &Socket) -> io::Result<()> {
+    sock.set_reuse_address(true)
+}
+
+#[cfg(windows)]
+fn set_reuse(sock: &Socket) -> io::Result<()> {
+    use std::os::windows::io::AsRawSocket;
+    use winapi::{
+        ctypes::{c_char, c_int},
+        um::winsock2::{setsockopt, SOCKET, SOCKET_ERROR, SOL_SOCKET, SO_EXCLUSIVEADDRUSE},
+    };
+    let value: c_int = 1;
+    // Safety: the socket handle is valid for the lifetime of `sock`,
+    // and the option value points to a live c_int of the size given.
+    let ret = unsafe {
+        setsockopt(
+            sock.as_raw_socket() as SOCKET,
+            SOL_SOCKET,
+            SO_EXCLUSIVEADDRUSE,
+            &value as *const c_int as *const c_char,
+            std::mem::size_of::<c_int>() as c_int,
+        )
+    };
+    if ret == SOCKET_ERROR {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Options for the sockets of endpoints, for tuning latency-sensitive deployments.
+///
+/// Each `None` leaves the system default.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SocketConfig {
+    /// `SO_RCVBUF`, in bytes.
+    pub recv_buffer_size: Option<usize>,
+    /// `SO_SNDBUF`, in bytes.
+    pub send_buffer_size: Option<usize>,
+    /// Turn on TCP keepalive, probing once the connection has been idle this long.
+    pub keepalive: Option<Duration>,
+    /// DSCP code point (0 to 63) to mark the packets of the low-latency UDP channel with.
+    ///
+    /// Only applied to IPv4 sockets.
+    pub udp_dscp: Option<u8>,
+}
+
+impl SocketConfig {
+    fn apply_buffer_sizes(&self, sock: &SockRef<'_>) -> io::Result<()> {
+        if let Some(size) = self.recv_buffer_size {
+            sock.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            sock.set_send_buffer_size(size)?;
+        }
+        Ok(())
+    }
+
+    /// Apply to the socket of a reliable (TCP) channel.
+    pub fn apply_to_tcp(&self, sock: SockRef<'_>) -> io::Result<()> {
+        self.apply_buffer_sizes(&sock)?;
+        if let Some(time) = self.keepalive {
+            sock.set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+        Ok(())
+    }
+
+    /// Apply to the socket of a low-latency (UDP) channel.
+    pub fn apply_to_udp(&self, sock: SockRef<'_>) -> io::Result<()> {
+        self.apply_buffer_sizes(&sock)?;
+        if let Some(dscp) = self.udp_dscp {
+            if dscp > 63 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("DSCP code point {} is out of range", dscp),
+                ));
+            }
+            if sock.local_addr()?.as_socket_ipv4().is_some() {
+                // The DSCP is the upper six bits of the TOS byte.
+                sock.set_tos(u32::from(dscp) << 2)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Create a non-blocking TCP socket, ready to connect to `addr`.
+///
+/// On Windows, the socket is bound to the wildcard address of the matching family first,
+/// as mainline VRPN does.
+pub fn make_tcp_socket(addr: SocketAddr) -> io::Result<Socket> {
+    let sock = Socket::new(domain_for(&addr), Type::STREAM, Some(Protocol::TCP))?;
+    sock.set_nonblocking(true)?;
+    sock.set_nodelay(true)?;
+    set_reuse(&sock)?;
+
+    if cfg!(windows) {
+        sock.bind(&SockAddr::from(unspecified_like(&addr)))?;
+    }
+    Ok(sock)
+}
+
+/// Whether an error from `connect` on a non-blocking socket just means
+/// the connection is still being established.
+pub fn is_connect_in_progress(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(libc::EINPROGRESS) {
+        return true;
+    }
+    e.kind() == io::ErrorKind::WouldBlock
+}
+
+/// Create a non-blocking TCP listener bound to `addr`.
+///
+/// If `addr` is an IPv6 wildcard address, the listener also accepts IPv4 clients,
+/// regardless of the platform default.
+pub fn make_tcp_listener(addr: SocketAddr) -> io::Result<std::net::TcpListener> {
+    let sock = Socket::new(domain_for(&addr), Type::STREAM, Some(Protocol::TCP))?;
+    set_reuse(&sock)?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        sock.set_only_v6(false)?;
+    }
+    sock.bind(&SockAddr::from(addr))?;
+    sock.listen(LISTEN_BACKLOG)?;
+    sock.set_nonblocking(true)?;
+    Ok(sock.into())
+}
+
+/// Create a non-blocking UDP socket bound to an ephemeral port,
+/// for talking to peers in the same address family as `peer`.
+pub fn make_udp_socket(peer: SocketAddr) -> io::Result<std::net::UdpSocket> {
+    let sock = Socket::new(domain_for(&peer), Type::DGRAM, Some(Protocol::UDP))?;
+    sock.set_nonblocking(true)?;
+    set_reuse(&sock)?;
+    sock.bind(&SockAddr::from(unspecified_like(&peer)))?;
+    Ok(sock.into())
+}
+
+/// Create a non-blocking UDP socket bound to `addr`, for a server to receive connection requests on.
+///
+/// Like `make_tcp_listener`, an IPv6 wildcard address also receives from IPv4 clients.
+pub fn make_udp_listener(addr: SocketAddr) -> io::Result<std::net::UdpSocket> {
+    let sock = Socket::new(domain_for(&addr), Type::DGRAM, Some(Protocol::UDP))?;
+    set_reuse(&sock)?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        sock.set_only_v6(false)?;
+    }
+    sock.bind(&SockAddr::from(addr))?;
+    sock.set_nonblocking(true)?;
+    Ok(sock.into())
+}
+
+/// Create a non-blocking UDP socket receiving datagrams sent to the IPv4 multicast `group` on `port`.
+///
+/// Several of these may share a port on the same machine.
+pub fn make_multicast_listener(group: Ipv4Addr, port: u16) -> io::Result<std::net::UdpSocket> {
+    let sock = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    sock.set_reuse_address(true)?;
+    sock.bind(&SockAddr::from(SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        port,
+    )))?;
+    sock.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+    sock.set_nonblocking(true)?;
+    Ok(sock.into())
+}
+
+/// Create a non-blocking UDP socket for sending to IPv4 multicast groups,
+/// reaching at most `ttl` router hops away.
+///
+/// Datagrams are also delivered to listeners on this machine.
+pub fn make_multicast_sender(ttl: u32) -> io::Result<std::net::UdpSocket> {
+    let sock = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    sock.set_multicast_ttl_v4(ttl)?;
+    sock.set_multicast_loop_v4(true)?;
+    sock.bind(&SockAddr::from(SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        0,
+    )))?;
+    sock.set_nonblocking(true)?;
+    Ok(sock.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpStream;
+
+    #[test]
+    fn ipv4_listener() {
+        let listener = make_tcp_listener("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert!(addr.is_ipv4());
+        let _client = TcpStream::connect(addr).unwrap();
+        listener.set_nonblocking(false).unwrap();
+        assert!(listener.accept().is_ok());
+    }
+
+    #[test]
+    fn ipv6_wildcard_listener_is_dual_stack() {
+        let listener = match make_tcp_listener("[::]:0".parse().unwrap()) {
+            Ok(listener) => listener,
+            // No IPv6 on this machine.
+            Err(_) => return,
+        };
+        let port = listener.local_addr().unwrap().port();
+        let _client =
+            TcpStream::connect(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)).unwrap();
+        listener.set_nonblocking(false).unwrap();
+        assert!(listener.accept().is_ok());
+    }
+
+    #[test]
+    fn udp_listener_binds_requested_port() {
+        let tcp = make_tcp_listener("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let udp = make_udp_listener(addr).unwrap();
+        assert_eq!(udp.local_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn socket_config() {
+        let config = SocketConfig {
+            recv_buffer_size: Some(65536),
+            send_buffer_size: Some(65536),
+            keepalive: Some(Duration::from_secs(10)),
+            udp_dscp: Some(46),
+        };
+        let listener = make_tcp_listener("127.0.0.1:0".parse().unwrap()).unwrap();
+        let tcp = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        config.apply_to_tcp(SockRef::from(&tcp)).unwrap();
+        let sock = SockRef::from(&tcp);
+        assert!(sock.keepalive().unwrap());
+        // The system may round the size up, or double it as Linux does.
+        assert!(sock.recv_buffer_size().unwrap() >= 65536);
+
+        let udp = make_udp_socket("127.0.0.1:3883".parse().unwrap()).unwrap();
+        config.apply_to_udp(SockRef::from(&udp)).unwrap();
+        assert_eq!(SockRef::from(&udp).tos().unwrap(), 46 << 2);
+
+        let bad = SocketConfig {
+            udp_dscp: Some(64),
+            ..SocketConfig::default()
+        };
+        assert!(bad.apply_to_udp(SockRef::from(&udp)).is_err());
+    }
+
+    #[test]
+    fn udp_matches_family() {
+        let v4 = make_udp_socket("192.0.2.1:3883".parse().unwrap()).unwrap();
+        assert!(v4.local_addr().unwrap().is_ipv4());
+        if let Ok(v6) = make_udp_socket("[2001:db8::1]:3883".parse().unwrap()) {
+            assert!(v6.local_addr().unwrap().is_ipv6());
+        }
+    }
+}
@@ -2,20 +2,42 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
-use crate::{constants, Result, VrpnError};
-use std::{net::SocketAddr, str::FromStr};
+use crate::{constants, data_types::SenderName, Result, TlsClientOptions, VrpnError};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+};
 use url::Url;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum Scheme {
     UdpAndTcp,
     TcpOnly,
+    /// Connects like `Scheme::UdpAndTcp`, then asks the server to send all user messages
+    /// over UDP, like `udp://host:3883`, so a lost message never holds up later ones.
+    /// Not part of mainline VRPN, whose servers ignore the request.
+    UdpOnly,
+    /// A Unix domain socket on the local machine, like `unix:///run/vrpn.sock`.
+    Unix,
+    /// VRPN messages framed in WebSocket binary frames, like `ws://host:3883/vrpn`.
+    WebSocket,
+    /// A server in the same process, like `memory://name`, skipping the network stack:
+    /// for tests, or clients co-located with the server.
+    Memory,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct ServerInfo {
+    /// The address to connect to. Unspecified for `Scheme::Unix` and `Scheme::Memory`.
     pub socket_addr: SocketAddr,
     pub scheme: Scheme,
+    /// The socket path for `Scheme::Unix`, the request path for `Scheme::WebSocket`,
+    /// or the server name for `Scheme::Memory`.
+    pub path: Option<PathBuf>,
+    /// If set, the reliable channel is wrapped in TLS. Only for `Scheme::TcpOnly`.
+    pub tls: Option<Arc<TlsClientOptions>>,
 }
 
 impl ServerInfo {
@@ -23,17 +45,80 @@ impl ServerInfo {
         ServerInfo {
             socket_addr,
             scheme,
+            path: None,
+            tls: None,
         }
     }
+
+    /// Encrypt the connection to this server with TLS.
+    pub fn with_tls(mut self, tls: TlsClientOptions) -> ServerInfo {
+        self.tls = Some(Arc::new(tls));
+        self
+    }
+
+    /// Create server info for a Unix domain socket at the given path.
+    pub fn unix(path: impl Into<PathBuf>) -> ServerInfo {
+        ServerInfo {
+            socket_addr: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            scheme: Scheme::Unix,
+            path: Some(path.into()),
+            tls: None,
+        }
+    }
+
+    /// Create server info for a server in this process, listening as `name`.
+    ///
+    /// See `ConnectionIp::new_server_memory`.
+    pub fn memory(name: impl Into<PathBuf>) -> ServerInfo {
+        ServerInfo {
+            socket_addr: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            scheme: Scheme::Memory,
+            path: Some(name.into()),
+            tls: None,
+        }
+    }
+
+    /// The suffix, like `@127.0.0.1:3883`, that tells this server's senders apart
+    /// from those of other servers on the same connection.
+    ///
+    /// See `ConnectionIp::add_server`.
+    pub fn sender_suffix(&self) -> String {
+        match (&self.scheme, &self.path) {
+            (Scheme::Unix, Some(path)) => format!("@{}", path.display()),
+            (Scheme::Memory, Some(name)) => format!("@memory://{}", name.display()),
+            _ => format!("@{}", self.socket_addr),
+        }
+    }
+
+    /// The local name for a sender called `name` on this server,
+    /// when this server was added to a connection with `ConnectionIp::add_server`.
+    pub fn qualified_sender_name(&self, name: &str) -> SenderName {
+        SenderName(format!("{}{}", name, self.sender_suffix()).into())
+    }
 }
 
+/// A parsed VRPN device URL, like `Tracker0@localhost:3883`:
+/// the device (sender) name before the `@`, and the server after it.
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct DeviceInfo {
     pub device: Option<String>,
     pub server: ServerInfo,
 }
 
-const SCHEMES: &[&str] = &["x-vrpn:", "x-vrsh:", "tcp:", "mpi:"];
+impl DeviceInfo {
+    pub fn new(device: Option<String>, server: ServerInfo) -> DeviceInfo {
+        DeviceInfo { device, server }
+    }
+
+    /// Get the device name as a sender name, if there is one.
+    pub fn sender_name(&self) -> Option<SenderName> {
+        self.device
+            .as_ref()
+            .map(|device| SenderName(device.clone().into()))
+    }
+}
+
+const SCHEMES: &[&str] = &["x-vrpn:", "x-vrsh:", "tcp:", "udp:", "mpi:"];
 
 /// Makes sure there's a scheme followed by ://, and ending with a trailing slash.
 fn normalize_scheme(server: &str) -> String {
@@ -54,12 +139,50 @@ fn normalize_scheme(server: &str) -> String {
             return format!("{}//{}/", *scheme, server.trim_start_matches(*scheme));
         }
     }
-    return format!("x-vrpn://{}/", server);
+    format!("x-vrpn://{}/", server)
 }
 
 impl FromStr for ServerInfo {
     type Err = VrpnError;
     fn from_str(url: &str) -> Result<ServerInfo> {
+        if let Some(path) = url.strip_prefix("unix://") {
+            if !path.starts_with('/') {
+                return Err(VrpnError::OtherMessage(format!(
+                    "unix socket address {} must have an absolute path",
+                    url
+                )));
+            }
+            return Ok(ServerInfo::unix(path));
+        }
+        if let Some(name) = url.strip_prefix("memory://") {
+            return Ok(ServerInfo::memory(name.trim_end_matches('/')));
+        }
+        if url.starts_with("wss://") {
+            return Err(VrpnError::OtherMessage(format!(
+                "wss scheme of address {} not supported",
+                url
+            )));
+        }
+        if url.starts_with("ws://") {
+            let mut parsed = Url::parse(url)?;
+            // Default to the VRPN port rather than the HTTP one.
+            if parsed.port().is_none() {
+                let _ = parsed.set_port(Some(constants::DEFAULT_PORT));
+            }
+            let socket_addr: SocketAddr = parsed
+                .socket_addrs(|| Some(constants::DEFAULT_PORT))?
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    VrpnError::OtherMessage(format!("could not parse address {}", url))
+                })?;
+            return Ok(ServerInfo {
+                socket_addr,
+                scheme: Scheme::WebSocket,
+                path: Some(parsed.path().into()),
+                tls: None,
+            });
+        }
         let urlpart = normalize_scheme(url);
 
         let parsed = Url::parse(&urlpart)?;
@@ -67,6 +190,7 @@ impl FromStr for ServerInfo {
         let scheme = match parsed.scheme() {
             "x-vrpn" => Scheme::UdpAndTcp,
             "tcp" => Scheme::TcpOnly,
+            "udp" => Scheme::UdpOnly,
             "x-vrsh" => {
                 return Err(VrpnError::OtherMessage(format!(
                     "x-vrsh scheme of address {} (url portion {}) not supported",
@@ -96,10 +220,7 @@ impl FromStr for ServerInfo {
                     url, urlpart
                 ))
             })?;
-        Ok(ServerInfo {
-            socket_addr,
-            scheme,
-        })
+        Ok(ServerInfo::new(socket_addr, scheme))
     }
 }
 impl FromStr for DeviceInfo {
@@ -108,10 +229,10 @@ impl FromStr for DeviceInfo {
         let parts: Vec<&str> = url.split('@').collect();
         let device = match parts.len() {
             1 => None,
-            2 => Some(String::from(parts[0])),
+            2 if !parts[0].is_empty() => Some(String::from(parts[0])),
             _ => {
                 return Err(VrpnError::OtherMessage(format!(
-                    "could not parse address {}",
+                    "could not parse device name of address {}",
                     url
                 )));
             }
@@ -172,6 +293,55 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn device_parsing() {
+        let info = "Tracker0@tcp://127.0.0.1:3883"
+            .parse::<DeviceInfo>()
+            .unwrap();
+        assert_eq!(info.device.as_deref(), Some("Tracker0"));
+        assert_eq!(
+            info.server,
+            ServerInfo::new(to_addr("127.0.0.1:3883"), Scheme::TcpOnly)
+        );
+        assert_eq!(info.sender_name(), Some(SenderName("Tracker0".into())));
+
+        let info = "Tracker0@127.0.0.1".parse::<DeviceInfo>().unwrap();
+        assert_eq!(info.server.socket_addr.port(), constants::DEFAULT_PORT);
+
+        assert_eq!(
+            "127.0.0.1:3883"
+                .parse::<DeviceInfo>()
+                .unwrap()
+                .sender_name(),
+            None
+        );
+        assert!("@127.0.0.1:3883".parse::<DeviceInfo>().is_err());
+
+        let info = "Tracker0@unix:///run/vrpn.sock"
+            .parse::<DeviceInfo>()
+            .unwrap();
+        assert_eq!(info.device.as_deref(), Some("Tracker0"));
+        assert_eq!(info.server.scheme, Scheme::Unix);
+        assert_eq!(info.server, ServerInfo::unix("/run/vrpn.sock"));
+        assert!("unix://relative.sock".parse::<ServerInfo>().is_err());
+
+        let info = "Tracker0@memory://lab".parse::<DeviceInfo>().unwrap();
+        assert_eq!(info.server, ServerInfo::memory("lab"));
+        assert_eq!(info.server.sender_suffix(), "@memory://lab");
+        assert!("a@b@127.0.0.1:3883".parse::<DeviceInfo>().is_err());
+
+        let info = "Tracker0@ws://127.0.0.1/vrpn"
+            .parse::<DeviceInfo>()
+            .unwrap();
+        assert_eq!(info.server.scheme, Scheme::WebSocket);
+        assert_eq!(info.server.socket_addr.port(), constants::DEFAULT_PORT);
+        assert_eq!(info.server.path, Some("/vrpn".into()));
+        let info = "ws://127.0.0.1:8080".parse::<ServerInfo>().unwrap();
+        assert_eq!(info.socket_addr, to_addr("127.0.0.1:8080"));
+        assert_eq!(info.path, Some("/".into()));
+        assert!("wss://127.0.0.1".parse::<ServerInfo>().is_err());
+    }
     proptest! {
         #[test]
         fn noncrash_weird_server(ref s in "\\PC*") {
@@ -184,7 +354,8 @@ mod tests {
             let proto_and_scheme = [
                 ("", Scheme::UdpAndTcp),
                 ("x-vrpn:", Scheme::UdpAndTcp),
-                ("tcp:", Scheme::TcpOnly)
+                ("tcp:", Scheme::TcpOnly),
+                ("udp:", Scheme::UdpOnly)
             ];
 
             for (proto, scheme) in proto_and_scheme.iter() {
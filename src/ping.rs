@@ -11,11 +11,13 @@ use crate::{
         MessageTypeIdentifier, SenderName, StaticMessageTypeName, TypedMessage, TypedMessageBody,
     },
     handler::{HandlerCode, HandlerHandle, TypedBodylessHandler},
+    lifecycle::LifecycleEvent,
+    sync::Mutex,
     Connection, VrpnError,
 };
 use std::{
     fmt,
-    sync::{Arc, Mutex, Weak},
+    sync::{Arc, Weak},
     time::{Duration, Instant},
 };
 
@@ -23,6 +25,7 @@ use std::{
 ///
 /// Has no body.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Ping;
 const PING_MESSAGE: StaticMessageTypeName = StaticMessageTypeName(b"vrpn_Base ping_message");
 impl Default for Ping {
@@ -41,6 +44,7 @@ impl TypedMessageBody for Ping {
 ///
 /// Has no body.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pong;
 const PONG_MESSAGE: StaticMessageTypeName = StaticMessageTypeName(b"vrpn_Base pong_message");
 impl Default for Pong {
@@ -69,11 +73,11 @@ impl TypedBodylessHandler for PongHandler {
     fn handle_typed_bodyless(&mut self, _header: &MessageHeader) -> Result<HandlerCode, VrpnError> {
         match self.inner.upgrade() {
             Some(inner) => {
-                let mut inner = inner.lock()?;
+                let mut inner = inner.lock();
                 inner.unanswered_ping = None;
                 inner.last_warning = None;
                 if inner.flatlined {
-                    eprintln!("Remote host started responding again");
+                    info!("Remote host started responding again");
                     inner.flatlined = false;
                 }
                 Ok(HandlerCode::ContinueProcessing)
@@ -140,7 +144,7 @@ impl<T: Connection + 'static> Client<T> {
 
     pub fn initiate_ping_cycle(&self) -> Result<(), VrpnError> {
         {
-            let mut inner = self.inner.lock()?;
+            let mut inner = self.inner.lock();
             inner.unanswered_ping = Some(Instant::now());
         }
         self.send_ping()
@@ -150,20 +154,32 @@ impl<T: Connection + 'static> Client<T> {
     ///
     /// Returns the duration since the first unanswered ping,
     /// or None if there are no unanswered pings.
+    ///
+    /// When the server first seems unresponsive, a `LifecycleEvent::PingTimeout` is sent.
     pub fn check_ping_cycle(&self) -> Result<Option<Duration>, VrpnError> {
-        let mut inner = self.inner.lock()?;
+        let mut inner = self.inner.lock();
         if let (Some(unanswered), Some(last_warning)) =
             (inner.unanswered_ping, &mut inner.last_warning)
         {
             let now = Instant::now();
             let radio_silence = now.checked_duration_since(unanswered).unwrap();
+            let mut timed_out = false;
             if now.checked_duration_since(*last_warning).unwrap() > Duration::from_secs(1) {
                 *last_warning = now;
-                if radio_silence > Duration::from_secs(10) {
+                if radio_silence > Duration::from_secs(10) && !inner.flatlined {
                     inner.flatlined = true;
+                    timed_out = true;
                 }
                 self.send_ping()?;
             }
+            // The pong handler locks these the other way around.
+            drop(inner);
+            if timed_out {
+                self.connection
+                    .dispatcher()
+                    .read()
+                    .emit_event(LifecycleEvent::PingTimeout(radio_silence));
+            }
             Ok(Some(radio_silence))
         } else {
             Ok(None)
@@ -171,7 +187,7 @@ impl<T: Connection + 'static> Client<T> {
     }
 
     fn send_ping(&self) -> Result<(), VrpnError> {
-        let msg = TypedMessage::new(None, self.ping_type, self.sender, Pong::default());
+        let msg = TypedMessage::new(None, self.ping_type, self.sender, Pong);
         self.connection
             .pack_message(msg, ClassOfService::RELIABLE)?;
         Ok(())
@@ -191,7 +207,7 @@ impl<T: Connection + Send> TypedBodylessHandler for PingHandler<T> {
         // TODO use sender from header?
         match self.connection.upgrade() {
             Some(connection) => {
-                let msg = TypedMessage::new(None, self.pong_type, self.sender, Pong::default());
+                let msg = TypedMessage::new(None, self.pong_type, self.sender, Pong);
                 connection.pack_message(msg, ClassOfService::RELIABLE)?;
                 Ok(HandlerCode::ContinueProcessing)
             }
@@ -204,7 +220,7 @@ impl<T: Connection + Send> TypedBodylessHandler for PingHandler<T> {
 /// and server (replies with pong)
 #[derive(Debug)]
 pub struct Server {
-    handler: HandlerHandle,
+    _handler: HandlerHandle,
 }
 
 impl Server {
@@ -221,7 +237,7 @@ impl Server {
             }),
             Some(sender),
         )?;
-        Ok(Server { handler })
+        Ok(Server { _handler: handler })
     }
 
     pub fn new_from_name<T: Connection + 'static>(
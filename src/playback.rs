@@ -0,0 +1,465 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Playing back a recording as if its messages were arriving on a live connection.
+//!
+//! A recording is in the same format as a capture (see `capture`):
+//! a cookie, then the messages as sent over TCP.
+//! It may be played with its original timing, sped up or slowed down,
+//! or as fast as possible, and may be paused and moved to another time.
+//!
+//! Nothing drives playback in the background: call `ConnectionFile::play`
+//! regularly, e.g. after waiting for `ConnectionFile::next_message_delay`.
+
+use crate::{
+    buffer_unbuffer::UnbufferFrom,
+    codec::maybe_decode_one,
+    connection::{ConnectionCore, ConnectionStatus},
+    data_types::{ClassOfService, CookieData, GenericMessage, Message, TimeVal},
+    endpoint::{handle_system_command, is_known_system_message, parse_system_message},
+    sync::Mutex,
+    Connection, Endpoint, EndpointGeneric, Result, TranslationTables, TypeDispatcher,
+};
+use bytes::{Buf, Bytes};
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How quickly to play back a recording.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackRate {
+    /// Keep the recorded time between messages, divided by this (positive) speed:
+    /// 1.0 is real time, 2.0 twice as fast.
+    Timed(f64),
+    /// Deliver every remaining message on the next call to `play`.
+    AsFastAsPossible,
+}
+
+impl Default for PlaybackRate {
+    fn default() -> Self {
+        PlaybackRate::Timed(1.0)
+    }
+}
+
+/// The only endpoint of a `ConnectionFile`: it tracks the recorded descriptions,
+/// and discards any messages sent, as there is nobody to receive them.
+#[derive(Debug, Default)]
+pub struct EndpointFile {
+    translation: TranslationTables,
+}
+
+impl Endpoint for EndpointFile {
+    fn translation_tables(&self) -> &TranslationTables {
+        &self.translation
+    }
+    fn translation_tables_mut(&mut self) -> &mut TranslationTables {
+        &mut self.translation
+    }
+    fn send_system_change(&self, _message: crate::endpoint::SystemCommand) -> Result<()> {
+        // Recorded system messages are applied directly when played.
+        Ok(())
+    }
+    fn buffer_generic_message(
+        &mut self,
+        _msg: GenericMessage,
+        _class: ClassOfService,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct PlaybackState {
+    messages: Vec<GenericMessage>,
+    /// Index of the next message to deliver.
+    next: usize,
+    rate: PlaybackRate,
+    paused: bool,
+    /// Recording time reached.
+    position: TimeVal,
+    /// A wall-clock time and the recording time it corresponds to,
+    /// set on the first call to `play` after any change.
+    anchor: Option<(Instant, TimeVal)>,
+}
+
+impl PlaybackState {
+    /// Recording time that corresponds to `now`, or `None` if there is no limit.
+    fn target(&mut self, now: Instant) -> Option<TimeVal> {
+        match self.rate {
+            PlaybackRate::AsFastAsPossible => None,
+            PlaybackRate::Timed(speed) => {
+                let (wall, recorded) = *self.anchor.get_or_insert((now, self.position));
+                let elapsed = now.saturating_duration_since(wall).as_secs_f64() * speed;
+                Some(TimeVal::from_micros(
+                    recorded.as_micros() + (elapsed * 1e6) as i64,
+                ))
+            }
+        }
+    }
+
+    fn reset_anchor(&mut self) {
+        self.anchor = None;
+    }
+}
+
+/// A connection whose incoming messages come from a recording.
+///
+/// Messages sent on it are discarded.
+#[derive(Debug)]
+pub struct ConnectionFile {
+    core: ConnectionCore<EndpointFile>,
+    state: Mutex<PlaybackState>,
+}
+
+impl ConnectionFile {
+    /// Load a recording from memory.
+    ///
+    /// Any incomplete message at the end is ignored.
+    pub fn from_bytes(data: Bytes) -> Result<Arc<ConnectionFile>> {
+        let mut buf = data;
+        // Either a file or network cookie is fine: only the messages matter.
+        let _ = CookieData::unbuffer_from(&mut buf)?;
+        let mut messages = Vec::new();
+        while buf.has_remaining() {
+            match maybe_decode_one(&mut buf)? {
+                Some(msg) => messages.push(msg.into_inner()),
+                None => break,
+            }
+        }
+        let position = messages
+            .first()
+            .map(|msg| msg.header.time)
+            .unwrap_or_default();
+        Ok(Arc::new(ConnectionFile {
+            core: ConnectionCore::without_logs(vec![Some(EndpointFile::default())]),
+            state: Mutex::new(PlaybackState {
+                messages,
+                next: 0,
+                rate: PlaybackRate::default(),
+                paused: false,
+                position,
+                anchor: None,
+            }),
+        }))
+    }
+
+    /// Load a recording from a file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Arc<ConnectionFile>> {
+        ConnectionFile::from_bytes(Bytes::from(std::fs::read(path)?))
+    }
+
+    /// Change the playback rate, from the position reached by the last call to `play`.
+    pub fn set_rate(&self, rate: PlaybackRate) -> Result<()> {
+        let mut state = self.state.lock();
+        state.rate = rate;
+        state.reset_anchor();
+        Ok(())
+    }
+
+    /// The playback rate.
+    pub fn rate(&self) -> Result<PlaybackRate> {
+        Ok(self.state.lock().rate)
+    }
+
+    /// Stop delivering messages, at the position reached by the last call to `play`.
+    pub fn pause(&self) -> Result<()> {
+        let mut state = self.state.lock();
+        state.paused = true;
+        state.reset_anchor();
+        Ok(())
+    }
+
+    /// Continue delivering messages: recording time starts moving again on the next call to `play`.
+    pub fn resume(&self) -> Result<()> {
+        self.state.lock().paused = false;
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> Result<bool> {
+        Ok(self.state.lock().paused)
+    }
+
+    /// Move to a time in the recording: the next message delivered is the first recorded at or after it.
+    ///
+    /// Sender and type descriptions in the skipped part are still applied,
+    /// so that later messages can be understood.
+    pub fn seek(&self, time: TimeVal) -> Result<()> {
+        let mut state = self.state.lock();
+        let dispatcher = self.core.type_dispatcher.read();
+        let mut endpoints = self.core.endpoints.lock();
+        let endpoint = endpoints[0].as_mut().unwrap();
+        let target = state
+            .messages
+            .iter()
+            .position(|msg| msg.header.time >= time)
+            .unwrap_or(state.messages.len());
+        for msg in &state.messages[state.next.min(target)..target] {
+            if msg.is_system_message() && is_known_system_message(msg.header.message_type) {
+                handle_system_command(
+                    &dispatcher,
+                    endpoint.translation_tables_mut(),
+                    parse_system_message(msg.clone())?,
+                )?;
+            }
+        }
+        state.next = target;
+        state.position = time;
+        state.reset_anchor();
+        Ok(())
+    }
+
+    /// The recording time reached.
+    pub fn position(&self) -> Result<TimeVal> {
+        Ok(self.state.lock().position)
+    }
+
+    /// Time of the first message in the recording.
+    pub fn start_time(&self) -> Result<Option<TimeVal>> {
+        Ok(self.state.lock().messages.first().map(|m| m.header.time))
+    }
+
+    /// Time of the last message in the recording.
+    pub fn end_time(&self) -> Result<Option<TimeVal>> {
+        Ok(self.state.lock().messages.last().map(|m| m.header.time))
+    }
+
+    /// Whether every message has been delivered.
+    pub fn is_finished(&self) -> Result<bool> {
+        let state = self.state.lock();
+        Ok(state.next >= state.messages.len())
+    }
+
+    /// How long after `now` the next message is due,
+    /// or `None` if paused or finished.
+    pub fn next_message_delay(&self, now: Instant) -> Result<Option<Duration>> {
+        let mut state = self.state.lock();
+        if state.paused {
+            return Ok(None);
+        }
+        let next_time = match state.messages.get(state.next) {
+            Some(msg) => msg.header.time,
+            None => return Ok(None),
+        };
+        let speed = match state.rate {
+            PlaybackRate::AsFastAsPossible => return Ok(Some(Duration::ZERO)),
+            PlaybackRate::Timed(speed) => speed,
+        };
+        let reached = state.target(now).unwrap_or(next_time);
+        let remaining = (next_time.as_micros() - reached.as_micros()).max(0) as f64 / speed;
+        Ok(Some(Duration::from_secs_f64(remaining / 1e6)))
+    }
+
+    /// Deliver the messages due by `now` to their handlers, returning how many were delivered.
+    ///
+    /// With a timed rate, the first call after a change (or the first at all)
+    /// sets which wall-clock time corresponds to the position reached.
+    pub fn play(&self, now: Instant) -> Result<usize> {
+        let mut state = self.state.lock();
+        let dispatcher = self.core.type_dispatcher.read();
+        let mut endpoints = self.core.endpoints.lock();
+        let endpoint = endpoints[0].as_mut().unwrap();
+        dispatcher.flush_throttled()?;
+        if state.paused {
+            return Ok(0);
+        }
+        let target = state.target(now);
+        let mut delivered = 0;
+        while let Some(msg) = state.messages.get(state.next) {
+            if matches!(target, Some(target) if msg.header.time > target) {
+                break;
+            }
+            let msg = msg.clone();
+            state.next += 1;
+            state.position = state.position.max(msg.header.time);
+            deliver(&dispatcher, endpoint, msg)?;
+            delivered += 1;
+        }
+        if let Some(target) = target {
+            state.position = state.position.max(target);
+        }
+        Ok(delivered)
+    }
+}
+
+fn deliver(
+    dispatcher: &TypeDispatcher,
+    endpoint: &mut EndpointFile,
+    msg: GenericMessage,
+) -> Result<()> {
+    let msg = endpoint.map_remote_message_to_local(msg)?;
+    if msg.is_system_message() && !is_known_system_message(msg.header.message_type) {
+        dispatcher.call_system_handler(&msg)
+    } else if msg.is_system_message() {
+        handle_system_command(
+            dispatcher,
+            endpoint.translation_tables_mut(),
+            parse_system_message(msg)?,
+        )?;
+        Ok(())
+    } else {
+        dispatcher.call(&msg).map(|_| ())
+    }
+}
+
+impl Connection for ConnectionFile {
+    type SpecificEndpoint = EndpointFile;
+
+    fn connection_core(&self) -> &ConnectionCore<Self::SpecificEndpoint> {
+        &self.core
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        match self.is_finished() {
+            Ok(false) => ConnectionStatus::ClientConnected,
+            _ => ConnectionStatus::ClientDisconnected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        buffer_unbuffer::{BufferTo, BytesMutExtras},
+        data_types::{id_types::*, MessageTypeId, Microseconds, Quat, Seconds, TypedMessage, Vec3},
+        handler::{HandlerCode, TypedHandler},
+        tracker::PoseReport,
+        type_dispatcher::TryIntoDescriptionMessage,
+    };
+    use bytes::BytesMut;
+    use std::{
+        convert::TryFrom,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    const START: i32 = 1_650_000_000;
+
+    fn at(seconds: i32, micros: i32) -> TimeVal {
+        TimeVal::new(Seconds(START + seconds), Microseconds(micros))
+    }
+
+    /// A recording of a tracker reporting at 0, 1, and 2 seconds.
+    fn recording() -> Bytes {
+        let mut messages = vec![
+            LocalId(SenderId(0))
+                .try_into_description_message("Tracker0")
+                .unwrap(),
+            LocalId(MessageTypeId(0))
+                .try_into_description_message("vrpn_Tracker Pos_Quat")
+                .unwrap(),
+        ];
+        for message in &mut messages {
+            message.header.time = at(0, 0);
+        }
+        for i in 0..3 {
+            let pose = PoseReport {
+                sensor: Sensor(i),
+                pos: Vec3::new(0.0, 0.0, 0.0),
+                quat: Quat::identity(),
+            };
+            let msg = TypedMessage::new(Some(at(i, 0)), MessageTypeId(0), SenderId(0), pose);
+            messages.push(GenericMessage::try_from(msg).unwrap());
+        }
+        let mut buf = BytesMut::allocate_and_buffer(CookieData::make_cookie()).unwrap();
+        for (i, msg) in messages.into_iter().enumerate() {
+            msg.into_sequenced_message(SequenceNumber(i as u32))
+                .buffer_to(&mut buf)
+                .unwrap();
+        }
+        buf.freeze()
+    }
+
+    struct CountPoses {
+        count: Arc<AtomicUsize>,
+    }
+    impl TypedHandler for CountPoses {
+        type Item = PoseReport;
+        fn handle_typed(&mut self, _msg: &TypedMessage<PoseReport>) -> Result<HandlerCode> {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            Ok(HandlerCode::ContinueProcessing)
+        }
+    }
+
+    fn open() -> (Arc<ConnectionFile>, Arc<AtomicUsize>) {
+        let conn = ConnectionFile::from_bytes(recording()).unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        conn.add_typed_handler(
+            Box::new(CountPoses {
+                count: Arc::clone(&count),
+            }),
+            None,
+        )
+        .unwrap();
+        (conn, count)
+    }
+
+    #[test]
+    fn timed() {
+        let (conn, count) = open();
+        assert_eq!(conn.start_time().unwrap(), Some(at(0, 0)));
+        assert_eq!(conn.end_time().unwrap(), Some(at(2, 0)));
+
+        let start = Instant::now();
+        // Descriptions and the first report.
+        assert_eq!(conn.play(start).unwrap(), 3);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            conn.next_message_delay(start).unwrap(),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(conn.play(start + Duration::from_millis(500)).unwrap(), 0);
+        assert_eq!(conn.play(start + Duration::from_secs(1)).unwrap(), 1);
+        assert_eq!(conn.position().unwrap(), at(1, 0));
+
+        conn.set_rate(PlaybackRate::Timed(2.0)).unwrap();
+        let later = start + Duration::from_secs(10);
+        assert_eq!(conn.play(later).unwrap(), 0);
+        assert_eq!(
+            conn.next_message_delay(later).unwrap(),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(conn.play(later + Duration::from_millis(500)).unwrap(), 1);
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+        assert!(conn.is_finished().unwrap());
+        assert_eq!(conn.status(), ConnectionStatus::ClientDisconnected);
+        assert_eq!(conn.next_message_delay(later).unwrap(), None);
+    }
+
+    #[test]
+    fn as_fast_as_possible() {
+        let (conn, count) = open();
+        conn.set_rate(PlaybackRate::AsFastAsPossible).unwrap();
+        assert_eq!(
+            conn.next_message_delay(Instant::now()).unwrap(),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(conn.play(Instant::now()).unwrap(), 5);
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+        assert_eq!(conn.position().unwrap(), at(2, 0));
+    }
+
+    #[test]
+    fn pause_and_seek() {
+        let (conn, count) = open();
+        let start = Instant::now();
+        conn.pause().unwrap();
+        assert_eq!(conn.play(start).unwrap(), 0);
+        assert_eq!(conn.next_message_delay(start).unwrap(), None);
+
+        // Skipping the descriptions still applies them.
+        conn.seek(at(1, 500_000)).unwrap();
+        conn.resume().unwrap();
+        assert_eq!(conn.play(start + Duration::from_secs(5)).unwrap(), 0);
+        assert_eq!(conn.play(start + Duration::from_millis(5500)).unwrap(), 1);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // Back to the start: descriptions are delivered again, harmlessly.
+        conn.seek(at(0, 0)).unwrap();
+        conn.set_rate(PlaybackRate::AsFastAsPossible).unwrap();
+        assert_eq!(conn.play(start).unwrap(), 5);
+        assert_eq!(count.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! How much each endpoint receives per poll, shared by all async backends.
+
+use futures::{Stream, StreamExt};
+use std::task::{Context, Poll};
+
+use crate::{
+    buffer_unbuffer::BufferSize,
+    compression::update_peer_compression,
+    data_types::{GenericMessage, Message},
+    endpoint::{
+        is_known_system_message, parse_system_message, update_udp_only, Endpoint, EndpointGeneric,
+    },
+    message_history::Direction,
+    timestamp_policy::{stamp_now, ReceiveTimestamp},
+    tracker::update_sensor_filter,
+    Result, TypeDispatcher,
+};
+
+/// What to do when an endpoint reaches its per-poll limit with messages still waiting.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum YieldStrategy {
+    /// Wake the task right away, so it is polled again once other tasks have had a turn.
+    ///
+    /// This is the default.
+    #[default]
+    Wake,
+    /// Leave the remaining messages for whenever the connection is next polled.
+    ///
+    /// Suits applications that poll once per frame: a burst is spread over several frames
+    /// instead of stalling one, and the task is not woken again in between.
+    WaitForNextPoll,
+}
+
+/// Limits on how much a single poll of an endpoint receives and dispatches.
+///
+/// Lower limits bound how long one busy endpoint can hold up the others (and the caller),
+/// higher limits reduce latency for high-rate streams.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct PollConfig {
+    /// Most messages to dispatch per poll, or `None` for no limit.
+    pub max_messages_per_tick: Option<usize>,
+    /// Most message body bytes to dispatch per poll, or `None` for no limit.
+    ///
+    /// Checked after each message, so at least one message is always dispatched.
+    pub max_bytes_per_tick: Option<usize>,
+    /// What to do when a limit is reached.
+    pub yield_strategy: YieldStrategy,
+}
+
+/// Default number of messages dispatched per poll of an endpoint.
+pub const DEFAULT_MAX_MESSAGES_PER_TICK: usize = 10;
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        PollConfig {
+            max_messages_per_tick: Some(DEFAULT_MAX_MESSAGES_PER_TICK),
+            max_bytes_per_tick: None,
+            yield_strategy: YieldStrategy::default(),
+        }
+    }
+}
+
+impl PollConfig {
+    /// A configuration with no limits: each poll reads until nothing more is available.
+    pub fn unlimited() -> PollConfig {
+        PollConfig {
+            max_messages_per_tick: None,
+            max_bytes_per_tick: None,
+            yield_strategy: YieldStrategy::default(),
+        }
+    }
+
+    fn limit_reached(&self, messages: usize, bytes: usize) -> bool {
+        matches!(self.max_messages_per_tick, Some(max) if messages >= max)
+            || matches!(self.max_bytes_per_tick, Some(max) if bytes >= max)
+    }
+}
+
+/// Given a stream of GenericMessage, poll the stream and dispatch received messages,
+/// up to the limits in the config.
+///
+/// Is only ready when the stream is closed.
+pub(crate) fn poll_and_dispatch<T, U>(
+    endpoint: &mut T,
+    stream: &mut U,
+    dispatcher: &TypeDispatcher,
+    config: &PollConfig,
+    cx: &mut Context<'_>,
+) -> Poll<Result<()>>
+where
+    T: Endpoint,
+    U: Stream<Item = GenericMessage> + Unpin,
+{
+    dispatcher.flush_throttled()?;
+    dispatcher.poll_async_handlers(cx)?;
+    let mut messages = 0;
+    let mut bytes = 0;
+    loop {
+        match stream.poll_next_unpin(cx) {
+            Poll::Ready(Some(msg)) => {
+                if let Some(history) = endpoint.message_history_mut() {
+                    history.record(Direction::Inbound, &msg);
+                }
+                if let Some(log) = endpoint.message_log_mut(Direction::Inbound) {
+                    if let Err(e) = log.write_message(&msg) {
+                        warn!("Could not log incoming message: {}", e);
+                    }
+                }
+                let mut msg = endpoint.map_remote_message_to_local(msg)?;
+                if msg.is_system_message() && !is_known_system_message(msg.header.message_type) {
+                    messages += 1;
+                    bytes += msg.body_ref().buffer_size();
+                    dispatcher.call_system_handler(&msg)?;
+                } else if msg.is_system_message() {
+                    endpoint.send_system_change(parse_system_message(msg)?)?;
+                    // The change is applied after we return: later messages may depend on it
+                    // (e.g. a sender description), so stop here and get polled again.
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                } else {
+                    if endpoint.timestamp_policy().receive == ReceiveTimestamp::Arrival {
+                        stamp_now(&mut msg);
+                    }
+                    update_sensor_filter(endpoint, dispatcher, &msg)?;
+                    update_udp_only(endpoint, dispatcher, &msg);
+                    let offer = update_peer_compression(endpoint, dispatcher, &msg)?;
+                    messages += 1;
+                    bytes += msg.body_ref().buffer_size();
+                    dispatcher.call(&msg)?;
+                    if offer {
+                        // Compressed frames may follow: get polled again, to accept them.
+                        cx.waker().wake_by_ref();
+                        return Poll::Pending;
+                    }
+                }
+            }
+            Poll::Ready(None) => {
+                debug!("poll_and_dispatch decided the channel was closed");
+                return Poll::Ready(Ok(()));
+            }
+            Poll::Pending => return Poll::Pending,
+        }
+        if config.limit_reached(messages, bytes) {
+            // We did not reach Poll::Pending, so nothing will wake us for the remaining messages.
+            if config.yield_strategy == YieldStrategy::Wake {
+                cx.waker().wake_by_ref();
+            }
+            return Poll::Pending;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data_types::{id_types::*, ClassOfService, GenericBody, MessageHeader, TimeVal},
+        endpoint::SystemCommand,
+        type_dispatcher::TryIntoDescriptionMessage,
+        TranslationTables,
+    };
+    use bytes::Bytes;
+    use futures::{stream, task::noop_waker_ref};
+    use std::sync::Mutex;
+
+    /// Endpoint that keeps the system changes it is asked to send.
+    #[derive(Debug, Default)]
+    struct ChangeRecorder {
+        translation: TranslationTables,
+        changes: Mutex<Vec<SystemCommand>>,
+    }
+
+    impl Endpoint for ChangeRecorder {
+        fn translation_tables(&self) -> &TranslationTables {
+            &self.translation
+        }
+        fn translation_tables_mut(&mut self) -> &mut TranslationTables {
+            &mut self.translation
+        }
+        fn send_system_change(&self, message: SystemCommand) -> Result<()> {
+            self.changes.lock().unwrap().push(message);
+            Ok(())
+        }
+        fn buffer_generic_message(
+            &mut self,
+            _msg: GenericMessage,
+            _class: ClassOfService,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let config = PollConfig::default();
+        assert!(!config.limit_reached(DEFAULT_MAX_MESSAGES_PER_TICK - 1, 1 << 20));
+        assert!(config.limit_reached(DEFAULT_MAX_MESSAGES_PER_TICK, 0));
+
+        let config = PollConfig {
+            max_messages_per_tick: None,
+            max_bytes_per_tick: Some(100),
+            ..PollConfig::default()
+        };
+        assert!(!config.limit_reached(1000, 99));
+        assert!(config.limit_reached(1, 100));
+
+        assert!(!PollConfig::unlimited().limit_reached(usize::MAX, usize::MAX));
+    }
+
+    #[test]
+    fn description_applied_before_later_messages() {
+        let description = SenderId(0)
+            .try_into_description_message(Bytes::from_static(b"Tracker0"))
+            .unwrap();
+        let user = GenericMessage::from_header_and_body(
+            MessageHeader::new(Some(TimeVal::default()), MessageTypeId(0), SenderId(0)),
+            GenericBody::new(Bytes::new()),
+        );
+        let mut messages = stream::iter(vec![description, user]);
+        let mut endpoint = ChangeRecorder::default();
+        let dispatcher = TypeDispatcher::new();
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        // Stops after the description, leaving the user message for the next poll.
+        assert!(poll_and_dispatch(
+            &mut endpoint,
+            &mut messages,
+            &dispatcher,
+            &PollConfig::default(),
+            &mut cx
+        )
+        .is_pending());
+        assert_eq!(endpoint.changes.lock().unwrap().len(), 1);
+        assert_eq!(messages.size_hint(), (1, Some(1)));
+    }
+}
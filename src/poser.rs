@@ -0,0 +1,270 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Types related to the `vrpn_Poser` device class: requests from a client
+//! to move a device to a pose or at a velocity.
+
+use crate::{
+    buffer_unbuffer::{
+        buffer::{check_buffer_remaining, BufferResult, BufferTo},
+        unbuffer::{check_unbuffer_remaining, UnbufferFrom, UnbufferResult},
+        ConstantBufferSize,
+    },
+    data_types::{
+        id_types::{LocalId, SenderId},
+        message::TypedMessageBody,
+        name_types::StaticMessageTypeName,
+        ClassOfService, MessageTypeIdentifier, Quat, SenderName, TimeVal, Vec3,
+    },
+    Connection, Result,
+};
+use bytes::{Buf, BufMut};
+use std::sync::Arc;
+
+/// Request to move to a position and orientation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PoseRequest {
+    pub pos: Vec3,
+    pub quat: Quat,
+}
+
+impl TypedMessageBody for PoseRequest {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier = MessageTypeIdentifier::UserMessageName(
+        StaticMessageTypeName(b"vrpn_Poser Request Pos_Quat"),
+    );
+}
+
+impl ConstantBufferSize for PoseRequest {
+    fn constant_buffer_size() -> usize {
+        Vec3::constant_buffer_size() + Quat::constant_buffer_size()
+    }
+}
+
+impl BufferTo for PoseRequest {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        check_buffer_remaining(buf, Self::constant_buffer_size())?;
+        self.pos.buffer_to(buf)?;
+        self.quat.buffer_to(buf)?;
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for PoseRequest {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        check_unbuffer_remaining(buf, Self::constant_buffer_size())?;
+        let pos = Vec3::unbuffer_from(buf)?;
+        let quat = Quat::unbuffer_from(buf)?;
+        Ok(PoseRequest { pos, quat })
+    }
+}
+
+/// Request to move by a translation and rotation, relative to the current pose.
+///
+/// Same wire format as `PoseRequest`, different message type.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RelativePoseRequest(pub PoseRequest);
+
+impl TypedMessageBody for RelativePoseRequest {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier = MessageTypeIdentifier::UserMessageName(
+        StaticMessageTypeName(b"vrpn_Poser Request Relative Pos_Quat"),
+    );
+}
+
+impl ConstantBufferSize for RelativePoseRequest {
+    fn constant_buffer_size() -> usize {
+        PoseRequest::constant_buffer_size()
+    }
+}
+
+impl BufferTo for RelativePoseRequest {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        self.0.buffer_to(buf)
+    }
+}
+
+impl UnbufferFrom for RelativePoseRequest {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        Ok(RelativePoseRequest(PoseRequest::unbuffer_from(buf)?))
+    }
+}
+
+/// Request to move with a linear and angular velocity.
+///
+/// The angular velocity is the rotation `vel_quat` per `vel_quat_dt` seconds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VelocityRequest {
+    pub vel: Vec3,
+    pub vel_quat: Quat,
+    pub vel_quat_dt: f64,
+}
+
+impl TypedMessageBody for VelocityRequest {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier = MessageTypeIdentifier::UserMessageName(
+        StaticMessageTypeName(b"vrpn_Poser Request Velocity"),
+    );
+}
+
+impl ConstantBufferSize for VelocityRequest {
+    fn constant_buffer_size() -> usize {
+        Vec3::constant_buffer_size() + Quat::constant_buffer_size() + f64::constant_buffer_size()
+    }
+}
+
+impl BufferTo for VelocityRequest {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        check_buffer_remaining(buf, Self::constant_buffer_size())?;
+        self.vel.buffer_to(buf)?;
+        self.vel_quat.buffer_to(buf)?;
+        self.vel_quat_dt.buffer_to(buf)?;
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for VelocityRequest {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        check_unbuffer_remaining(buf, Self::constant_buffer_size())?;
+        let vel = Vec3::unbuffer_from(buf)?;
+        let vel_quat = Quat::unbuffer_from(buf)?;
+        let vel_quat_dt = f64::unbuffer_from(buf)?;
+        Ok(VelocityRequest {
+            vel,
+            vel_quat,
+            vel_quat_dt,
+        })
+    }
+}
+
+/// Request to change velocity, relative to the current one.
+///
+/// Same wire format as `VelocityRequest`, different message type.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RelativeVelocityRequest(pub VelocityRequest);
+
+impl TypedMessageBody for RelativeVelocityRequest {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier = MessageTypeIdentifier::UserMessageName(
+        StaticMessageTypeName(b"vrpn_Poser Request Relative Velocity"),
+    );
+}
+
+impl ConstantBufferSize for RelativeVelocityRequest {
+    fn constant_buffer_size() -> usize {
+        VelocityRequest::constant_buffer_size()
+    }
+}
+
+impl BufferTo for RelativeVelocityRequest {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        self.0.buffer_to(buf)
+    }
+}
+
+impl UnbufferFrom for RelativeVelocityRequest {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        Ok(RelativeVelocityRequest(VelocityRequest::unbuffer_from(
+            buf,
+        )?))
+    }
+}
+
+/// Client side of a poser device, sending it requests.
+///
+/// Poser servers do not reply: watch a tracker on the same device to see the result.
+#[derive(Debug)]
+pub struct PoserRemote<C: Connection> {
+    connection: Arc<C>,
+    sender: LocalId<SenderId>,
+}
+
+impl<C: Connection> PoserRemote<C> {
+    pub fn new(connection: Arc<C>, name: impl Into<SenderName>) -> Result<PoserRemote<C>> {
+        let sender = connection.register_sender(name.into())?;
+        Ok(PoserRemote { connection, sender })
+    }
+
+    /// The local sender ID of this poser.
+    pub fn sender(&self) -> LocalId<SenderId> {
+        self.sender
+    }
+
+    fn request<T>(&self, time: Option<TimeVal>, body: T) -> Result<()>
+    where
+        T: TypedMessageBody + BufferTo,
+    {
+        self.connection
+            .pack_message_body(time, self.sender, body, ClassOfService::RELIABLE)
+    }
+
+    /// Ask to move to a pose.
+    pub fn request_pose(&self, time: Option<TimeVal>, pos: Vec3, quat: Quat) -> Result<()> {
+        self.request(time, PoseRequest { pos, quat })
+    }
+
+    /// Ask to move by a pose delta.
+    pub fn request_relative_pose(
+        &self,
+        time: Option<TimeVal>,
+        pos: Vec3,
+        quat: Quat,
+    ) -> Result<()> {
+        self.request(time, RelativePoseRequest(PoseRequest { pos, quat }))
+    }
+
+    /// Ask to move at a velocity.
+    pub fn request_velocity(&self, time: Option<TimeVal>, request: VelocityRequest) -> Result<()> {
+        self.request(time, request)
+    }
+
+    /// Ask to change velocity by a delta.
+    pub fn request_relative_velocity(
+        &self,
+        time: Option<TimeVal>,
+        request: VelocityRequest,
+    ) -> Result<()> {
+        self.request(time, RelativeVelocityRequest(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_unbuffer::BytesMutExtras;
+    use bytes::BytesMut;
+
+    #[test]
+    fn pose_wire_format() {
+        let request = PoseRequest {
+            pos: Vec3::new(1.0, 0.0, 0.0),
+            quat: Quat::identity(),
+        };
+        let buf = BytesMut::allocate_and_buffer(request).unwrap().freeze();
+        // Position, then quaternion x, y, z, w, as vrpn_Poser_Remote::encode_to
+        assert_eq!(
+            &buf[..],
+            &hex!(
+                "3ff0000000000000 0000000000000000 0000000000000000"
+                "0000000000000000 0000000000000000 0000000000000000 3ff0000000000000"
+            )[..]
+        );
+        assert_eq!(
+            RelativePoseRequest::unbuffer_from(&mut buf.clone()).unwrap(),
+            RelativePoseRequest(request)
+        );
+    }
+
+    #[test]
+    fn velocity_roundtrip() {
+        let request = VelocityRequest {
+            vel: Vec3::new(0.0, 2.0, 0.0),
+            vel_quat: Quat::new(0.5, 0.5, 0.5, 0.5),
+            vel_quat_dt: 0.1,
+        };
+        let mut buf = BytesMut::allocate_and_buffer(request).unwrap().freeze();
+        assert_eq!(buf.len(), VelocityRequest::constant_buffer_size());
+        assert_eq!(VelocityRequest::unbuffer_from(&mut buf).unwrap(), request);
+    }
+}
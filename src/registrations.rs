@@ -0,0 +1,125 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Looking up the local IDs of sender and message type names without the dispatcher lock,
+//! for code that sends often and only needs to resolve names.
+//!
+//! The dispatcher keeps the lookup up to date as names are registered,
+//! whether locally or because a peer described them, and as described names are cleared.
+
+use crate::{
+    data_types::{
+        id_types::{LocalId, SenderId},
+        name_types::{MessageTypeName, NameIntoBytes, SenderName},
+        MessageTypeId, MessageTypeIdentifier, TypedMessageBody,
+    },
+    sync::RwLock,
+};
+use bytes::Bytes;
+use std::{collections::HashMap, sync::Arc};
+
+#[derive(Debug, Default)]
+struct Ids {
+    senders: HashMap<Bytes, LocalId<SenderId>>,
+    message_types: HashMap<Bytes, LocalId<MessageTypeId>>,
+}
+
+/// The IDs registered in a `TypeDispatcher`, by name, from `TypeDispatcher::registrations`.
+///
+/// Cheap to clone, and all clones see the same, latest, registrations.
+#[derive(Debug, Clone, Default)]
+pub struct Registrations {
+    ids: Arc<RwLock<Ids>>,
+}
+
+impl Registrations {
+    /// The ID of a sender name, if registered.
+    pub fn sender(&self, name: impl Into<SenderName>) -> Option<LocalId<SenderId>> {
+        let name = name.into().into_bytes();
+        self.ids.read().senders.get(&name).copied()
+    }
+
+    /// The ID of a message type name, if registered.
+    pub fn message_type(&self, name: impl Into<MessageTypeName>) -> Option<LocalId<MessageTypeId>> {
+        let name = name.into().into_bytes();
+        self.ids.read().message_types.get(&name).copied()
+    }
+
+    /// The ID of a typed message body's type, if registered.
+    pub fn typed_message_type<T: TypedMessageBody>(&self) -> Option<LocalId<MessageTypeId>> {
+        match T::MESSAGE_IDENTIFIER {
+            MessageTypeIdentifier::UserMessageName(name) => self.message_type(name),
+            MessageTypeIdentifier::SystemMessageId(id) => Some(LocalId(id)),
+        }
+    }
+
+    pub(crate) fn insert_sender(&self, name: Bytes, id: LocalId<SenderId>) {
+        self.ids.write().senders.insert(name, id);
+    }
+
+    pub(crate) fn insert_message_type(&self, name: Bytes, id: LocalId<MessageTypeId>) {
+        self.ids.write().message_types.insert(name, id);
+    }
+
+    /// Replace all the registrations, as when some were removed.
+    pub(crate) fn replace(
+        &self,
+        senders: impl Iterator<Item = (Bytes, LocalId<SenderId>)>,
+        message_types: impl Iterator<Item = (Bytes, LocalId<MessageTypeId>)>,
+    ) {
+        let ids = Ids {
+            senders: senders.collect(),
+            message_types: message_types.collect(),
+        };
+        *self.ids.write() = ids;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::{StaticMessageTypeName, StaticSenderName},
+        tracker::PoseReport,
+        type_dispatcher::TypeDispatcher,
+    };
+
+    #[test]
+    fn follows_dispatcher() {
+        let mut dispatcher = TypeDispatcher::new();
+        let registrations = dispatcher.registrations();
+        assert_eq!(registrations.sender(StaticSenderName(b"Tracker0")), None);
+        assert_eq!(registrations.typed_message_type::<PoseReport>(), None);
+
+        let tracker = dispatcher
+            .register_sender(StaticSenderName(b"Tracker0"))
+            .unwrap()
+            .into_inner();
+        let pose = dispatcher
+            .register_type(StaticMessageTypeName(b"vrpn_Tracker Pos_Quat"))
+            .unwrap()
+            .into_inner();
+        let remote = dispatcher
+            .register_remote_sender(StaticSenderName(b"Tracker1"))
+            .unwrap()
+            .into_inner();
+        let clone = registrations.clone();
+        assert_eq!(clone.sender(StaticSenderName(b"Tracker0")), Some(tracker));
+        assert_eq!(clone.sender(StaticSenderName(b"Tracker1")), Some(remote));
+        assert_eq!(clone.typed_message_type::<PoseReport>(), Some(pose));
+
+        dispatcher.clear_remote_registrations().unwrap();
+        assert_eq!(
+            registrations.sender(StaticSenderName(b"Tracker0")),
+            Some(tracker)
+        );
+        assert_eq!(registrations.sender(StaticSenderName(b"Tracker1")), None);
+        dispatcher
+            .register_sender(StaticSenderName(b"Tracker1"))
+            .unwrap();
+        assert_eq!(
+            registrations.sender(StaticSenderName(b"Tracker1")),
+            Some(remote)
+        );
+    }
+}
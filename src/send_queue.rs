@@ -0,0 +1,33 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Limits on how far behind a peer may fall in reading what we send it,
+//! so one stalled client cannot hold up a server, or exhaust its memory.
+
+use std::time::Duration;
+
+/// What to do with a peer whose reliable send queue has backed up.
+///
+/// The default has no limit, queuing everything for as long as the peer stays connected.
+/// Only the async-std backend applies these.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub struct SendQueueLimits {
+    /// How many messages may be waiting to be written before the queue counts as full.
+    pub max_queued: Option<usize>,
+    /// While the queue is full, discard the oldest messages not sent as `ClassOfService::RELIABLE`
+    /// instead of writing them.
+    ///
+    /// Only affects messages going over the reliable channel,
+    /// for lack of a UDP channel or because of class of service overrides.
+    pub drop_low_latency: bool,
+    /// Close the endpoint with `VrpnError::Timeout` once the queue has been full for this long.
+    pub disconnect_after: Option<Duration>,
+}
+
+impl SendQueueLimits {
+    /// Whether the given number of unwritten messages makes the queue full.
+    pub fn is_full(&self, backlog: usize) -> bool {
+        matches!(self.max_queued, Some(max) if backlog >= max)
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Tracking the sequence numbers of received messages, to quantify loss and reordering.
+//!
+//! Each side numbers the messages it sends on a channel consecutively.
+//! On the reliable channel, a gap means messages were dropped by the sender
+//! (or a bug), while on a lossy channel like UDP it measures the link.
+
+use bytes::{Buf, BufMut};
+
+use crate::{
+    buffer_unbuffer::{
+        check_buffer_remaining, check_unbuffer_remaining, BufferResult, BufferTo,
+        ConstantBufferSize, UnbufferFrom, UnbufferResult,
+    },
+    data_types::{
+        id_types::SequenceNumber, MessageTypeIdentifier, StaticMessageTypeName, TypedMessageBody,
+    },
+};
+
+/// The message type of `SequenceGap` events.
+///
+/// Not part of mainline VRPN, and never sent over the wire.
+pub const SEQUENCE_GAP: StaticMessageTypeName =
+    StaticMessageTypeName(b"vrpn-rs Connection Sequence Gap");
+
+/// Synthesized, from the "VRPN Control" sender, when an endpoint receives a message
+/// whose sequence number skips ahead of the one expected.
+///
+/// Register a `TypedHandler` for this type to be notified of gaps.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct SequenceGap {
+    /// The sequence number that should have come next.
+    pub expected: SequenceNumber,
+    /// The sequence number that actually arrived.
+    pub received: SequenceNumber,
+}
+
+impl SequenceGap {
+    /// The number of messages skipped.
+    pub fn missing(&self) -> u32 {
+        self.received.0.wrapping_sub(self.expected.0)
+    }
+}
+
+impl TypedMessageBody for SequenceGap {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(SEQUENCE_GAP);
+}
+
+impl ConstantBufferSize for SequenceGap {
+    fn constant_buffer_size() -> usize {
+        SequenceNumber::constant_buffer_size() * 2
+    }
+}
+
+impl BufferTo for SequenceGap {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        check_buffer_remaining(buf, Self::constant_buffer_size())?;
+        self.expected.buffer_to(buf)?;
+        self.received.buffer_to(buf)
+    }
+}
+
+impl UnbufferFrom for SequenceGap {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        check_unbuffer_remaining(buf, Self::constant_buffer_size())?;
+        let expected = SequenceNumber::unbuffer_from(buf)?;
+        let received = SequenceNumber::unbuffer_from(buf)?;
+        Ok(SequenceGap { expected, received })
+    }
+}
+
+/// Counters of the sequence numbers received on one channel of an endpoint.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct SequenceStats {
+    /// Messages received.
+    pub received: u64,
+    /// Times the sequence number skipped ahead.
+    pub gaps: u64,
+    /// Messages skipped over by those gaps.
+    ///
+    /// Messages that later arrive out of order are still counted here.
+    pub missing: u64,
+    /// Messages that arrived with an earlier sequence number than one already seen:
+    /// either late or duplicated.
+    pub reordered: u64,
+}
+
+/// Keeps track of the sequence numbers received on one channel.
+#[derive(Debug, Clone, Default)]
+pub struct SequenceTracker {
+    expected: Option<SequenceNumber>,
+    stats: SequenceStats,
+}
+
+/// Sequence numbers wrap around: differences at least this large are taken as going backwards.
+const HALF_RANGE: u32 = 1 << 31;
+
+impl SequenceTracker {
+    pub fn new() -> SequenceTracker {
+        SequenceTracker::default()
+    }
+
+    /// Record a received sequence number, returning the gap if it skipped ahead.
+    ///
+    /// The first sequence number received is taken as the start.
+    pub fn record(&mut self, seq: SequenceNumber) -> Option<SequenceGap> {
+        self.stats.received += 1;
+        let expected = match self.expected {
+            Some(expected) => expected,
+            None => {
+                self.expected = Some(SequenceNumber(seq.0.wrapping_add(1)));
+                return None;
+            }
+        };
+        let ahead = seq.0.wrapping_sub(expected.0);
+        if ahead >= HALF_RANGE {
+            self.stats.reordered += 1;
+            return None;
+        }
+        self.expected = Some(SequenceNumber(seq.0.wrapping_add(1)));
+        if ahead == 0 {
+            return None;
+        }
+        self.stats.gaps += 1;
+        self.stats.missing += u64::from(ahead);
+        Some(SequenceGap {
+            expected,
+            received: seq,
+        })
+    }
+
+    pub fn stats(&self) -> SequenceStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_unbuffer::BytesMutExtras;
+    use bytes::BytesMut;
+
+    #[test]
+    fn in_order() {
+        let mut tracker = SequenceTracker::new();
+        for i in 5..10 {
+            assert_eq!(tracker.record(SequenceNumber(i)), None);
+        }
+        assert_eq!(
+            tracker.stats(),
+            SequenceStats {
+                received: 5,
+                ..SequenceStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn gaps_and_reordering() {
+        let mut tracker = SequenceTracker::new();
+        tracker.record(SequenceNumber(0));
+        let gap = tracker.record(SequenceNumber(3)).unwrap();
+        assert_eq!(gap.expected, SequenceNumber(1));
+        assert_eq!(gap.missing(), 2);
+        // A late arrival does not reset what comes next.
+        assert_eq!(tracker.record(SequenceNumber(1)), None);
+        assert_eq!(tracker.record(SequenceNumber(4)), None);
+        assert_eq!(
+            tracker.stats(),
+            SequenceStats {
+                received: 4,
+                gaps: 1,
+                missing: 2,
+                reordered: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut tracker = SequenceTracker::new();
+        tracker.record(SequenceNumber(u32::MAX));
+        assert_eq!(tracker.record(SequenceNumber(0)), None);
+        assert_eq!(tracker.record(SequenceNumber(2)).unwrap().missing(), 1);
+        tracker.record(SequenceNumber(u32::MAX));
+        assert_eq!(tracker.stats().reordered, 1);
+    }
+
+    #[test]
+    fn gap_roundtrip() {
+        let gap = SequenceGap {
+            expected: SequenceNumber(1),
+            received: SequenceNumber(4),
+        };
+        let mut buf = BytesMut::allocate_and_buffer(gap).unwrap().freeze();
+        assert_eq!(SequenceGap::unbuffer_from(&mut buf).unwrap(), gap);
+    }
+}
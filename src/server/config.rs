@@ -0,0 +1,318 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Devices configured from a subset of the C++ `vrpn.cfg` format.
+//!
+//! Each line names a device class, then the device name and class-specific arguments,
+//! separated by whitespace. Blank lines and lines starting with `#` are ignored,
+//! and a line ending in `\` continues on the next. The supported classes are:
+//!
+//! - `vrpn_Tracker_NULL NAME SENSORS RATE`: reports the identity pose of each sensor at RATE Hz.
+//! - `vrpn_Button_Example NAME BUTTONS RATE`: toggles all buttons at RATE Hz.
+//! - `vrpn_Dial_Example NAME DIALS SPIN_RATE UPDATE_RATE`: turns each dial at SPIN_RATE
+//!   revolutions per second, reporting at UPDATE_RATE Hz.
+
+use crate::{
+    button::MAX_BUTTONS,
+    data_types::SenderName,
+    simulation::{ButtonExample, DialExample, SimulatedDevice, TrackerNull},
+    Connection, Result, VrpnError,
+};
+use bytes::Bytes;
+use std::{path::Path, str::FromStr, sync::Arc, time::Instant};
+
+/// One device line of a configuration file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceConfig {
+    TrackerNull {
+        name: String,
+        sensors: i32,
+        rate: f64,
+    },
+    ButtonExample {
+        name: String,
+        buttons: usize,
+        rate: f64,
+    },
+    DialExample {
+        name: String,
+        dials: i32,
+        spin_rate: f64,
+        update_rate: f64,
+    },
+}
+
+impl DeviceConfig {
+    /// The name clients use for this device, like `Tracker0`.
+    pub fn name(&self) -> &str {
+        match self {
+            DeviceConfig::TrackerNull { name, .. }
+            | DeviceConfig::ButtonExample { name, .. }
+            | DeviceConfig::DialExample { name, .. } => name,
+        }
+    }
+}
+
+/// The whitespace-separated fields of one line, for parsing with context in errors.
+struct Fields<'a> {
+    line_number: usize,
+    fields: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> Fields<'a> {
+    fn error(&self, message: impl std::fmt::Display) -> VrpnError {
+        VrpnError::ConfigLine {
+            line: self.line_number,
+            message: message.to_string(),
+        }
+    }
+
+    fn next<T: FromStr>(&mut self, what: &str) -> Result<T> {
+        let field = self
+            .fields
+            .next()
+            .ok_or_else(|| self.error(format!("missing {}", what)))?;
+        field
+            .parse()
+            .map_err(|_| self.error(format!("could not parse {} from '{}'", what, field)))
+    }
+
+    fn finish(mut self, config: DeviceConfig) -> Result<DeviceConfig> {
+        match self.fields.next() {
+            Some(extra) => Err(self.error(format!("unexpected '{}'", extra))),
+            None => Ok(config),
+        }
+    }
+}
+
+fn parse_line(line_number: usize, line: &str) -> Result<DeviceConfig> {
+    let mut fields = Fields {
+        line_number,
+        fields: line.split_whitespace(),
+    };
+    let class: String = fields.next("device class")?;
+    let name: String = fields.next("device name")?;
+    let config = match class.as_str() {
+        "vrpn_Tracker_NULL" => DeviceConfig::TrackerNull {
+            name,
+            sensors: fields.next("number of sensors")?,
+            rate: fields.next("rate")?,
+        },
+        "vrpn_Button_Example" => {
+            let buttons = fields.next("number of buttons")?;
+            if buttons > MAX_BUTTONS {
+                return Err(fields.error(format!("at most {} buttons", MAX_BUTTONS)));
+            }
+            DeviceConfig::ButtonExample {
+                name,
+                buttons,
+                rate: fields.next("rate")?,
+            }
+        }
+        "vrpn_Dial_Example" => DeviceConfig::DialExample {
+            name,
+            dials: fields.next("number of dials")?,
+            spin_rate: fields.next("spin rate")?,
+            update_rate: fields.next("update rate")?,
+        },
+        _ => return Err(fields.error(format!("unsupported device class {}", class))),
+    };
+    fields.finish(config)
+}
+
+/// Parse the devices of a configuration file's contents.
+pub fn parse_config(text: &str) -> Result<Vec<DeviceConfig>> {
+    let mut devices = Vec::new();
+    let mut continued = String::new();
+    let mut first_line = 0;
+    for (index, line) in text.lines().enumerate() {
+        if continued.is_empty() {
+            first_line = index + 1;
+        }
+        let line = line.trim();
+        if continued.is_empty() && (line.is_empty() || line.starts_with('#')) {
+            continue;
+        }
+        if let Some(line) = line.strip_suffix('\\') {
+            continued.push_str(line);
+            continued.push(' ');
+            continue;
+        }
+        continued.push_str(line);
+        devices.push(parse_line(first_line, &continued)?);
+        continued.clear();
+    }
+    if !continued.is_empty() {
+        devices.push(parse_line(first_line, &continued)?);
+    }
+    Ok(devices)
+}
+
+/// Read and parse the devices of a configuration file.
+pub fn read_config(path: impl AsRef<Path>) -> Result<Vec<DeviceConfig>> {
+    parse_config(&std::fs::read_to_string(path)?)
+}
+
+#[derive(Debug)]
+enum Device<C: Connection> {
+    TrackerNull(TrackerNull<C>),
+    ButtonExample(ButtonExample<C>),
+    DialExample(DialExample<C>),
+}
+
+impl<C: Connection> Device<C> {
+    fn as_simulated(&self) -> &dyn SimulatedDevice {
+        match self {
+            Device::TrackerNull(device) => device,
+            Device::ButtonExample(device) => device,
+            Device::DialExample(device) => device,
+        }
+    }
+
+    fn as_simulated_mut(&mut self) -> &mut dyn SimulatedDevice {
+        match self {
+            Device::TrackerNull(device) => device,
+            Device::ButtonExample(device) => device,
+            Device::DialExample(device) => device,
+        }
+    }
+}
+
+/// The devices of a configuration, served on one connection.
+///
+/// Call `report_due` whenever `next_due` passes, from whatever loop drives the server.
+#[derive(Debug)]
+pub struct ServerDevices<C: Connection> {
+    devices: Vec<Device<C>>,
+}
+
+impl<C: Connection> ServerDevices<C> {
+    /// Create each configured device on the connection.
+    pub fn new(connection: Arc<C>, configs: &[DeviceConfig]) -> Result<ServerDevices<C>> {
+        let devices = configs
+            .iter()
+            .map(|config| {
+                let name = SenderName(Bytes::copy_from_slice(config.name().as_bytes()));
+                let connection = Arc::clone(&connection);
+                Ok(match *config {
+                    DeviceConfig::TrackerNull { sensors, rate, .. } => {
+                        Device::TrackerNull(TrackerNull::new(connection, name, sensors, rate)?)
+                    }
+                    DeviceConfig::ButtonExample { buttons, rate, .. } => {
+                        Device::ButtonExample(ButtonExample::new(connection, name, buttons, rate)?)
+                    }
+                    DeviceConfig::DialExample {
+                        dials,
+                        spin_rate,
+                        update_rate,
+                        ..
+                    } => Device::DialExample(DialExample::new(
+                        connection,
+                        name,
+                        dials,
+                        spin_rate,
+                        update_rate,
+                    )?),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(ServerDevices { devices })
+    }
+}
+
+impl<C: Connection> SimulatedDevice for ServerDevices<C> {
+    /// When the next report is due, if any device reports at all.
+    fn next_due(&self) -> Option<Instant> {
+        self.devices
+            .iter()
+            .filter_map(|device| device.as_simulated().next_due())
+            .min()
+    }
+
+    /// Send the reports of each device that are due at `now`.
+    fn report_due(&mut self, now: Instant) -> Result<()> {
+        for device in &mut self.devices {
+            device.as_simulated_mut().report_due(now)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let devices = parse_config(
+            "# Trackers\n\
+             vrpn_Tracker_NULL\tTracker0\t2\t60.0\n\
+             \n\
+             vrpn_Button_Example Button0 \\\n\
+                 3 0.5\n\
+             vrpn_Dial_Example Dial0 1 0.25 10\n",
+        )
+        .unwrap();
+        assert_eq!(
+            devices,
+            vec![
+                DeviceConfig::TrackerNull {
+                    name: "Tracker0".to_string(),
+                    sensors: 2,
+                    rate: 60.0
+                },
+                DeviceConfig::ButtonExample {
+                    name: "Button0".to_string(),
+                    buttons: 3,
+                    rate: 0.5
+                },
+                DeviceConfig::DialExample {
+                    name: "Dial0".to_string(),
+                    dials: 1,
+                    spin_rate: 0.25,
+                    update_rate: 10.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err = parse_config("\nvrpn_Tracker_NULL Tracker0 two 60\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+        assert!(parse_config("vrpn_Tracker_Fastrak Tracker0 /dev/ttyS0 115200").is_err());
+        assert!(parse_config("vrpn_Tracker_NULL Tracker0 2").is_err());
+        assert!(parse_config("vrpn_Tracker_NULL Tracker0 2 60 extra").is_err());
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn reports_when_due() {
+        use crate::vrpn_async_std::connection_ip::ConnectionIp;
+        use std::time::Duration;
+        let connection = ConnectionIp::new_server(None, None).unwrap();
+        let configs =
+            parse_config("vrpn_Tracker_NULL Tracker0 1 10\nvrpn_Button_Example Button0 1 0")
+                .unwrap();
+        let mut devices = ServerDevices::new(Arc::clone(&connection), &configs).unwrap();
+        assert!(connection
+            .dispatcher()
+            .read()
+            .get_sender_id(crate::data_types::StaticSenderName(b"Button0"))
+            .is_some());
+
+        // Only the tracker reports, every 100 ms.
+        let start = devices.next_due().unwrap();
+        devices.report_due(start).unwrap();
+        assert_eq!(devices.next_due(), Some(start + Duration::from_millis(100)));
+        devices
+            .report_due(start + Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(devices.next_due(), Some(start + Duration::from_millis(100)));
+        // Far behind: the next report is an interval from now.
+        let late = start + Duration::from_secs(5);
+        devices.report_due(late).unwrap();
+        assert_eq!(devices.next_due(), Some(late + Duration::from_millis(100)));
+    }
+}
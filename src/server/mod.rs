@@ -0,0 +1,9 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Running a server of devices, as the C++ `vrpn_server` does.
+
+pub mod config;
+
+pub use config::{parse_config, read_config, DeviceConfig, ServerDevices};
@@ -0,0 +1,370 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Server devices reporting made-up data on a schedule, like the C++
+//! `vrpn_Tracker_NULL` and `vrpn_Button_Example`, for running clients
+//! and integration tests without hardware.
+
+use super::trajectory::{SensorGenerator, Trajectory};
+use crate::{
+    analog::{AnalogChannels, AnalogServer},
+    button::{ButtonChange, ButtonServer},
+    data_types::{id_types::Sensor, ClassOfService, SenderName, TimeVal, Vec3},
+    dial::{DialChange, DialServer},
+    tracker::TrackerServer,
+    Connection, Result,
+};
+use std::{
+    f64::consts::PI,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// A device that reports on its own schedule.
+///
+/// Call `report_due` whenever `next_due` passes, from whatever loop drives the server.
+pub trait SimulatedDevice {
+    /// When the next report is due, or `None` if this device never reports.
+    fn next_due(&self) -> Option<Instant>;
+
+    /// Send the report due at `now`, if any.
+    fn report_due(&mut self, now: Instant) -> Result<()>;
+}
+
+/// When a device reports, at a fixed rate from its creation.
+#[derive(Debug, Clone)]
+struct Schedule {
+    start: Instant,
+    interval: Option<Duration>,
+    next: Instant,
+}
+
+impl Schedule {
+    fn new(rate: f64) -> Schedule {
+        let start = Instant::now();
+        Schedule {
+            start,
+            interval: if rate > 0.0 {
+                Some(Duration::from_secs_f64(1.0 / rate))
+            } else {
+                None
+            },
+            next: start,
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.interval.map(|_| self.next)
+    }
+
+    /// If a report is due at `now`, advance to the next one and return the seconds since start.
+    fn take_due(&mut self, now: Instant) -> Option<f64> {
+        let interval = self.interval?;
+        if self.next > now {
+            return None;
+        }
+        self.next += interval;
+        if self.next <= now {
+            // Fell behind: skip the missed reports rather than bursting.
+            self.next = now + interval;
+        }
+        Some(now.saturating_duration_since(self.start).as_secs_f64())
+    }
+
+    fn interval_secs(&self) -> f64 {
+        self.interval.unwrap_or_default().as_secs_f64()
+    }
+}
+
+/// A tracker whose sensors sit at the origin, or follow a trajectory.
+#[derive(Debug)]
+pub struct TrackerNull<C: Connection> {
+    server: TrackerServer<C>,
+    sensors: Vec<SensorGenerator>,
+    schedule: Schedule,
+}
+
+impl<C: Connection> TrackerNull<C> {
+    /// Create a tracker reporting the identity pose of each sensor at `rate` Hz.
+    ///
+    /// A rate of 0 never reports.
+    pub fn new(
+        connection: Arc<C>,
+        name: impl Into<SenderName>,
+        sensors: i32,
+        rate: f64,
+    ) -> Result<TrackerNull<C>> {
+        let mut tracker = TrackerNull {
+            server: TrackerServer::new(connection, name)?,
+            sensors: Vec::new(),
+            schedule: Schedule::new(rate),
+        };
+        tracker.set_trajectory(sensors, Trajectory::Stationary(Vec3::new(0.0, 0.0, 0.0)));
+        Ok(tracker)
+    }
+
+    /// Have every sensor follow a trajectory, timed from the tracker's creation.
+    pub fn with_trajectory(mut self, trajectory: Trajectory) -> TrackerNull<C> {
+        let sensors = self.sensors.len() as i32;
+        self.set_trajectory(sensors, trajectory);
+        self
+    }
+
+    fn set_trajectory(&mut self, sensors: i32, trajectory: Trajectory) {
+        // Only the ground truth is used, so the sample rate doesn't matter.
+        self.sensors = (0..sensors)
+            .map(|sensor| SensorGenerator::with_interval(Sensor(sensor), trajectory.clone(), 1.0))
+            .collect();
+    }
+
+    pub fn server(&self) -> &TrackerServer<C> {
+        &self.server
+    }
+}
+
+impl<C: Connection> SimulatedDevice for TrackerNull<C> {
+    fn next_due(&self) -> Option<Instant> {
+        self.schedule.next_due()
+    }
+
+    fn report_due(&mut self, now: Instant) -> Result<()> {
+        let t = match self.schedule.take_due(now) {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        self.server.report_poses(
+            Some(TimeVal::get_time_of_day()),
+            self.sensors.iter().map(|sensor| sensor.ground_truth(t)),
+            ClassOfService::LOW_LATENCY,
+        )
+    }
+}
+
+/// Buttons that all toggle together at a fixed rate.
+#[derive(Debug)]
+pub struct ButtonExample<C: Connection> {
+    server: ButtonServer<C>,
+    buttons: usize,
+    pressed: bool,
+    schedule: Schedule,
+}
+
+impl<C: Connection> ButtonExample<C> {
+    /// Create `buttons` buttons, all released, toggling at `rate` Hz.
+    ///
+    /// A rate of 0 never reports.
+    pub fn new(
+        connection: Arc<C>,
+        name: impl Into<SenderName>,
+        buttons: usize,
+        rate: f64,
+    ) -> Result<ButtonExample<C>> {
+        Ok(ButtonExample {
+            server: ButtonServer::new(connection, name)?,
+            buttons,
+            pressed: false,
+            schedule: Schedule::new(rate),
+        })
+    }
+
+    pub fn server(&self) -> &ButtonServer<C> {
+        &self.server
+    }
+}
+
+impl<C: Connection> SimulatedDevice for ButtonExample<C> {
+    fn next_due(&self) -> Option<Instant> {
+        self.schedule.next_due()
+    }
+
+    fn report_due(&mut self, now: Instant) -> Result<()> {
+        if self.schedule.take_due(now).is_none() {
+            return Ok(());
+        }
+        let time = TimeVal::get_time_of_day();
+        self.pressed = !self.pressed;
+        for button in 0..self.buttons {
+            self.server.report_change(
+                Some(time),
+                ButtonChange {
+                    button: button as i32,
+                    pressed: self.pressed,
+                },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Analog channels following sine waves, each a quarter cycle ahead of the one before.
+#[derive(Debug)]
+pub struct AnalogSine<C: Connection> {
+    server: AnalogServer<C>,
+    channels: usize,
+    frequency: f64,
+    schedule: Schedule,
+}
+
+impl<C: Connection> AnalogSine<C> {
+    /// Create `channels` channels, cycling every 2 seconds, reporting at `rate` Hz.
+    ///
+    /// A rate of 0 never reports.
+    pub fn new(
+        connection: Arc<C>,
+        name: impl Into<SenderName>,
+        channels: usize,
+        rate: f64,
+    ) -> Result<AnalogSine<C>> {
+        Ok(AnalogSine {
+            server: AnalogServer::new(connection, name)?,
+            channels,
+            frequency: 0.5,
+            schedule: Schedule::new(rate),
+        })
+    }
+
+    /// Set the frequency of the sine waves, in Hz.
+    pub fn with_frequency(mut self, frequency: f64) -> AnalogSine<C> {
+        self.frequency = frequency;
+        self
+    }
+
+    /// The channel values at `t` seconds after creation.
+    pub fn channels_at(&self, t: f64) -> Vec<f64> {
+        (0..self.channels)
+            .map(|channel| (2.0 * PI * self.frequency * t + channel as f64 * PI / 2.0).sin())
+            .collect()
+    }
+
+    pub fn server(&self) -> &AnalogServer<C> {
+        &self.server
+    }
+}
+
+impl<C: Connection> SimulatedDevice for AnalogSine<C> {
+    fn next_due(&self) -> Option<Instant> {
+        self.schedule.next_due()
+    }
+
+    fn report_due(&mut self, now: Instant) -> Result<()> {
+        let t = match self.schedule.take_due(now) {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        self.server.report_channels(
+            Some(TimeVal::get_time_of_day()),
+            AnalogChannels {
+                channels: self.channels_at(t),
+            },
+        )
+    }
+}
+
+/// Dials that each turn at a fixed speed.
+#[derive(Debug)]
+pub struct DialExample<C: Connection> {
+    server: DialServer<C>,
+    dials: i32,
+    spin_rate: f64,
+    schedule: Schedule,
+}
+
+impl<C: Connection> DialExample<C> {
+    /// Create `dials` dials turning at `spin_rate` revolutions per second,
+    /// reporting at `update_rate` Hz.
+    ///
+    /// An update rate of 0 never reports.
+    pub fn new(
+        connection: Arc<C>,
+        name: impl Into<SenderName>,
+        dials: i32,
+        spin_rate: f64,
+        update_rate: f64,
+    ) -> Result<DialExample<C>> {
+        Ok(DialExample {
+            server: DialServer::new(connection, name)?,
+            dials,
+            spin_rate,
+            schedule: Schedule::new(update_rate),
+        })
+    }
+
+    pub fn server(&self) -> &DialServer<C> {
+        &self.server
+    }
+}
+
+impl<C: Connection> SimulatedDevice for DialExample<C> {
+    fn next_due(&self) -> Option<Instant> {
+        self.schedule.next_due()
+    }
+
+    fn report_due(&mut self, now: Instant) -> Result<()> {
+        if self.schedule.take_due(now).is_none() {
+            return Ok(());
+        }
+        let time = TimeVal::get_time_of_day();
+        let change = self.spin_rate * self.schedule.interval_secs();
+        for dial in 0..self.dials {
+            self.server
+                .report_change(Some(time), DialChange { change, dial })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "async-std"))]
+mod tests {
+    use super::*;
+    use crate::{data_types::StaticSenderName, vrpn_async_std::connection_ip::ConnectionIp};
+
+    #[test]
+    fn schedules() {
+        let connection = ConnectionIp::new_server(None, None).unwrap();
+        let mut tracker = TrackerNull::new(
+            Arc::clone(&connection),
+            StaticSenderName(b"Tracker0"),
+            2,
+            10.0,
+        )
+        .unwrap();
+        let start = tracker.next_due().unwrap();
+        tracker.report_due(start).unwrap();
+        assert_eq!(tracker.next_due(), Some(start + Duration::from_millis(100)));
+        tracker
+            .report_due(start + Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(tracker.next_due(), Some(start + Duration::from_millis(100)));
+
+        let buttons = ButtonExample::new(connection, StaticSenderName(b"Button0"), 1, 0.0).unwrap();
+        assert_eq!(buttons.next_due(), None);
+    }
+
+    #[test]
+    fn circle_and_sines() {
+        let connection = ConnectionIp::new_server(None, None).unwrap();
+        let tracker = TrackerNull::new(
+            Arc::clone(&connection),
+            StaticSenderName(b"Tracker0"),
+            2,
+            60.0,
+        )
+        .unwrap()
+        .with_trajectory(Trajectory::Circle {
+            center: Vec3::new(0.0, 0.0, 1.0),
+            radius: 0.5,
+            period: 4.0,
+        });
+        assert_eq!(tracker.sensors.len(), 2);
+        let pose = tracker.sensors[1].ground_truth(1.0);
+        assert_eq!(pose.sensor, Sensor(1));
+        assert!(pose.pos.x.abs() < 1e-9);
+        assert!((pose.pos.y - 0.5).abs() < 1e-9);
+
+        let analog = AnalogSine::new(connection, StaticSenderName(b"Analog0"), 2, 60.0).unwrap();
+        let channels = analog.channels_at(0.5);
+        assert!((channels[0] - 1.0).abs() < 1e-9);
+        assert!(channels[1].abs() < 1e-9);
+    }
+}
@@ -0,0 +1,14 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Simulated devices, producing data with known ground truth,
+//! for testing clients without real hardware.
+
+pub mod devices;
+pub mod trajectory;
+
+pub use devices::{AnalogSine, ButtonExample, DialExample, SimulatedDevice, TrackerNull};
+pub use trajectory::{
+    parse_keyframes_csv, Keyframe, Sample, SensorGenerator, Trajectory, TrajectoryGenerator,
+};
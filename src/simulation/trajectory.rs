@@ -0,0 +1,527 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Test-pattern trajectories for `TrackerServer` sensors.
+//!
+//! Each sensor follows a trajectory, sampled at its own rate, optionally with
+//! Gaussian position noise. Every sample carries both the noisy report and the
+//! ground truth it came from, so client-side filtering or prediction can be checked.
+//! All randomness comes from a seeded generator, so runs are reproducible.
+
+use crate::{
+    data_types::{id_types::Sensor, ClassOfService, Quat, TimeVal, Vec3},
+    tracker::{PoseReport, TrackerServer},
+    Connection, Result, VrpnError,
+};
+use std::{
+    f64::consts::PI,
+    time::{Duration, SystemTime},
+};
+
+/// A pose at a point in time, for scripted trajectories.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    /// Seconds from the start.
+    pub time: f64,
+    pub pos: Vec3,
+    pub quat: Quat,
+}
+
+/// The path a simulated sensor follows.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trajectory {
+    /// Stay in one place.
+    Stationary(Vec3),
+    /// Go around a circle in the XY plane, turning about Z to face along the path.
+    Circle {
+        center: Vec3,
+        radius: f64,
+        /// Seconds per revolution.
+        period: f64,
+    },
+    /// Move along a Lissajous curve: each axis is `amplitude * sin(2π * frequency * t + phase)`.
+    Lissajous {
+        center: Vec3,
+        amplitude: Vec3,
+        /// Hz, per axis.
+        frequency: Vec3,
+        /// Radians, per axis.
+        phase: Vec3,
+    },
+    /// Take a Gaussian step on each axis at every sample.
+    RandomWalk {
+        start: Vec3,
+        /// Standard deviation of each step, per axis.
+        step: f64,
+        seed: u64,
+    },
+    /// Interpolate between keyframes, sorted by time, holding the ends.
+    Keyframes(Vec<Keyframe>),
+}
+
+/// SplitMix64: small, fast, and good enough for test patterns.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in (0, 1].
+    fn next_f64(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// Standard normal, by Box-Muller.
+    fn next_gaussian(&mut self) -> f64 {
+        let u1 = self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    }
+
+    fn next_gaussian_vec(&mut self, stddev: f64) -> Vec3 {
+        Vec3::new(
+            self.next_gaussian() * stddev,
+            self.next_gaussian() * stddev,
+            self.next_gaussian() * stddev,
+        )
+    }
+}
+
+fn add(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3::new(a.x + b.x, a.y + b.y, a.z + b.z)
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f64) -> Vec3 {
+    Vec3::new(
+        a.x + (b.x - a.x) * t,
+        a.y + (b.y - a.y) * t,
+        a.z + (b.z - a.z) * t,
+    )
+}
+
+/// Normalized linear interpolation, taking the short way around.
+fn nlerp(a: Quat, b: Quat, t: f64) -> Quat {
+    let dot = a.s * b.s + a.v.x * b.v.x + a.v.y * b.v.y + a.v.z * b.v.z;
+    let sign = if dot < 0.0 { -1.0 } else { 1.0 };
+    let s = a.s + (b.s * sign - a.s) * t;
+    let v = lerp(a.v, Vec3::new(b.v.x * sign, b.v.y * sign, b.v.z * sign), t);
+    let norm = (s * s + v.x * v.x + v.y * v.y + v.z * v.z).sqrt();
+    Quat::from_sv(s / norm, Vec3::new(v.x / norm, v.y / norm, v.z / norm))
+}
+
+fn rotation_about_z(angle: f64) -> Quat {
+    Quat::new((angle / 2.0).cos(), 0.0, 0.0, (angle / 2.0).sin())
+}
+
+fn interpolate_keyframes(keyframes: &[Keyframe], t: f64) -> (Vec3, Quat) {
+    let (first, last) = match (keyframes.first(), keyframes.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return (Vec3::default(), Quat::identity()),
+    };
+    if t <= first.time {
+        return (first.pos, first.quat);
+    }
+    if t >= last.time {
+        return (last.pos, last.quat);
+    }
+    let next = keyframes.iter().position(|k| k.time > t).unwrap();
+    let (a, b) = (&keyframes[next - 1], &keyframes[next]);
+    let alpha = (t - a.time) / (b.time - a.time);
+    (lerp(a.pos, b.pos, alpha), nlerp(a.quat, b.quat, alpha))
+}
+
+/// Parse keyframes from CSV text.
+///
+/// Each line is `time,x,y,z` or `time,x,y,z,qw,qx,qy,qz`, with time in seconds.
+/// Blank lines, lines starting with `#`, and a header line are ignored.
+/// Times must not decrease.
+pub fn parse_keyframes_csv(text: &str) -> Result<Vec<Keyframe>> {
+    let mut keyframes: Vec<Keyframe> = Vec::new();
+    let mut seen_data = false;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: std::result::Result<Vec<f64>, _> =
+            line.split(',').map(|f| f.trim().parse::<f64>()).collect();
+        let fields = match fields {
+            Ok(fields) => fields,
+            // Allow for a header.
+            Err(_) if !seen_data => {
+                seen_data = true;
+                continue;
+            }
+            Err(e) => {
+                return Err(VrpnError::OtherMessage(format!(
+                    "keyframe line {}: {}",
+                    index + 1,
+                    e
+                )))
+            }
+        };
+        seen_data = true;
+        let quat = match fields.len() {
+            4 => Quat::identity(),
+            8 => Quat::new(fields[4], fields[5], fields[6], fields[7]),
+            n => {
+                return Err(VrpnError::OtherMessage(format!(
+                    "keyframe line {}: expected 4 or 8 fields, got {}",
+                    index + 1,
+                    n
+                )))
+            }
+        };
+        let keyframe = Keyframe {
+            time: fields[0],
+            pos: Vec3::new(fields[1], fields[2], fields[3]),
+            quat,
+        };
+        if let Some(prev) = keyframes.last() {
+            if keyframe.time < prev.time {
+                return Err(VrpnError::OtherMessage(format!(
+                    "keyframe line {}: time {} is before the previous keyframe",
+                    index + 1,
+                    keyframe.time
+                )));
+            }
+        }
+        keyframes.push(keyframe);
+    }
+    Ok(keyframes)
+}
+
+/// One sample from a sensor: what was reported, and what was true.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// Seconds from the start.
+    pub time: f64,
+    /// The pose without noise.
+    pub truth: PoseReport,
+    /// The pose as reported, with noise.
+    pub reported: PoseReport,
+}
+
+/// Generates samples for a single sensor.
+#[derive(Debug, Clone)]
+pub struct SensorGenerator {
+    sensor: Sensor,
+    trajectory: Trajectory,
+    interval: f64,
+    noise: f64,
+    noise_rng: Rng,
+    walk: Option<(Vec3, Rng)>,
+    next_index: u64,
+}
+
+impl SensorGenerator {
+    /// Create a generator sampling a trajectory at a rate in Hz, without noise.
+    ///
+    /// Fails with `VrpnError::InvalidArgument` unless the rate is positive and finite.
+    pub fn new(sensor: Sensor, trajectory: Trajectory, rate_hz: f64) -> Result<SensorGenerator> {
+        if !(rate_hz > 0.0 && rate_hz.is_finite()) {
+            return Err(VrpnError::InvalidArgument(format!(
+                "sample rate must be positive and finite, got {}",
+                rate_hz
+            )));
+        }
+        Ok(SensorGenerator::with_interval(
+            sensor,
+            trajectory,
+            1.0 / rate_hz,
+        ))
+    }
+
+    /// Create a generator sampling a trajectory every `interval` seconds, which must be positive.
+    pub(super) fn with_interval(
+        sensor: Sensor,
+        trajectory: Trajectory,
+        interval: f64,
+    ) -> SensorGenerator {
+        let walk = match &trajectory {
+            Trajectory::RandomWalk { start, seed, .. } => Some((*start, Rng(*seed))),
+            _ => None,
+        };
+        SensorGenerator {
+            sensor,
+            trajectory,
+            interval,
+            noise: 0.0,
+            noise_rng: Rng(0),
+            walk,
+            next_index: 0,
+        }
+    }
+
+    /// Add Gaussian noise with this standard deviation to each reported position axis.
+    pub fn with_noise(mut self, stddev: f64, seed: u64) -> SensorGenerator {
+        self.noise = stddev;
+        self.noise_rng = Rng(seed);
+        self
+    }
+
+    pub fn sensor(&self) -> Sensor {
+        self.sensor
+    }
+
+    /// Time of the next sample, in seconds from the start.
+    pub fn next_time(&self) -> f64 {
+        // Multiply rather than accumulate, so rounding errors don't add up.
+        self.next_index as f64 * self.interval
+    }
+
+    /// The noiseless pose at a time.
+    ///
+    /// For a random walk, this is the position as of the latest sample, whatever the time.
+    pub fn ground_truth(&self, t: f64) -> PoseReport {
+        let (pos, quat) = match &self.trajectory {
+            Trajectory::Stationary(pos) => (*pos, Quat::identity()),
+            Trajectory::Circle {
+                center,
+                radius,
+                period,
+            } => {
+                let angle = 2.0 * PI * t / period;
+                let pos = add(
+                    *center,
+                    Vec3::new(radius * angle.cos(), radius * angle.sin(), 0.0),
+                );
+                (pos, rotation_about_z(angle + PI / 2.0))
+            }
+            Trajectory::Lissajous {
+                center,
+                amplitude,
+                frequency,
+                phase,
+            } => {
+                let axis = |a: f64, f: f64, p: f64| a * (2.0 * PI * f * t + p).sin();
+                let offset = Vec3::new(
+                    axis(amplitude.x, frequency.x, phase.x),
+                    axis(amplitude.y, frequency.y, phase.y),
+                    axis(amplitude.z, frequency.z, phase.z),
+                );
+                (add(*center, offset), Quat::identity())
+            }
+            Trajectory::RandomWalk { start, .. } => (
+                self.walk.as_ref().map(|(pos, _)| *pos).unwrap_or(*start),
+                Quat::identity(),
+            ),
+            Trajectory::Keyframes(keyframes) => interpolate_keyframes(keyframes, t),
+        };
+        PoseReport {
+            sensor: self.sensor,
+            pos,
+            quat,
+        }
+    }
+
+    /// Take the next sample, advancing to the following sample time.
+    pub fn next_sample(&mut self) -> Sample {
+        let time = self.next_time();
+        if let (Some((pos, rng)), Trajectory::RandomWalk { step, .. }) =
+            (&mut self.walk, &self.trajectory)
+        {
+            // The first sample is the start.
+            if time > 0.0 {
+                *pos = add(*pos, rng.next_gaussian_vec(*step));
+            }
+        }
+        let truth = self.ground_truth(time);
+        let mut reported = truth.clone();
+        if self.noise > 0.0 {
+            reported.pos = add(reported.pos, self.noise_rng.next_gaussian_vec(self.noise));
+        }
+        self.next_index += 1;
+        Sample {
+            time,
+            truth,
+            reported,
+        }
+    }
+
+    /// Take all samples due up to and including time `t`.
+    pub fn samples_until(&mut self, t: f64) -> Vec<Sample> {
+        let mut samples = Vec::new();
+        while self.next_time() <= t {
+            samples.push(self.next_sample());
+        }
+        samples
+    }
+}
+
+/// Generates samples for any number of sensors of one tracker.
+#[derive(Debug, Clone, Default)]
+pub struct TrajectoryGenerator {
+    sensors: Vec<SensorGenerator>,
+}
+
+impl TrajectoryGenerator {
+    pub fn new() -> TrajectoryGenerator {
+        TrajectoryGenerator::default()
+    }
+
+    /// Add a sensor.
+    pub fn with_sensor(mut self, sensor: SensorGenerator) -> TrajectoryGenerator {
+        self.sensors.push(sensor);
+        self
+    }
+
+    pub fn sensors(&self) -> &[SensorGenerator] {
+        &self.sensors
+    }
+
+    /// Take all samples from all sensors due up to and including time `t`, in time order.
+    pub fn samples_until(&mut self, t: f64) -> Vec<Sample> {
+        let mut samples: Vec<Sample> = self
+            .sensors
+            .iter_mut()
+            .flat_map(|sensor| sensor.samples_until(t))
+            .collect();
+        samples.sort_by(|a, b| a.time.total_cmp(&b.time));
+        samples
+    }
+
+    /// Report all samples due up to time `t` through a tracker server,
+    /// timestamped relative to `start`, returning them for comparison.
+    pub fn report_until<C: Connection>(
+        &mut self,
+        server: &TrackerServer<C>,
+        start: SystemTime,
+        t: f64,
+        class: ClassOfService,
+    ) -> Result<Vec<Sample>> {
+        let samples = self.samples_until(t);
+        for sample in &samples {
+            let time = TimeVal::from(start + Duration::from_secs_f64(sample.time));
+            server.report_pose(Some(time), sample.reported.clone(), class)?;
+        }
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a.x - b.x).abs() < 1e-9 && (a.y - b.y).abs() < 1e-9 && (a.z - b.z).abs() < 1e-9
+    }
+
+    #[test]
+    fn circle_and_rate() {
+        let mut gen = SensorGenerator::new(
+            Sensor(0),
+            Trajectory::Circle {
+                center: Vec3::new(0.0, 0.0, 1.0),
+                radius: 2.0,
+                period: 4.0,
+            },
+            10.0,
+        )
+        .unwrap();
+        let samples = gen.samples_until(1.0);
+        // 0.0, 0.1, ..., 1.0
+        assert_eq!(samples.len(), 11);
+        assert!(close(samples[0].truth.pos, Vec3::new(2.0, 0.0, 1.0)));
+        assert!(close(gen.ground_truth(1.0).pos, Vec3::new(0.0, 2.0, 1.0)));
+        // Without noise, reported is truth.
+        assert!(samples.iter().all(|s| s.truth == s.reported));
+    }
+
+    #[test]
+    fn invalid_rate() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                SensorGenerator::new(Sensor(0), Trajectory::Stationary(Vec3::default()), rate),
+                Err(VrpnError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn noise_is_reproducible() {
+        let make = || {
+            SensorGenerator::new(Sensor(1), Trajectory::Stationary(Vec3::default()), 100.0)
+                .unwrap()
+                .with_noise(0.01, 42)
+        };
+        let a = make().samples_until(0.5);
+        let b = make().samples_until(0.5);
+        assert_eq!(a, b);
+        assert!(a.iter().any(|s| s.reported != s.truth));
+        assert!(a.iter().all(|s| s.truth.pos == Vec3::default()));
+    }
+
+    #[test]
+    fn random_walk_moves() {
+        let mut gen = SensorGenerator::new(
+            Sensor(0),
+            Trajectory::RandomWalk {
+                start: Vec3::new(1.0, 1.0, 1.0),
+                step: 0.1,
+                seed: 7,
+            },
+            10.0,
+        )
+        .unwrap();
+        let first = gen.next_sample();
+        assert_eq!(first.truth.pos, Vec3::new(1.0, 1.0, 1.0));
+        let second = gen.next_sample();
+        assert_ne!(second.truth.pos, first.truth.pos);
+        assert_eq!(gen.ground_truth(123.0).pos, second.truth.pos);
+    }
+
+    #[test]
+    fn keyframes() {
+        let keyframes = parse_keyframes_csv(
+            "# a comment\n\
+             time,x,y,z,qw,qx,qy,qz\n\
+             0, 0,0,0, 1,0,0,0\n\
+             2, 2,0,0, 0,0,0,1\n",
+        )
+        .unwrap();
+        assert_eq!(keyframes.len(), 2);
+        let gen = SensorGenerator::new(Sensor(0), Trajectory::Keyframes(keyframes), 1.0).unwrap();
+        assert!(close(gen.ground_truth(1.0).pos, Vec3::new(1.0, 0.0, 0.0)));
+        let quat = gen.ground_truth(1.0).quat;
+        assert!((quat.s - quat.v.z).abs() < 1e-9);
+        assert!(close(gen.ground_truth(5.0).pos, Vec3::new(2.0, 0.0, 0.0)));
+
+        assert!(parse_keyframes_csv("0,1,2\n").is_err());
+        assert!(parse_keyframes_csv("1,0,0,0\n0,0,0,0\n").is_err());
+        assert!(parse_keyframes_csv("0,0,0,0\nx,0,0,0\n").is_err());
+    }
+
+    #[test]
+    fn merged_in_time_order() {
+        let mut gen = TrajectoryGenerator::new()
+            .with_sensor(
+                SensorGenerator::new(Sensor(0), Trajectory::Stationary(Vec3::default()), 3.0)
+                    .unwrap(),
+            )
+            .with_sensor(
+                SensorGenerator::new(
+                    Sensor(1),
+                    Trajectory::Lissajous {
+                        center: Vec3::default(),
+                        amplitude: Vec3::new(1.0, 1.0, 0.0),
+                        frequency: Vec3::new(1.0, 2.0, 0.0),
+                        phase: Vec3::default(),
+                    },
+                    5.0,
+                )
+                .unwrap(),
+            );
+        let samples = gen.samples_until(1.0);
+        assert!(samples.windows(2).all(|w| w[0].time <= w[1].time));
+        assert!(samples.iter().any(|s| s.truth.sensor == Sensor(0)));
+        assert!(samples.iter().any(|s| s.truth.sensor == Sensor(1)));
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! `futures::Sink` adapter for packing messages on a connection.
+
+use futures::Sink;
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    buffer_unbuffer::BufferTo,
+    data_types::{
+        id_types::{LocalId, SenderId},
+        ClassOfService, TypedMessage, TypedMessageBody,
+    },
+    Connection, VrpnError,
+};
+
+/// A sink that packs each message it is given to all endpoints of a connection.
+///
+/// Accepts either bare message bodies, which are sent from the sender this sink was
+/// created with, timestamped when packed, or complete `TypedMessage`s, which are sent as-is.
+///
+/// Like `Connection::pack_message`, items are queued rather than written out immediately:
+/// the connection still needs to be polled (or driven) to actually send them.
+/// The sink itself never applies backpressure.
+///
+/// Created by `Connection::sink`.
+#[derive(Debug)]
+pub struct MessageSink<'a, C, T> {
+    connection: &'a C,
+    sender: LocalId<SenderId>,
+    class: ClassOfService,
+    _body: PhantomData<fn(T)>,
+}
+
+impl<'a, C: Connection, T> MessageSink<'a, C, T> {
+    /// Create a sink packing messages on a connection.
+    pub fn new(
+        connection: &'a C,
+        sender: LocalId<SenderId>,
+        class: ClassOfService,
+    ) -> MessageSink<'a, C, T> {
+        MessageSink {
+            connection,
+            sender,
+            class,
+            _body: PhantomData,
+        }
+    }
+
+    /// The sender used for bare message bodies.
+    pub fn sender(&self) -> LocalId<SenderId> {
+        self.sender
+    }
+
+    /// The class of service messages are packed with.
+    pub fn class(&self) -> ClassOfService {
+        self.class
+    }
+}
+
+impl<'a, C, T> Sink<T> for MessageSink<'a, C, T>
+where
+    C: Connection,
+    T: TypedMessageBody + BufferTo,
+{
+    type Error = VrpnError;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        self.connection.send(self.sender, item, self.class)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<'a, C, T> Sink<TypedMessage<T>> for MessageSink<'a, C, T>
+where
+    C: Connection,
+    T: TypedMessageBody + BufferTo,
+{
+    type Error = VrpnError;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: TypedMessage<T>) -> Result<(), Self::Error> {
+        self.connection.pack_message(item, self.class)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(all(test, feature = "async-std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        data_types::{id_types::Sensor, Message, Quat, StaticSenderName, Vec3},
+        driver,
+        tracker::PoseReport,
+        vrpn_async::MessageStream,
+        vrpn_async::{connection_ip::ConnectionIp, endpoint_ip::EndpointIp},
+        vrpn_async_std::AsyncStd,
+        CompatibilityProfile,
+    };
+    use futures::{stream, SinkExt, StreamExt};
+    use std::{sync::Arc, time::Duration};
+
+    #[test]
+    fn send_all_reports() {
+        async_std::task::block_on(async {
+            let listener = async_std::net::TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap();
+            let client = async_std::net::TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (server_side, _) = listener.accept().await.unwrap();
+
+            let conn = ConnectionIp::new_server(None, None).unwrap();
+            conn.endpoints().lock().push(Some(EndpointIp::new(
+                Arc::new(AsyncStd),
+                server_side.into(),
+                None,
+                CompatibilityProfile::default(),
+            )));
+            let (handle, driver) = driver::split(conn);
+            let _driver = async_std::task::spawn(driver);
+
+            let sender = handle
+                .register_sender(StaticSenderName(b"Tracker0"))
+                .unwrap();
+            let reports = (0..3).map(|i| {
+                Ok(PoseReport {
+                    sensor: Sensor(i),
+                    pos: Vec3::new(0.0, 0.0, 0.0),
+                    quat: Quat::identity(),
+                })
+            });
+            handle
+                .sink::<PoseReport>(sender, ClassOfService::RELIABLE)
+                .send_all(&mut stream::iter(reports))
+                .await
+                .unwrap();
+
+            let user_messages = MessageStream::new(client)
+                .filter(|msg| {
+                    let is_user = matches!(msg, Ok(msg) if !msg.message().is_system_message());
+                    async move { is_user }
+                })
+                .take(3)
+                .collect::<Vec<_>>();
+            let received = async_std::future::timeout(Duration::from_secs(5), user_messages)
+                .await
+                .expect("reports should arrive");
+            assert_eq!(received.len(), 3);
+        })
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Printing every message a connection receives, for protocol debugging.
+//! Enabled by the `tools` feature, and used by the `vrpn-sniff` tool.
+//!
+//! Names are resolved from the descriptions the peer sends, so nothing needs registering first.
+
+use crate::{
+    data_types::{
+        constants,
+        id_types::{LocalId, SenderId},
+        GenericMessage, MessageTypeId, MessageTypeName, SenderName, StaticMessageTypeName,
+    },
+    format::{MessageFormatter, NameLookup},
+    handler::{Handler, HandlerCode, HandlerHandle},
+    sync::Mutex,
+    type_dispatcher::{ANY_SENDER, ANY_TYPE},
+    Connection, Result,
+};
+use bytes::Bytes;
+use std::{collections::HashMap, io::Write, sync::Arc};
+
+/// Names by local ID, as described by the peer.
+#[derive(Debug, Default)]
+struct Names {
+    senders: HashMap<LocalId<SenderId>, Bytes>,
+    types: HashMap<LocalId<MessageTypeId>, Bytes>,
+}
+
+impl NameLookup for Names {
+    fn sender_name(&self, id: SenderId) -> Option<Bytes> {
+        self.senders.get(&LocalId(id)).cloned()
+    }
+
+    fn type_name(&self, id: MessageTypeId) -> Option<Bytes> {
+        self.types.get(&LocalId(id)).cloned()
+    }
+}
+
+/// Writes each message it handles, with names looked up in the shared table.
+struct SniffHandler {
+    names: Arc<Mutex<Names>>,
+    output: Mutex<Box<dyn Write + Send>>,
+    decode_bodies: bool,
+}
+
+impl Handler for SniffHandler {
+    fn handle(&mut self, msg: &GenericMessage) -> Result<HandlerCode> {
+        let text = {
+            let names = self.names.lock();
+            MessageFormatter::new(&*names)
+                .decode_bodies(self.decode_bodies)
+                .format(msg)
+        };
+        let mut output = self.output.lock();
+        writeln!(output, "{}", text)?;
+        output.flush()?;
+        Ok(HandlerCode::ContinueProcessing)
+    }
+}
+
+/// Write every message `connection` receives to `output`, from now on.
+///
+/// Remove the returned handle to stop. Messages from senders described
+/// before this is called are shown without their sender name.
+pub fn sniff<C: Connection>(
+    connection: &C,
+    output: impl Write + Send + 'static,
+    decode_bodies: bool,
+) -> Result<HandlerHandle> {
+    let names = Arc::new(Mutex::new(Names::default()));
+    {
+        // Our own names for the system events.
+        let dispatcher = connection.dispatcher();
+        let dispatcher = dispatcher.read();
+        let mut names = names.lock();
+        if let Some(id) = dispatcher.get_sender_id(constants::CONTROL) {
+            names
+                .senders
+                .insert(id, Bytes::from_static(constants::CONTROL.0));
+        }
+        for name in [
+            constants::GOT_FIRST_CONNECTION,
+            constants::GOT_CONNECTION,
+            constants::DROPPED_CONNECTION,
+            constants::DROPPED_LAST_CONNECTION,
+        ] {
+            let StaticMessageTypeName(bytes) = name;
+            if let Some(id) = dispatcher.get_type_id(name) {
+                names.types.insert(id, Bytes::from_static(bytes));
+            }
+        }
+    }
+    let senders = Arc::clone(&names);
+    connection.on_new_sender(move |name: &SenderName, id| {
+        senders.lock().senders.insert(id, name.0.clone());
+    })?;
+    let types = Arc::clone(&names);
+    connection.on_new_type(move |name: &MessageTypeName, id| {
+        types.lock().types.insert(id, name.0.clone());
+    })?;
+    connection.add_handler(
+        Box::new(SniffHandler {
+            names,
+            output: Mutex::new(Box::new(output)),
+            decode_bodies,
+        }),
+        ANY_TYPE,
+        ANY_SENDER,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        button::{ButtonChange, ButtonServer},
+        data_types::StaticSenderName,
+        loopback::LoopbackConnection,
+        PollEndpoints,
+    };
+    use std::io;
+
+    #[derive(Debug, Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sniffs_loopback() {
+        let (server, client) = LoopbackConnection::pair().unwrap();
+        let output = Shared::default();
+        sniff(&*client, output.clone(), true).unwrap();
+        let button = ButtonServer::new(Arc::clone(&server), StaticSenderName(b"Button0")).unwrap();
+        button
+            .report_change(
+                None,
+                ButtonChange {
+                    button: 4,
+                    pressed: true,
+                },
+            )
+            .unwrap();
+        for _ in 0..4 {
+            server.mainloop(None).unwrap();
+            client.mainloop(None).unwrap();
+        }
+        let text = String::from_utf8(output.0.lock().clone()).unwrap();
+        assert!(text.contains("\"Button0\" MessageTypeId("), "{}", text);
+        assert!(text.contains("\"vrpn_Button Change\""), "{}", text);
+        assert!(text.contains("button: 4"), "{}", text);
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Watching a connection's status, as a stream of changes,
+//! instead of calling `Connection::status` in a loop.
+//!
+//! Like a watch channel, only the latest status is kept:
+//! a watcher that falls behind skips straight to it.
+
+use crate::{connection::ConnectionStatus, sync::Mutex};
+use futures::Stream;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+
+#[derive(Debug)]
+struct State {
+    status: ConnectionStatus,
+    version: u64,
+    closed: bool,
+    wakers: Vec<Waker>,
+}
+
+/// The sending side of the watch, kept by the connection core.
+#[derive(Debug)]
+pub(crate) struct StatusSender {
+    state: Arc<Mutex<State>>,
+}
+
+impl StatusSender {
+    pub(crate) fn new(status: ConnectionStatus) -> StatusSender {
+        StatusSender {
+            state: Arc::new(Mutex::new(State {
+                status,
+                version: 0,
+                closed: false,
+                wakers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Record the current status, waking watchers if it changed.
+    pub(crate) fn send(&self, status: ConnectionStatus) {
+        let mut state = self.state.lock();
+        if state.status != status {
+            state.status = status;
+            state.version += 1;
+            state.wakers.drain(..).for_each(Waker::wake);
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> StatusWatch {
+        StatusWatch {
+            state: Arc::clone(&self.state),
+            seen: None,
+        }
+    }
+}
+
+impl Drop for StatusSender {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        state.closed = true;
+        state.wakers.drain(..).for_each(Waker::wake);
+    }
+}
+
+/// The status of a connection, from `Connection::status_watch`.
+///
+/// As a stream, it yields the status when first polled, then each time it changes,
+/// and ends once the connection is dropped. The status is updated as the connection is polled.
+#[derive(Debug, Clone)]
+pub struct StatusWatch {
+    state: Arc<Mutex<State>>,
+    seen: Option<u64>,
+}
+
+impl StatusWatch {
+    /// The latest status, without marking it as seen.
+    pub fn current(&self) -> ConnectionStatus {
+        self.state.lock().status
+    }
+
+    /// Whether the stream has a status to yield that it has not yet.
+    pub fn has_changed(&self) -> bool {
+        Some(self.state.lock().version) != self.seen
+    }
+}
+
+impl Stream for StatusWatch {
+    type Item = ConnectionStatus;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let state = Arc::clone(&self.state);
+        let mut state = state.lock();
+        if Some(state.version) != self.seen {
+            self.seen = Some(state.version);
+            Poll::Ready(Some(state.status))
+        } else if state.closed {
+            Poll::Ready(None)
+        } else {
+            if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                state.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, StreamExt};
+
+    #[test]
+    fn latest_only() {
+        let sender = StatusSender::new(ConnectionStatus::ClientConnecting);
+        let mut watch = sender.subscribe();
+        assert!(watch.has_changed());
+        assert_eq!(
+            block_on(watch.next()),
+            Some(ConnectionStatus::ClientConnecting)
+        );
+        assert!(!watch.has_changed());
+
+        sender.send(ConnectionStatus::ClientConnected);
+        sender.send(ConnectionStatus::ClientConnected);
+        sender.send(ConnectionStatus::ClientDisconnected);
+        assert_eq!(watch.current(), ConnectionStatus::ClientDisconnected);
+        assert_eq!(
+            block_on(watch.next()),
+            Some(ConnectionStatus::ClientDisconnected)
+        );
+        assert!(!watch.has_changed());
+
+        drop(sender);
+        assert_eq!(block_on(watch.next()), None);
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! The locks guarding a connection's shared state.
+//!
+//! They do not poison: a handler that panics while a lock is held does not leave
+//! every later call on the connection failing. With the `parking_lot` feature,
+//! these are `parking_lot`'s locks; otherwise they wrap the `std::sync` ones.
+
+#[cfg(feature = "parking_lot")]
+pub use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(not(feature = "parking_lot"))]
+pub use self::std_locks::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(not(feature = "parking_lot"))]
+mod std_locks {
+    use std::sync::PoisonError;
+
+    pub type MutexGuard<'a, T> = std::sync::MutexGuard<'a, T>;
+    pub type RwLockReadGuard<'a, T> = std::sync::RwLockReadGuard<'a, T>;
+    pub type RwLockWriteGuard<'a, T> = std::sync::RwLockWriteGuard<'a, T>;
+
+    /// A `std::sync::Mutex` that ignores poisoning.
+    #[derive(Debug, Default)]
+    pub struct Mutex<T: ?Sized>(std::sync::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        pub const fn new(value: T) -> Mutex<T> {
+            Mutex(std::sync::Mutex::new(value))
+        }
+
+        pub fn into_inner(self) -> T {
+            self.0.into_inner().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    impl<T: ?Sized> Mutex<T> {
+        /// Wait for the lock, even if a thread panicked while holding it.
+        pub fn lock(&self) -> MutexGuard<'_, T> {
+            self.0.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.0.get_mut().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    /// A `std::sync::RwLock` that ignores poisoning.
+    #[derive(Debug, Default)]
+    pub struct RwLock<T: ?Sized>(std::sync::RwLock<T>);
+
+    impl<T> RwLock<T> {
+        pub const fn new(value: T) -> RwLock<T> {
+            RwLock(std::sync::RwLock::new(value))
+        }
+
+        pub fn into_inner(self) -> T {
+            self.0.into_inner().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    impl<T: ?Sized> RwLock<T> {
+        /// Wait for shared access, even if a thread panicked while writing.
+        pub fn read(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        /// Wait for exclusive access, even if a thread panicked while writing.
+        pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.0.get_mut().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn usable_after_panic() {
+        let value = Arc::new(Mutex::new(1));
+        let other = Arc::clone(&value);
+        let result = std::thread::spawn(move || {
+            let _guard = other.lock();
+            panic!("while holding the lock");
+        })
+        .join();
+        assert!(result.is_err());
+        *value.lock() += 1;
+        assert_eq!(*value.lock(), 2);
+
+        let value = Arc::new(RwLock::new(1));
+        let other = Arc::clone(&value);
+        let result = std::thread::spawn(move || {
+            let _guard = other.write();
+            panic!("while holding the lock");
+        })
+        .join();
+        assert!(result.is_err());
+        *value.write() += 1;
+        assert_eq!(*value.read(), 2);
+    }
+}
@@ -11,16 +11,16 @@ extern crate bytes;
 
 use crate::{
     buffer_unbuffer::{
-        peek_u32, size_requirement::MayContainSizeRequirement, BytesMutExtras, ConstantBufferSize,
-        SizeRequirement,
+        peek_u32, size_requirement::MayContainSizeRequirement, BufferUnbufferError, BytesMutExtras,
+        ConstantBufferSize, SizeRequirement,
     },
     data_types::{
         self, id_types::SequenceNumber, CookieData, GenericMessage, Message, MessageSize,
-        SequencedGenericMessage,
+        SequencedGenericMessage, DEFAULT_MAX_MESSAGE_SIZE,
     },
     endpoint::SystemCommand,
     error::VrpnError,
-    handle_system_command, parse_system_message,
+    handle_system_command, is_known_system_message, parse_system_message,
     translation_table::TranslationTables,
     Endpoint, EndpointGeneric, TypeDispatcher,
 };
@@ -79,10 +79,10 @@ impl EndpointSyncTcp {
     fn read_single_message(&mut self) -> Result<SequencedGenericMessage, VrpnError> {
         self.stream
             .set_read_timeout(Some(Duration::from_millis(1)))?;
-        let mut buf = BytesMut::with_capacity(24);
+        let mut header = [0u8; 24];
 
         // Peek the message header and padding
-        self.stream.peek(buf.as_mut()).map_err(|e| {
+        let peeked = self.stream.peek(&mut header).map_err(|e| {
             use io::ErrorKind::*;
             match e.kind() {
                 WouldBlock | TimedOut => VrpnError::from(SizeRequirement::Unknown),
@@ -92,25 +92,36 @@ impl EndpointSyncTcp {
         })?;
 
         // Peek the size field, to compute the MessageSize.
-        let total_len = peek_u32(&buf.clone().freeze()).unwrap();
+        let total_len = peek_u32(&&header[..peeked])
+            .ok_or_else(|| VrpnError::from(SizeRequirement::Unknown))?;
         let size = MessageSize::try_from_length_field(total_len)?;
+        if size.padded_message_size() > DEFAULT_MAX_MESSAGE_SIZE {
+            return Err(BufferUnbufferError::MessageTooLarge {
+                size: size.padded_message_size(),
+                max: DEFAULT_MAX_MESSAGE_SIZE,
+            }
+            .into());
+        }
 
         // Read the body of the message
-        let mut msg_buf = BytesMut::with_capacity(size.padded_message_size());
-        self.stream.read_exact(msg_buf.as_mut())?;
-        let mut msg_buf = msg_buf.freeze();
+        let mut msg_buf = vec![0u8; size.padded_message_size()];
+        self.stream.read_exact(&mut msg_buf)?;
+        let mut msg_buf = &msg_buf[..];
 
         // Unbuffer the message.
         let result = SequencedGenericMessage::try_read_from_buf(&mut msg_buf)?;
         Ok(result)
     }
 
-    pub fn poll_endpoint(&mut self, mut dispatcher: &mut TypeDispatcher) -> Result<(), VrpnError> {
+    pub fn poll_endpoint(&mut self, dispatcher: &TypeDispatcher) -> Result<(), VrpnError> {
         loop {
             match self.read_single_message() {
                 Ok(msg) => {
                     let msg = self.map_remote_message_to_local(msg.into_inner())?;
-                    if msg.is_system_message() {
+                    if msg.is_system_message() && !is_known_system_message(msg.header.message_type)
+                    {
+                        dispatcher.call_system_handler(&msg)?;
+                    } else if msg.is_system_message() {
                         self.send_system_change(parse_system_message(msg)?)?;
                     } else {
                         dispatcher.call(&msg)?;
@@ -128,7 +139,7 @@ impl EndpointSyncTcp {
         loop {
             match self.system_rx.recv_timeout(Duration::from_micros(1)) {
                 Ok(cmd) => {
-                    if handle_system_command(&mut dispatcher, self.translation_tables_mut(), cmd)?
+                    if handle_system_command(dispatcher, self.translation_tables_mut(), cmd)?
                         .is_some()
                     {
                         // we don't handle any other system commands in this endpoint right now
@@ -158,10 +169,10 @@ impl Endpoint for EndpointSyncTcp {
     }
 
     fn send_system_change(&self, message: SystemCommand) -> Result<(), VrpnError> {
-        println!("send_system_change {:?}", message);
+        trace!("send_system_change {:?}", message);
         self.system_tx
             .send(message)
-            .map_err(|e| VrpnError::OtherMessage(e.to_string()))?;
+            .map_err(|_| VrpnError::ChannelClosed)?;
         Ok(())
     }
 
@@ -178,4 +189,20 @@ impl Endpoint for EndpointSyncTcp {
         self.stream.write_all(&buf[..])?;
         Ok(())
     }
+
+    fn buffer_generic_batch(
+        &mut self,
+        msgs: Vec<GenericMessage>,
+        _class: data_types::ClassOfService,
+    ) -> Result<(), VrpnError> {
+        // Consecutive sequence numbers, in one write.
+        let first = self.seq.fetch_add(msgs.len(), Ordering::SeqCst);
+        let mut buf = BytesMut::new();
+        for (seq, msg) in (first..).zip(msgs) {
+            let sequenced = msg.into_sequenced_message(SequenceNumber(seq as u32));
+            buf.extend_from_slice(&sequenced.try_into_buf()?);
+        }
+        self.stream.write_all(&buf[..])?;
+        Ok(())
+    }
 }
@@ -0,0 +1,89 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Connection lifecycle pseudo-messages, as in mainline VRPN.
+//!
+//! These are never sent over the wire: they are synthesized locally,
+//! from the "VRPN Control" sender, when endpoints connect and disconnect.
+//! Register a `TypedBodylessHandler` for one of these types to be notified.
+
+use crate::{
+    buffer_unbuffer::{EmptyMessage, UnbufferFrom},
+    data_types::{constants, MessageHeader, MessageTypeIdentifier, TypedMessageBody},
+    handler::{HandlerCode, TypedBodylessHandler},
+    Result,
+};
+use std::{fmt, marker::PhantomData};
+
+/// Synthesized when the first endpoint connects (before `GotConnection`).
+///
+/// Has no body.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct GotFirstConnection;
+impl EmptyMessage for GotFirstConnection {}
+impl TypedMessageBody for GotFirstConnection {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(constants::GOT_FIRST_CONNECTION);
+}
+
+/// Synthesized when any endpoint connects.
+///
+/// Has no body.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct GotConnection;
+impl EmptyMessage for GotConnection {}
+impl TypedMessageBody for GotConnection {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(constants::GOT_CONNECTION);
+}
+
+/// Synthesized when any endpoint disconnects.
+///
+/// Has no body.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct DroppedConnection;
+impl EmptyMessage for DroppedConnection {}
+impl TypedMessageBody for DroppedConnection {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(constants::DROPPED_CONNECTION);
+}
+
+/// Synthesized when the last endpoint disconnects (after `DroppedConnection`).
+///
+/// Has no body.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct DroppedLastConnection;
+impl EmptyMessage for DroppedLastConnection {}
+impl TypedMessageBody for DroppedLastConnection {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(constants::DROPPED_LAST_CONNECTION);
+}
+
+/// Calls a closure on each event of type `T`, for `Connection::on_disconnect` and `on_reconnect`.
+pub(crate) struct EventCallback<T, F> {
+    callback: F,
+    event: PhantomData<fn() -> T>,
+}
+
+impl<T, F> EventCallback<T, F> {
+    pub(crate) fn new(callback: F) -> EventCallback<T, F> {
+        EventCallback {
+            callback,
+            event: PhantomData,
+        }
+    }
+}
+
+impl<T, F> TypedBodylessHandler for EventCallback<T, F>
+where
+    T: TypedMessageBody + EmptyMessage + UnbufferFrom + fmt::Debug,
+    F: FnMut() + Send + Sync,
+{
+    type Item = T;
+
+    fn handle_typed_bodyless(&mut self, _header: &MessageHeader) -> Result<HandlerCode> {
+        (self.callback)();
+        Ok(HandlerCode::ContinueProcessing)
+    }
+}
@@ -0,0 +1,46 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Helpers for testing a server and client together in one process,
+//! without an external server. Enabled by the `testing` feature.
+//!
+//! ```ignore
+//! let pair = TestPair::memory()?;
+//! let tracker = TrackerServer::new(Arc::clone(&pair.server), StaticSenderName(b"Tracker0"))?;
+//! pair.connect(Duration::from_secs(5))?;
+//! ```
+
+use crate::{
+    buffer_unbuffer::UnbufferFrom,
+    data_types::{TypedMessage, TypedMessageBody},
+    handler::{HandlerCode, TypedHandler},
+    Result,
+};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "async-std")]
+mod pair;
+
+#[cfg(feature = "async-std")]
+pub use crate::vrpn_async_std::{MemoryListener, MemoryStream};
+#[cfg(feature = "async-std")]
+pub use pair::TestPair;
+
+/// A handler keeping the body of each message it gets, to check what arrived.
+#[derive(Debug)]
+pub struct Record<T>(pub Arc<Mutex<Vec<T>>>);
+
+impl<T> TypedHandler for Record<T>
+where
+    T: TypedMessageBody + UnbufferFrom + fmt::Debug + Clone + Send,
+{
+    type Item = T;
+    fn handle_typed(&mut self, msg: &TypedMessage<T>) -> Result<HandlerCode> {
+        self.0.lock()?.push(msg.body.clone());
+        Ok(HandlerCode::ContinueProcessing)
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+use crate::{
+    data_types::LogFileNames, message_log::RemoteLogPolicy, timeouts::TimeoutKind,
+    vrpn_async_std::connection_ip::ConnectionIp, CompatibilityProfile, Connection,
+    ConnectionStatus, Result, Scheme, ServerInfo, VrpnError,
+};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+/// A server connection and a client connection to it, polled together from one thread.
+///
+/// Only `server` has endpoints until `connect` or `pump_until` has polled them connected.
+pub struct TestPair {
+    pub server: Arc<ConnectionIp>,
+    pub client: Arc<ConnectionIp>,
+}
+
+impl TestPair {
+    /// A server on an unused localhost TCP port, and a client connecting directly over TCP.
+    pub fn tcp() -> Result<TestPair> {
+        TestPair::new(Scheme::TcpOnly)
+    }
+
+    /// A server and client connected by an in-memory stream under a name unique to this pair.
+    ///
+    /// Nothing depends on the network, so this is the most deterministic.
+    pub fn memory() -> Result<TestPair> {
+        TestPair::new(Scheme::Memory)
+    }
+
+    /// A server and client connected by `scheme`:
+    /// `Scheme::TcpOnly`, `Scheme::UdpAndTcp` or `Scheme::UdpOnly` (on localhost),
+    /// or `Scheme::Memory`.
+    pub fn new(scheme: Scheme) -> Result<TestPair> {
+        let profile = CompatibilityProfile::default();
+        let (server, server_info) = match scheme {
+            Scheme::TcpOnly | Scheme::UdpAndTcp | Scheme::UdpOnly => {
+                let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+                let server =
+                    ConnectionIp::new_server_with_compatibility(None, Some(addr), profile)?;
+                let addr = server.listen_addr().ok_or(VrpnError::CouldNotConnect)?;
+                (server, ServerInfo::new(addr, scheme))
+            }
+            Scheme::Memory => {
+                static NEXT: AtomicUsize = AtomicUsize::new(0);
+                let name = format!(
+                    "test-pair-{}-{}",
+                    std::process::id(),
+                    NEXT.fetch_add(1, Ordering::Relaxed)
+                );
+                let server = ConnectionIp::new_server_memory(&name, None, profile)?;
+                (server, ServerInfo::memory(name))
+            }
+            _ => return Err(VrpnError::UnsupportedScheme(scheme)),
+        };
+        let client = ConnectionIp::new_client_with_compatibility(server_info, None, None, profile)?;
+        Ok(TestPair { server, client })
+    }
+
+    /// As `tcp`, with log files: `server_logs` and `client_logs` kept by each end,
+    /// and `remote_logs` that the client asks the server to keep, which it does by `policy`.
+    pub fn tcp_with_logs(
+        server_logs: Option<LogFileNames>,
+        client_logs: Option<LogFileNames>,
+        remote_logs: Option<LogFileNames>,
+        policy: RemoteLogPolicy,
+    ) -> Result<TestPair> {
+        let profile = CompatibilityProfile::default();
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        let server = ConnectionIp::new_server_with_compatibility(server_logs, Some(addr), profile)?;
+        server.set_remote_log_policy(policy)?;
+        let addr = server.listen_addr().ok_or(VrpnError::CouldNotConnect)?;
+        let client = ConnectionIp::new_client_with_compatibility(
+            ServerInfo::new(addr, Scheme::TcpOnly),
+            client_logs,
+            remote_logs,
+            profile,
+        )?;
+        Ok(TestPair { server, client })
+    }
+
+    /// Poll the client and then the server, once each.
+    pub fn poll_once(&self) -> Result<()> {
+        let mut cx = std::task::Context::from_waker(futures::task::noop_waker_ref());
+        for connection in [&self.client, &self.server] {
+            if let Poll::Ready(Err(e)) = connection.poll_endpoints(&mut cx) {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Poll both ends until `done` returns true, or fail after `limit`.
+    pub fn pump_until(&self, limit: Duration, mut done: impl FnMut() -> bool) -> Result<()> {
+        let deadline = Instant::now() + limit;
+        while !done() {
+            if Instant::now() > deadline {
+                return Err(VrpnError::Timeout(TimeoutKind::ReadIdle));
+            }
+            self.poll_once()?;
+            std::thread::yield_now();
+        }
+        Ok(())
+    }
+
+    /// Poll both ends until the client has connected to the server.
+    pub fn connect(&self, limit: Duration) -> Result<()> {
+        self.pump_until(limit, || {
+            self.client.status() == ConnectionStatus::ClientConnected
+                && self.server.status() == ConnectionStatus::Server(1)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        button::{ButtonChange, ButtonRemote, ButtonServer},
+        data_types::StaticSenderName,
+        testing::Record,
+    };
+    use std::sync::Mutex;
+
+    #[test]
+    fn each_transport() {
+        for scheme in [Scheme::Memory, Scheme::TcpOnly, Scheme::UdpAndTcp] {
+            let pair = TestPair::new(scheme).unwrap();
+            let server =
+                ButtonServer::new(Arc::clone(&pair.server), StaticSenderName(b"Button0")).unwrap();
+            let remote =
+                ButtonRemote::new(Arc::clone(&pair.client), StaticSenderName(b"Button0")).unwrap();
+            let received = Arc::new(Mutex::new(Vec::<ButtonChange>::new()));
+            remote
+                .add_handler(Box::new(Record(Arc::clone(&received))))
+                .unwrap();
+            pair.connect(Duration::from_secs(5)).unwrap();
+
+            let change = ButtonChange {
+                button: 2,
+                pressed: true,
+            };
+            server.report_change(None, change).unwrap();
+            pair.pump_until(Duration::from_secs(5), || {
+                !received.lock().unwrap().is_empty()
+            })
+            .unwrap();
+            assert_eq!(received.lock().unwrap()[..], [change], "{:?}", scheme);
+        }
+    }
+
+    #[test]
+    fn remote_versions() {
+        let pair = TestPair::tcp().unwrap();
+        assert!(pair.client.remote_versions().unwrap().is_empty());
+        pair.connect(Duration::from_secs(5)).unwrap();
+        for connection in [&pair.client, &pair.server] {
+            let cookies = connection.remote_versions().unwrap();
+            assert_eq!(cookies.len(), 1);
+            assert_eq!(cookies[0].version, crate::data_types::constants::MAGIC_DATA);
+            assert_eq!(cookies[0].log_mode, Some(crate::data_types::LogMode::NONE));
+        }
+    }
+}
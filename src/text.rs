@@ -0,0 +1,121 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Text messages, which any device may send to report its status, warnings and errors:
+//! `vrpn_BaseClass` text messages in the C++ implementation.
+
+use crate::{
+    buffer_unbuffer::{
+        buffer::{check_buffer_remaining, BufferResult, BufferTo},
+        unbuffer::{check_unbuffer_remaining, UnbufferFrom, UnbufferResult},
+        BufferSize, BufferUnbufferError, ConstantBufferSize,
+    },
+    data_types::{
+        message::TypedMessageBody, name_types::StaticMessageTypeName, MessageTypeIdentifier,
+    },
+};
+use bytes::{Buf, BufMut, Bytes};
+
+/// The message type of text messages.
+pub const TEXT_MESSAGE: StaticMessageTypeName = StaticMessageTypeName(b"vrpn_Base text_message");
+
+/// The longest text, including its null terminator, as in the C++ implementation.
+pub const MAX_TEXT_LEN: usize = 1024;
+
+/// How serious a text message is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TextSeverity {
+    Normal,
+    Warning,
+    Error,
+}
+
+impl TextSeverity {
+    fn to_u32(self) -> u32 {
+        match self {
+            TextSeverity::Normal => 0,
+            TextSeverity::Warning => 1,
+            TextSeverity::Error => 2,
+        }
+    }
+}
+
+/// A text message from a device.
+///
+/// The text is sent null-terminated, after the severity and level.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TextMessage {
+    pub severity: TextSeverity,
+    /// Interpretation is up to the device.
+    pub level: u32,
+    /// Without a null terminator.
+    pub text: Bytes,
+}
+
+impl TextMessage {
+    /// A message, truncating the text to fit in `MAX_TEXT_LEN`.
+    pub fn new(severity: TextSeverity, level: u32, text: impl Into<Bytes>) -> TextMessage {
+        let mut text = text.into();
+        text.truncate(MAX_TEXT_LEN - 1);
+        TextMessage {
+            severity,
+            level,
+            text,
+        }
+    }
+}
+
+impl TypedMessageBody for TextMessage {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(TEXT_MESSAGE);
+}
+
+impl BufferSize for TextMessage {
+    fn buffer_size(&self) -> usize {
+        2 * u32::constant_buffer_size() + self.text.len() + 1
+    }
+}
+
+impl BufferTo for TextMessage {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        check_buffer_remaining(buf, self.buffer_size())?;
+        self.severity.to_u32().buffer_to(buf)?;
+        self.level.buffer_to(buf)?;
+        buf.put_slice(&self.text);
+        buf.put_u8(0);
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for TextMessage {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        check_unbuffer_remaining(buf, 2 * u32::constant_buffer_size())?;
+        let severity = match u32::unbuffer_from(buf)? {
+            0 => TextSeverity::Normal,
+            1 => TextSeverity::Warning,
+            2 => TextSeverity::Error,
+            v => {
+                return Err(BufferUnbufferError::ParseError {
+                    parsing_kind: "text severity".to_string(),
+                    s: v.to_string(),
+                })
+            }
+        };
+        let level = u32::unbuffer_from(buf)?;
+        let mut text = Vec::new();
+        while buf.has_remaining() {
+            match buf.get_u8() {
+                0 => break,
+                c => text.push(c),
+            }
+        }
+        Ok(TextMessage {
+            severity,
+            level,
+            text: Bytes::from(text),
+        })
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Rate limiting the delivery of chosen message types to handlers,
+//! so a high-rate device does not swamp a slow consumer.
+//!
+//! Throttled messages are still received and tracked: only dispatching them is skipped.
+
+use crate::data_types::{id_types::*, GenericMessage};
+use bytes::Buf;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// What to do with messages that arrive too soon after the last one delivered.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ThrottleMode {
+    /// Discard them.
+    #[default]
+    Drop,
+    /// Keep the latest, and deliver it once the interval has passed,
+    /// so the last state before a stream stops is never lost.
+    ///
+    /// It is delivered the next time the connection is polled after that.
+    Coalesce,
+}
+
+/// A limit on how often messages of one type are delivered.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Throttle {
+    /// Shortest time between delivered messages.
+    pub min_interval: Duration,
+    /// Limit each sensor separately, rather than each sender.
+    ///
+    /// The sensor is taken to be the first 32-bit integer of the body, as in tracker reports.
+    pub per_sensor: bool,
+    pub mode: ThrottleMode,
+}
+
+impl Throttle {
+    /// Deliver at most this many messages per second, for each sender, dropping the rest.
+    pub fn max_rate(per_second: f64) -> Throttle {
+        Throttle {
+            min_interval: Duration::from_secs_f64(1.0 / per_second),
+            per_sensor: false,
+            mode: ThrottleMode::Drop,
+        }
+    }
+
+    /// Apply the limit to each sensor separately.
+    pub fn per_sensor(self) -> Throttle {
+        Throttle {
+            per_sensor: true,
+            ..self
+        }
+    }
+
+    /// Use the given mode for messages over the limit.
+    pub fn with_mode(self, mode: ThrottleMode) -> Throttle {
+        Throttle { mode, ..self }
+    }
+}
+
+/// Counters of what a throttle has done.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ThrottleStats {
+    pub delivered: u64,
+    /// Messages discarded, including coalesced ones replaced by a later one.
+    pub dropped: u64,
+}
+
+type StreamKey = (SenderId, Option<i32>);
+
+#[derive(Debug)]
+struct StreamState {
+    last_delivered: Instant,
+    pending: Option<GenericMessage>,
+}
+
+#[derive(Debug)]
+struct TypeThrottle {
+    throttle: Throttle,
+    streams: HashMap<StreamKey, StreamState>,
+    stats: ThrottleStats,
+}
+
+impl TypeThrottle {
+    fn key(&self, msg: &GenericMessage) -> StreamKey {
+        let sensor = if self.throttle.per_sensor {
+            let mut body = msg.body.as_bytes().clone();
+            if body.remaining() >= 4 {
+                Some(body.get_i32())
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        (msg.header.sender, sensor)
+    }
+}
+
+/// The throttles of all message types, kept by the dispatcher.
+#[derive(Debug, Default)]
+pub struct MessageThrottle {
+    by_type: HashMap<MessageTypeId, TypeThrottle>,
+}
+
+impl MessageThrottle {
+    pub fn new() -> MessageThrottle {
+        MessageThrottle::default()
+    }
+
+    /// Set (or with `None`, remove) the throttle for a message type.
+    ///
+    /// Any coalesced messages waiting are discarded.
+    pub fn set(&mut self, message_type: LocalId<MessageTypeId>, throttle: Option<Throttle>) {
+        match throttle {
+            Some(throttle) => {
+                self.by_type.insert(
+                    message_type.into_id(),
+                    TypeThrottle {
+                        throttle,
+                        streams: HashMap::new(),
+                        stats: ThrottleStats::default(),
+                    },
+                );
+            }
+            None => {
+                self.by_type.remove(&message_type.into_id());
+            }
+        }
+    }
+
+    /// The counters for a throttled message type.
+    pub fn stats(&self, message_type: LocalId<MessageTypeId>) -> Option<ThrottleStats> {
+        self.by_type.get(&message_type.into_id()).map(|t| t.stats)
+    }
+
+    /// Whether a message should be delivered now.
+    ///
+    /// If not, it may be kept to be returned by `take_due` later.
+    pub fn admit(&mut self, msg: &GenericMessage, now: Instant) -> bool {
+        let entry = match self.by_type.get_mut(&msg.header.message_type) {
+            Some(entry) => entry,
+            None => return true,
+        };
+        let key = entry.key(msg);
+        let throttle = entry.throttle;
+        let stats = &mut entry.stats;
+        match entry.streams.get_mut(&key) {
+            Some(state) if now.duration_since(state.last_delivered) < throttle.min_interval => {
+                let replaced = match throttle.mode {
+                    ThrottleMode::Drop => true,
+                    ThrottleMode::Coalesce => state.pending.replace(msg.clone()).is_some(),
+                };
+                if replaced {
+                    stats.dropped += 1;
+                }
+                false
+            }
+            Some(state) => {
+                if state.pending.take().is_some() {
+                    stats.dropped += 1;
+                }
+                state.last_delivered = now;
+                stats.delivered += 1;
+                true
+            }
+            None => {
+                entry.streams.insert(
+                    key,
+                    StreamState {
+                        last_delivered: now,
+                        pending: None,
+                    },
+                );
+                stats.delivered += 1;
+                true
+            }
+        }
+    }
+
+    /// Whether any coalesced messages are waiting.
+    pub fn has_pending(&self) -> bool {
+        self.by_type
+            .values()
+            .flat_map(|entry| entry.streams.values())
+            .any(|state| state.pending.is_some())
+    }
+
+    /// Take the coalesced messages whose interval has passed, to deliver them.
+    pub fn take_due(&mut self, now: Instant) -> Vec<GenericMessage> {
+        let mut due = Vec::new();
+        for entry in self.by_type.values_mut() {
+            let min_interval = entry.throttle.min_interval;
+            for state in entry.streams.values_mut() {
+                if state.pending.is_some()
+                    && now.duration_since(state.last_delivered) >= min_interval
+                {
+                    due.extend(state.pending.take());
+                    state.last_delivered = now;
+                    entry.stats.delivered += 1;
+                }
+            }
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::{GenericBody, Message, MessageHeader, TimeVal};
+    use bytes::Bytes;
+
+    fn message(sender: i32, sensor: i32) -> GenericMessage {
+        GenericMessage::from_header_and_body(
+            MessageHeader::new(Some(TimeVal::default()), MessageTypeId(1), SenderId(sender)),
+            GenericBody::new(Bytes::copy_from_slice(&sensor.to_be_bytes())),
+        )
+    }
+
+    #[test]
+    fn drop_over_rate() {
+        let mut throttle = MessageThrottle::new();
+        throttle.set(LocalId(MessageTypeId(1)), Some(Throttle::max_rate(10.0)));
+        let start = Instant::now();
+        assert!(throttle.admit(&message(0, 0), start));
+        assert!(!throttle.admit(&message(0, 1), start + Duration::from_millis(50)));
+        // Other senders are limited separately, other types not at all.
+        assert!(throttle.admit(&message(1, 0), start + Duration::from_millis(50)));
+        let mut other = message(0, 0);
+        other.header.message_type = MessageTypeId(2);
+        assert!(throttle.admit(&other, start + Duration::from_millis(50)));
+
+        assert!(throttle.admit(&message(0, 0), start + Duration::from_millis(100)));
+        assert!(throttle.take_due(start + Duration::from_secs(1)).is_empty());
+        assert_eq!(
+            throttle.stats(LocalId(MessageTypeId(1))),
+            Some(ThrottleStats {
+                delivered: 3,
+                dropped: 1
+            })
+        );
+    }
+
+    #[test]
+    fn coalesce_per_sensor() {
+        let mut throttle = MessageThrottle::new();
+        throttle.set(
+            LocalId(MessageTypeId(1)),
+            Some(
+                Throttle::max_rate(10.0)
+                    .per_sensor()
+                    .with_mode(ThrottleMode::Coalesce),
+            ),
+        );
+        let start = Instant::now();
+        assert!(throttle.admit(&message(0, 0), start));
+        assert!(throttle.admit(&message(0, 1), start));
+        assert!(!throttle.admit(&message(0, 0), start + Duration::from_millis(20)));
+        let latest = message(0, 0);
+        assert!(!throttle.admit(&latest, start + Duration::from_millis(40)));
+        assert!(throttle.has_pending());
+
+        assert!(throttle
+            .take_due(start + Duration::from_millis(60))
+            .is_empty());
+        assert_eq!(
+            throttle.take_due(start + Duration::from_millis(100)),
+            vec![latest]
+        );
+        assert!(!throttle.has_pending());
+
+        throttle.set(LocalId(MessageTypeId(1)), None);
+        assert!(throttle.admit(&message(0, 0), start + Duration::from_millis(101)));
+    }
+}
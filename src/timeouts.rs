@@ -0,0 +1,81 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Limits on how long connecting and receiving may take, shared by all async backends.
+
+use std::{fmt, time::Duration};
+
+/// Default limit on establishing the transport connection to a server.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default limit on exchanging cookies (and any TLS or WebSocket handshake) once connected.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Which limit in `Timeouts` was exceeded.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum TimeoutKind {
+    Connect,
+    Handshake,
+    ReadIdle,
+    SendQueueFull,
+}
+
+impl fmt::Display for TimeoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TimeoutKind::Connect => "connecting",
+            TimeoutKind::Handshake => "waiting for the handshake",
+            TimeoutKind::ReadIdle => "waiting for data",
+            TimeoutKind::SendQueueFull => "waiting for the peer to read",
+        })
+    }
+}
+
+/// How long each stage of a connection may take before failing with `VrpnError::Timeout`.
+///
+/// `None` means no limit.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Timeouts {
+    /// Limit on establishing the transport connection, including UDP lobbing.
+    pub connect: Option<Duration>,
+    /// Limit on the handshake once connected, so a peer that accepts but never sends
+    /// a cookie cannot hold up the connection forever.
+    pub handshake: Option<Duration>,
+    /// Close an endpoint that receives nothing for this long.
+    ///
+    /// VRPN has no keep-alive of its own, so only set this for peers known to send regularly,
+    /// such as ones with `keepalive` set shorter than this.
+    /// Only the async-std backend applies this.
+    pub read_idle: Option<Duration>,
+    /// Send a harmless system message on an endpoint that has sent nothing for this long.
+    ///
+    /// Writing to a peer that has gone away without closing the connection eventually fails,
+    /// closing the endpoint, rather than waiting for the OS to notice, which can take hours.
+    /// The message is a repeat of a sender description, which C++ peers accept too.
+    /// Only the async-std backend applies this.
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Some(DEFAULT_CONNECT_TIMEOUT),
+            handshake: Some(DEFAULT_HANDSHAKE_TIMEOUT),
+            read_idle: None,
+            keepalive: None,
+        }
+    }
+}
+
+impl Timeouts {
+    /// No limits at all: wait as long as it takes.
+    pub fn none() -> Timeouts {
+        Timeouts {
+            connect: None,
+            handshake: None,
+            read_idle: None,
+            keepalive: None,
+        }
+    }
+}
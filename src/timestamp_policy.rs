@@ -0,0 +1,53 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Which clock the time in the header of a user message reflects,
+//! for filters and predictors downstream that need to know.
+//!
+//! System messages, like descriptions, keep whatever time they were given.
+
+use crate::data_types::{GenericMessage, Message, TimeVal};
+
+/// The time put in the header of outgoing user messages.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum SendTimestamp {
+    /// The time the caller gave, or the time the message was packed if it gave none.
+    #[default]
+    Caller,
+    /// The time the message is queued on each endpoint, replacing the caller's.
+    Buffered,
+    /// The time the message is serialized to be written out, replacing the caller's.
+    ///
+    /// Endpoints that do not write to a socket stamp it when queued instead.
+    Written,
+}
+
+/// The time in the header of received user messages, as handlers see it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum ReceiveTimestamp {
+    /// The time the peer sent.
+    #[default]
+    Sender,
+    /// The time the message arrived, by the local clock, replacing the peer's.
+    ///
+    /// Message history and logs still record the peer's time,
+    /// and latency tracking then measures only the time spent before dispatch.
+    Arrival,
+}
+
+/// How to timestamp user messages in each direction.
+///
+/// The default keeps the times callers and peers give.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub struct TimestampPolicy {
+    pub send: SendTimestamp,
+    pub receive: ReceiveTimestamp,
+}
+
+/// Replace the time of a user message with the current time.
+pub(crate) fn stamp_now(msg: &mut GenericMessage) {
+    if !msg.is_system_message() {
+        msg.header.time = TimeVal::get_time_of_day();
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Settings for TLS-encrypted reliable channels.
+//!
+//! These are plain settings, always available: actually using them requires
+//! the `tls` feature, with the async-std backend.
+
+use std::path::PathBuf;
+
+/// How a client verifies a TLS server. Set with `ServerInfo::with_tls`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct TlsClientOptions {
+    /// PEM file of the certificate authorities to trust.
+    pub ca_cert: PathBuf,
+    /// DNS name the server certificate must be valid for.
+    pub server_name: String,
+}
+
+impl TlsClientOptions {
+    pub fn new(ca_cert: impl Into<PathBuf>, server_name: impl Into<String>) -> TlsClientOptions {
+        TlsClientOptions {
+            ca_cert: ca_cert.into(),
+            server_name: server_name.into(),
+        }
+    }
+}
+
+/// The identity a TLS server presents to its clients.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct TlsServerOptions {
+    /// PEM file of the server certificate, followed by any intermediates.
+    pub cert_chain: PathBuf,
+    /// PEM file of the PKCS#8 or RSA private key.
+    pub private_key: PathBuf,
+}
+
+impl TlsServerOptions {
+    pub fn new(
+        cert_chain: impl Into<PathBuf>,
+        private_key: impl Into<PathBuf>,
+    ) -> TlsServerOptions {
+        TlsServerOptions {
+            cert_chain: cert_chain.into(),
+            private_key: private_key.into(),
+        }
+    }
+}
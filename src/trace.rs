@@ -0,0 +1,54 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Internal diagnostics macros.
+//!
+//! With the `tracing` feature, these forward to the `tracing` macros of the same name,
+//! so subscribers can filter by level and target. Without it, they compile to nothing.
+//! Only the plain format-string form is supported, so both variants accept the same input.
+
+#[cfg(feature = "tracing")]
+macro_rules! vrpn_event {
+    ($level:ident, $($arg:tt)+) => {
+        tracing::$level!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! vrpn_event {
+    ($level:ident, $($arg:tt)+) => {{
+        let _ = format_args!($($arg)+);
+    }};
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => { vrpn_event!(warn, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { vrpn_event!(info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => { vrpn_event!(debug, $($arg)+) };
+}
+
+macro_rules! trace {
+    ($($arg:tt)+) => { vrpn_event!(trace, $($arg)+) };
+}
+
+/// Enter a debug-level span for the rest of the enclosing block.
+#[cfg(feature = "tracing")]
+macro_rules! enter_span {
+    ($name:expr $(, $field:ident = $value:expr)*) => {
+        let _span = tracing::debug_span!($name $(, $field = $value)*).entered();
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! enter_span {
+    ($name:expr $(, $field:ident = $value:expr)*) => {
+        $(let _ = &$value;)*
+    };
+}
@@ -6,19 +6,37 @@
 
 use crate::{
     buffer_unbuffer::{
-        buffer::{BufferResult, BufferTo},
+        buffer::{check_buffer_remaining, BufferResult, BufferTo},
         unbuffer::{check_unbuffer_remaining, UnbufferFrom, UnbufferResult},
-        ConstantBufferSize,
+        BufferSize, BufferUnbufferError, ConstantBufferSize, EmptyMessage,
     },
     data_types::{
-        id_types::Sensor, message::TypedMessageBody, name_types::StaticMessageTypeName,
-        MessageTypeIdentifier, Quat, Vec3,
+        id_types::{LocalId, SenderId, Sensor},
+        message::TypedMessageBody,
+        name_types::StaticMessageTypeName,
+        ClassOfService, GenericMessage, MessageTypeIdentifier, Quat, SenderName, TimeVal,
+        TypedMessage, Vec3,
     },
+    handler::{HandlerCode, HandlerHandle},
+    type_dispatcher::ScopedHandler,
+    Connection, Endpoint, Handler, Result, TypeDispatcher, TypedHandler,
 };
 use bytes::{Buf, BufMut};
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    Stream, StreamExt,
+};
+use std::{
+    collections::{BTreeSet, HashMap},
+    convert::TryFrom,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
 
 /// Position and orientation for trackers.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PoseReport {
     /// Sensor id
     pub sensor: Sensor,
@@ -65,6 +83,7 @@ impl UnbufferFrom for PoseReport {
 
 /// Linear and angular velocity for trackers.
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VelocityReport {
     pub sensor: Sensor,
     pub vel: Vec3,
@@ -79,6 +98,7 @@ impl TypedMessageBody for VelocityReport {
 
 /// Linear and angular acceleration for trackers.
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AccelReport {
     pub sensor: Sensor,
     pub acc: Vec3,
@@ -90,3 +110,680 @@ impl TypedMessageBody for AccelReport {
     const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
         MessageTypeIdentifier::UserMessageName(StaticMessageTypeName(b"vrpn_Tracker Acceleration"));
 }
+
+/// Transform from the tracker's own coordinate system to the room's.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackerToRoom {
+    pub pos: Vec3,
+    pub quat: Quat,
+}
+
+impl TypedMessageBody for TrackerToRoom {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(StaticMessageTypeName(b"vrpn_Tracker To_Room"));
+}
+
+impl ConstantBufferSize for TrackerToRoom {
+    fn constant_buffer_size() -> usize {
+        Vec3::constant_buffer_size() + Quat::constant_buffer_size()
+    }
+}
+
+impl BufferTo for TrackerToRoom {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        check_buffer_remaining(buf, Self::constant_buffer_size())?;
+        self.pos.buffer_to(buf)?;
+        self.quat.buffer_to(buf)?;
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for TrackerToRoom {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        check_unbuffer_remaining(buf, Self::constant_buffer_size())?;
+        let pos = Vec3::unbuffer_from(buf)?;
+        let quat = Quat::unbuffer_from(buf)?;
+        Ok(TrackerToRoom { pos, quat })
+    }
+}
+
+/// Transform from a sensor's coordinate system to that of the unit it is mounted on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitToSensor {
+    pub sensor: Sensor,
+    pub pos: Vec3,
+    pub quat: Quat,
+}
+
+impl TypedMessageBody for UnitToSensor {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier = MessageTypeIdentifier::UserMessageName(
+        StaticMessageTypeName(b"vrpn_Tracker Unit_To_Sensor"),
+    );
+}
+
+impl ConstantBufferSize for UnitToSensor {
+    fn constant_buffer_size() -> usize {
+        // The sensor is padded to 8 bytes, as in a pose report.
+        Sensor::constant_buffer_size() * 2
+            + Vec3::constant_buffer_size()
+            + Quat::constant_buffer_size()
+    }
+}
+
+impl BufferTo for UnitToSensor {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        check_buffer_remaining(buf, Self::constant_buffer_size())?;
+        self.sensor.buffer_to(buf)?;
+        Sensor(0).buffer_to(buf)?;
+        self.pos.buffer_to(buf)?;
+        self.quat.buffer_to(buf)?;
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for UnitToSensor {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        check_unbuffer_remaining(buf, Self::constant_buffer_size())?;
+        let sensor = Sensor::unbuffer_from(buf)?;
+        let _ = Sensor::unbuffer_from(buf)?;
+        let pos = Vec3::unbuffer_from(buf)?;
+        let quat = Quat::unbuffer_from(buf)?;
+        Ok(UnitToSensor { sensor, pos, quat })
+    }
+}
+
+/// The box, in room coordinates, within which the tracker works.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Workspace {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl TypedMessageBody for Workspace {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+        MessageTypeIdentifier::UserMessageName(StaticMessageTypeName(b"vrpn_Tracker Workspace"));
+}
+
+impl ConstantBufferSize for Workspace {
+    fn constant_buffer_size() -> usize {
+        Vec3::constant_buffer_size() * 2
+    }
+}
+
+impl BufferTo for Workspace {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        check_buffer_remaining(buf, Self::constant_buffer_size())?;
+        self.min.buffer_to(buf)?;
+        self.max.buffer_to(buf)?;
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for Workspace {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        check_unbuffer_remaining(buf, Self::constant_buffer_size())?;
+        let min = Vec3::unbuffer_from(buf)?;
+        let max = Vec3::unbuffer_from(buf)?;
+        Ok(Workspace { min, max })
+    }
+}
+
+/// Defines an empty request message type, answered by a server with the corresponding report.
+macro_rules! tracker_request {
+    ($(#[$meta:meta])* $name:ident, $message_name:expr) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub struct $name;
+
+        impl EmptyMessage for $name {}
+        impl TypedMessageBody for $name {
+            const MESSAGE_IDENTIFIER: MessageTypeIdentifier =
+                MessageTypeIdentifier::UserMessageName(StaticMessageTypeName($message_name));
+        }
+    };
+}
+
+tracker_request!(
+    /// Request for a `TrackerToRoom` report.
+    RequestTrackerToRoom,
+    b"vrpn_Tracker Request_Tracker_To_Room"
+);
+tracker_request!(
+    /// Request for a `UnitToSensor` report for every sensor.
+    RequestUnitToSensor,
+    b"vrpn_Tracker Request_Unit_To_Sensor"
+);
+tracker_request!(
+    /// Request for a `Workspace` report.
+    RequestWorkspace,
+    b"vrpn_Tracker Request_Tracker_Workspace"
+);
+
+/// Request from a client for only certain sensors of a tracker sender.
+///
+/// This is an extension not present in mainline VRPN: C++ servers ignore it
+/// (as an unknown message type), and C++ clients never send it, so servers
+/// send them every sensor. An empty list of sensors means "all sensors".
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SensorSubscription {
+    pub sensors: Vec<Sensor>,
+}
+
+impl SensorSubscription {
+    pub fn new(sensors: impl IntoIterator<Item = Sensor>) -> SensorSubscription {
+        SensorSubscription {
+            sensors: sensors.into_iter().collect(),
+        }
+    }
+}
+
+impl TypedMessageBody for SensorSubscription {
+    const MESSAGE_IDENTIFIER: MessageTypeIdentifier = MessageTypeIdentifier::UserMessageName(
+        StaticMessageTypeName(b"vrpn_Tracker Sensor_Subscribe"),
+    );
+}
+
+impl BufferSize for SensorSubscription {
+    fn buffer_size(&self) -> usize {
+        i32::constant_buffer_size() + self.sensors.len() * Sensor::constant_buffer_size()
+    }
+}
+
+impl BufferTo for SensorSubscription {
+    fn buffer_to<T: BufMut>(&self, buf: &mut T) -> BufferResult {
+        let len =
+            i32::try_from(self.sensors.len()).map_err(|_| BufferUnbufferError::OutOfBuffer)?;
+        len.buffer_to(buf)?;
+        for sensor in &self.sensors {
+            sensor.buffer_to(buf)?;
+        }
+        Ok(())
+    }
+}
+
+impl UnbufferFrom for SensorSubscription {
+    fn unbuffer_from<T: Buf>(buf: &mut T) -> UnbufferResult<Self> {
+        let len = i32::unbuffer_from(buf)?;
+        let len = usize::try_from(len).map_err(|_| BufferUnbufferError::ParseError {
+            parsing_kind: "sensor subscription length".to_string(),
+            s: len.to_string(),
+        })?;
+        check_unbuffer_remaining(buf, len * Sensor::constant_buffer_size())?;
+        let sensors = (0..len)
+            .map(|_| Sensor::unbuffer_from(buf))
+            .collect::<UnbufferResult<Vec<Sensor>>>()?;
+        Ok(SensorSubscription { sensors })
+    }
+}
+
+/// Per-endpoint record of which sensors of which tracker senders a client wants.
+///
+/// Senders with no subscription recorded get all sensors.
+#[derive(Clone, Debug, Default)]
+pub struct SensorFilter {
+    by_sender: HashMap<LocalId<SenderId>, BTreeSet<Sensor>>,
+}
+
+impl SensorFilter {
+    pub fn new() -> SensorFilter {
+        SensorFilter::default()
+    }
+
+    /// Record a subscription request for a sender, replacing any previous one.
+    pub fn apply(&mut self, sender: LocalId<SenderId>, subscription: &SensorSubscription) {
+        if subscription.sensors.is_empty() {
+            self.by_sender.remove(&sender);
+        } else {
+            self.by_sender
+                .insert(sender, subscription.sensors.iter().copied().collect());
+        }
+    }
+
+    /// Does the client want reports about this sensor from this sender?
+    pub fn allows(&self, sender: LocalId<SenderId>, sensor: Sensor) -> bool {
+        match self.by_sender.get(&sender) {
+            Some(sensors) => sensors.contains(&sensor),
+            None => true,
+        }
+    }
+}
+
+/// If this message is a sensor subscription, update the endpoint's filter.
+///
+/// Call with messages that have already been mapped to local IDs.
+pub(crate) fn update_sensor_filter<T: Endpoint + ?Sized>(
+    endpoint: &mut T,
+    dispatcher: &TypeDispatcher,
+    msg: &GenericMessage,
+) -> Result<()> {
+    if let MessageTypeIdentifier::UserMessageName(name) = SensorSubscription::MESSAGE_IDENTIFIER {
+        if dispatcher.get_type_id(name) != Some(LocalId(msg.header.message_type)) {
+            return Ok(());
+        }
+    }
+    if let Some(filter) = endpoint.sensor_filter_mut() {
+        let subscription = TypedMessage::<SensorSubscription>::try_from(msg)?;
+        filter.apply(LocalId(msg.header.sender), &subscription.body);
+    }
+    Ok(())
+}
+
+/// Ask servers to only send reports about certain sensors of a tracker.
+///
+/// Pass an empty iterator to go back to receiving all sensors.
+pub fn subscribe_sensors<C: Connection>(
+    connection: &C,
+    sender: LocalId<SenderId>,
+    sensors: impl IntoIterator<Item = Sensor>,
+) -> Result<()> {
+    connection.pack_message_body(
+        None,
+        sender,
+        SensorSubscription::new(sensors),
+        ClassOfService::RELIABLE,
+    )
+}
+
+/// Forwards the pose reports of one sensor, or all of them, into a channel.
+#[derive(Debug)]
+struct PoseForwarder {
+    sensor: Option<Sensor>,
+    tx: UnboundedSender<PoseReport>,
+}
+
+impl TypedHandler for PoseForwarder {
+    type Item = PoseReport;
+    fn handle_typed(&mut self, msg: &TypedMessage<PoseReport>) -> Result<HandlerCode> {
+        if (self.sensor.is_none() || self.sensor == Some(msg.body.sensor))
+            && self.tx.unbounded_send(msg.body.clone()).is_err()
+        {
+            return Ok(HandlerCode::RemoveThisHandler);
+        }
+        Ok(HandlerCode::ContinueProcessing)
+    }
+}
+
+/// The pose reports of a `TrackerRemote`, as they are dispatched while polling its connection.
+///
+/// Its handler is removed when this is dropped: don't drop it from within a handler
+/// of the same connection. Ends if the connection is dropped.
+#[derive(Debug)]
+pub struct PoseStream {
+    rx: UnboundedReceiver<PoseReport>,
+    _handler: ScopedHandler,
+}
+
+impl Stream for PoseStream {
+    type Item = PoseReport;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<PoseReport>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
+
+/// Client side of a tracker device.
+#[derive(Debug)]
+pub struct TrackerRemote<C: Connection> {
+    connection: Arc<C>,
+    sender: LocalId<SenderId>,
+}
+
+impl<C: Connection> TrackerRemote<C> {
+    pub fn new(connection: Arc<C>, name: impl Into<SenderName>) -> Result<TrackerRemote<C>> {
+        let sender = connection.register_sender(name.into())?;
+        Ok(TrackerRemote { connection, sender })
+    }
+
+    /// The local sender ID of this tracker.
+    pub fn sender(&self) -> LocalId<SenderId> {
+        self.sender
+    }
+
+    /// Add a handler for one kind of report from this tracker,
+    /// e.g. `PoseReport`, `TrackerToRoom`, `UnitToSensor`, or `Workspace`.
+    pub fn add_handler<H>(&self, handler: Box<H>) -> Result<HandlerHandle>
+    where
+        H: TypedHandler + Handler + 'static,
+    {
+        self.connection
+            .add_typed_handler(handler, Some(self.sender))
+    }
+
+    fn pose_stream(&self, sensor: Option<Sensor>) -> Result<PoseStream> {
+        let (tx, rx) = unbounded();
+        let handler = self
+            .connection
+            .add_typed_handler_scoped(Box::new(PoseForwarder { sensor, tx }), Some(self.sender))?;
+        Ok(PoseStream {
+            rx,
+            _handler: handler,
+        })
+    }
+
+    /// The pose reports of one sensor, skipping the rest.
+    pub fn sensor_stream(&self, sensor: Sensor) -> Result<PoseStream> {
+        self.pose_stream(Some(sensor))
+    }
+
+    /// The pose reports of every sensor, each with the sensor it is from.
+    pub fn all_sensors(&self) -> Result<impl Stream<Item = (Sensor, PoseReport)>> {
+        Ok(self
+            .pose_stream(None)?
+            .map(|report| (report.sensor, report)))
+    }
+
+    fn request<T: TypedMessageBody + BufferTo + Default>(&self) -> Result<()> {
+        self.connection
+            .pack_message_body(None, self.sender, T::default(), ClassOfService::RELIABLE)
+    }
+
+    /// Ask the server for the tracker-to-room transform.
+    pub fn request_tracker_to_room(&self) -> Result<()> {
+        self.request::<RequestTrackerToRoom>()
+    }
+
+    /// Ask the server for the unit-to-sensor transform of every sensor.
+    pub fn request_unit_to_sensor(&self) -> Result<()> {
+        self.request::<RequestUnitToSensor>()
+    }
+
+    /// Ask the server for the workspace bounds.
+    pub fn request_workspace(&self) -> Result<()> {
+        self.request::<RequestWorkspace>()
+    }
+}
+
+/// Server side of a tracker device, which honors per-endpoint sensor subscriptions.
+#[derive(Debug)]
+pub struct TrackerServer<C: Connection> {
+    connection: Arc<C>,
+    sender: LocalId<SenderId>,
+}
+
+impl<C: Connection> TrackerServer<C> {
+    pub fn new(connection: Arc<C>, name: impl Into<SenderName>) -> Result<TrackerServer<C>> {
+        let sender = connection.register_sender(name.into())?;
+        // Make sure we recognize subscription and other requests.
+        for identifier in &[
+            SensorSubscription::MESSAGE_IDENTIFIER,
+            RequestTrackerToRoom::MESSAGE_IDENTIFIER,
+            RequestUnitToSensor::MESSAGE_IDENTIFIER,
+            RequestWorkspace::MESSAGE_IDENTIFIER,
+        ] {
+            if let MessageTypeIdentifier::UserMessageName(name) = identifier {
+                connection.register_type(name.clone())?;
+            }
+        }
+        Ok(TrackerServer { connection, sender })
+    }
+
+    /// The local sender ID of this tracker.
+    pub fn sender(&self) -> LocalId<SenderId> {
+        self.sender
+    }
+
+    /// Send the tracker-to-room transform, e.g. in reply to a `RequestTrackerToRoom`.
+    pub fn report_tracker_to_room(
+        &self,
+        time: Option<TimeVal>,
+        xform: TrackerToRoom,
+    ) -> Result<()> {
+        self.connection
+            .pack_message_body(time, self.sender, xform, ClassOfService::RELIABLE)
+    }
+
+    /// Send the unit-to-sensor transform of one sensor, e.g. in reply to a `RequestUnitToSensor`.
+    pub fn report_unit_to_sensor(&self, time: Option<TimeVal>, xform: UnitToSensor) -> Result<()> {
+        self.connection
+            .pack_message_body(time, self.sender, xform, ClassOfService::RELIABLE)
+    }
+
+    /// Send the workspace bounds, e.g. in reply to a `RequestWorkspace`.
+    pub fn report_workspace(&self, time: Option<TimeVal>, workspace: Workspace) -> Result<()> {
+        self.connection
+            .pack_message_body(time, self.sender, workspace, ClassOfService::RELIABLE)
+    }
+
+    /// Send a pose report to every endpoint that has not excluded its sensor.
+    pub fn report_pose(
+        &self,
+        time: Option<TimeVal>,
+        report: PoseReport,
+        class: ClassOfService,
+    ) -> Result<()> {
+        let sensor = report.sensor;
+        let message_type = match PoseReport::MESSAGE_IDENTIFIER {
+            MessageTypeIdentifier::UserMessageName(name) => self.connection.register_type(name)?,
+            MessageTypeIdentifier::SystemMessageId(id) => LocalId(id),
+        };
+        let msg =
+            GenericMessage::try_from(TypedMessage::new(time, message_type, self.sender, report))?;
+        let endpoints = self.connection.endpoints();
+        let mut endpoints = endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            let wanted = match ep.sensor_filter() {
+                Some(filter) => filter.allows(self.sender, sensor),
+                None => true,
+            };
+            if wanted {
+                ep.buffer_generic_message(msg.clone(), class)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the pose reports of several sensors together, as of one frame,
+    /// to every endpoint: each gets those of the sensors it has not excluded.
+    pub fn report_poses(
+        &self,
+        time: Option<TimeVal>,
+        reports: impl IntoIterator<Item = PoseReport>,
+        class: ClassOfService,
+    ) -> Result<()> {
+        let message_type = match PoseReport::MESSAGE_IDENTIFIER {
+            MessageTypeIdentifier::UserMessageName(name) => self.connection.register_type(name)?,
+            MessageTypeIdentifier::SystemMessageId(id) => LocalId(id),
+        };
+        let msgs = reports
+            .into_iter()
+            .map(|report| {
+                let sensor = report.sensor;
+                let msg = TypedMessage::new(time, message_type, self.sender, report);
+                Ok((sensor, GenericMessage::try_from(msg)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let endpoints = self.connection.endpoints();
+        let mut endpoints = endpoints.lock();
+        for ep in endpoints.iter_mut().flatten() {
+            let batch = msgs
+                .iter()
+                .filter(|(sensor, _)| match ep.sensor_filter() {
+                    Some(filter) => filter.allows(self.sender, *sensor),
+                    None => true,
+                })
+                .map(|(_, msg)| msg.clone())
+                .collect();
+            ep.buffer_generic_batch(batch, class)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_unbuffer::BytesMutExtras;
+    use bytes::BytesMut;
+
+    #[test]
+    fn transform_wire_formats() {
+        let u2s = UnitToSensor {
+            sensor: Sensor(1),
+            pos: Vec3::new(1.0, 0.0, 0.0),
+            quat: Quat::identity(),
+        };
+        let buf = BytesMut::allocate_and_buffer(u2s).unwrap().freeze();
+        // Sensor and padding, then position and quaternion (x, y, z, w), as vrpn_Tracker
+        assert_eq!(
+            &buf[..],
+            &hex!(
+                "00000001 00000000"
+                "3ff0000000000000 0000000000000000 0000000000000000"
+                "0000000000000000 0000000000000000 0000000000000000 3ff0000000000000"
+            )[..]
+        );
+        assert_eq!(UnitToSensor::unbuffer_from(&mut buf.clone()).unwrap(), u2s);
+
+        let t2r = TrackerToRoom {
+            pos: Vec3::new(0.0, 1.5, 0.0),
+            quat: Quat::identity(),
+        };
+        let buf = BytesMut::allocate_and_buffer(t2r).unwrap().freeze();
+        assert_eq!(buf.len(), 56);
+        assert_eq!(TrackerToRoom::unbuffer_from(&mut buf.clone()).unwrap(), t2r);
+
+        let workspace = Workspace {
+            min: Vec3::new(-1.0, 0.0, -1.0),
+            max: Vec3::new(1.0, 2.0, 1.0),
+        };
+        let buf = BytesMut::allocate_and_buffer(workspace).unwrap().freeze();
+        assert_eq!(buf.len(), 48);
+        assert_eq!(
+            Workspace::unbuffer_from(&mut buf.clone()).unwrap(),
+            workspace
+        );
+
+        assert_eq!(RequestWorkspace.buffer_size(), 0);
+    }
+
+    #[test]
+    fn subscription_roundtrip() {
+        let sub = SensorSubscription::new(vec![Sensor(0), Sensor(5)]);
+        let mut buf = BytesMut::allocate_and_buffer(sub.clone()).unwrap().freeze();
+        assert_eq!(buf.len(), sub.buffer_size());
+        assert_eq!(SensorSubscription::unbuffer_from(&mut buf).unwrap(), sub);
+
+        let mut buf = BytesMut::allocate_and_buffer(-1_i32).unwrap().freeze();
+        assert!(SensorSubscription::unbuffer_from(&mut buf).is_err());
+        let mut buf = BytesMut::allocate_and_buffer(3_i32).unwrap().freeze();
+        assert!(SensorSubscription::unbuffer_from(&mut buf).is_err());
+    }
+
+    #[test]
+    fn filter() {
+        let a = LocalId(SenderId(1));
+        let b = LocalId(SenderId(2));
+        let mut filter = SensorFilter::new();
+        assert!(filter.allows(a, Sensor(3)));
+
+        filter.apply(a, &SensorSubscription::new(vec![Sensor(1)]));
+        assert!(filter.allows(a, Sensor(1)));
+        assert!(!filter.allows(a, Sensor(3)));
+        assert!(filter.allows(b, Sensor(3)));
+
+        filter.apply(a, &SensorSubscription::default());
+        assert!(filter.allows(a, Sensor(3)));
+    }
+
+    #[test]
+    fn sensor_streams() {
+        use crate::{data_types::StaticSenderName, loopback::LoopbackConnection, PollEndpoints};
+        use futures::FutureExt;
+
+        let (server, client) = LoopbackConnection::pair().unwrap();
+        let tracker =
+            TrackerServer::new(Arc::clone(&server), StaticSenderName(b"Tracker0")).unwrap();
+        let remote =
+            TrackerRemote::new(Arc::clone(&client), StaticSenderName(b"Tracker0")).unwrap();
+        let mut second = remote.sensor_stream(Sensor(1)).unwrap();
+        let mut all = Box::pin(remote.all_sensors().unwrap());
+        for sensor in [0, 1, 2] {
+            let report = PoseReport {
+                sensor: Sensor(sensor),
+                pos: Vec3::new(f64::from(sensor), 0.0, 0.0),
+                quat: Quat::identity(),
+            };
+            tracker
+                .report_pose(None, report, ClassOfService::RELIABLE)
+                .unwrap();
+        }
+        for _ in 0..4 {
+            server.mainloop(None).unwrap();
+            client.mainloop(None).unwrap();
+        }
+
+        let report = second.next().now_or_never().flatten().unwrap();
+        assert_eq!(report.sensor, Sensor(1));
+        assert_eq!(report.pos, Vec3::new(1.0, 0.0, 0.0));
+        assert!(second.next().now_or_never().is_none());
+        let sensors: Vec<Sensor> = (0..3)
+            .map(|_| all.next().now_or_never().flatten().unwrap().0)
+            .collect();
+        assert_eq!(sensors, [Sensor(0), Sensor(1), Sensor(2)]);
+
+        drop(second);
+        drop(server);
+        drop(remote);
+        drop(client);
+        assert_eq!(all.next().now_or_never(), Some(None));
+    }
+
+    #[test]
+    fn batched_poses() {
+        use crate::{data_types::StaticSenderName, loopback::LoopbackConnection, PollEndpoints};
+        use futures::FutureExt;
+
+        let (server, client) = LoopbackConnection::pair().unwrap();
+        let tracker =
+            TrackerServer::new(Arc::clone(&server), StaticSenderName(b"Tracker0")).unwrap();
+        let remote =
+            TrackerRemote::new(Arc::clone(&client), StaticSenderName(b"Tracker0")).unwrap();
+        let mut all = Box::pin(remote.all_sensors().unwrap());
+        let reports = (0..3).map(|sensor| PoseReport {
+            sensor: Sensor(sensor),
+            pos: Vec3::new(f64::from(sensor), 0.0, 0.0),
+            quat: Quat::identity(),
+        });
+        tracker
+            .report_poses(None, reports, ClassOfService::RELIABLE)
+            .unwrap();
+        for _ in 0..4 {
+            server.mainloop(None).unwrap();
+            client.mainloop(None).unwrap();
+        }
+
+        let sensors: Vec<Sensor> = (0..3)
+            .map(|_| all.next().now_or_never().flatten().unwrap().0)
+            .collect();
+        assert_eq!(sensors, [Sensor(0), Sensor(1), Sensor(2)]);
+        assert!(all.next().now_or_never().is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn pose_json() {
+        use crate::data_types::{MessageTypeId, Microseconds, Seconds};
+        let msg = TypedMessage::new(
+            Some(TimeVal::new(Seconds(10), Microseconds(500))),
+            MessageTypeId(4),
+            SenderId(1),
+            PoseReport {
+                sensor: Sensor(2),
+                pos: Vec3::new(0.5, 0.0, -1.0),
+                quat: Quat::identity(),
+            },
+        );
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"header":{"time":{"sec":10,"usec":500},"message_type":4,"sender":1},"body":{"sensor":2,"pos":{"x":0.5,"y":0.0,"z":-1.0},"quat":{"s":1.0,"v":{"x":0.0,"y":0.0,"z":0.0}}}}"#
+        );
+        let back: TypedMessage<PoseReport> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, msg);
+    }
+}
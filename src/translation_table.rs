@@ -4,7 +4,7 @@
 
 //! Code for associating names and local IDs with their remote equivalents.
 
-use std::convert::TryFrom;
+use std::{convert::TryFrom, fmt};
 
 use crate::{
     data_types::{id_types::*, GenericMessage},
@@ -33,17 +33,9 @@ impl<T: UnwrappedId> Entry<T> {
         self.local_id = local_id;
     }
 
-    #[deprecated]
-    pub(crate) fn name(&self) -> &Bytes {
-        &self.name
-    }
     pub(crate) fn local_id(&self) -> LocalId<T> {
         self.local_id
     }
-    #[deprecated]
-    pub(crate) fn remote_id(&self) -> RemoteId<T> {
-        self.remote_id
-    }
 }
 
 impl<T: TryIntoDescriptionMessage + UnwrappedId> TryFrom<Entry<T>> for GenericMessage {
@@ -54,6 +46,36 @@ impl<T: TryIntoDescriptionMessage + UnwrappedId> TryFrom<Entry<T>> for GenericMe
     }
 }
 
+/// A public copy of a translation table entry: a name, and its remote and local IDs.
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Mapping<T: UnwrappedId> {
+    pub name: Bytes,
+    pub local_id: LocalId<T>,
+    pub remote_id: RemoteId<T>,
+}
+
+impl<T: UnwrappedId> From<&Entry<T>> for Mapping<T> {
+    fn from(entry: &Entry<T>) -> Mapping<T> {
+        Mapping {
+            name: entry.name.clone(),
+            local_id: entry.local_id,
+            remote_id: entry.remote_id,
+        }
+    }
+}
+
+impl<T: UnwrappedId> fmt::Display for Mapping<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}: remote {} -> local {}",
+            self.name,
+            self.remote_id.get(),
+            self.local_id.get()
+        )
+    }
+}
+
 /// A structure mapping names and local IDs to their remote equivalents
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct TranslationTable<T: UnwrappedId> {
@@ -148,10 +170,40 @@ impl<T: UnwrappedId> TranslationTable<T> {
         }
     }
 
-    /// Get an iterator to non-None table entries.
-    #[deprecated]
-    pub(crate) fn iter(&self) -> impl Iterator<Item = &Entry<T>> {
-        self.entries.iter().flatten()
+    /// Iterate through all mappings the remote side has declared, in remote ID order.
+    pub fn mappings(&self) -> impl Iterator<Item = Mapping<T>> + '_ {
+        self.entries.iter().flatten().map(Mapping::from)
+    }
+
+    /// Look up the mapping for a name.
+    pub fn find_by_name(&self, name: &[u8]) -> Option<Mapping<T>> {
+        self.find_by_predicate(|entry| entry.name == name)
+            .map(Mapping::from)
+    }
+
+    /// Whether the table already maps this remote ID to this name, as a repeated description would.
+    pub(crate) fn contains_remote_entry(&self, name: &[u8], remote_id: RemoteId<T>) -> bool {
+        match self.determine_remote_id_range(remote_id) {
+            CategorizedId::InArray(v) => {
+                matches!(&self.entries[v as usize], Some(entry) if entry.name == name)
+            }
+            _ => false,
+        }
+    }
+
+    /// Look up the mapping for a remote ID.
+    pub fn find_by_remote_id(&self, remote_id: RemoteId<T>) -> Option<Mapping<T>> {
+        self.find_by_predicate(|entry| entry.remote_id == remote_id)
+            .map(Mapping::from)
+    }
+
+    /// Get the number of mappings in the table.
+    pub fn len(&self) -> usize {
+        self.entries.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     /// Deletes every entry in the table
@@ -165,6 +217,7 @@ impl<T: UnwrappedId> TranslationTable<T> {
 pub struct TranslationTables {
     types: TranslationTable<MessageTypeId>,
     senders: TranslationTable<SenderId>,
+    duplicate_descriptions: u64,
 }
 
 impl TranslationTables {
@@ -172,6 +225,7 @@ impl TranslationTables {
         TranslationTables {
             types: TranslationTable::new(),
             senders: TranslationTable::new(),
+            duplicate_descriptions: 0,
         }
     }
 
@@ -179,6 +233,66 @@ impl TranslationTables {
         self.types.clear();
         self.senders.clear();
     }
+
+    /// Access the message type table.
+    pub fn types(&self) -> &TranslationTable<MessageTypeId> {
+        &self.types
+    }
+
+    /// Access the sender table.
+    pub fn senders(&self) -> &TranslationTable<SenderId> {
+        &self.senders
+    }
+
+    /// How many descriptions repeated a mapping already in the tables, and so were ignored.
+    pub fn duplicate_descriptions(&self) -> u64 {
+        self.duplicate_descriptions
+    }
+
+    /// Count an ignored, repeated description, returning the new count.
+    pub(crate) fn count_duplicate_description(&mut self) -> u64 {
+        self.duplicate_descriptions += 1;
+        self.duplicate_descriptions
+    }
+
+    /// Copy the current contents of both tables, for debugging or later inspection.
+    pub fn snapshot(&self) -> TranslationTablesSnapshot {
+        TranslationTablesSnapshot {
+            types: self.types.mappings().collect(),
+            senders: self.senders.mappings().collect(),
+            duplicate_descriptions: self.duplicate_descriptions,
+        }
+    }
+}
+
+/// A copy of the contents of a `TranslationTables`, independent of any locks.
+#[derive(Debug, Clone, Default, Hash, Eq, PartialEq)]
+pub struct TranslationTablesSnapshot {
+    pub types: Vec<Mapping<MessageTypeId>>,
+    pub senders: Vec<Mapping<SenderId>>,
+    /// Descriptions ignored for repeating a mapping already in the tables.
+    pub duplicate_descriptions: u64,
+}
+
+impl fmt::Display for TranslationTablesSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Senders:")?;
+        for mapping in &self.senders {
+            writeln!(f, "  {}", mapping)?;
+        }
+        writeln!(f, "Message types:")?;
+        for mapping in &self.types {
+            writeln!(f, "  {}", mapping)?;
+        }
+        if self.duplicate_descriptions > 0 {
+            writeln!(
+                f,
+                "Repeated descriptions ignored: {}",
+                self.duplicate_descriptions
+            )?;
+        }
+        Ok(())
+    }
 }
 
 impl Default for TranslationTables {
@@ -211,26 +325,6 @@ impl AsMut<TranslationTable<MessageTypeId>> for TranslationTables {
     }
 }
 
-pub(crate) trait InTranslationTables: UnwrappedId
-where
-    TranslationTables: AsRef<TranslationTable<Self>>,
-{
-}
-
-impl<T: UnwrappedId> InTranslationTables for T where TranslationTables: AsRef<TranslationTable<Self>>
-{}
-
-pub(crate) trait InMutTranslationTables: UnwrappedId
-where
-    TranslationTables: AsMut<TranslationTable<Self>>,
-{
-}
-
-impl<T: UnwrappedId> InMutTranslationTables for T where
-    TranslationTables: AsMut<TranslationTable<Self>>
-{
-}
-
 pub(crate) trait TranslationTableExt<I: UnwrappedId>: AsRef<TranslationTable<I>> {
     /// Gets a shared borrow of an entry, given its local ID.
     fn find_by_local_id(&self, local_id: LocalId<I>) -> Option<&Entry<I>> {
@@ -256,4 +350,49 @@ mod tests {
             )
             .expect("Failed adding remote entry");
     }
+
+    #[test]
+    fn introspection() {
+        use super::*;
+        let mut tables = TranslationTables::new();
+        {
+            let senders: &mut TranslationTable<SenderId> = tables.as_mut();
+            senders
+                .add_remote_entry(
+                    Bytes::from_static(b"Tracker0"),
+                    RemoteId(SenderId(1)),
+                    LocalId(SenderId(3)),
+                )
+                .unwrap();
+        }
+        {
+            let types: &mut TranslationTable<MessageTypeId> = tables.as_mut();
+            types
+                .add_remote_entry(
+                    Bytes::from_static(b"vrpn_Tracker Pos_Quat"),
+                    RemoteId(MessageTypeId(0)),
+                    LocalId(MessageTypeId(5)),
+                )
+                .unwrap();
+        }
+        // The gap at remote sender ID 0 is skipped.
+        assert_eq!(tables.senders().len(), 1);
+        let mapping = tables.senders().find_by_name(b"Tracker0").unwrap();
+        assert_eq!(mapping.local_id, LocalId(SenderId(3)));
+        assert_eq!(mapping.remote_id, RemoteId(SenderId(1)));
+        assert_eq!(
+            tables.senders().find_by_remote_id(RemoteId(SenderId(1))),
+            Some(mapping)
+        );
+        assert!(tables.senders().find_by_name(b"Tracker1").is_none());
+
+        let snapshot = tables.snapshot();
+        tables.clear();
+        assert!(tables.types().is_empty());
+        assert_eq!(snapshot.types.len(), 1);
+        assert_eq!(snapshot.senders.len(), 1);
+        let text = snapshot.to_string();
+        assert!(text.contains("Tracker0"));
+        assert!(text.contains("vrpn_Tracker Pos_Quat"));
+    }
 }
@@ -3,31 +3,56 @@
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
 use crate::{
-    buffer_unbuffer::constants::GENERIC,
+    buffer_unbuffer::{constants::GENERIC, BytesMutExtras},
+    compression::COMPRESSION_OFFER,
     data_types::{
         constants,
         id_types::*,
-        message::{GenericMessage, TypedMessageBody},
-        name_types::{IdWithNameAndDescription, MessageTypeName, SenderName},
-        Description, MessageTypeIdentifier,
+        message::{GenericBody, GenericMessage, Message, MessageHeader, TypedMessageBody},
+        name_types::{
+            IdWithNameAndDescription, MessageTypeName, SenderName, StaticMessageTypeName,
+        },
+        Description, MessageTypeIdentifier, TimeVal,
     },
+    endpoint::{is_known_system_message, DescriptionTracker, UDP_ONLY_REQUEST},
     handler::*,
+    handler_collection::{
+        CallbackCollection, HandlerHandleInner, HandlerHandleInnerType, HandlerPolicy,
+        SharedHandlerEntry,
+    },
+    latency::LatencyTracker,
+    lifecycle::{LifecycleEvent, LifecycleEventBus, LifecycleEvents},
+    limits::Limit,
+    message_cache::MessageCache,
     name_registration::{
-        ExtraDataById, InsertOrGet, IntoCorrespondingName, IterableNameRegistration,
-        LocalNameRegistration, NameRegistrationContainer, PerIdData,
+        ExtraDataById, InsertOrGet, IterableNameRegistration, LocalNameRegistration,
+        NameRegistrationContainer, PerIdData,
     },
+    registrations::Registrations,
+    sequence::{SequenceGap, SEQUENCE_GAP},
+    sync::{Mutex, MutexGuard, RwLock},
+    throttle::{MessageThrottle, Throttle, ThrottleStats},
     Result, VrpnError,
 };
-use bytes::Bytes;
-use futures::future::LocalBoxFuture;
+use bytes::{Bytes, BytesMut};
+use futures::{
+    channel::mpsc,
+    future::BoxFuture,
+    stream::{FuturesUnordered, StreamExt},
+    FutureExt,
+};
 
 use std::{
-    collections::HashMap,
-    convert::{TryFrom, TryInto},
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
     fmt,
-    hash::Hash,
+    sync::{Arc, Weak},
+    task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
+pub use crate::limits::{DispatcherLimits, LimitKind};
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub enum RegisterMapping<I: UnwrappedId> {
     /// This was an existing mapping with the given ID
@@ -55,175 +80,167 @@ impl<I: UnwrappedId> From<InsertOrGet<LocalId<I>>> for RegisterMapping<I> {
     }
 }
 
-type HandlerHandleInnerType = IdType;
-
+/// A way to refer uniquely to a single added handler in a TypeDispatcher, in case
+/// you want to remove it in the future.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
-struct HandlerHandleInner(HandlerHandleInnerType);
+pub struct HandlerHandle(HandleKind);
 
-impl HandlerHandleInner {
-    fn into_handler_handle(
-        self,
-        message_type_filter: Option<LocalId<MessageTypeId>>,
-    ) -> HandlerHandle {
-        HandlerHandle(message_type_filter, self.0)
-    }
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum HandleKind {
+    /// In the callbacks of one message type, or the generic callbacks for `None`.
+    Single(Option<LocalId<MessageTypeId>>, HandlerHandleInnerType),
+    /// In the callbacks of several message types, listed in the dispatcher's `shared_handlers`.
+    Shared(HandlerHandleInnerType),
 }
 
-/// A way to refer uniquely to a single added handler in a TypeDispatcher, in case
-/// you want to remove it in the future.
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub struct HandlerHandle(Option<LocalId<MessageTypeId>>, HandlerHandleInnerType);
-
-/// Type storing a boxed callback function, an optional sender ID filter,
-/// and the unique-per-CallbackCollection handle that can be used to unregister a handler.
-struct MsgCallbackEntry {
-    handle: HandlerHandleInner,
-    pub handler: Box<dyn Handler + Send>,
-    pub sender_filter: Option<LocalId<SenderId>>,
+/// A sender filter matching every sender, like `vrpn_ANY_SENDER` in the C++ code.
+pub const ANY_SENDER: Option<LocalId<SenderId>> = None;
+
+/// A message type filter matching every type, like `vrpn_ANY_TYPE` in the C++ code.
+///
+/// As there, such handlers are called before those for the message's type,
+/// but not for system messages: see `TypeDispatcher::set_system_handler`.
+pub const ANY_TYPE: Option<LocalId<MessageTypeId>> = None;
+
+/// A handler that is removed from its dispatcher when this is dropped.
+///
+/// Dropping it after the dispatcher is gone does nothing.
+/// Don't drop it from within a handler of the same connection: that would deadlock.
+#[derive(Debug)]
+#[must_use = "the handler is removed when this is dropped"]
+pub struct ScopedHandler {
+    dispatcher: Weak<RwLock<TypeDispatcher>>,
+    handle: Option<HandlerHandle>,
 }
 
-impl fmt::Debug for MsgCallbackEntry {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("MsgCallbackEntry")
-            .field("handle", &self.handle)
-            .field("sender_filter", &self.sender_filter)
-            .finish()
+impl ScopedHandler {
+    pub fn new(dispatcher: &Arc<RwLock<TypeDispatcher>>, handle: HandlerHandle) -> ScopedHandler {
+        ScopedHandler {
+            dispatcher: Arc::downgrade(dispatcher),
+            handle: Some(handle),
+        }
+    }
+
+    pub fn handle(&self) -> Option<HandlerHandle> {
+        self.handle
+    }
+
+    /// Keep the handler registered, returning its handle for removing it manually.
+    pub fn into_handle(mut self) -> Option<HandlerHandle> {
+        self.handle.take()
     }
 }
 
-impl MsgCallbackEntry {
-    pub fn new(
-        handle: HandlerHandleInner,
-        handler: Box<dyn Handler + Send>,
-        sender_filter: Option<LocalId<SenderId>>,
-    ) -> MsgCallbackEntry {
-        MsgCallbackEntry {
-            handle,
-            handler,
-            sender_filter,
+impl Drop for ScopedHandler {
+    fn drop(&mut self) {
+        let (handle, dispatcher) = match (self.handle.take(), self.dispatcher.upgrade()) {
+            (Some(handle), Some(dispatcher)) => (handle, dispatcher),
+            _ => return,
+        };
+        // Not found if it already asked to be removed.
+        let result = dispatcher.write().remove_handler(handle);
+        match result {
+            Ok(()) | Err(VrpnError::HandlerNotFound) => {}
+            Err(e) => warn!("Could not remove scoped handler: {}", e),
         }
     }
+}
 
-    /// Invokes the callback with the given msg, if the sender filter (if not None) matches.
-    pub fn call(&mut self, msg: &GenericMessage) -> Result<HandlerCode> {
-        if id_filter_matches(self.sender_filter, LocalId(msg.header.sender)) {
-            self.handler.handle(msg)
-        } else {
-            Ok(HandlerCode::ContinueProcessing)
-        }
+/// A running async handler future, with the header of the message it handles.
+type AsyncHandlerFuture = BoxFuture<'static, (MessageHeader, Result<()>)>;
+
+/// Adapts an async handler to a handler, passing its futures to the dispatcher to run.
+struct AsyncHandlerEntry {
+    handler: Box<dyn AsyncHandler + Send>,
+    tasks: mpsc::UnboundedSender<AsyncHandlerFuture>,
+}
+
+impl Handler for AsyncHandlerEntry {
+    fn handle(&mut self, msg: &GenericMessage) -> Result<HandlerCode> {
+        let header = msg.header.clone();
+        let task = self.handler.handle(msg).map(|result| (header, result));
+        self.tasks
+            .unbounded_send(task.boxed())
+            .map_err(|_| VrpnError::ChannelClosed)?;
+        Ok(HandlerCode::ContinueProcessing)
     }
 }
 
-/// Stores a collection of callbacks with a name, associated with either a message type,
-/// or as a "global" handler mapping called for all message types.
-#[derive(Debug)]
-struct CallbackCollection {
-    name: Bytes,
-    callbacks: Vec<Option<MsgCallbackEntry>>,
-    next_handle: HandlerHandleInnerType,
+/// Called with each sender name a peer describes, and its local ID.
+pub type SenderObserver = Box<dyn FnMut(&SenderName, LocalId<SenderId>) + Send>;
+
+/// Called with each message type name a peer describes, and its local ID.
+pub type TypeObserver = Box<dyn FnMut(&MessageTypeName, LocalId<MessageTypeId>) + Send>;
+
+#[derive(Default)]
+struct DescriptionObservers {
+    senders: Vec<SenderObserver>,
+    types: Vec<TypeObserver>,
 }
-impl Default for CallbackCollection {
-    fn default() -> Self {
-        Self::new()
+
+impl fmt::Debug for DescriptionObservers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DescriptionObservers")
+            .field("senders", &self.senders.len())
+            .field("types", &self.types.len())
+            .finish()
     }
 }
 
-impl CallbackCollection {
-    /// Create CallbackCollection instance
-    pub fn new() -> CallbackCollection {
-        CallbackCollection {
-            name: Bytes::new(),
-            callbacks: Vec::new(),
-            next_handle: 0,
-        }
+/// Handler for a system message type not handled internally.
+struct SystemHandlerEntry(Box<dyn Handler + Send>);
+
+impl fmt::Debug for SystemHandlerEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SystemHandlerEntry").finish()
     }
+}
 
-    /// Add a callback with optional sender ID filter
-    fn add(
-        &mut self,
-        handler: Box<dyn Handler + Send>,
-        sender: Option<LocalId<SenderId>>,
-    ) -> Result<HandlerHandleInner> {
-        if self.callbacks.len() > MAX_VEC_USIZE {
-            return Err(VrpnError::TooManyHandlers);
-        }
-        let handle = HandlerHandleInner(self.next_handle);
-        self.callbacks
-            .push(Some(MsgCallbackEntry::new(handle, handler, sender)));
-        self.next_handle += 1;
-        Ok(handle)
-    }
-
-    /// Remove a callback
-    fn remove(&mut self, handle: HandlerHandleInner) -> Result<()> {
-        let index = self
-            .callbacks
+/// The sender and message type names, with the callbacks for each type.
+#[derive(Debug)]
+struct Names {
+    /// Index is the local type ID
+    message_types: PerIdData<NameRegistrationContainer<MessageTypeId>, Mutex<CallbackCollection>>,
+    /// Index is the local sender ID
+    senders: NameRegistrationContainer<SenderId>,
+    /// Registered only because a peer described them.
+    remote_senders: HashSet<LocalId<SenderId>>,
+    remote_types: HashSet<LocalId<MessageTypeId>>,
+}
+
+impl Names {
+    /// caution: expensive
+    fn senders_iter(&'_ self) -> impl Iterator<Item = (LocalId<SenderId>, SenderName)> + '_ {
+        self.senders
             .iter()
-            .position(|x| {
-                x.as_ref()
-                    .map(|handler| handler.handle == handle)
-                    .unwrap_or(false)
-            })
-            .ok_or(VrpnError::HandlerNotFound)?;
-        self.callbacks.remove(index);
-        Ok(())
+            .map(|(id, name)| (id, SenderName(name.as_ref().clone())))
     }
 
-    /// Call all callbacks (subject to sender filters) and remove the callbacks who ask for it.
-    fn call(&mut self, msg: &GenericMessage) -> Result<()> {
-        for entry in &mut self.callbacks.iter_mut() {
-            if let Some(unwrapped_entry) = entry {
-                if unwrapped_entry.call(msg)? == HandlerCode::RemoveThisHandler {
-                    entry.take();
-                }
-            }
-        }
-        Ok(())
+    /// caution: expensive
+    fn types_iter(
+        &'_ self,
+    ) -> impl Iterator<Item = (LocalId<MessageTypeId>, MessageTypeName)> + '_ {
+        self.message_types
+            .as_ref()
+            .iter()
+            .map(|(id, name)| (id, MessageTypeName(name.as_ref().clone())))
     }
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
-pub(crate) struct MessageTypeIndex(usize);
-
-pub(crate) trait TryIntoIndex {
-    type Index;
-    fn try_into_index(self, len: usize) -> Result<Self::Index>;
+/// The futures returned by async handlers, and the channel bringing new ones.
+struct AsyncTasks {
+    receiver: mpsc::UnboundedReceiver<AsyncHandlerFuture>,
+    running: FuturesUnordered<AsyncHandlerFuture>,
 }
 
-impl TryIntoIndex for MessageTypeId {
-    type Index = MessageTypeIndex;
-    fn try_into_index(self, len: usize) -> Result<Self::Index> {
-        use CategorizedId::*;
-        match categorize_id(self, len) {
-            BelowZero(v) => Err(VrpnError::InvalidId(v)),
-            AboveArray(v) => Err(VrpnError::InvalidId(v)),
-            InArray(index) => Ok(MessageTypeIndex(index as usize)),
-        }
-    }
-}
-#[deprecated = "Use TryIntoIndex instead"]
-fn message_type_into_index(message_type: MessageTypeId, len: usize) -> Result<usize> {
-    use CategorizedId::*;
-    match categorize_id(message_type, len) {
-        BelowZero(v) => Err(VrpnError::InvalidId(v)),
-        AboveArray(v) => Err(VrpnError::InvalidId(v)),
-        InArray(v) => Ok(v as usize),
+impl fmt::Debug for AsyncTasks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AsyncTasks")
+            .field("running", &self.running.len())
+            .finish()
     }
 }
 
-// impl TryIntoIndex for SenderId {
-//     fn try_into_index(self, len: usize) -> Result<Index> {
-//         use RangedId::*;
-//         match determine_id_range(self, len) {
-//             BelowZero(v) => Err(VrpnError::InvalidId(v)),
-//             AboveArray(v) => Err(VrpnError::InvalidId(v)),
-//             InArray(index) => Ok(Index(index as usize)),
-//         }
-//     }
-// }
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
-struct Name(Bytes);
-
 pub trait TryIntoDescriptionMessage {
     fn try_into_description_message<N: Into<Bytes>>(self, name: N) -> Result<GenericMessage>;
 }
@@ -252,6 +269,9 @@ pub(crate) fn try_register_system_senders_and_messages(
     message_type_registration.try_insert_or_get(constants::GOT_CONNECTION)?;
     message_type_registration.try_insert_or_get(constants::DROPPED_CONNECTION)?;
     message_type_registration.try_insert_or_get(constants::DROPPED_LAST_CONNECTION)?;
+    message_type_registration.try_insert_or_get(SEQUENCE_GAP)?;
+    message_type_registration.try_insert_or_get(UDP_ONLY_REQUEST)?;
+    message_type_registration.try_insert_or_get(COMPRESSION_OFFER)?;
     Ok(())
 }
 
@@ -262,13 +282,48 @@ pub(crate) fn try_register_system_senders_and_messages(
 /// they're operating on, which can be a struggle to get past the borrow checker.
 /// Thus, a hard-coded setup simply turns system messages into SystemCommand enum values,
 /// which get queued through the Endpoint trait using interior mutability (e.g. with something like mpsc)
-#[derive(Debug)]
+///
+/// Adding handlers and changing settings takes `&mut self`, but dispatching, and registering
+/// what peers describe, only takes `&self`: the state they change has a lock of its own,
+/// with one per message type's callbacks, so endpoints can dispatch at the same time.
 pub struct TypeDispatcher {
-    /// Index is the local type ID
-    message_types: PerIdData<NameRegistrationContainer<MessageTypeId>, CallbackCollection>,
-    generic_callbacks: CallbackCollection,
-    /// Index is the local sender ID
-    senders: NameRegistrationContainer<SenderId>,
+    names: RwLock<Names>,
+    generic_callbacks: Mutex<CallbackCollection>,
+    throttle: Mutex<MessageThrottle>,
+    system_handlers: Mutex<HashMap<MessageTypeId, SystemHandlerEntry>>,
+    events: Mutex<LifecycleEventBus>,
+    description_observers: Mutex<DescriptionObservers>,
+    handler_policy: HandlerPolicy,
+    async_sender: mpsc::UnboundedSender<AsyncHandlerFuture>,
+    async_tasks: Mutex<AsyncTasks>,
+    message_cache: Option<Mutex<MessageCache>>,
+    latency: Option<Mutex<LatencyTracker>>,
+    /// The handles making up each handler added for several message types.
+    shared_handlers: HashMap<HandlerHandleInnerType, Vec<HandlerHandle>>,
+    next_shared_handle: HandlerHandleInnerType,
+    limits: DispatcherLimits,
+    registrations: Registrations,
+}
+
+impl fmt::Debug for TypeDispatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TypeDispatcher")
+            .field("names", &self.names)
+            .field("generic_callbacks", &self.generic_callbacks)
+            .field("throttle", &self.throttle)
+            .field("system_handlers", &self.system_handlers)
+            .field("events", &self.events)
+            .field("description_observers", &self.description_observers)
+            .field("handler_policy", &self.handler_policy)
+            .field("async_tasks", &self.async_tasks)
+            .field("message_cache", &self.message_cache)
+            .field("latency", &self.latency)
+            .field("shared_handlers", &self.shared_handlers)
+            .field("next_shared_handle", &self.next_shared_handle)
+            .field("limits", &self.limits)
+            .field("registrations", &self.registrations)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Default for TypeDispatcher {
@@ -279,14 +334,67 @@ impl Default for TypeDispatcher {
 
 impl TypeDispatcher {
     pub fn new() -> TypeDispatcher {
-        let mut disp = TypeDispatcher {
-            message_types: PerIdData::new(NameRegistrationContainer::default()),
-            generic_callbacks: CallbackCollection::new(/* Bytes::from_static(GENERIC) */),
-            senders: NameRegistrationContainer::default(),
+        TypeDispatcher::with_limits(DispatcherLimits::default())
+    }
+
+    /// Create a dispatcher registering no more than `limits` allow.
+    pub fn with_limits(limits: DispatcherLimits) -> TypeDispatcher {
+        let (async_sender, async_receiver) = mpsc::unbounded();
+        let mut names = Names {
+            message_types: PerIdData::new(NameRegistrationContainer::new(
+                LimitKind::MessageTypes,
+                limits.max_message_types,
+            )),
+            senders: NameRegistrationContainer::new(LimitKind::Senders, limits.max_senders),
+            remote_senders: HashSet::new(),
+            remote_types: HashSet::new(),
         };
+        // Only fails once the limits are reached, when user registrations fail as well.
+        let _ =
+            try_register_system_senders_and_messages(&mut names.senders, &mut names.message_types);
+        let registrations = Registrations::default();
+        registrations.replace(
+            names.senders_iter().map(|(id, name)| (name.0, id)),
+            names.types_iter().map(|(id, name)| (name.0, id)),
+        );
+        TypeDispatcher {
+            names: RwLock::new(names),
+            generic_callbacks: Mutex::new(
+                CallbackCollection::new(/* Bytes::from_static(GENERIC) */),
+            ),
+            throttle: Mutex::new(MessageThrottle::new()),
+            system_handlers: Mutex::new(HashMap::new()),
+            events: Mutex::new(LifecycleEventBus::new()),
+            description_observers: Mutex::new(DescriptionObservers::default()),
+            handler_policy: HandlerPolicy::default(),
+            async_sender,
+            async_tasks: Mutex::new(AsyncTasks {
+                receiver: async_receiver,
+                running: FuturesUnordered::new(),
+            }),
+            message_cache: None,
+            latency: None,
+            shared_handlers: HashMap::new(),
+            next_shared_handle: 0,
+            limits,
+            registrations,
+        }
+    }
 
-        try_register_system_senders_and_messages(&mut disp.senders, &mut disp.message_types);
-        disp
+    /// The limits on what this dispatcher registers.
+    pub fn limits(&self) -> DispatcherLimits {
+        self.limits
+    }
+
+    /// Change the limits on what this dispatcher registers from now on.
+    ///
+    /// Anything already registered beyond the new limits is kept.
+    pub fn set_limits(&mut self, limits: DispatcherLimits) {
+        let names = self.names.get_mut();
+        names.senders.set_limit(limits.max_senders);
+        AsMut::<NameRegistrationContainer<MessageTypeId>>::as_mut(&mut names.message_types)
+            .set_limit(limits.max_message_types);
+        self.limits = limits;
     }
 
     /// Get a mutable borrow of the CallbackCollection associated with the supplied MessageTypeId
@@ -295,9 +403,11 @@ impl TypeDispatcher {
         &'_ mut self,
         type_id_filter: Option<LocalId<MessageTypeId>>,
     ) -> Result<&'_ mut CallbackCollection> {
+        let message_types = &mut self.names.get_mut().message_types;
         match type_id_filter {
-            Some(id) => self.message_types.try_get_data_mut(id.into_id()),
-            None => Ok(&mut self.generic_callbacks),
+            Some(id) if message_types.is_removed(id) => Err(VrpnError::RemovedId(id.get())),
+            Some(id) => Ok(message_types.try_get_data_mut(id.into_id())?.get_mut()),
+            None => Ok(self.generic_callbacks.get_mut()),
         }
     }
 
@@ -307,7 +417,39 @@ impl TypeDispatcher {
         T: Into<MessageTypeName>,
     {
         let name: MessageTypeName = name.into();
-        self.message_types.try_get_id_by_name(name)
+        self.names.read().message_types.try_get_id_by_name(name)
+    }
+
+    /// Returns the name registered for a message type ID, if any.
+    pub fn type_name(&self, id: LocalId<MessageTypeId>) -> Option<Bytes> {
+        let names = self.names.read();
+        let message_types: &NameRegistrationContainer<MessageTypeId> = names.message_types.as_ref();
+        message_types
+            .try_get_name(id)
+            .map(|name| name.as_ref().clone())
+    }
+
+    /// Returns the name registered for a sender ID, if any.
+    pub fn sender_name(&self, id: LocalId<SenderId>) -> Option<Bytes> {
+        self.names
+            .read()
+            .senders
+            .try_get_name(id)
+            .map(|name| name.as_ref().clone())
+    }
+
+    /// A handle for looking up IDs by name without locking this dispatcher,
+    /// kept up to date as names are registered and cleared.
+    pub fn registrations(&self) -> Registrations {
+        self.registrations.clone()
+    }
+
+    /// Returns the ID for a typed message body's type, if registered.
+    pub fn get_typed_message_type_id<T: TypedMessageBody>(&self) -> Option<LocalId<MessageTypeId>> {
+        match T::MESSAGE_IDENTIFIER {
+            MessageTypeIdentifier::UserMessageName(name) => self.get_type_id(name),
+            MessageTypeIdentifier::SystemMessageId(id) => Some(LocalId(id)),
+        }
     }
 
     /// Calls add_type if get_type_id() returns None.
@@ -316,7 +458,32 @@ impl TypeDispatcher {
         &mut self,
         name: impl Into<MessageTypeName>,
     ) -> Result<RegisterMapping<MessageTypeId>> {
-        Ok(self.message_types.try_insert_or_get(name)?.into())
+        let name: MessageTypeName = name.into();
+        let names = self.names.get_mut();
+        let mapping: RegisterMapping<_> =
+            names.message_types.try_insert_or_get(name.clone())?.into();
+        names.remote_types.remove(&mapping.into_inner());
+        if let RegisterMapping::NewMapping(id) = mapping {
+            self.registrations.insert_message_type(name.0, id);
+        }
+        Ok(mapping)
+    }
+
+    /// Register a type described by a peer, to be removed by `clear_remote_registrations`
+    /// unless it is also registered locally.
+    pub fn register_remote_type(
+        &self,
+        name: impl Into<MessageTypeName>,
+    ) -> Result<RegisterMapping<MessageTypeId>> {
+        let name: MessageTypeName = name.into();
+        let mut names = self.names.write();
+        let mapping: RegisterMapping<_> =
+            names.message_types.try_insert_or_get(name.clone())?.into();
+        if let RegisterMapping::NewMapping(id) = mapping {
+            names.remote_types.insert(id);
+            self.registrations.insert_message_type(name.0, id);
+        }
+        Ok(mapping)
     }
 
     /// Calls add_sender if get_sender_id() returns None.
@@ -324,20 +491,80 @@ impl TypeDispatcher {
         &mut self,
         name: impl Into<SenderName>,
     ) -> Result<RegisterMapping<SenderId>> {
-        Ok(self.senders.try_insert_or_get(name)?.into())
+        let name: SenderName = name.into();
+        let names = self.names.get_mut();
+        let mapping: RegisterMapping<_> = names.senders.try_insert_or_get(name.clone())?.into();
+        names.remote_senders.remove(&mapping.into_inner());
+        if let RegisterMapping::NewMapping(id) = mapping {
+            self.registrations.insert_sender(name.0, id);
+        }
+        Ok(mapping)
+    }
+
+    /// Register a sender described by a peer, to be removed by `clear_remote_registrations`
+    /// unless it is also registered locally.
+    pub fn register_remote_sender(
+        &self,
+        name: impl Into<SenderName>,
+    ) -> Result<RegisterMapping<SenderId>> {
+        let name: SenderName = name.into();
+        let mut names = self.names.write();
+        let mapping: RegisterMapping<_> = names.senders.try_insert_or_get(name.clone())?.into();
+        if let RegisterMapping::NewMapping(id) = mapping {
+            names.remote_senders.insert(id);
+            self.registrations.insert_sender(name.0, id);
+        }
+        Ok(mapping)
+    }
+
+    /// Remove the senders and types registered only because a peer described them,
+    /// ready to synchronize with a new peer.
+    ///
+    /// Their IDs are not reused: when a name is registered again, it gets its old ID back,
+    /// with any handlers for it. Until then, adding handlers for it fails with
+    /// `VrpnError::RemovedId`, and it is not described to peers.
+    ///
+    /// Called when the last endpoint is dropped.
+    pub fn clear_remote_registrations(&self) -> Result<()> {
+        let mut names = self.names.write();
+        let names = &mut *names;
+        for id in names.remote_senders.drain() {
+            names.senders.remove(id)?;
+        }
+        for id in names.remote_types.drain() {
+            names.message_types.remove(id)?;
+        }
+        self.registrations.replace(
+            names.senders_iter().map(|(id, name)| (name.0, id)),
+            names.types_iter().map(|(id, name)| (name.0, id)),
+        );
+        Ok(())
     }
 
     /// Returns the ID for the sender name, if found.
     pub fn get_sender_id(&self, name: impl Into<SenderName>) -> Option<LocalId<SenderId>> {
-        self.senders.try_get_id_by_name(name)
+        self.names.read().senders.try_get_id_by_name(name)
     }
 
+    /// Add a handler, with filters on message type and sender, or `ANY_TYPE` and `ANY_SENDER`.
+    ///
+    /// As in the C++ code, the handlers for any type are called first, then those for
+    /// the message's type, each in the order added. The sender must be registered.
     pub fn add_handler(
         &mut self,
         handler: Box<dyn Handler + Send>,
         message_type_filter: Option<LocalId<MessageTypeId>>,
         sender_filter: Option<LocalId<SenderId>>,
     ) -> Result<HandlerHandle> {
+        let senders = &self.names.get_mut().senders;
+        if let Some(sender) = sender_filter {
+            if senders.is_removed(sender) {
+                return Err(VrpnError::RemovedId(sender.get()));
+            }
+            if !matches!(senders.categorize_id(sender.0), CategorizedId::InArray(_)) {
+                return Err(VrpnError::InvalidId(sender.get()));
+            }
+        }
         // let mut collection = match message_type_filter {
         //     Some(message_type) => self
         //         .message_types
@@ -345,18 +572,24 @@ impl TypeDispatcher {
         //     None => &mut self.generic_callbacks,
         // };
         // collection
+        let handlers = self.get_type_callbacks_mut(message_type_filter)?.len();
+        Limit::new(LimitKind::Handlers, self.limits.max_handlers).check(handlers, || {
+            message_type_filter
+                .and_then(|id| self.type_name(id))
+                .unwrap_or_else(|| Bytes::from_static(GENERIC))
+        })?;
         self.get_type_callbacks_mut(message_type_filter)?
             .add(handler, sender_filter)
-            .map(|h| h.into_handler_handle(message_type_filter))
+            .map(|h| HandlerHandle(HandleKind::Single(message_type_filter, h.0)))
     }
 
-    pub fn add_typed_handler<T: 'static>(
+    pub fn add_typed_handler<T>(
         &mut self,
         handler: Box<T>,
         sender_filter: Option<LocalId<SenderId>>,
     ) -> Result<HandlerHandle>
     where
-        T: TypedHandler + Handler + Sized,
+        T: TypedHandler + Handler + Sized + 'static,
     {
         let message_type = match T::Item::MESSAGE_IDENTIFIER {
             MessageTypeIdentifier::UserMessageName(name) => self.register_type(name)?.into_inner(),
@@ -365,55 +598,417 @@ impl TypeDispatcher {
         self.add_handler(handler, Some(message_type), sender_filter)
     }
 
+    /// Add one handler for several message types, with an optional filter on sender.
+    ///
+    /// The returned handle removes it from all of them at once,
+    /// as does the handler returning `HandlerCode::RemoveThisHandler`.
+    pub fn add_handler_for_types(
+        &mut self,
+        handler: Box<dyn Handler + Send>,
+        message_types: &[LocalId<MessageTypeId>],
+        sender_filter: Option<LocalId<SenderId>>,
+    ) -> Result<HandlerHandle> {
+        let shared = SharedHandlerEntry(Arc::new(Mutex::new(Some(handler))));
+        let mut handles = Vec::with_capacity(message_types.len());
+        for message_type in message_types {
+            match self.add_handler(Box::new(shared.clone()), Some(*message_type), sender_filter) {
+                Ok(handle) => handles.push(handle),
+                Err(e) => {
+                    // Roll back every type added so far, still reporting the original error.
+                    for handle in handles {
+                        if let Err(remove_error) = self.remove_handler(handle) {
+                            warn!(
+                                "Could not remove handler while rolling back: {}",
+                                remove_error
+                            );
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let id = self.next_shared_handle;
+        self.next_shared_handle += 1;
+        self.shared_handlers.insert(id, handles);
+        Ok(HandlerHandle(HandleKind::Shared(id)))
+    }
+
+    /// Add an async handler, with optional filters on message type and sender.
+    ///
+    /// Its futures are run by `poll_async_handlers`.
+    pub fn add_async_handler(
+        &mut self,
+        handler: Box<dyn AsyncHandler + Send>,
+        message_type_filter: Option<LocalId<MessageTypeId>>,
+        sender_filter: Option<LocalId<SenderId>>,
+    ) -> Result<HandlerHandle> {
+        let entry = AsyncHandlerEntry {
+            handler,
+            tasks: self.async_sender.clone(),
+        };
+        self.add_handler(Box::new(entry), message_type_filter, sender_filter)
+    }
+
+    /// Make progress on the futures returned by async handlers.
+    ///
+    /// Called when endpoints are polled. Under `HandlerErrorPolicy::Abort`,
+    /// returns the first error from a future.
+    pub fn poll_async_handlers(&self, cx: &mut Context<'_>) -> Result<()> {
+        let mut tasks = self.async_tasks.lock();
+        let tasks = &mut *tasks;
+        while let Poll::Ready(Some(task)) = tasks.receiver.poll_next_unpin(cx) {
+            tasks.running.push(task);
+        }
+        while let Poll::Ready(Some((header, result))) = tasks.running.poll_next_unpin(cx) {
+            if let Err(e) = result {
+                self.handler_policy
+                    .handler_failed("Async handler", &header, e)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Enable or disable caching the latest message of each type from each sender.
+    ///
+    /// Disabling discards the cache.
+    pub fn set_message_cache(&mut self, enabled: bool) {
+        match (enabled, self.message_cache.is_some()) {
+            (true, false) => self.message_cache = Some(Mutex::new(MessageCache::new())),
+            (false, true) => self.message_cache = None,
+            _ => {}
+        }
+    }
+
+    /// The message cache, if enabled.
+    pub fn message_cache(&self) -> Option<MutexGuard<'_, MessageCache>> {
+        self.message_cache.as_ref().map(Mutex::lock)
+    }
+
+    /// Enable or disable measuring the latency of each message type,
+    /// keeping up to `window` recent samples of each.
+    ///
+    /// Disabling discards the samples.
+    pub fn set_latency_tracking(&mut self, window: Option<usize>) {
+        self.latency = window.map(|window| Mutex::new(LatencyTracker::new(window)));
+    }
+
+    /// The latency tracker, if enabled.
+    pub fn latency_tracker(&self) -> Option<MutexGuard<'_, LatencyTracker>> {
+        self.latency.as_ref().map(Mutex::lock)
+    }
+
+    /// The number of async handler futures not yet finished.
+    pub fn async_handlers_running(&self) -> usize {
+        self.async_tasks.lock().running.len()
+    }
+
     pub fn remove_handler(&mut self, handler_handle: HandlerHandle) -> Result<()> {
-        let HandlerHandle(message_type, inner) = handler_handle;
-        self.get_type_callbacks_mut(message_type)?
-            .remove(HandlerHandleInner(inner))
+        match handler_handle.0 {
+            HandleKind::Single(message_type, inner) => self
+                .get_type_callbacks_mut(message_type)?
+                .remove(HandlerHandleInner(inner)),
+            HandleKind::Shared(id) => {
+                let handles = self
+                    .shared_handlers
+                    .remove(&id)
+                    .ok_or(VrpnError::HandlerNotFound)?;
+                for handle in handles {
+                    match self.remove_handler(handle) {
+                        // Already gone from types where it asked to be removed.
+                        Ok(()) | Err(VrpnError::HandlerNotFound) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Set (or with `None`, remove) the handler for a system message type
+    /// that is not handled internally, e.g. for a protocol extension.
+    ///
+    /// Messages of such types with no handler are logged and ignored.
+    /// Fails if the type is not a system message type, or is one handled internally.
+    pub fn set_system_handler(
+        &mut self,
+        message_type: MessageTypeId,
+        handler: Option<Box<dyn Handler + Send>>,
+    ) -> Result<()> {
+        if !message_type.is_system_message() {
+            return Err(VrpnError::NotSystemMessage);
+        }
+        if is_known_system_message(message_type) {
+            return Err(VrpnError::InvalidId(message_type.get()));
+        }
+        match handler {
+            Some(handler) => {
+                self.system_handlers
+                    .get_mut()
+                    .insert(message_type, SystemHandlerEntry(handler));
+            }
+            None => {
+                self.system_handlers.get_mut().remove(&message_type);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pass a system message not handled internally to its handler, if any.
+    pub fn call_system_handler(&self, msg: &GenericMessage) -> Result<()> {
+        let message_type = msg.header.message_type;
+        let mut system_handlers = self.system_handlers.lock();
+        let code = match system_handlers.get_mut(&message_type) {
+            Some(SystemHandlerEntry(handler)) => handler.handle(msg)?,
+            None => {
+                warn!(
+                    "Ignoring system message of unrecognized type {}",
+                    message_type.get()
+                );
+                return Ok(());
+            }
+        };
+        if code == HandlerCode::RemoveThisHandler {
+            system_handlers.remove(&message_type);
+        }
+        Ok(())
+    }
+
+    /// Set (or with `None`, remove) a limit on how often messages of a type are delivered.
+    pub fn set_throttle(
+        &mut self,
+        message_type: LocalId<MessageTypeId>,
+        throttle: Option<Throttle>,
+    ) {
+        self.throttle.get_mut().set(message_type, throttle)
+    }
+
+    /// The counters for a throttled message type.
+    pub fn throttle_stats(&self, message_type: LocalId<MessageTypeId>) -> Option<ThrottleStats> {
+        self.throttle.lock().stats(message_type)
+    }
+
+    /// Deliver the coalesced messages of throttled types whose interval has passed.
+    pub fn flush_throttled(&self) -> Result<()> {
+        let due = {
+            let mut throttle = self.throttle.lock();
+            if !throttle.has_pending() {
+                return Ok(());
+            }
+            throttle.take_due(Instant::now())
+        };
+        for msg in due {
+            self.deliver(&msg)?;
+        }
+        Ok(())
     }
 
     /// Akin to vrpn_TypeDispatcher::doCallbacksFor
-    pub fn call(&mut self, msg: &GenericMessage) -> Result<()> {
-        self.generic_callbacks.call(msg)?;
-        if let Ok(mapping) = self.message_types.try_get_data_mut(msg.header.message_type) {
-            mapping.call(msg)?;
+    ///
+    /// Messages of throttled types may be held back or dropped.
+    pub fn call(&self, msg: &GenericMessage) -> Result<()> {
+        if let Some(cache) = &self.message_cache {
+            cache.lock().record(msg, Instant::now());
+        }
+        if let Some(latency) = &self.latency {
+            latency.lock().record(msg, TimeVal::get_time_of_day());
+        }
+        if !self.throttle.lock().admit(msg, Instant::now()) {
+            return Ok(());
+        }
+        self.deliver(msg)
+    }
+
+    fn deliver(&self, msg: &GenericMessage) -> Result<()> {
+        if msg.is_system_message() {
+            // Not even for ANY_TYPE handlers, as in the C++ code.
+            return Ok(());
+        }
+        let policy = &self.handler_policy;
+        self.generic_callbacks.lock().call(msg, policy)?;
+        let names = self.names.read();
+        if let Ok(callbacks) = names.message_types.try_get_data(msg.header.message_type) {
+            callbacks.lock().call(msg, policy)?;
         }
         Ok(())
     }
 
-    /// caution: expensive
-    fn senders_iter(&'_ self) -> impl Iterator<Item = (LocalId<SenderId>, SenderName)> + '_ {
-        self.senders
-            .iter()
-            .map(|(id, name)| (id, SenderName(name.as_ref().clone())))
+    /// Warn whenever a handler takes longer than this to handle a message, or never with `None`.
+    ///
+    /// For async handlers, only the time to start their futures counts.
+    pub fn set_handler_budget(&mut self, budget: Option<Duration>) {
+        self.handler_policy.budget = budget;
     }
 
-    /// caution: expensive
-    fn types_iter(
-        &'_ self,
-    ) -> impl Iterator<Item = (LocalId<MessageTypeId>, MessageTypeName)> + '_ {
-        self.message_types
-            .as_ref()
-            .iter()
-            .map(|(id, name)| (id, MessageTypeName(name.as_ref().clone())))
+    /// How long a handler has taken to handle messages so far,
+    /// summed over its message types if added for several.
+    ///
+    /// `None` if it has been removed. Don't call this from within a handler: it would deadlock.
+    pub fn handler_timing(&self, handler_handle: HandlerHandle) -> Option<HandlerTiming> {
+        match handler_handle.0 {
+            HandleKind::Single(None, inner) => self
+                .generic_callbacks
+                .lock()
+                .timing(HandlerHandleInner(inner)),
+            HandleKind::Single(Some(message_type), inner) => self
+                .names
+                .read()
+                .message_types
+                .try_get_data(message_type.into_id())
+                .ok()?
+                .lock()
+                .timing(HandlerHandleInner(inner)),
+            HandleKind::Shared(id) => self
+                .shared_handlers
+                .get(&id)?
+                .iter()
+                .filter_map(|handle| self.handler_timing(*handle))
+                .reduce(HandlerTiming::merge),
+        }
+    }
+
+    /// Set what to do when a handler returns an error.
+    pub fn set_handler_error_policy(&mut self, policy: HandlerErrorPolicy) {
+        self.handler_policy.on_error = policy;
+    }
+
+    /// The handler errors recorded since last taken.
+    pub fn handler_errors(&self) -> MutexGuard<'_, HandlerErrorReport> {
+        self.handler_policy.errors()
+    }
+
+    /// Take the handler errors recorded, starting a new report.
+    pub fn take_handler_errors(&self) -> HandlerErrorReport {
+        self.handler_policy.take_errors()
+    }
+
+    /// Dispatch a locally-synthesized system event message,
+    /// using the (always-registered) control sender and the given event type name.
+    fn call_system_event(&self, name: StaticMessageTypeName, body: GenericBody) -> Result<()> {
+        let message_type = self
+            .get_type_id(name.clone())
+            .ok_or_else(|| VrpnError::OtherMessage(format!("system type {:?} not found", name)))?;
+        let sender = self
+            .get_sender_id(constants::CONTROL)
+            .ok_or_else(|| VrpnError::OtherMessage("control sender not found".to_string()))?;
+        let msg = GenericMessage::from_header_and_body(
+            MessageHeader::new(None, message_type, sender),
+            body,
+        );
+        self.call(&msg)
+    }
+
+    /// Dispatch the system events for a newly-connected endpoint:
+    /// `GOT_FIRST_CONNECTION` if it is the only one, then `GOT_CONNECTION`.
+    pub fn call_got_connection(&self, first: bool) -> Result<()> {
+        if first {
+            self.emit_event(LifecycleEvent::Connected);
+        }
+        self.emit_event(LifecycleEvent::EndpointAdded);
+        if first {
+            self.call_system_event(constants::GOT_FIRST_CONNECTION, GenericBody::default())?;
+        }
+        self.call_system_event(constants::GOT_CONNECTION, GenericBody::default())
+    }
+
+    /// Dispatch the system events for a dropped endpoint:
+    /// `DROPPED_CONNECTION`, then `DROPPED_LAST_CONNECTION` if none remain,
+    /// after which the remote registrations are cleared.
+    pub fn call_dropped_connection(&self, last: bool) -> Result<()> {
+        self.emit_event(LifecycleEvent::EndpointRemoved);
+        if last {
+            self.emit_event(LifecycleEvent::Disconnected);
+        }
+        self.call_system_event(constants::DROPPED_CONNECTION, GenericBody::default())?;
+        if last {
+            self.call_system_event(constants::DROPPED_LAST_CONNECTION, GenericBody::default())?;
+            self.clear_remote_registrations()?;
+        }
+        Ok(())
+    }
+
+    /// Get a stream of lifecycle events from now on.
+    pub fn subscribe_events(&self) -> LifecycleEvents {
+        self.events.lock().subscribe()
+    }
+
+    /// Send a lifecycle event to subscribers.
+    pub fn emit_event(&self, event: LifecycleEvent) {
+        self.events.lock().emit(event)
+    }
+
+    /// Add a function to call with each sender a peer describes.
+    pub fn add_sender_observer(&mut self, observer: SenderObserver) {
+        self.description_observers.get_mut().senders.push(observer);
+    }
+
+    /// Add a function to call with each message type a peer describes.
+    pub fn add_type_observer(&mut self, observer: TypeObserver) {
+        self.description_observers.get_mut().types.push(observer);
+    }
+
+    /// Tell observers and event subscribers that a peer described a sender.
+    pub fn sender_described(&self, name: SenderName, id: LocalId<SenderId>) {
+        for observer in &mut self.description_observers.lock().senders {
+            observer(&name, id);
+        }
+        self.emit_event(LifecycleEvent::SenderDescribed(name));
+    }
+
+    /// Tell observers and event subscribers that a peer described a message type.
+    pub fn type_described(&self, name: MessageTypeName, id: LocalId<MessageTypeId>) {
+        for observer in &mut self.description_observers.lock().types {
+            observer(&name, id);
+        }
+        self.emit_event(LifecycleEvent::TypeDescribed(name));
+    }
+
+    /// Dispatch the event for a gap in the sequence numbers received by an endpoint.
+    pub fn call_sequence_gap(&self, gap: SequenceGap) -> Result<()> {
+        let body = GenericBody::new(BytesMut::allocate_and_buffer(gap)?.freeze());
+        self.call_system_event(SEQUENCE_GAP, body)
+    }
+
+    /// Pack the sender and type descriptions not yet recorded in the tracker,
+    /// recording them as sent.
+    pub fn pack_new_descriptions(
+        &self,
+        tracker: &mut DescriptionTracker,
+    ) -> Result<Vec<GenericMessage>> {
+        let names = self.names.read();
+        let mut messages = names
+            .senders_iter()
+            .filter(|(id, _)| tracker.record(*id))
+            .map(|(id, name)| id.try_into_description_message(name))
+            .collect::<Result<Vec<GenericMessage>>>()?;
+        for msg in names
+            .types_iter()
+            .filter(|(id, _)| tracker.record(*id))
+            .map(|(id, name)| id.try_into_description_message(name))
+        {
+            messages.push(msg?);
+        }
+        Ok(messages)
     }
 
     /// Pack all sender and type descriptions into a vector of generic messages.
     pub fn pack_all_descriptions(&self) -> Result<impl Iterator<Item = GenericMessage>> {
-        let sender_messages = self
+        let names = self.names.read();
+        let sender_messages = names
             .senders_iter()
             .map(|(id, name)| id.try_into_description_message(name.clone()))
             .collect::<Result<Vec<GenericMessage>>>()?;
 
-        let type_messages = self
+        let type_messages = names
             .types_iter()
             .map(|(id, name)| id.try_into_description_message(name))
             .collect::<Result<Vec<GenericMessage>>>()?;
 
-        Ok(sender_messages.into_iter().chain(type_messages.into_iter()))
+        Ok(sender_messages.into_iter().chain(type_messages))
     }
 }
 #[cfg(test)]
 mod tests {
+    use crate::data_types::name_types::StaticSenderName;
     use crate::data_types::{
         message::{GenericBody, GenericMessage, Message},
         MessageHeader, TimeVal,
@@ -444,16 +1039,16 @@ mod tests {
         }
     }
     #[test]
-    fn callback_collection() {
+    fn type_dispatcher() {
         let val: Arc<Mutex<i8>> = Arc::new(Mutex::new(5));
         let a = Arc::clone(&val);
         let sample_callback = SetTo10 { val: a };
         let b = Arc::clone(&val);
         let sample_callback2 = SetTo15 { val: b };
 
-        let mut collection = CallbackCollection::new();
-        let handler = collection
-            .add(Box::new(sample_callback.clone()), None)
+        let mut dispatcher = TypeDispatcher::new();
+        let handler = dispatcher
+            .add_handler(Box::new(sample_callback.clone()), None, None)
             .unwrap();
         let msg = GenericMessage::from_header_and_body(
             MessageHeader::new(
@@ -463,89 +1058,650 @@ mod tests {
             ),
             GenericBody::default(),
         );
-        collection.call(&msg).unwrap();
+        dispatcher.call(&msg).unwrap();
         assert_eq!(*val.lock().unwrap(), 10);
 
-        collection
-            .remove(handler)
+        dispatcher
+            .remove_handler(handler)
             .expect("Can't remove added callback");
         // No callbacks should fire now.
         *val.lock().unwrap() = 5;
-        collection.call(&msg).unwrap();
+        dispatcher.call(&msg).unwrap();
         assert_eq!(*val.lock().unwrap(), 5);
 
-        let _ = collection
-            .add(Box::new(sample_callback2), Some(LocalId(SenderId(0))))
+        let _ = dispatcher
+            .add_handler(Box::new(sample_callback2), None, Some(LocalId(SenderId(0))))
             .unwrap();
         *val.lock().unwrap() = 5;
-        collection.call(&msg).unwrap();
+        dispatcher.call(&msg).unwrap();
         assert_eq!(*val.lock().unwrap(), 15);
 
         // Check that later-registered callbacks get run later
-        let _ = collection.add(Box::new(sample_callback), None).unwrap();
+        let _ = dispatcher
+            .add_handler(Box::new(sample_callback), None, None)
+            .unwrap();
         *val.lock().unwrap() = 5;
-        collection.call(&msg).unwrap();
+        dispatcher.call(&msg).unwrap();
         assert_eq!(*val.lock().unwrap(), 10);
 
         // This shouldn't trigger callback 2
         let mut msg2 = msg.clone();
         msg2.header.sender = SenderId(1);
         *val.lock().unwrap() = 5;
-        collection.call(&msg2).unwrap();
+        dispatcher.call(&msg2).unwrap();
         assert_eq!(*val.lock().unwrap(), 10);
     }
 
+    #[derive(Debug)]
+    struct CountEvents<T> {
+        count: Arc<Mutex<i8>>,
+        phantom: std::marker::PhantomData<fn() -> T>,
+    }
+    impl<T> TypedBodylessHandler for CountEvents<T>
+    where
+        T: TypedMessageBody
+            + crate::buffer_unbuffer::EmptyMessage
+            + crate::buffer_unbuffer::UnbufferFrom
+            + fmt::Debug,
+    {
+        type Item = T;
+        fn handle_typed_bodyless(&mut self, header: &MessageHeader) -> Result<HandlerCode> {
+            assert!(header.sender.0 >= 0);
+            *self.count.lock()? += 1;
+            Ok(HandlerCode::ContinueProcessing)
+        }
+    }
+    fn count_events<T>(dispatcher: &mut TypeDispatcher) -> Arc<Mutex<i8>>
+    where
+        T: TypedMessageBody
+            + crate::buffer_unbuffer::EmptyMessage
+            + crate::buffer_unbuffer::UnbufferFrom
+            + fmt::Debug
+            + 'static,
+    {
+        let count = Arc::new(Mutex::new(0));
+        dispatcher
+            .add_typed_handler(
+                Box::new(CountEvents::<T> {
+                    count: Arc::clone(&count),
+                    phantom: std::marker::PhantomData,
+                }),
+                None,
+            )
+            .unwrap();
+        count
+    }
+
     #[test]
-    fn type_dispatcher() {
-        let val: Arc<Mutex<i8>> = Arc::new(Mutex::new(5));
-        let a = Arc::clone(&val);
-        let sample_callback = SetTo10 { val: a };
-        let b = Arc::clone(&val);
-        let sample_callback2 = SetTo15 { val: b };
+    fn system_events() {
+        use crate::system_events::*;
+        let mut dispatcher = TypeDispatcher::new();
+        let got_first = count_events::<GotFirstConnection>(&mut dispatcher);
+        let got = count_events::<GotConnection>(&mut dispatcher);
+        let dropped = count_events::<DroppedConnection>(&mut dispatcher);
+        let dropped_last = count_events::<DroppedLastConnection>(&mut dispatcher);
+        let mut events = dispatcher.subscribe_events();
+
+        dispatcher.call_got_connection(true).unwrap();
+        dispatcher.call_got_connection(false).unwrap();
+        assert_eq!(*got_first.lock().unwrap(), 1);
+        assert_eq!(*got.lock().unwrap(), 2);
+
+        dispatcher.call_dropped_connection(false).unwrap();
+        dispatcher.call_dropped_connection(true).unwrap();
+        assert_eq!(*dropped.lock().unwrap(), 2);
+        assert_eq!(*dropped_last.lock().unwrap(), 1);
+
+        use LifecycleEvent::*;
+        let received: Vec<_> = std::iter::from_fn(|| events.try_next().ok().flatten()).collect();
+        assert_eq!(
+            received,
+            vec![
+                Connected,
+                EndpointAdded,
+                EndpointAdded,
+                EndpointRemoved,
+                EndpointRemoved,
+                Disconnected
+            ]
+        );
+    }
+
+    #[derive(Debug, Clone)]
+    struct Fail;
+    impl Handler for Fail {
+        fn handle(&mut self, _msg: &GenericMessage) -> Result<HandlerCode> {
+            Err(VrpnError::GenericErrorReturn)
+        }
+    }
 
+    #[test]
+    fn handler_errors() {
         let mut dispatcher = TypeDispatcher::new();
-        let handler = dispatcher
-            .add_handler(Box::new(sample_callback.clone()), None, None)
+        let val: Arc<Mutex<i8>> = Arc::new(Mutex::new(5));
+        dispatcher.add_handler(Box::new(Fail), None, None).unwrap();
+        dispatcher
+            .add_handler(
+                Box::new(SetTo10 {
+                    val: Arc::clone(&val),
+                }),
+                None,
+                None,
+            )
             .unwrap();
         let msg = GenericMessage::from_header_and_body(
-            MessageHeader::new(
-                Some(TimeVal::get_time_of_day()),
-                MessageTypeId(0),
-                SenderId(0),
-            ),
+            MessageHeader::new(None, MessageTypeId(0), SenderId(0)),
             GenericBody::default(),
         );
+
+        // By default, the other handlers are still called.
         dispatcher.call(&msg).unwrap();
         assert_eq!(*val.lock().unwrap(), 10);
+        let report = dispatcher.take_handler_errors();
+        assert_eq!(report.count, 1);
+        assert_eq!(report.recent[0].message_type, MessageTypeId(0));
+        assert!(dispatcher.handler_errors().is_empty());
 
-        dispatcher
-            .remove_handler(handler)
-            .expect("Can't remove added callback");
-        // No callbacks should fire now.
+        dispatcher.set_handler_error_policy(HandlerErrorPolicy::Abort);
         *val.lock().unwrap() = 5;
-        dispatcher.call(&msg).unwrap();
+        assert!(dispatcher.call(&msg).is_err());
         assert_eq!(*val.lock().unwrap(), 5);
+        assert!(dispatcher.handler_errors().is_empty());
+    }
 
-        let _ = dispatcher
-            .add_handler(Box::new(sample_callback2), None, Some(LocalId(SenderId(0))))
+    struct Sleep(Duration);
+
+    impl Handler for Sleep {
+        fn handle(&mut self, _msg: &GenericMessage) -> Result<HandlerCode> {
+            std::thread::sleep(self.0);
+            Ok(HandlerCode::ContinueProcessing)
+        }
+    }
+
+    #[test]
+    fn handler_timing() {
+        let mut dispatcher = TypeDispatcher::new();
+        dispatcher.set_handler_budget(Some(Duration::from_millis(1)));
+        let sender = dispatcher
+            .register_sender(StaticSenderName(b"Tracker0"))
+            .unwrap()
+            .into_inner();
+        let types = [
+            dispatcher
+                .register_type(StaticMessageTypeName(b"a"))
+                .unwrap()
+                .into_inner(),
+            dispatcher
+                .register_type(StaticMessageTypeName(b"b"))
+                .unwrap()
+                .into_inner(),
+        ];
+        let slow = dispatcher
+            .add_handler_for_types(Box::new(Sleep(Duration::from_millis(3))), &types, None)
             .unwrap();
-        *val.lock().unwrap() = 5;
-        dispatcher.call(&msg).unwrap();
-        assert_eq!(*val.lock().unwrap(), 15);
+        let fast = dispatcher
+            .add_handler(Box::new(Sleep(Duration::ZERO)), ANY_TYPE, None)
+            .unwrap();
+        let filtered = dispatcher
+            .add_handler(
+                Box::new(Sleep(Duration::ZERO)),
+                Some(types[0]),
+                Some(sender),
+            )
+            .unwrap();
+        for message_type in [types[0], types[1], types[1]] {
+            let msg = GenericMessage::from_header_and_body(
+                MessageHeader::new(None, message_type.0, SenderId(sender.0 .0 + 1)),
+                GenericBody::default(),
+            );
+            dispatcher.call(&msg).unwrap();
+        }
 
-        // Check that later-registered callbacks get run later
-        let _ = dispatcher
-            .add_handler(Box::new(sample_callback), None, None)
+        let timing = dispatcher.handler_timing(slow).unwrap();
+        assert_eq!(timing.calls, 3);
+        assert_eq!(timing.over_budget, 3);
+        assert!(timing.max >= Duration::from_millis(3));
+        assert!(timing.mean().unwrap() >= Duration::from_millis(3));
+        assert_eq!(dispatcher.handler_timing(fast).unwrap().calls, 3);
+        let timing = dispatcher.handler_timing(filtered).unwrap();
+        assert_eq!(timing.calls, 0);
+        assert_eq!(timing.mean(), None);
+
+        dispatcher.remove_handler(slow).unwrap();
+        assert_eq!(dispatcher.handler_timing(slow), None);
+    }
+
+    struct Forward {
+        tx: mpsc::UnboundedSender<MessageTypeId>,
+    }
+    impl AsyncHandler for Forward {
+        fn handle(&mut self, msg: &GenericMessage) -> BoxFuture<'static, Result<()>> {
+            let tx = self.tx.clone();
+            let message_type = msg.header.message_type;
+            async move {
+                if message_type == MessageTypeId(1) {
+                    return Err(VrpnError::GenericErrorReturn);
+                }
+                tx.unbounded_send(message_type)
+                    .map_err(|_| VrpnError::EndpointClosed)
+            }
+            .boxed()
+        }
+    }
+
+    #[test]
+    fn async_handlers() {
+        let mut dispatcher = TypeDispatcher::new();
+        let (tx, mut rx) = mpsc::unbounded();
+        dispatcher
+            .add_async_handler(Box::new(Forward { tx }), None, None)
             .unwrap();
-        *val.lock().unwrap() = 5;
+        for message_type in 0..2 {
+            let msg = GenericMessage::from_header_and_body(
+                MessageHeader::new(None, MessageTypeId(message_type), SenderId(0)),
+                GenericBody::default(),
+            );
+            dispatcher.call(&msg).unwrap();
+        }
+        // Nothing runs until polled.
+        assert!(rx.try_next().is_err());
+
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        dispatcher.poll_async_handlers(&mut cx).unwrap();
+        assert_eq!(dispatcher.async_handlers_running(), 0);
+        assert_eq!(rx.try_next().unwrap().unwrap(), MessageTypeId(0));
+        assert_eq!(dispatcher.handler_errors().count, 1);
+    }
+
+    #[test]
+    fn clear_remote_registrations() {
+        let mut dispatcher = TypeDispatcher::new();
+        let local = dispatcher
+            .register_sender(StaticSenderName(b"Local"))
+            .unwrap()
+            .into_inner();
+        let remote = dispatcher
+            .register_remote_sender(StaticSenderName(b"Remote"))
+            .unwrap()
+            .into_inner();
+        let remote_type = dispatcher
+            .register_remote_type(StaticMessageTypeName(b"Remote Type"))
+            .unwrap()
+            .into_inner();
+        let val: Arc<Mutex<i8>> = Arc::new(Mutex::new(5));
+        dispatcher
+            .add_handler(
+                Box::new(SetTo10 {
+                    val: Arc::clone(&val),
+                }),
+                Some(remote_type),
+                None,
+            )
+            .unwrap();
+
+        dispatcher.call_dropped_connection(true).unwrap();
+        assert_eq!(
+            dispatcher.get_sender_id(StaticSenderName(b"Local")),
+            Some(local)
+        );
+        assert_eq!(dispatcher.get_sender_id(StaticSenderName(b"Remote")), None);
+        assert!(dispatcher
+            .names
+            .read()
+            .senders_iter()
+            .all(|(id, _)| id != remote));
+        assert!(matches!(
+            dispatcher.add_handler(
+                Box::new(SetTo10 {
+                    val: Arc::clone(&val),
+                }),
+                None,
+                Some(remote),
+            ),
+            Err(VrpnError::RemovedId(_))
+        ));
+
+        // Described again by a new peer: same IDs, handlers kept.
+        assert_eq!(
+            dispatcher
+                .register_remote_sender(StaticSenderName(b"Remote"))
+                .unwrap(),
+            RegisterMapping::NewMapping(remote)
+        );
+        assert_eq!(
+            dispatcher
+                .register_remote_type(StaticMessageTypeName(b"Remote Type"))
+                .unwrap()
+                .into_inner(),
+            remote_type
+        );
+        let msg = GenericMessage::from_header_and_body(
+            MessageHeader::new(None, remote_type.into_id(), remote.into_id()),
+            GenericBody::default(),
+        );
         dispatcher.call(&msg).unwrap();
         assert_eq!(*val.lock().unwrap(), 10);
+    }
 
-        // This shouldn't trigger callback 2
-        let mut msg2 = msg.clone();
-        msg2.header.sender = SenderId(1);
-        *val.lock().unwrap() = 5;
-        dispatcher.call(&msg2).unwrap();
+    #[test]
+    fn system_handlers() {
+        let mut dispatcher = TypeDispatcher::new();
+        let val: Arc<Mutex<i8>> = Arc::new(Mutex::new(5));
+        let msg = GenericMessage::from_header_and_body(
+            MessageHeader::new(
+                Some(TimeVal::get_time_of_day()),
+                MessageTypeId(-20),
+                SenderId(0),
+            ),
+            GenericBody::default(),
+        );
+
+        // Unhandled: ignored
+        dispatcher.call_system_handler(&msg).unwrap();
+
+        assert!(dispatcher
+            .set_system_handler(
+                MessageTypeId(3),
+                Some(Box::new(SetTo10 { val: val.clone() }))
+            )
+            .is_err());
+        assert!(dispatcher
+            .set_system_handler(
+                constants::SENDER_DESCRIPTION,
+                Some(Box::new(SetTo10 { val: val.clone() }))
+            )
+            .is_err());
+        dispatcher
+            .set_system_handler(
+                MessageTypeId(-20),
+                Some(Box::new(SetTo10 { val: val.clone() })),
+            )
+            .unwrap();
+        dispatcher.call_system_handler(&msg).unwrap();
         assert_eq!(*val.lock().unwrap(), 10);
     }
+
+    /// Signals its arrival, then waits for the handler of another type to arrive.
+    #[derive(Debug)]
+    struct Rendezvous {
+        arrived: Mutex<std::sync::mpsc::Sender<()>>,
+        other: Mutex<std::sync::mpsc::Receiver<()>>,
+    }
+    impl Handler for Rendezvous {
+        fn handle(&mut self, _msg: &GenericMessage) -> Result<HandlerCode> {
+            self.arrived.lock()?.send(()).unwrap();
+            self.other
+                .lock()?
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .map_err(|_| VrpnError::GenericErrorReturn)?;
+            Ok(HandlerCode::ContinueProcessing)
+        }
+    }
+
+    #[test]
+    fn concurrent_dispatch() {
+        use std::sync::mpsc::channel;
+        let mut dispatcher = TypeDispatcher::new();
+        let (a_tx, a_rx) = channel();
+        let (b_tx, b_rx) = channel();
+        let mut messages = Vec::new();
+        for (name, arrived, other) in [
+            (StaticMessageTypeName(b"A"), a_tx, b_rx),
+            (StaticMessageTypeName(b"B"), b_tx, a_rx),
+        ] {
+            let id = dispatcher.register_type(name).unwrap().into_inner();
+            dispatcher
+                .add_handler(
+                    Box::new(Rendezvous {
+                        arrived: Mutex::new(arrived),
+                        other: Mutex::new(other),
+                    }),
+                    Some(id),
+                    None,
+                )
+                .unwrap();
+            messages.push(GenericMessage::from_header_and_body(
+                MessageHeader::new(None, id.into_id(), SenderId(0)),
+                GenericBody::default(),
+            ));
+        }
+
+        // Each handler only returns once the other has started.
+        let dispatcher = Arc::new(crate::sync::RwLock::new(dispatcher));
+        let threads: Vec<_> = messages
+            .into_iter()
+            .map(|msg| {
+                let dispatcher = Arc::clone(&dispatcher);
+                std::thread::spawn(move || dispatcher.read().call(&msg))
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap().unwrap();
+        }
+    }
+
+    #[derive(Debug)]
+    struct Count {
+        count: Arc<Mutex<i8>>,
+        once: bool,
+    }
+    impl Handler for Count {
+        fn handle(&mut self, _msg: &GenericMessage) -> Result<HandlerCode> {
+            *self.count.lock()? += 1;
+            Ok(if self.once {
+                HandlerCode::RemoveThisHandler
+            } else {
+                HandlerCode::ContinueProcessing
+            })
+        }
+    }
+
+    #[test]
+    fn handler_for_types() {
+        let mut dispatcher = TypeDispatcher::new();
+        let messages: Vec<_> = [&b"A"[..], b"B", b"C"]
+            .iter()
+            .map(|name| {
+                let id = dispatcher
+                    .register_type(StaticMessageTypeName(name))
+                    .unwrap()
+                    .into_inner();
+                GenericMessage::from_header_and_body(
+                    MessageHeader::new(None, id.into_id(), SenderId(0)),
+                    GenericBody::default(),
+                )
+            })
+            .collect();
+        let types: Vec<_> = messages[..2]
+            .iter()
+            .map(|msg| LocalId(msg.header.message_type))
+            .collect();
+        let call_all = |dispatcher: &TypeDispatcher| {
+            for msg in &messages {
+                dispatcher.call(msg).unwrap();
+            }
+        };
+
+        let count = Arc::new(Mutex::new(0));
+        let handle = dispatcher
+            .add_handler_for_types(
+                Box::new(Count {
+                    count: Arc::clone(&count),
+                    once: false,
+                }),
+                &types,
+                None,
+            )
+            .unwrap();
+        call_all(&dispatcher);
+        assert_eq!(*count.lock().unwrap(), 2);
+        dispatcher.remove_handler(handle).unwrap();
+        call_all(&dispatcher);
+        assert_eq!(*count.lock().unwrap(), 2);
+        assert!(matches!(
+            dispatcher.remove_handler(handle),
+            Err(VrpnError::HandlerNotFound)
+        ));
+
+        // Asking to be removed by one type removes it from all of them.
+        *count.lock().unwrap() = 0;
+        let handle = dispatcher
+            .add_handler_for_types(
+                Box::new(Count {
+                    count: Arc::clone(&count),
+                    once: true,
+                }),
+                &types,
+                None,
+            )
+            .unwrap();
+        call_all(&dispatcher);
+        call_all(&dispatcher);
+        assert_eq!(*count.lock().unwrap(), 1);
+        dispatcher.remove_handler(handle).unwrap();
+
+        // Nothing is added if any type is unknown.
+        let bad = [types[0], LocalId(MessageTypeId(100))];
+        assert!(dispatcher
+            .add_handler_for_types(
+                Box::new(Count {
+                    count: Arc::clone(&count),
+                    once: false,
+                }),
+                &bad,
+                None,
+            )
+            .is_err());
+        call_all(&dispatcher);
+        assert_eq!(*count.lock().unwrap(), 1);
+    }
+
+    #[derive(Debug)]
+    struct Push {
+        log: Arc<Mutex<Vec<char>>>,
+        tag: char,
+    }
+    impl Handler for Push {
+        fn handle(&mut self, _msg: &GenericMessage) -> Result<HandlerCode> {
+            self.log.lock()?.push(self.tag);
+            Ok(HandlerCode::ContinueProcessing)
+        }
+    }
+
+    #[test]
+    fn wildcards() {
+        let mut dispatcher = TypeDispatcher::new();
+        let tracker = dispatcher
+            .register_sender(StaticSenderName(b"Tracker0"))
+            .unwrap()
+            .into_inner();
+        let button = dispatcher
+            .register_sender(StaticSenderName(b"Button0"))
+            .unwrap()
+            .into_inner();
+        let message_type = dispatcher
+            .register_type(StaticMessageTypeName(b"Report"))
+            .unwrap()
+            .into_inner();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut add = |tag, message_type, sender| {
+            let log = Arc::clone(&log);
+            dispatcher.add_handler(Box::new(Push { log, tag }), message_type, sender)
+        };
+        add('a', Some(message_type), ANY_SENDER).unwrap();
+        add('b', ANY_TYPE, Some(tracker)).unwrap();
+        add('c', Some(message_type), Some(button)).unwrap();
+        add('d', ANY_TYPE, ANY_SENDER).unwrap();
+        assert!(matches!(
+            add('e', ANY_TYPE, Some(LocalId(SenderId(100)))),
+            Err(VrpnError::InvalidId(100))
+        ));
+
+        let message = |message_type: MessageTypeId, sender: LocalId<SenderId>| {
+            GenericMessage::from_header_and_body(
+                MessageHeader::new(None, message_type, sender.into_id()),
+                GenericBody::default(),
+            )
+        };
+        // Any-type handlers first, then by type, each in the order added.
+        dispatcher
+            .call(&message(message_type.into_id(), tracker))
+            .unwrap();
+        assert_eq!(*log.lock().unwrap(), ['b', 'd', 'a']);
+        log.lock().unwrap().clear();
+        dispatcher
+            .call(&message(message_type.into_id(), button))
+            .unwrap();
+        assert_eq!(*log.lock().unwrap(), ['d', 'a', 'c']);
+
+        // System messages are not given to any-type handlers.
+        log.lock().unwrap().clear();
+        dispatcher
+            .call(&message(constants::DISCONNECT_MESSAGE, tracker))
+            .unwrap();
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn limits() {
+        // One sender and seven message types are registered for system use.
+        let mut dispatcher = TypeDispatcher::with_limits(DispatcherLimits {
+            max_senders: 2,
+            max_message_types: 8,
+            max_handlers: 1,
+        });
+        let tracker = dispatcher
+            .register_sender(StaticSenderName(b"Tracker0"))
+            .unwrap()
+            .into_inner();
+        let err = dispatcher
+            .register_remote_sender(StaticSenderName(b"Tracker1"))
+            .unwrap_err();
+        assert!(matches!(
+            &err,
+            VrpnError::LimitReached { kind: LimitKind::Senders, limit: 2, name } if name == "Tracker1"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "reached the limit of 2 senders, registering \"Tracker1\""
+        );
+        // Registering existing names still works.
+        assert_eq!(
+            dispatcher
+                .register_sender(StaticSenderName(b"Tracker0"))
+                .unwrap(),
+            RegisterMapping::Found(tracker)
+        );
+
+        let message_type = dispatcher
+            .register_type(StaticMessageTypeName(b"Report"))
+            .unwrap()
+            .into_inner();
+        assert!(matches!(
+            dispatcher.register_type(StaticMessageTypeName(b"Other")),
+            Err(VrpnError::LimitReached { kind: LimitKind::MessageTypes, limit: 8, name }) if name == "Other"
+        ));
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let add = |dispatcher: &mut TypeDispatcher, message_type| {
+            let log = Arc::clone(&log);
+            dispatcher.add_handler(Box::new(Push { log, tag: 'a' }), message_type, ANY_SENDER)
+        };
+        add(&mut dispatcher, Some(message_type)).unwrap();
+        add(&mut dispatcher, ANY_TYPE).unwrap();
+        assert!(matches!(
+            add(&mut dispatcher, Some(message_type)),
+            Err(VrpnError::LimitReached { kind: LimitKind::Handlers, limit: 1, name }) if name == "Report"
+        ));
+        assert!(matches!(
+            add(&mut dispatcher, ANY_TYPE),
+            Err(VrpnError::LimitReached { kind: LimitKind::Handlers, name, .. }) if name == GENERIC
+        ));
+
+        dispatcher.set_limits(DispatcherLimits::default());
+        dispatcher
+            .register_sender(StaticSenderName(b"Tracker1"))
+            .unwrap();
+        add(&mut dispatcher, Some(message_type)).unwrap();
+    }
 }
@@ -0,0 +1,414 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Gathering the options for a `ConnectionIp` in one place, rather than in constructor arguments.
+
+use super::{connection_ip::ConnectionIp, runtime::AsyncRuntime};
+#[cfg(feature = "async-std")]
+use crate::vrpn_async_std::AsyncStd;
+#[cfg(feature = "async-tokio")]
+use crate::vrpn_tokio::Tokio;
+use crate::{
+    compression::Compression, data_types::LogFileNames, driver::split,
+    message_log::RemoteLogPolicy, net_util::SocketConfig, poll_config::PollConfig,
+    send_queue::SendQueueLimits, timeouts::Timeouts, timestamp_policy::TimestampPolicy,
+    type_dispatcher::DispatcherLimits, CompatibilityProfile, Connection, ConnectionHandle,
+    ReconnectPolicy, Result, ServerInfo, VrpnError,
+};
+use futures::FutureExt;
+use std::{net::SocketAddr, sync::Arc};
+
+/// The runtime a built connection's sockets and timers are on,
+/// and that `spawn_client` and `spawn_server` run its driver on.
+///
+/// Defaults to async-std, if enabled.
+/// For runtimes of your own, see `ConnectionIp::new_client_on` and `new_server_on`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum Runtime {
+    #[cfg(feature = "async-std")]
+    #[default]
+    AsyncStd,
+    /// The current Tokio runtime: building panics outside of one. See `vrpn_tokio`.
+    #[cfg(feature = "async-tokio")]
+    #[cfg_attr(not(feature = "async-std"), default)]
+    Tokio,
+}
+
+impl Runtime {
+    /// The sockets and timers of this runtime.
+    pub fn async_runtime(self) -> Arc<dyn AsyncRuntime> {
+        match self {
+            #[cfg(feature = "async-std")]
+            Runtime::AsyncStd => Arc::new(AsyncStd),
+            #[cfg(feature = "async-tokio")]
+            Runtime::Tokio => Arc::new(Tokio::current()),
+        }
+    }
+}
+
+/// Builder for client and server `ConnectionIp`s.
+///
+/// Settings left alone keep the same defaults as the `ConnectionIp` constructors.
+/// Unlike setting them on the connection afterwards, the timeouts given here
+/// already apply to a client's first connection attempt, and a server's first handshakes.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionBuilder {
+    server: Option<ServerInfo>,
+    listen_addr: Option<SocketAddr>,
+    local_log: Option<LogFileNames>,
+    remote_log: Option<LogFileNames>,
+    compatibility: CompatibilityProfile,
+    timeouts: Timeouts,
+    socket_config: Option<SocketConfig>,
+    poll_config: Option<PollConfig>,
+    send_queue_limits: Option<SendQueueLimits>,
+    remote_log_policy: Option<RemoteLogPolicy>,
+    timestamp_policy: Option<TimestampPolicy>,
+    dispatcher_limits: Option<DispatcherLimits>,
+    compression: Option<Compression>,
+    reconnect_policy: Option<ReconnectPolicy>,
+    runtime: Runtime,
+}
+
+impl ConnectionBuilder {
+    pub fn new() -> ConnectionBuilder {
+        ConnectionBuilder::default()
+    }
+
+    /// Set the server for a client to connect to. Required by `build_client`.
+    pub fn server_info(mut self, server: ServerInfo) -> ConnectionBuilder {
+        self.server = Some(server);
+        self
+    }
+
+    /// Set the address for a server to accept clients on, over TCP and UDP.
+    ///
+    /// Without one, the server accepts no clients over IP.
+    pub fn listen_addr(mut self, addr: SocketAddr) -> ConnectionBuilder {
+        self.listen_addr = Some(addr);
+        self
+    }
+
+    /// Log each endpoint's messages to these files on this host.
+    pub fn local_log(mut self, names: LogFileNames) -> ConnectionBuilder {
+        self.local_log = Some(names);
+        self
+    }
+
+    /// Ask the server to log this client's connection to these files on its host.
+    pub fn remote_log(mut self, names: LogFileNames) -> ConnectionBuilder {
+        self.remote_log = Some(names);
+        self
+    }
+
+    /// Talk to peers according to this compatibility profile.
+    pub fn compatibility(mut self, compatibility: CompatibilityProfile) -> ConnectionBuilder {
+        self.compatibility = compatibility;
+        self
+    }
+
+    /// Set the limits on connecting, the handshake, and idle endpoints.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> ConnectionBuilder {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Set options on the sockets of endpoints: see `Connection::set_socket_config`.
+    pub fn socket_config(mut self, config: SocketConfig) -> ConnectionBuilder {
+        self.socket_config = Some(config);
+        self
+    }
+
+    /// Limit how much each endpoint receives and dispatches per poll.
+    pub fn poll_config(mut self, config: PollConfig) -> ConnectionBuilder {
+        self.poll_config = Some(config);
+        self
+    }
+
+    /// Limit how far behind each peer may fall in reading what we send.
+    pub fn send_queue_limits(mut self, limits: SendQueueLimits) -> ConnectionBuilder {
+        self.send_queue_limits = Some(limits);
+        self
+    }
+
+    /// Set whether to log connections to the files peers name, when they ask.
+    pub fn remote_log_policy(mut self, policy: RemoteLogPolicy) -> ConnectionBuilder {
+        self.remote_log_policy = Some(policy);
+        self
+    }
+
+    /// Set which clock the times of user messages reflect, sent and received.
+    pub fn timestamp_policy(mut self, policy: TimestampPolicy) -> ConnectionBuilder {
+        self.timestamp_policy = Some(policy);
+        self
+    }
+
+    /// Compress TCP traffic with peers that are also this crate, once they offer the same.
+    pub fn compression(mut self, compression: Compression) -> ConnectionBuilder {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Set when a client connects to its server again without being asked to.
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> ConnectionBuilder {
+        self.reconnect_policy = Some(policy);
+        self
+    }
+
+    /// Set the runtime for the connection's sockets and timers, and to drive it on
+    /// with `spawn_client` and `spawn_server`.
+    pub fn runtime(mut self, runtime: Runtime) -> ConnectionBuilder {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Limit the senders, message types, and handlers the connection registers.
+    pub fn dispatcher_limits(mut self, limits: DispatcherLimits) -> ConnectionBuilder {
+        self.dispatcher_limits = Some(limits);
+        self
+    }
+
+    /// Create a client, connecting to the server from `server_info`.
+    ///
+    /// Fails if no server was set.
+    pub fn build_client(self) -> Result<Arc<ConnectionIp>> {
+        let server = self.server.clone().ok_or(VrpnError::NoServerSet)?;
+        let conn = ConnectionIp::new_client_on(
+            self.runtime.async_runtime(),
+            server,
+            self.local_log.clone(),
+            self.remote_log.clone(),
+            self.compatibility,
+            self.timeouts,
+        )?;
+        self.apply(&conn)?;
+        Ok(conn)
+    }
+
+    /// Create a server, accepting clients on `listen_addr` if one was set.
+    pub fn build_server(self) -> Result<Arc<ConnectionIp>> {
+        let conn = ConnectionIp::new_server_on(
+            self.runtime.async_runtime(),
+            self.local_log.clone(),
+            self.listen_addr,
+            self.compatibility,
+            self.timeouts,
+        )?;
+        self.apply(&conn)?;
+        Ok(conn)
+    }
+
+    /// Create a client as with `build_client`, and spawn its driver on the runtime set.
+    ///
+    /// The driver runs until the last handle is dropped: errors that stop it early are logged.
+    pub fn spawn_client(self) -> Result<ConnectionHandle<ConnectionIp>> {
+        Ok(spawn(self.build_client()?))
+    }
+
+    /// Create a server as with `build_server`, and spawn its driver on the runtime set.
+    ///
+    /// The driver runs until the last handle is dropped: errors that stop it early are logged.
+    pub fn spawn_server(self) -> Result<ConnectionHandle<ConnectionIp>> {
+        Ok(spawn(self.build_server()?))
+    }
+
+    /// Apply the settings that are not needed at construction.
+    fn apply(&self, conn: &ConnectionIp) -> Result<()> {
+        if let Some(config) = self.socket_config {
+            conn.set_socket_config(config)?;
+        }
+        if let Some(config) = self.poll_config {
+            conn.set_poll_config(config)?;
+        }
+        if let Some(limits) = self.send_queue_limits {
+            conn.set_send_queue_limits(limits)?;
+        }
+        if let Some(policy) = self.remote_log_policy {
+            conn.set_remote_log_policy(policy)?;
+        }
+        if let Some(policy) = self.timestamp_policy {
+            conn.set_timestamp_policy(policy)?;
+        }
+        if let Some(limits) = self.dispatcher_limits {
+            conn.set_dispatcher_limits(limits)?;
+        }
+        if let Some(compression) = self.compression {
+            conn.set_compression(compression)?;
+        }
+        if let Some(policy) = self.reconnect_policy {
+            conn.set_reconnect_policy(policy);
+        }
+        Ok(())
+    }
+}
+
+/// Spawn the driver of `conn` on the runtime its sockets are on.
+fn spawn(conn: Arc<ConnectionIp>) -> ConnectionHandle<ConnectionIp> {
+    let runtime = Arc::clone(conn.runtime());
+    let (handle, driver) = split(conn);
+    runtime.spawn(
+        async move {
+            if let Err(e) = driver.await {
+                warn!("Connection driver stopped: {}", e);
+            }
+        }
+        .boxed(),
+    );
+    handle
+}
+
+#[cfg(all(test, feature = "async-std"))]
+mod tests {
+    use super::*;
+    use crate::ConnectionStatus;
+    use std::{
+        task::Poll,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn build_and_connect() {
+        let timeouts = Timeouts {
+            connect: Some(Duration::from_secs(2)),
+            ..Timeouts::default()
+        };
+        let limits = SendQueueLimits {
+            max_queued: Some(100),
+            ..SendQueueLimits::default()
+        };
+        let server = ConnectionBuilder::new()
+            .listen_addr("127.0.0.1:0".parse().unwrap())
+            .timeouts(timeouts)
+            .send_queue_limits(limits)
+            .build_server()
+            .unwrap();
+        assert_eq!(server.connection_core().timeouts().unwrap(), timeouts);
+        assert_eq!(
+            server.connection_core().send_queue_limits().unwrap(),
+            limits
+        );
+
+        assert!(matches!(
+            ConnectionBuilder::new().build_client(),
+            Err(VrpnError::NoServerSet)
+        ));
+        let client = ConnectionBuilder::new()
+            .server_info(
+                format!("tcp://{}", server.listen_addr().unwrap())
+                    .parse()
+                    .unwrap(),
+            )
+            .timeouts(timeouts)
+            .build_client()
+            .unwrap();
+        assert_eq!(client.connection_core().timeouts().unwrap(), timeouts);
+
+        let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while client.status() != ConnectionStatus::ClientConnected
+            || server.status() != ConnectionStatus::Server(1)
+        {
+            assert!(Instant::now() < deadline, "timed out connecting");
+            let _ = server.poll_endpoints(&mut cx);
+            let _ = client.poll_endpoints(&mut cx);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn retry_until_server_listens() {
+        // Find a free port, and leave it closed for now.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = ConnectionBuilder::new()
+            .server_info(format!("tcp://{}", addr).parse().unwrap())
+            .reconnect_policy(ReconnectPolicy::Retry {
+                delay: Duration::from_millis(20),
+            })
+            .build_client()
+            .unwrap();
+        let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
+        let deadline = Instant::now() + Duration::from_millis(200);
+        while Instant::now() < deadline {
+            assert!(!matches!(
+                client.poll_endpoints(&mut cx),
+                Poll::Ready(Err(_))
+            ));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(client.status(), ConnectionStatus::ClientConnecting);
+
+        let server = ConnectionBuilder::new()
+            .listen_addr(addr)
+            .build_server()
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while client.status() != ConnectionStatus::ClientConnected {
+            assert!(Instant::now() < deadline, "timed out retrying");
+            let _ = server.poll_endpoints(&mut cx);
+            let _ = client.poll_endpoints(&mut cx);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn spawn_drivers() {
+        let server = ConnectionBuilder::new()
+            .listen_addr("127.0.0.1:0".parse().unwrap())
+            .runtime(Runtime::AsyncStd)
+            .spawn_server()
+            .unwrap();
+        let client = ConnectionBuilder::new()
+            .server_info(
+                format!("tcp://{}", server.connection().listen_addr().unwrap())
+                    .parse()
+                    .unwrap(),
+            )
+            .spawn_client()
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while client.status() != ConnectionStatus::ClientConnected
+            || server.status() != ConnectionStatus::Server(1)
+        {
+            assert!(Instant::now() < deadline, "timed out connecting");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn manual_stays_disconnected() {
+        let server = ConnectionBuilder::new()
+            .listen_addr("127.0.0.1:0".parse().unwrap())
+            .build_server()
+            .unwrap();
+        let client = ConnectionBuilder::new()
+            .server_info(
+                format!("tcp://{}", server.listen_addr().unwrap())
+                    .parse()
+                    .unwrap(),
+            )
+            .reconnect_policy(ReconnectPolicy::Manual)
+            .build_client()
+            .unwrap();
+        let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while client.status() != ConnectionStatus::ClientConnected {
+            assert!(Instant::now() < deadline, "timed out connecting");
+            let _ = server.poll_endpoints(&mut cx);
+            let _ = client.poll_endpoints(&mut cx);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        drop(server);
+        while client.status() != ConnectionStatus::ClientDisconnected {
+            assert!(Instant::now() < deadline, "timed out noticing the loss");
+            let _ = client.poll_endpoints(&mut cx);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = client.poll_endpoints(&mut cx);
+        assert_eq!(client.status(), ConnectionStatus::ClientDisconnected);
+    }
+}
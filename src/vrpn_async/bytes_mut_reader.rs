@@ -10,10 +10,7 @@ impl BytesMutReader {
     pub fn with_capacity(capacity: usize) -> Self {
         Self(BytesMut::with_capacity(capacity))
     }
-    pub async fn read_from<T: AsyncRead + Unpin>(
-        self,
-        stream: &mut T,
-    ) -> async_std::io::Result<Self> {
+    pub async fn read_from<T: AsyncRead + Unpin>(self, stream: &mut T) -> std::io::Result<Self> {
         let mut buf = self.0;
         let orig_cap = buf.capacity();
         let orig_len = buf.len();
@@ -49,7 +46,7 @@ impl BytesMutReader {
 pub async fn read_into_bytes_mut<T: AsyncRead + Unpin>(
     stream: &mut T,
     buf: &mut BytesMut,
-) -> async_std::io::Result<usize> {
+) -> std::io::Result<usize> {
     let orig_cap = buf.capacity();
     let orig_len = buf.len();
     let mut before = buf.split();
@@ -68,12 +65,12 @@ pub async fn read_n_into_bytes_mut<T: AsyncRead + Unpin>(
     stream: &mut T,
     buf: &mut BytesMut,
     max_len: usize,
-) -> async_std::io::Result<usize> {
+) -> std::io::Result<usize> {
     buf.reserve(max_len);
     let orig_cap = buf.capacity();
     let orig_len = buf.len();
     let mut local_buf: Vec<u8> = vec![0u8; max_len];
-    async_std::io::ReadExt::read_exact(stream, &mut local_buf).await?;
+    stream.read_exact(&mut local_buf).await?;
     buf.extend_from_slice(&local_buf);
     assert_eq!(orig_cap, buf.capacity());
     assert_eq!(orig_len + max_len, buf.len());
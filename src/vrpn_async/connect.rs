@@ -0,0 +1,395 @@
+// Copyright 2018-2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Connecting to servers and accepting clients, up to the end of the cookie handshake.
+
+use std::{
+    collections::HashSet,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::{
+    future::{self, Either},
+    stream::{self, BoxStream, StreamExt},
+};
+use socket2::SockAddr;
+
+use super::{
+    memory::connect_memory,
+    reliable_stream::ReliableStream,
+    runtime::{within, AsyncRuntime, TcpListener, TcpStream, UdpSocket},
+};
+use crate::{
+    buffer_unbuffer::{BytesMutExtras, UnbufferFrom},
+    constants::UDP_BUFLEN,
+    data_types::{ConnectionRequest, CookieData},
+    net_util::{is_connect_in_progress, make_tcp_listener, make_tcp_socket, make_udp_socket},
+    sync::Mutex,
+    timeouts::{TimeoutKind, Timeouts},
+    vrpn_async::cookie::exchange_nonfile_cookies,
+    CompatibilityProfile, Result, Scheme, ServerInfo, TlsClientOptions, VrpnError,
+};
+
+pub struct ConnectResults {
+    pub(crate) stream: ReliableStream,
+    pub(crate) udp: Option<UdpSocket>,
+    /// The cookie the peer sent in the handshake.
+    pub(crate) remote_cookie: CookieData,
+}
+
+/// How often to check whether a non-blocking connect has finished.
+const CONNECT_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+async fn outgoing_tcp_connect(
+    runtime: &dyn AsyncRuntime,
+    addr: std::net::SocketAddr,
+) -> Result<TcpStream> {
+    let sock = make_tcp_socket(addr)?;
+    match sock.connect(&SockAddr::from(addr)) {
+        Ok(()) => {}
+        Err(e) if is_connect_in_progress(&e) => loop {
+            if let Some(e) = sock.take_error()? {
+                return Err(connect_error(e, addr));
+            }
+            if sock.peer_addr().is_ok() {
+                break;
+            }
+            runtime.sleep(CONNECT_POLL_INTERVAL).await;
+        },
+        Err(e) => return Err(connect_error(e, addr)),
+    }
+    Ok(runtime.tcp_stream(sock.into())?)
+}
+
+/// Report a refused connection as such, rather than as a bare I/O error.
+fn connect_error(e: std::io::Error, addr: std::net::SocketAddr) -> VrpnError {
+    if e.kind() == std::io::ErrorKind::ConnectionRefused {
+        VrpnError::ConnectionRefused { addr }
+    } else {
+        e.into()
+    }
+}
+
+async fn lobbing(
+    runtime: &dyn AsyncRuntime,
+    udp: &UdpSocket,
+    buf: &Bytes,
+    tcp_listener: &TcpListener,
+    server: ServerInfo,
+) -> Result<Option<TcpStream>> {
+    udp.send_to(buf, server.socket_addr).await?;
+    let accept = tcp_listener.accept();
+    futures::pin_mut!(accept);
+    let wait = runtime.sleep(Duration::from_millis(MILLIS_BETWEEN_ATTEMPTS));
+    match future::select(accept, wait).await {
+        Either::Left((accepted, _)) => Ok(Some(accepted?.0)),
+        Either::Right(_) => Ok(None),
+    }
+}
+
+pub(crate) async fn handshake(
+    stream: impl Into<ReliableStream>,
+    udp: Option<UdpSocket>,
+    profile: CompatibilityProfile,
+) -> Result<ConnectResults> {
+    let mut stream = stream.into();
+    let remote_cookie = exchange_nonfile_cookies(&mut stream, profile)
+        .await
+        .map_err(|e| match e {
+            VrpnError::VersionMismatch { .. } => e,
+            e => VrpnError::HandshakeFailed(Box::new(e)),
+        })?;
+    Ok(ConnectResults {
+        stream,
+        udp,
+        remote_cookie,
+    })
+}
+
+async fn connect_tcp_and_udp(
+    runtime: &dyn AsyncRuntime,
+    server: ServerInfo,
+    profile: CompatibilityProfile,
+    timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    let udp = runtime.udp_socket(make_udp_socket(server.socket_addr)?)?;
+    let addr = ("localhost", 0)
+        .to_socket_addrs()?
+        .next()
+        .ok_or(VrpnError::CouldNotConnect)?;
+    let tcp_listener = runtime.tcp_listener(make_tcp_listener(addr)?)?;
+    let addr = tcp_listener.local_addr()?;
+    let lobbed_buf = BytesMut::allocate_and_buffer(ConnectionRequest::new(addr))?.freeze();
+    let tcp_stream = within(runtime, timeouts.connect, TimeoutKind::Connect, async {
+        for _ in 0..5 {
+            if let Some(tcp_stream) =
+                lobbing(runtime, &udp, &lobbed_buf, &tcp_listener, server.clone()).await?
+            {
+                return Ok(tcp_stream);
+            }
+        }
+        Err(VrpnError::CouldNotConnect)
+    })
+    .await?;
+    within(
+        runtime,
+        timeouts.handshake,
+        TimeoutKind::Handshake,
+        handshake(tcp_stream, Some(udp), profile),
+    )
+    .await
+}
+async fn connect_tcp_only(
+    runtime: &dyn AsyncRuntime,
+    server: ServerInfo,
+    profile: CompatibilityProfile,
+    timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    let tcp = within(
+        runtime,
+        timeouts.connect,
+        TimeoutKind::Connect,
+        outgoing_tcp_connect(runtime, server.socket_addr),
+    )
+    .await?;
+    within(
+        runtime,
+        timeouts.handshake,
+        TimeoutKind::Handshake,
+        handshake(tcp, None, profile),
+    )
+    .await
+}
+
+/// Unix domain sockets are async-std's, whichever runtime the connection is on.
+#[cfg(all(unix, feature = "async-std"))]
+async fn connect_unix(
+    runtime: &dyn AsyncRuntime,
+    server: ServerInfo,
+    profile: CompatibilityProfile,
+    timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    let path = server
+        .path
+        .ok_or(VrpnError::MissingServerPath(Scheme::Unix))?;
+    let stream = within(runtime, timeouts.connect, TimeoutKind::Connect, async {
+        Ok(async_std::os::unix::net::UnixStream::connect(path).await?)
+    })
+    .await?;
+    within(
+        runtime,
+        timeouts.handshake,
+        TimeoutKind::Handshake,
+        handshake(stream, None, profile),
+    )
+    .await
+}
+
+#[cfg(all(unix, not(feature = "async-std")))]
+async fn connect_unix(
+    _runtime: &dyn AsyncRuntime,
+    _server: ServerInfo,
+    _profile: CompatibilityProfile,
+    _timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    Err(VrpnError::FeatureDisabled("vrpn-async-std"))
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(
+    _runtime: &dyn AsyncRuntime,
+    _server: ServerInfo,
+    _profile: CompatibilityProfile,
+    _timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    Err(VrpnError::UnsupportedScheme(Scheme::Unix))
+}
+
+/// Where a server-side connection that has not yet done the handshake came from.
+enum Incoming {
+    Accepted(TcpStream),
+    /// A client asked over UDP to be connected to this address.
+    Requested(SocketAddr),
+}
+
+/// Most handshakes with new clients a server will run at once.
+const MAX_PENDING_HANDSHAKES: usize = 16;
+
+/// Where to connect back to for a request: the requested address,
+/// except that an unspecified IP means the one the request came from.
+fn callback_address(requested: SocketAddr, from: SocketAddr) -> SocketAddr {
+    if requested.ip().is_unspecified() {
+        SocketAddr::new(from.ip(), requested.port())
+    } else {
+        requested
+    }
+}
+
+async fn receive_connection_request(udp: &UdpSocket) -> Result<SocketAddr> {
+    let mut buf = [0u8; UDP_BUFLEN];
+    let (len, from) = udp.recv_from(&mut buf).await?;
+    let request = ConnectionRequest::unbuffer_from(&mut &buf[..len])?;
+    Ok(callback_address(request.socket_address, from))
+}
+
+/// Accept TCP clients for a server: both those connecting directly,
+/// and those asking on the UDP socket to be connected back to, as mainline VRPN clients do.
+///
+/// Clients repeat their request until connected to, so requests for an address
+/// already being connected to are ignored.
+/// Clients that asked over UDP get a UDP socket for low-latency messages, as in mainline VRPN.
+/// Handshakes run concurrently, so one slow client does not hold up the others.
+pub(crate) fn incoming_tcp(
+    runtime: Arc<dyn AsyncRuntime>,
+    listener: TcpListener,
+    udp: UdpSocket,
+    profile: CompatibilityProfile,
+    timeouts: Timeouts,
+) -> BoxStream<'static, Result<ConnectResults>> {
+    let accepted = stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await;
+        Some((
+            accepted
+                .map(|(stream, _)| Incoming::Accepted(stream))
+                .map_err(VrpnError::from),
+            listener,
+        ))
+    });
+    let connecting = Arc::new(Mutex::new(HashSet::new()));
+    let requested = {
+        let connecting = Arc::clone(&connecting);
+        stream::unfold(udp, |udp| async move {
+            let requested = receive_connection_request(&udp).await;
+            Some((requested.map(Incoming::Requested), udp))
+        })
+        .filter(move |incoming| {
+            future::ready(match (incoming, connecting.lock()) {
+                (Ok(Incoming::Requested(addr)), mut connecting) => connecting.insert(*addr),
+                _ => true,
+            })
+        })
+    };
+    stream::select(accepted, requested)
+        .map(move |incoming| {
+            let connecting = Arc::clone(&connecting);
+            let runtime = Arc::clone(&runtime);
+            async move {
+                let runtime = &*runtime;
+                let (stream, udp) = match incoming? {
+                    Incoming::Accepted(stream) => {
+                        stream.set_nodelay(true)?;
+                        (stream, None)
+                    }
+                    Incoming::Requested(addr) => {
+                        let connected = within(
+                            runtime,
+                            timeouts.connect,
+                            TimeoutKind::Connect,
+                            outgoing_tcp_connect(runtime, addr),
+                        )
+                        .await;
+                        connecting.lock().remove(&addr);
+                        let udp = runtime.udp_socket(make_udp_socket(addr)?)?;
+                        (connected?, Some(udp))
+                    }
+                };
+                within(
+                    runtime,
+                    timeouts.handshake,
+                    TimeoutKind::Handshake,
+                    handshake(stream, udp, profile),
+                )
+                .await
+            }
+        })
+        .buffer_unordered(MAX_PENDING_HANDSHAKES)
+        .boxed()
+}
+
+#[cfg(feature = "websocket")]
+async fn connect_websocket(
+    server: ServerInfo,
+    profile: CompatibilityProfile,
+    timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    crate::vrpn_async_std::websocket::connect_ws(server, profile, timeouts).await
+}
+
+#[cfg(not(feature = "websocket"))]
+async fn connect_websocket(
+    _server: ServerInfo,
+    _profile: CompatibilityProfile,
+    _timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    Err(VrpnError::FeatureDisabled("websocket"))
+}
+
+#[cfg(feature = "tls")]
+async fn connect_tls(
+    server: ServerInfo,
+    tls: Arc<TlsClientOptions>,
+    profile: CompatibilityProfile,
+    timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    crate::vrpn_async_std::tls::connect_tls(server, &tls, profile, timeouts).await
+}
+
+#[cfg(not(feature = "tls"))]
+async fn connect_tls(
+    _server: ServerInfo,
+    _tls: Arc<TlsClientOptions>,
+    _profile: CompatibilityProfile,
+    _timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    Err(VrpnError::FeatureDisabled("tls"))
+}
+
+const MILLIS_BETWEEN_ATTEMPTS: u64 = 500;
+
+/// Connect to a server on `runtime`, failing with `VrpnError::Timeout` if connecting
+/// or the handshake take longer than allowed.
+///
+/// TLS, WebSocket and Unix domain socket connections use async-std's sockets,
+/// whichever runtime is given: their features need `vrpn-async-std`.
+pub async fn connect_on(
+    runtime: Arc<dyn AsyncRuntime>,
+    server: ServerInfo,
+    profile: CompatibilityProfile,
+    timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    if let Some(tls) = server.tls.clone() {
+        return connect_tls(server, tls, profile, timeouts).await;
+    }
+    let runtime = &*runtime;
+    match server.scheme {
+        Scheme::UdpAndTcp | Scheme::UdpOnly => {
+            connect_tcp_and_udp(runtime, server, profile, timeouts).await
+        }
+        Scheme::TcpOnly => connect_tcp_only(runtime, server, profile, timeouts).await,
+        Scheme::Unix => connect_unix(runtime, server, profile, timeouts).await,
+        Scheme::WebSocket => connect_websocket(server, profile, timeouts).await,
+        Scheme::Memory => connect_memory(runtime, server, profile, timeouts).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn callback_to_sender_if_unspecified() {
+        let from: SocketAddr = "192.168.1.5:40000".parse().unwrap();
+        assert_eq!(
+            callback_address("10.0.0.2:4500".parse().unwrap(), from),
+            "10.0.0.2:4500".parse().unwrap()
+        );
+        assert_eq!(
+            callback_address("0.0.0.0:4500".parse().unwrap(), from),
+            "192.168.1.5:4500".parse().unwrap()
+        );
+    }
+}
@@ -0,0 +1,1496 @@
+// Copyright 2018, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+use crate::net_util::{make_tcp_listener, make_udp_listener};
+use crate::{
+    compression::{compression_offer, Compression},
+    connection::*,
+    connection_state::{
+        ConnectionAction, ConnectionEvent, ConnectionFsm, ConnectionState, ReconnectPolicy,
+    },
+    data_types::{
+        id_types::{LocalId, SenderId},
+        ClassOfService, CookieData, LogFileNames, LogMode, TypedMessage,
+    },
+    endpoint::udp_only_request,
+    message_history::Direction,
+    message_log::LogWriter,
+    sequence::SequenceStats,
+    sync::Mutex,
+    timeouts::Timeouts,
+    CompatibilityProfile, DeviceInfo, Endpoint, EndpointGeneric, PollEndpoints, Result, Scheme,
+    ServerInfo, VrpnError,
+};
+use futures::{
+    future::BoxFuture,
+    stream::{BoxStream, StreamExt},
+    FutureExt, Stream,
+};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    task::Poll,
+    time::{Duration, SystemTime},
+};
+
+use super::{
+    connect::{connect_on, incoming_tcp, ConnectResults},
+    endpoint_ip::EndpointIp,
+    memory::{accept_memory, MemoryListener},
+    runtime::AsyncRuntime,
+};
+
+/// Accept clients from a memory listener, past the handshake.
+fn incoming_memory(
+    runtime: Arc<dyn AsyncRuntime>,
+    listener: MemoryListener,
+    compatibility: CompatibilityProfile,
+) -> BoxStream<'static, Result<ConnectResults>> {
+    futures::stream::unfold(listener, move |mut listener| {
+        let runtime = Arc::clone(&runtime);
+        async move {
+            let accepted = accept_memory(&*runtime, &mut listener, compatibility).await;
+            Some((accepted, listener))
+        }
+    })
+    .boxed()
+}
+
+/// The state machine for one server (or the listening side of a server),
+/// along with any connection attempt in progress.
+struct ServerLink {
+    runtime: Arc<dyn AsyncRuntime>,
+    fsm: ConnectionFsm,
+    connect_future: Option<BoxFuture<'static, Result<ConnectResults>>>,
+    compatibility: CompatibilityProfile,
+    reconnect_policy: ReconnectPolicy,
+}
+
+impl ServerLink {
+    fn new_server(
+        runtime: Arc<dyn AsyncRuntime>,
+        compatibility: CompatibilityProfile,
+    ) -> ServerLink {
+        ServerLink {
+            runtime,
+            fsm: ConnectionFsm::new_server(),
+            connect_future: None,
+            compatibility,
+            reconnect_policy: ReconnectPolicy::default(),
+        }
+    }
+
+    fn new_client(
+        runtime: Arc<dyn AsyncRuntime>,
+        server: ServerInfo,
+        compatibility: CompatibilityProfile,
+        reconnect_policy: ReconnectPolicy,
+        timeouts: Timeouts,
+    ) -> ServerLink {
+        let (fsm, action) = ConnectionFsm::new_client(server);
+        let mut link = ServerLink {
+            runtime,
+            fsm,
+            connect_future: None,
+            compatibility,
+            reconnect_policy,
+        };
+        link.apply(action, timeouts, None);
+        link
+    }
+
+    /// The server this link connects to, if it is a client.
+    fn server(&self) -> Option<&ServerInfo> {
+        self.fsm.state().server()
+    }
+
+    fn is_disconnected(&self) -> bool {
+        matches!(self.fsm.state(), ConnectionState::ClientDisconnected(_))
+    }
+
+    /// Feed an event to the state machine, and carry out the resulting action.
+    fn handle(&mut self, event: ConnectionEvent, timeouts: Timeouts) -> Result<()> {
+        let action = self.fsm.handle(event)?;
+        if let (ConnectionEvent::AllEndpointsClosed, ConnectionAction::StartConnecting(_)) =
+            (event, &action)
+        {
+            if self.reconnect_policy == ReconnectPolicy::Manual {
+                // Leave it for `reconnect`, as after a failed attempt.
+                self.fsm.handle(ConnectionEvent::ConnectFailed)?;
+                return Ok(());
+            }
+        }
+        self.apply(action, timeouts, None);
+        Ok(())
+    }
+
+    fn apply(&mut self, action: ConnectionAction, timeouts: Timeouts, delay: Option<Duration>) {
+        match action {
+            ConnectionAction::None => {}
+            ConnectionAction::StartConnecting(server) => {
+                let attempt = connect_on(
+                    Arc::clone(&self.runtime),
+                    server,
+                    self.compatibility,
+                    timeouts,
+                );
+                self.connect_future = Some(match delay {
+                    Some(delay) => {
+                        let wait = self.runtime.sleep(delay);
+                        async move {
+                            wait.await;
+                            attempt.await
+                        }
+                        .boxed()
+                    }
+                    None => attempt.boxed(),
+                })
+            }
+        }
+    }
+
+    /// Poll the connection attempt in progress, if any, updating the state machine when done.
+    fn poll_connect(
+        &mut self,
+        timeouts: Timeouts,
+        cx: &mut std::task::Context<'_>,
+    ) -> Option<Result<ConnectResults>> {
+        let result = match self.connect_future.as_mut()?.as_mut().poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return None,
+        };
+        self.connect_future = None;
+        let event = match result {
+            Ok(_) => ConnectionEvent::ConnectSucceeded,
+            Err(_) => ConnectionEvent::ConnectFailed,
+        };
+        let mut handled = self.handle(event, timeouts);
+        if let (Err(_), Ok(()), ReconnectPolicy::Retry { delay }) =
+            (&result, &handled, self.reconnect_policy)
+        {
+            handled = self
+                .fsm
+                .handle(ConnectionEvent::Reconnect)
+                .map(|action| self.apply(action, timeouts, Some(delay)))
+                .map_err(VrpnError::from);
+            // Get polled again to start the wait.
+            cx.waker().wake_by_ref();
+        }
+        Some(handled.and(result))
+    }
+}
+
+/// The connection state machines, for the primary and any added servers.
+pub(crate) struct ClientState {
+    /// The server this connection was created for, or the listening side of a server.
+    primary: ServerLink,
+    /// Servers added with `ConnectionIp::add_server`.
+    added: Vec<ServerLink>,
+    /// For servers, the incoming clients that have completed the handshake.
+    incoming: Option<BoxStream<'static, Result<ConnectResults>>>,
+    /// Set by `shutdown`: do not accept or reconnect any more.
+    shut_down: bool,
+    /// The ID to give the next client accepted.
+    next_client_id: u64,
+}
+
+impl ClientState {
+    fn new_server(
+        runtime: Arc<dyn AsyncRuntime>,
+        compatibility: CompatibilityProfile,
+    ) -> ClientState {
+        ClientState {
+            primary: ServerLink::new_server(runtime, compatibility),
+            added: Vec::new(),
+            incoming: None,
+            shut_down: false,
+            next_client_id: 0,
+        }
+    }
+
+    fn new_client(
+        runtime: Arc<dyn AsyncRuntime>,
+        server: ServerInfo,
+        compatibility: CompatibilityProfile,
+        timeouts: Timeouts,
+    ) -> ClientState {
+        ClientState {
+            primary: ServerLink::new_client(
+                runtime,
+                server,
+                compatibility,
+                ReconnectPolicy::default(),
+                timeouts,
+            ),
+            added: Vec::new(),
+            incoming: None,
+            shut_down: false,
+            next_client_id: 0,
+        }
+    }
+
+    fn links_mut(&mut self) -> impl Iterator<Item = &mut ServerLink> {
+        std::iter::once(&mut self.primary).chain(self.added.iter_mut())
+    }
+
+    fn find_link(&mut self, server: &ServerInfo) -> Option<&mut ServerLink> {
+        self.links_mut().find(|link| link.server() == Some(server))
+    }
+}
+
+/// Identifies one client of a server, unique for the life of the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClientId(pub u64);
+
+/// What a server knows about one of its connected clients.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub id: ClientId,
+    /// The client's address, if it connected over plain TCP.
+    pub peer_addr: Option<SocketAddr>,
+    pub connected_at: SystemTime,
+    /// The cookie the client sent in the handshake.
+    pub cookie: Option<CookieData>,
+    pub sequence: Option<SequenceStats>,
+    /// How many messages are waiting to be written to the client.
+    pub send_backlog: usize,
+    /// How many low-latency messages were dropped because the client was not reading.
+    pub dropped_messages: usize,
+}
+
+pub struct ConnectionIp {
+    runtime: Arc<dyn AsyncRuntime>,
+    core: ConnectionCore<EndpointIp>,
+    /// For servers listening on IP, the address clients connect to.
+    listen_addr: Option<SocketAddr>,
+    client_state: Mutex<ClientState>,
+}
+
+impl ConnectionIp {
+    /// Create a server on `runtime`, whose handshakes with clients are limited by `timeouts`.
+    ///
+    /// If an address is given, clients are accepted there over TCP,
+    /// and requests to be connected back to are received on the UDP port of the same number.
+    /// Use port 0 to pick a free port, and `listen_addr` to find out which.
+    pub fn new_server_on(
+        runtime: Arc<dyn AsyncRuntime>,
+        local_log_names: Option<LogFileNames>,
+        addr: Option<SocketAddr>,
+        compatibility: CompatibilityProfile,
+        timeouts: Timeouts,
+    ) -> Result<Arc<ConnectionIp>> {
+        let mut client_state = ClientState::new_server(Arc::clone(&runtime), compatibility);
+        let listen_addr = match addr {
+            Some(addr) => {
+                let listener = runtime.tcp_listener(make_tcp_listener(addr)?)?;
+                let listen_addr = listener.local_addr()?;
+                let udp = runtime.udp_socket(make_udp_listener(listen_addr)?)?;
+                client_state.incoming = Some(incoming_tcp(
+                    Arc::clone(&runtime),
+                    listener,
+                    udp,
+                    compatibility,
+                    timeouts,
+                ));
+                Some(listen_addr)
+            }
+            None => None,
+        };
+        Ok(Arc::new(ConnectionIp {
+            runtime,
+            core: ConnectionCore::new(Vec::new(), local_log_names, None)?
+                .with_compatibility(compatibility)
+                .with_timeouts(timeouts),
+            listen_addr,
+            client_state: Mutex::new(client_state),
+        }))
+    }
+
+    /// Create a server on `runtime` accepting the clients that come out of `incoming`,
+    /// already past the handshake.
+    pub(crate) fn new_server_accepting(
+        runtime: Arc<dyn AsyncRuntime>,
+        incoming: BoxStream<'static, Result<ConnectResults>>,
+        listen_addr: Option<SocketAddr>,
+        local_log_names: Option<LogFileNames>,
+        compatibility: CompatibilityProfile,
+    ) -> Result<Arc<ConnectionIp>> {
+        let mut client_state = ClientState::new_server(Arc::clone(&runtime), compatibility);
+        client_state.incoming = Some(incoming);
+        Ok(Arc::new(ConnectionIp {
+            runtime,
+            core: ConnectionCore::new(Vec::new(), local_log_names, None)?
+                .with_compatibility(compatibility),
+            listen_addr,
+            client_state: Mutex::new(client_state),
+        }))
+    }
+
+    /// Create a server on `runtime`, accepting only clients in this process
+    /// that connect to `memory://name`.
+    ///
+    /// Fails if there is already a memory server with this name.
+    pub fn new_server_memory_on(
+        runtime: Arc<dyn AsyncRuntime>,
+        name: &str,
+        local_log_names: Option<LogFileNames>,
+        compatibility: CompatibilityProfile,
+    ) -> Result<Arc<ConnectionIp>> {
+        let incoming = incoming_memory(
+            Arc::clone(&runtime),
+            MemoryListener::bind(name)?,
+            compatibility,
+        );
+        ConnectionIp::new_server_accepting(runtime, incoming, None, local_log_names, compatibility)
+    }
+
+    /// Also accept clients in this process that connect to `memory://name`,
+    /// alongside any others this server accepts.
+    ///
+    /// Co-located clients then skip the network stack, but still see the same
+    /// handshake and messages as remote ones.
+    /// Fails if this is not a server, or there is already a memory server with this name.
+    pub fn listen_memory(&self, name: &str) -> Result<()> {
+        let mut state = self.client_state.lock();
+        if state.primary.fsm.state().server().is_some() || state.shut_down {
+            return Err(VrpnError::NotAServer);
+        }
+        let memory = incoming_memory(
+            Arc::clone(&self.runtime),
+            MemoryListener::bind(name)?,
+            state.primary.compatibility,
+        );
+        state.incoming = Some(match state.incoming.take() {
+            Some(incoming) => futures::stream::select(incoming, memory).boxed(),
+            None => memory,
+        });
+        self.core.wake_driver();
+        Ok(())
+    }
+
+    /// Create a client on `runtime`, whose first connection attempt is already limited by `timeouts`.
+    pub fn new_client_on(
+        runtime: Arc<dyn AsyncRuntime>,
+        server: ServerInfo,
+        local_log_names: Option<LogFileNames>,
+        remote_log_names: Option<LogFileNames>,
+        compatibility: CompatibilityProfile,
+        timeouts: Timeouts,
+    ) -> Result<Arc<ConnectionIp>> {
+        let endpoints: Vec<Option<EndpointIp>> = Vec::new();
+        let client_state =
+            ClientState::new_client(Arc::clone(&runtime), server, compatibility, timeouts);
+        let ret = Arc::new(ConnectionIp {
+            runtime,
+            core: ConnectionCore::new(endpoints, local_log_names, remote_log_names)?
+                .with_compatibility(compatibility)
+                .with_timeouts(timeouts),
+            listen_addr: None,
+            client_state: Mutex::new(client_state),
+        });
+        ret.send_all_descriptions()?;
+        Ok(ret)
+    }
+
+    /// Create a client on `runtime`, for a device URL like `Tracker0@localhost`.
+    ///
+    /// Registers the device name as a sender, returning its ID along with the connection.
+    pub fn for_device_on(
+        runtime: Arc<dyn AsyncRuntime>,
+        device: &str,
+    ) -> Result<(Arc<ConnectionIp>, LocalId<SenderId>)> {
+        let info: DeviceInfo = device.parse()?;
+        let sender_name = info
+            .sender_name()
+            .ok_or_else(|| VrpnError::MissingDeviceName(device.to_string()))?;
+        let conn = ConnectionIp::new_client_on(
+            runtime,
+            info.server,
+            None,
+            None,
+            CompatibilityProfile::default(),
+            Timeouts::default(),
+        )?;
+        let sender = conn.register_sender(sender_name)?;
+        Ok((conn, sender))
+    }
+
+    /// The runtime this connection's sockets and timers are on.
+    pub fn runtime(&self) -> &Arc<dyn AsyncRuntime> {
+        &self.runtime
+    }
+
+    /// Try connecting again, after a failed connection attempt.
+    ///
+    /// Retries every server whose last attempt failed, including those from `add_server`.
+    /// Only valid when the status is `ConnectionStatus::ClientDisconnected`,
+    /// or an added server is disconnected.
+    pub fn reconnect(&self) -> Result<()> {
+        let mut state = self.client_state.lock();
+        let timeouts = self.core.timeouts()?;
+        let mut reconnected = false;
+        for link in state.added.iter_mut().filter(|link| link.is_disconnected()) {
+            link.handle(ConnectionEvent::Reconnect, timeouts)?;
+            reconnected = true;
+        }
+        if !reconnected || state.primary.is_disconnected() {
+            state.primary.handle(ConnectionEvent::Reconnect, timeouts)?;
+        }
+        self.core.wake_driver();
+        Ok(())
+    }
+
+    /// Set when to connect to each server again without being asked to by `reconnect`.
+    ///
+    /// Applies to the servers from `add_server` as well, including those added later.
+    pub fn set_reconnect_policy(&self, policy: ReconnectPolicy) {
+        let mut state = self.client_state.lock();
+        for link in state.links_mut() {
+            link.reconnect_policy = policy;
+        }
+    }
+
+    /// Connect to another server as well, alongside any existing endpoints.
+    ///
+    /// Senders on an added server are known locally by their qualified name,
+    /// like `Tracker0@127.0.0.1:3883` (see `ServerInfo::qualified_sender_name`),
+    /// so that devices with the same name on different servers stay apart:
+    /// register that name to handle messages from one server's device.
+    /// The server the connection was created for keeps the plain names.
+    ///
+    /// Like the original server, an added server is reconnected and retried
+    /// according to the `ReconnectPolicy`, and by `reconnect`.
+    /// Failing to connect to an added server is logged, rather than returned from polling.
+    ///
+    /// Fails if this connection already has this server.
+    pub fn add_server(&self, server: ServerInfo) -> Result<()> {
+        let mut state = self.client_state.lock();
+        if state.find_link(&server).is_some() {
+            return Err(VrpnError::AlreadyConnected(Box::new(server)));
+        }
+        let compatibility = state.primary.compatibility;
+        let reconnect_policy = state.primary.reconnect_policy;
+        state.added.push(ServerLink::new_client(
+            Arc::clone(&self.runtime),
+            server,
+            compatibility,
+            reconnect_policy,
+            self.core.timeouts()?,
+        ));
+        self.core.wake_driver();
+        Ok(())
+    }
+
+    /// Get the status of the connection to one server,
+    /// whether the one this connection was created for or one from `add_server`.
+    ///
+    /// Returns `None` if this connection does not have this server.
+    pub fn server_status(&self, server: &ServerInfo) -> Option<ConnectionStatus> {
+        let mut state = self.client_state.lock();
+        state.find_link(server).map(|link| link.fsm.status(1))
+    }
+
+    /// Apply the connection's settings to a new endpoint, and describe our senders and types to it.
+    fn setup_endpoint(
+        &self,
+        mut endpoint: EndpointIp,
+        dispatcher: &crate::TypeDispatcher,
+    ) -> Result<EndpointIp> {
+        endpoint.set_message_history(self.core.message_history_config()?);
+        endpoint.set_coalesce_threshold(self.core.coalesce_threshold());
+        endpoint.set_max_message_size(self.core.max_message_size());
+        endpoint.set_framing_recovery(self.core.framing_recovery()?);
+        if let Err(e) = endpoint.set_socket_config(&self.core.socket_config()?) {
+            warn!("Could not set socket options: {}", e);
+        }
+        endpoint.set_poll_config(self.core.poll_config()?);
+        let timeouts = self.core.timeouts()?;
+        endpoint.set_read_idle_timeout(timeouts.read_idle);
+        endpoint.set_keepalive(timeouts.keepalive);
+        let log_names = self.core.local_log_names();
+        for (direction, name) in [
+            (Direction::Inbound, log_names.in_log()),
+            (Direction::Outbound, log_names.out_log()),
+        ] {
+            if let Some(name) = name {
+                let path = String::from_utf8_lossy(name).into_owned();
+                endpoint.set_message_log(direction, Some(LogWriter::create(path)?));
+            }
+        }
+        endpoint.send_all_descriptions(dispatcher)?;
+        endpoint.set_remote_log_policy(self.core.remote_log_policy()?);
+        endpoint.set_class_overrides(self.core.class_overrides()?);
+        endpoint.set_send_queue_limits(self.core.send_queue_limits()?);
+        endpoint.set_timestamp_policy(self.core.timestamp_policy()?);
+        let compression = self.core.compression()?;
+        if compression != Compression::Off {
+            endpoint.set_compression(compression);
+            endpoint.buffer_generic_message(
+                compression_offer(dispatcher, compression)?,
+                ClassOfService::RELIABLE,
+            )?;
+        }
+        endpoint.answer_log_request(dispatcher, None)?;
+        if endpoint.server().map(|server| server.scheme) == Some(Scheme::UdpOnly) {
+            endpoint.set_udp_only();
+            endpoint
+                .buffer_generic_message(udp_only_request(dispatcher)?, ClassOfService::RELIABLE)?;
+        }
+        Ok(endpoint)
+    }
+
+    /// The clients currently connected to this server.
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        let endpoints = self.core.endpoints.lock();
+        endpoints
+            .iter()
+            .flatten()
+            .filter_map(|ep| {
+                Some(ClientInfo {
+                    id: ep.client_id()?,
+                    peer_addr: ep.peer_addr(),
+                    connected_at: ep.connected_at(),
+                    cookie: ep.remote_cookie(),
+                    sequence: ep.sequence_stats(),
+                    send_backlog: ep.send_backlog(),
+                    dropped_messages: ep.dropped_messages(),
+                })
+            })
+            .collect()
+    }
+
+    /// Tell one client we are disconnecting, and drop it once that is sent.
+    ///
+    /// Returns false if no such client is connected.
+    pub fn disconnect_client(&self, id: ClientId) -> Result<bool> {
+        {
+            let mut endpoints = self.core.endpoints.lock();
+            let ep = match endpoints
+                .iter_mut()
+                .flatten()
+                .find(|ep| ep.client_id() == Some(id))
+            {
+                Some(ep) => ep,
+                None => return Ok(false),
+            };
+            ep.buffer_generic_message(disconnect_message(), ClassOfService::RELIABLE)?;
+            ep.close_when_sent();
+        }
+        self.core.wake_driver();
+        Ok(true)
+    }
+
+    /// The address this server accepts clients on, if it listens on IP.
+    pub fn listen_addr(&self) -> Option<SocketAddr> {
+        self.listen_addr
+    }
+
+    pub fn poll_endpoints(&self, cx: &mut std::task::Context<'_>) -> Poll<Result<Option<()>>> {
+        // Held throughout, first in the lock order.
+        let mut client_state = self.client_state.lock();
+
+        // Connect/reconnect if needed.
+        {
+            let dispatcher = self.dispatcher();
+            let dispatcher = dispatcher.read();
+            let ep_arc = self.endpoints();
+            let mut endpoints = ep_arc.lock();
+            let state = &mut *client_state;
+            let timeouts = self.core.timeouts()?;
+            for link in state.added.iter_mut() {
+                let server = match link.server() {
+                    Some(server) => server.clone(),
+                    None => continue,
+                };
+                match link.poll_connect(timeouts, cx) {
+                    Some(Ok(results)) => {
+                        info!("Connected to added server {:?}", server.socket_addr);
+                        let endpoint = self.setup_endpoint(
+                            EndpointIp::new(
+                                Arc::clone(&self.runtime),
+                                results.stream,
+                                results.udp,
+                                link.compatibility,
+                            )
+                            .with_remote_cookie(results.remote_cookie)
+                            .for_server(server, true),
+                            &dispatcher,
+                        )?;
+                        endpoints.push(Some(endpoint));
+                        let first = endpoints.iter().flatten().count() == 1;
+                        dispatcher.call_got_connection(first)?;
+                    }
+                    Some(Err(e)) => {
+                        warn!(
+                            "Failed to connect to added server {:?}: {}",
+                            server.socket_addr, e
+                        );
+                    }
+                    None => {}
+                }
+            }
+            if let Some(server) = state.primary.server().cloned() {
+                match state.primary.poll_connect(timeouts, cx) {
+                    Some(Ok(results)) => {
+                        info!("Connected to server");
+                        let mut endpoint = self.setup_endpoint(
+                            EndpointIp::new(
+                                Arc::clone(&self.runtime),
+                                results.stream,
+                                results.udp,
+                                state.primary.compatibility,
+                            )
+                            .with_remote_cookie(results.remote_cookie)
+                            .for_server(server, false),
+                            &dispatcher,
+                        )?;
+                        let remote_log_names = self.core.remote_log_names();
+                        if remote_log_names.log_mode() != LogMode::NONE {
+                            // Ask the server to log this connection for us.
+                            endpoint.buffer_message(
+                                TypedMessage::from(remote_log_names.clone()),
+                                ClassOfService::RELIABLE,
+                            )?;
+                        }
+                        endpoints.push(Some(endpoint));
+                        let first = endpoints.iter().flatten().count() == 1;
+                        dispatcher.call_got_connection(first)?;
+                    }
+                    Some(Err(e)) if state.primary.connect_future.is_some() => {
+                        warn!("Failed to connect to server, retrying: {}", e);
+                    }
+                    Some(Err(e)) => {
+                        warn!("Failed to connect to server: {}", e);
+                        return Poll::Ready(Err(e));
+                    }
+                    None => {}
+                }
+            }
+
+            if let Some(incoming) = &mut state.incoming {
+                loop {
+                    match incoming.as_mut().poll_next(cx) {
+                        Poll::Ready(Some(Ok(results))) => {
+                            let id = ClientId(state.next_client_id);
+                            state.next_client_id += 1;
+                            info!("Accepted client {:?}", id);
+                            let endpoint = self.setup_endpoint(
+                                EndpointIp::new(
+                                    Arc::clone(&self.runtime),
+                                    results.stream,
+                                    results.udp,
+                                    state.primary.compatibility,
+                                )
+                                .with_remote_cookie(results.remote_cookie)
+                                .with_client_id(id),
+                                &dispatcher,
+                            )?;
+                            endpoints.push(Some(endpoint));
+                            let first = endpoints.iter().flatten().count() == 1;
+                            dispatcher.call_got_connection(first)?;
+                        }
+                        Poll::Ready(Some(Err(e))) => {
+                            warn!("Failed to accept client: {}", e);
+                        }
+                        Poll::Ready(None) => {
+                            state.incoming = None;
+                            break;
+                        }
+                        Poll::Pending => break,
+                    }
+                }
+            }
+        }
+
+        let endpoints = self.endpoints();
+        let dispatcher = self.dispatcher();
+        {
+            let dispatcher = dispatcher.read();
+            let mut endpoints = endpoints.lock();
+            let mut got_not_ready = false;
+            let mut dropped = 0;
+            let mut dropped_servers = Vec::new();
+            // Go through and poll each endpoint, "taking" the ones that are closed.
+            for (i, ep) in endpoints.iter_mut().enumerate() {
+                enter_span!("endpoint", index = i);
+                let ready = match ep {
+                    Some(endpoint) => match endpoint.poll_endpoint(&dispatcher, cx) {
+                        Poll::Ready(Err(e)) => {
+                            warn!("Closing endpoint after an error: {}", e);
+                            true
+                        }
+                        poll => poll.is_ready(),
+                    },
+                    _ => true,
+                };
+                if ready {
+                    if let Some(endpoint) = ep.take() {
+                        dropped += 1;
+                        dropped_servers.push(endpoint.server().cloned());
+                    }
+                } else {
+                    got_not_ready = true;
+                }
+            }
+            // Now, retain only the non-taken endpoints in the vector.
+            endpoints.retain(|ep| ep.is_some());
+            // Names first seen in one endpoint's descriptions got new local IDs,
+            // which every endpoint (including that one) needs described before we use them.
+            for endpoint in endpoints.iter_mut().flatten() {
+                endpoint.send_all_descriptions(&dispatcher)?;
+            }
+            for i in 0..dropped {
+                let last = endpoints.is_empty() && i + 1 == dropped;
+                dispatcher.call_dropped_connection(last)?;
+            }
+            if dropped > 0 && !client_state.shut_down {
+                // Each server reconnects on its own when its endpoint closes.
+                let timeouts = self.core.timeouts()?;
+                for server in dropped_servers.iter().flatten() {
+                    if let Some(link) = client_state.find_link(server) {
+                        link.handle(ConnectionEvent::AllEndpointsClosed, timeouts)?;
+                    }
+                }
+                if endpoints.is_empty() && client_state.primary.server().is_none() {
+                    client_state
+                        .primary
+                        .handle(ConnectionEvent::AllEndpointsClosed, timeouts)?;
+                }
+                if client_state
+                    .links_mut()
+                    .any(|link| link.connect_future.is_some())
+                {
+                    // Get polled again to start reconnecting.
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+            }
+
+            let connecting = client_state
+                .links_mut()
+                .any(|link| link.connect_future.is_some());
+            if got_not_ready || connecting || client_state.incoming.is_some() {
+                Poll::Pending
+            } else {
+                Poll::Ready(Ok(Some(())))
+            }
+        }
+    }
+}
+
+impl PollEndpoints for ConnectionIp {
+    fn poll_endpoints(&self, cx: &mut std::task::Context<'_>) -> Poll<Result<Option<()>>> {
+        let poll = ConnectionIp::poll_endpoints(self, cx);
+        self.core.publish_status(self.status());
+        poll
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        {
+            let mut client_state = self.client_state.lock();
+            client_state.shut_down = true;
+            client_state.incoming = None;
+            for link in client_state.links_mut() {
+                link.connect_future = None;
+            }
+        }
+        self.close_endpoints()
+    }
+}
+
+impl Connection for ConnectionIp {
+    type SpecificEndpoint = EndpointIp;
+    fn connection_core(&self) -> &ConnectionCore<Self::SpecificEndpoint> {
+        &self.core
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        let state = self.client_state.lock();
+        let ep = self.endpoints();
+        let endpoints = ep.lock();
+        state.primary.fsm.status(endpoints.len())
+    }
+}
+
+pub struct ConnectionIpStream {
+    connection: Arc<ConnectionIp>,
+}
+
+impl ConnectionIpStream {
+    pub fn new(connection: Arc<ConnectionIp>) -> ConnectionIpStream {
+        ConnectionIpStream { connection }
+    }
+}
+
+impl Stream for ConnectionIpStream {
+    type Item = Result<()>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        // eprintln!("in <ConnectionIpStream as Stream>::poll");
+        self.connection.poll_endpoints(cx).map(|x| x.transpose())
+    }
+}
+
+#[cfg(all(test, feature = "async-std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        data_types::{StaticMessageTypeName, StaticSenderName, TypedMessage},
+        handler::{HandlerCode, TypedHandler},
+        message_log::RemoteLogPolicy,
+        testing::{Record, TestPair},
+        tracker::*,
+        vrpn_async_std::AsyncStd,
+        Scheme,
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    #[derive(Debug)]
+    struct TrackerHandler {
+        flag: Arc<AtomicBool>,
+    }
+    impl TrackerHandler {
+        fn new(flag: &Arc<AtomicBool>) -> Box<TrackerHandler> {
+            Box::new(TrackerHandler {
+                flag: Arc::clone(flag),
+            })
+        }
+    }
+    impl TypedHandler for TrackerHandler {
+        type Item = PoseReport;
+        fn handle_typed(&mut self, msg: &TypedMessage<PoseReport>) -> Result<HandlerCode> {
+            println!("{:?}", msg);
+            self.flag.store(true, Ordering::SeqCst);
+            Ok(HandlerCode::ContinueProcessing)
+        }
+    }
+
+    static_assertions::assert_impl_all!(ConnectionIp: Send, Sync);
+    static_assertions::assert_impl_all!(ConnectionIpStream: Send, Sync);
+    static_assertions::assert_impl_all!(EndpointIp: Send);
+    static_assertions::assert_impl_all!(crate::TypeDispatcher: Send);
+
+    /// Make a server connection with one endpoint, connected over loopback TCP
+    /// to a thread that discards everything it receives.
+    fn server_with_loopback_endpoint() -> Arc<ConnectionIp> {
+        use std::io::Read;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::net::TcpStream::connect(addr).unwrap();
+        let (server_side, _) = listener.accept().unwrap();
+        std::thread::spawn(move || {
+            let mut client = client;
+            let mut buf = [0_u8; 4096];
+            while let Ok(n) = client.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+            }
+        });
+        let conn = ConnectionIp::new_server(None, None).unwrap();
+        conn.endpoints().lock().push(Some(EndpointIp::new(
+            Arc::new(AsyncStd),
+            server_side.into(),
+            None,
+            CompatibilityProfile::default(),
+        )));
+        conn
+    }
+
+    /// Send a `Tracker0` pose for sensor 0 from `connection`.
+    fn send_pose(connection: &ConnectionIp, sender: LocalId<SenderId>) {
+        use crate::data_types::{id_types::Sensor, Quat, Vec3};
+        connection
+            .pack_message_body(
+                None,
+                sender,
+                PoseReport {
+                    sensor: Sensor(0),
+                    pos: Vec3::new(1.0, 2.0, 3.0),
+                    quat: Quat::identity(),
+                },
+                ClassOfService::RELIABLE,
+            )
+            .unwrap();
+    }
+
+    /// Connect `pair`, then send a `Tracker0` pose from `from` and poll until `to` has it.
+    fn send_pose_between(pair: &TestPair, from: &ConnectionIp, to: &ConnectionIp) {
+        let flag = Arc::new(AtomicBool::new(false));
+        let to_sender = to.register_sender(StaticSenderName(b"Tracker0")).unwrap();
+        to.add_typed_handler(TrackerHandler::new(&flag), Some(to_sender))
+            .unwrap();
+        let from_sender = from.register_sender(StaticSenderName(b"Tracker0")).unwrap();
+        pair.connect(Duration::from_secs(5)).unwrap();
+        send_pose(from, from_sender);
+        pair.pump_until(Duration::from_secs(5), || flag.load(Ordering::SeqCst))
+            .unwrap();
+    }
+
+    #[test]
+    fn concurrent_register_send_poll() {
+        use crate::data_types::ClassOfService;
+        use crate::data_types::{id_types::Sensor, Quat, SenderName, Vec3};
+        const THREADS: usize = 4;
+        const ITERATIONS: usize = 100;
+        let conn = server_with_loopback_endpoint();
+
+        let workers: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let conn = Arc::clone(&conn);
+                std::thread::spawn(move || {
+                    for i in 0..ITERATIONS {
+                        let name = format!("Tracker{}_{}", thread, i);
+                        let sender = conn.register_sender(SenderName(name.into())).unwrap();
+                        conn.pack_message_body(
+                            None,
+                            sender,
+                            PoseReport {
+                                sensor: Sensor(0),
+                                pos: Vec3::new(0.0, 0.0, 0.0),
+                                quat: Quat::identity(),
+                            },
+                            ClassOfService::RELIABLE,
+                        )
+                        .unwrap();
+                        let _ = conn.status();
+                    }
+                })
+            })
+            .collect();
+
+        let poller = {
+            let conn = Arc::clone(&conn);
+            std::thread::spawn(move || {
+                let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
+                for _ in 0..(THREADS * ITERATIONS) {
+                    let _ = conn.poll_endpoints(&mut cx);
+                }
+            })
+        };
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        poller.join().unwrap();
+
+        let dispatcher = conn.dispatcher();
+        let dispatcher = dispatcher.read();
+        for thread in 0..THREADS {
+            for i in 0..ITERATIONS {
+                let name = format!("Tracker{}_{}", thread, i);
+                assert!(dispatcher.get_sender_id(SenderName(name.into())).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn for_device() {
+        let (conn, sender) = ConnectionIp::for_device("Tracker0@tcp://127.0.0.1:3883").unwrap();
+        assert_eq!(conn.status(), ConnectionStatus::ClientConnecting);
+        assert_eq!(
+            conn.dispatcher()
+                .read()
+                .get_sender_id(StaticSenderName(b"Tracker0")),
+            Some(sender)
+        );
+
+        assert!(ConnectionIp::for_device("tcp://127.0.0.1:3883").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() {
+        let path = std::env::temp_dir().join(format!("vrpn-rs-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let pair = TestPair {
+            server: ConnectionIp::new_server_unix(&path, None, CompatibilityProfile::default())
+                .unwrap(),
+            client: ConnectionIp::new_client(ServerInfo::unix(&path), None, None).unwrap(),
+        };
+        send_pose_between(&pair, &pair.server, &pair.client);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn add_server() {
+        use std::time::Instant;
+        let paths: Vec<_> = ["a", "b"]
+            .iter()
+            .map(|n| {
+                std::env::temp_dir().join(format!("vrpn-rs-test-{}-{}.sock", std::process::id(), n))
+            })
+            .collect();
+        let servers: Vec<_> = paths
+            .iter()
+            .map(|path| {
+                let _ = std::fs::remove_file(path);
+                let server =
+                    ConnectionIp::new_server_unix(path, None, CompatibilityProfile::default())
+                        .unwrap();
+                let sender = server
+                    .register_sender(StaticSenderName(b"Tracker0"))
+                    .unwrap();
+                (server, sender)
+            })
+            .collect();
+
+        let first = ServerInfo::unix(&paths[0]);
+        let second = ServerInfo::unix(&paths[1]);
+        let client = ConnectionIp::new_client(first.clone(), None, None).unwrap();
+        client.add_server(second.clone()).unwrap();
+        assert!(client.add_server(second.clone()).is_err());
+        assert!(client.add_server(first.clone()).is_err());
+
+        let first_flag = Arc::new(AtomicBool::new(false));
+        let second_flag = Arc::new(AtomicBool::new(false));
+        let first_sender = client
+            .register_sender(StaticSenderName(b"Tracker0"))
+            .unwrap();
+        let second_sender = client
+            .register_sender(second.qualified_sender_name("Tracker0"))
+            .unwrap();
+        assert_ne!(first_sender, second_sender);
+        client
+            .add_typed_handler(TrackerHandler::new(&first_flag), Some(first_sender))
+            .unwrap();
+        client
+            .add_typed_handler(TrackerHandler::new(&second_flag), Some(second_sender))
+            .unwrap();
+
+        let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
+        let poll_all = |cx: &mut std::task::Context<'_>| {
+            let _ = client.poll_endpoints(cx);
+            for (server, _) in &servers {
+                let _ = server.poll_endpoints(cx);
+            }
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while client.server_status(&second) != Some(ConnectionStatus::ClientConnected)
+            || client.status() != ConnectionStatus::ClientConnected
+            || servers
+                .iter()
+                .any(|(server, _)| server.status() != ConnectionStatus::Server(1))
+        {
+            assert!(Instant::now() < deadline, "timed out connecting");
+            poll_all(&mut cx);
+        }
+
+        let (server, sender) = &servers[1];
+        send_pose(server, *sender);
+        while !second_flag.load(Ordering::SeqCst) {
+            assert!(Instant::now() < deadline, "timed out waiting for report");
+            poll_all(&mut cx);
+        }
+        assert!(!first_flag.load(Ordering::SeqCst));
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn tcp_server() {
+        use std::time::Instant;
+        let server = ConnectionIp::new_server(None, Some("127.0.0.1:0".parse().unwrap())).unwrap();
+        let addr = server.listen_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+        let server_sender = server
+            .register_sender(StaticSenderName(b"Tracker0"))
+            .unwrap();
+
+        // One client connects directly, the other asks to be connected to over UDP.
+        let flags = [
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        ];
+        let clients: Vec<_> = [format!("tcp://{}", addr), addr.to_string()]
+            .iter()
+            .zip(&flags)
+            .map(|(server_name, flag)| {
+                let (client, sender) =
+                    ConnectionIp::for_device(&format!("Tracker0@{}", server_name)).unwrap();
+                client
+                    .add_typed_handler(TrackerHandler::new(flag), Some(sender))
+                    .unwrap();
+                client
+            })
+            .collect();
+
+        let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while clients
+            .iter()
+            .any(|client| client.status() != ConnectionStatus::ClientConnected)
+            || server.status() != ConnectionStatus::Server(2)
+        {
+            assert!(Instant::now() < deadline, "timed out connecting");
+            let _ = server.poll_endpoints(&mut cx);
+            for client in &clients {
+                let _ = client.poll_endpoints(&mut cx);
+            }
+        }
+        send_pose(&server, server_sender);
+        while flags.iter().any(|flag| !flag.load(Ordering::SeqCst)) {
+            assert!(Instant::now() < deadline, "timed out waiting for report");
+            let _ = server.poll_endpoints(&mut cx);
+            for client in &clients {
+                let _ = client.poll_endpoints(&mut cx);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_log_names() {
+        let same = LogFileNames::from_names(Some("same.vrpn"), Some("same.vrpn"));
+        let result =
+            ConnectionIp::new_client("tcp://127.0.0.1:3883".parse().unwrap(), Some(same), None);
+        assert!(matches!(result, Err(VrpnError::InvalidLogFileName(_))));
+    }
+
+    #[test]
+    fn for_device_needs_device_name() {
+        let result = ConnectionIp::for_device("tcp://127.0.0.1:3883");
+        assert!(matches!(result, Err(VrpnError::MissingDeviceName(_))));
+    }
+
+    #[test]
+    fn local_logs() {
+        let dir = std::env::temp_dir();
+        let in_log = dir.join(format!("vrpn-test-{}-in.vrpn", std::process::id()));
+        let out_log = dir.join(format!("vrpn-test-{}-out.vrpn", std::process::id()));
+        let pair = TestPair::tcp_with_logs(
+            Some(LogFileNames::from_names(
+                None,
+                Some(out_log.to_string_lossy().into_owned()),
+            )),
+            Some(LogFileNames::from_names(
+                Some(in_log.to_string_lossy().into_owned()),
+                None,
+            )),
+            None,
+            RemoteLogPolicy::default(),
+        )
+        .unwrap();
+        send_pose_between(&pair, &pair.server, &pair.client);
+
+        // Both sides logged the report, along with the descriptions it needs.
+        for path in &[&in_log, &out_log] {
+            let data = bytes::Bytes::from(std::fs::read(path).unwrap());
+            let _ = std::fs::remove_file(path);
+            let capture = crate::capture::decode_capture(data).unwrap();
+            assert!(capture.messages.iter().any(|msg| {
+                msg.sender_name.as_deref() == Some(&b"Tracker0"[..])
+                    && msg.body.starts_with("PoseReport")
+            }));
+        }
+    }
+
+    #[test]
+    fn remote_log_request() {
+        use crate::text::TextMessage;
+        for policy in [RemoteLogPolicy::Refuse, RemoteLogPolicy::Allow] {
+            let in_log = std::env::temp_dir().join(format!(
+                "vrpn-test-{}-remote-{:?}.vrpn",
+                std::process::id(),
+                policy
+            ));
+            let pair = TestPair::tcp_with_logs(
+                None,
+                None,
+                Some(LogFileNames::from_names(
+                    Some(in_log.to_string_lossy().into_owned()),
+                    None,
+                )),
+                policy,
+            )
+            .unwrap();
+            let texts = Arc::new(std::sync::Mutex::new(Vec::<TextMessage>::new()));
+            pair.client
+                .add_typed_handler(Box::new(Record(Arc::clone(&texts))), None)
+                .unwrap();
+            send_pose_between(&pair, &pair.client, &pair.server);
+            if policy == RemoteLogPolicy::Refuse {
+                pair.pump_until(Duration::from_secs(5), || !texts.lock().unwrap().is_empty())
+                    .unwrap();
+            }
+            drop(pair);
+
+            if policy == RemoteLogPolicy::Refuse {
+                assert!(!in_log.exists());
+                let texts = texts.lock().unwrap();
+                assert!(
+                    String::from_utf8_lossy(&texts[0].text).contains("not allowed"),
+                    "{:?}",
+                    texts
+                );
+            } else {
+                assert!(texts.lock().unwrap().is_empty());
+                let data = bytes::Bytes::from(std::fs::read(&in_log).unwrap());
+                let _ = std::fs::remove_file(&in_log);
+                let capture = crate::capture::decode_capture(data).unwrap();
+                assert!(capture.messages.iter().any(|msg| {
+                    msg.sender_name.as_deref() == Some(&b"Tracker0"[..])
+                        && msg.body.starts_with("PoseReport")
+                }));
+            }
+        }
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn websocket() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let pair = TestPair {
+            server: ConnectionIp::new_server_ws(addr, None, CompatibilityProfile::default())
+                .unwrap(),
+            client: ConnectionIp::new_client(
+                format!("ws://{}/vrpn", addr).parse().unwrap(),
+                None,
+                None,
+            )
+            .unwrap(),
+        };
+        send_pose_between(&pair, &pair.server, &pair.client);
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls() {
+        use crate::{Scheme, TlsClientOptions, TlsServerOptions};
+        let dir = std::env::temp_dir().join(format!("vrpn-rs-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let pair = TestPair {
+            server: ConnectionIp::new_server_tls(
+                addr,
+                &TlsServerOptions::new(&cert_path, &key_path),
+                None,
+                CompatibilityProfile::default(),
+            )
+            .unwrap(),
+            client: ConnectionIp::new_client(
+                ServerInfo::new(addr, Scheme::TcpOnly)
+                    .with_tls(TlsClientOptions::new(&cert_path, "localhost")),
+                None,
+                None,
+            )
+            .unwrap(),
+        };
+        send_pose_between(&pair, &pair.server, &pair.client);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Connect a client to an in-process server over `scheme`,
+    /// and have the server report a pose to it.
+    fn report_pose_over(scheme: Scheme, manual: bool) -> Result<()> {
+        report_pose_with_class(scheme, manual, ClassOfService::RELIABLE)
+    }
+
+    /// Like `report_pose_over`, sending with `class`.
+    ///
+    /// Datagrams can overtake the descriptions they depend on, and be dropped,
+    /// so reports that may go over UDP are repeated until one arrives.
+    fn report_pose_with_class(scheme: Scheme, manual: bool, class: ClassOfService) -> Result<()> {
+        use crate::data_types::{id_types::Sensor, Quat, Vec3};
+        let flag = Arc::new(AtomicBool::new(false));
+        let pair = TestPair::new(scheme)?;
+        let server = TrackerServer::new(Arc::clone(&pair.server), StaticSenderName(b"Tracker0"))?;
+        let conn = &pair.client;
+        let sender = conn
+            .register_sender(StaticSenderName(b"Tracker0"))
+            .expect("should be able to register sender");
+        let handler_handle = if manual {
+            let tracker_message_id = conn
+                .register_type(StaticMessageTypeName(b"vrpn_Tracker Pos_Quat"))
+                .expect("should be able to register type");
+            conn.add_handler(
+                TrackerHandler::new(&flag),
+                Some(tracker_message_id),
+                Some(sender),
+            )?
+        } else {
+            conn.add_typed_handler(TrackerHandler::new(&flag), Some(sender))?
+        };
+        pair.connect(Duration::from_secs(5))?;
+        let report = || {
+            server.report_pose(
+                None,
+                PoseReport {
+                    sensor: Sensor(0),
+                    pos: Vec3::new(1.0, 2.0, 3.0),
+                    quat: Quat::identity(),
+                },
+                class,
+            )
+        };
+        report()?;
+        let repeat = scheme == Scheme::UdpOnly || !class.contains(ClassOfService::RELIABLE);
+        pair.pump_until(Duration::from_secs(5), || {
+            if repeat {
+                report().unwrap();
+            }
+            flag.load(Ordering::SeqCst)
+        })?;
+        conn.remove_handler(handler_handle)
+            .expect("should be able to remove handler");
+        Ok(())
+    }
+
+    #[test]
+    fn tracker_tcp() {
+        report_pose_over(Scheme::TcpOnly, false).unwrap();
+    }
+
+    #[test]
+    fn tracker() {
+        report_pose_over(Scheme::UdpAndTcp, false).unwrap();
+    }
+
+    #[test]
+    fn tracker_low_latency() {
+        report_pose_with_class(Scheme::UdpAndTcp, false, ClassOfService::LOW_LATENCY).unwrap();
+    }
+
+    #[test]
+    fn tracker_udp_only() {
+        report_pose_over(Scheme::UdpOnly, false).unwrap();
+    }
+
+    #[test]
+    fn tracker_manual() {
+        report_pose_over(Scheme::Memory, true).unwrap();
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_poses() {
+        use crate::{
+            compression::Compression,
+            data_types::{id_types::Sensor, Quat, Vec3},
+            tracker::TrackerRemote,
+        };
+        use futures::{FutureExt, StreamExt};
+
+        // Compression only on the client leaves the server's traffic plain.
+        for server_compression in [Compression::Off, Compression::Lz4] {
+            let pair = TestPair::new(Scheme::TcpOnly).unwrap();
+            pair.server.set_compression(server_compression).unwrap();
+            pair.client.set_compression(Compression::Lz4).unwrap();
+            let server =
+                TrackerServer::new(Arc::clone(&pair.server), StaticSenderName(b"Tracker0"))
+                    .unwrap();
+            let remote =
+                TrackerRemote::new(Arc::clone(&pair.client), StaticSenderName(b"Tracker0"))
+                    .unwrap();
+            let mut all = Box::pin(remote.all_sensors().unwrap());
+            pair.connect(Duration::from_secs(5)).unwrap();
+            let reports = (0..100).map(|sensor| PoseReport {
+                sensor: Sensor(sensor),
+                pos: Vec3::new(1.0, 2.0, 3.0),
+                quat: Quat::identity(),
+            });
+            server
+                .report_poses(None, reports, ClassOfService::RELIABLE)
+                .unwrap();
+            let mut sensors = Vec::new();
+            pair.pump_until(Duration::from_secs(5), || {
+                while let Some(Some((sensor, _))) = all.next().now_or_never() {
+                    sensors.push(sensor.0);
+                }
+                sensors.len() == 100
+            })
+            .unwrap();
+            assert_eq!(sensors, (0..100).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn clients() {
+        use std::time::Instant;
+
+        let server = ConnectionIp::new_server(None, Some("127.0.0.1:0".parse().unwrap())).unwrap();
+        let url = format!("tcp://{}", server.listen_addr().unwrap());
+        let first = ConnectionIp::new_client(url.parse().unwrap(), None, None).unwrap();
+        let second = ConnectionIp::new_client(url.parse().unwrap(), None, None).unwrap();
+
+        let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
+        let poll_until = |done: &dyn Fn() -> bool, cx: &mut std::task::Context<'_>| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !done() {
+                assert!(Instant::now() < deadline, "timed out");
+                let _ = server.poll_endpoints(cx);
+                let _ = first.poll_endpoints(cx);
+                let _ = second.poll_endpoints(cx);
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+        poll_until(&|| server.status() == ConnectionStatus::Server(2), &mut cx);
+
+        let clients = server.clients();
+        assert_eq!(clients.len(), 2);
+        assert_ne!(clients[0].id, clients[1].id);
+        for client in &clients {
+            assert!(client.peer_addr.is_some());
+            assert!(client.cookie.is_some());
+            assert!(client.sequence.is_some());
+        }
+        assert!(first.clients().is_empty());
+
+        assert!(server.disconnect_client(clients[0].id).unwrap());
+        poll_until(&|| server.status() == ConnectionStatus::Server(1), &mut cx);
+        let remaining = server.clients();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].id, clients[1].id);
+        assert!(!server.disconnect_client(clients[0].id).unwrap());
+    }
+
+    #[test]
+    fn memory_alongside_tcp() {
+        use std::time::Instant;
+
+        let server = ConnectionIp::new_server(None, Some("127.0.0.1:0".parse().unwrap())).unwrap();
+        server.listen_memory("alongside-tcp").unwrap();
+        assert!(server.listen_memory("alongside-tcp").is_err());
+        let url = format!("tcp://{}", server.listen_addr().unwrap());
+        let remote = ConnectionIp::new_client(url.parse().unwrap(), None, None).unwrap();
+        let local = ConnectionIp::new_client("memory://alongside-tcp".parse().unwrap(), None, None)
+            .unwrap();
+        assert!(local.listen_memory("from-client").is_err());
+
+        let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.status() != ConnectionStatus::Server(2)
+            || local.status() != ConnectionStatus::ClientConnected
+        {
+            assert!(Instant::now() < deadline, "timed out");
+            let _ = server.poll_endpoints(&mut cx);
+            let _ = remote.poll_endpoints(&mut cx);
+            let _ = local.poll_endpoints(&mut cx);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let clients = server.clients();
+        assert_eq!(clients.iter().filter(|c| c.peer_addr.is_none()).count(), 1);
+    }
+}
@@ -4,9 +4,10 @@
 
 use crate::{
     buffer_unbuffer::{BytesMutExtras, UnbufferFrom},
+    compatibility::{CompatibilityProfile, WireConfig},
     data_types::{
         constants::COOKIE_SIZE,
-        cookie::{check_ver_file_compatible, check_ver_nonfile_compatible, CookieData},
+        cookie::{check_ver_file_compatible, CookieData},
     },
     VrpnError,
 };
@@ -43,6 +44,14 @@ where
     write_cookie(stream, CookieData::make_cookie()).await
 }
 
+/// Writes the "non-file" magic cookie to the stream, with the version from `wire`.
+pub async fn send_nonfile_cookie_with<T>(stream: &mut T, wire: WireConfig) -> Result<(), VrpnError>
+where
+    T: AsyncWrite + Unpin,
+{
+    write_cookie(stream, CookieData::from(wire.cookie_version())).await
+}
+
 /// Writes the "file" magic cookie to the stream.
 pub async fn send_file_cookie<T>(stream: &mut T) -> Result<(), VrpnError>
 where
@@ -53,14 +62,44 @@ where
 
 /// Reads a cookie's worth of data from the stream, and checks to make sure it is the right version.
 pub async fn read_and_check_nonfile_cookie<T>(stream: &mut T) -> Result<(), VrpnError>
+where
+    T: AsyncRead + Unpin,
+{
+    read_and_check_nonfile_cookie_with(stream, CompatibilityProfile::default()).await?;
+    Ok(())
+}
+
+/// Reads a cookie's worth of data from the stream, and checks its version against the profile.
+///
+/// Returns the cookie, with the peer's version and log mode.
+pub async fn read_and_check_nonfile_cookie_with<T>(
+    stream: &mut T,
+    profile: CompatibilityProfile,
+) -> Result<CookieData, VrpnError>
 where
     T: AsyncRead + Unpin,
 {
     let read_buf: Vec<u8> = read_cookie(stream).await?;
     let mut buf = Bytes::from(read_buf);
     let msg = CookieData::unbuffer_from(&mut buf)?;
-    check_ver_nonfile_compatible(msg.version)?;
-    Ok(())
+    profile.check_cookie_version(msg.version)?;
+    Ok(msg)
+}
+
+/// Performs the handshake at the start of a network connection:
+/// sends our cookie, then reads the other side's and checks its version against the profile.
+///
+/// Both ends send first, so the same function serves clients and servers.
+/// Returns the other side's cookie.
+pub async fn exchange_nonfile_cookies<T>(
+    stream: &mut T,
+    profile: CompatibilityProfile,
+) -> Result<CookieData, VrpnError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    send_nonfile_cookie_with(stream, profile.wire_config()).await?;
+    read_and_check_nonfile_cookie_with(stream, profile).await
 }
 
 /// Reads a cookie's worth of data from the stream, and checks to make sure it is the right version.
@@ -81,9 +120,8 @@ mod tests {
         buffer_unbuffer::{BytesMutExtras, ConstantBufferSize},
         data_types::{constants::COOKIE_SIZE, CookieData},
     };
-    use async_std::task;
     use bytes::{Bytes, BytesMut};
-    use futures::io::Cursor;
+    use futures::{executor::block_on, io::Cursor};
 
     fn get_cookie_buf(file_cookie: bool) -> Bytes {
         assert_eq!(CookieData::constant_buffer_size(), COOKIE_SIZE);
@@ -101,14 +139,14 @@ mod tests {
         {
             let cookie = get_cookie_buf(false);
             let mut reader = Cursor::new(&cookie[..]);
-            let read_buf = task::block_on(super::read_cookie(&mut reader)).unwrap();
+            let read_buf = block_on(super::read_cookie(&mut reader)).unwrap();
             assert_eq!(CookieData::constant_buffer_size(), read_buf.len());
             assert_eq!(&cookie[..], &read_buf[..]);
         }
         {
             let cookie = get_cookie_buf(true);
             let mut reader = Cursor::new(&cookie[..]);
-            let read_buf = task::block_on(super::read_cookie(&mut reader)).unwrap();
+            let read_buf = block_on(super::read_cookie(&mut reader)).unwrap();
             assert_eq!(CookieData::constant_buffer_size(), read_buf.len());
             assert_eq!(&cookie[..], &read_buf[..]);
         }
@@ -119,13 +157,13 @@ mod tests {
         {
             let cookie = get_cookie_buf(false);
             let mut reader = Cursor::new(&cookie[..]);
-            task::block_on(super::read_and_check_nonfile_cookie(&mut reader))
+            block_on(super::read_and_check_nonfile_cookie(&mut reader))
                 .expect("checking cookie should pass");
         }
         {
             let cookie = get_cookie_buf(true);
             let mut reader = Cursor::new(&cookie[..]);
-            task::block_on(super::read_and_check_file_cookie(&mut reader))
+            block_on(super::read_and_check_file_cookie(&mut reader))
                 .expect("checking cookie should pass");
         }
     }
@@ -134,13 +172,13 @@ mod tests {
     fn write_cookie() {
         {
             let mut writer = Cursor::new(vec![0u8; COOKIE_SIZE]);
-            task::block_on(super::send_nonfile_cookie(&mut writer)).unwrap();
+            block_on(super::send_nonfile_cookie(&mut writer)).unwrap();
             let write_buf = writer.into_inner();
             assert_eq!(&get_cookie_buf(false), &write_buf);
         }
         {
             let mut writer = Cursor::new(vec![0u8; COOKIE_SIZE]);
-            task::block_on(super::send_file_cookie(&mut writer)).unwrap();
+            block_on(super::send_file_cookie(&mut writer)).unwrap();
             let write_buf = writer.into_inner();
             assert_eq!(&get_cookie_buf(true), &write_buf);
         }
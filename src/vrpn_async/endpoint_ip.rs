@@ -0,0 +1,1308 @@
+// Copyright 2018-2021, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+use super::{
+    connection_ip::ClientId,
+    runtime::{AsyncRuntime, TcpStream, UdpSocket},
+    udp_channel::UdpChannel,
+    ReliableStream,
+};
+use crate::{
+    class_policy::ClassOfServiceOverrides,
+    codec::{FramingRecovery, MessageCodec},
+    compression::Compression,
+    data_types::{
+        constants,
+        descriptions::InnerDescription,
+        id_types::{LocalId, SenderId},
+        ClassOfService, CookieData, GenericMessage, LogFileNames, LogMode, TypedMessage,
+        UdpDescription,
+    },
+    dyn_endpoint::DynEndpoint,
+    endpoint::*,
+    lifecycle::LifecycleEvent,
+    message_history::{Direction, MessageHistory, MessageHistoryConfig},
+    message_log::{FileLogWriter, LogWriter, RemoteLogPolicy},
+    net_util::SocketConfig,
+    poll_config::{poll_and_dispatch, PollConfig},
+    send_queue::SendQueueLimits,
+    sequence::SequenceStats,
+    sync::Mutex,
+    text::{TextMessage, TextSeverity, TEXT_MESSAGE},
+    timeouts::TimeoutKind,
+    timestamp_policy::{stamp_now, SendTimestamp, TimestampPolicy},
+    tracker::SensorFilter,
+    type_dispatcher::TryIntoDescriptionMessage,
+    vrpn_async::MessageStream,
+    vrpn_async::{
+        endpoint_rx::{merge_status, EndpointRx, EndpointStatus, ToEndpointStatus},
+        MessageQueue, UnboundedMessageSender,
+    },
+    CompatibilityProfile, Result, ServerInfo, TranslationTables, TypeDispatcher, VrpnError,
+};
+use bytes::{Bytes, BytesMut};
+use futures::{channel::mpsc, future::BoxFuture, ready, stream, Future, Stream};
+use std::convert::TryFrom;
+
+use std::{
+    net::SocketAddr,
+    ops::DerefMut,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Wakes the endpoint when its read idle timeout may have expired,
+/// or a keep-alive may be due.
+struct IdleTimer(BoxFuture<'static, ()>);
+
+impl std::fmt::Debug for IdleTimer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("IdleTimer")
+    }
+}
+
+#[derive(Debug)]
+pub struct EndpointIp {
+    runtime: Arc<dyn AsyncRuntime>,
+    translation: TranslationTables,
+    /// Writes queued messages on the reliable channel. Taken by `split`.
+    reliable_tx: Option<Pin<Box<UnboundedMessageSender>>>,
+    reliable_queue: MessageQueue,
+    reliable_rx: Arc<Mutex<EndpointRx<MessageStream<ReliableStream>>>>,
+    /// The socket of the reliable channel, if plain TCP, for setting options on.
+    reliable_tcp: Option<TcpStream>,
+    low_latency_channel: Option<UdpChannel>,
+    system_rx: Option<Pin<Box<mpsc::UnboundedReceiver<SystemCommand>>>>,
+    system_tx: Option<Pin<Box<mpsc::UnboundedSender<SystemCommand>>>>,
+    sensor_filter: SensorFilter,
+    history: Option<MessageHistory>,
+    in_log: Option<FileLogWriter>,
+    out_log: Option<FileLogWriter>,
+    descriptions_sent: DescriptionTracker,
+    compatibility: CompatibilityProfile,
+    poll_config: PollConfig,
+    /// The server this endpoint connected to, if we are its client.
+    server: Option<ServerInfo>,
+    /// Appended to the names of this peer's senders, to keep them apart from other servers'.
+    sender_suffix: Option<Bytes>,
+    read_idle_timeout: Option<Duration>,
+    idle_timer: Option<IdleTimer>,
+    keepalive_interval: Option<Duration>,
+    /// Fires at the end of each keep-alive interval,
+    /// with how many messages had been queued when it started.
+    keepalive_timer: Option<(IdleTimer, usize)>,
+    remote_cookie: Option<CookieData>,
+    remote_log_policy: RemoteLogPolicy,
+    class_overrides: ClassOfServiceOverrides,
+    send_queue_limits: SendQueueLimits,
+    timestamp_policy: TimestampPolicy,
+    compression: Compression,
+    /// The algorithms the peer offered to compress with.
+    peer_compression: u32,
+    /// Started when the send queue is found full, to close the endpoint if it stays that way.
+    queue_full_timer: Option<IdleTimer>,
+    /// Whether we have started logging for the peer, or refused to.
+    log_request_answered: bool,
+    /// Set for the clients of a server.
+    client_id: Option<ClientId>,
+    connected_at: SystemTime,
+}
+
+impl EndpointIp {
+    /// Create an endpoint on `runtime` that reads its peer's messages according to a compatibility profile.
+    pub(crate) fn new(
+        runtime: Arc<dyn AsyncRuntime>,
+        reliable_stream: ReliableStream,
+        udp: Option<UdpSocket>,
+        compatibility: CompatibilityProfile,
+    ) -> EndpointIp {
+        let reliable_tcp = reliable_stream.tcp_stream().cloned();
+        let reliable_tx = UnboundedMessageSender::new(reliable_stream.clone());
+        let reliable_queue = reliable_tx.queue();
+        let reliable_rx =
+            EndpointRx::from_reader(reliable_stream, MessageCodec::with_profile(compatibility));
+        let (system_tx, system_rx) = mpsc::unbounded();
+        let mut endpoint = EndpointIp {
+            runtime,
+            translation: TranslationTables::new(),
+            reliable_tx: Some(reliable_tx),
+            reliable_queue,
+            reliable_rx,
+            reliable_tcp,
+            low_latency_channel: udp
+                .map(|socket| UdpChannel::new(socket, compatibility.wire_config())),
+            system_tx: Some(Box::pin(system_tx)),
+            system_rx: Some(Box::pin(system_rx)),
+            sensor_filter: SensorFilter::new(),
+            history: None,
+            in_log: None,
+            out_log: None,
+            descriptions_sent: DescriptionTracker::new(),
+            compatibility,
+            poll_config: PollConfig::default(),
+            server: None,
+            sender_suffix: None,
+            read_idle_timeout: None,
+            idle_timer: None,
+            keepalive_interval: None,
+            keepalive_timer: None,
+            remote_cookie: None,
+            remote_log_policy: RemoteLogPolicy::default(),
+            class_overrides: ClassOfServiceOverrides::default(),
+            send_queue_limits: SendQueueLimits::default(),
+            timestamp_policy: TimestampPolicy::default(),
+            compression: Compression::default(),
+            peer_compression: 0,
+            queue_full_timer: None,
+            log_request_answered: false,
+            client_id: None,
+            connected_at: SystemTime::now(),
+        };
+        endpoint.describe_udp();
+        endpoint
+    }
+
+    /// Tell the peer where to send us low-latency messages, if we can receive them.
+    fn describe_udp(&mut self) {
+        let channel = match &self.low_latency_channel {
+            Some(channel) => channel,
+            None => return,
+        };
+        // The address the peer already reaches us at, with the port of our UDP socket.
+        let local_ip = match &self.reliable_tcp {
+            Some(tcp) => tcp.local_addr().map(|addr| addr.ip()),
+            None => channel.socket().local_addr().map(|addr| addr.ip()),
+        };
+        let description = local_ip
+            .map_err(VrpnError::from)
+            .and_then(|ip| channel.description(ip))
+            .and_then(|msg| self.reliable_queue.unbounded_send(msg));
+        if let Err(e) = description {
+            warn!("Could not describe our UDP socket, so not using it: {}", e);
+            self.low_latency_channel = None;
+        }
+    }
+
+    /// Start sending low-latency messages to where the peer says it receives them.
+    fn connect_udp(&mut self, desc: UdpDescription) {
+        let channel = match &mut self.low_latency_channel {
+            Some(channel) => channel,
+            None => {
+                debug!(
+                    "Ignoring UDP description on an endpoint without UDP: {:?}",
+                    desc
+                );
+                return;
+            }
+        };
+        // An unspecified IP means the one the peer reaches us from.
+        let mut addr = desc.socket_address;
+        if addr.ip().is_unspecified() {
+            if let Some(Ok(peer)) = self.reliable_tcp.as_ref().map(TcpStream::peer_addr) {
+                addr.set_ip(peer.ip());
+            }
+        }
+        debug!("Sending low-latency messages to {}", addr);
+        channel.set_peer(addr);
+    }
+
+    /// Dispatch what has arrived on the low-latency channel, if any.
+    fn poll_udp(&mut self, dispatcher: &TypeDispatcher, cx: &mut Context<'_>) -> EndpointStatus {
+        let mut channel = match self.low_latency_channel.take() {
+            Some(channel) => channel,
+            None => return EndpointStatus::Open,
+        };
+        channel.receive(cx, |msg| {
+            self.map_remote_message_to_local(msg.clone()).is_ok()
+        });
+        let config = self.poll_config;
+        let mut pending = stream::poll_fn(|_| Poll::Ready(channel.next_pending()));
+        // Running out of pending messages is not the end of the channel.
+        let status = match poll_and_dispatch(self, &mut pending, dispatcher, &config, cx) {
+            Poll::Ready(Err(e)) => EndpointStatus::ClosedError(e),
+            _ => EndpointStatus::Open,
+        };
+        self.low_latency_channel = Some(channel);
+        status
+    }
+
+    /// Record the server this endpoint is connected to.
+    ///
+    /// If `qualify_senders` is set, the server's senders are known locally by their
+    /// qualified name (see `ServerInfo::qualified_sender_name`),
+    /// so that same-named senders on different servers do not collide.
+    pub(crate) fn for_server(mut self, server: ServerInfo, qualify_senders: bool) -> EndpointIp {
+        if qualify_senders {
+            self.sender_suffix = Some(Bytes::from(server.sender_suffix()));
+        }
+        self.server = Some(server);
+        self
+    }
+
+    /// Record the cookie the peer sent in the handshake.
+    pub(crate) fn with_remote_cookie(mut self, cookie: CookieData) -> EndpointIp {
+        self.remote_cookie = Some(cookie);
+        self
+    }
+
+    /// Identify this endpoint as a client of our server.
+    pub(crate) fn with_client_id(mut self, id: ClientId) -> EndpointIp {
+        self.client_id = Some(id);
+        self
+    }
+
+    /// The ID of this client, if we are its server.
+    pub fn client_id(&self) -> Option<ClientId> {
+        self.client_id
+    }
+
+    /// When the handshake with the peer finished.
+    pub fn connected_at(&self) -> SystemTime {
+        self.connected_at
+    }
+
+    /// The address of the peer, if the reliable channel is plain TCP.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.reliable_tcp
+            .as_ref()
+            .and_then(|tcp| tcp.peer_addr().ok())
+    }
+
+    /// How many messages are waiting to be written on the reliable channel.
+    pub fn send_backlog(&self) -> usize {
+        self.reliable_queue.backlog()
+    }
+
+    /// How many low-latency messages were dropped because the peer was not reading.
+    pub fn dropped_messages(&self) -> usize {
+        self.reliable_queue.dropped()
+    }
+
+    /// Start logging as the peer asked, in its cookie or a log description,
+    /// or tell it why not. Each request is answered once.
+    ///
+    /// The cookie only has the log mode: the file names come in the log description.
+    pub(crate) fn answer_log_request(
+        &mut self,
+        dispatcher: &TypeDispatcher,
+        names: Option<LogFileNames>,
+    ) -> Result<()> {
+        let requested = match &names {
+            Some(names) => names.log_mode(),
+            None => self
+                .remote_cookie
+                .and_then(|cookie| cookie.log_mode)
+                .unwrap_or(LogMode::NONE),
+        };
+        if requested == LogMode::NONE || self.log_request_answered {
+            return Ok(());
+        }
+        let names = match (self.remote_log_policy, names) {
+            (RemoteLogPolicy::Refuse, _) => {
+                return self.refuse_log_request(dispatcher, "remote logging is not allowed")
+            }
+            // Wait for the log description to name the files.
+            (RemoteLogPolicy::Allow, None) => return Ok(()),
+            // Mainline VRPN takes whatever names it is sent: only check them now we act on them.
+            (RemoteLogPolicy::Allow, Some(names)) => match names.validated() {
+                Ok(names) => names,
+                Err(e) => {
+                    return self
+                        .refuse_log_request(dispatcher, &format!("invalid log file names: {}", e))
+                }
+            },
+        };
+        self.log_request_answered = true;
+        for (direction, name) in [
+            (Direction::Inbound, names.in_log()),
+            (Direction::Outbound, names.out_log()),
+        ] {
+            let name = match name {
+                Some(name) if self.message_log_mut(direction).is_none() => name,
+                _ => continue,
+            };
+            let path = String::from_utf8_lossy(name).into_owned();
+            match self.start_log(dispatcher, direction, &path) {
+                Ok(log) => {
+                    info!("Logging {:?} messages to {} for the peer", direction, path);
+                    self.set_message_log(direction, Some(log));
+                }
+                Err(e) => {
+                    self.log_request_answered = false;
+                    return self.refuse_log_request(
+                        dispatcher,
+                        &format!("could not create log file {}: {}", path, e),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a log for a connection already under way,
+    /// starting with the descriptions exchanged so far, so the log can be understood.
+    fn start_log(
+        &self,
+        dispatcher: &TypeDispatcher,
+        direction: Direction,
+        path: &str,
+    ) -> Result<FileLogWriter> {
+        let mut log = LogWriter::create(path)?;
+        let descriptions: Vec<GenericMessage> = match direction {
+            // Incoming messages are logged with the peer's IDs.
+            Direction::Inbound => {
+                let tables = self.translation_tables();
+                let senders = tables.senders().mappings().map(|mapping| {
+                    mapping
+                        .remote_id
+                        .0
+                        .try_into_description_message(mapping.name)
+                });
+                let types = tables.types().mappings().map(|mapping| {
+                    mapping
+                        .remote_id
+                        .0
+                        .try_into_description_message(mapping.name)
+                });
+                senders.chain(types).collect::<Result<_>>()?
+            }
+            Direction::Outbound => dispatcher.pack_all_descriptions()?.collect(),
+        };
+        for msg in &descriptions {
+            log.write_message(msg)?;
+        }
+        Ok(log)
+    }
+
+    /// Tell the peer, with a text message from `VRPN Control`, that we will not log for it.
+    fn refuse_log_request(&mut self, dispatcher: &TypeDispatcher, reason: &str) -> Result<()> {
+        self.log_request_answered = true;
+        warn!("Refusing the peer's request to log: {}", reason);
+        let sender = match dispatcher.get_sender_id(constants::CONTROL) {
+            Some(sender) => sender,
+            None => return Ok(()),
+        };
+        let message_type = dispatcher.register_remote_type(TEXT_MESSAGE)?.into_inner();
+        self.send_all_descriptions(dispatcher)?;
+        let text = TextMessage::new(
+            TextSeverity::Error,
+            0,
+            format!("Not logging this connection: {}", reason),
+        );
+        self.buffer_message(
+            TypedMessage::new(None, message_type.0, sender.0, text),
+            ClassOfService::RELIABLE,
+        )
+    }
+
+    /// The server this endpoint is connected to, if it was opened by a client.
+    pub fn server(&self) -> Option<&ServerInfo> {
+        self.server.as_ref()
+    }
+
+    /// Detach the writing of queued messages, so it can run as its own task.
+    ///
+    /// This endpoint remains the read half: polling it still dispatches incoming messages,
+    /// but no longer waits on writes, so sends do not wait on the poll loop and vice versa.
+    /// Returns `None` if already split.
+    pub fn split(&mut self) -> Option<EndpointWriter> {
+        self.reliable_tx.take().map(EndpointWriter)
+    }
+
+    /// Get a handle for sending on this endpoint from other tasks.
+    ///
+    /// Messages sent this way skip this endpoint's message history and log.
+    pub fn sender(&self) -> EndpointSender {
+        EndpointSender {
+            queue: self.reliable_queue.clone(),
+            sender_suffix: self.sender_suffix.clone(),
+        }
+    }
+
+    /// Give an incoming sender description the local, qualified name.
+    fn qualify_sender(&self, cmd: SystemCommand) -> SystemCommand {
+        match (cmd, &self.sender_suffix) {
+            (SystemCommand::SenderDescription(mut desc), Some(suffix)) => {
+                let mut name = BytesMut::from(&desc.name[..]);
+                name.extend_from_slice(suffix);
+                desc.name = name.freeze();
+                SystemCommand::SenderDescription(desc)
+            }
+            (cmd, _) => cmd,
+        }
+    }
+
+    /// The compatibility profile used for this endpoint's peer.
+    pub fn compatibility(&self) -> CompatibilityProfile {
+        self.compatibility
+    }
+
+    /// Check whether the peer has been silent for too long,
+    /// arranging to be woken when the limit would next be reached.
+    fn poll_read_idle(&mut self, last_received: Instant, cx: &mut Context<'_>) -> EndpointStatus {
+        let limit = match self.read_idle_timeout {
+            Some(limit) => limit,
+            None => return EndpointStatus::Open,
+        };
+        loop {
+            let idle = last_received.elapsed();
+            if idle >= limit {
+                return EndpointStatus::ClosedError(VrpnError::Timeout(TimeoutKind::ReadIdle));
+            }
+            // The timer may have been started before the latest message arrived:
+            // when it fires, check again and start a new one for the rest of the time.
+            let runtime = &self.runtime;
+            let timer = self
+                .idle_timer
+                .get_or_insert_with(|| IdleTimer(runtime.sleep(limit - idle)));
+            match timer.0.as_mut().poll(cx) {
+                Poll::Ready(()) => self.idle_timer = None,
+                Poll::Pending => return EndpointStatus::Open,
+            }
+        }
+    }
+
+    /// Check whether the peer has left the send queue full for too long,
+    /// arranging to be woken when the limit would be reached.
+    ///
+    /// The queue counts as full that long if it is full when first checked and when the timer fires.
+    fn poll_send_queue(&mut self, cx: &mut Context<'_>) -> EndpointStatus {
+        let limit = match self.send_queue_limits.disconnect_after {
+            Some(limit)
+                if self
+                    .send_queue_limits
+                    .is_full(self.reliable_queue.backlog()) =>
+            {
+                limit
+            }
+            _ => {
+                self.queue_full_timer = None;
+                return EndpointStatus::Open;
+            }
+        };
+        let runtime = &self.runtime;
+        let timer = self
+            .queue_full_timer
+            .get_or_insert_with(|| IdleTimer(runtime.sleep(limit)));
+        match timer.0.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                warn!(
+                    "Peer left {} messages unread for {:?}, closing",
+                    self.reliable_queue.backlog(),
+                    limit
+                );
+                EndpointStatus::ClosedError(VrpnError::Timeout(TimeoutKind::SendQueueFull))
+            }
+            Poll::Pending => EndpointStatus::Open,
+        }
+    }
+
+    /// Send a keep-alive if nothing else was queued during the last interval,
+    /// arranging to be woken at the end of the next one.
+    ///
+    /// The keep-alive repeats the description of our control sender.
+    fn poll_keepalive(&mut self, dispatcher: &TypeDispatcher, cx: &mut Context<'_>) -> Result<()> {
+        let interval = match self.keepalive_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+        loop {
+            let queued = self.reliable_queue.queued();
+            let runtime = &self.runtime;
+            let (timer, queued_at_start) = self
+                .keepalive_timer
+                .get_or_insert_with(|| (IdleTimer(runtime.sleep(interval)), queued));
+            if timer.0.as_mut().poll(cx).is_pending() {
+                return Ok(());
+            }
+            let idle = *queued_at_start == queued;
+            self.keepalive_timer = None;
+            if idle {
+                if let Some(id) = dispatcher.get_sender_id(constants::CONTROL) {
+                    trace!("Sending keep-alive");
+                    self.buffer_generic_message(
+                        id.try_into_description_message(constants::CONTROL.0)?,
+                        ClassOfService::RELIABLE,
+                    )?;
+                }
+            }
+        }
+    }
+
+    fn poll_system_rx(
+        &mut self,
+        dispatcher: &TypeDispatcher,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<EndpointStatus>> {
+        match self.system_rx.as_mut() {
+            Some(rx) => match ready!(rx.as_mut().poll_next(cx)) {
+                None => Poll::Ready(Ok(EndpointStatus::Closed)),
+                Some(cmd) => {
+                    let cmd = self.qualify_sender(cmd);
+                    if let Some(cmd) =
+                        handle_system_command(dispatcher, self.translation_tables_mut(), cmd)?
+                    {
+                        match cmd {
+                            ExtendedSystemCommand::UdpDescription(desc) => self.connect_udp(desc),
+                            ExtendedSystemCommand::LogDescription(desc) => {
+                                debug!("LogDescription: {:?}", desc);
+                                self.answer_log_request(dispatcher, Some(desc))?;
+                            }
+                            ExtendedSystemCommand::DisconnectMessage => {
+                                debug!("DisconnectMessage");
+                            }
+                        }
+                    }
+                    Poll::Ready(Ok(EndpointStatus::Open))
+                }
+            },
+            None => Poll::Ready(Ok(EndpointStatus::Closed)),
+        }
+    }
+
+    pub(crate) fn poll_endpoint(
+        &mut self,
+        dispatcher: &TypeDispatcher,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        let channel_rx_arc = Arc::clone(&self.reliable_rx);
+        let mut channel_rx = channel_rx_arc.lock();
+        // The peer compresses once both sides have offered to.
+        channel_rx.set_compressed_frames(self.compression.agreed_with(self.peer_compression));
+
+        let config = self.poll_config;
+        let mut endpoint_status =
+            poll_and_dispatch(self, channel_rx.deref_mut(), dispatcher, &config, cx)
+                .to_endpoint_status();
+        endpoint_status = merge_status(
+            endpoint_status,
+            self.poll_read_idle(channel_rx.last_received(), cx),
+        );
+        if let Err(e) = self.poll_keepalive(dispatcher, cx) {
+            endpoint_status = merge_status(endpoint_status, EndpointStatus::ClosedError(e));
+        }
+        endpoint_status = merge_status(endpoint_status, self.poll_send_queue(cx));
+        for gap in channel_rx.take_gaps() {
+            debug!("Sequence gap: {:?}", gap);
+            if let Err(e) = dispatcher.call_sequence_gap(gap) {
+                warn!("Error dispatching sequence gap: {}", e);
+            }
+        }
+        for error in channel_rx.take_framing_errors() {
+            dispatcher.emit_event(LifecycleEvent::FramingError(error));
+        }
+
+        if let Some(reliable_tx) = self.reliable_tx.as_mut() {
+            match reliable_tx.as_mut().poll(cx) {
+                Poll::Ready(Ok(())) => {
+                    info!("Remote end of reliable connection has shut down.");
+                    endpoint_status = merge_status(endpoint_status, EndpointStatus::Closed);
+                }
+                Poll::Ready(Err(e)) => endpoint_status = EndpointStatus::ClosedError(e),
+                Poll::Pending => {}
+            }
+        } else if self.reliable_queue.is_closed() {
+            // Split off, and the writer is done with us.
+            endpoint_status = merge_status(endpoint_status, EndpointStatus::Closed);
+        }
+        endpoint_status = merge_status(endpoint_status, self.poll_udp(dispatcher, cx));
+
+        // Now, process the messages we sent ourself.
+        loop {
+            match self.poll_system_rx(dispatcher, cx) {
+                Poll::Ready(Ok(new_status)) => {
+                    endpoint_status = merge_status(endpoint_status, new_status)
+                }
+                Poll::Ready(Err(e)) => {
+                    // e.g. a peer describing more names than the dispatcher's limits allow.
+                    endpoint_status = merge_status(endpoint_status, EndpointStatus::ClosedError(e));
+                    break;
+                }
+                Poll::Pending => break,
+            }
+        }
+        if endpoint_status.is_closed() {
+            if let Some(history) = &self.history {
+                let reason = match &endpoint_status {
+                    EndpointStatus::ClosedError(e) => format!("endpoint error: {}", e),
+                    _ => "endpoint closed".to_string(),
+                };
+                if let Err(e) = history.dump(&reason) {
+                    warn!("Could not dump message history: {}", e);
+                }
+            }
+            self.reliable_queue.close();
+        }
+        for log in self.in_log.iter_mut().chain(self.out_log.iter_mut()) {
+            if let Err(e) = log.flush() {
+                warn!("Could not flush message log: {}", e);
+            }
+        }
+
+        endpoint_status.into()
+    }
+
+    /// Record, log, and stamp an outgoing message, then send it right away if it goes over UDP.
+    ///
+    /// Otherwise returns it for the reliable queue, with whether it may be dropped.
+    fn prepare_outgoing(
+        &mut self,
+        msg: GenericMessage,
+        class: ClassOfService,
+    ) -> Result<Option<(GenericMessage, bool)>> {
+        let mut msg = unqualify_sender(self.sender_suffix.as_ref(), msg)?;
+        if self.timestamp_policy.send == SendTimestamp::Buffered {
+            stamp_now(&mut msg);
+        }
+        if let Some(history) = &mut self.history {
+            history.record(Direction::Outbound, &msg);
+        }
+        if let Some(log) = &mut self.out_log {
+            if let Err(e) = log.write_message(&msg) {
+                warn!("Could not log outgoing message: {}", e);
+            }
+        }
+        let class = self
+            .class_overrides
+            .class_for(LocalId(msg.header.message_type), class);
+        match &mut self.low_latency_channel {
+            Some(channel)
+                if !self.class_overrides.is_all_reliable()
+                    && channel.carries(&msg, class.contains(ClassOfService::RELIABLE)) =>
+            {
+                // Written out right away.
+                if self.timestamp_policy.send == SendTimestamp::Written {
+                    stamp_now(&mut msg);
+                }
+                channel.send(msg).map(|()| None)
+            }
+            _ => {
+                let droppable = self.send_queue_limits.drop_low_latency
+                    && !class.contains(ClassOfService::RELIABLE);
+                Ok(Some((msg, droppable)))
+            }
+        }
+    }
+}
+
+/// Describe our own senders to the peer by their unqualified name.
+fn unqualify_sender(suffix: Option<&Bytes>, msg: GenericMessage) -> Result<GenericMessage> {
+    let suffix = match suffix {
+        Some(suffix) if msg.header.message_type == constants::SENDER_DESCRIPTION => suffix,
+        _ => return Ok(msg),
+    };
+    let desc = TypedMessage::<InnerDescription<SenderId>>::try_from(&msg)?;
+    match desc.body.name.strip_suffix(&suffix[..]) {
+        Some(name) => {
+            LocalId(msg.header.sender).try_into_description_message(Bytes::copy_from_slice(name))
+        }
+        None => Ok(msg),
+    }
+}
+
+/// The write half of a split `EndpointIp`: a future that writes queued messages
+/// until the endpoint closes, to be spawned as its own task.
+#[derive(Debug)]
+pub struct EndpointWriter(Pin<Box<UnboundedMessageSender>>);
+
+impl Future for EndpointWriter {
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+/// A cloneable handle for sending messages reliably on an `EndpointIp`, from any task.
+#[derive(Debug, Clone)]
+pub struct EndpointSender {
+    queue: MessageQueue,
+    sender_suffix: Option<Bytes>,
+}
+
+impl EndpointSender {
+    /// Queue a message to be sent on the reliable channel.
+    ///
+    /// Fails once the endpoint has closed.
+    pub fn send(&self, msg: GenericMessage) -> Result<()> {
+        self.queue
+            .unbounded_send(unqualify_sender(self.sender_suffix.as_ref(), msg)?)
+    }
+
+    /// Stop sending: the writer finishes once what is already queued is written.
+    pub fn close(&self) {
+        self.queue.close();
+    }
+}
+
+impl DynEndpoint for EndpointIp {
+    fn poll_endpoint(
+        &mut self,
+        dispatcher: &TypeDispatcher,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        EndpointIp::poll_endpoint(self, dispatcher, cx)
+    }
+}
+
+impl Endpoint for EndpointIp {
+    fn translation_tables(&self) -> &TranslationTables {
+        &self.translation
+    }
+
+    fn translation_tables_mut(&mut self) -> &mut TranslationTables {
+        &mut self.translation
+    }
+
+    fn sensor_filter(&self) -> Option<&SensorFilter> {
+        Some(&self.sensor_filter)
+    }
+
+    fn sensor_filter_mut(&mut self) -> Option<&mut SensorFilter> {
+        Some(&mut self.sensor_filter)
+    }
+
+    fn set_poll_config(&mut self, config: PollConfig) {
+        self.poll_config = config;
+    }
+
+    fn set_message_log(&mut self, direction: Direction, log: Option<FileLogWriter>) {
+        match direction {
+            Direction::Inbound => self.in_log = log,
+            Direction::Outbound => self.out_log = log,
+        }
+    }
+
+    fn message_log_mut(&mut self, direction: Direction) -> Option<&mut FileLogWriter> {
+        match direction {
+            Direction::Inbound => self.in_log.as_mut(),
+            Direction::Outbound => self.out_log.as_mut(),
+        }
+    }
+
+    fn set_coalesce_threshold(&mut self, threshold: usize) {
+        self.reliable_queue.set_coalesce_threshold(threshold);
+    }
+
+    fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.reliable_rx
+            .lock()
+            .set_max_message_size(max_message_size);
+    }
+
+    fn set_socket_config(&mut self, config: &SocketConfig) -> Result<()> {
+        if let Some(tcp) = &self.reliable_tcp {
+            config.apply_to_tcp(tcp.socket())?;
+        }
+        if let Some(channel) = &self.low_latency_channel {
+            config.apply_to_udp(channel.socket().socket())?;
+        }
+        Ok(())
+    }
+
+    fn set_framing_recovery(&mut self, recovery: FramingRecovery) {
+        self.reliable_rx.lock().set_framing_recovery(recovery);
+    }
+
+    fn sequence_stats(&self) -> Option<SequenceStats> {
+        Some(self.reliable_rx.lock().sequence_stats())
+    }
+
+    fn set_read_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_idle_timeout = timeout;
+        self.idle_timer = None;
+    }
+
+    fn set_keepalive(&mut self, interval: Option<Duration>) {
+        self.keepalive_interval = interval;
+        self.keepalive_timer = None;
+    }
+
+    fn set_udp_only(&mut self) {
+        if let Some(channel) = &mut self.low_latency_channel {
+            channel.set_exclusive();
+        }
+    }
+
+    fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+        self.reliable_queue
+            .set_compress(compression.agreed_with(self.peer_compression));
+    }
+
+    fn set_peer_compression(&mut self, algorithms: u32) {
+        self.peer_compression = algorithms;
+        self.reliable_queue
+            .set_compress(self.compression.agreed_with(algorithms));
+    }
+
+    fn remote_cookie(&self) -> Option<CookieData> {
+        self.remote_cookie
+    }
+
+    fn set_remote_log_policy(&mut self, policy: RemoteLogPolicy) {
+        self.remote_log_policy = policy;
+    }
+
+    fn set_class_overrides(&mut self, overrides: ClassOfServiceOverrides) {
+        self.class_overrides = overrides;
+    }
+
+    fn set_send_queue_limits(&mut self, limits: SendQueueLimits) {
+        self.send_queue_limits = limits;
+        self.reliable_queue.set_drop_threshold(match limits {
+            SendQueueLimits {
+                max_queued: Some(max),
+                drop_low_latency: true,
+                ..
+            } => Some(max),
+            _ => None,
+        });
+        self.queue_full_timer = None;
+    }
+
+    fn set_timestamp_policy(&mut self, policy: TimestampPolicy) {
+        self.timestamp_policy = policy;
+        self.reliable_queue
+            .set_stamp_on_write(policy.send == SendTimestamp::Written);
+    }
+
+    fn timestamp_policy(&self) -> TimestampPolicy {
+        self.timestamp_policy
+    }
+
+    fn close_when_sent(&mut self) {
+        self.reliable_queue.close();
+    }
+
+    fn poll_flushed(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.reliable_queue.poll_flushed(cx)
+    }
+
+    fn send_system_change(&self, message: SystemCommand) -> Result<()> {
+        trace!("send_system_change {:?}", message);
+        if let Some(tx) = self.system_tx.clone().as_deref_mut() {
+            tx.unbounded_send(message)?;
+        }
+        Ok(())
+    }
+
+    fn set_message_history(&mut self, config: Option<MessageHistoryConfig>) {
+        self.history = config.map(MessageHistory::new);
+    }
+
+    fn message_history_mut(&mut self) -> Option<&mut MessageHistory> {
+        self.history.as_mut()
+    }
+
+    fn buffer_generic_message(&mut self, msg: GenericMessage, class: ClassOfService) -> Result<()> {
+        match self.prepare_outgoing(msg, class)? {
+            Some((msg, true)) => self.reliable_queue.send_droppable(msg),
+            Some((msg, false)) => self.reliable_queue.unbounded_send(msg),
+            None => Ok(()),
+        }
+    }
+
+    fn buffer_generic_batch(
+        &mut self,
+        msgs: Vec<GenericMessage>,
+        class: ClassOfService,
+    ) -> Result<()> {
+        let mut batch = Vec::with_capacity(msgs.len());
+        let mut droppable = true;
+        for msg in msgs {
+            if let Some((msg, may_drop)) = self.prepare_outgoing(msg, class)? {
+                batch.push(msg);
+                droppable &= may_drop;
+            }
+        }
+        self.reliable_queue.send_batch(batch, droppable)
+    }
+
+    fn description_tracker_mut(&mut self) -> Option<&mut DescriptionTracker> {
+        Some(&mut self.descriptions_sent)
+    }
+}
+
+#[cfg(all(test, feature = "async-std"))]
+mod tests {
+    use super::*;
+    use crate::{
+        data_types::{GenericBody, Message, MessageHeader, MessageTypeId},
+        vrpn_async::{connection_ip::ConnectionIp, cookie},
+        vrpn_async_std::AsyncStd,
+        VrpnError,
+    };
+    use async_std::net::{TcpStream, UdpSocket};
+    use futures::StreamExt;
+
+    /// Start a server on localhost, driven in the background, and connect to it.
+    async fn connect_and_handshake() -> crate::Result<(TcpStream, Arc<ConnectionIp>)> {
+        let server = ConnectionIp::new_server(None, Some("127.0.0.1:0".parse().unwrap()))?;
+        let (handle, driver) = crate::driver::split(Arc::clone(&server));
+        async_std::task::spawn(async move {
+            let _handle = handle;
+            driver.await
+        });
+        let mut stream = TcpStream::connect(server.listen_addr().unwrap()).await?;
+        stream.set_nodelay(true)?;
+
+        // We first write our cookie, then read and check the server's cookie, before the loop.
+        cookie::send_nonfile_cookie(&mut stream).await?;
+        cookie::read_and_check_nonfile_cookie(&mut stream).await?;
+        Ok((stream, server))
+    }
+
+    /// An endpoint over loopback TCP, with no UDP, and the socket at the other end.
+    async fn endpoint_and_peer() -> Result<(EndpointIp, TcpStream)> {
+        endpoint_with_udp_and_peer(None).await
+    }
+
+    /// An endpoint over loopback TCP, with `udp` if given, and the socket at the other end.
+    async fn endpoint_with_udp_and_peer(udp: Option<UdpSocket>) -> Result<(EndpointIp, TcpStream)> {
+        let listener = async_std::net::TcpListener::bind("127.0.0.1:0").await?;
+        let client = TcpStream::connect(listener.local_addr()?).await?;
+        let (peer, _) = listener.accept().await?;
+        let ep = EndpointIp::new(
+            Arc::new(AsyncStd),
+            client.into(),
+            udp.map(Into::into),
+            CompatibilityProfile::default(),
+        );
+        Ok((ep, peer))
+    }
+
+    /// A message of type 0 from `sender`, with a four-byte body.
+    fn message(sender: i32) -> GenericMessage {
+        GenericMessage::from_header_and_body(
+            MessageHeader::new(None, MessageTypeId(0), SenderId(sender)),
+            GenericBody::new(Bytes::from_static(b"abcd")),
+        )
+    }
+
+    #[test]
+    fn split_writer_runs_alone() {
+        use crate::{codec::maybe_decode_one, data_types::id_types::SequenceNumber};
+        use async_std::io::ReadExt;
+        let result: Result<()> = async_std::task::block_on(async {
+            let (mut ep, mut peer) = endpoint_and_peer().await?;
+            let writer = ep.split().unwrap();
+            assert!(ep.split().is_none());
+            let writing = async_std::task::spawn(writer);
+
+            // Nothing polls the endpoint itself.
+            let sender = ep.sender();
+            sender.send(message(0))?;
+            sender.close();
+            writing.await?;
+            assert!(sender.send(message(0)).is_err());
+
+            let mut buf = vec![0u8; 32];
+            peer.read_exact(&mut buf).await?;
+            let msg = maybe_decode_one(&mut Bytes::from(buf))?.unwrap();
+            assert_eq!(msg.sequence_number, SequenceNumber(1));
+            assert_eq!(&msg.into_inner().body.into_inner()[..], b"abcd");
+            Ok(())
+        });
+        result.unwrap();
+    }
+
+    #[test]
+    fn batch_sequence_numbers() {
+        use crate::{codec::maybe_decode_one, data_types::id_types::SequenceNumber};
+        use async_std::io::ReadExt;
+        let result: Result<()> = async_std::task::block_on(async {
+            let (mut ep, mut peer) = endpoint_and_peer().await?;
+            let writing = async_std::task::spawn(ep.split().unwrap());
+            ep.buffer_generic_message(message(0), ClassOfService::RELIABLE)?;
+            ep.buffer_generic_batch(
+                vec![message(1), message(2), message(3)],
+                ClassOfService::RELIABLE,
+            )?;
+            ep.buffer_generic_message(message(4), ClassOfService::RELIABLE)?;
+            ep.close_when_sent();
+            writing.await?;
+
+            let mut buf = vec![0u8; 5 * 32];
+            peer.read_exact(&mut buf).await?;
+            let mut buf = Bytes::from(buf);
+            for i in 0..5 {
+                let msg = maybe_decode_one(&mut buf)?.unwrap();
+                assert_eq!(msg.sequence_number, SequenceNumber(i + 1));
+                assert_eq!(msg.into_inner().header.sender, SenderId(i as i32));
+            }
+            Ok(())
+        });
+        result.unwrap();
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_once_agreed() {
+        use crate::{codec::MessageCodec, data_types::id_types::SequenceNumber};
+        use async_std::io::ReadExt;
+        use bytes::BytesMut;
+        let result: Result<()> = async_std::task::block_on(async {
+            let (mut ep, mut peer) = endpoint_and_peer().await?;
+            let writing = async_std::task::spawn(ep.split().unwrap());
+            let msg = message(0);
+            ep.set_compression(Compression::Lz4);
+            ep.set_peer_compression(0);
+            ep.buffer_generic_batch(vec![msg.clone(); 20], ClassOfService::RELIABLE)?;
+            futures::future::poll_fn(|cx| ep.poll_flushed(cx)).await;
+            ep.set_peer_compression(1);
+            ep.buffer_generic_batch(vec![msg; 20], ClassOfService::RELIABLE)?;
+            ep.close_when_sent();
+            writing.await?;
+            drop(ep);
+
+            let mut buf = Vec::new();
+            peer.read_to_end(&mut buf).await?;
+            // The first batch is plain, the second much smaller.
+            assert!(buf.len() < 20 * 32 + 10 * 32, "{} bytes", buf.len());
+            let mut buf = BytesMut::from(&buf[..]);
+            let mut codec = MessageCodec::new().with_compressed_frames(true);
+            for i in 0..40 {
+                let msg = codec.decode_from(&mut buf)?.unwrap();
+                assert_eq!(msg.sequence_number, SequenceNumber(i + 1));
+            }
+            assert!(buf.is_empty());
+            Ok(())
+        });
+        result.unwrap();
+    }
+
+    #[test]
+    fn peer_exceeds_sender_limit() {
+        use crate::{
+            codec::MessageCodec, data_types::id_types::SequenceNumber, limits::LimitKind,
+            type_dispatcher::TryIntoDescriptionMessage, DispatcherLimits,
+        };
+        use async_std::io::WriteExt;
+        use bytes::BytesMut;
+        let result: Result<()> = async_std::task::block_on(async {
+            let (mut ep, mut peer) = endpoint_and_peer().await?;
+            let mut dispatcher = TypeDispatcher::new();
+            // The control sender, and one more.
+            dispatcher.set_limits(DispatcherLimits {
+                max_senders: 2,
+                ..DispatcherLimits::default()
+            });
+
+            let mut codec = MessageCodec::new();
+            let mut buf = BytesMut::new();
+            for (i, name) in ["Tracker0", "Tracker1"].iter().enumerate() {
+                let msg = SenderId(i as i32).try_into_description_message(*name)?;
+                codec.encode_into(
+                    msg.into_sequenced_message(SequenceNumber(i as u32 + 1)),
+                    &mut buf,
+                )?;
+            }
+            peer.write_all(&buf).await?;
+
+            let closed = async_std::future::timeout(
+                Duration::from_secs(10),
+                futures::future::poll_fn(|cx| ep.poll_endpoint(&dispatcher, cx)),
+            )
+            .await
+            .expect("timed out waiting for the endpoint to close");
+            assert!(matches!(
+                closed,
+                Err(VrpnError::LimitReached { kind: LimitKind::Senders, limit: 2, name })
+                    if name == "Tracker1"
+            ));
+            Ok(())
+        });
+        result.unwrap();
+    }
+
+    #[test]
+    fn keepalive_when_idle() {
+        use crate::{codec::maybe_decode_one, timeouts::Timeouts, Connection};
+        use async_std::io::{timeout, ReadExt};
+        let result: Result<()> = async_std::task::block_on(async {
+            let server = ConnectionIp::new_server(None, Some("127.0.0.1:0".parse().unwrap()))?;
+            server.set_timeouts(Timeouts {
+                keepalive: Some(Duration::from_millis(50)),
+                ..Timeouts::default()
+            })?;
+            let (handle, driver) = crate::driver::split(Arc::clone(&server));
+            async_std::task::spawn(async move {
+                let _handle = handle;
+                driver.await
+            });
+            let mut stream = TcpStream::connect(server.listen_addr().unwrap()).await?;
+            cookie::send_nonfile_cookie(&mut stream).await?;
+            cookie::read_and_check_nonfile_cookie(&mut stream).await?;
+
+            // After the initial descriptions, the control sender is described again and again.
+            let mut received = BytesMut::new();
+            let mut descriptions = 0;
+            while descriptions < 3 {
+                let mut buf = [0u8; 256];
+                let n = timeout(Duration::from_secs(5), stream.read(&mut buf)).await?;
+                assert_ne!(n, 0);
+                received.extend_from_slice(&buf[..n]);
+                let mut bytes = received.split().freeze();
+                while let Some(msg) = maybe_decode_one(&mut bytes)? {
+                    if msg.into_inner().header.message_type == constants::SENDER_DESCRIPTION {
+                        descriptions += 1;
+                    }
+                }
+                received.extend_from_slice(&bytes);
+            }
+            Ok(())
+        });
+        result.unwrap();
+    }
+
+    #[test]
+    fn class_overrides_pick_channel() {
+        use crate::{class_policy::ClassOfServicePolicy, data_types::StaticMessageTypeName};
+        let result: Result<()> = async_std::task::block_on(async {
+            let udp = UdpSocket::bind("127.0.0.1:0").await?;
+            let peer_udp = UdpSocket::bind("127.0.0.1:0").await?;
+            let (mut ep, _peer) = endpoint_with_udp_and_peer(Some(udp)).await?;
+            ep.connect_udp(UdpDescription::new(peer_udp.local_addr()?));
+
+            let mut dispatcher = TypeDispatcher::new();
+            const POSE: StaticMessageTypeName = StaticMessageTypeName(b"vrpn_Tracker Pos_Quat");
+            let pose = dispatcher.register_type(POSE)?.into_inner();
+            let send = |ep: &mut EndpointIp, message_type: MessageTypeId, class| {
+                let before = ep.reliable_queue.queued();
+                ep.buffer_generic_message(
+                    GenericMessage::from_header_and_body(
+                        MessageHeader::new(None, message_type, SenderId(0)),
+                        GenericBody::new(Bytes::from_static(b"abcd")),
+                    ),
+                    class,
+                )
+                .unwrap();
+                ep.reliable_queue.queued() > before
+            };
+            assert!(!send(&mut ep, pose.0, ClassOfService::LOW_LATENCY));
+            assert!(send(&mut ep, pose.0, ClassOfService::RELIABLE));
+
+            ep.set_class_overrides(
+                ClassOfServicePolicy::default()
+                    .with_type(POSE, ClassOfService::LOW_LATENCY)
+                    .resolve(&mut dispatcher)?,
+            );
+            assert!(!send(&mut ep, pose.0, ClassOfService::RELIABLE));
+            assert!(send(
+                &mut ep,
+                MessageTypeId(pose.0 .0 + 1),
+                ClassOfService::RELIABLE
+            ));
+
+            ep.set_class_overrides(
+                ClassOfServicePolicy::default()
+                    .all_reliable()
+                    .resolve(&mut dispatcher)?,
+            );
+            assert!(send(&mut ep, pose.0, ClassOfService::LOW_LATENCY));
+            Ok(())
+        });
+        result.unwrap();
+    }
+
+    #[test]
+    fn make_endpoint() {
+        let result: Result<EndpointIp> = async_std::task::block_on(async {
+            let (tcp, _server) = connect_and_handshake().await?;
+            Ok(EndpointIp::new(
+                Arc::new(AsyncStd),
+                tcp.into(),
+                None,
+                CompatibilityProfile::default(),
+            ))
+        });
+        result.unwrap();
+    }
+
+    #[test]
+    fn run_endpoint() {
+        use crate::{
+            data_types::{id_types::Sensor, ClassOfService, Quat, StaticSenderName, Vec3},
+            tracker::{PoseReport, TrackerServer},
+            Connection, ConnectionStatus,
+        };
+        let result: Result<()> = async_std::task::block_on(async {
+            let (tcp, server) = connect_and_handshake().await?;
+
+            let ep = EndpointIp::new(
+                Arc::new(AsyncStd),
+                tcp.into(),
+                None,
+                CompatibilityProfile::default(),
+            );
+            while server.status() != ConnectionStatus::Server(1) {
+                async_std::task::sleep(std::time::Duration::from_millis(1)).await;
+            }
+            let tracker = TrackerServer::new(Arc::clone(&server), StaticSenderName(b"Tracker0"))?;
+            for _i in 0..4 {
+                tracker.report_pose(
+                    None,
+                    PoseReport {
+                        sensor: Sensor(0),
+                        pos: Vec3::new(0.0, 0.0, 0.0),
+                        quat: Quat::identity(),
+                    },
+                    ClassOfService::RELIABLE,
+                )?;
+            }
+            let rx = Arc::clone(&ep.reliable_rx);
+            for _i in 0..4 {
+                let msg = futures::future::poll_fn(|cx| rx.lock().poll_next_unpin(cx))
+                    .await
+                    .ok_or(VrpnError::GenericErrorReturn)?;
+                trace!("Received message {:?}", msg);
+            }
+            Ok(())
+        });
+        result.unwrap();
+    }
+
+    #[test]
+    fn full_send_queue_disconnects() {
+        use crate::{send_queue::SendQueueLimits, timeouts::TimeoutKind};
+        let result: Result<()> = async_std::task::block_on(async {
+            // The peer is never read from.
+            let (mut ep, _peer) = endpoint_and_peer().await?;
+            ep.set_send_queue_limits(SendQueueLimits {
+                max_queued: Some(4),
+                drop_low_latency: false,
+                disconnect_after: Some(Duration::from_millis(100)),
+            });
+            let body = Bytes::from(vec![0u8; 1 << 20]);
+            for _ in 0..64 {
+                ep.buffer_generic_message(
+                    GenericMessage::from_header_and_body(
+                        MessageHeader::new(None, MessageTypeId(0), SenderId(0)),
+                        GenericBody::new(body.clone()),
+                    ),
+                    ClassOfService::RELIABLE,
+                )?;
+            }
+            let dispatcher = TypeDispatcher::new();
+            let closed = async_std::future::timeout(
+                Duration::from_secs(10),
+                futures::future::poll_fn(|cx| ep.poll_endpoint(&dispatcher, cx)),
+            )
+            .await
+            .expect("timed out waiting for the endpoint to close");
+            assert!(matches!(
+                closed,
+                Err(VrpnError::Timeout(TimeoutKind::SendQueueFull))
+            ));
+            assert!(ep.send_backlog() >= 4);
+            Ok(())
+        });
+        result.unwrap();
+    }
+}
@@ -3,33 +3,81 @@
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
 use crate::{
-    data_types::{GenericMessage, Message, SequencedGenericMessage},
-    endpoint::*,
-    vrpn_async::{AsyncReadMessagesExt, MessageStream},
-    Result, TypeDispatcher, VrpnError,
+    codec::{FramingError, FramingRecovery, MessageCodec},
+    data_types::{GenericMessage, SequencedGenericMessage},
+    sequence::{SequenceGap, SequenceStats, SequenceTracker},
+    sync::Mutex,
+    vrpn_async::MessageStream,
+    Result, VrpnError,
 };
 
-use futures::{ready, AsyncRead, Stream, StreamExt};
+use futures::{ready, AsyncRead, Stream};
 use std::{
     fmt::Debug,
     pin::Pin,
-    sync::{Arc, Mutex},
+    sync::Arc,
     task::{Context, Poll},
+    time::Instant,
 };
 
 #[derive(Debug)]
 pub(crate) struct EndpointRx<T> {
     stream: Pin<Box<T>>,
     error: Option<VrpnError>,
+    last_received: Instant,
+    sequence: SequenceTracker,
+    /// Gaps detected, not yet dispatched.
+    gaps: Vec<SequenceGap>,
+}
+
+impl<T> EndpointRx<T> {
+    /// When a message was last received, or when this was created if none have been.
+    pub(crate) fn last_received(&self) -> Instant {
+        self.last_received
+    }
+
+    pub(crate) fn sequence_stats(&self) -> SequenceStats {
+        self.sequence.stats()
+    }
+
+    /// Take the sequence gaps detected since the last call.
+    pub(crate) fn take_gaps(&mut self) -> Vec<SequenceGap> {
+        std::mem::take(&mut self.gaps)
+    }
 }
 
 impl<T> EndpointRx<T> where T: Stream<Item = SequencedGenericMessage> {}
 
 impl<U: AsyncRead + Unpin> EndpointRx<MessageStream<U>> {
-    pub(crate) fn from_reader(reader: U) -> Arc<Mutex<EndpointRx<MessageStream<U>>>> {
+    pub(crate) fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.stream.as_mut().set_max_message_size(max_message_size);
+    }
+
+    pub(crate) fn set_framing_recovery(&mut self, recovery: FramingRecovery) {
+        self.stream.as_mut().set_framing_recovery(recovery);
+    }
+
+    pub(crate) fn set_compressed_frames(&mut self, compressed_frames: bool) {
+        self.stream
+            .as_mut()
+            .set_compressed_frames(compressed_frames);
+    }
+
+    /// Take the framing errors found since the last call.
+    pub(crate) fn take_framing_errors(&mut self) -> Vec<FramingError> {
+        self.stream.as_mut().take_framing_errors()
+    }
+
+    pub(crate) fn from_reader(
+        reader: U,
+        codec: MessageCodec,
+    ) -> Arc<Mutex<EndpointRx<MessageStream<U>>>> {
         Arc::new(Mutex::new(EndpointRx {
-            stream: Box::pin(AsyncReadMessagesExt::messages(reader)),
+            stream: Box::pin(MessageStream::with_codec(reader, codec)),
             error: None,
+            last_received: Instant::now(),
+            sequence: SequenceTracker::new(),
+            gaps: Vec::new(),
         }))
     }
 }
@@ -49,7 +97,13 @@ impl<T: Stream<Item = Result<SequencedGenericMessage>>> Stream for EndpointRx<T>
                 self.error = Some(e);
                 Poll::Ready(None)
             }
-            Some(Ok(sgm)) => Poll::Ready(Some(sgm.into_inner())),
+            Some(Ok(sgm)) => {
+                self.last_received = Instant::now();
+                if let Some(gap) = self.sequence.record(sgm.sequence_number) {
+                    self.gaps.push(gap);
+                }
+                Poll::Ready(Some(sgm.into_inner()))
+            }
             None => Poll::Ready(None),
         }
     }
@@ -77,12 +131,6 @@ impl EndpointStatus {
             EndpointStatus::ClosedError(_) => true,
         }
     }
-    pub(crate) fn from_closed(closed: bool) -> EndpointStatus {
-        match closed {
-            true => EndpointStatus::Closed,
-            false => EndpointStatus::Open,
-        }
-    }
     // pub(crate) fn accumulate_closed(&mut self, other: EndpointStatus) {
     //     let max = self.max(&mut other);
     //     self = max;
@@ -127,57 +175,3 @@ impl From<EndpointStatus> for std::task::Poll<std::result::Result<(), VrpnError>
         }
     }
 }
-
-/// Given a stream of GenericMessage, poll the stream and dispatch received messages.
-///
-/// Is only ready when the stream is closed.
-pub(crate) fn poll_and_dispatch<T, U>(
-    endpoint: &mut T,
-    stream: &mut U,
-    dispatcher: &mut TypeDispatcher,
-    cx: &mut Context<'_>,
-) -> Poll<std::result::Result<(), VrpnError>>
-where
-    T: Endpoint,
-    U: Stream<Item = GenericMessage> + Unpin,
-{
-    const MAX_PER_TICK: usize = 10;
-    let mut closed = false;
-    // for i in 0..MAX_PER_TICK {
-    loop {
-        let poll_result = stream.poll_next_unpin(cx);
-        match poll_result {
-            Poll::Ready(Some(msg)) => {
-                let msg = endpoint.map_remote_message_to_local(msg)?;
-                if msg.is_system_message() {
-                    endpoint.send_system_change(parse_system_message(msg)?)?;
-                } else {
-                    dispatcher.call(&msg)?;
-                }
-            }
-            Poll::Ready(None) => {
-                // connection closed
-                closed = true;
-                break;
-            }
-            Poll::Pending => {
-                break;
-            }
-        }
-        // If this is the last iteration, the loop will break even
-        // though there could still be messages to read. Because we did
-        // not reach `Async::NotReady`, we have to notify ourselves
-        // in order to tell the executor to schedule the task again.
-        // if i + 1 == MAX_PER_TICK {
-        //     task::current().notify();
-        // }
-    }
-    if closed {
-        eprintln!("poll_and_dispatch decided the channel was closed");
-        Poll::Ready(Ok(()))
-    } else {
-        // eprintln!("poll_and_dispatch decided that it's not ready");
-        // task::current().notify();
-        Poll::Pending
-    }
-}
@@ -0,0 +1,255 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! An in-process transport, for testing servers and clients together
+//! without sockets: `memory://name` connects to the server listening as `name`.
+//!
+//! The stream carries the same cookie handshake and message framing as TCP.
+
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    AsyncRead, AsyncWrite, StreamExt,
+};
+use std::{
+    collections::VecDeque,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+
+use super::{
+    connect::{handshake, ConnectResults},
+    runtime::{within, AsyncRuntime},
+};
+use crate::{
+    sync::Mutex,
+    timeouts::{TimeoutKind, Timeouts},
+    CompatibilityProfile, Result, Scheme, ServerInfo, VrpnError,
+};
+
+/// One direction of a duplex stream.
+#[derive(Debug, Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    closed: bool,
+    reader: Option<Waker>,
+}
+
+impl Pipe {
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(waker) = self.reader.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The writing end of a pipe, closing it once every clone of its stream is gone.
+#[derive(Debug)]
+struct Writer(Arc<Mutex<Pipe>>);
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.0.lock().close();
+    }
+}
+
+/// One end of an in-memory duplex byte stream.
+///
+/// Clones share the same end. Writes never block.
+#[derive(Debug, Clone)]
+pub struct MemoryStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Writer>,
+}
+
+impl MemoryStream {
+    /// Create two connected ends.
+    pub fn pair() -> (MemoryStream, MemoryStream) {
+        let a = Arc::new(Mutex::new(Pipe::default()));
+        let b = Arc::new(Mutex::new(Pipe::default()));
+        (
+            MemoryStream {
+                read: Arc::clone(&a),
+                write: Arc::new(Writer(Arc::clone(&b))),
+            },
+            MemoryStream {
+                read: b,
+                write: Arc::new(Writer(a)),
+            },
+        )
+    }
+}
+
+impl AsyncRead for MemoryStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.read.lock();
+        if pipe.buf.is_empty() {
+            if pipe.closed {
+                return Poll::Ready(Ok(0));
+            }
+            pipe.reader = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let n = buf.len().min(pipe.buf.len());
+        for (dest, src) in buf.iter_mut().zip(pipe.buf.drain(..n)) {
+            *dest = src;
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for MemoryStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.write.0.lock();
+        if pipe.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        pipe.buf.extend(buf);
+        if let Some(waker) = pipe.reader.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.write.0.lock().close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Servers listening in this process, by name.
+static LISTENERS: Mutex<Vec<(String, UnboundedSender<MemoryStream>)>> = Mutex::new(Vec::new());
+
+/// A name that `memory://` clients in this process can connect to.
+///
+/// The name is free again once this is dropped.
+#[derive(Debug)]
+pub struct MemoryListener {
+    name: String,
+    incoming: UnboundedReceiver<MemoryStream>,
+}
+
+impl MemoryListener {
+    /// Listen as `name`, failing if something in this process already is.
+    pub fn bind(name: impl Into<String>) -> Result<MemoryListener> {
+        let name = name.into();
+        let mut listeners = LISTENERS.lock();
+        listeners.retain(|(_, tx)| !tx.is_closed());
+        if listeners.iter().any(|(existing, _)| *existing == name) {
+            return Err(VrpnError::MemoryServerExists(name));
+        }
+        let (tx, incoming) = unbounded();
+        listeners.push((name.clone(), tx));
+        Ok(MemoryListener { name, incoming })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Wait for a client to connect.
+    pub async fn accept(&mut self) -> Result<MemoryStream> {
+        self.incoming.next().await.ok_or(VrpnError::EndpointClosed)
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        LISTENERS.lock().retain(|(name, _)| *name != self.name);
+    }
+}
+
+/// Open a stream to the server listening as `name` in this process.
+pub fn connect_stream(name: &str) -> Result<MemoryStream> {
+    let listeners = LISTENERS.lock();
+    let (_, tx) = listeners
+        .iter()
+        .find(|(existing, _)| existing == name)
+        .ok_or(VrpnError::CouldNotConnect)?;
+    let (ours, theirs) = MemoryStream::pair();
+    tx.unbounded_send(theirs)
+        .map_err(|_| VrpnError::CouldNotConnect)?;
+    Ok(ours)
+}
+
+/// Connect to a `Scheme::Memory` server, and perform the client side of the handshake.
+pub(crate) async fn connect_memory(
+    runtime: &dyn AsyncRuntime,
+    server: ServerInfo,
+    profile: CompatibilityProfile,
+    timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    let name = server
+        .path
+        .ok_or(VrpnError::MissingServerPath(Scheme::Memory))?;
+    let stream = connect_stream(&name.to_string_lossy())?;
+    within(
+        runtime,
+        timeouts.handshake,
+        TimeoutKind::Handshake,
+        handshake(stream, None, profile),
+    )
+    .await
+}
+
+/// Accept one client, and perform the server side of the handshake.
+pub(crate) async fn accept_memory(
+    runtime: &dyn AsyncRuntime,
+    listener: &mut MemoryListener,
+    profile: CompatibilityProfile,
+) -> Result<ConnectResults> {
+    let stream = listener.accept().await?;
+    within(
+        runtime,
+        Timeouts::default().handshake,
+        TimeoutKind::Handshake,
+        handshake(stream, None, profile),
+    )
+    .await
+}
+
+#[cfg(all(test, feature = "async-std"))]
+mod tests {
+    use super::*;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn pair_and_listener() {
+        async_std::task::block_on(async {
+            let (mut a, b) = MemoryStream::pair();
+            let mut b2 = b.clone();
+            a.write_all(b"hello").await.unwrap();
+            let mut buf = [0u8; 5];
+            b2.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+            // Closed once every clone of the other end is gone.
+            drop(a);
+            assert_eq!(b2.read(&mut buf).await.unwrap(), 0);
+
+            let mut listener = MemoryListener::bind("memory-test").unwrap();
+            assert!(MemoryListener::bind("memory-test").is_err());
+            let mut client = connect_stream("memory-test").unwrap();
+            let mut server = listener.accept().await.unwrap();
+            client.write_all(b"x").await.unwrap();
+            server.read_exact(&mut buf[..1]).await.unwrap();
+            drop(listener);
+            assert!(connect_stream("memory-test").is_err());
+            assert!(MemoryListener::bind("memory-test").is_ok());
+        });
+    }
+}
@@ -4,8 +4,12 @@
 
 use std::borrow::BorrowMut;
 
-use crate::{buffer_unbuffer::BufferUnbufferError, data_types::SequencedGenericMessage, Result};
-use bytes::{Buf, BytesMut};
+use crate::{
+    codec::{FramingError, FramingRecovery, MessageCodec},
+    data_types::SequencedGenericMessage,
+    Result,
+};
+use bytes::BytesMut;
 use futures::{ready, task, AsyncRead, AsyncReadExt, Stream};
 use pin_project_lite::pin_project;
 
@@ -24,20 +28,52 @@ pin_project! {
         state: MessageStreamState,
         mini_buf: [u8; 1024],
         buf: BytesMut,
+        codec: MessageCodec,
     }
 }
 
-impl<'a, R: AsyncReadExt + Unpin> MessageStream<R> {
+impl<R: AsyncReadExt + Unpin> MessageStream<R> {
     pub fn new(stream: R) -> MessageStream<R> {
+        MessageStream::with_codec(stream, MessageCodec::new())
+    }
+
+    /// Create a message stream that decodes with the given codec,
+    /// e.g. to apply a non-default compatibility profile.
+    pub fn with_codec(stream: R, codec: MessageCodec) -> MessageStream<R> {
         MessageStream {
             stream,
             state: MessageStreamState::Reading,
             mini_buf: [0u8; 1024],
             buf: BytesMut::with_capacity(2048),
+            codec,
         }
     }
 }
 
+impl<R> MessageStream<R> {
+    /// Change the limit on the padded size of incoming messages: larger ones are skipped.
+    pub fn set_max_message_size(self: std::pin::Pin<&mut Self>, max_message_size: usize) {
+        self.project().codec.set_max_message_size(max_message_size);
+    }
+
+    /// Change how corrupt framing in the incoming bytes is handled.
+    pub fn set_framing_recovery(self: std::pin::Pin<&mut Self>, recovery: FramingRecovery) {
+        self.project().codec.set_framing_recovery(recovery);
+    }
+
+    /// Change whether compressed frames are accepted in the incoming bytes.
+    pub fn set_compressed_frames(self: std::pin::Pin<&mut Self>, compressed_frames: bool) {
+        self.project()
+            .codec
+            .set_compressed_frames(compressed_frames);
+    }
+
+    /// Take the framing errors found since the last call.
+    pub fn take_framing_errors(self: std::pin::Pin<&mut Self>) -> Vec<FramingError> {
+        self.project().codec.take_framing_errors()
+    }
+}
+
 impl<R> Stream for MessageStream<R>
 where
     R: AsyncRead + Unpin,
@@ -59,6 +95,11 @@ where
                         .as_mut()
                         .poll_read(cx, pinned.mini_buf.borrow_mut()))
                     {
+                        Ok(0) => {
+                            // End of stream: reading again would just return 0 forever.
+                            *state = MessageStreamState::Error;
+                            return task::Poll::Ready(None);
+                        }
                         Ok(n) => {
                             // println!("Read {} bytes from stream", n);
                             pinned.buf.extend_from_slice(&pinned.mini_buf[..n]);
@@ -70,35 +111,20 @@ where
                         }
                     }
                 }
-                MessageStreamState::Parsing => {
-                    let mut existing_bytes = std::io::Cursor::new(&*pinned.buf);
-
-                    match SequencedGenericMessage::try_read_from_buf(&mut existing_bytes) {
-                        Ok(sgm) => {
-                            // consume the bytes from the original buffer.
-                            let consumed = pinned.buf.remaining() - existing_bytes.remaining();
-                            pinned.buf.advance(consumed);
-                            // println!(
-                            //     "consumed {} bytes, {} remain in buffer",
-                            //     consumed,
-                            //     pinned.buf.remaining()
-                            // );
-                            // println!("{:?}", pinned.buf);
-
-                            // Queue an immediate wakeup since the buf may contain more.
-                            cx.waker().wake_by_ref();
-                            return task::Poll::Ready(Some(Ok(sgm)));
-                        }
-                        Err(BufferUnbufferError::NeedMoreData(_requirement)) => {
-                            // println!("need more data: {} bytes", _requirement);
-                            *state = MessageStreamState::Reading;
-                        }
-                        Err(e) => {
-                            *state = MessageStreamState::Error;
-                            return task::Poll::Ready(Some(Err(e.into())));
-                        }
+                MessageStreamState::Parsing => match pinned.codec.decode_from(pinned.buf) {
+                    Ok(Some(sgm)) => {
+                        // Queue an immediate wakeup since the buf may contain more.
+                        cx.waker().wake_by_ref();
+                        return task::Poll::Ready(Some(Ok(sgm)));
                     }
-                }
+                    Ok(None) => {
+                        *state = MessageStreamState::Reading;
+                    }
+                    Err(e) => {
+                        *state = MessageStreamState::Error;
+                        return task::Poll::Ready(Some(Err(e)));
+                    }
+                },
                 MessageStreamState::Error => {
                     // once in this state we never escape
                     return task::Poll::Ready(None);
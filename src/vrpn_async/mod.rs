@@ -2,7 +2,36 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
+//! The async connections, independent of the runtime they run on.
+//!
+//! Message framing, the cookie handshake, connecting and accepting, `EndpointIp` and
+//! `ConnectionIp` are all written against the `futures` I/O traits and the `AsyncRuntime`
+//! trait, which supplies the sockets and timers. `vrpn_async_std` implements it for
+//! async-std, and `vrpn_tokio` for Tokio.
+//!
+//! Unix domain sockets, TLS, WebSocket and serial ports are async-std's streams whichever
+//! runtime a connection is on, so need the `vrpn-async-std` feature.
+
+#[cfg(any(feature = "async-std", feature = "async-tokio"))]
+pub mod builder;
 pub mod bytes_mut_reader;
+pub mod connect;
+pub mod connection_ip;
 pub mod cookie;
+pub mod endpoint_ip;
+pub(crate) mod endpoint_rx;
+pub mod memory;
 pub mod message_stream;
+pub mod reliable_stream;
+pub mod runtime;
+mod udp_channel;
+pub(crate) mod unbounded_message_sender;
+#[cfg(any(feature = "async-std", feature = "async-tokio"))]
+pub use builder::{ConnectionBuilder, Runtime};
+pub use connection_ip::ConnectionIp;
+pub use endpoint_ip::EndpointIp;
+pub use memory::{MemoryListener, MemoryStream};
 pub use message_stream::{AsyncReadMessagesExt, MessageStream};
+pub use reliable_stream::ReliableStream;
+pub use runtime::AsyncRuntime;
+pub(crate) use unbounded_message_sender::{MessageQueue, UnboundedMessageSender};
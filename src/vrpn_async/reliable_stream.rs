@@ -0,0 +1,176 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+use super::{memory::MemoryStream, runtime::TcpStream};
+#[cfg(feature = "serial")]
+use crate::vrpn_async_std::serial::SerialStream;
+#[cfg(feature = "tls")]
+use crate::vrpn_async_std::tls::TlsStream;
+#[cfg(feature = "websocket")]
+use crate::vrpn_async_std::websocket::WsStream;
+#[cfg(all(unix, feature = "async-std"))]
+use async_std::os::unix::net::UnixStream;
+use futures::{AsyncRead, AsyncWrite};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The stream carrying the reliable channel of an endpoint.
+///
+/// All kinds carry the same cookie handshake and message framing.
+/// Plain TCP is on whichever runtime made it; Unix, TLS, WebSocket and serial streams
+/// are async-std's.
+#[derive(Debug, Clone)]
+pub enum ReliableStream {
+    Tcp(TcpStream),
+    #[cfg(all(unix, feature = "async-std"))]
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(TlsStream),
+    #[cfg(feature = "websocket")]
+    WebSocket(WsStream),
+    Memory(MemoryStream),
+    #[cfg(feature = "serial")]
+    Serial(SerialStream),
+}
+
+impl ReliableStream {
+    /// The TCP stream underneath, if it is a plain one whose socket options may be set.
+    pub(crate) fn tcp_stream(&self) -> Option<&TcpStream> {
+        match self {
+            ReliableStream::Tcp(s) => Some(s),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+}
+
+impl From<TcpStream> for ReliableStream {
+    fn from(stream: TcpStream) -> ReliableStream {
+        ReliableStream::Tcp(stream)
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl From<async_std::net::TcpStream> for ReliableStream {
+    fn from(stream: async_std::net::TcpStream) -> ReliableStream {
+        ReliableStream::Tcp(stream.into())
+    }
+}
+
+/// Registers the stream with async-std.
+#[cfg(feature = "async-std")]
+impl From<std::net::TcpStream> for ReliableStream {
+    fn from(stream: std::net::TcpStream) -> ReliableStream {
+        ReliableStream::from(async_std::net::TcpStream::from(stream))
+    }
+}
+
+#[cfg(all(unix, feature = "async-std"))]
+impl From<UnixStream> for ReliableStream {
+    fn from(stream: UnixStream) -> ReliableStream {
+        ReliableStream::Unix(stream)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<TlsStream> for ReliableStream {
+    fn from(stream: TlsStream) -> ReliableStream {
+        ReliableStream::Tls(stream)
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl From<WsStream> for ReliableStream {
+    fn from(stream: WsStream) -> ReliableStream {
+        ReliableStream::WebSocket(stream)
+    }
+}
+
+impl From<MemoryStream> for ReliableStream {
+    fn from(stream: MemoryStream) -> ReliableStream {
+        ReliableStream::Memory(stream)
+    }
+}
+
+#[cfg(feature = "serial")]
+impl From<SerialStream> for ReliableStream {
+    fn from(stream: SerialStream) -> ReliableStream {
+        ReliableStream::Serial(stream)
+    }
+}
+
+impl AsyncRead for ReliableStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ReliableStream::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(all(unix, feature = "async-std"))]
+            ReliableStream::Unix(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            ReliableStream::Tls(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(feature = "websocket")]
+            ReliableStream::WebSocket(s) => Pin::new(s).poll_read(cx, buf),
+            ReliableStream::Memory(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(feature = "serial")]
+            ReliableStream::Serial(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ReliableStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ReliableStream::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(all(unix, feature = "async-std"))]
+            ReliableStream::Unix(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            ReliableStream::Tls(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(feature = "websocket")]
+            ReliableStream::WebSocket(s) => Pin::new(s).poll_write(cx, buf),
+            ReliableStream::Memory(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(feature = "serial")]
+            ReliableStream::Serial(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ReliableStream::Tcp(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(all(unix, feature = "async-std"))]
+            ReliableStream::Unix(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            ReliableStream::Tls(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(feature = "websocket")]
+            ReliableStream::WebSocket(s) => Pin::new(s).poll_flush(cx),
+            ReliableStream::Memory(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(feature = "serial")]
+            ReliableStream::Serial(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ReliableStream::Tcp(s) => Pin::new(s).poll_close(cx),
+            #[cfg(all(unix, feature = "async-std"))]
+            ReliableStream::Unix(s) => Pin::new(s).poll_close(cx),
+            #[cfg(feature = "tls")]
+            ReliableStream::Tls(s) => Pin::new(s).poll_close(cx),
+            #[cfg(feature = "websocket")]
+            ReliableStream::WebSocket(s) => Pin::new(s).poll_close(cx),
+            ReliableStream::Memory(s) => Pin::new(s).poll_close(cx),
+            #[cfg(feature = "serial")]
+            ReliableStream::Serial(s) => Pin::new(s).poll_close(cx),
+        }
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! The sockets and timers that connections and endpoints need from an async runtime.
+//!
+//! Sockets are made with `std::net` and `socket2` as usual (see `net_util`),
+//! then handed to the runtime to be registered with its reactor.
+//! What comes back is wrapped in `TcpStream`, `TcpListener` and `UdpSocket`,
+//! so the connection logic is the same whichever runtime drives it.
+
+use futures::{
+    future::{self, BoxFuture, Either},
+    AsyncRead, AsyncWrite,
+};
+use socket2::SockRef;
+use std::{
+    fmt, io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use crate::{timeouts::TimeoutKind, Result, VrpnError};
+
+/// An async runtime that connections can run their sockets and timers on.
+///
+/// Implemented by `vrpn_async_std::AsyncStd` and `vrpn_tokio::Tokio`;
+/// another runtime needs only these conversions, a timer and a way to spawn tasks.
+pub trait AsyncRuntime: fmt::Debug + Send + Sync + 'static {
+    /// Register a connected TCP stream with this runtime.
+    fn tcp_stream(&self, stream: std::net::TcpStream) -> io::Result<TcpStream>;
+
+    /// Register a bound and listening TCP socket with this runtime.
+    fn tcp_listener(&self, listener: std::net::TcpListener) -> io::Result<TcpListener>;
+
+    /// Register a bound UDP socket with this runtime.
+    fn udp_socket(&self, socket: std::net::UdpSocket) -> io::Result<UdpSocket>;
+
+    /// A future completing once `duration` has passed.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    /// Run a future to completion as its own task.
+    fn spawn(&self, future: BoxFuture<'static, ()>);
+}
+
+/// A TCP stream as registered with a runtime, readable and writable through a shared reference.
+pub trait RawTcpStream: fmt::Debug + Send + Sync {
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>>;
+    fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>>;
+    fn poll_flush(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+    fn poll_close(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+    /// The socket underneath, for its addresses and options.
+    fn socket(&self) -> SockRef<'_>;
+}
+
+/// A TCP listener as registered with a runtime.
+pub trait RawTcpListener: fmt::Debug + Send + Sync {
+    fn accept(&self) -> BoxFuture<'_, io::Result<(TcpStream, SocketAddr)>>;
+    fn socket(&self) -> SockRef<'_>;
+}
+
+/// A UDP socket as registered with a runtime.
+pub trait RawUdpSocket: fmt::Debug + Send + Sync {
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8])
+        -> BoxFuture<'a, io::Result<(usize, SocketAddr)>>;
+    fn send_to<'a>(&'a self, buf: &'a [u8], target: SocketAddr)
+        -> BoxFuture<'a, io::Result<usize>>;
+    fn socket(&self) -> SockRef<'_>;
+}
+
+fn socket_addr(addr: socket2::SockAddr) -> io::Result<SocketAddr> {
+    addr.as_socket()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not an IP socket address"))
+}
+
+/// A TCP stream on some runtime.
+///
+/// Clones share the same stream, so one can read while another writes.
+#[derive(Debug, Clone)]
+pub struct TcpStream(Arc<dyn RawTcpStream>);
+
+impl TcpStream {
+    pub fn new(raw: impl RawTcpStream + 'static) -> TcpStream {
+        TcpStream(Arc::new(raw))
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        socket_addr(self.0.socket().peer_addr()?)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        socket_addr(self.0.socket().local_addr()?)
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.0.socket().set_nodelay(nodelay)
+    }
+
+    pub(crate) fn socket(&self) -> SockRef<'_> {
+        self.0.socket()
+    }
+}
+
+impl AsyncRead for TcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.0.poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.0.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.poll_close(cx)
+    }
+}
+
+/// A TCP listener on some runtime.
+#[derive(Debug)]
+pub struct TcpListener(Box<dyn RawTcpListener>);
+
+impl TcpListener {
+    pub fn new(raw: impl RawTcpListener + 'static) -> TcpListener {
+        TcpListener(Box::new(raw))
+    }
+
+    /// Wait for a client to connect.
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        self.0.accept().await
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        socket_addr(self.0.socket().local_addr()?)
+    }
+}
+
+/// A UDP socket on some runtime.
+#[derive(Debug)]
+pub struct UdpSocket(Box<dyn RawUdpSocket>);
+
+impl UdpSocket {
+    pub fn new(raw: impl RawUdpSocket + 'static) -> UdpSocket {
+        UdpSocket(Box::new(raw))
+    }
+
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.0.recv_from(buf).await
+    }
+
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.0.send_to(buf, target).await
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        socket_addr(self.0.socket().local_addr()?)
+    }
+
+    pub(crate) fn socket(&self) -> SockRef<'_> {
+        self.0.socket()
+    }
+}
+
+/// Run `fut`, failing with `VrpnError::Timeout(kind)` if it takes longer than `limit`.
+pub(crate) async fn within<T>(
+    runtime: &dyn AsyncRuntime,
+    limit: Option<Duration>,
+    kind: TimeoutKind,
+    fut: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return fut.await,
+    };
+    futures::pin_mut!(fut);
+    match future::select(fut, runtime.sleep(limit)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(VrpnError::Timeout(kind)),
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! The low-latency channel of an endpoint: UDP datagrams in each direction,
+//! to the address each side gives the other in a `UDP_DESCRIPTION` message.
+//!
+//! Messages are framed as on the reliable channel, one per datagram.
+//! Nothing is resent: a message that cannot be sent right away is dropped.
+
+use bytes::BytesMut;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use socket2::SockAddr;
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use crate::{
+    codec::MessageCodec,
+    compatibility::WireConfig,
+    constants::UDP_BUFLEN,
+    data_types::{
+        id_types::SequenceNumber, message::Message, GenericMessage, TypedMessage, UdpDescription,
+    },
+    Result,
+};
+
+use super::runtime::UdpSocket;
+
+/// Decode the messages of one datagram, dropping all of it if it is bad.
+fn decode_datagram(codec: &MessageCodec, datagram: &[u8]) -> Vec<GenericMessage> {
+    match codec.decode_frame(datagram) {
+        Ok(messages) => messages.into_iter().map(|msg| msg.into_inner()).collect(),
+        Err(e) => {
+            warn!("Dropping a bad datagram: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn receive_messages(
+    socket: Arc<UdpSocket>,
+    codec: MessageCodec,
+) -> BoxStream<'static, GenericMessage> {
+    stream::unfold((socket, codec), |(socket, codec)| async move {
+        let mut buf = vec![0u8; UDP_BUFLEN];
+        let messages = match socket.recv_from(&mut buf).await {
+            Ok((len, _)) => decode_datagram(&codec, &buf[..len]),
+            Err(e) => {
+                warn!("Could not receive on the low-latency channel: {}", e);
+                Vec::new()
+            }
+        };
+        Some((stream::iter(messages), (socket, codec)))
+    })
+    .flatten()
+    .boxed()
+}
+
+pub(crate) struct UdpChannel {
+    socket: Arc<UdpSocket>,
+    /// Where the peer receives, once it has told us.
+    peer: Option<SocketAddr>,
+    incoming: BoxStream<'static, GenericMessage>,
+    /// Received, but not yet dispatched.
+    pending: VecDeque<GenericMessage>,
+    sequence: u32,
+    codec: MessageCodec,
+    /// Send all user messages this way, not just those without `ClassOfService::RELIABLE`.
+    exclusive: bool,
+}
+
+impl fmt::Debug for UdpChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpChannel")
+            .field("socket", &self.socket)
+            .field("peer", &self.peer)
+            .field("pending", &self.pending.len())
+            .field("exclusive", &self.exclusive)
+            .finish()
+    }
+}
+
+impl UdpChannel {
+    pub(crate) fn new(socket: UdpSocket, wire: WireConfig) -> UdpChannel {
+        let socket = Arc::new(socket);
+        let codec = MessageCodec::new().with_wire_config(wire);
+        UdpChannel {
+            incoming: receive_messages(Arc::clone(&socket), codec.clone()),
+            socket,
+            peer: None,
+            pending: VecDeque::new(),
+            sequence: 0,
+            codec,
+            exclusive: false,
+        }
+    }
+
+    pub(crate) fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// The description of our socket to send the peer, which reaches us at `local_ip`.
+    pub(crate) fn description(&self, local_ip: IpAddr) -> Result<GenericMessage> {
+        let port = self.socket.local_addr()?.port();
+        Ok(GenericMessage::try_from(TypedMessage::from(
+            UdpDescription::new(SocketAddr::new(local_ip, port)),
+        ))?)
+    }
+
+    /// Send to the address the peer described.
+    pub(crate) fn set_peer(&mut self, peer: SocketAddr) {
+        self.peer = Some(peer);
+    }
+
+    pub(crate) fn set_exclusive(&mut self) {
+        self.exclusive = true;
+    }
+
+    /// Whether a message of this class should be sent on this channel.
+    ///
+    /// System messages, and any message before the peer has described its socket,
+    /// go on the reliable channel.
+    pub(crate) fn carries(&self, msg: &GenericMessage, reliable: bool) -> bool {
+        self.peer.is_some() && !msg.is_system_message() && (self.exclusive || !reliable)
+    }
+
+    /// Send a message, dropping it if the socket is not ready for it.
+    pub(crate) fn send(&mut self, msg: GenericMessage) -> Result<()> {
+        let peer = match self.peer {
+            Some(peer) => peer,
+            None => return Ok(()),
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+        let mut buf = BytesMut::new();
+        self.codec.encode_into(
+            msg.into_sequenced_message(SequenceNumber(self.sequence)),
+            &mut buf,
+        )?;
+        match self.socket.socket().send_to(&buf, &SockAddr::from(peer)) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                trace!("Dropping a low-latency message: socket busy");
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Could not send on the low-latency channel: {}", e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Move what has arrived to the pending messages, keeping those `known` accepts.
+    ///
+    /// Datagrams can overtake the descriptions of their sender and type,
+    /// so messages with IDs not yet described are dropped.
+    pub(crate) fn receive(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut known: impl FnMut(&GenericMessage) -> bool,
+    ) {
+        while let Poll::Ready(Some(msg)) = self.incoming.poll_next_unpin(cx) {
+            if known(&msg) {
+                self.pending.push_back(msg);
+            } else {
+                trace!("Dropping a low-latency message from an undescribed sender or type");
+            }
+        }
+    }
+
+    /// The next received message to dispatch.
+    pub(crate) fn next_pending(&mut self) -> Option<GenericMessage> {
+        self.pending.pop_front()
+    }
+}
+
+#[cfg(all(test, feature = "async-std"))]
+mod tests {
+    use super::*;
+    use crate::data_types::{id_types::SenderId, GenericBody, MessageHeader, MessageTypeId};
+    use bytes::Bytes;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn exchange() {
+        let bind = || {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.set_nonblocking(true).unwrap();
+            UdpChannel::new(
+                async_std::net::UdpSocket::from(socket).into(),
+                WireConfig::default(),
+            )
+        };
+        let (mut a, mut b) = (bind(), bind());
+        let msg = GenericMessage::from_header_and_body(
+            MessageHeader::new(None, MessageTypeId(2), SenderId(1)),
+            GenericBody::new(Bytes::from_static(b"abcd")),
+        );
+        assert!(!a.carries(&msg, false), "no peer yet");
+        a.set_peer(b.socket().local_addr().unwrap());
+        assert!(a.carries(&msg, false));
+        assert!(!a.carries(&msg, true));
+        let desc = a.description(IpAddr::from([127, 0, 0, 1])).unwrap();
+        assert!(!a.carries(&desc, false));
+        a.set_exclusive();
+        assert!(a.carries(&msg, true));
+
+        a.send(msg.clone()).unwrap();
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let received = loop {
+            b.receive(&mut cx, |_| true);
+            if let Some(received) = b.next_pending() {
+                break received;
+            }
+            assert!(Instant::now() < deadline, "timed out receiving");
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(received.header.message_type, msg.header.message_type);
+        assert_eq!(received.body, msg.body);
+    }
+}
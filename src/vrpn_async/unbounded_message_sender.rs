@@ -0,0 +1,535 @@
+// Copyright 2018-2021, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+use crate::{
+    buffer_unbuffer::{BufferSize, BufferTo},
+    compression::compress_frame,
+    data_types::{id_types::SequenceNumber, GenericMessage},
+    timestamp_policy::stamp_now,
+    Result, VrpnError, DEFAULT_COALESCE_THRESHOLD,
+};
+use bytes::BytesMut;
+use futures::{
+    channel::mpsc, future::FusedFuture, task::AtomicWaker, AsyncWrite, AsyncWriteExt, Future,
+    FutureExt, StreamExt,
+};
+use std::{
+    fmt::Debug,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+/// How many messages have been queued, and how many of those written and flushed (or dropped),
+/// shared by the queue handles and the sender.
+#[derive(Debug)]
+struct SendProgress {
+    queued: AtomicUsize,
+    flushed: AtomicUsize,
+    dropped: AtomicUsize,
+    /// Droppable messages are dropped while more than this many are waiting.
+    drop_threshold: AtomicUsize,
+    /// Whether to replace the time of user messages as they are serialized.
+    stamp_on_write: AtomicBool,
+    /// Whether to write runs of messages as compressed frames, where that makes them smaller.
+    compress: AtomicBool,
+    stopped: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl Default for SendProgress {
+    fn default() -> Self {
+        SendProgress {
+            queued: AtomicUsize::new(0),
+            flushed: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            drop_threshold: AtomicUsize::new(usize::MAX),
+            stamp_on_write: AtomicBool::new(false),
+            compress: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        }
+    }
+}
+
+impl SendProgress {
+    fn set_flushed(&self, count: usize) {
+        self.flushed.store(count, Ordering::SeqCst);
+        self.waker.wake();
+    }
+
+    /// Whether a droppable message should be dropped, given how many have been taken off the queue.
+    fn should_drop(&self, taken: usize) -> bool {
+        let backlog = self.queued.load(Ordering::SeqCst).saturating_sub(taken);
+        backlog > self.drop_threshold.load(Ordering::Relaxed)
+    }
+
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.waker.wake();
+    }
+}
+
+/// Write out and clear the serialized messages in `pending`, compressed if enabled.
+async fn write_pending<T: AsyncWrite>(
+    stream: &mut Pin<Box<T>>,
+    pending: &mut BytesMut,
+    progress: &SendProgress,
+) -> Result<()> {
+    if progress.compress.load(Ordering::Relaxed) {
+        if let Some(frame) = compress_frame(pending) {
+            stream.write_all(&frame).await?;
+            pending.clear();
+            return Ok(());
+        }
+    }
+    stream.write_all(pending).await?;
+    pending.clear();
+    Ok(())
+}
+
+/// The actual async function underlying UnboundedMessageSender
+///
+/// Messages already queued are serialized into a single buffer,
+/// which is written out once it reaches the threshold or the queue is drained.
+/// A batch is always serialized whole before the threshold is checked.
+/// Droppable messages are skipped while the queue is backed up past the drop threshold.
+/// With compression on, each write is compressed separately, so the threshold also bounds
+/// how much goes into one compressed frame.
+async fn sender<T: AsyncWrite>(
+    stream: T,
+    channel_rx: mpsc::UnboundedReceiver<QueuedMessage>,
+    coalesce_threshold: Arc<AtomicUsize>,
+    progress: Arc<SendProgress>,
+) -> Result<()> {
+    let mut seq: u32 = 0;
+    let mut sent = 0;
+    let mut pending = BytesMut::new();
+    let mut channel_rx = channel_rx;
+    let mut stream = Box::pin(stream);
+    while let Some(msg) = channel_rx.next().await {
+        let mut next = Some(msg);
+        while let Some(QueuedMessage { msgs, droppable }) = next {
+            let count = msgs.len();
+            sent += count;
+            if droppable && progress.should_drop(sent - count) {
+                progress.dropped.fetch_add(count, Ordering::SeqCst);
+                next = channel_rx.try_next().ok().flatten();
+                continue;
+            }
+            for mut msg in msgs {
+                if progress.stamp_on_write.load(Ordering::Relaxed) {
+                    stamp_now(&mut msg);
+                }
+                // Numbered one by one, as if queued separately.
+                seq += 1;
+                let msg = msg.into_sequenced_message(SequenceNumber(seq));
+                pending.reserve(msg.buffer_size());
+                msg.buffer_to(&mut pending)?;
+            }
+            if pending.len() >= coalesce_threshold.load(Ordering::Relaxed) {
+                write_pending(&mut stream, &mut pending, &progress).await?;
+            }
+            // Keep accumulating whatever is already queued before writing.
+            next = channel_rx.try_next().ok().flatten();
+        }
+        if !pending.is_empty() {
+            write_pending(&mut stream, &mut pending, &progress).await?;
+        }
+        stream.flush().await?;
+        progress.set_flushed(sent);
+    }
+    stream.flush().await?;
+    Ok(())
+}
+
+/// A message, or batch of messages, waiting in the queue.
+#[derive(Debug)]
+struct QueuedMessage {
+    msgs: Vec<GenericMessage>,
+    droppable: bool,
+}
+
+type FusedBoxFuture<'a, T> = Pin<Box<dyn FusedFuture<Output = T> + Send + 'a>>;
+
+/// A structure that lets you send messages to some stream just like an unbounded channel
+pub(crate) struct UnboundedMessageSender {
+    channel_tx: mpsc::UnboundedSender<QueuedMessage>,
+    send_future: FusedBoxFuture<'static, Result<()>>,
+    coalesce_threshold: Arc<AtomicUsize>,
+    progress: Arc<SendProgress>,
+}
+
+impl UnboundedMessageSender {
+    /// Create a future that pumps transmission of sequenced messages to an AsyncWrite implementation.
+    pub(crate) fn new<T: 'static + AsyncWrite + Send>(
+        writer: T,
+    ) -> Pin<Box<UnboundedMessageSender>> {
+        let (channel_tx, channel_rx) = mpsc::unbounded();
+        let coalesce_threshold = Arc::new(AtomicUsize::new(DEFAULT_COALESCE_THRESHOLD));
+        let progress = Arc::new(SendProgress::default());
+        Box::pin(UnboundedMessageSender {
+            channel_tx,
+            send_future: Box::pin(
+                sender(
+                    writer,
+                    channel_rx,
+                    Arc::clone(&coalesce_threshold),
+                    Arc::clone(&progress),
+                )
+                .fuse(),
+            ),
+            coalesce_threshold,
+            progress,
+        })
+    }
+}
+
+impl UnboundedMessageSender {
+    /// Get a handle for queuing messages, usable while this is polled elsewhere.
+    pub(crate) fn queue(&self) -> MessageQueue {
+        MessageQueue {
+            channel_tx: self.channel_tx.clone(),
+            coalesce_threshold: Arc::clone(&self.coalesce_threshold),
+            progress: Arc::clone(&self.progress),
+        }
+    }
+}
+
+/// A cloneable handle for queuing messages on an `UnboundedMessageSender`.
+#[derive(Debug, Clone)]
+pub(crate) struct MessageQueue {
+    channel_tx: mpsc::UnboundedSender<QueuedMessage>,
+    coalesce_threshold: Arc<AtomicUsize>,
+    progress: Arc<SendProgress>,
+}
+
+impl MessageQueue {
+    /// Queues a message to be sequenced and sent.
+    ///
+    /// Fails once the queue is closed or the sender has stopped.
+    pub(crate) fn unbounded_send(&self, msg: GenericMessage) -> Result<()> {
+        self.send_queued(QueuedMessage {
+            msgs: vec![msg],
+            droppable: false,
+        })
+    }
+
+    /// Queues messages to be sequenced and serialized back to back, then written together.
+    ///
+    /// A droppable batch is dropped whole, if the queue backs up.
+    pub(crate) fn send_batch(&self, msgs: Vec<GenericMessage>, droppable: bool) -> Result<()> {
+        if msgs.is_empty() {
+            return Ok(());
+        }
+        self.send_queued(QueuedMessage { msgs, droppable })
+    }
+
+    /// Queues a message that may be dropped instead of sent, if the queue backs up.
+    ///
+    /// See `set_drop_threshold`.
+    pub(crate) fn send_droppable(&self, msg: GenericMessage) -> Result<()> {
+        self.send_queued(QueuedMessage {
+            msgs: vec![msg],
+            droppable: true,
+        })
+    }
+
+    fn send_queued(&self, msg: QueuedMessage) -> Result<()> {
+        // Counted first, so the sender can't have flushed more than we queued.
+        let count = msg.msgs.len();
+        self.progress.queued.fetch_add(count, Ordering::SeqCst);
+        self.channel_tx.unbounded_send(msg).map_err(|_| {
+            self.progress.queued.fetch_sub(count, Ordering::SeqCst);
+            VrpnError::EndpointClosed
+        })
+    }
+
+    /// Ready once everything queued so far has been written and flushed,
+    /// or the sender has stopped.
+    pub(crate) fn poll_flushed(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let progress = &self.progress;
+        progress.waker.register(cx.waker());
+        if progress.stopped.load(Ordering::SeqCst)
+            || progress.flushed.load(Ordering::SeqCst) >= progress.queued.load(Ordering::SeqCst)
+        {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// How many messages have ever been queued, from any handle.
+    pub(crate) fn queued(&self) -> usize {
+        self.progress.queued.load(Ordering::SeqCst)
+    }
+
+    /// How many messages are queued but not yet written and flushed, or dropped.
+    pub(crate) fn backlog(&self) -> usize {
+        let progress = &self.progress;
+        progress
+            .queued
+            .load(Ordering::SeqCst)
+            .saturating_sub(progress.flushed.load(Ordering::SeqCst))
+    }
+
+    /// How many droppable messages have been dropped rather than sent.
+    pub(crate) fn dropped(&self) -> usize {
+        self.progress.dropped.load(Ordering::SeqCst)
+    }
+
+    /// Drop droppable messages, oldest first, while more than this many are waiting.
+    ///
+    /// `None` never drops any.
+    pub(crate) fn set_drop_threshold(&self, threshold: Option<usize>) {
+        self.progress
+            .drop_threshold
+            .store(threshold.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    /// Replace the time of each user message with the current time as it is serialized.
+    pub(crate) fn set_stamp_on_write(&self, stamp: bool) {
+        self.progress.stamp_on_write.store(stamp, Ordering::Relaxed);
+    }
+
+    /// Write runs of messages as compressed frames, where that makes them smaller.
+    ///
+    /// Only for a peer that can unpack them.
+    pub(crate) fn set_compress(&self, compress: bool) {
+        self.progress.compress.store(compress, Ordering::Relaxed);
+    }
+
+    /// Set how many bytes of serialized messages to accumulate before writing them out.
+    ///
+    /// Whatever is queued is still written out once the queue is drained,
+    /// so this only bounds how much is held back while more messages are waiting.
+    pub(crate) fn set_coalesce_threshold(&self, threshold: usize) {
+        self.coalesce_threshold.store(threshold, Ordering::Relaxed);
+    }
+
+    /// Close the queue: the sender stops once it has sent what is already queued.
+    pub(crate) fn close(&self) {
+        self.channel_tx.close_channel()
+    }
+
+    /// Whether the queue is closed, or the sender has stopped.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.channel_tx.is_closed()
+    }
+}
+
+impl Debug for UnboundedMessageSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("UnboundedMessageSender")
+            .field("channel_tx", &self.channel_tx)
+            .field("send_future", &!self.send_future.is_terminated())
+            .field(
+                "coalesce_threshold",
+                &self.coalesce_threshold.load(Ordering::Relaxed),
+            )
+            .finish()
+    }
+}
+
+impl Future for UnboundedMessageSender {
+    type Output = Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = self.send_future.as_mut().poll(cx);
+        if result.is_ready() {
+            self.progress.stop();
+        }
+        result
+    }
+}
+
+impl Unpin for UnboundedMessageSender {}
+
+impl FusedFuture for UnboundedMessageSender {
+    fn is_terminated(&self) -> bool {
+        self.send_future.is_terminated() || self.channel_tx.is_closed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types::{
+        id_types::SenderId, GenericBody, Message, MessageHeader, MessageTypeId,
+    };
+    use bytes::Bytes;
+    use std::sync::Mutex;
+
+    /// Records the size of each write.
+    #[derive(Clone, Default)]
+    struct WriteRecorder(Arc<Mutex<Vec<usize>>>);
+
+    impl AsyncWrite for WriteRecorder {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.0.lock().unwrap().push(buf.len());
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn message() -> GenericMessage {
+        GenericMessage::from_header_and_body(
+            MessageHeader::new(None, MessageTypeId(0), SenderId(0)),
+            GenericBody::new(Bytes::from_static(b"abcd")),
+        )
+    }
+
+    fn send_queued(count: usize, threshold: Option<usize>) -> Vec<usize> {
+        let recorder = WriteRecorder::default();
+        let sender = UnboundedMessageSender::new(recorder.clone());
+        let queue = sender.queue();
+        if let Some(threshold) = threshold {
+            queue.set_coalesce_threshold(threshold);
+        }
+        for _ in 0..count {
+            queue.unbounded_send(message()).unwrap();
+        }
+        queue.close();
+        futures::executor::block_on(sender).unwrap();
+        let writes = recorder.0.lock().unwrap().clone();
+        writes
+    }
+
+    #[test]
+    fn queue_handle() {
+        let recorder = WriteRecorder::default();
+        let mut sender = UnboundedMessageSender::new(recorder.clone());
+        let queue = sender.queue();
+        queue.unbounded_send(message()).unwrap();
+        queue.clone().unbounded_send(message()).unwrap();
+        queue.close();
+        assert!(matches!(
+            queue.unbounded_send(message()),
+            Err(VrpnError::EndpointClosed)
+        ));
+        futures::executor::block_on(sender.as_mut()).unwrap();
+        assert_eq!(recorder.0.lock().unwrap().iter().sum::<usize>(), 2 * 32);
+    }
+
+    #[test]
+    fn flushed_once_written() {
+        let recorder = WriteRecorder::default();
+        let mut sender = UnboundedMessageSender::new(recorder.clone());
+        let queue = sender.queue();
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(queue.poll_flushed(&mut cx).is_ready());
+        queue.unbounded_send(message()).unwrap();
+        assert!(queue.poll_flushed(&mut cx).is_pending());
+        assert!(sender.as_mut().poll(&mut cx).is_pending());
+        assert!(queue.poll_flushed(&mut cx).is_ready());
+        assert_eq!(recorder.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn queued_messages_coalesce() {
+        let writes = send_queued(10, None);
+        assert_eq!(writes.len(), 1);
+        assert_eq!(
+            writes[0],
+            10 * message()
+                .into_sequenced_message(SequenceNumber(0))
+                .buffer_size()
+        );
+    }
+
+    #[test]
+    fn threshold_splits_writes() {
+        let size = message()
+            .into_sequenced_message(SequenceNumber(0))
+            .buffer_size();
+        let writes = send_queued(10, Some(size * 4));
+        assert_eq!(writes, vec![size * 4, size * 4, size * 2]);
+    }
+
+    #[test]
+    fn batch_written_together() {
+        let recorder = WriteRecorder::default();
+        let sender = UnboundedMessageSender::new(recorder.clone());
+        let queue = sender.queue();
+        let size = message()
+            .into_sequenced_message(SequenceNumber(0))
+            .buffer_size();
+        queue.set_coalesce_threshold(size);
+        queue.set_drop_threshold(Some(1));
+        queue.send_batch(vec![message(); 3], false).unwrap();
+        queue.send_batch(Vec::new(), false).unwrap();
+        queue.send_batch(vec![message(); 2], true).unwrap();
+        queue.unbounded_send(message()).unwrap();
+        assert_eq!(queue.queued(), 6);
+        queue.close();
+        futures::executor::block_on(sender).unwrap();
+        // The droppable batch is dropped whole, with three messages still waiting after it.
+        assert_eq!(queue.dropped(), 2);
+        assert_eq!(*recorder.0.lock().unwrap(), vec![size * 3, size]);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_writes() {
+        let recorder = WriteRecorder::default();
+        let sender = UnboundedMessageSender::new(recorder.clone());
+        let queue = sender.queue();
+        queue.set_compress(true);
+        queue.send_batch(vec![message(); 20], false).unwrap();
+        queue.close();
+        futures::executor::block_on(sender).unwrap();
+        let size = message()
+            .into_sequenced_message(SequenceNumber(0))
+            .buffer_size();
+        let writes = recorder.0.lock().unwrap().clone();
+        assert_eq!(writes.len(), 1);
+        assert!(writes[0] < 20 * size / 2, "{:?}", writes);
+
+        // Too little to be worth compressing.
+        let recorder = WriteRecorder::default();
+        let sender = UnboundedMessageSender::new(recorder.clone());
+        let queue = sender.queue();
+        queue.set_compress(true);
+        queue.unbounded_send(message()).unwrap();
+        queue.close();
+        futures::executor::block_on(sender).unwrap();
+        assert_eq!(*recorder.0.lock().unwrap(), vec![size]);
+    }
+
+    #[test]
+    fn backed_up_queue_drops_oldest() {
+        let recorder = WriteRecorder::default();
+        let sender = UnboundedMessageSender::new(recorder.clone());
+        let queue = sender.queue();
+        queue.set_drop_threshold(Some(2));
+        for _ in 0..5 {
+            queue.send_droppable(message()).unwrap();
+        }
+        queue.unbounded_send(message()).unwrap();
+        assert_eq!(queue.backlog(), 6);
+        queue.close();
+        futures::executor::block_on(sender).unwrap();
+        // The oldest four go, leaving the last droppable one and the reliable one.
+        assert_eq!(queue.dropped(), 4);
+        assert_eq!(queue.backlog(), 0);
+        let size = message()
+            .into_sequenced_message(SequenceNumber(0))
+            .buffer_size();
+        assert_eq!(recorder.0.lock().unwrap().iter().sum::<usize>(), 2 * size);
+    }
+}
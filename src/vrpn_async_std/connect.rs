@@ -2,148 +2,123 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
-use std::{
-    io,
-    net::{IpAddr, SocketAddr, ToSocketAddrs},
-    time::Duration,
-};
+//! Connecting with async-std: see `vrpn_async::connect` for other runtimes.
 
-use async_std::{
-    future::timeout,
-    net::{TcpListener, TcpStream, UdpSocket},
-};
-use bytes::{BufMut, Bytes, BytesMut};
-use socket2::{SockAddr, SockRef};
+use std::{future::Future, sync::Arc, time::Duration};
+
+use async_std::future::timeout;
+#[cfg(unix)]
+use async_std::os::unix::net::UnixListener;
 
+use super::runtime::AsyncStd;
+pub(crate) use crate::vrpn_async::connect::handshake;
+pub use crate::vrpn_async::connect::ConnectResults;
 use crate::{
-    vrpn_async::cookie::{read_and_check_nonfile_cookie, send_nonfile_cookie},
-    Result, Scheme, ServerInfo, VrpnError,
+    timeouts::{TimeoutKind, Timeouts},
+    vrpn_async::connect::connect_on,
+    CompatibilityProfile, Result, ServerInfo, VrpnError,
 };
 
-pub struct ConnectResults {
-    pub(crate) server_info: ServerInfo,
-    pub(crate) tcp: TcpStream,
-    pub(crate) udp: Option<UdpSocket>,
-}
-
-pub(crate) fn make_tcp_socket(addr: SocketAddr) -> io::Result<socket2::Socket> {
-    use socket2::*;
-    let domain = if addr.is_ipv4() {
-        Domain::IPV4
-    } else {
-        Domain::IPV6
-    };
-    let sock = socket2::Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
-    sock.set_nonblocking(true)?;
-    sock.set_nodelay(true)?;
-
-    if cfg!(windows) {
-        if addr.is_ipv4() {
-            let any = std::net::Ipv4Addr::new(0, 0, 0, 0);
-            let addr = std::net::SocketAddrV4::new(any, 0);
-            sock.bind(&socket2::SockAddr::from(addr))?;
-        } else {
-            unimplemented!();
-        }
+/// Run `fut`, failing with `VrpnError::Timeout(kind)` if it takes longer than `limit`.
+pub(crate) async fn within<T>(
+    limit: Option<Duration>,
+    kind: TimeoutKind,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match limit {
+        Some(limit) => timeout(limit, fut)
+            .await
+            .map_err(|_| VrpnError::Timeout(kind))?,
+        None => fut.await,
     }
-    sock.set_reuse_address(true)?;
-    Ok(sock)
 }
 
-async fn make_udp_socket() -> io::Result<UdpSocket> {
-    let any = std::net::Ipv4Addr::new(0, 0, 0, 0);
-    let addr = SocketAddr::new(IpAddr::V4(any), 0);
-    let sock = UdpSocket::bind(addr).await?;
-    {
-        let sock = SockRef::from(&sock);
-        sock.set_reuse_address(true)?;
-        sock.set_nonblocking(true)?;
-        sock.set_nodelay(true)?;
-    }
-    Ok(sock)
-}
-/// Connect members that only are populated for UDP connections.
-#[derive(Debug)]
-pub(crate) struct UdpConnect {
-    udp: UdpSocket,
-    lobbed_buf: Bytes,
+/// Accept one client on a Unix domain socket, and perform the server side of the handshake.
+#[cfg(unix)]
+pub async fn accept_unix(
+    listener: &UnixListener,
+    profile: CompatibilityProfile,
+) -> Result<ConnectResults> {
+    let (stream, _) = listener.accept().await?;
+    within(
+        Timeouts::default().handshake,
+        TimeoutKind::Handshake,
+        handshake(stream, None, profile),
+    )
+    .await
 }
-async fn outgoing_tcp_connect(addr: std::net::SocketAddr) -> Result<TcpStream> {
-    let sock = make_tcp_socket(addr)?;
-    sock.connect(&SockAddr::from(addr))?;
-    Ok(TcpStream::from(std::net::TcpStream::from(sock)))
+
+pub async fn connect(server: ServerInfo) -> Result<ConnectResults> {
+    connect_with(server, CompatibilityProfile::default()).await
 }
 
-async fn lobbing(
-    udp: &UdpSocket,
-    buf: &Bytes,
-    tcp_listener: &TcpListener,
+/// Connect to a server, checking its cookie against the given compatibility profile.
+pub async fn connect_with(
     server: ServerInfo,
-) -> std::result::Result<Option<(TcpStream, SocketAddr)>, io::Error> {
-    udp.send_to(buf, server.socket_addr).await?;
-    match timeout(
-        Duration::from_millis(MILLIS_BETWEEN_ATTEMPTS),
-        tcp_listener.accept(),
-    )
-    .await
-    {
-        Ok(result) => Ok(Some(result?)),
-        Err(_) => Ok(None),
-    }
+    profile: CompatibilityProfile,
+) -> Result<ConnectResults> {
+    connect_with_timeouts(server, profile, Timeouts::default()).await
 }
 
-async fn handshake(
-    server_info: ServerInfo,
-    tcp: TcpStream,
-    udp: Option<UdpSocket>,
+/// Connect to a server, failing with `VrpnError::Timeout` if connecting or the handshake
+/// take longer than allowed.
+pub async fn connect_with_timeouts(
+    server: ServerInfo,
+    profile: CompatibilityProfile,
+    timeouts: Timeouts,
 ) -> Result<ConnectResults> {
-    let mut tcp = tcp;
-    send_nonfile_cookie(&mut tcp).await?;
-    read_and_check_nonfile_cookie(&mut tcp).await?;
-    Ok(ConnectResults {
-        server_info,
-        tcp,
-        udp,
-    })
+    connect_on(Arc::new(AsyncStd), server, profile, timeouts).await
 }
 
-async fn connect_tcp_and_udp(server: ServerInfo) -> Result<ConnectResults> {
-    let udp = make_udp_socket().await?;
-    let addr = "localhost".to_socket_addrs()?.next().unwrap();
-    let addr = SocketAddr::new(addr.ip(), 0);
-    let tcp_listener = TcpListener::bind(&addr).await?;
-    let port = udp.local_addr()?.port();
-    let addr = SocketAddr::new(addr.ip(), port);
-    let lobbed_buf = {
-        let addr_str = addr.ip().to_string();
-        let port_str = addr.port().to_string();
-        let mut buf = BytesMut::with_capacity(addr_str.len() + port_str.len() + 2);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data_types::constants::COOKIE_SIZE, Scheme};
 
-        buf.put(addr_str.as_bytes());
-        buf.put(" ".as_bytes());
-        buf.put(port_str.as_bytes());
-        buf.put_u8(0);
-        buf
-    };
-    let lobbed_buf = lobbed_buf.freeze();
-    for _ in 0..5 {
-        if let Some((tcp_stream, _)) =
-            lobbing(&udp, &lobbed_buf, &tcp_listener, server.clone()).await?
-        {
-            return handshake(server, tcp_stream, Some(udp)).await;
-        }
+    #[test]
+    fn handshake_timeout() {
+        // Accepts connections, but never sends a cookie.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = ServerInfo::new(listener.local_addr().unwrap(), Scheme::TcpOnly);
+        let timeouts = Timeouts {
+            handshake: Some(Duration::from_millis(100)),
+            ..Timeouts::default()
+        };
+        let result = async_std::task::block_on(connect_with_timeouts(
+            server,
+            CompatibilityProfile::default(),
+            timeouts,
+        ));
+        assert!(matches!(
+            result,
+            Err(VrpnError::Timeout(TimeoutKind::Handshake))
+        ));
     }
-    Err(VrpnError::CouldNotConnect)
-}
-async fn connect_tcp_only(server: ServerInfo) -> Result<ConnectResults> {
-    let tcp = outgoing_tcp_connect(server.socket_addr).await?;
-    return handshake(server, tcp, None).await;
-}
 
-const MILLIS_BETWEEN_ATTEMPTS: u64 = 500;
-pub async fn connect(server: ServerInfo) -> Result<ConnectResults> {
-    match server.scheme {
-        Scheme::UdpAndTcp => connect_tcp_and_udp(server).await,
-        Scheme::TcpOnly => connect_tcp_only(server).await,
+    #[test]
+    fn refused_and_bad_cookie() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let result = async_std::task::block_on(connect(ServerInfo::new(addr, Scheme::TcpOnly)));
+        assert!(matches!(result, Err(VrpnError::ConnectionRefused { addr: a }) if a == addr));
+
+        // Sends something other than a cookie.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = ServerInfo::new(listener.local_addr().unwrap(), Scheme::TcpOnly);
+        let thread = std::thread::spawn(move || {
+            use std::io::Write;
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&[b'x'; COOKIE_SIZE]).unwrap();
+            stream
+        });
+        let result = async_std::task::block_on(connect(server));
+        assert!(
+            matches!(result, Err(VrpnError::HandshakeFailed(_))),
+            "{:?}",
+            result.err()
+        );
+        drop(thread.join());
     }
 }
@@ -2,320 +2,225 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
-use crate::{connection::*, data_types::log::LogFileNames, Result, ServerInfo};
+//! Creating connections on async-std, including over its Unix, TLS, WebSocket and serial streams.
+//!
+//! The connection itself is `vrpn_async::connection_ip::ConnectionIp`.
+
+#[cfg(any(feature = "tls", feature = "websocket"))]
 use async_std::net::TcpListener;
-use futures::{future::BoxFuture, FutureExt, Stream};
-use std::{
-    net::SocketAddr,
-    sync::{Arc, Mutex},
-    task::Poll,
+#[cfg(unix)]
+use async_std::os::unix::net::UnixListener;
+#[cfg(any(unix, feature = "tls", feature = "websocket", feature = "serial"))]
+use futures::StreamExt;
+#[cfg(unix)]
+use std::path::Path;
+use std::{net::SocketAddr, sync::Arc};
+
+#[cfg(unix)]
+use super::connect::accept_unix;
+use super::runtime::AsyncStd;
+#[cfg(feature = "serial")]
+use super::serial::{accept_serial, SerialStream};
+#[cfg(feature = "tls")]
+use super::tls::{accept_tls, make_acceptor};
+#[cfg(feature = "websocket")]
+use super::websocket::accept_ws;
+#[cfg(any(feature = "tls", feature = "websocket"))]
+use crate::net_util::make_tcp_listener;
+#[cfg(feature = "tls")]
+use crate::TlsServerOptions;
+use crate::{
+    data_types::{
+        id_types::{LocalId, SenderId},
+        LogFileNames,
+    },
+    timeouts::Timeouts,
+    CompatibilityProfile, Result, ServerInfo,
 };
 
-use super::{
-    connect::{connect, ConnectResults},
-    endpoint_ip::EndpointIp,
+pub use crate::vrpn_async::connection_ip::{
+    ClientId, ClientInfo, ConnectionIp, ConnectionIpStream,
 };
 
-pub(crate) enum ConnectionIpInfo {
-    /// This variant stores the server info for reconnecting
-    ClientConnectionInfo(ServerInfo),
-    /// This stores the future that connects
-    ClientConnectionSetupFuture(BoxFuture<'static, Result<ConnectResults>>),
-    /// This just marks us as a server
-    Server,
-}
-
-impl ConnectionIpInfo {
-    pub(crate) fn status(&self, num_endpoints: usize) -> ConnectionStatus {
-        match self {
-            ConnectionIpInfo::ClientConnectionSetupFuture(_) => ConnectionStatus::ClientConnecting,
-            ConnectionIpInfo::ClientConnectionInfo(_) => ConnectionStatus::ClientConnected,
-            ConnectionIpInfo::Server => ConnectionStatus::Server(num_endpoints),
-        }
-    }
-}
-pub struct ConnectionIp {
-    core: ConnectionCore<EndpointIp>,
-    server_tcp: Option<Mutex<TcpListener>>,
-    // server_acceptor: Arc<Mutex<Option<ConnectionIpAcceptor>>>,
-    client_info: Mutex<ConnectionIpInfo>,
-}
-
-const DEFAULT_PORT: u16 = 3883;
-
 impl ConnectionIp {
-    /// Create a new ConnectionIp that is a server.
+    /// Create a new ConnectionIp that is a server, on async-std.
+    ///
+    /// If an address is given, clients are accepted there over TCP,
+    /// and requests to be connected back to are received on the UDP port of the same number.
+    /// Use port 0 to pick a free port, and `listen_addr` to find out which.
     pub fn new_server(
         local_log_names: Option<LogFileNames>,
-        _addr: Option<SocketAddr>,
+        addr: Option<SocketAddr>,
     ) -> Result<Arc<ConnectionIp>> {
-        let conn = Arc::new(ConnectionIp {
-            core: ConnectionCore::new(Vec::new(), local_log_names, None),
-            // server_acceptor: Arc::new(Mutex::new(None)),
-            // server_tcp: Some(Mutex::new(server_tcp)),
-            server_tcp: None,
-            client_info: Mutex::new(ConnectionIpInfo::Server),
-        });
-        // {
-        //     let accepter = ConnectionIpAcceptor::new(Arc::downgrade(&conn), addr)?;
-        //     let mut locked_acceptor = conn.server_acceptor.lock()?;
-        //     *locked_acceptor = Some(accepter);
-        // }
-        Ok(conn)
+        ConnectionIp::new_server_with_compatibility(
+            local_log_names,
+            addr,
+            CompatibilityProfile::default(),
+        )
     }
 
-    /// Create a new ConnectionIp that is a client.
-    pub fn new_client(
-        server: ServerInfo,
+    /// Create a new ConnectionIp that is a server, accepting clients according to a compatibility profile.
+    pub fn new_server_with_compatibility(
         local_log_names: Option<LogFileNames>,
-        remote_log_names: Option<LogFileNames>,
+        addr: Option<SocketAddr>,
+        compatibility: CompatibilityProfile,
     ) -> Result<Arc<ConnectionIp>> {
-        let endpoints: Vec<Option<EndpointIp>> = Vec::new();
-        // let connect = Connect::new(server)?;
-        let ret = Arc::new(ConnectionIp {
-            core: ConnectionCore::new(endpoints, local_log_names, remote_log_names),
-            // server_acceptor: None,
-            client_info: Mutex::new(ConnectionIpInfo::ClientConnectionSetupFuture(
-                connect(server).boxed(),
-            )),
-            server_tcp: None,
-        });
-        ret.send_all_descriptions()?;
-        Ok(ret)
+        ConnectionIp::new_server_on(
+            Arc::new(AsyncStd),
+            local_log_names,
+            addr,
+            compatibility,
+            Timeouts::default(),
+        )
     }
 
-    pub fn poll_endpoints(&self, cx: &mut std::task::Context<'_>) -> Poll<Result<Option<()>>> {
-        // eprintln!("in <ConnectionIp as Future>::poll");
-        // if let Some(listener_mutex) = &self.server_tcp {
-        //     let listener = listener_mutex.lock()?;
-        //     match listener.incoming().poll()? {
-        //         Async::Ready(Some(sock)) => {
-        //             // OK, we got a new one.
-        //             let endpoints = self.endpoints();
-        //             tokio::spawn(
-        //                 incoming_handshake(sock)
-        //                     .and_then(move |stream| {
-        //                         if let Ok(mut epoints) = endpoints.lock() {
-        //                             epoints.push(Some(EndpointIp::new(stream)));
-        //                         }
-        //                         Ok(())
-        //                     })
-        //                     .map_err(|e| {
-        //                         eprintln!("err: {:?}", e);
-        //                     }),
-        //             );
-        //         }
-        //         Async::Ready(None) => return Ok(Async::Ready(None)),
-        //         Async::NotReady => (),
-        //     }
-        // }
-
-        // Connect/reconnect if needed.
-        {
-            let mut client_info = self.client_info.lock()?;
-            let ep_arc = self.endpoints();
-            let mut endpoints = ep_arc.lock()?;
-            if let ConnectionIpInfo::ClientConnectionSetupFuture(f) = &mut *client_info {
-                match f.as_mut().poll(cx) {
-                    Poll::Ready(Ok(results)) => {
-                        endpoints.push(Some(EndpointIp::new(results.tcp, results.udp)));
-                        *client_info = ConnectionIpInfo::ClientConnectionInfo(results.server_info)
-                    }
-                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
-                    Poll::Pending => return Poll::Pending,
-                }
-            };
-        }
-
-        // let mut acceptor = self.server_acceptor.lock()?;
-        // match &mut (*acceptor) {
-        //     Some(a) => loop {
-        //         let poll_result = a.poll()?;
-        //         match poll_result {
-        //             Poll::Pending => break,
-        //             Poll::Ready(Some(_)) => (),
-        //             Poll::Ready(None) => return Ok(Poll::Ready(None)),
-        //         }
-        //     },
-        //     None => (),
-        // }
-        let endpoints = self.endpoints();
-        let dispatcher = self.dispatcher();
-        {
-            let mut endpoints = endpoints.lock()?;
-            let mut dispatcher = dispatcher.lock()?;
-            let mut got_not_ready = false;
-            // Go through and poll each endpoint, "taking" the ones that are closed.
-            for ep in endpoints.iter_mut() {
-                let ready = match ep {
-                    Some(endpoint) => endpoint.poll_endpoint(&mut dispatcher, cx).is_ready(),
-                    _ => true,
-                };
-                if ready {
-                    let _ = ep.take();
-                } else {
-                    got_not_ready = true;
-                }
-            }
-            // Now, retain only the non-taken endpoints in the vector.
-            endpoints.retain(|ep| ep.is_some());
-
-            if got_not_ready {
-                Poll::Pending
-            } else {
-                Poll::Ready(Ok(Some(())))
-            }
-        }
+    /// Create a new ConnectionIp that is a server, listening on a Unix domain socket.
+    ///
+    /// Fails if something already exists at the path: removing stale sockets is up to the caller.
+    #[cfg(unix)]
+    pub fn new_server_unix(
+        path: impl AsRef<Path>,
+        local_log_names: Option<LogFileNames>,
+        compatibility: CompatibilityProfile,
+    ) -> Result<Arc<ConnectionIp>> {
+        let listener = Arc::new(UnixListener::from(std::os::unix::net::UnixListener::bind(
+            path,
+        )?));
+        let incoming = futures::stream::unfold(listener, move |listener| async move {
+            let accepted = accept_unix(&listener, compatibility).await;
+            Some((accepted, listener))
+        });
+        ConnectionIp::new_server_accepting(
+            Arc::new(AsyncStd),
+            incoming.boxed(),
+            None,
+            local_log_names,
+            compatibility,
+        )
     }
-}
 
-impl Connection for ConnectionIp {
-    type SpecificEndpoint = EndpointIp;
-    fn connection_core(&self) -> &ConnectionCore<Self::SpecificEndpoint> {
-        &self.core
+    /// Create a new ConnectionIp that is a server, accepting TLS clients on the given address.
+    ///
+    /// Fails if the certificate chain or private key cannot be loaded.
+    #[cfg(feature = "tls")]
+    pub fn new_server_tls(
+        addr: SocketAddr,
+        tls: &TlsServerOptions,
+        local_log_names: Option<LogFileNames>,
+        compatibility: CompatibilityProfile,
+    ) -> Result<Arc<ConnectionIp>> {
+        let acceptor = make_acceptor(tls)?;
+        let listener = Arc::new(TcpListener::from(make_tcp_listener(addr)?));
+        let listen_addr = Some(listener.local_addr()?);
+        let incoming = futures::stream::unfold(listener, move |listener| {
+            let acceptor = acceptor.clone();
+            async move {
+                let accepted = accept_tls(&listener, &acceptor, compatibility).await;
+                Some((accepted, listener))
+            }
+        });
+        ConnectionIp::new_server_accepting(
+            Arc::new(AsyncStd),
+            incoming.boxed(),
+            listen_addr,
+            local_log_names,
+            compatibility,
+        )
     }
 
-    fn status(&self) -> ConnectionStatus {
-        let ep = self.endpoints();
-        let endpoints = ep.lock().unwrap();
-        let info = self.client_info.lock().unwrap();
-        info.status(endpoints.len())
+    /// Create a new ConnectionIp that is a server, accepting WebSocket clients on the given address.
+    ///
+    /// The request path of incoming WebSocket upgrades is not checked.
+    #[cfg(feature = "websocket")]
+    pub fn new_server_ws(
+        addr: SocketAddr,
+        local_log_names: Option<LogFileNames>,
+        compatibility: CompatibilityProfile,
+    ) -> Result<Arc<ConnectionIp>> {
+        let listener = Arc::new(TcpListener::from(make_tcp_listener(addr)?));
+        let listen_addr = Some(listener.local_addr()?);
+        let incoming = futures::stream::unfold(listener, move |listener| async move {
+            let accepted = accept_ws(&listener, compatibility).await;
+            Some((accepted, listener))
+        });
+        ConnectionIp::new_server_accepting(
+            Arc::new(AsyncStd),
+            incoming.boxed(),
+            listen_addr,
+            local_log_names,
+            compatibility,
+        )
     }
-}
-
-pub struct ConnectionIpStream {
-    connection: Arc<ConnectionIp>,
-}
 
-impl ConnectionIpStream {
-    pub fn new(connection: Arc<ConnectionIp>) -> ConnectionIpStream {
-        ConnectionIpStream { connection }
+    /// Create a new ConnectionIp that is a server, whose one client is the device
+    /// on the other end of a serial port.
+    ///
+    /// The handshake happens once polling starts. Once the device is gone, the port is not
+    /// reopened: create a new connection for that.
+    #[cfg(feature = "serial")]
+    pub fn new_server_serial(
+        port: SerialStream,
+        local_log_names: Option<LogFileNames>,
+        compatibility: CompatibilityProfile,
+    ) -> Result<Arc<ConnectionIp>> {
+        let incoming = futures::stream::once(accept_serial(port, compatibility))
+            .chain(futures::stream::pending());
+        ConnectionIp::new_server_accepting(
+            Arc::new(AsyncStd),
+            incoming.boxed(),
+            None,
+            local_log_names,
+            compatibility,
+        )
     }
-}
 
-impl Stream for ConnectionIpStream {
-    type Item = Result<()>;
-
-    fn poll_next(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> Poll<Option<Self::Item>> {
-        // eprintln!("in <ConnectionIpStream as Stream>::poll");
-        self.connection.poll_endpoints(cx).map(|x| x.transpose())
+    /// Create a new ConnectionIp that is a server, accepting only clients in this process
+    /// that connect to `memory://name`.
+    ///
+    /// Fails if there is already a memory server with this name.
+    pub fn new_server_memory(
+        name: &str,
+        local_log_names: Option<LogFileNames>,
+        compatibility: CompatibilityProfile,
+    ) -> Result<Arc<ConnectionIp>> {
+        ConnectionIp::new_server_memory_on(Arc::new(AsyncStd), name, local_log_names, compatibility)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        data_types::{Message, StaticMessageTypeName, StaticSenderName, TypedMessage},
-        handler::{HandlerCode, TypedHandler},
-        tracker::*,
-    };
-    use std::sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
-    };
-
-    #[derive(Debug)]
-    struct TrackerHandler {
-        flag: Arc<AtomicBool>,
-    }
-    impl TrackerHandler {
-        fn new(flag: &Arc<AtomicBool>) -> Box<TrackerHandler> {
-            Box::new(TrackerHandler {
-                flag: Arc::clone(flag),
-            })
-        }
-    }
-    impl TypedHandler for TrackerHandler {
-        type Item = PoseReport;
-        fn handle_typed(&mut self, msg: &TypedMessage<PoseReport>) -> Result<HandlerCode> {
-            println!("{:?}", msg);
-            self.flag.store(true, Ordering::SeqCst);
-            Ok(HandlerCode::ContinueProcessing)
-        }
+    /// Create a new ConnectionIp that is a client, on async-std.
+    pub fn new_client(
+        server: ServerInfo,
+        local_log_names: Option<LogFileNames>,
+        remote_log_names: Option<LogFileNames>,
+    ) -> Result<Arc<ConnectionIp>> {
+        ConnectionIp::new_client_with_compatibility(
+            server,
+            local_log_names,
+            remote_log_names,
+            CompatibilityProfile::default(),
+        )
     }
 
-    #[ignore] // because it requires an external server to be running.
-    #[test]
-    fn tracker_tcp() {
-        let flag = Arc::new(AtomicBool::new(false));
-        async fn function(flag: &Arc<AtomicBool>) -> Result<()> {
-            let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
-            let server = "tcp://127.0.0.1:3883".parse::<ServerInfo>()?;
-            let conn = ConnectionIp::new_client(server, None, None)?;
-            let sender = conn
-                .register_sender(StaticSenderName(b"Tracker0"))
-                .expect("should be able to register sender");
-            let handler_handle = conn.add_typed_handler(TrackerHandler::new(flag), Some(sender))?;
-            conn.send_all_descriptions()?;
-            for _ in 0..4 {
-                let _ = conn.poll_endpoints(&mut cx)?;
-            }
-            conn.remove_handler(handler_handle)
-                .expect("should be able to remove handler");
-            Ok(())
-        }
-        futures::executor::block_on(function(&flag)).unwrap();
-
-        assert!(flag.load(Ordering::SeqCst));
+    /// Create a new ConnectionIp that is a client, talking to the server according to a compatibility profile.
+    pub fn new_client_with_compatibility(
+        server: ServerInfo,
+        local_log_names: Option<LogFileNames>,
+        remote_log_names: Option<LogFileNames>,
+        compatibility: CompatibilityProfile,
+    ) -> Result<Arc<ConnectionIp>> {
+        ConnectionIp::new_client_on(
+            Arc::new(AsyncStd),
+            server,
+            local_log_names,
+            remote_log_names,
+            compatibility,
+            Timeouts::default(),
+        )
     }
 
-    #[ignore] // because it requires an external server to be running.
-    #[test]
-    fn tracker() {
-        let flag = Arc::new(AtomicBool::new(false));
-        async fn function(flag: &Arc<AtomicBool>) -> Result<()> {
-            let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
-            let server = "127.0.0.1:3883".parse::<ServerInfo>()?;
-            let conn = ConnectionIp::new_client(server, None, None)?;
-            let sender = conn
-                .register_sender(StaticSenderName(b"Tracker0"))
-                .expect("should be able to register sender");
-            let handler_handle = conn.add_typed_handler(TrackerHandler::new(flag), Some(sender))?;
-            while conn.status() == ConnectionStatus::ClientConnecting {
-                let _ = conn.poll_endpoints(&mut cx)?;
-            }
-            for _ in 0..4 {
-                let _ = conn.poll_endpoints(&mut cx)?;
-            }
-            conn.remove_handler(handler_handle)
-                .expect("should be able to remove handler");
-            Ok(())
-        }
-        futures::executor::block_on(function(&flag)).unwrap();
-        assert!(flag.load(Ordering::SeqCst));
-    }
-    #[ignore] // because it requires an external server to be running.
-    #[test]
-    fn tracker_manual() {
-        let flag = Arc::new(AtomicBool::new(false));
-        async fn function(flag: &Arc<AtomicBool>) -> Result<()> {
-            let mut cx = futures::task::Context::from_waker(futures::task::noop_waker_ref());
-            let server = "tcp://127.0.0.1:3883".parse::<ServerInfo>()?;
-            let conn = ConnectionIp::new_client(server, None, None)?;
-            let tracker_message_id = conn
-                .register_type(StaticMessageTypeName(b"vrpn_Tracker Pos_Quat"))
-                .expect("should be able to register type");
-            let sender = conn
-                .register_sender(StaticSenderName(b"Tracker0"))
-                .expect("should be able to register sender");
-            conn.add_handler(
-                TrackerHandler::new(flag),
-                Some(tracker_message_id),
-                Some(sender),
-            )?;
-            while conn.status() == ConnectionStatus::ClientConnecting {
-                let _ = conn.poll_endpoints(&mut cx)?;
-            }
-            for _ in 0..4 {
-                let _ = conn.poll_endpoints(&mut cx)?;
-            }
-            Ok(())
-        }
-        futures::executor::block_on(function(&flag)).unwrap();
-        assert!(flag.load(Ordering::SeqCst));
+    /// Create a new ConnectionIp that is a client, for a device URL like `Tracker0@localhost`.
+    ///
+    /// Registers the device name as a sender, returning its ID along with the connection.
+    pub fn for_device(device: &str) -> Result<(Arc<ConnectionIp>, LocalId<SenderId>)> {
+        ConnectionIp::for_device_on(Arc::new(AsyncStd), device)
     }
 }
@@ -0,0 +1,161 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! Finding servers on the local network without knowing their addresses.
+//!
+//! Servers periodically multicast an [`Announcement`] to a well-known group,
+//! and clients listen for a while to collect them.
+//! This is not part of the C++ implementation.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use async_std::{future::timeout, net::UdpSocket, task::sleep};
+use bytes::{Bytes, BytesMut};
+
+use crate::{
+    buffer_unbuffer::{BytesMutExtras, UnbufferFrom},
+    constants::UDP_BUFLEN,
+    data_types::Announcement,
+    net_util::{make_multicast_listener, make_multicast_sender},
+    Result, Scheme, ServerInfo,
+};
+
+/// The multicast group announcements are sent to by default.
+pub const DEFAULT_DISCOVERY_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 38, 83);
+
+/// The port announcements are sent to by default.
+pub const DEFAULT_DISCOVERY_PORT: u16 = 3884;
+
+/// Where and how often servers announce themselves, and how long clients listen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DiscoveryConfig {
+    pub group: Ipv4Addr,
+    pub port: u16,
+    /// How many router hops announcements may cross. 1 keeps them on the local network.
+    pub ttl: u32,
+    /// How often a server sends its announcement.
+    pub announce_interval: Duration,
+    /// How long a client collects announcements before returning.
+    ///
+    /// Should be longer than `announce_interval` to hear from every server.
+    pub listen_for: Duration,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            group: DEFAULT_DISCOVERY_GROUP,
+            port: DEFAULT_DISCOVERY_PORT,
+            ttl: 1,
+            announce_interval: Duration::from_secs(1),
+            listen_for: Duration::from_millis(1500),
+        }
+    }
+}
+
+/// A server heard from during discovery.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DiscoveredServer {
+    /// Where to connect to the server.
+    pub server_info: ServerInfo,
+    /// What the server announced about itself.
+    pub announcement: Announcement,
+}
+
+/// Announce a server with the default configuration, forever.
+///
+/// Run this alongside the connection's driver.
+pub async fn announce(announcement: Announcement) -> Result<()> {
+    announce_with(&DiscoveryConfig::default(), announcement).await
+}
+
+/// Announce a server, forever. Only returns on a socket error.
+pub async fn announce_with(config: &DiscoveryConfig, announcement: Announcement) -> Result<()> {
+    let sock = UdpSocket::from(make_multicast_sender(config.ttl)?);
+    let buf = BytesMut::allocate_and_buffer(announcement)?.freeze();
+    let group = SocketAddr::new(IpAddr::V4(config.group), config.port);
+    loop {
+        sock.send_to(&buf[..], group).await?;
+        sleep(config.announce_interval).await;
+    }
+}
+
+/// Collect announcements with the default configuration.
+pub async fn discover() -> Result<Vec<DiscoveredServer>> {
+    discover_with(&DiscoveryConfig::default()).await
+}
+
+/// Collect announcements for `config.listen_for`,
+/// returning each server once in the order first heard from.
+pub async fn discover_with(config: &DiscoveryConfig) -> Result<Vec<DiscoveredServer>> {
+    let sock = UdpSocket::from(make_multicast_listener(config.group, config.port)?);
+    let deadline = Instant::now() + config.listen_for;
+    let mut servers: Vec<DiscoveredServer> = Vec::new();
+    let mut buf = vec![0u8; UDP_BUFLEN];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let (len, from) = match timeout(remaining, sock.recv_from(&mut buf)).await {
+            Ok(received) => received?,
+            Err(_) => break,
+        };
+        let announcement =
+            match Announcement::unbuffer_from(&mut Bytes::copy_from_slice(&buf[..len])) {
+                Ok(announcement) => announcement,
+                Err(e) => {
+                    debug!("Ignoring malformed announcement from {}: {}", from, e);
+                    continue;
+                }
+            };
+        let server_info = ServerInfo::new(
+            SocketAddr::new(from.ip(), announcement.port),
+            Scheme::UdpAndTcp,
+        );
+        let server = DiscoveredServer {
+            server_info,
+            announcement,
+        };
+        match servers
+            .iter_mut()
+            .find(|s| s.server_info.socket_addr == server.server_info.socket_addr)
+        {
+            Some(existing) => *existing = server,
+            None => servers.push(server),
+        }
+    }
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{select, Either};
+
+    #[test]
+    fn announce_and_discover() {
+        async_std::task::block_on(async {
+            let config = DiscoveryConfig {
+                port: 38840,
+                announce_interval: Duration::from_millis(50),
+                listen_for: Duration::from_millis(500),
+                ..DiscoveryConfig::default()
+            };
+            let announcement = Announcement::new("test server", 4500).with_devices(["Tracker0"]);
+            let announcing = Box::pin(announce_with(&config, announcement.clone()));
+            let discovering = Box::pin(discover_with(&config));
+            let servers = match select(announcing, discovering).await {
+                Either::Left((result, _)) => panic!("announcing stopped: {:?}", result),
+                Either::Right((servers, _)) => servers.unwrap(),
+            };
+            let server = servers
+                .iter()
+                .find(|s| s.announcement == announcement)
+                .expect("should hear our own announcement");
+            assert_eq!(server.server_info.socket_addr.port(), 4500);
+            assert_eq!(server.server_info.scheme, Scheme::UdpAndTcp);
+        });
+    }
+}
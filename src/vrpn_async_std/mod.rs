@@ -2,12 +2,30 @@
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
+//! The async-std runtime for the connections in `vrpn_async`,
+//! along with the transports that are only available on it.
+
 extern crate pin_project_lite;
 
 pub mod connect;
 pub mod connection_ip;
-pub mod endpoint_ip;
-mod endpoints;
-mod unbounded_message_sender;
+pub mod discovery;
+mod runtime;
+#[cfg(feature = "serial")]
+pub mod serial;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
-pub(crate) use unbounded_message_sender::UnboundedMessageSender;
+pub use crate::vrpn_async::{builder, endpoint_ip, memory, reliable_stream};
+pub use builder::{ConnectionBuilder, Runtime};
+pub use memory::{MemoryListener, MemoryStream};
+pub use reliable_stream::ReliableStream;
+pub use runtime::AsyncStd;
+#[cfg(feature = "serial")]
+pub use serial::SerialStream;
+#[cfg(feature = "tls")]
+pub use tls::TlsStream;
+#[cfg(feature = "websocket")]
+pub use websocket::{ConnectionWs, WsStream};
@@ -0,0 +1,113 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+use async_std::net;
+use futures::{future::BoxFuture, AsyncRead, AsyncWrite, FutureExt};
+use socket2::SockRef;
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use crate::vrpn_async::runtime::{
+    AsyncRuntime, RawTcpListener, RawTcpStream, RawUdpSocket, TcpListener, TcpStream, UdpSocket,
+};
+
+/// The async-std runtime: its reactor runs on its own thread, so this works inside any executor.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncStd;
+
+impl AsyncRuntime for AsyncStd {
+    fn tcp_stream(&self, stream: std::net::TcpStream) -> io::Result<TcpStream> {
+        Ok(net::TcpStream::from(stream).into())
+    }
+
+    fn tcp_listener(&self, listener: std::net::TcpListener) -> io::Result<TcpListener> {
+        Ok(TcpListener::new(net::TcpListener::from(listener)))
+    }
+
+    fn udp_socket(&self, socket: std::net::UdpSocket) -> io::Result<UdpSocket> {
+        Ok(net::UdpSocket::from(socket).into())
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        async_std::task::sleep(duration).boxed()
+    }
+
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        async_std::task::spawn(future);
+    }
+}
+
+impl RawTcpStream for net::TcpStream {
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_read(cx, buf)
+    }
+
+    fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &*self).poll_write(cx, buf)
+    }
+
+    fn poll_flush(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_flush(cx)
+    }
+
+    fn poll_close(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut &*self).poll_close(cx)
+    }
+
+    fn socket(&self) -> SockRef<'_> {
+        SockRef::from(self)
+    }
+}
+
+impl RawTcpListener for net::TcpListener {
+    fn accept(&self) -> BoxFuture<'_, io::Result<(TcpStream, SocketAddr)>> {
+        async move {
+            let (stream, addr) = net::TcpListener::accept(self).await?;
+            Ok((stream.into(), addr))
+        }
+        .boxed()
+    }
+
+    fn socket(&self) -> SockRef<'_> {
+        SockRef::from(self)
+    }
+}
+
+impl RawUdpSocket for net::UdpSocket {
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>> {
+        net::UdpSocket::recv_from(self, buf).boxed()
+    }
+
+    fn send_to<'a>(
+        &'a self,
+        buf: &'a [u8],
+        target: SocketAddr,
+    ) -> BoxFuture<'a, io::Result<usize>> {
+        net::UdpSocket::send_to(self, buf, target).boxed()
+    }
+
+    fn socket(&self) -> SockRef<'_> {
+        SockRef::from(self)
+    }
+}
+
+impl From<net::TcpStream> for TcpStream {
+    fn from(stream: net::TcpStream) -> TcpStream {
+        TcpStream::new(stream)
+    }
+}
+
+impl From<net::UdpSocket> for UdpSocket {
+    fn from(socket: net::UdpSocket) -> UdpSocket {
+        UdpSocket::new(socket)
+    }
+}
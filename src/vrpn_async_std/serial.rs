@@ -0,0 +1,193 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! VRPN over a serial port, for devices like embedded trackers wired straight to the host.
+//!
+//! The port carries the same byte stream as a TCP connection would, starting with the
+//! cookie handshake. There is no listening or reconnecting: each end of the link is
+//! a server with the other as its only client. See `ConnectionIp::new_server_serial`.
+
+use blocking::Unblock;
+use futures::{AsyncRead, AsyncWrite};
+use serialport::SerialPort;
+use std::{
+    fmt, io,
+    io::Read,
+    pin::Pin,
+    sync::{Arc, Weak},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use super::connect::{handshake, within, ConnectResults};
+use crate::{
+    sync::Mutex,
+    timeouts::{TimeoutKind, Timeouts},
+    CompatibilityProfile, Result,
+};
+
+/// How long each blocking read waits before checking whether the stream is still wanted.
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The reading half of a port, waiting out read timeouts until its stream is dropped.
+struct PortReader {
+    port: Box<dyn SerialPort>,
+    alive: Weak<()>,
+}
+
+impl Read for PortReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.port.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    if self.alive.upgrade().is_none() {
+                        return Ok(0);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// A serial port exposed as an async byte stream.
+///
+/// Reads and writes each run on a blocking thread. Clones share the same port.
+#[derive(Clone)]
+pub struct SerialStream {
+    name: Option<String>,
+    reader: Arc<Mutex<Unblock<PortReader>>>,
+    writer: Arc<Mutex<Unblock<Box<dyn SerialPort>>>>,
+    /// Dropped with the last clone, letting the reading thread finish.
+    _alive: Arc<()>,
+}
+
+impl fmt::Debug for SerialStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SerialStream")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl SerialStream {
+    /// Open the serial port at `path`, like `/dev/ttyUSB0` or `COM3`, at this many bits per second.
+    pub fn open(path: &str, baud_rate: u32) -> Result<SerialStream> {
+        let port = serialport::new(path, baud_rate)
+            .open()
+            .map_err(io::Error::from)?;
+        SerialStream::new(port)
+    }
+
+    /// Use a port already opened and configured.
+    pub fn new(mut port: Box<dyn SerialPort>) -> Result<SerialStream> {
+        port.set_timeout(READ_POLL_INTERVAL)
+            .map_err(io::Error::from)?;
+        let alive = Arc::new(());
+        let reader = PortReader {
+            port: port.try_clone().map_err(io::Error::from)?,
+            alive: Arc::downgrade(&alive),
+        };
+        Ok(SerialStream {
+            name: port.name(),
+            reader: Arc::new(Mutex::new(Unblock::new(reader))),
+            writer: Arc::new(Mutex::new(Unblock::new(port))),
+            _alive: alive,
+        })
+    }
+
+    /// The name of the port, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+impl AsyncRead for SerialStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.reader.lock()).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SerialStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.writer.lock()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.writer.lock()).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.writer.lock()).poll_close(cx)
+    }
+}
+
+/// Perform the handshake with the device on the other end of a serial port.
+pub(crate) async fn accept_serial(
+    stream: SerialStream,
+    profile: CompatibilityProfile,
+) -> Result<ConnectResults> {
+    within(
+        Timeouts::default().handshake,
+        TimeoutKind::Handshake,
+        handshake(stream, None, profile),
+    )
+    .await
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{
+        button::{ButtonChange, ButtonRemote, ButtonServer},
+        data_types::StaticSenderName,
+        testing::Record,
+        vrpn_async_std::connection_ip::ConnectionIp,
+        PollEndpoints,
+    };
+    use serialport::TTYPort;
+    use std::time::Instant;
+
+    #[test]
+    fn pseudo_terminal_pair() {
+        let (a, b) = TTYPort::pair().unwrap();
+        let device = ConnectionIp::new_server_serial(
+            SerialStream::new(Box::new(a)).unwrap(),
+            None,
+            CompatibilityProfile::default(),
+        )
+        .unwrap();
+        let host = ConnectionIp::new_server_serial(
+            SerialStream::new(Box::new(b)).unwrap(),
+            None,
+            CompatibilityProfile::default(),
+        )
+        .unwrap();
+        let button = ButtonServer::new(Arc::clone(&device), StaticSenderName(b"Button0")).unwrap();
+        let received = Arc::new(std::sync::Mutex::new(Vec::<ButtonChange>::new()));
+        ButtonRemote::new(Arc::clone(&host), StaticSenderName(b"Button0"))
+            .unwrap()
+            .add_handler(Box::new(Record(Arc::clone(&received))))
+            .unwrap();
+        let change = ButtonChange {
+            button: 3,
+            pressed: true,
+        };
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while received.lock().unwrap().is_empty() && Instant::now() < deadline {
+            button.report_change(None, change).unwrap();
+            device.mainloop(Some(Duration::from_millis(10))).unwrap();
+            host.mainloop(Some(Duration::from_millis(10))).unwrap();
+        }
+        assert_eq!(received.lock().unwrap()[0], change);
+    }
+}
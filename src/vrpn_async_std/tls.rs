@@ -0,0 +1,176 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! TLS-encrypted reliable channels, using rustls.
+//!
+//! Only the TCP channel is encrypted: TLS connections are TCP-only, with no UDP channel.
+
+use async_std::net::{TcpListener, TcpStream};
+use futures::{AsyncRead, AsyncWrite};
+use futures_rustls::{
+    rustls::{
+        self, Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig, ServerName,
+    },
+    TlsAcceptor, TlsConnector,
+};
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use super::connect::{handshake, within, ConnectResults};
+use crate::{
+    sync::Mutex,
+    timeouts::{TimeoutKind, Timeouts},
+    CompatibilityProfile, Result, Scheme, ServerInfo, TlsClientOptions, TlsServerOptions,
+    VrpnError,
+};
+
+/// A TLS session over TCP, shareable between the reading and writing halves of an endpoint.
+#[derive(Debug, Clone)]
+pub struct TlsStream {
+    inner: Arc<Mutex<futures_rustls::TlsStream<TcpStream>>>,
+}
+
+impl TlsStream {
+    fn new(stream: impl Into<futures_rustls::TlsStream<TcpStream>>) -> TlsStream {
+        TlsStream {
+            inner: Arc::new(Mutex::new(stream.into())),
+        }
+    }
+}
+
+impl AsyncRead for TlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.inner.lock()).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.inner.lock()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner.lock()).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner.lock()).poll_close(cx)
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    if certs.is_empty() {
+        return Err(VrpnError::OtherMessage(format!(
+            "no certificates found in {}",
+            path.display()
+        )));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key) | rustls_pemfile::Item::RSAKey(key) => {
+                return Ok(PrivateKey(key))
+            }
+            _ => {}
+        }
+    }
+    Err(VrpnError::OtherMessage(format!(
+        "no private key found in {}",
+        path.display()
+    )))
+}
+
+/// Build a TLS acceptor for a server, loading its certificate chain and key.
+pub fn make_acceptor(options: &TlsServerOptions) -> Result<TlsAcceptor> {
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            load_certs(&options.cert_chain)?,
+            load_private_key(&options.private_key)?,
+        )?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn make_connector(options: &TlsClientOptions) -> Result<(TlsConnector, ServerName)> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(&options.ca_cert)? {
+        roots
+            .add(&cert)
+            .map_err(|e| VrpnError::OtherMessage(format!("invalid CA certificate: {}", e)))?;
+    }
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(options.server_name.as_str()).map_err(|_| {
+        VrpnError::from(rustls::Error::General(format!(
+            "invalid server name {}",
+            options.server_name
+        )))
+    })?;
+    Ok((TlsConnector::from(Arc::new(config)), name))
+}
+
+/// Connect to a TLS server, and perform the client side of the handshake.
+pub(crate) async fn connect_tls(
+    server: ServerInfo,
+    options: &TlsClientOptions,
+    profile: CompatibilityProfile,
+    timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    if server.scheme != Scheme::TcpOnly {
+        return Err(VrpnError::UnsupportedScheme(server.scheme));
+    }
+    let (connector, name) = make_connector(options)?;
+    let tcp = within(timeouts.connect, TimeoutKind::Connect, async {
+        Ok(TcpStream::connect(server.socket_addr).await?)
+    })
+    .await?;
+    tcp.set_nodelay(true)?;
+    within(timeouts.handshake, TimeoutKind::Handshake, async {
+        let stream = connector.connect(name, tcp).await?;
+        handshake(TlsStream::new(stream), None, profile).await
+    })
+    .await
+}
+
+/// Accept one TLS client, and perform the server side of the handshake.
+pub async fn accept_tls(
+    listener: &TcpListener,
+    acceptor: &TlsAcceptor,
+    profile: CompatibilityProfile,
+) -> Result<ConnectResults> {
+    let (tcp, _) = listener.accept().await?;
+    tcp.set_nodelay(true)?;
+    within(
+        Timeouts::default().handshake,
+        TimeoutKind::Handshake,
+        async {
+            let stream = acceptor.accept(tcp).await?;
+            handshake(TlsStream::new(stream), None, profile).await
+        },
+    )
+    .await
+}
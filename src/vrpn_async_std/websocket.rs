@@ -0,0 +1,171 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+//! VRPN over WebSocket binary frames, for clients that cannot open plain TCP
+//! connections: browsers, WASM, or hosts behind restrictive firewalls.
+//!
+//! The WebSocket carries the same byte stream as a TCP connection would,
+//! starting with the cookie handshake, so one frame is not necessarily one message.
+
+use async_std::net::{TcpListener, TcpStream};
+use async_tungstenite::{
+    accept_async, client_async,
+    tungstenite::{Error as WsError, Message},
+    WebSocketStream,
+};
+use bytes::{Buf, Bytes};
+use futures::{AsyncRead, AsyncWrite, Sink, Stream};
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use super::{
+    connect::{handshake, within, ConnectResults},
+    connection_ip::ConnectionIp,
+};
+use crate::{
+    sync::Mutex,
+    timeouts::{TimeoutKind, Timeouts},
+    CompatibilityProfile, Result, ServerInfo,
+};
+
+/// A VRPN connection whose endpoints talk over WebSockets.
+///
+/// This is a `ConnectionIp`: create a server with `ConnectionIp::new_server_ws`,
+/// or a client by passing a `ws://` server to `ConnectionIp::new_client`.
+pub type ConnectionWs = ConnectionIp;
+
+#[derive(Debug)]
+struct WsInner {
+    ws: WebSocketStream<TcpStream>,
+    /// Unread remainder of the last binary frame received.
+    read_buf: Bytes,
+}
+
+/// A WebSocket connection exposed as a byte stream.
+///
+/// Writes are sent as binary frames; reads return the contents of received
+/// binary frames, ignoring other frame types. A close frame reads as end of stream.
+#[derive(Debug, Clone)]
+pub struct WsStream {
+    inner: Arc<Mutex<WsInner>>,
+}
+
+fn to_io_error(e: WsError) -> io::Error {
+    match e {
+        WsError::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+impl WsStream {
+    fn new(ws: WebSocketStream<TcpStream>) -> WsStream {
+        WsStream {
+            inner: Arc::new(Mutex::new(WsInner {
+                ws,
+                read_buf: Bytes::new(),
+            })),
+        }
+    }
+}
+
+impl AsyncRead for WsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut inner = self.inner.lock();
+        while inner.read_buf.is_empty() {
+            match Pin::new(&mut inner.ws).poll_next(cx) {
+                Poll::Ready(Some(Ok(Message::Binary(data)))) => inner.read_buf = data.into(),
+                Poll::Ready(Some(Ok(Message::Close(_)))) | Poll::Ready(None) => {
+                    return Poll::Ready(Ok(0))
+                }
+                // Pings are answered by the websocket stream itself.
+                Poll::Ready(Some(Ok(_))) => {}
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(to_io_error(e))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let n = buf.len().min(inner.read_buf.len());
+        buf[..n].copy_from_slice(&inner.read_buf[..n]);
+        inner.read_buf.advance(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for WsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut inner = self.inner.lock();
+        let mut ws = Pin::new(&mut inner.ws);
+        match ws.as_mut().poll_ready(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(to_io_error(e))),
+            Poll::Pending => return Poll::Pending,
+        }
+        ws.start_send(Message::Binary(buf.to_vec()))
+            .map_err(to_io_error)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut inner = self.inner.lock();
+        Pin::new(&mut inner.ws).poll_flush(cx).map_err(to_io_error)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut inner = self.inner.lock();
+        Pin::new(&mut inner.ws).poll_close(cx).map_err(to_io_error)
+    }
+}
+
+/// Open a WebSocket to a `Scheme::WebSocket` server, and perform the client side of the handshake.
+pub(crate) async fn connect_ws(
+    server: ServerInfo,
+    profile: CompatibilityProfile,
+    timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    let path = server
+        .path
+        .as_ref()
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "/".to_string());
+    let url = format!("ws://{}{}", server.socket_addr, path);
+    let tcp = within(timeouts.connect, TimeoutKind::Connect, async {
+        Ok(TcpStream::connect(server.socket_addr).await?)
+    })
+    .await?;
+    tcp.set_nodelay(true)?;
+    within(timeouts.handshake, TimeoutKind::Handshake, async {
+        let (ws, _) = client_async(url, tcp).await?;
+        handshake(WsStream::new(ws), None, profile).await
+    })
+    .await
+}
+
+/// Accept one WebSocket client, and perform the server side of the handshake.
+pub async fn accept_ws(
+    listener: &TcpListener,
+    profile: CompatibilityProfile,
+) -> Result<ConnectResults> {
+    let (tcp, _) = listener.accept().await?;
+    tcp.set_nodelay(true)?;
+    within(
+        Timeouts::default().handshake,
+        TimeoutKind::Handshake,
+        async {
+            let ws = accept_async(tcp).await?;
+            handshake(WsStream::new(ws), None, profile).await
+        },
+    )
+    .await
+}
@@ -1,16 +1,221 @@
-// Copyright 2018, Collabora, Ltd.
+// Copyright 2018-2022, Collabora, Ltd.
 // SPDX-License-Identifier: BSL-1.0
 // Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
 
-pub mod codec;
-pub mod connect;
-pub mod connection_file;
-pub mod connection_ip;
-pub mod cookie;
-pub mod endpoint_channel;
-pub mod endpoint_file;
-pub mod endpoint_ip;
-pub mod ping;
-// pub mod util;
-
-pub use self::codec::apply_message_framing;
+//! Running connections on a Tokio runtime.
+//!
+//! The connections are those of `vrpn_async`, with their TCP and UDP sockets registered
+//! with Tokio's reactor and their timers on Tokio's clock: see `Tokio`.
+//! The runtime needs its I/O and time drivers enabled.
+//! `spawn` runs a connection's `ConnectionDriver` as a Tokio task,
+//! shutting the connection down cleanly once a `CancellationToken` is cancelled;
+//! `ConnectionBuilder::runtime(Runtime::Tokio)` builds and spawns connections without the token.
+//!
+//! Unix domain sockets, TLS and WebSocket are only available with `vrpn-async-std` as well.
+
+mod runtime;
+
+use crate::{
+    data_types::{
+        id_types::{LocalId, SenderId},
+        LogFileNames,
+    },
+    driver::{split, ConnectionHandle, PollEndpoints},
+    timeouts::Timeouts,
+    vrpn_async::connect::{connect_on, ConnectResults},
+    CompatibilityProfile, Result, ServerInfo,
+};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+pub use crate::vrpn_async::{connection_ip::ConnectionIp, ConnectionBuilder, Runtime};
+pub use runtime::Tokio;
+
+/// Connect to a server on the current Tokio runtime.
+pub async fn connect(server: ServerInfo) -> Result<ConnectResults> {
+    connect_with_timeouts(server, CompatibilityProfile::default(), Timeouts::default()).await
+}
+
+/// Connect to a server on the current Tokio runtime, failing with `VrpnError::Timeout`
+/// if connecting or the handshake take longer than allowed.
+pub async fn connect_with_timeouts(
+    server: ServerInfo,
+    profile: CompatibilityProfile,
+    timeouts: Timeouts,
+) -> Result<ConnectResults> {
+    connect_on(Arc::new(Tokio::current()), server, profile, timeouts).await
+}
+
+/// Create a server on the current Tokio runtime: see `ConnectionIp::new_server_on`.
+pub fn new_server(
+    local_log_names: Option<LogFileNames>,
+    addr: Option<SocketAddr>,
+) -> Result<Arc<ConnectionIp>> {
+    ConnectionIp::new_server_on(
+        Arc::new(Tokio::current()),
+        local_log_names,
+        addr,
+        CompatibilityProfile::default(),
+        Timeouts::default(),
+    )
+}
+
+/// Create a client on the current Tokio runtime.
+pub fn new_client(
+    server: ServerInfo,
+    local_log_names: Option<LogFileNames>,
+    remote_log_names: Option<LogFileNames>,
+) -> Result<Arc<ConnectionIp>> {
+    ConnectionIp::new_client_on(
+        Arc::new(Tokio::current()),
+        server,
+        local_log_names,
+        remote_log_names,
+        CompatibilityProfile::default(),
+        Timeouts::default(),
+    )
+}
+
+/// Create a client on the current Tokio runtime, for a device URL like `Tracker0@localhost`.
+///
+/// Registers the device name as a sender, returning its ID along with the connection.
+pub fn for_device(device: &str) -> Result<(Arc<ConnectionIp>, LocalId<SenderId>)> {
+    ConnectionIp::for_device_on(Arc::new(Tokio::current()), device)
+}
+
+/// Spawn the driver of `connection` on the current Tokio runtime.
+///
+/// Returns a handle for using the connection, and the driver's task. The task completes
+/// once `token` is cancelled and the connection has shut down, or once every handle is dropped.
+///
+/// Panics outside of a Tokio runtime, like `tokio::spawn`.
+pub fn spawn<C>(
+    connection: Arc<C>,
+    token: CancellationToken,
+) -> (ConnectionHandle<C>, JoinHandle<Result<()>>)
+where
+    C: PollEndpoints + Send + Sync + 'static,
+{
+    let (handle, driver) = split(connection);
+    (handle, tokio::spawn(driver.run_until_cancelled(token)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data_types::StaticSenderName, timeouts::TimeoutKind, Connection, ConnectionStatus, Scheme,
+        VrpnError,
+    };
+    use std::time::{Duration, Instant};
+
+    static_assertions::assert_impl_all!(Tokio: Send, Sync);
+    static_assertions::assert_impl_all!(ConnectionHandle<ConnectionIp>: Send, Sync);
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn client_and_server_on_tokio() {
+        let runtime = runtime();
+        let _guard = runtime.enter();
+        let token = CancellationToken::new();
+
+        let server = new_server(None, Some("127.0.0.1:0".parse().unwrap())).unwrap();
+        let addr = server.listen_addr().unwrap();
+        let (server, server_task) = spawn(server, token.clone());
+        let client = new_client(format!("tcp://{}", addr).parse().unwrap(), None, None).unwrap();
+        let (client, client_task) = spawn(client, token.clone());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while client.status() != ConnectionStatus::ClientConnected
+            || server.status() != ConnectionStatus::Server(1)
+        {
+            assert!(Instant::now() < deadline, "timed out connecting");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        token.cancel();
+        runtime.block_on(async {
+            client_task.await.unwrap().unwrap();
+            server_task.await.unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn builder_spawns_on_tokio() {
+        let runtime = runtime();
+        let _guard = runtime.enter();
+        let server = ConnectionBuilder::new()
+            .listen_addr("127.0.0.1:0".parse().unwrap())
+            .runtime(Runtime::Tokio)
+            .spawn_server()
+            .unwrap();
+        // Over UDP and TCP, as mainline VRPN clients connect.
+        let client = ConnectionBuilder::new()
+            .server_info(
+                format!("{}", server.connection().listen_addr().unwrap())
+                    .parse()
+                    .unwrap(),
+            )
+            .runtime(Runtime::Tokio)
+            .spawn_client()
+            .unwrap();
+        assert!(format!("{:?}", client.connection().runtime()).starts_with("Tokio"));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while client.status() != ConnectionStatus::ClientConnected
+            || server.status() != ConnectionStatus::Server(1)
+        {
+            assert!(Instant::now() < deadline, "timed out connecting");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn for_device_on_tokio() {
+        let runtime = runtime();
+        let _guard = runtime.enter();
+        let (conn, sender) = for_device("Tracker0@tcp://127.0.0.1:3883").unwrap();
+        assert_eq!(conn.status(), ConnectionStatus::ClientConnecting);
+        assert_eq!(
+            conn.dispatcher()
+                .read()
+                .get_sender_id(StaticSenderName(b"Tracker0")),
+            Some(sender)
+        );
+        assert!(for_device("tcp://127.0.0.1:3883").is_err());
+    }
+
+    #[test]
+    fn handshake_timeout_on_tokio() {
+        // Accepts connections, but never sends a cookie.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = ServerInfo::new(listener.local_addr().unwrap(), Scheme::TcpOnly);
+        let timeouts = Timeouts {
+            handshake: Some(Duration::from_millis(100)),
+            ..Timeouts::default()
+        };
+        let result = runtime().block_on(connect_with_timeouts(
+            server,
+            CompatibilityProfile::default(),
+            timeouts,
+        ));
+        assert!(matches!(
+            result,
+            Err(VrpnError::Timeout(TimeoutKind::Handshake))
+        ));
+
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let result = runtime().block_on(connect(ServerInfo::new(addr, Scheme::TcpOnly)));
+        assert!(matches!(result, Err(VrpnError::ConnectionRefused { addr: a }) if a == addr));
+    }
+}
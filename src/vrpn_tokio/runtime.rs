@@ -0,0 +1,137 @@
+// Copyright 2022, Collabora, Ltd.
+// SPDX-License-Identifier: BSL-1.0
+// Author: Ryan A. Pavlik <ryan.pavlik@collabora.com>
+
+use futures::{future::BoxFuture, ready, FutureExt};
+use socket2::SockRef;
+use std::{
+    io,
+    net::{Shutdown, SocketAddr},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{net, runtime::Handle};
+
+use crate::vrpn_async::runtime::{
+    AsyncRuntime, RawTcpListener, RawTcpStream, RawUdpSocket, TcpListener, TcpStream, UdpSocket,
+};
+
+/// A Tokio runtime: sockets are registered with its reactor, and tasks spawned on it.
+///
+/// The runtime needs its I/O and time drivers enabled.
+#[derive(Debug, Clone)]
+pub struct Tokio {
+    handle: Handle,
+}
+
+impl Tokio {
+    pub fn new(handle: Handle) -> Tokio {
+        Tokio { handle }
+    }
+
+    /// The Tokio runtime this is called from.
+    ///
+    /// Panics outside of a Tokio runtime, like `tokio::spawn`.
+    pub fn current() -> Tokio {
+        Tokio::new(Handle::current())
+    }
+}
+
+impl AsyncRuntime for Tokio {
+    fn tcp_stream(&self, stream: std::net::TcpStream) -> io::Result<TcpStream> {
+        let _guard = self.handle.enter();
+        stream.set_nonblocking(true)?;
+        Ok(TcpStream::new(net::TcpStream::from_std(stream)?))
+    }
+
+    fn tcp_listener(&self, listener: std::net::TcpListener) -> io::Result<TcpListener> {
+        let _guard = self.handle.enter();
+        listener.set_nonblocking(true)?;
+        Ok(TcpListener::new(net::TcpListener::from_std(listener)?))
+    }
+
+    fn udp_socket(&self, socket: std::net::UdpSocket) -> io::Result<UdpSocket> {
+        let _guard = self.handle.enter();
+        socket.set_nonblocking(true)?;
+        Ok(UdpSocket::new(net::UdpSocket::from_std(socket)?))
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        // Made here, so it may be polled from outside the runtime.
+        let _guard = self.handle.enter();
+        tokio::time::sleep(duration).boxed()
+    }
+
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        self.handle.spawn(future);
+    }
+}
+
+impl RawTcpStream for net::TcpStream {
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            ready!(self.poll_read_ready(cx))?;
+            match self.try_read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        loop {
+            ready!(self.poll_write_ready(cx))?;
+            match self.try_write(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    fn poll_flush(&self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(&self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(RawTcpStream::socket(self).shutdown(Shutdown::Write))
+    }
+
+    fn socket(&self) -> SockRef<'_> {
+        SockRef::from(self)
+    }
+}
+
+impl RawTcpListener for net::TcpListener {
+    fn accept(&self) -> BoxFuture<'_, io::Result<(TcpStream, SocketAddr)>> {
+        async move {
+            let (stream, addr) = net::TcpListener::accept(self).await?;
+            Ok((TcpStream::new(stream), addr))
+        }
+        .boxed()
+    }
+
+    fn socket(&self) -> SockRef<'_> {
+        SockRef::from(self)
+    }
+}
+
+impl RawUdpSocket for net::UdpSocket {
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>> {
+        net::UdpSocket::recv_from(self, buf).boxed()
+    }
+
+    fn send_to<'a>(
+        &'a self,
+        buf: &'a [u8],
+        target: SocketAddr,
+    ) -> BoxFuture<'a, io::Result<usize>> {
+        net::UdpSocket::send_to(self, buf, target).boxed()
+    }
+
+    fn socket(&self) -> SockRef<'_> {
+        SockRef::from(self)
+    }
+}